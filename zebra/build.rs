@@ -1,4 +1,7 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::compile_protos("../proto/vtysh.proto")?;
+    tonic_build::compile_protos("../proto/ipc.proto")?;
+    tonic_build::compile_protos("../proto/health.proto")?;
+    tonic_build::compile_protos("../proto/dataplane.proto")?;
     Ok(())
 }
@@ -0,0 +1,170 @@
+use super::packet::{Attrs, UpdatePacket};
+use super::ptree::PrefixTree;
+use ipnet::{Ipv4Net, Ipv6Net};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+// Paths of a prefix keyed by Path Identifier, which is 0 unless ADD-PATH is
+// negotiated (RFC 7911).
+pub type AdjPaths = BTreeMap<u32, Attrs>;
+
+// Adj-RIB-In holds routes received from a peer before any policy is applied.
+// Adj-RIB-Out holds routes advertised to a peer.
+pub struct AdjRib {
+    pub v4: PrefixTree<Ipv4Net, AdjPaths>,
+    pub v6: PrefixTree<Ipv6Net, AdjPaths>,
+}
+
+impl fmt::Debug for AdjRib {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdjRib")
+            .field("v4", &self.v4.len())
+            .field("v6", &self.v6.len())
+            .finish()
+    }
+}
+
+pub fn path_id(ids: &[u32], i: usize) -> u32 {
+    ids.get(i).copied().unwrap_or(0)
+}
+
+impl AdjRib {
+    pub fn new() -> Self {
+        Self {
+            v4: PrefixTree::new(),
+            v6: PrefixTree::new(),
+        }
+    }
+
+    pub fn insert(&mut self, prefix: Ipv4Net, path_id: u32, attrs: Attrs) {
+        self.v4
+            .get_or_insert_with(prefix, AdjPaths::new)
+            .insert(path_id, attrs);
+    }
+
+    pub fn remove(&mut self, prefix: &Ipv4Net, path_id: u32) -> bool {
+        let paths = match self.v4.get_mut(prefix) {
+            Some(paths) => paths,
+            None => return false,
+        };
+        let removed = paths.remove(&path_id).is_some();
+        if paths.is_empty() {
+            self.v4.remove(prefix);
+        }
+        removed
+    }
+
+    pub fn insert6(&mut self, prefix: Ipv6Net, path_id: u32, attrs: Attrs) {
+        self.v6
+            .get_or_insert_with(prefix, AdjPaths::new)
+            .insert(path_id, attrs);
+    }
+
+    pub fn remove6(&mut self, prefix: &Ipv6Net, path_id: u32) -> bool {
+        let paths = match self.v6.get_mut(prefix) {
+            Some(paths) => paths,
+            None => return false,
+        };
+        let removed = paths.remove(&path_id).is_some();
+        if paths.is_empty() {
+            self.v6.remove(prefix);
+        }
+        removed
+    }
+
+    // Apply withdrawn routes and NLRI of the UPDATE message.
+    pub fn update(&mut self, packet: &UpdatePacket) {
+        for (i, prefix) in packet.ipv4_withdraw.iter().enumerate() {
+            self.remove(prefix, path_id(&packet.ipv4_withdraw_id, i));
+        }
+        for (i, prefix) in packet.ipv4_update.iter().enumerate() {
+            self.insert(
+                *prefix,
+                path_id(&packet.ipv4_update_id, i),
+                packet.attrs.clone(),
+            );
+        }
+        if let Some(mp) = packet.mp_unreach() {
+            for (i, prefix) in mp.prefix.iter().enumerate() {
+                self.remove6(prefix, path_id(&mp.path_id, i));
+            }
+        }
+        if let Some(mp) = packet.mp_reach() {
+            let attrs = packet.mp_attrs();
+            for (i, prefix) in mp.prefix.iter().enumerate() {
+                let attrs = packet.mp_prefix_attrs(&attrs, i);
+                self.insert6(*prefix, path_id(&mp.path_id, i), attrs);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.v4 = PrefixTree::new();
+        self.v6 = PrefixTree::new();
+    }
+
+    // Number of paths.
+    pub fn count(&self) -> usize {
+        self.v4.iter().map(|(_, paths)| paths.len()).sum::<usize>()
+            + self.v6.iter().map(|(_, paths)| paths.len()).sum::<usize>()
+    }
+
+    pub fn prefixes(&self) -> Vec<Ipv4Net> {
+        self.v4.iter().map(|(prefix, _)| *prefix).collect()
+    }
+
+    pub fn prefixes6(&self) -> Vec<Ipv6Net> {
+        self.v6.iter().map(|(prefix, _)| *prefix).collect()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = (&Ipv4Net, u32, &Attrs)> {
+        self.v4.iter().flat_map(|(prefix, paths)| {
+            paths
+                .iter()
+                .map(move |(path_id, attrs)| (prefix, *path_id, attrs))
+        })
+    }
+
+    pub fn paths6(&self) -> impl Iterator<Item = (&Ipv6Net, u32, &Attrs)> {
+        self.v6.iter().flat_map(|(prefix, paths)| {
+            paths
+                .iter()
+                .map(move |(path_id, attrs)| (prefix, *path_id, attrs))
+        })
+    }
+}
+
+// Paths of Adj-RIB-In marked stale by BoRR of Enhanced Route Refresh. The
+// paths stay in Adj-RIB-In, and the mark is cleared when they are advertised
+// or withdrawn again (RFC 7313 4.2).
+#[derive(Debug, Default)]
+pub struct StaleIndex {
+    pub v4: BTreeSet<(Ipv4Net, u32)>,
+    pub v6: BTreeSet<(Ipv6Net, u32)>,
+}
+
+impl StaleIndex {
+    pub fn count(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+
+    // Paths of the UPDATE message are no longer stale.
+    pub fn update(&mut self, packet: &UpdatePacket) {
+        if self.count() == 0 {
+            return;
+        }
+        for (i, prefix) in packet.ipv4_withdraw.iter().enumerate() {
+            self.v4
+                .remove(&(*prefix, path_id(&packet.ipv4_withdraw_id, i)));
+        }
+        for (i, prefix) in packet.ipv4_update.iter().enumerate() {
+            self.v4
+                .remove(&(*prefix, path_id(&packet.ipv4_update_id, i)));
+        }
+        for mp in packet.mp_unreach().into_iter().chain(packet.mp_reach()) {
+            for (i, prefix) in mp.prefix.iter().enumerate() {
+                self.v6.remove(&(*prefix, path_id(&mp.path_id, i)));
+            }
+        }
+    }
+}
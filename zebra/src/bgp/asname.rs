@@ -0,0 +1,144 @@
+use super::handler::{Bgp, Message};
+use super::task::Task;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const AS_NAMES_REFRESH_INTERVAL: u64 = 86400;
+pub const AS_NAMES_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Names of the neighbor ASes shown with the neighbors. The dataset is loaded
+// from a local file or fetched by HTTP, e.g. PeeringDB, by a task and
+// refreshed periodically, so that the event loop never waits for it.
+#[derive(Debug, Default)]
+pub struct AsNames {
+    pub source: Option<String>,
+    pub refresh_interval: Option<u64>,
+    pub names: HashMap<u32, String>,
+    task: Option<Task<()>>,
+}
+
+impl AsNames {
+    pub fn get(&self, asn: u32) -> Option<&str> {
+        self.names.get(&asn).map(|name| name.as_str())
+    }
+
+    pub fn restart(&mut self, tx: UnboundedSender<Message>) {
+        self.task = None;
+        let source = match &self.source {
+            Some(source) => source.clone(),
+            None => {
+                self.names.clear();
+                return;
+            }
+        };
+        let interval = self.refresh_interval.unwrap_or(AS_NAMES_REFRESH_INTERVAL);
+        self.task = Some(Task::spawn(as_names_refresh(source, interval, tx)));
+    }
+}
+
+fn as_number(asn: &str) -> Option<u32> {
+    let asn = asn
+        .strip_prefix("AS")
+        .or(asn.strip_prefix("as"))
+        .unwrap_or(asn);
+    asn.parse().ok()
+}
+
+// PeeringDB net objects, {"data": [{"asn": 64496, "name": "..."}, ...]}.
+fn as_names_parse_json(text: &str) -> Option<HashMap<u32, String>> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    let mut names = HashMap::new();
+    for net in value.get("data")?.as_array()?.iter() {
+        let asn = net.get("asn").and_then(|x| x.as_u64());
+        let name = net.get("name").and_then(|x| x.as_str());
+        if let (Some(asn), Some(name)) = (asn, name) {
+            if let Ok(asn) = u32::try_from(asn) {
+                names.insert(asn, name.to_string());
+            }
+        }
+    }
+    Some(names)
+}
+
+// Either PeeringDB JSON or one "ASN name" per line with optional "AS" prefix
+// and comma separator. Lines starting with '#' are comments.
+pub fn as_names_parse(text: &str) -> Option<HashMap<u32, String>> {
+    if text.trim_start().starts_with('{') {
+        return as_names_parse_json(text);
+    }
+    let mut names = HashMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (asn, name) = match line.split_once([',', ' ', '\t']) {
+            Some((asn, name)) => (asn, name.trim_matches([',', ' ', '\t', '"'])),
+            None => continue,
+        };
+        if let Some(asn) = as_number(asn) {
+            if !name.is_empty() {
+                names.insert(asn, name.to_string());
+            }
+        }
+    }
+    Some(names)
+}
+
+async fn as_names_fetch(source: &str) -> anyhow::Result<String> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let client = reqwest::Client::builder()
+            .timeout(AS_NAMES_TIMEOUT)
+            .build()?;
+        let resp = client.get(source).send().await?.error_for_status()?;
+        Ok(resp.text().await?)
+    } else {
+        Ok(tokio::fs::read_to_string(source).await?)
+    }
+}
+
+async fn as_names_refresh(source: String, interval: u64, tx: UnboundedSender<Message>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval.max(60)));
+    loop {
+        interval.tick().await;
+        let text = match as_names_fetch(&source).await {
+            Ok(text) => text,
+            Err(err) => {
+                println!("as-names: can't load {}: {}", source, err);
+                continue;
+            }
+        };
+        match as_names_parse(&text) {
+            Some(names) => {
+                println!("as-names: {} names loaded from {}", names.len(), source);
+                let _ = tx.send(Message::AsNames(names));
+            }
+            None => println!("as-names: can't parse {}", source),
+        }
+    }
+}
+
+pub fn as_names_update(bgp: &mut Bgp, names: HashMap<u32, String>) {
+    // Names loaded by the task of the removed source are stale.
+    if bgp.as_names.source.is_some() {
+        bgp.as_names.names = names;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn as_names_parse_format() {
+        let names = as_names_parse("# comment\nAS64496 Example Net\n64497,\"Other\"\n").unwrap();
+        assert_eq!(names.get(&64496).map(|x| x.as_str()), Some("Example Net"));
+        assert_eq!(names.get(&64497).map(|x| x.as_str()), Some("Other"));
+        assert_eq!(names.len(), 2);
+
+        let names = as_names_parse(r#"{"data": [{"asn": 64498, "name": "PDB Net"}]}"#).unwrap();
+        assert_eq!(names.get(&64498).map(|x| x.as_str()), Some("PDB Net"));
+        assert_eq!(as_names_parse("{ broken"), None);
+    }
+}
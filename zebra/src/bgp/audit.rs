@@ -0,0 +1,119 @@
+use super::packet::{shutdown_message_decode, NotificationCode};
+use super::peer::{Peer, PeerReset, ResetDirection, State};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Audit file is rotated when it exceeds the size, keeping older files as
+// <file>.1 ... <file>.N.
+pub const AUDIT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+pub const AUDIT_ROTATE: usize = 5;
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: u64,
+    peer: IpAddr,
+    remote_as: u32,
+    state: &'a str,
+    direction: ResetDirection,
+    code: u8,
+    sub_code: u8,
+    reason: String,
+    message: Option<String>,
+    duration: u64,
+}
+
+#[derive(Default)]
+pub struct Audit {
+    path: Option<PathBuf>,
+    file: Option<File>,
+}
+
+fn rotate_path(path: &PathBuf, index: usize) -> PathBuf {
+    let mut path = path.clone().into_os_string();
+    path.push(format!(".{}", index));
+    PathBuf::from(path)
+}
+
+impl Audit {
+    pub fn open(&mut self, path: PathBuf) {
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => {
+                self.file = Some(file);
+                self.path = Some(path);
+            }
+            Err(err) => {
+                println!("audit: can't open {}: {}", path.display(), err);
+                self.close();
+            }
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.file = None;
+        self.path = None;
+    }
+
+    fn rotate(&mut self) {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let size = self
+            .file
+            .as_ref()
+            .and_then(|file| file.metadata().ok())
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+        if size < AUDIT_MAX_SIZE {
+            return;
+        }
+        for index in (1..AUDIT_ROTATE).rev() {
+            let _ = fs::rename(rotate_path(&path, index), rotate_path(&path, index + 1));
+        }
+        let _ = fs::rename(&path, rotate_path(&path, 1));
+        self.open(path);
+    }
+
+    pub fn reset(&mut self, peer: &Peer, state: &State, reset: &PeerReset) {
+        if self.file.is_none() {
+            return;
+        }
+        self.rotate();
+
+        let duration = match (state, peer.instant) {
+            (State::Established, Some(instant)) => instant.elapsed().as_secs(),
+            _ => 0,
+        };
+        let reason = if reset.direction == ResetDirection::Local {
+            String::from("Connection closed")
+        } else {
+            NotificationCode(reset.code).to_string()
+        };
+        let record = AuditRecord {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0),
+            peer: peer.address,
+            remote_as: peer.peer_as,
+            state: state.to_str(),
+            direction: reset.direction,
+            code: reset.code,
+            sub_code: reset.sub_code,
+            reason,
+            message: shutdown_message_decode(reset.code, reset.sub_code, &reset.data),
+            duration,
+        };
+        if let Some(file) = self.file.as_mut() {
+            let mut line = serde_json::to_string(&record).unwrap();
+            line.push('\n');
+            if let Err(err) = file.write_all(line.as_bytes()) {
+                println!("audit: write error: {}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use super::{BGP_IDLE_HOLD_STABLE, BGP_IDLE_HOLD_TIME, BGP_IDLE_HOLD_TIME_MAX};
+use std::time::Duration;
+
+// DampPeerOscillations. Flapping peer waits longer in Idle before the next
+// connection attempt.
+#[derive(Debug)]
+pub struct IdleHold {
+    pub time: u64,
+    pub flaps: u32,
+}
+
+impl Default for IdleHold {
+    fn default() -> Self {
+        Self {
+            time: BGP_IDLE_HOLD_TIME,
+            flaps: 0,
+        }
+    }
+}
+
+impl IdleHold {
+    // Session went down after it was established for the uptime, None when
+    // it failed before reaching Established.
+    pub fn failure(&mut self, uptime: Option<Duration>) {
+        let stable = Duration::from_secs(BGP_IDLE_HOLD_STABLE);
+        if uptime.map(|uptime| uptime >= stable).unwrap_or(false) {
+            self.reset();
+            return;
+        }
+        self.flaps += 1;
+        self.time = (self.time * 2).min(BGP_IDLE_HOLD_TIME_MAX);
+    }
+
+    // Manual stop and clear are not failures of the peer.
+    pub fn reset(&mut self) {
+        self.time = BGP_IDLE_HOLD_TIME;
+        self.flaps = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn idle_hold_backoff() {
+        let mut idle = IdleHold::default();
+        idle.failure(None);
+        idle.failure(Some(Duration::from_secs(10)));
+        assert_eq!(idle.time, BGP_IDLE_HOLD_TIME * 4);
+        assert_eq!(idle.flaps, 2);
+        for _ in 0..20 {
+            idle.failure(None);
+        }
+        assert_eq!(idle.time, BGP_IDLE_HOLD_TIME_MAX);
+        idle.failure(Some(Duration::from_secs(BGP_IDLE_HOLD_STABLE)));
+        assert_eq!(idle.time, BGP_IDLE_HOLD_TIME);
+        assert_eq!(idle.flaps, 0);
+    }
+}
@@ -0,0 +1,44 @@
+use super::handler::{Bgp, Message};
+use super::peer::{Event, State};
+use crate::config::Args;
+
+// Clear commands reach BGP through the show channel which only borrows Bgp,
+// so the soft reset is queued to the event loop as a peer event.
+fn clear_bgp_neighbor_soft(bgp: &Bgp, mut args: Args, event: Event) -> String {
+    let addr = match args.addr() {
+        Some(addr) => addr,
+        None => return String::from("% Invalid neighbor address\n"),
+    };
+    match bgp.peers.get(&addr) {
+        Some(peer) if peer.state == State::Established => {
+            let _ = bgp.tx.send(Message::Event(addr, event));
+            String::new()
+        }
+        Some(_) => String::from("% Neighbor is not established\n"),
+        None => String::from("% No such neighbor\n"),
+    }
+}
+
+pub fn clear_bgp_neighbor_soft_in(bgp: &Bgp, args: Args) -> String {
+    clear_bgp_neighbor_soft(bgp, args, Event::SoftIn)
+}
+
+pub fn clear_bgp_neighbor_soft_out(bgp: &Bgp, args: Args) -> String {
+    clear_bgp_neighbor_soft(bgp, args, Event::SoftOut)
+}
+
+// Session comes back after the idle hold time unlike the shutdown in the
+// configuration.
+pub fn clear_bgp_neighbor_shutdown(bgp: &Bgp, mut args: Args) -> String {
+    let addr = match args.addr() {
+        Some(addr) => addr,
+        None => return String::from("% Invalid neighbor address\n"),
+    };
+    if !bgp.peers.contains_key(&addr) {
+        return String::from("% No such neighbor\n");
+    }
+    let _ = bgp
+        .tx
+        .send(Message::Event(addr, Event::Shutdown(args.string())));
+    String::new()
+}
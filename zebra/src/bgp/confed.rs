@@ -0,0 +1,43 @@
+use super::handler::{Bgp, Message};
+use super::peer::{Event, Peer, State};
+use std::collections::BTreeSet;
+
+// Confederation identifier and the member ASes of the confederation other
+// than the local one (RFC 5065).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Confederation {
+    pub id: Option<u32>,
+    pub peers: BTreeSet<u32>,
+}
+
+impl Peer {
+    // Neighbor in another member AS of the confederation.
+    pub fn confed_member(&self) -> bool {
+        self.peer_as != self.local_as && self.confed.peers.contains(&self.peer_as)
+    }
+
+    // Confederation identifier is our AS for the neighbors outside of the
+    // confederation, the member AS inside of it (RFC 5065 4). Local AS of the
+    // neighbor takes precedence during AS migration.
+    pub fn open_as(&self) -> u32 {
+        if let Some(asn) = self.migration_as() {
+            return asn;
+        }
+        match self.confed.id {
+            Some(id) if self.peer_as != self.local_as && !self.confed_member() => id,
+            _ => self.local_as,
+        }
+    }
+}
+
+// Sessions are reset when our AS in OPEN or the confederation membership of
+// the neighbor changes.
+pub fn confed_update(bgp: &mut Bgp) {
+    for (addr, peer) in bgp.peers.iter_mut() {
+        let prev = (peer.open_as(), peer.confed_member());
+        peer.confed = bgp.confed.clone();
+        if prev != (peer.open_as(), peer.confed_member()) && peer.state != State::Idle {
+            let _ = bgp.tx.send(Message::Event(*addr, Event::Shutdown(None)));
+        }
+    }
+}
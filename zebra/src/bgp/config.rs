@@ -1,13 +1,39 @@
 use super::{
-    handler::Callback,
-    peer::{fsm_init, Peer, PeerType},
-    AfiSafi, Bgp,
+    confed::confed_update,
+    dynamic::{dynamic_range_clear, DYNAMIC_PEER_LIMIT},
+    group::group_member_apply,
+    handler::{Callback, Message},
+    health::{health_add, health_delete, health_restart, ProbeConfig, ProbeType},
+    mirror::{mirror_lab_update, MirrorDirection},
+    mrt::dump::{mrt_dump_restart, MRT_DUMP_TABLE_INTERVAL, MRT_DUMP_TABLE_KEEP},
+    mrt::journal::MESSAGE_JOURNAL_MAX_SIZE,
+    multipath::multipath_config_update,
+    network::{network_add, network_delete, network_health_check, network_update},
+    nexthop::NEXTHOP_MAX_DEPTH,
+    packet::{
+        CommunityAttr, ExtComAdmin, ExtendedComAttr, LargeCom, RouteDistinguisher, ORF_RECEIVE,
+        ORF_SEND,
+    },
+    peer::{fsm_init, Event, Peer, PeerAddPath, PrivateAs, RemoteAs, State, UpdateSource},
+    persist::PERSIST_SNAPSHOT_INTERVAL,
+    redist::{redist_source_apply, redist_update},
+    restart::restart_peer,
+    route::{route_advertise, route_bestpath_update},
+    rpki::{rpki_cache_add, rpki_cache_delete, rpki_cache_start, rtr::RTR_PORT},
+    sla::sla_metrics_restart,
+    unnumbered::{unnumbered_add, unnumbered_delete, unnumbered_remote_as},
+    vpn::{vrf_delete, vrf_update},
+    AfiSafi, Bgp, BGP_LOCAL_PREF, BGP_STALE_ROUTES_TIME,
 };
 use crate::{
     config::{Args, ConfigOp},
-    policy::CommunityMember,
+    policy::{self, CommunityMember, Policy, PolicyDirection},
+    rib::api::RedistSource,
 };
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
 
 fn config_global_asn(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
     if op == ConfigOp::Set && !args.is_empty() {
@@ -24,36 +50,614 @@ fn config_global_identifier(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Opti
     Some(())
 }
 
+fn config_global_monitor_only(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    if op == ConfigOp::Set {
+        bgp.monitor_only = args.boolean()?;
+    } else {
+        bgp.monitor_only = false;
+    }
+    Some(())
+}
+
+fn config_global_parse_mode(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    if op == ConfigOp::Set {
+        bgp.strict_parse = match args.string()?.as_str() {
+            "strict" => true,
+            "lenient" => false,
+            _ => return None,
+        };
+    } else {
+        bgp.strict_parse = false;
+    }
+    Some(())
+}
+
+fn config_global_show_cache(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    if op == ConfigOp::Set {
+        bgp.show_cache.enable(args.boolean()?);
+    } else {
+        bgp.show_cache.enable(false);
+    }
+    Some(())
+}
+
+fn config_global_nexthop_max_depth(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    if op == ConfigOp::Set {
+        bgp.nexthop.max_depth = args.u8()?;
+    } else {
+        bgp.nexthop.max_depth = NEXTHOP_MAX_DEPTH;
+    }
+    bgp.nexthop.changed = true;
+    Some(())
+}
+
+fn config_global_nexthop_resolve_over_bgp(
+    bgp: &mut Bgp,
+    mut args: Args,
+    op: ConfigOp,
+) -> Option<()> {
+    if op == ConfigOp::Set {
+        bgp.nexthop.resolve_over_bgp = args.boolean()?;
+    } else {
+        bgp.nexthop.resolve_over_bgp = true;
+    }
+    bgp.nexthop.changed = true;
+    Some(())
+}
+
+fn config_global_nexthop_prefix_list(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    if op == ConfigOp::Set {
+        bgp.nexthop.prefix_list = Some(args.string()?);
+    } else {
+        bgp.nexthop.prefix_list = None;
+    }
+    bgp.nexthop.changed = true;
+    Some(())
+}
+
+// Adj-RIB-In is restored at the end of the commit, once the peers are
+// configured.
+fn config_global_persist_directory(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.persist.stop();
+    if op == ConfigOp::Set {
+        bgp.persist.dir = Some(PathBuf::from(args.string()?));
+    }
+    Some(())
+}
+
+fn config_global_persist_interval(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.persist.interval = if op == ConfigOp::Set {
+        args.u32()? as u64
+    } else {
+        PERSIST_SNAPSHOT_INTERVAL
+    };
+    bgp.persist.timer_start(bgp.tx.clone());
+    Some(())
+}
+
+// Journal files are reopened, messages are recorded from the next session.
+fn msg_journal_restart(bgp: &mut Bgp) {
+    for peer in bgp.peers.values_mut() {
+        peer.msg_journal = if peer.config.message_journal {
+            bgp.msg_journal.start(peer.address)
+        } else {
+            None
+        };
+    }
+}
+
+fn config_global_msg_journal_directory(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.msg_journal.dir = if op == ConfigOp::Set {
+        Some(PathBuf::from(args.string()?))
+    } else {
+        None
+    };
+    msg_journal_restart(bgp);
+    Some(())
+}
+
+fn config_global_msg_journal_max_size(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.msg_journal.max_size = if op == ConfigOp::Set {
+        args.u32()? as u64
+    } else {
+        MESSAGE_JOURNAL_MAX_SIZE
+    };
+    msg_journal_restart(bgp);
+    Some(())
+}
+
+fn config_global_mrt_dump_directory(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.mrt_dump.dir = if op == ConfigOp::Set {
+        Some(PathBuf::from(args.string()?))
+    } else {
+        None
+    };
+    mrt_dump_restart(bgp);
+    Some(())
+}
+
+fn config_global_mrt_dump_table_interval(
+    bgp: &mut Bgp,
+    mut args: Args,
+    op: ConfigOp,
+) -> Option<()> {
+    bgp.mrt_dump.table_interval = if op == ConfigOp::Set {
+        args.u32()? as u64
+    } else {
+        MRT_DUMP_TABLE_INTERVAL
+    };
+    mrt_dump_restart(bgp);
+    Some(())
+}
+
+fn config_global_mrt_dump_table_keep(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.mrt_dump.table_keep = if op == ConfigOp::Set {
+        args.u32()? as usize
+    } else {
+        MRT_DUMP_TABLE_KEEP
+    };
+    Some(())
+}
+
+fn config_global_mrt_dump_updates(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.mrt_dump.updates = if op == ConfigOp::Set {
+        args.boolean()?
+    } else {
+        true
+    };
+    mrt_dump_restart(bgp);
+    Some(())
+}
+
+fn config_global_mrt_dump_max_size(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.mrt_dump.max_size = if op == ConfigOp::Set {
+        args.u32()? as u64
+    } else {
+        MESSAGE_JOURNAL_MAX_SIZE
+    };
+    mrt_dump_restart(bgp);
+    Some(())
+}
+
+fn config_global_sla_metrics_file(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.sla_metrics.file = if op == ConfigOp::Set {
+        Some(PathBuf::from(args.string()?))
+    } else {
+        None
+    };
+    sla_metrics_restart(bgp);
+    Some(())
+}
+
+fn config_global_restart_enabled(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.restart_config.enabled = op == ConfigOp::Set && args.boolean()?;
+    Some(())
+}
+
+fn config_global_restart_helper_only(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.restart_config.helper_only = op != ConfigOp::Set || args.boolean()?;
+    Some(())
+}
+
+fn config_global_restart_deferral(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.restart_config.deferral_time = if op == ConfigOp::Set {
+        args.u32()?
+    } else {
+        BGP_STALE_ROUTES_TIME
+    };
+    Some(())
+}
+
+fn config_global_restart_directory(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.restart_config.dir = if op == ConfigOp::Set {
+        Some(PathBuf::from(args.string()?))
+    } else {
+        None
+    };
+    Some(())
+}
+
+fn config_global_audit_file(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.audit.close();
+    if op == ConfigOp::Set {
+        bgp.audit.open(PathBuf::from(args.string()?));
+    }
+    Some(())
+}
+
+fn config_global_webhook_url(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.webhook.stop();
+    if op == ConfigOp::Set {
+        bgp.webhook.start(args.string()?);
+    }
+    Some(())
+}
+
+fn config_global_as_names_source(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.as_names.source = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    bgp.as_names.restart(bgp.tx.clone());
+    Some(())
+}
+
+fn config_global_as_names_interval(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.as_names.refresh_interval = if op == ConfigOp::Set {
+        Some(args.u32()? as u64)
+    } else {
+        None
+    };
+    bgp.as_names.restart(bgp.tx.clone());
+    Some(())
+}
+
+fn config_global_irr_source(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.irr_source = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+fn config_origin_watch(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?.trunc();
+    if op == ConfigOp::Set {
+        bgp.origin_watch.prefixes.entry(prefix).or_default();
+    } else {
+        bgp.origin_watch.prefixes.remove(&prefix);
+    }
+    Some(())
+}
+
+fn config_origin_watch_origin_as(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?.trunc();
+    let asn = args.u32()?;
+    let origins = bgp.origin_watch.prefixes.entry(prefix).or_default();
+    if op == ConfigOp::Set {
+        origins.insert(asn);
+    } else {
+        origins.remove(&asn);
+    }
+    Some(())
+}
+
 fn config_peer(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
     if op == ConfigOp::Set {
-        let addr: Ipv4Addr = args.v4addr()?;
-        let peer = Peer::new(addr, bgp.asn, bgp.router_id, 0u32, addr, bgp.tx.clone());
+        let addr: IpAddr = args.addr()?;
+        let mut peer = Peer::new(
+            addr,
+            bgp.asn,
+            bgp.router_id,
+            0u32,
+            addr,
+            bgp.tx.clone(),
+            bgp.updates.tx.clone(),
+        );
+        peer.global_cluster_id = bgp.cluster_id;
+        peer.rr_clients = bgp.rr_clients.clone();
+        peer.confed = bgp.confed.clone();
+        peer.default_local_pref = bgp.bestpath.local_pref;
+        peer.instance_shutdown = bgp.shutdown.clone();
+        peer.mrt_updates = bgp.mrt_dump.writer();
+        if let Some(restart) = bgp.restart.as_mut() {
+            restart_peer(restart, &mut peer);
+        }
         bgp.peers.insert(addr, peer);
+        mirror_lab_update(bgp);
     }
     Some(())
 }
 
 fn config_peer_as(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let peer = bgp.peers.get_mut(&addr)?;
     if op == ConfigOp::Set {
-        let addr: Ipv4Addr = args.v4addr()?;
         let asn: u32 = args.u32()?;
-        if let Some(peer) = bgp.peers.get_mut(&addr) {
-            peer.peer_as = asn;
-            peer.peer_type = if peer.peer_as == bgp.asn {
-                PeerType::Internal
-            } else {
-                PeerType::External
-            };
-            peer.update();
+        peer.config.peer_as = Some(asn);
+        peer.remote_as_set(RemoteAs::Asn(asn));
+    } else {
+        peer.config.peer_as = None;
+    }
+    Some(())
+}
+
+// Same as peer-as, with external and internal keywords for the neighbors
+// which AS is learned from OPEN.
+fn config_remote_as(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let peer = bgp.peers.get_mut(&addr)?;
+    let remote_as = if op == ConfigOp::Set {
+        remote_as_parse(&args.string()?)?
+    } else {
+        peer.config.peer_as.map(RemoteAs::Asn).unwrap_or_default()
+    };
+    peer.remote_as_set(remote_as);
+    Some(())
+}
+
+fn config_peer_group(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let name = args.string()?;
+    if op == ConfigOp::Set {
+        bgp.peers.get_mut(&addr)?.config.peer_group = Some(name.clone());
+        group_member_apply(bgp, addr, &name, op);
+    } else {
+        group_member_apply(bgp, addr, &name, op);
+        bgp.peers.get_mut(&addr)?.config.peer_group = None;
+    }
+    Some(())
+}
+
+// Session is ceased or started at the end of the commit, so that changing
+// the message does not flap the session.
+fn config_peer_shutdown(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let peer = bgp.peers.get_mut(&addr)?;
+    peer.config.shutdown = if op == ConfigOp::Set {
+        Some(args.string().unwrap_or_default())
+    } else {
+        None
+    };
+    bgp.shutdown_peers.insert(addr);
+    Some(())
+}
+
+// All of the sessions are ceased at the end of the commit and no connection is
+// accepted until the shutdown is removed. Configuration is kept as is.
+fn config_shutdown_set(bgp: &mut Bgp, shutdown: Option<String>) {
+    bgp.shutdown = shutdown;
+    for (addr, peer) in bgp.peers.iter_mut() {
+        peer.instance_shutdown = bgp.shutdown.clone();
+        bgp.shutdown_peers.insert(*addr);
+    }
+}
+
+fn config_shutdown(bgp: &mut Bgp, _args: Args, op: ConfigOp) -> Option<()> {
+    let shutdown = if op == ConfigOp::Set {
+        Some(bgp.shutdown.clone().unwrap_or_default())
+    } else {
+        None
+    };
+    config_shutdown_set(bgp, shutdown);
+    Some(())
+}
+
+// Removing the message keeps the instance shut down.
+fn config_shutdown_message(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let shutdown = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        bgp.shutdown.as_ref().map(|_| String::new())
+    };
+    config_shutdown_set(bgp, shutdown);
+    Some(())
+}
+
+// Routes advertised to iBGP neighbors are updated with the new cluster ID.
+fn cluster_id_update(bgp: &mut Bgp) {
+    for peer in bgp.peers.values_mut() {
+        peer.global_cluster_id = bgp.cluster_id;
+        if peer.peer_as == peer.local_as {
+            let _ = bgp.tx.send(Message::Event(peer.address, Event::SoftOut));
+        }
+    }
+}
+
+fn config_global_cluster_id(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.cluster_id = if op == ConfigOp::Set {
+        Some(cluster_id_parse(&args.string()?)?)
+    } else {
+        None
+    };
+    cluster_id_update(bgp);
+    Some(())
+}
+
+fn config_global_confed_id(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.confed.id = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    confed_update(bgp);
+    Some(())
+}
+
+fn config_global_confed_member(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let asn = args.u32()?;
+    if op == ConfigOp::Set {
+        bgp.confed.peers.insert(asn);
+    } else {
+        bgp.confed.peers.remove(&asn);
+    }
+    confed_update(bgp);
+    Some(())
+}
+
+// Cluster ID is either a 4 octets number or dotted quad.
+fn cluster_id_parse(s: &str) -> Option<Ipv4Addr> {
+    match s.parse::<Ipv4Addr>() {
+        Ok(addr) => Some(addr),
+        Err(_) => s.parse::<u32>().ok().map(Ipv4Addr::from),
+    }
+}
+
+fn config_peer_cluster_id(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let cluster_id = if op == ConfigOp::Set {
+        Some(cluster_id_parse(&args.string()?)?)
+    } else {
+        None
+    };
+    let peer = bgp.peers.get_mut(&addr)?;
+    peer.config.cluster_id = cluster_id;
+    let _ = bgp.tx.send(Message::Event(addr, Event::SoftOut));
+    Some(())
+}
+
+// Neighbors look up whether a route is learned from a client when they
+// advertise it.
+fn rr_clients_update(bgp: &mut Bgp) {
+    let clients = bgp
+        .peers
+        .values()
+        .filter(|peer| peer.config.route_reflector_client)
+        .map(|peer| peer.address)
+        .collect();
+    bgp.rr_clients = Arc::new(clients);
+    for peer in bgp.peers.values_mut() {
+        peer.rr_clients = bgp.rr_clients.clone();
+    }
+}
+
+// Routes to the iBGP neighbors are updated for the routes reflected to or
+// from the neighbor.
+fn config_route_reflector_client(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let client = op == ConfigOp::Set && args.boolean()?;
+    let peer = bgp.peers.get_mut(&addr)?;
+    if peer.config.route_reflector_client == client {
+        return Some(());
+    }
+    peer.config.route_reflector_client = client;
+    rr_clients_update(bgp);
+    for peer in bgp.peers.values() {
+        if peer.peer_as == peer.local_as {
+            let _ = bgp.tx.send(Message::Event(peer.address, Event::SoftOut));
+        }
+    }
+    Some(())
+}
+
+// Routes are requested again to be evaluated with the new count.
+fn config_allowas_in(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let count = if op == ConfigOp::Set { args.u8()? } else { 0 };
+    let peer = bgp.peers.get_mut(&addr)?;
+    if peer.config.allowas_in == count {
+        return Some(());
+    }
+    peer.config.allowas_in = count;
+    let _ = bgp.tx.send(Message::Event(addr, Event::SoftIn));
+    Some(())
+}
+
+// Outbound rewrites are applied by advertising the routes again.
+fn config_next_hop_self(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let enable = if op == ConfigOp::Set {
+        args.boolean()?
+    } else {
+        false
+    };
+    let peer = bgp.peers.get_mut(&addr)?;
+    if peer.config.next_hop_self == enable {
+        return Some(());
+    }
+    peer.config.next_hop_self = enable;
+    let _ = bgp.tx.send(Message::Event(addr, Event::SoftOut));
+    Some(())
+}
+
+fn config_remove_private_as(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let option = if op == ConfigOp::Set {
+        match args.string()?.as_str() {
+            "private-as-remove-all" => Some(PrivateAs::RemoveAll),
+            "private-as-replace-all" => Some(PrivateAs::ReplaceAll),
+            _ => return None,
         }
+    } else {
+        None
+    };
+    let peer = bgp.peers.get_mut(&addr)?;
+    if peer.config.remove_private_as == option {
+        return Some(());
+    }
+    peer.config.remove_private_as = option;
+    let _ = bgp.tx.send(Message::Event(addr, Event::SoftOut));
+    Some(())
+}
+
+fn config_as_override(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let enable = if op == ConfigOp::Set {
+        args.boolean()?
+    } else {
+        false
+    };
+    let peer = bgp.peers.get_mut(&addr)?;
+    if peer.config.as_override == enable {
+        return Some(());
     }
+    peer.config.as_override = enable;
+    let _ = bgp.tx.send(Message::Event(addr, Event::SoftOut));
+    Some(())
+}
+
+// Session is reset as the AS in OPEN changes.
+fn config_local_as(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let asn = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    let peer = bgp.peers.get_mut(&addr)?;
+    if peer.config.local_as.asn == asn {
+        return Some(());
+    }
+    peer.config.local_as.asn = asn;
+    if peer.state != State::Idle {
+        let _ = bgp.tx.send(Message::Event(addr, Event::Shutdown(None)));
+    }
+    Some(())
+}
+
+// Routes are received again with or without the local AS.
+fn config_local_as_no_prepend(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let enable = if op == ConfigOp::Set {
+        args.boolean()?
+    } else {
+        false
+    };
+    let peer = bgp.peers.get_mut(&addr)?;
+    if peer.config.local_as.no_prepend == enable {
+        return Some(());
+    }
+    peer.config.local_as.no_prepend = enable;
+    let _ = bgp.tx.send(Message::Event(addr, Event::SoftIn));
+    Some(())
+}
+
+fn config_local_as_replace_as(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let enable = if op == ConfigOp::Set {
+        args.boolean()?
+    } else {
+        false
+    };
+    let peer = bgp.peers.get_mut(&addr)?;
+    if peer.config.local_as.replace_as == enable {
+        return Some(());
+    }
+    peer.config.local_as.replace_as = enable;
+    let _ = bgp.tx.send(Message::Event(addr, Event::SoftOut));
     Some(())
 }
 
 fn config_afi_safi(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
     if op == ConfigOp::Set {
-        let addr: Ipv4Addr = args.v4addr()?;
-        let afi_safi: AfiSafi = args.afi_safi()?;
+        let addr: IpAddr = args.addr()?;
+        // Registered address family when the name is not the built-in one.
+        let afi_safi: AfiSafi = match args.0.front().and_then(|x| bgp.families.lookup(x)) {
+            Some(afi_safi) => afi_safi,
+            None => args.afi_safi()?,
+        };
         if let Some(peer) = bgp.peers.get_mut(&addr) {
             if !peer.config.afi_safi.has(&afi_safi) {
                 peer.config.afi_safi.push(afi_safi);
@@ -63,9 +667,51 @@ fn config_afi_safi(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
     Some(())
 }
 
+// ADD-PATH of the AFI/SAFI takes effect when the session is established
+// next time.
+fn peer_add_path(bgp: &mut Bgp, addr: IpAddr, afi_safi: AfiSafi) -> Option<&mut PeerAddPath> {
+    let peer = bgp.peers.get_mut(&addr)?;
+    let add_path = &mut peer.config.add_path;
+    let index = match add_path.iter().position(|x| x.afi_safi == afi_safi) {
+        Some(index) => index,
+        None => {
+            add_path.push(PeerAddPath::new(afi_safi));
+            add_path.len() - 1
+        }
+    };
+    add_path.get_mut(index)
+}
+
+fn config_add_path_receive(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let afi_safi: AfiSafi = args.afi_safi()?;
+    let receive = op == ConfigOp::Set && args.boolean()?;
+    peer_add_path(bgp, addr, afi_safi)?.receive = receive;
+    Some(())
+}
+
+fn config_add_path_max(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let afi_safi: AfiSafi = args.afi_safi()?;
+    let max = if op == ConfigOp::Set {
+        Some(args.u8()?)
+    } else {
+        None
+    };
+    peer_add_path(bgp, addr, afi_safi)?.send_max = max;
+    Some(())
+}
+
+fn config_add_path_all(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let afi_safi: AfiSafi = args.afi_safi()?;
+    peer_add_path(bgp, addr, afi_safi)?.send_all = op == ConfigOp::Set;
+    Some(())
+}
+
 fn config_local_identifier(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
     if op == ConfigOp::Set {
-        let addr: Ipv4Addr = args.v4addr()?;
+        let addr: IpAddr = args.addr()?;
         let identifier: Ipv4Addr = args.v4addr()?;
         if let Some(peer) = bgp.peers.get_mut(&addr) {
             peer.local_identifier = Some(identifier);
@@ -76,7 +722,7 @@ fn config_local_identifier(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Optio
 }
 
 fn config_transport_passive(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
-    let addr = args.v4addr()?;
+    let addr = args.addr()?;
     let passive = args.boolean()?;
 
     if op == ConfigOp::Set {
@@ -89,36 +735,1531 @@ fn config_transport_passive(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Opti
     Some(())
 }
 
-fn config_hold_time(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+fn config_health_check(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
     if op == ConfigOp::Set {
-        let addr: Ipv4Addr = args.v4addr()?;
-        let hold_time: u16 = args.u16()?;
-        if let Some(peer) = bgp.peers.get_mut(&addr) {
-            peer.config.hold_time = Some(hold_time);
-        }
+        health_add(bgp, &name);
+    } else {
+        health_delete(bgp, &name);
     }
     Some(())
 }
 
-fn config_clist(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
-    let x = CommunityMember::Regexp(String::from("x"));
+fn config_rpki_cache(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr = args.addr()?;
+    if op == ConfigOp::Set {
+        rpki_cache_add(bgp, addr);
+    } else {
+        rpki_cache_delete(bgp, addr);
+    }
     Some(())
 }
 
-impl Bgp {
-    fn callback_peer(&mut self, path: &str, cb: Callback) {
-        let neighbor_prefix = String::from("/routing/bgp/neighbors/neighbor");
-        self.callbacks.insert(neighbor_prefix + path, cb);
+// RTR session is restarted on the new port.
+fn config_rpki_cache_port(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr = args.addr()?;
+    let port = if op == ConfigOp::Set {
+        args.u16()?
+    } else {
+        RTR_PORT
+    };
+    let cache = bgp.rpki.caches.get_mut(&addr)?;
+    if cache.port != port {
+        cache.port = port;
+        rpki_cache_start(bgp, addr);
     }
+    Some(())
+}
 
-    pub fn callback_build(&mut self) {
-        self.callback_add("/routing/bgp/global/as", config_global_asn);
-        self.callback_add("/routing/bgp/global/identifier", config_global_identifier);
-        self.callback_peer("", config_peer);
-        self.callback_peer("/peer-as", config_peer_as);
-        self.callback_peer("/local-identifier", config_local_identifier);
-        self.callback_peer("/transport/passive-mode", config_transport_passive);
-        self.callback_peer("/afi-safis/afi-safi/enabled", config_afi_safi);
-        self.callback_peer("/timers/hold-time", config_hold_time);
+// Update the probe of the health check and restart it. Deleted leaf is
+// reverted to the default.
+fn config_health_probe(
+    bgp: &mut Bgp,
+    mut args: Args,
+    op: ConfigOp,
+    f: fn(&mut ProbeConfig, Option<&mut Args>) -> Option<()>,
+) -> Option<()> {
+    let name = args.string()?;
+    let health = bgp.health.get_mut(&name)?;
+    if op == ConfigOp::Set {
+        f(&mut health.config, Some(&mut args))?;
+    } else {
+        f(&mut health.config, None)?;
+    }
+    health_restart(bgp, &name);
+    Some(())
+}
+
+fn config_health_type(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_health_probe(bgp, args, op, |config, args| {
+        config.typ = match args {
+            Some(args) => args.string()?.parse::<ProbeType>().ok()?,
+            None => ProbeConfig::default().typ,
+        };
+        Some(())
+    })
+}
+
+fn config_health_address(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_health_probe(bgp, args, op, |config, args| {
+        config.address = match args {
+            Some(args) => Some(args.addr()?),
+            None => None,
+        };
+        Some(())
+    })
+}
+
+fn config_health_port(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_health_probe(bgp, args, op, |config, args| {
+        config.port = match args {
+            Some(args) => args.u16()?,
+            None => ProbeConfig::default().port,
+        };
+        Some(())
+    })
+}
+
+fn config_health_path(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_health_probe(bgp, args, op, |config, args| {
+        config.path = match args {
+            Some(args) => args.string()?,
+            None => ProbeConfig::default().path,
+        };
+        Some(())
+    })
+}
+
+fn config_health_interval(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_health_probe(bgp, args, op, |config, args| {
+        config.interval = match args {
+            Some(args) => args.u16()? as u64,
+            None => ProbeConfig::default().interval,
+        };
+        Some(())
+    })
+}
+
+fn config_health_timeout(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_health_probe(bgp, args, op, |config, args| {
+        config.timeout = match args {
+            Some(args) => args.u16()? as u64,
+            None => ProbeConfig::default().timeout,
+        };
+        Some(())
+    })
+}
+
+fn config_health_rise(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_health_probe(bgp, args, op, |config, args| {
+        config.rise = match args {
+            Some(args) => args.u8()? as u32,
+            None => ProbeConfig::default().rise,
+        };
+        Some(())
+    })
+}
+
+fn config_health_fall(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_health_probe(bgp, args, op, |config, args| {
+        config.fall = match args {
+            Some(args) => args.u8()? as u32,
+            None => ProbeConfig::default().fall,
+        };
+        Some(())
+    })
+}
+
+fn config_network(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?;
+    if op == ConfigOp::Set {
+        network_add(bgp, prefix);
+    } else {
+        network_delete(bgp, prefix);
+    }
+    Some(())
+}
+
+fn config_network_health_check(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?;
+    let name = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    network_health_check(bgp, prefix, name);
+    Some(())
+}
+
+fn config_network_med(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?;
+    let med = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    network_update(bgp, prefix, |network| network.med = med);
+    Some(())
+}
+
+fn config_network_local_pref(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?;
+    let local_pref = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    network_update(bgp, prefix, |network| network.local_pref = local_pref);
+    Some(())
+}
+
+fn config_network_community(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?;
+    let community = CommunityAttr::from_str(&args.string()?).ok()?;
+    network_update(bgp, prefix, |network| {
+        for value in community.0.into_iter() {
+            if op == ConfigOp::Set {
+                network.community.insert(value);
+            } else {
+                network.community.remove(&value);
+            }
+        }
+    });
+    Some(())
+}
+
+fn config_network_large_community(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?;
+    let value = LargeCom::from_str(&args.string()?).ok()?;
+    network_update(bgp, prefix, |network| {
+        if op == ConfigOp::Set {
+            network.large_community.insert(value);
+        } else {
+            network.large_community.remove(&value);
+        }
+    });
+    Some(())
+}
+
+fn config_network_ext_community(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?;
+    let value = *ExtendedComAttr::from_str(&args.string()?).ok()?.0.first()?;
+    network_update(bgp, prefix, |network| {
+        if op == ConfigOp::Set {
+            network.ext_community.insert(value);
+        } else {
+            network.ext_community.remove(&value);
+        }
+    });
+    Some(())
+}
+
+fn config_network_label(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?;
+    let label = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    network_update(bgp, prefix, |network| network.label = label);
+    Some(())
+}
+
+fn config_network_route_map(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?;
+    let route_map = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    network_update(bgp, prefix, |network| network.route_map = route_map);
+    Some(())
+}
+
+fn config_vrf(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    if op == ConfigOp::Set {
+        bgp.vpn.vrfs.entry(name).or_default();
+    } else {
+        vrf_delete(bgp, &name);
+    }
+    Some(())
+}
+
+fn config_vrf_rd(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let rd = if op == ConfigOp::Set {
+        Some(RouteDistinguisher::from_str(&args.string()?).ok()?)
+    } else {
+        None
+    };
+    vrf_update(bgp, &name, |vrf| vrf.rd = rd);
+    Some(())
+}
+
+fn config_vrf_rt_import(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let rt = ExtComAdmin::from_str(&args.string()?).ok()?;
+    let vrf = bgp.vpn.vrfs.get_mut(&name)?;
+    if op == ConfigOp::Set {
+        vrf.import.insert(rt);
+    } else {
+        vrf.import.remove(&rt);
+    }
+    bgp.vpn.reimport(&name);
+    Some(())
+}
+
+fn config_vrf_rt_export(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let rt = ExtComAdmin::from_str(&args.string()?).ok()?;
+    vrf_update(bgp, &name, |vrf| {
+        if op == ConfigOp::Set {
+            vrf.export.insert(rt);
+        } else {
+            vrf.export.remove(&rt);
+        }
+    });
+    Some(())
+}
+
+fn config_vrf_network(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let prefix = args.net()?;
+    vrf_update(bgp, &name, |vrf| {
+        if op == ConfigOp::Set {
+            vrf.networks.insert(prefix);
+        } else {
+            vrf.networks.remove(&prefix);
+        }
+    });
+    Some(())
+}
+
+fn config_multipath_ebgp(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.multipath.config.ebgp = if op == ConfigOp::Set { args.u32()? } else { 1 };
+    multipath_config_update(bgp);
+    Some(())
+}
+
+fn config_multipath_ibgp(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.multipath.config.ibgp = if op == ConfigOp::Set { args.u32()? } else { 1 };
+    multipath_config_update(bgp);
+    Some(())
+}
+
+fn config_multipath_multiple_as(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.multipath.config.allow_multiple_as = op == ConfigOp::Set && args.boolean()?;
+    multipath_config_update(bgp);
+    Some(())
+}
+
+fn config_bestpath_compare_med(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.bestpath.always_compare_med = op == ConfigOp::Set && args.boolean()?;
+    route_bestpath_update(bgp);
+    Some(())
+}
+
+fn config_bestpath_deterministic_med(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.bestpath.deterministic_med = op == ConfigOp::Set && args.boolean()?;
+    route_bestpath_update(bgp);
+    Some(())
+}
+
+fn config_bestpath_router_id(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.bestpath.compare_router_id = op != ConfigOp::Set || args.boolean()?;
+    route_bestpath_update(bgp);
+    Some(())
+}
+
+// Default LOCAL_PREF is used in the selection and sent to iBGP peers, so the
+// whole table is advertised again.
+fn config_default_local_pref(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.bestpath.local_pref = if op == ConfigOp::Set {
+        args.u32()?
+    } else {
+        BGP_LOCAL_PREF
+    };
+    for peer in bgp.peers.values_mut() {
+        peer.default_local_pref = bgp.bestpath.local_pref;
+    }
+    route_bestpath_update(bgp);
+    let prefixes: Vec<_> = bgp.ptree.iter().map(|(prefix, _)| *prefix).collect();
+    let prefixes6: Vec<_> = bgp.ptree6.iter().map(|(prefix, _)| *prefix).collect();
+    route_advertise(bgp, &prefixes, &prefixes6);
+    Some(())
+}
+
+fn config_redistribute(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let source = RedistSource::from_str(&args.string()?).ok()?;
+    if op == ConfigOp::Set {
+        bgp.redistribute.config.entry(source).or_default();
+    } else {
+        bgp.redistribute.config.remove(&source);
+    }
+    redist_source_apply(bgp, source);
+    Some(())
+}
+
+fn config_redistribute_metric(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let source = RedistSource::from_str(&args.string()?).ok()?;
+    let metric = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    redist_update(bgp, source, |config| config.metric = metric);
+    Some(())
+}
+
+fn config_redistribute_route_map(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let source = RedistSource::from_str(&args.string()?).ok()?;
+    let route_map = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    redist_update(bgp, source, |config| config.route_map = route_map);
+    Some(())
+}
+
+fn config_listen_limit(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    bgp.listen_range.limit = if op == ConfigOp::Set {
+        args.u32()?
+    } else {
+        DYNAMIC_PEER_LIMIT
+    };
+    Some(())
+}
+
+fn config_listen_range(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?.trunc();
+    if op == ConfigOp::Set {
+        bgp.listen_range.ranges.entry(prefix).or_insert(None);
+    } else {
+        bgp.listen_range.ranges.remove(&prefix);
+        dynamic_range_clear(bgp, &prefix);
+    }
+    Some(())
+}
+
+// Established dynamic peers keep the settings of the old group until they
+// are removed, so the peers of the range are closed on the change.
+fn config_listen_range_peer_group(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let prefix = args.net()?.trunc();
+    let name = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    if let Some(group) = bgp.listen_range.ranges.get_mut(&prefix) {
+        *group = name;
+    }
+    dynamic_range_clear(bgp, &prefix);
+    Some(())
+}
+
+fn config_unnumbered(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    if op == ConfigOp::Set {
+        unnumbered_add(bgp, &name);
+    } else {
+        unnumbered_delete(bgp, &name);
+    }
+    Some(())
+}
+
+fn config_unnumbered_peer_as(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let peer_as = if op == ConfigOp::Set { args.u32()? } else { 0 };
+    bgp.unnumbered.get_mut(&name)?.peer_as = (op == ConfigOp::Set).then_some(peer_as);
+    unnumbered_remote_as(bgp, &name, RemoteAs::Asn(peer_as));
+    Some(())
+}
+
+// AS number, or external and internal keywords.
+fn remote_as_parse(s: &str) -> Option<RemoteAs> {
+    match s {
+        "external" => Some(RemoteAs::External),
+        "internal" => Some(RemoteAs::Internal),
+        _ => s.parse::<u32>().ok().map(RemoteAs::Asn),
+    }
+}
+
+fn config_unnumbered_remote_as(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let remote_as = if op == ConfigOp::Set {
+        remote_as_parse(&args.string()?)?
+    } else {
+        let peer_as = bgp.unnumbered.get(&name)?.peer_as;
+        peer_as.map(RemoteAs::Asn).unwrap_or_default()
+    };
+    unnumbered_remote_as(bgp, &name, remote_as);
+    Some(())
+}
+
+fn config_message_journal(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let enabled = op == ConfigOp::Set && args.boolean()?;
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.message_journal = enabled;
+        peer.msg_journal = if enabled {
+            bgp.msg_journal.start(addr)
+        } else {
+            None
+        };
+    }
+    Some(())
+}
+
+// Applied to the next connection attempt. The value is an address or an
+// interface name.
+fn config_transport_local_address(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let update_source = if op == ConfigOp::Set {
+        let value = args.string()?;
+        match value.parse::<IpAddr>() {
+            Ok(local_address) => Some(UpdateSource::Address(local_address)),
+            Err(_) => Some(UpdateSource::Interface(value)),
+        }
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.transport.update_source = update_source;
+    }
+    Some(())
+}
+
+// Applied to the next connection.
+fn config_transport_ebgp_multihop(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let enabled = op == ConfigOp::Set && args.boolean()?;
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.transport.ebgp_multihop = enabled;
+    }
+    Some(())
+}
+
+fn config_transport_multihop_ttl(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let ttl = if op == ConfigOp::Set {
+        Some(args.u8()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.transport.multihop_ttl = ttl;
+    }
+    Some(())
+}
+
+// Applied to the next connection attempt. Listener of the VRF is started at
+// the end of the commit.
+fn config_transport_vrf(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let vrf = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.transport.vrf = vrf;
+    }
+    Some(())
+}
+
+// Applied to the next connection attempt. Key of the listener is set at the
+// end of the commit.
+fn config_transport_password(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let password = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.transport.password = password;
+    }
+    Some(())
+}
+
+// Applied to the next connection.
+fn config_transport_ttl_security(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let hops = if op == ConfigOp::Set {
+        Some(args.u8()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.transport.ttl_security = hops;
+    }
+    Some(())
+}
+
+fn config_hold_time(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let hold_time = if op == ConfigOp::Set {
+        Some(args.u16()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.hold_time = hold_time;
+        peer.timer_update();
+    }
+    Some(())
+}
+
+fn config_graceful_restart(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let enabled = op == ConfigOp::Set && args.boolean()?;
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.graceful_restart.enabled = enabled;
+    }
+    Some(())
+}
+
+fn config_restart_time(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let restart_time = if op == ConfigOp::Set {
+        Some(args.u16()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.graceful_restart.restart_time = restart_time;
+    }
+    Some(())
+}
+
+fn config_stale_routes_time(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let stale_routes_time = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.graceful_restart.stale_routes_time = stale_routes_time;
+    }
+    Some(())
+}
+
+fn config_keepalive(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let keepalive = if op == ConfigOp::Set {
+        Some(args.u16()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.keepalive = keepalive;
+        peer.timer_update();
+    }
+    Some(())
+}
+
+fn config_connect_retry(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let connect_retry = if op == ConfigOp::Set {
+        Some(args.u16()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.connect_retry = connect_retry;
+        peer.timer_update();
+    }
+    Some(())
+}
+
+fn config_route_adv_interval(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let interval = if op == ConfigOp::Set {
+        Some(args.u16()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.route_adv_interval = interval;
+    }
+    Some(())
+}
+
+fn config_coalesce_window(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let window = if op == ConfigOp::Set { args.u32()? } else { 0 };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.coalesce_window = window;
+        // Changes held in the window are sent at once when it is disabled.
+        if window == 0 && peer.timer.coalesce.take().is_some() {
+            peer.event(peer.ident, Event::CoalesceTimerExpires);
+        }
+    }
+    Some(())
+}
+
+fn config_refresh_interval(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let interval = if op == ConfigOp::Set {
+        Some(args.u16()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.refresh_interval = interval;
+    }
+    Some(())
+}
+
+fn config_refresh_stale_time(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let stale_time = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.refresh_stale_time = stale_time;
+    }
+    Some(())
+}
+
+// Lab neighbor must be another configured neighbor.
+fn config_mirror_neighbor(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let to = if op == ConfigOp::Set {
+        let to: IpAddr = args.addr()?;
+        if to == addr {
+            println!("mirror: {} can not be mirrored to itself", addr);
+            return None;
+        }
+        Some(to)
+    } else {
+        None
+    };
+    let peer = bgp.peers.get_mut(&addr)?;
+    peer.config.mirror.to = to;
+    peer.mirror_queue.clear();
+    mirror_lab_update(bgp);
+    Some(())
+}
+
+fn config_mirror_direction(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let direction = if op == ConfigOp::Set {
+        match args.string()?.as_str() {
+            "received" => MirrorDirection::Received,
+            "sent" => MirrorDirection::Sent,
+            "both" => MirrorDirection::Both,
+            _ => return None,
+        }
+    } else {
+        MirrorDirection::default()
+    };
+    let peer = bgp.peers.get_mut(&addr)?;
+    peer.config.mirror.direction = direction;
+    Some(())
+}
+
+fn config_max_prefixes(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let max = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.prefix_limit.max_prefixes = max;
+    }
+    Some(())
+}
+
+fn config_warning_threshold(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let pct = if op == ConfigOp::Set {
+        Some(args.u8()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.prefix_limit.warning_threshold_pct = pct;
+    }
+    Some(())
+}
+
+fn config_prefix_limit_teardown(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let teardown = if op == ConfigOp::Set {
+        args.boolean()?
+    } else {
+        false
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.prefix_limit.teardown = teardown;
+    }
+    Some(())
+}
+
+fn config_prefix_list(
+    bgp: &mut Bgp,
+    mut args: Args,
+    op: ConfigOp,
+    direction: PolicyDirection,
+) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let name = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        match direction {
+            PolicyDirection::In => peer.config.prefix_list_in = name,
+            PolicyDirection::Out => peer.config.prefix_list_out = name,
+        }
+        bgp.policy_peers.insert((addr, direction));
+    }
+    Some(())
+}
+
+fn config_route_map_in(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let name = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        peer.config.route_map_in = name;
+        bgp.policy_peers.insert((addr, PolicyDirection::In));
+    }
+    Some(())
+}
+
+fn config_prefix_list_in(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_prefix_list(bgp, args, op, PolicyDirection::In)
+}
+
+fn config_prefix_list_out(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_prefix_list(bgp, args, op, PolicyDirection::Out)
+}
+
+// Takes effect with the next session, as the capability is exchanged in OPEN.
+fn config_orf(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let orf = if op == ConfigOp::Set {
+        match args.string()?.as_str() {
+            "send" => ORF_SEND,
+            "receive" => ORF_RECEIVE,
+            "both" => ORF_SEND | ORF_RECEIVE,
+            _ => return None,
+        }
+    } else {
+        0
+    };
+    bgp.peers.get_mut(&addr)?.config.orf = orf;
+    Some(())
+}
+
+fn config_large_community_list(
+    bgp: &mut Bgp,
+    mut args: Args,
+    op: ConfigOp,
+    direction: PolicyDirection,
+) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let name = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        match direction {
+            PolicyDirection::In => peer.config.large_community_list_in = name,
+            PolicyDirection::Out => peer.config.large_community_list_out = name,
+        }
+        bgp.policy_peers.insert((addr, direction));
+    }
+    Some(())
+}
+
+fn config_ext_community_list(
+    bgp: &mut Bgp,
+    mut args: Args,
+    op: ConfigOp,
+    direction: PolicyDirection,
+) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let name = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        match direction {
+            PolicyDirection::In => peer.config.ext_community_list_in = name,
+            PolicyDirection::Out => peer.config.ext_community_list_out = name,
+        }
+        bgp.policy_peers.insert((addr, direction));
+    }
+    Some(())
+}
+
+fn config_ext_community_list_in(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_ext_community_list(bgp, args, op, PolicyDirection::In)
+}
+
+fn config_ext_community_list_out(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_ext_community_list(bgp, args, op, PolicyDirection::Out)
+}
+
+fn config_large_community_list_in(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_large_community_list(bgp, args, op, PolicyDirection::In)
+}
+
+fn config_large_community_list_out(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_large_community_list(bgp, args, op, PolicyDirection::Out)
+}
+
+// Routes are refreshed with the new attributes, received ones by
+// ROUTE-REFRESH and advertised ones by sending them again.
+fn config_large_community_add(
+    bgp: &mut Bgp,
+    mut args: Args,
+    op: ConfigOp,
+    direction: PolicyDirection,
+) -> Option<()> {
+    let addr: IpAddr = args.addr()?;
+    let value = LargeCom::from_str(&args.string()?).ok()?;
+    let peer = bgp.peers.get_mut(&addr)?;
+    let values = match direction {
+        PolicyDirection::In => &mut peer.config.large_community_in,
+        PolicyDirection::Out => &mut peer.config.large_community_out,
+    };
+    if op == ConfigOp::Set {
+        values.insert(value);
+    } else {
+        values.remove(&value);
+    }
+    let event = match direction {
+        PolicyDirection::In => Event::SoftIn,
+        PolicyDirection::Out => Event::SoftOut,
+    };
+    let _ = bgp.tx.send(Message::Event(addr, event));
+    Some(())
+}
+
+fn config_large_community_add_in(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_large_community_add(bgp, args, op, PolicyDirection::In)
+}
+
+fn config_large_community_add_out(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_large_community_add(bgp, args, op, PolicyDirection::Out)
+}
+
+// Policy change is applied to routes when the commit ends.
+fn config_policy(
+    bgp: &mut Bgp,
+    args: Args,
+    op: ConfigOp,
+    f: fn(&mut Policy, Args, ConfigOp) -> Option<()>,
+) -> Option<()> {
+    let name = args.0.front()?.clone();
+    f(&mut bgp.policy, args, op)?;
+    bgp.policy_changed.insert(name);
+    Some(())
+}
+
+fn config_plist(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_plist)
+}
+
+fn config_plist_seq(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_plist_seq)
+}
+
+fn config_plist_action(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_plist_action)
+}
+
+fn config_plist_prefix(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_plist_prefix)
+}
+
+fn config_plist_le(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_plist_le)
+}
+
+fn config_plist_ge(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_plist_ge)
+}
+
+fn config_lclist(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_lclist)
+}
+
+fn config_lclist_seq(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_lclist_seq)
+}
+
+fn config_lclist_action(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_lclist_action)
+}
+
+fn config_lclist_member(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_lclist_member)
+}
+
+fn config_eclist(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_eclist)
+}
+
+fn config_eclist_seq(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_eclist_seq)
+}
+
+fn config_eclist_action(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_eclist_action)
+}
+
+fn config_eclist_member(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_eclist_member)
+}
+
+fn config_rmap(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_rmap)
+}
+
+fn config_rmap_seq(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_rmap_seq)
+}
+
+fn config_rmap_action(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_rmap_action)
+}
+
+fn config_rmap_match_prefix_list(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_rmap_match_prefix_list)
+}
+
+fn config_rmap_match_rpki(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_rmap_match_rpki)
+}
+
+fn config_rmap_set_med(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_rmap_set_med)
+}
+
+fn config_rmap_set_local_pref(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_rmap_set_local_pref)
+}
+
+fn config_rmap_set_origin(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_rmap_set_origin)
+}
+
+fn config_rmap_set_community(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_rmap_set_community)
+}
+
+fn config_aslist(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_aslist)
+}
+
+fn config_aslist_seq(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_aslist_seq)
+}
+
+fn config_aslist_action(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_aslist_action)
+}
+
+fn config_aslist_regexp(bgp: &mut Bgp, args: Args, op: ConfigOp) -> Option<()> {
+    config_policy(bgp, args, op, policy::config_aslist_regexp)
+}
+
+fn config_clist(bgp: &mut Bgp, mut args: Args, op: ConfigOp) -> Option<()> {
+    let x = CommunityMember::Regexp(String::from("x"));
+    Some(())
+}
+
+// Config subtrees sent to BGP, which cover the paths of the callbacks.
+pub const BGP_SUBTREES: &[&str] = &[
+    "/routing/bgp",
+    "/community-list",
+    "/large-community-list",
+    "/extended-community-list",
+    "/prefix-list",
+    "/as-path-list",
+    "/route-map",
+];
+
+// YANG modules of BGP merged into the exec and the configuration trees.
+pub const BGP_YANG_MODULES: &[(&str, &str)] = &[("exec", "exec-bgp"), ("config", "config-bgp")];
+
+impl Bgp {
+    fn callback_peer(&mut self, path: &str, cb: Callback) {
+        let neighbor_prefix = String::from("/routing/bgp/neighbors/neighbor");
+        self.callbacks.insert(neighbor_prefix + path, cb);
+    }
+
+    pub fn callback_build(&mut self) {
+        self.callback_add("/routing/bgp/global/as", config_global_asn);
+        self.callback_add("/routing/bgp/shutdown", config_shutdown);
+        self.callback_add("/routing/bgp/shutdown/message", config_shutdown_message);
+        self.callback_add("/routing/bgp/global/identifier", config_global_identifier);
+        self.callback_add(
+            "/routing/bgp/global/monitor-only",
+            config_global_monitor_only,
+        );
+        self.callback_add("/routing/bgp/global/show-cache", config_global_show_cache);
+        self.callback_add("/routing/bgp/global/parse-mode", config_global_parse_mode);
+        self.callback_add(
+            "/routing/bgp/global/nexthop-resolution/max-depth",
+            config_global_nexthop_max_depth,
+        );
+        self.callback_add(
+            "/routing/bgp/global/nexthop-resolution/resolve-over-bgp",
+            config_global_nexthop_resolve_over_bgp,
+        );
+        self.callback_add(
+            "/routing/bgp/global/nexthop-resolution/prefix-list",
+            config_global_nexthop_prefix_list,
+        );
+        self.callback_add(
+            "/routing/bgp/global/persistence/directory",
+            config_global_persist_directory,
+        );
+        self.callback_add(
+            "/routing/bgp/global/persistence/snapshot-interval",
+            config_global_persist_interval,
+        );
+        self.callback_add(
+            "/routing/bgp/global/message-journal/directory",
+            config_global_msg_journal_directory,
+        );
+        self.callback_add(
+            "/routing/bgp/global/message-journal/max-size",
+            config_global_msg_journal_max_size,
+        );
+        self.callback_add(
+            "/routing/bgp/global/mrt-dump/directory",
+            config_global_mrt_dump_directory,
+        );
+        self.callback_add(
+            "/routing/bgp/global/mrt-dump/table-interval",
+            config_global_mrt_dump_table_interval,
+        );
+        self.callback_add(
+            "/routing/bgp/global/mrt-dump/table-keep",
+            config_global_mrt_dump_table_keep,
+        );
+        self.callback_add(
+            "/routing/bgp/global/mrt-dump/updates",
+            config_global_mrt_dump_updates,
+        );
+        self.callback_add(
+            "/routing/bgp/global/mrt-dump/max-size",
+            config_global_mrt_dump_max_size,
+        );
+        self.callback_add(
+            "/routing/bgp/global/sla/metrics-file",
+            config_global_sla_metrics_file,
+        );
+        self.callback_add(
+            "/routing/bgp/global/graceful-restart/enabled",
+            config_global_restart_enabled,
+        );
+        self.callback_add(
+            "/routing/bgp/global/graceful-restart/helper-only",
+            config_global_restart_helper_only,
+        );
+        self.callback_add(
+            "/routing/bgp/global/graceful-restart/stale-routes-time",
+            config_global_restart_deferral,
+        );
+        self.callback_add(
+            "/routing/bgp/global/graceful-restart/state-directory",
+            config_global_restart_directory,
+        );
+        self.callback_add("/routing/bgp/global/audit-file", config_global_audit_file);
+        self.callback_add("/routing/bgp/global/webhook/url", config_global_webhook_url);
+        self.callback_add(
+            "/routing/bgp/global/as-names/source",
+            config_global_as_names_source,
+        );
+        self.callback_add(
+            "/routing/bgp/global/as-names/refresh-interval",
+            config_global_as_names_interval,
+        );
+        self.callback_add("/routing/bgp/global/irr/source", config_global_irr_source);
+        self.callback_add(
+            "/routing/bgp/global/route-reflector/cluster-id",
+            config_global_cluster_id,
+        );
+        self.callback_add(
+            "/routing/bgp/global/confederation/identifier",
+            config_global_confed_id,
+        );
+        self.callback_add(
+            "/routing/bgp/global/confederation/member-as",
+            config_global_confed_member,
+        );
+        self.callback_add("/routing/bgp/global/origin-watch", config_origin_watch);
+        self.callback_add(
+            "/routing/bgp/global/origin-watch/origin-as",
+            config_origin_watch_origin_as,
+        );
+        self.callback_add("/routing/bgp/global/health-check", config_health_check);
+        self.callback_add("/routing/bgp/global/health-check/type", config_health_type);
+        self.callback_add(
+            "/routing/bgp/global/health-check/address",
+            config_health_address,
+        );
+        self.callback_add("/routing/bgp/global/health-check/port", config_health_port);
+        self.callback_add("/routing/bgp/global/health-check/path", config_health_path);
+        self.callback_add(
+            "/routing/bgp/global/health-check/interval",
+            config_health_interval,
+        );
+        self.callback_add(
+            "/routing/bgp/global/health-check/timeout",
+            config_health_timeout,
+        );
+        self.callback_add("/routing/bgp/global/health-check/rise", config_health_rise);
+        self.callback_add("/routing/bgp/global/health-check/fall", config_health_fall);
+        self.callback_add("/routing/bgp/global/rpki/cache", config_rpki_cache);
+        self.callback_add(
+            "/routing/bgp/global/rpki/cache/port",
+            config_rpki_cache_port,
+        );
+        self.callback_add("/routing/bgp/global/network", config_network);
+        self.callback_add(
+            "/routing/bgp/global/network/health-check",
+            config_network_health_check,
+        );
+        self.callback_add("/routing/bgp/global/network/med", config_network_med);
+        self.callback_add(
+            "/routing/bgp/global/network/local-pref",
+            config_network_local_pref,
+        );
+        self.callback_add(
+            "/routing/bgp/global/network/community",
+            config_network_community,
+        );
+        self.callback_add(
+            "/routing/bgp/global/network/large-community",
+            config_network_large_community,
+        );
+        self.callback_add(
+            "/routing/bgp/global/network/extended-community",
+            config_network_ext_community,
+        );
+        self.callback_add("/routing/bgp/global/network/label", config_network_label);
+        self.callback_add(
+            "/routing/bgp/global/network/route-map",
+            config_network_route_map,
+        );
+        self.callback_add("/routing/bgp/global/vrf", config_vrf);
+        self.callback_add("/routing/bgp/global/vrf/rd", config_vrf_rd);
+        self.callback_add(
+            "/routing/bgp/global/vrf/route-target/import",
+            config_vrf_rt_import,
+        );
+        self.callback_add(
+            "/routing/bgp/global/vrf/route-target/export",
+            config_vrf_rt_export,
+        );
+        self.callback_add("/routing/bgp/global/vrf/network", config_vrf_network);
+        self.callback_add(
+            "/routing/bgp/global/use-multiple-paths/ebgp/maximum-paths",
+            config_multipath_ebgp,
+        );
+        self.callback_add(
+            "/routing/bgp/global/use-multiple-paths/ebgp/allow-multiple-as",
+            config_multipath_multiple_as,
+        );
+        self.callback_add(
+            "/routing/bgp/global/use-multiple-paths/ibgp/maximum-paths",
+            config_multipath_ibgp,
+        );
+        self.callback_add(
+            "/routing/bgp/global/route-selection-options/always-compare-med",
+            config_bestpath_compare_med,
+        );
+        self.callback_add(
+            "/routing/bgp/global/route-selection-options/deterministic-med",
+            config_bestpath_deterministic_med,
+        );
+        self.callback_add(
+            "/routing/bgp/global/route-selection-options/external-compare-router-id",
+            config_bestpath_router_id,
+        );
+        self.callback_add(
+            "/routing/bgp/global/default-local-preference",
+            config_default_local_pref,
+        );
+        self.callback_add("/routing/bgp/global/redistribute", config_redistribute);
+        self.callback_add(
+            "/routing/bgp/global/redistribute/metric",
+            config_redistribute_metric,
+        );
+        self.callback_add(
+            "/routing/bgp/global/redistribute/route-map",
+            config_redistribute_route_map,
+        );
+        self.callback_add("/routing/bgp/global/listen/limit", config_listen_limit);
+        self.callback_add("/routing/bgp/global/listen/range", config_listen_range);
+        self.callback_add(
+            "/routing/bgp/global/listen/range/peer-group",
+            config_listen_range_peer_group,
+        );
+        self.callback_peer("", config_peer);
+        self.callback_add("/routing/bgp/neighbors/interface", config_unnumbered);
+        self.callback_add(
+            "/routing/bgp/neighbors/interface/peer-as",
+            config_unnumbered_peer_as,
+        );
+        self.callback_add(
+            "/routing/bgp/neighbors/interface/remote-as",
+            config_unnumbered_remote_as,
+        );
+        self.callback_peer("/peer-as", config_peer_as);
+        self.callback_peer("/remote-as", config_remote_as);
+        self.callback_peer("/peer-group", config_peer_group);
+        self.callback_peer("/shutdown", config_peer_shutdown);
+        self.callback_peer("/shutdown/message", config_peer_shutdown);
+        self.callback_peer("/local-identifier", config_local_identifier);
+        self.callback_peer("/transport/passive-mode", config_transport_passive);
+        self.callback_peer("/transport/local-address", config_transport_local_address);
+        self.callback_peer(
+            "/transport/ebgp-multihop/enabled",
+            config_transport_ebgp_multihop,
+        );
+        self.callback_peer(
+            "/transport/ebgp-multihop/multihop-ttl",
+            config_transport_multihop_ttl,
+        );
+        self.callback_peer("/transport/vrf", config_transport_vrf);
+        self.callback_peer("/transport/password", config_transport_password);
+        self.callback_peer("/transport/ttl-security", config_transport_ttl_security);
+        self.callback_peer("/afi-safis/afi-safi/enabled", config_afi_safi);
+        self.callback_peer(
+            "/afi-safis/afi-safi/add-paths/receive",
+            config_add_path_receive,
+        );
+        self.callback_peer("/afi-safis/afi-safi/add-paths/max", config_add_path_max);
+        self.callback_peer("/afi-safis/afi-safi/add-paths/all", config_add_path_all);
+        self.callback_peer("/timers/hold-time", config_hold_time);
+        self.callback_peer("/timers/keepalive", config_keepalive);
+        self.callback_peer("/timers/connect-retry-interval", config_connect_retry);
+        self.callback_peer(
+            "/timers/min-route-advertisement-interval",
+            config_route_adv_interval,
+        );
+        self.callback_peer("/timers/coalesce-window", config_coalesce_window);
+        self.callback_peer("/timers/route-refresh-interval", config_refresh_interval);
+        self.callback_peer(
+            "/timers/route-refresh-stale-time",
+            config_refresh_stale_time,
+        );
+        self.callback_peer("/graceful-restart/enabled", config_graceful_restart);
+        self.callback_peer("/graceful-restart/restart-time", config_restart_time);
+        self.callback_peer(
+            "/graceful-restart/stale-routes-time",
+            config_stale_routes_time,
+        );
+        self.callback_peer("/prefix-limit/max-prefixes", config_max_prefixes);
+        self.callback_peer(
+            "/prefix-limit/warning-threshold-pct",
+            config_warning_threshold,
+        );
+        self.callback_peer("/prefix-limit/teardown", config_prefix_limit_teardown);
+        self.callback_peer("/route-reflector/cluster-id", config_peer_cluster_id);
+        self.callback_peer("/route-reflector/client", config_route_reflector_client);
+        self.callback_peer("/as-path-options/allow-own-as", config_allowas_in);
+        self.callback_peer("/as-path-options/replace-peer-as", config_as_override);
+        self.callback_peer("/remove-private-as", config_remove_private_as);
+        self.callback_peer("/next-hop-self", config_next_hop_self);
+        self.callback_peer("/local-as", config_local_as);
+        self.callback_peer("/local-as-options/no-prepend", config_local_as_no_prepend);
+        self.callback_peer("/local-as-options/replace-as", config_local_as_replace_as);
+        self.callback_peer("/prefix-list/in", config_prefix_list_in);
+        self.callback_peer("/prefix-list/out", config_prefix_list_out);
+        self.callback_peer("/prefix-list/orf", config_orf);
+        self.callback_peer("/route-map/in", config_route_map_in);
+        self.callback_peer("/large-community-list/in", config_large_community_list_in);
+        self.callback_peer("/large-community-list/out", config_large_community_list_out);
+        self.callback_peer("/extended-community-list/in", config_ext_community_list_in);
+        self.callback_peer(
+            "/extended-community-list/out",
+            config_ext_community_list_out,
+        );
+        self.callback_peer("/large-community/add-in", config_large_community_add_in);
+        self.callback_peer("/large-community/add-out", config_large_community_add_out);
+        self.callback_peer("/message-journal", config_message_journal);
+        self.callback_peer("/mirror/neighbor", config_mirror_neighbor);
+        self.callback_peer("/mirror/direction", config_mirror_direction);
+        self.callback_add("/prefix-list", config_plist);
+        self.callback_add("/prefix-list/seq", config_plist_seq);
+        self.callback_add("/prefix-list/seq/action", config_plist_action);
+        self.callback_add("/prefix-list/seq/prefix", config_plist_prefix);
+        self.callback_add("/prefix-list/seq/le", config_plist_le);
+        self.callback_add("/prefix-list/seq/ge", config_plist_ge);
+        self.callback_add("/as-path-list", config_aslist);
+        self.callback_add("/as-path-list/seq", config_aslist_seq);
+        self.callback_add("/as-path-list/seq/action", config_aslist_action);
+        self.callback_add("/as-path-list/seq/regexp", config_aslist_regexp);
+        self.callback_add("/large-community-list", config_lclist);
+        self.callback_add("/large-community-list/seq", config_lclist_seq);
+        self.callback_add("/large-community-list/seq/action", config_lclist_action);
+        self.callback_add("/large-community-list/seq/member", config_lclist_member);
+        self.callback_add("/extended-community-list", config_eclist);
+        self.callback_add("/extended-community-list/seq", config_eclist_seq);
+        self.callback_add("/extended-community-list/seq/action", config_eclist_action);
+        self.callback_add("/extended-community-list/seq/member", config_eclist_member);
+        self.callback_add("/route-map", config_rmap);
+        self.callback_add("/route-map/seq", config_rmap_seq);
+        self.callback_add("/route-map/seq/action", config_rmap_action);
+        self.callback_add(
+            "/route-map/seq/match/prefix-list",
+            config_rmap_match_prefix_list,
+        );
+        self.callback_add("/route-map/seq/match/rpki", config_rmap_match_rpki);
+        self.callback_add("/route-map/seq/set/med", config_rmap_set_med);
+        self.callback_add(
+            "/route-map/seq/set/local-preference",
+            config_rmap_set_local_pref,
+        );
+        self.callback_add("/route-map/seq/set/origin", config_rmap_set_origin);
+        self.callback_add("/route-map/seq/set/community", config_rmap_set_community);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{parse_bgp_packet, shutdown_message_decode, BgpPacket};
+    use crate::bgp::peer::peer_shutdown_update;
+    use crate::rib::RibTxChannel;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn instance_shutdown() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let addr: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65002,
+            addr,
+            bgp.tx.clone(),
+            bgp.tx.clone(),
+        );
+        let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+        peer.packet_tx = Some(packet_tx);
+        peer.state = State::Established;
+        bgp.peers.insert(addr, peer);
+
+        // Session is ceased with the message at the end of the commit.
+        let args = Args(vec![String::from("maintenance")].into());
+        config_shutdown_message(&mut bgp, args, ConfigOp::Set);
+        assert_eq!(bgp.peers[&addr].state, State::Established);
+        peer_shutdown_update(&mut bgp);
+        let peer = &bgp.peers[&addr];
+        assert_eq!(peer.state, State::Idle);
+        assert!(peer.timer.idle_hold_timer.is_none());
+        let bytes = packet_rx.try_recv().unwrap();
+        let notification = match parse_bgp_packet(&bytes, true) {
+            Ok((_, BgpPacket::Notification(notification))) => notification,
+            _ => panic!("not a notification packet"),
+        };
+        let message = shutdown_message_decode(
+            notification.code.0,
+            notification.sub_code,
+            &notification.data,
+        );
+        assert_eq!(message.as_deref(), Some("maintenance"));
+
+        // Removing the message keeps the instance shut down.
+        config_shutdown_message(&mut bgp, Args(Vec::new().into()), ConfigOp::Delete);
+        peer_shutdown_update(&mut bgp);
+        assert_eq!(bgp.shutdown.as_deref(), Some(""));
+        assert!(bgp.peers[&addr].timer.idle_hold_timer.is_none());
+
+        // Removing the shutdown starts the session again.
+        config_shutdown(&mut bgp, Args(Vec::new().into()), ConfigOp::Delete);
+        assert!(bgp.shutdown.is_none());
+        peer_shutdown_update(&mut bgp);
+        let peer = &bgp.peers[&addr];
+        assert!(peer.shutdown().is_none());
+        assert_eq!(peer.state, State::Idle);
+        assert!(peer.timer.idle_hold_timer.is_some());
+    }
+
+    #[tokio::test]
+    async fn remote_as_delete() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let addr: IpAddr = "10.0.0.2".parse().unwrap();
+        let peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            0,
+            addr,
+            bgp.tx.clone(),
+            bgp.tx.clone(),
+        );
+        bgp.peers.insert(addr, peer);
+        let args = |x: &[&str]| Args(x.iter().map(|x| x.to_string()).collect());
+
+        config_peer_as(&mut bgp, args(&["10.0.0.2", "65002"]), ConfigOp::Set);
+        config_remote_as(&mut bgp, args(&["10.0.0.2", "external"]), ConfigOp::Set);
+        assert_eq!(bgp.peers[&addr].remote_as, RemoteAs::External);
+
+        // Configured peer-as applies again.
+        config_remote_as(&mut bgp, args(&["10.0.0.2", "external"]), ConfigOp::Delete);
+        assert_eq!(bgp.peers[&addr].remote_as, RemoteAs::Asn(65002));
+        assert_eq!(bgp.peers[&addr].peer_as, 65002);
+
+        config_peer_as(&mut bgp, args(&["10.0.0.2", "65002"]), ConfigOp::Delete);
+        config_remote_as(&mut bgp, args(&["10.0.0.2", "internal"]), ConfigOp::Set);
+        config_remote_as(&mut bgp, args(&["10.0.0.2", "internal"]), ConfigOp::Delete);
+        assert_eq!(bgp.peers[&addr].remote_as, RemoteAs::default());
+    }
+
+    #[test]
+    fn route_reflector_client() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        for addr in ["10.0.0.2", "10.0.0.3"] {
+            let addr: IpAddr = addr.parse().unwrap();
+            let peer = Peer::new(
+                addr,
+                65001,
+                Ipv4Addr::new(10, 0, 0, 1),
+                65001,
+                addr,
+                bgp.tx.clone(),
+                bgp.tx.clone(),
+            );
+            bgp.peers.insert(addr, peer);
+        }
+        let client: IpAddr = "10.0.0.2".parse().unwrap();
+        let args = || Args(vec![String::from("10.0.0.2"), String::from("true")].into());
+
+        // Every neighbor sees the client, so the routes already learned from
+        // it are reflected.
+        config_route_reflector_client(&mut bgp, args(), ConfigOp::Set);
+        assert!(bgp
+            .peers
+            .values()
+            .all(|peer| peer.rr_clients.contains(&client)));
+
+        config_route_reflector_client(&mut bgp, args(), ConfigOp::Delete);
+        assert!(bgp.peers.values().all(|peer| peer.rr_clients.is_empty()));
     }
 }
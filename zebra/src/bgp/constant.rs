@@ -1,3 +1,21 @@
 pub const BGP_VERSION: u8 = 4;
 pub const BGP_PORT: u16 = 179;
 pub const BGP_HOLD_TIME: u16 = 90;
+pub const BGP_CONNECT_RETRY: u16 = 5;
+pub const BGP_LOCAL_PREF: u32 = 100;
+// MinRouteAdvertisementInterval suggested by RFC 4271 10.
+pub const BGP_ROUTE_ADV_EBGP: u16 = 30;
+pub const BGP_ROUTE_ADV_IBGP: u16 = 5;
+// Graceful restart times (RFC 4724). Restart time should not exceed the hold
+// time.
+pub const BGP_RESTART_TIME: u16 = 90;
+pub const BGP_STALE_ROUTES_TIME: u32 = 360;
+// Minimum interval of the re-advertisements requested with ROUTE-REFRESH, and
+// time the stale paths of Enhanced Route Refresh are kept without EoRR.
+pub const BGP_REFRESH_INTERVAL: u16 = 5;
+pub const BGP_REFRESH_STALE_TIME: u32 = 360;
+// IdleHoldTime is doubled on each session failure up to the max, and reset
+// once the session stays up for the stable time (RFC 4271 8.1.1).
+pub const BGP_IDLE_HOLD_TIME: u64 = 1;
+pub const BGP_IDLE_HOLD_TIME_MAX: u64 = 120;
+pub const BGP_IDLE_HOLD_STABLE: u64 = 300;
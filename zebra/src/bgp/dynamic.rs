@@ -0,0 +1,164 @@
+use super::group::group_member_apply;
+use super::handler::Bgp;
+use super::peer::{fsm, Event, Peer, RemoteAs, State};
+use crate::config::ConfigOp;
+use ipnet::IpNet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+
+pub const DYNAMIC_PEER_LIMIT: u32 = 100;
+
+// Dynamic neighbors. Connection from an address in one of the listen ranges
+// creates a passive peer from the peer group of the range. The peer is
+// removed when the session goes down.
+#[derive(Debug)]
+pub struct ListenRange {
+    pub ranges: BTreeMap<IpNet, Option<String>>,
+    pub limit: u32,
+    pub peers: BTreeSet<IpAddr>,
+}
+
+impl Default for ListenRange {
+    fn default() -> Self {
+        Self {
+            ranges: BTreeMap::new(),
+            limit: DYNAMIC_PEER_LIMIT,
+            peers: BTreeSet::new(),
+        }
+    }
+}
+
+impl ListenRange {
+    // Peer group of the longest range containing the address.
+    pub fn lookup(&self, addr: &IpAddr) -> Option<&String> {
+        self.ranges
+            .iter()
+            .filter(|(prefix, _)| prefix.contains(addr))
+            .max_by_key(|(prefix, _)| prefix.prefix_len())
+            .and_then(|(_, group)| group.as_ref())
+    }
+}
+
+// Instantiate the peer for the inbound connection. Returns true when the
+// peer is created.
+pub fn dynamic_accept(bgp: &mut Bgp, addr: IpAddr, vrf: &Option<String>) -> bool {
+    let name = match bgp.listen_range.lookup(&addr) {
+        Some(name) => name.clone(),
+        None => return false,
+    };
+    if vrf.is_some() {
+        println!("dynamic: {} is not accepted in VRF {:?}", addr, vrf);
+        return false;
+    }
+    if !bgp.peer_groups.groups.contains_key(&name) {
+        println!("dynamic: {} peer group {} does not exist", addr, name);
+        return false;
+    }
+    if bgp.listen_range.peers.len() >= bgp.listen_range.limit as usize {
+        println!(
+            "dynamic: {} rejected, limit {} reached",
+            addr, bgp.listen_range.limit
+        );
+        return false;
+    }
+    // Dynamic peer never initiates the connection, so it is made passive
+    // before the group settings start the FSM.
+    let mut peer = Peer::new(
+        addr,
+        bgp.asn,
+        bgp.router_id,
+        0u32,
+        addr,
+        bgp.tx.clone(),
+        bgp.updates.tx.clone(),
+    );
+    peer.global_cluster_id = bgp.cluster_id;
+    peer.rr_clients = bgp.rr_clients.clone();
+    peer.confed = bgp.confed.clone();
+    peer.default_local_pref = bgp.bestpath.local_pref;
+    peer.instance_shutdown = bgp.shutdown.clone();
+    peer.mrt_updates = bgp.mrt_dump.writer();
+    peer.config.transport.passive = true;
+    peer.config.peer_group = Some(name.clone());
+    bgp.peers.insert(addr, peer);
+    group_member_apply(bgp, addr, &name, ConfigOp::Set);
+
+    let peer = match bgp.peers.get_mut(&addr) {
+        Some(peer) => peer,
+        None => return false,
+    };
+    peer.config.transport.passive = true;
+    peer.timer.idle_hold_timer = None;
+    if peer.remote_as == RemoteAs::default() {
+        println!("dynamic: {} peer group {} has no remote-as", addr, name);
+        bgp.peers.remove(&addr);
+        return false;
+    }
+    println!("dynamic: {} created from peer group {}", addr, name);
+    bgp.listen_range.peers.insert(addr);
+    true
+}
+
+// Remove the dynamic peer once the session is down. Peer waiting for the
+// graceful restart is kept until the restart timer expires.
+pub fn dynamic_release(bgp: &mut Bgp, addr: IpAddr) {
+    if !bgp.listen_range.peers.contains(&addr) {
+        return;
+    }
+    let idle = bgp
+        .peers
+        .get(&addr)
+        .map(|peer| peer.state == State::Idle && peer.timer.restart.is_none())
+        .unwrap_or(true);
+    if idle {
+        println!("dynamic: {} removed", addr);
+        bgp.listen_range.peers.remove(&addr);
+        bgp.peers.remove(&addr);
+    }
+}
+
+// Peers of the range are closed when the range is deleted or moved to
+// another peer group.
+pub fn dynamic_range_clear(bgp: &mut Bgp, prefix: &IpNet) {
+    let addrs: Vec<IpAddr> = bgp
+        .listen_range
+        .peers
+        .iter()
+        .filter(|addr| prefix.contains(*addr))
+        .copied()
+        .collect();
+    for addr in addrs.into_iter() {
+        if bgp.peers.contains_key(&addr) {
+            fsm(bgp, addr, Event::Stop);
+        }
+        bgp.listen_range.peers.remove(&addr);
+        bgp.peers.remove(&addr);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup_longest() {
+        let mut listen = ListenRange::default();
+        listen
+            .ranges
+            .insert("10.0.0.0/8".parse().unwrap(), Some("wide".to_string()));
+        listen
+            .ranges
+            .insert("10.1.0.0/16".parse().unwrap(), Some("narrow".to_string()));
+        listen.ranges.insert("10.2.0.0/16".parse().unwrap(), None);
+
+        let addr: IpAddr = "10.1.0.1".parse().unwrap();
+        assert_eq!(listen.lookup(&addr).map(|x| x.as_str()), Some("narrow"));
+        let addr: IpAddr = "10.3.0.1".parse().unwrap();
+        assert_eq!(listen.lookup(&addr).map(|x| x.as_str()), Some("wide"));
+        // Range without peer group is not ready.
+        let addr: IpAddr = "10.2.0.1".parse().unwrap();
+        assert_eq!(listen.lookup(&addr), None);
+        let addr: IpAddr = "192.168.0.1".parse().unwrap();
+        assert_eq!(listen.lookup(&addr), None);
+    }
+}
@@ -0,0 +1,274 @@
+use super::handler::Bgp;
+use super::route::Route;
+use crate::config::Args;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Route of Loc-RIB flattened for the export. Rows are taken from the table
+// in the show callback, then written to the file by a blocking task so that
+// the event loop is not held by the file I/O and the encoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportRow {
+    pub prefix: String,
+    pub peer: String,
+    pub best: bool,
+    pub nexthop: String,
+    pub as_path: String,
+    pub origin: char,
+    pub local_pref: Option<u32>,
+    pub med: Option<u32>,
+    pub community: String,
+    pub large_community: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "csv" => Some(Self::Csv),
+            "parquet" => Some(Self::Parquet),
+            _ => None,
+        }
+    }
+}
+
+const CSV_HEADER: &str =
+    "prefix,peer,best,nexthop,as_path,origin,local_pref,med,community,large_community\n";
+
+fn export_row(prefix: String, nexthop: String, route: &Route) -> ExportRow {
+    ExportRow {
+        prefix,
+        peer: route.from.to_string(),
+        best: route.selected,
+        nexthop,
+        as_path: route.aspath(),
+        origin: route.origin(),
+        local_pref: route.local_pref(),
+        med: route.med(),
+        community: route
+            .community()
+            .map(|community| community.to_string())
+            .unwrap_or_default(),
+        large_community: route
+            .large_community()
+            .map(|lcom| lcom.to_string())
+            .unwrap_or_default(),
+    }
+}
+
+pub fn export_rows(bgp: &Bgp) -> Vec<ExportRow> {
+    let mut rows = Vec::new();
+    for (prefix, routes) in bgp.ptree.iter() {
+        for route in routes.iter() {
+            let nexthop = route
+                .nexthop()
+                .map(|nexthop| nexthop.to_string())
+                .unwrap_or_default();
+            rows.push(export_row(prefix.to_string(), nexthop, route));
+        }
+    }
+    for (prefix, routes) in bgp.ptree6.iter() {
+        for route in routes.iter() {
+            let nexthop = route
+                .nexthop6()
+                .map(|nexthop| nexthop.to_string())
+                .unwrap_or_default();
+            rows.push(export_row(prefix.to_string(), nexthop, route));
+        }
+    }
+    rows
+}
+
+// Field is quoted when it contains the delimiter or the quote (RFC 4180).
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_encode(rows: &[ExportRow]) -> String {
+    let mut out = String::from(CSV_HEADER);
+    for row in rows.iter() {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{}",
+            csv_field(&row.prefix),
+            csv_field(&row.peer),
+            row.best,
+            csv_field(&row.nexthop),
+            csv_field(&row.as_path),
+            row.origin,
+            row.local_pref.map(|x| x.to_string()).unwrap_or_default(),
+            row.med.map(|x| x.to_string()).unwrap_or_default(),
+            csv_field(&row.community),
+            csv_field(&row.large_community),
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_file {
+    use super::ExportRow;
+    use parquet::data_type::{BoolType, ByteArray, ByteArrayType, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::fs::File;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    const SCHEMA: &str = "
+        message bgp_table {
+            required binary prefix (UTF8);
+            required binary peer (UTF8);
+            required boolean best;
+            required binary nexthop (UTF8);
+            required binary as_path (UTF8);
+            required binary origin (UTF8);
+            optional int64 local_pref;
+            optional int64 med;
+            required binary community (UTF8);
+            required binary large_community (UTF8);
+        }
+    ";
+
+    fn strings(rows: &[ExportRow], f: impl Fn(&ExportRow) -> String) -> Vec<ByteArray> {
+        rows.iter()
+            .map(|row| ByteArray::from(f(row).as_str()))
+            .collect()
+    }
+
+    // Values of the optional column with the definition levels, 1 when the
+    // value is present.
+    fn optional(rows: &[ExportRow], f: impl Fn(&ExportRow) -> Option<u32>) -> (Vec<i64>, Vec<i16>) {
+        let values = rows.iter().filter_map(&f).map(|x| x as i64).collect();
+        let levels = rows.iter().map(|row| f(row).is_some() as i16).collect();
+        (values, levels)
+    }
+
+    pub fn parquet_write(path: &Path, rows: &[ExportRow]) -> anyhow::Result<()> {
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(path)?, schema, props)?;
+        let mut group = writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = group.next_column()? {
+            match index {
+                2 => {
+                    let values: Vec<bool> = rows.iter().map(|row| row.best).collect();
+                    column
+                        .typed::<BoolType>()
+                        .write_batch(&values, None, None)?;
+                }
+                6 | 7 => {
+                    let (values, levels) = if index == 6 {
+                        optional(rows, |row| row.local_pref)
+                    } else {
+                        optional(rows, |row| row.med)
+                    };
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&values, Some(&levels), None)?;
+                }
+                _ => {
+                    let values = match index {
+                        0 => strings(rows, |row| row.prefix.clone()),
+                        1 => strings(rows, |row| row.peer.clone()),
+                        3 => strings(rows, |row| row.nexthop.clone()),
+                        4 => strings(rows, |row| row.as_path.clone()),
+                        5 => strings(rows, |row| row.origin.to_string()),
+                        8 => strings(rows, |row| row.community.clone()),
+                        _ => strings(rows, |row| row.large_community.clone()),
+                    };
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&values, None, None)?;
+                }
+            }
+            column.close()?;
+            index += 1;
+        }
+        group.close()?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+fn export_write(path: &Path, format: ExportFormat, rows: &[ExportRow]) -> anyhow::Result<()> {
+    match format {
+        ExportFormat::Csv => fs::write(path, csv_encode(rows))?,
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => parquet_file::parquet_write(path, rows)?,
+        #[cfg(not(feature = "parquet"))]
+        ExportFormat::Parquet => anyhow::bail!("parquet export is not enabled in this build"),
+    }
+    Ok(())
+}
+
+// request export bgp table FILE [format csv|parquet]
+pub fn request_export_bgp_table(bgp: &Bgp, mut args: Args) -> String {
+    let path = match args.string() {
+        Some(path) => PathBuf::from(path),
+        None => return String::from("% File name is required\n"),
+    };
+    let format = match args.string() {
+        Some(format) => match ExportFormat::parse(&format) {
+            Some(format) => format,
+            None => return format!("% Unknown export format {}\n", format),
+        },
+        None => ExportFormat::Csv,
+    };
+    if cfg!(not(feature = "parquet")) && format == ExportFormat::Parquet {
+        return String::from("% Parquet export is not enabled in this build\n");
+    }
+    let rows = export_rows(bgp);
+    let count = rows.len();
+    let display = path.display().to_string();
+    tokio::task::spawn_blocking(move || match export_write(&path, format, &rows) {
+        Ok(_) => println!(
+            "export: {} routes written to {}",
+            rows.len(),
+            path.display()
+        ),
+        Err(err) => println!("export: {} error: {}", path.display(), err),
+    });
+    format!("Exporting {} routes to {}\n", count, display)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn csv_quote() {
+        let row = ExportRow {
+            prefix: "10.0.0.0/8".to_string(),
+            peer: "192.168.0.1".to_string(),
+            best: true,
+            nexthop: "192.168.0.1".to_string(),
+            as_path: "65001 65002".to_string(),
+            origin: 'i',
+            local_pref: Some(100),
+            med: None,
+            community: "65001:1 \"no-export\"".to_string(),
+            large_community: "65001:1:2".to_string(),
+        };
+        assert_eq!(
+            csv_encode(&[row]),
+            format!(
+                "{}10.0.0.0/8,192.168.0.1,true,192.168.0.1,65001 65002,i,100,,\"65001:1 \"\"no-export\"\"\",65001:1:2\n",
+                CSV_HEADER
+            )
+        );
+    }
+}
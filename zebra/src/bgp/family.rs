@@ -0,0 +1,270 @@
+use super::handler::Bgp;
+use super::packet::{Attribute, Attrs, MpRawAttr};
+use super::ptree::PrefixTree;
+use super::route::Route;
+use super::{Afi, AfiSafi, Safi};
+use crate::config::Args;
+use bytes::{BufMut, BytesMut};
+use ipnet::Ipv4Net;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+
+// NLRI of the address family in the wire format, which the family module
+// splits and renders.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Nlri(pub Vec<u8>);
+
+// Peer the NLRI is received from and the unicast RIBs, for the family which
+// validates the NLRI against unicast routes.
+pub struct FamilyContext<'a> {
+    pub from: IpAddr,
+    pub peer_as: u32,
+    pub local_as: u32,
+    pub ptree: &'a PrefixTree<Ipv4Net, Vec<Route>>,
+}
+
+// Address family other than IPv4 and IPv6 unicast, e.g. FlowSpec, EVPN or
+// BGP-LS. The core hands the NLRI field of MP_REACH_NLRI and MP_UNREACH_NLRI
+// of the AFI/SAFI to the module, so that a new SAFI is added without touching
+// the packet and the RIB code.
+pub trait AddressFamily: Send {
+    fn afi_safi(&self) -> AfiSafi;
+
+    // Name of the AFI/SAFI in the config, e.g. "ipv4-flowspec".
+    fn name(&self) -> &str;
+
+    // Split the NLRI field into NLRIs. None when the field is malformed.
+    fn decode(&self, input: &[u8]) -> Option<Vec<Nlri>>;
+
+    fn encode(&self, nlri: &[Nlri], buf: &mut BytesMut) {
+        for nlri in nlri.iter() {
+            buf.put(&nlri.0[..]);
+        }
+    }
+
+    // Whether the NLRI is accepted to the RIB. The path of the NLRI which is
+    // not accepted is withdrawn.
+    fn validate(&self, _nlri: &Nlri, _attrs: &Attrs, _ctx: &FamilyContext) -> bool {
+        true
+    }
+
+    // Called when the path of the NLRI is added or withdrawn in the RIB.
+    fn rib_update(&mut self, _nlri: &Nlri, _from: IpAddr, _attrs: Option<&Attrs>) {}
+
+    // NLRI in show output.
+    fn show(&self, nlri: &Nlri) -> String;
+}
+
+pub struct Family {
+    pub module: Box<dyn AddressFamily>,
+    pub rib: BTreeMap<Nlri, BTreeMap<IpAddr, Attrs>>,
+}
+
+impl Family {
+    fn update(&mut self, nlri: Nlri, from: IpAddr, attrs: Attrs) {
+        self.module.rib_update(&nlri, from, Some(&attrs));
+        self.rib.entry(nlri).or_default().insert(from, attrs);
+    }
+
+    fn withdraw(&mut self, nlri: &Nlri, from: IpAddr) {
+        let paths = match self.rib.get_mut(nlri) {
+            Some(paths) => paths,
+            None => return,
+        };
+        if paths.remove(&from).is_none() {
+            return;
+        }
+        if paths.is_empty() {
+            self.rib.remove(nlri);
+        }
+        self.module.rib_update(nlri, from, None);
+    }
+}
+
+// Registered address families.
+#[derive(Default)]
+pub struct Families(pub Vec<Family>);
+
+impl Families {
+    pub fn register(&mut self, module: Box<dyn AddressFamily>) {
+        let afi_safi = module.afi_safi();
+        self.0.retain(|x| x.module.afi_safi() != afi_safi);
+        self.0.push(Family {
+            module,
+            rib: BTreeMap::new(),
+        });
+    }
+
+    pub fn get(&self, afi: &Afi, safi: &Safi) -> Option<&Family> {
+        let afi_safi = AfiSafi::new(afi.clone(), safi.clone());
+        self.0.iter().find(|x| x.module.afi_safi() == afi_safi)
+    }
+
+    fn get_mut(&mut self, afi: &Afi, safi: &Safi) -> Option<&mut Family> {
+        let afi_safi = AfiSafi::new(afi.clone(), safi.clone());
+        self.0.iter_mut().find(|x| x.module.afi_safi() == afi_safi)
+    }
+
+    // AFI/SAFI of the config name.
+    pub fn lookup(&self, name: &str) -> Option<AfiSafi> {
+        self.0
+            .iter()
+            .find(|x| x.module.name() == name)
+            .map(|x| x.module.afi_safi())
+    }
+
+    // MP_REACH_NLRI of the family. Attributes are stored without the NLRI.
+    // None when the family is not registered or the NLRI is malformed.
+    pub fn reach(&mut self, ctx: &FamilyContext, mp: &MpRawAttr, attrs: &Attrs) -> Option<()> {
+        let family = self.get_mut(&mp.afi, &mp.safi)?;
+        let nlris = family.module.decode(&mp.nlri)?;
+        let attrs: Attrs = attrs
+            .iter()
+            .map(|attr| match attr {
+                Attribute::MpReachRaw(mp) => Attribute::MpReachRaw(MpRawAttr {
+                    nlri: Vec::new(),
+                    ..mp.clone()
+                }),
+                _ => attr.clone(),
+            })
+            .filter(|attr| !matches!(attr, Attribute::MpUnreachRaw(_)))
+            .collect();
+        for nlri in nlris.into_iter() {
+            if family.module.validate(&nlri, &attrs, ctx) {
+                family.update(nlri, ctx.from, attrs.clone());
+            } else {
+                println!("Reject {} from {}", family.module.show(&nlri), ctx.from);
+                family.withdraw(&nlri, ctx.from);
+            }
+        }
+        Some(())
+    }
+
+    pub fn unreach(&mut self, from: IpAddr, mp: &MpRawAttr) -> Option<()> {
+        let family = self.get_mut(&mp.afi, &mp.safi)?;
+        let nlris = family.module.decode(&mp.nlri)?;
+        for nlri in nlris.iter() {
+            family.withdraw(nlri, from);
+        }
+        Some(())
+    }
+
+    // Withdraw all of the paths from the peer.
+    pub fn peer_clear(&mut self, from: IpAddr) {
+        for family in self.0.iter_mut() {
+            let nlris: Vec<Nlri> = family
+                .rib
+                .iter()
+                .filter(|(_, paths)| paths.contains_key(&from))
+                .map(|(nlri, _)| nlri.clone())
+                .collect();
+            for nlri in nlris.iter() {
+                family.withdraw(nlri, from);
+            }
+        }
+    }
+
+    pub fn show(&self) -> String {
+        let mut out = String::new();
+        for family in self.0.iter() {
+            let afi_safi = family.module.afi_safi();
+            writeln!(
+                out,
+                "Address family {} (AFI {} SAFI {}), {} NLRIs",
+                family.module.name(),
+                afi_safi.afi.0,
+                afi_safi.safi.0,
+                family.rib.len()
+            )
+            .unwrap();
+            for (nlri, paths) in family.rib.iter() {
+                let from: Vec<String> = paths.keys().map(|x| x.to_string()).collect();
+                writeln!(
+                    out,
+                    "  {} from {}",
+                    family.module.show(nlri),
+                    from.join(", ")
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+pub fn show_bgp_families(bgp: &Bgp, _args: Args) -> String {
+    bgp.families.show()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // NLRI of a length octet followed by the value.
+    struct TestFamily;
+
+    impl AddressFamily for TestFamily {
+        fn afi_safi(&self) -> AfiSafi {
+            AfiSafi::new(Afi(1), Safi(133))
+        }
+
+        fn name(&self) -> &str {
+            "ipv4-flowspec"
+        }
+
+        fn decode(&self, mut input: &[u8]) -> Option<Vec<Nlri>> {
+            let mut nlris = Vec::new();
+            while let Some(len) = input.first() {
+                let len = *len as usize + 1;
+                nlris.push(Nlri(input.get(..len)?.to_vec()));
+                input = &input[len..];
+            }
+            Some(nlris)
+        }
+
+        fn show(&self, nlri: &Nlri) -> String {
+            format!("{:?}", &nlri.0[1..])
+        }
+    }
+
+    #[test]
+    fn family_update_withdraw() {
+        let mut families = Families::default();
+        families.register(Box::new(TestFamily));
+        assert!(families.lookup("ipv4-flowspec").is_some());
+
+        let from: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut mp = MpRawAttr {
+            afi: Afi(1),
+            safi: Safi(133),
+            next_hop: Vec::new(),
+            nlri: vec![2, 10, 20, 1, 30],
+        };
+        let attrs = vec![Attribute::MpReachRaw(mp.clone())];
+        let ptree = PrefixTree::new();
+        let ctx = FamilyContext {
+            from,
+            peer_as: 65001,
+            local_as: 65000,
+            ptree: &ptree,
+        };
+        assert!(families.reach(&ctx, &mp, &attrs).is_some());
+        let family = families.get(&Afi(1), &Safi(133)).unwrap();
+        assert_eq!(family.rib.len(), 2);
+        assert!(families.show().contains("[10, 20] from 192.0.2.1"));
+
+        mp.nlri = vec![1, 30];
+        assert!(families.unreach(from, &mp).is_some());
+        assert_eq!(families.get(&Afi(1), &Safi(133)).unwrap().rib.len(), 1);
+
+        // Malformed NLRI and unregistered family.
+        mp.nlri = vec![3, 10];
+        assert!(families.reach(&ctx, &mp, &attrs).is_none());
+        mp.safi = Safi(134);
+        assert!(families.unreach(from, &mp).is_none());
+
+        families.peer_clear(from);
+        assert!(families.get(&Afi(1), &Safi(133)).unwrap().rib.is_empty());
+    }
+}
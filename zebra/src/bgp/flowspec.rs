@@ -0,0 +1,290 @@
+use super::family::{AddressFamily, FamilyContext, Nlri};
+use super::handler::Bgp;
+use super::packet::{
+    flowspec_components, flowspec_split, Attribute, Attrs, ExtendedCom, FlowComponent,
+    FLOWSPEC_DEST_PREFIX,
+};
+use super::{Afi, AfiSafi, Safi};
+use crate::config::Args;
+use bytes::BytesMut;
+use std::cmp::Ordering;
+use std::fmt::Write;
+
+// IPv4 FlowSpec (RFC 8955). The FlowSpec RIB is the RIB of the family, and
+// NLRIs are validated against the unicast routes before they are accepted.
+pub struct FlowSpec;
+
+impl AddressFamily for FlowSpec {
+    fn afi_safi(&self) -> AfiSafi {
+        AfiSafi::new(Afi::IP, Safi::FlowSpec)
+    }
+
+    fn name(&self) -> &str {
+        "ipv4-flowspec"
+    }
+
+    fn decode(&self, input: &[u8]) -> Option<Vec<Nlri>> {
+        let mut nlris = Vec::new();
+        for nlri in flowspec_split(input)?.into_iter() {
+            flowspec_components(nlri)?;
+            nlris.push(Nlri(nlri.to_vec()));
+        }
+        Some(nlris)
+    }
+
+    fn validate(&self, nlri: &Nlri, attrs: &Attrs, ctx: &FamilyContext) -> bool {
+        match flowspec_components(&nlri.0) {
+            Some(components) => flowspec_feasible(&components, attrs, ctx),
+            None => false,
+        }
+    }
+
+    fn show(&self, nlri: &Nlri) -> String {
+        match flowspec_components(&nlri.0) {
+            Some(components) => {
+                let rule: Vec<String> = components.iter().map(|x| x.to_string()).collect();
+                rule.join(" ")
+            }
+            None => "malformed".to_string(),
+        }
+    }
+}
+
+// FlowSpec route is feasible when it has the destination prefix, the best
+// unicast route of the destination is learned from the same peer, and no
+// more specific unicast route is learned from another neighbor AS (RFC 8955
+// 6). Leftmost AS of the path from the eBGP peer must be the peer AS.
+fn flowspec_feasible(components: &[FlowComponent], attrs: &Attrs, ctx: &FamilyContext) -> bool {
+    let dest = match components.first() {
+        Some(FlowComponent::Prefix(FLOWSPEC_DEST_PREFIX, prefix)) => prefix,
+        _ => return false,
+    };
+    if ctx.peer_as != ctx.local_as {
+        let neighbor_as = attrs.iter().find_map(|attr| match attr {
+            Attribute::As4Path(aspath) => aspath.neighbor_as(),
+            _ => None,
+        });
+        if neighbor_as != Some(ctx.peer_as) {
+            return false;
+        }
+    }
+    let best = match ctx.ptree.lookup(dest) {
+        Some((_, routes)) => routes.iter().find(|x| x.selected),
+        None => None,
+    };
+    let best = match best {
+        Some(best) if best.from == ctx.from => best,
+        _ => return false,
+    };
+    let neighbor_as = best.as4path().and_then(|x| x.neighbor_as());
+    for (_, routes) in ctx.ptree.subtree(dest) {
+        let more = routes.iter().find(|x| x.selected);
+        if let Some(more) = more {
+            if more.as4path().and_then(|x| x.neighbor_as()) != neighbor_as {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn flowspec_component_bytes(component: &FlowComponent) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    component.encode(&mut buf);
+    buf.to_vec()
+}
+
+// Order of precedence of the rules (RFC 8955 5.1). Component of the lower
+// type comes first. Of the same prefix type, the lower address comes first
+// unless the prefixes overlap, in which case the more specific one does.
+// Other types are compared as octet strings, where the longer one comes first
+// when one is the prefix of the other. Rule with more components comes first
+// when all of the compared components are the same.
+pub fn flowspec_cmp(a: &[FlowComponent], b: &[FlowComponent]) -> Ordering {
+    for (x, y) in a.iter().zip(b.iter()) {
+        if x.typ() != y.typ() {
+            return x.typ().cmp(&y.typ());
+        }
+        let order = match (x, y) {
+            (FlowComponent::Prefix(_, x), FlowComponent::Prefix(_, y)) => {
+                if x.contains(y) || y.contains(x) {
+                    y.prefix_len().cmp(&x.prefix_len())
+                } else {
+                    x.network().cmp(&y.network())
+                }
+            }
+            _ => {
+                let x = flowspec_component_bytes(x);
+                let y = flowspec_component_bytes(y);
+                let common = x.len().min(y.len());
+                match x[..common].cmp(&y[..common]) {
+                    Ordering::Equal => y.len().cmp(&x.len()),
+                    order => order,
+                }
+            }
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    b.len().cmp(&a.len())
+}
+
+fn flowspec_actions(attrs: &Attrs) -> String {
+    let mut actions = Vec::new();
+    for attr in attrs.iter() {
+        if let Attribute::ExtendedCom(ecom) = attr {
+            for ecom in ecom.0.iter() {
+                if ecom.rate().is_some() || matches!(ecom, ExtendedCom::Redirect(_)) {
+                    actions.push(ecom.to_string());
+                }
+            }
+        }
+    }
+    if actions.is_empty() {
+        "accept".to_string()
+    } else {
+        actions.join(", ")
+    }
+}
+
+pub fn show_bgp_flowspec(bgp: &Bgp, _args: Args) -> String {
+    let mut out = String::new();
+    let family = match bgp.families.get(&Afi::IP, &Safi::FlowSpec) {
+        Some(family) => family,
+        None => return out,
+    };
+    let mut rules: Vec<_> = family
+        .rib
+        .iter()
+        .filter_map(|(nlri, paths)| Some((flowspec_components(&nlri.0)?, nlri, paths)))
+        .collect();
+    rules.sort_by(|a, b| flowspec_cmp(&a.0, &b.0));
+    for (_, nlri, paths) in rules.iter() {
+        writeln!(out, "{}", family.module.show(nlri)).unwrap();
+        for (from, attrs) in paths.iter() {
+            writeln!(out, "  from {}: {}", from, flowspec_actions(attrs)).unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{
+        flowspec_encode, As4PathAttr, As4Segment, FlowOp, AS_SEQUENCE, FLOWSPEC_DEST_PORT,
+        FLOWSPEC_IP_PROTO, OP_EQ,
+    };
+    use crate::bgp::ptree::PrefixTree;
+    use crate::bgp::route::Route;
+    use crate::bgp::rpki::RpkiState;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn route(from: &str, asn: u32) -> Route {
+        let aspath = As4PathAttr {
+            segments: vec![As4Segment {
+                typ: AS_SEQUENCE,
+                asn: vec![asn],
+            }],
+        };
+        Route {
+            from: from.parse().unwrap(),
+            router_id: Ipv4Addr::UNSPECIFIED,
+            attrs: vec![Attribute::As4Path(aspath)],
+            ibgp: false,
+            weight: 0,
+            metric: 0,
+            selected: true,
+            resolved: true,
+            path_id: 0,
+            local_path_id: 1,
+            local_label: None,
+            multipath: false,
+            rpki: RpkiState::NotFound,
+        }
+    }
+
+    fn rule(dest: &str, port: u64) -> Vec<FlowComponent> {
+        let mut rule = vec![FlowComponent::Prefix(
+            FLOWSPEC_DEST_PREFIX,
+            dest.parse().unwrap(),
+        )];
+        if port != 0 {
+            let op = FlowOp {
+                and: false,
+                op: OP_EQ,
+                value: port,
+            };
+            rule.push(FlowComponent::Ops(FLOWSPEC_DEST_PORT, vec![op]));
+        }
+        rule
+    }
+
+    #[test]
+    fn flowspec_validation() {
+        let mut ptree = PrefixTree::new();
+        ptree.insert(
+            "10.0.0.0/16".parse().unwrap(),
+            vec![route("192.0.2.1", 65001)],
+        );
+        let from: IpAddr = "192.0.2.1".parse().unwrap();
+        let ctx = FamilyContext {
+            from,
+            peer_as: 65001,
+            local_as: 65000,
+            ptree: &ptree,
+        };
+        let attrs = route("192.0.2.1", 65001).attrs;
+        let nlri = |rule: &[FlowComponent]| Nlri(flowspec_encode(rule).unwrap());
+        assert!(FlowSpec.validate(&nlri(&rule("10.0.1.0/24", 80)), &attrs, &ctx));
+
+        // No unicast route, another peer, or no destination prefix.
+        assert!(!FlowSpec.validate(&nlri(&rule("10.1.0.0/24", 80)), &attrs, &ctx));
+        let other = FamilyContext {
+            from: "192.0.2.2".parse().unwrap(),
+            ..ctx
+        };
+        assert!(!FlowSpec.validate(&nlri(&rule("10.0.1.0/24", 0)), &attrs, &other));
+        let op = FlowOp {
+            and: false,
+            op: OP_EQ,
+            value: 6,
+        };
+        let no_dest = vec![FlowComponent::Ops(FLOWSPEC_IP_PROTO, vec![op])];
+        assert!(!FlowSpec.validate(&nlri(&no_dest), &attrs, &ctx));
+
+        // More specific route from another neighbor AS.
+        ptree.insert(
+            "10.0.1.128/25".parse().unwrap(),
+            vec![route("192.0.2.3", 65003)],
+        );
+        let ctx = FamilyContext {
+            from,
+            peer_as: 65001,
+            local_as: 65000,
+            ptree: &ptree,
+        };
+        assert!(!FlowSpec.validate(&nlri(&rule("10.0.1.0/24", 80)), &attrs, &ctx));
+        assert!(FlowSpec.validate(&nlri(&rule("10.0.2.0/24", 80)), &attrs, &ctx));
+
+        // Leftmost AS is not the peer AS.
+        let attrs = route("192.0.2.1", 65009).attrs;
+        assert!(!FlowSpec.validate(&nlri(&rule("10.0.2.0/24", 80)), &attrs, &ctx));
+    }
+
+    #[test]
+    fn flowspec_order() {
+        let mut rules = vec![
+            rule("10.0.0.0/8", 0),
+            rule("10.0.1.0/24", 0),
+            rule("10.0.1.0/24", 80),
+            rule("10.0.0.0/24", 0),
+        ];
+        rules.sort_by(|a, b| flowspec_cmp(a, b));
+        assert_eq!(rules[0], rule("10.0.0.0/24", 0));
+        assert_eq!(rules[1], rule("10.0.1.0/24", 80));
+        assert_eq!(rules[2], rule("10.0.1.0/24", 0));
+        assert_eq!(rules[3], rule("10.0.0.0/8", 0));
+    }
+}
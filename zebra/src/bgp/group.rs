@@ -0,0 +1,200 @@
+use super::handler::Bgp;
+use crate::config::{Args, ConfigOp};
+use std::collections::{BTreeMap, VecDeque};
+use std::net::IpAddr;
+
+const NEIGHBOR: &str = "/routing/bgp/neighbors/neighbor";
+const PEER_GROUP: &str = "/routing/bgp/peer-groups/peer-group";
+
+// Config statement below a neighbor or a peer group entry, the path under
+// the entry and the arguments after the entry key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub path: String,
+    pub args: Vec<String>,
+}
+
+impl Statement {
+    // List keys on the way to the leaf, the arguments except the value.
+    fn keys(&self) -> &[String] {
+        &self.args[..self.args.len().saturating_sub(1)]
+    }
+
+    // Statement of the neighbor overrides the one of the peer group which
+    // sets the same leaf.
+    fn overrides(&self, other: &Statement) -> bool {
+        self.path == other.path && self.keys() == other.keys()
+    }
+}
+
+// Settings shared by the member neighbors. Statements are replayed on the
+// members through the neighbor callbacks, except the ones the member sets
+// by itself.
+#[derive(Debug, Default)]
+pub struct PeerGroup {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Default)]
+pub struct PeerGroups {
+    pub groups: BTreeMap<String, PeerGroup>,
+    // Statements configured on the neighbors themselves.
+    pub overrides: BTreeMap<IpAddr, Vec<Statement>>,
+}
+
+fn statement_apply(bgp: &mut Bgp, addr: IpAddr, statement: &Statement, op: ConfigOp) {
+    let path = format!("{}{}", NEIGHBOR, statement.path);
+    if let Some(f) = bgp.callbacks.get(&path).copied() {
+        let mut args = VecDeque::from(statement.args.clone());
+        args.push_front(addr.to_string());
+        f(bgp, Args(args), op);
+    }
+}
+
+fn is_overridden(bgp: &Bgp, addr: &IpAddr, statement: &Statement) -> bool {
+    bgp.peer_groups
+        .overrides
+        .get(addr)
+        .map(|own| own.iter().any(|x| x.overrides(statement)))
+        .unwrap_or(false)
+}
+
+fn group_members(bgp: &Bgp, name: &str) -> Vec<IpAddr> {
+    bgp.peers
+        .values()
+        .filter(|peer| peer.config.peer_group.as_deref() == Some(name))
+        .map(|peer| peer.address)
+        .collect()
+}
+
+fn group_statements(bgp: &Bgp, name: &str) -> Vec<Statement> {
+    bgp.peer_groups
+        .groups
+        .get(name)
+        .map(|group| group.statements.clone())
+        .unwrap_or_default()
+}
+
+// Apply the statements of the group to the neighbor joining it, or reset
+// them when it leaves.
+pub fn group_member_apply(bgp: &mut Bgp, addr: IpAddr, name: &str, op: ConfigOp) {
+    for statement in group_statements(bgp, name).iter() {
+        if !is_overridden(bgp, &addr, statement) {
+            statement_apply(bgp, addr, statement, op.clone());
+        }
+    }
+}
+
+// Statement under "peer-groups peer-group NAME". Returns false when the
+// path is not of a peer group.
+pub fn group_config(bgp: &mut Bgp, path: &str, args: &Args, op: ConfigOp) -> bool {
+    let path = match path.strip_prefix(PEER_GROUP) {
+        Some(path) => path,
+        None => return false,
+    };
+    let mut args: Vec<String> = args.0.iter().cloned().collect();
+    if args.is_empty() {
+        return true;
+    }
+    let name = args.remove(0);
+    if path.is_empty() {
+        if op == ConfigOp::Set {
+            bgp.peer_groups.groups.entry(name).or_default();
+        } else {
+            bgp.peer_groups.groups.remove(&name);
+        }
+        return true;
+    }
+    let statement = Statement {
+        path: path.to_string(),
+        args,
+    };
+    let group = bgp.peer_groups.groups.entry(name.clone()).or_default();
+    if op == ConfigOp::Set {
+        group.statements.retain(|x| !x.overrides(&statement));
+        group.statements.push(statement.clone());
+    } else {
+        group.statements.retain(|x| *x != statement);
+    }
+    for addr in group_members(bgp, &name).into_iter() {
+        if !is_overridden(bgp, &addr, &statement) {
+            statement_apply(bgp, addr, &statement, op.clone());
+        }
+    }
+    true
+}
+
+// Record the statement set on the neighbor itself. When it is deleted, the
+// one of the peer group is applied again.
+pub fn group_neighbor_config(bgp: &mut Bgp, path: &str, args: &Args, op: ConfigOp) {
+    let path = match path.strip_prefix(NEIGHBOR) {
+        Some(path) => path,
+        None => return,
+    };
+    let mut args: Vec<String> = args.0.iter().cloned().collect();
+    let addr: IpAddr = match args.first().and_then(|addr| addr.parse().ok()) {
+        Some(addr) => addr,
+        None => return,
+    };
+    args.remove(0);
+    if path.is_empty() {
+        if op == ConfigOp::Delete {
+            bgp.peer_groups.overrides.remove(&addr);
+        }
+        return;
+    }
+    if path == "/peer-group" {
+        return;
+    }
+    let statement = Statement {
+        path: path.to_string(),
+        args,
+    };
+    let own = bgp.peer_groups.overrides.entry(addr).or_default();
+    if op == ConfigOp::Set {
+        own.retain(|x| !x.overrides(&statement));
+        own.push(statement);
+        return;
+    }
+    own.retain(|x| *x != statement);
+    let name = match bgp
+        .peers
+        .get(&addr)
+        .and_then(|peer| peer.config.peer_group.clone())
+    {
+        Some(name) => name,
+        None => return,
+    };
+    for inherited in group_statements(bgp, &name).iter() {
+        if inherited.overrides(&statement) && !is_overridden(bgp, &addr, inherited) {
+            statement_apply(bgp, addr, inherited, ConfigOp::Set);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn statement(path: &str, args: &[&str]) -> Statement {
+        Statement {
+            path: path.to_string(),
+            args: args.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn statement_overrides() {
+        let hold = statement("/timers/hold-time", &["90"]);
+        assert!(statement("/timers/hold-time", &["30"]).overrides(&hold));
+        assert!(!statement("/timers/keepalive", &["30"]).overrides(&hold));
+
+        let ipv6 = statement("/afi-safis/afi-safi/enabled", &["ipv6-unicast", "true"]);
+        assert!(
+            statement("/afi-safis/afi-safi/enabled", &["ipv6-unicast", "false"]).overrides(&ipv6)
+        );
+        assert!(
+            !statement("/afi-safis/afi-safi/enabled", &["ipv4-unicast", "true"]).overrides(&ipv6)
+        );
+    }
+}
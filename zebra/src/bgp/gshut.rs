@@ -0,0 +1,238 @@
+use super::handler::{Bgp, Message};
+use super::packet::{Attribute, Attrs, CommunityAttr, CommunityValue, LocalPrefAttr};
+use super::peer::{fsm, Event, Peer, State};
+use crate::config::Args;
+use std::net::IpAddr;
+
+// LOCAL_PREF of the routes carrying GRACEFUL_SHUTDOWN (RFC 8326 4).
+pub const GSHUT_LOCAL_PREF: u32 = 0;
+
+fn has_gshut(attrs: &Attrs) -> bool {
+    attrs.iter().any(|attr| match attr {
+        Attribute::Community(community) => community.contains(&CommunityValue::GracefulShutdown.0),
+        _ => false,
+    })
+}
+
+fn local_pref_set(attrs: &mut Attrs, local_pref: u32) {
+    attrs.retain(|attr| !matches!(attr, Attribute::LocalPref(_)));
+    attrs.push(Attribute::LocalPref(LocalPrefAttr { local_pref }));
+}
+
+// Received route with GRACEFUL_SHUTDOWN is depreferenced, so that traffic
+// moves to the alternate paths before the session goes down.
+pub fn gshut_import(attrs: &mut Attrs) {
+    if has_gshut(attrs) {
+        local_pref_set(attrs, GSHUT_LOCAL_PREF);
+    }
+}
+
+// Route received from the neighbor in maintenance is depreferenced.
+pub fn maintenance_import(peer: &Peer, attrs: &mut Attrs) {
+    if peer.maintenance {
+        local_pref_set(attrs, GSHUT_LOCAL_PREF);
+    }
+}
+
+// Tag the route advertised while graceful shutdown or the maintenance of the
+// neighbor is in progress. LOCAL_PREF is lowered as well for iBGP peers.
+pub fn gshut_export(peer: &Peer, attrs: &mut Attrs) {
+    if !peer.graceful_shutdown && !peer.maintenance {
+        return;
+    }
+    let mut found = false;
+    for attr in attrs.iter_mut() {
+        if let Attribute::Community(community) = attr {
+            if !community.contains(&CommunityValue::GracefulShutdown.0) {
+                community.push(CommunityValue::GracefulShutdown.0);
+            }
+            found = true;
+        }
+    }
+    if !found {
+        let mut community = CommunityAttr::new();
+        community.push(CommunityValue::GracefulShutdown.0);
+        attrs.push(Attribute::Community(community));
+    }
+    if peer.peer_as == peer.local_as {
+        local_pref_set(attrs, GSHUT_LOCAL_PREF);
+    }
+}
+
+// Start or stop the graceful shutdown of the router. Routes are advertised
+// again to the established peers with or without the community.
+pub fn graceful_shutdown_set(bgp: &mut Bgp, enable: bool) {
+    if bgp.graceful_shutdown == enable {
+        return;
+    }
+    println!(
+        "graceful-shutdown: {}",
+        if enable { "started" } else { "stopped" }
+    );
+    bgp.graceful_shutdown = enable;
+    let mut addrs: Vec<IpAddr> = Vec::new();
+    for (addr, peer) in bgp.peers.iter_mut() {
+        peer.graceful_shutdown = enable;
+        if peer.state == State::Established {
+            addrs.push(*addr);
+        }
+    }
+    for addr in addrs.into_iter() {
+        fsm(bgp, addr, Event::SoftOut);
+    }
+}
+
+// request bgp graceful-shutdown on|off
+pub fn request_bgp_graceful_shutdown(bgp: &Bgp, mut args: Args) -> String {
+    let enable = match args.string().as_deref() {
+        Some("on") => true,
+        Some("off") => false,
+        _ => return String::from("% Specify on or off\n"),
+    };
+    let _ = bgp.tx.send(Message::GracefulShutdown(enable));
+    if enable {
+        String::from("Graceful shutdown started, routes are advertised with graceful-shutdown\n")
+    } else {
+        String::from("Graceful shutdown stopped\n")
+    }
+}
+
+// Drain the neighbor without changing the configuration. Routes are advertised
+// again, and requested again with ROUTE-REFRESH to apply the preference.
+pub fn maintenance_set(bgp: &mut Bgp, addr: IpAddr, enable: bool) {
+    let peer = match bgp.peers.get_mut(&addr) {
+        Some(peer) => peer,
+        None => return,
+    };
+    if peer.maintenance == enable {
+        return;
+    }
+    println!(
+        "maintenance: {} {}",
+        addr,
+        if enable { "started" } else { "stopped" }
+    );
+    peer.maintenance = enable;
+    if peer.state == State::Established {
+        fsm(bgp, addr, Event::SoftOut);
+        fsm(bgp, addr, Event::SoftIn);
+    }
+}
+
+// request bgp neighbor X maintenance on|off
+pub fn request_bgp_neighbor_maintenance(bgp: &Bgp, mut args: Args) -> String {
+    let addr = match args.addr() {
+        Some(addr) => addr,
+        None => return String::from("% Invalid neighbor address\n"),
+    };
+    if !bgp.peers.contains_key(&addr) {
+        return String::from("% No such neighbor\n");
+    }
+    let enable = match args.string().as_deref() {
+        Some("on") => true,
+        Some("off") => false,
+        _ => return String::from("% Specify on or off\n"),
+    };
+    let _ = bgp.tx.send(Message::Maintenance(addr, enable));
+    if enable {
+        format!(
+            "Maintenance of {} started, routes are advertised with graceful-shutdown\n",
+            addr
+        )
+    } else {
+        format!("Maintenance of {} stopped\n", addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rib::RibTxChannel;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn gshut_import_local_pref() {
+        let mut community = CommunityAttr::new();
+        community.push(CommunityValue::GracefulShutdown.0);
+        let mut attrs = vec![
+            Attribute::LocalPref(LocalPrefAttr { local_pref: 200 }),
+            Attribute::Community(community),
+        ];
+        gshut_import(&mut attrs);
+        let local_pref: Vec<u32> = attrs
+            .iter()
+            .filter_map(|attr| match attr {
+                Attribute::LocalPref(attr) => Some(attr.local_pref),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(local_pref, vec![GSHUT_LOCAL_PREF]);
+
+        let mut attrs = vec![Attribute::LocalPref(LocalPrefAttr { local_pref: 200 })];
+        gshut_import(&mut attrs);
+        assert!(matches!(
+            attrs[0],
+            Attribute::LocalPref(LocalPrefAttr { local_pref: 200 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn neighbor_maintenance() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let addr: IpAddr = "10.0.0.2".parse().unwrap();
+        let peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65002,
+            addr,
+            bgp.tx.clone(),
+            bgp.tx.clone(),
+        );
+        bgp.peers.insert(addr, peer);
+        let args = |v: &[&str]| Args(v.iter().map(|s| s.to_string()).collect());
+
+        // Request is checked before it is queued to the handler.
+        let out = request_bgp_neighbor_maintenance(&bgp, args(&["10.0.0.9", "on"]));
+        assert_eq!(out, "% No such neighbor\n");
+        let out = request_bgp_neighbor_maintenance(&bgp, args(&["10.0.0.2", "yes"]));
+        assert_eq!(out, "% Specify on or off\n");
+        assert!(bgp.rx.try_recv().is_err());
+        request_bgp_neighbor_maintenance(&bgp, args(&["10.0.0.2", "on"]));
+        assert!(matches!(
+            bgp.rx.try_recv(),
+            Ok(Message::Maintenance(a, true)) if a == addr
+        ));
+
+        // Received routes are depreferenced, advertised ones are tagged.
+        maintenance_set(&mut bgp, addr, true);
+        let peer = &bgp.peers[&addr];
+        assert!(peer.maintenance);
+        let mut attrs = vec![Attribute::LocalPref(LocalPrefAttr { local_pref: 200 })];
+        maintenance_import(peer, &mut attrs);
+        assert!(matches!(
+            attrs[..],
+            [Attribute::LocalPref(LocalPrefAttr {
+                local_pref: GSHUT_LOCAL_PREF
+            })]
+        ));
+        let mut attrs = Attrs::new();
+        gshut_export(peer, &mut attrs);
+        assert!(has_gshut(&attrs));
+        assert!(!attrs
+            .iter()
+            .any(|attr| matches!(attr, Attribute::LocalPref(_))));
+
+        // Leaving maintenance stops both.
+        maintenance_set(&mut bgp, addr, false);
+        let peer = &bgp.peers[&addr];
+        let mut attrs = vec![Attribute::LocalPref(LocalPrefAttr { local_pref: 200 })];
+        maintenance_import(peer, &mut attrs);
+        gshut_export(peer, &mut attrs);
+        assert!(!has_gshut(&attrs));
+        assert!(matches!(
+            attrs[..],
+            [Attribute::LocalPref(LocalPrefAttr { local_pref: 200 })]
+        ));
+    }
+}
@@ -0,0 +1,91 @@
+use std::io;
+use std::os::fd::RawFd;
+
+// Minimum hop limit of IPv6, not defined by libc.
+#[cfg(target_os = "linux")]
+const IPV6_MINHOPCOUNT: libc::c_int = 73;
+
+#[cfg(target_os = "linux")]
+fn setsockopt_int(fd: RawFd, level: libc::c_int, name: libc::c_int, value: i32) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const i32 as *const libc::c_void,
+            std::mem::size_of::<i32>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Generalized TTL Security Mechanism (RFC 5082). Packets are sent with TTL
+// 255, and the kernel drops the received ones which TTL is less than 255
+// minus the hops to the neighbor, so that the session can not be spoofed
+// from further away.
+#[cfg(target_os = "linux")]
+pub fn gtsm_set(fd: RawFd, ipv6: bool, hops: u8) -> io::Result<()> {
+    for (level, name, value) in gtsm_options(ipv6, hops) {
+        setsockopt_int(fd, level, name, value)?;
+    }
+    Ok(())
+}
+
+// Level, name and value of the socket options, the outgoing TTL followed by
+// the minimum one.
+#[cfg(target_os = "linux")]
+fn gtsm_options(ipv6: bool, hops: u8) -> [(libc::c_int, libc::c_int, i32); 2] {
+    let min_ttl = 256 - hops as i32;
+    if ipv6 {
+        [
+            (libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, 255),
+            (libc::IPPROTO_IPV6, IPV6_MINHOPCOUNT, min_ttl),
+        ]
+    } else {
+        [
+            (libc::IPPROTO_IP, libc::IP_TTL, 255),
+            (libc::IPPROTO_IP, libc::IP_MINTTL, min_ttl),
+        ]
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn gtsm_set(_fd: RawFd, _ipv6: bool, _hops: u8) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TTL security is not supported",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use socket2::{Domain, Socket, Type};
+    use std::os::fd::AsRawFd;
+
+    #[test]
+    fn gtsm_socket_options() {
+        assert_eq!(
+            gtsm_options(false, 1),
+            [
+                (libc::IPPROTO_IP, libc::IP_TTL, 255),
+                (libc::IPPROTO_IP, libc::IP_MINTTL, 255),
+            ]
+        );
+        assert_eq!(
+            gtsm_options(true, 3),
+            [
+                (libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, 255),
+                (libc::IPPROTO_IPV6, IPV6_MINHOPCOUNT, 253),
+            ]
+        );
+        assert_eq!(gtsm_options(false, 255)[1].2, 1);
+
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        gtsm_set(socket.as_raw_fd(), false, 1).unwrap();
+        assert_eq!(socket.ttl().unwrap(), 255);
+    }
+}
@@ -1,23 +1,97 @@
-use super::peer::{fsm, Event, Peer};
-use super::route::Route;
+use super::asname::{as_names_update, AsNames};
+use super::audit::Audit;
+use super::confed::Confederation;
+use super::dynamic::ListenRange;
+use super::family::Families;
+use super::flowspec::FlowSpec;
+use super::group::{group_config, group_neighbor_config, PeerGroups};
+use super::gshut::{graceful_shutdown_set, maintenance_set};
+use super::health::{health_update, HealthCheck};
+use super::helper::HelperState;
+use super::label::LabelTable;
+use super::latency::{latency_update, Latency};
+use super::md5::{tcp_md5_listen_addr, tcp_md5_set};
+use super::mirror::mirror_flush;
+use super::mrt::dump::{mrt_dump_table, MrtDump};
+use super::mrt::journal::MessageJournalConfig;
+use super::multipath::Multipath;
+use super::network::Network;
+use super::nexthop::{nexthop_update, NexthopConfig};
+use super::peer::{fsm, peer_shutdown_update, Event, Peer};
+use super::persist::{persist_load, persist_snapshot, Persist};
+use super::ptree::PrefixTree;
+use super::queue::UpdateQueue;
+use super::redist::{redist_recv, Redist};
+use super::restart::{restart_complete, restart_load, restart_save, RestartConfig, RestartState};
+use super::rib::Bestpath;
+use super::route::{route_policy_update, Route};
+use super::rpki::{rpki_recv, rtr::RtrEvent, Rpki};
+use super::show::ShowCache;
+use super::sla::{sla_metrics_write, SlaMetrics};
+use super::unnumbered::{unnumbered_learn, Unnumbered};
+use super::vpn::Vpn;
+use super::watch::OriginWatch;
+use super::webhook::Webhook;
+use super::BGP_PORT;
 use crate::bgp::peer::accept;
 use crate::bgp::task::Task;
 use crate::config::{
     path_from_command, Args, ConfigChannel, ConfigOp, ConfigRequest, DisplayRequest, ShowChannel,
 };
+use crate::policy::irr::prefix_list_generated;
+use crate::policy::{Policy, PolicyDirection, PrefixList};
 use crate::rib::api::{RibRxChannel, RibTx};
-use ipnet::Ipv4Net;
-use prefix_trie::PrefixMap;
-use std::collections::{BTreeMap, HashMap};
-use std::net::{Ipv4Addr, SocketAddr};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use socket2::{Domain, Socket, Type};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc::{self, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
 
 #[derive(Debug)]
 pub enum Message {
-    Event(Ipv4Addr, Event),
-    Accept(TcpStream, SocketAddr),
+    Event(IpAddr, Event),
+    // Message received in the session of the peer, via the update queue,
+    // with the time of the receipt.
+    Received(IpAddr, u64, Event, Instant),
+    // Connection accepted by the listener of the VRF.
+    Accept(TcpStream, SocketAddr, Option<String>),
     Show(Sender<String>),
+    Snapshot,
+    // Periodic TABLE_DUMP_V2 of the RIB.
+    MrtDump,
+    // Periodic export of the session availability metrics.
+    SlaMetrics,
+    // Link-local address of the neighbor learned on the unnumbered interface.
+    LinkLocal(String, Ipv6Addr),
+    // State change of the health check.
+    Health(String, bool),
+    // Event of the RTR session to the RPKI cache.
+    Rpki(IpAddr, RtrEvent),
+    // Selection deferral timer of our restart expired.
+    RestartDeferral,
+    // Save the state for graceful restart before the process exits.
+    Shutdown(oneshot::Sender<()>),
+    // Graceful shutdown of the router requested by the operator.
+    GracefulShutdown(bool),
+    // Maintenance of the neighbor requested by the operator.
+    Maintenance(IpAddr, bool),
+    // AS names loaded by the refresh task.
+    AsNames(HashMap<u32, String>),
+    // Prefix list generated from the IRR data.
+    PrefixListGenerated(String, PrefixList),
+}
+
+// Accept loop of the listening socket. The socket is kept open by the task,
+// and its descriptor is used for setting TCP MD5 keys of the peers.
+pub struct Listener {
+    pub task: Task<()>,
+    pub fd: RawFd,
+    pub v6: bool,
 }
 
 pub type Callback = fn(&mut Bgp, Args, ConfigOp) -> Option<()>;
@@ -26,18 +100,73 @@ pub type ShowCallback = fn(&Bgp, Args) -> String;
 pub struct Bgp {
     pub asn: u32,
     pub router_id: Ipv4Addr,
-    pub peers: BTreeMap<Ipv4Addr, Peer>,
+    pub monitor_only: bool,
+    // Routes are advertised with GRACEFUL_SHUTDOWN to drain the traffic.
+    pub graceful_shutdown: bool,
+    // Strict RFC checks of UPDATE instead of accepting the quirks of some
+    // implementations.
+    pub strict_parse: bool,
+    pub peers: BTreeMap<IpAddr, Peer>,
+    pub peer_groups: PeerGroups,
+    pub listen_range: ListenRange,
+    pub unnumbered: BTreeMap<String, Unnumbered>,
     pub tx: UnboundedSender<Message>,
     pub rx: UnboundedReceiver<Message>,
+    pub updates: UpdateQueue,
     pub cm: ConfigChannel,
     pub show: ShowChannel,
     pub show_cb: HashMap<String, ShowCallback>,
+    pub show_cache: ShowCache,
     pub rib: Sender<RibTx>,
     pub redist: RibRxChannel,
+    pub redistribute: Redist,
     pub callbacks: HashMap<String, Callback>,
-    pub ptree: PrefixMap<Ipv4Net, Vec<Route>>,
-    pub listen_task: Option<Task<()>>,
+    pub ptree: PrefixTree<Ipv4Net, Vec<Route>>,
+    pub ptree6: PrefixTree<Ipv6Net, Vec<Route>>,
+    pub families: Families,
+    pub multipath: Multipath,
+    pub bestpath: Bestpath,
+    pub latency: Latency,
+    pub persist: Persist,
+    pub audit: Audit,
+    pub webhook: Webhook,
+    pub origin_watch: OriginWatch,
+    pub as_names: AsNames,
+    pub msg_journal: MessageJournalConfig,
+    pub mrt_dump: MrtDump,
+    pub sla_metrics: SlaMetrics,
+    pub health: BTreeMap<String, HealthCheck>,
+    pub rpki: Rpki,
+    pub networks: BTreeMap<IpNet, Network>,
+    pub labels: LabelTable,
+    pub vpn: Vpn,
+    pub restart_config: RestartConfig,
+    // Present while best path selection is deferred after our restart.
+    pub restart: Option<RestartState>,
+    pub helper: HelperState,
+    pub policy: Policy,
+    // Cluster ID of route reflection, the router ID when not configured.
+    pub cluster_id: Option<Ipv4Addr>,
+    // Route reflector clients, shared with the neighbors.
+    pub rr_clients: Arc<BTreeSet<IpAddr>>,
+    pub confed: Confederation,
+    // RPSL dump or bgpq4 JSON output to generate prefix lists from.
+    pub irr_source: Option<String>,
+    pub nexthop: NexthopConfig,
+    // Policies and neighbor attachments changed in the current commit.
+    pub policy_changed: BTreeSet<String>,
+    pub policy_peers: BTreeSet<(IpAddr, PolicyDirection)>,
+    // Neighbors which administrative shutdown changed in the current commit.
+    pub shutdown_peers: BTreeSet<IpAddr>,
+    // Administrative shutdown of the whole instance with the message.
+    pub shutdown: Option<String>,
+    pub listen_task: Option<Listener>,
     pub listen_err: Option<anyhow::Error>,
+    // Listeners of the VRFs the neighbors are configured in.
+    pub listen_vrf: BTreeMap<String, Listener>,
+    // TCP MD5 keys set on the listeners, by the peer address with the VRF
+    // and the key.
+    pub listen_md5: BTreeMap<IpAddr, (Option<String>, String)>,
 }
 
 impl Bgp {
@@ -46,21 +175,64 @@ impl Bgp {
         let mut bgp = Self {
             asn: 0,
             router_id: Ipv4Addr::UNSPECIFIED,
+            monitor_only: false,
+            graceful_shutdown: false,
+            strict_parse: false,
             peers: BTreeMap::new(),
+            peer_groups: PeerGroups::default(),
+            listen_range: ListenRange::default(),
+            unnumbered: BTreeMap::new(),
             tx,
             rx,
-            ptree: PrefixMap::<Ipv4Net, Vec<Route>>::new(),
+            updates: UpdateQueue::new(),
+            ptree: PrefixTree::new(),
+            ptree6: PrefixTree::new(),
+            families: Families::default(),
+            multipath: Multipath::default(),
+            bestpath: Bestpath::default(),
+            latency: Latency::default(),
+            persist: Persist::default(),
+            audit: Audit::default(),
+            webhook: Webhook::default(),
+            origin_watch: OriginWatch::default(),
+            as_names: AsNames::default(),
+            msg_journal: MessageJournalConfig::default(),
+            mrt_dump: MrtDump::default(),
+            sla_metrics: SlaMetrics::default(),
+            health: BTreeMap::new(),
+            rpki: Rpki::default(),
+            networks: BTreeMap::new(),
+            labels: LabelTable::default(),
+            vpn: Vpn::default(),
+            restart_config: RestartConfig::default(),
+            restart: None,
+            helper: HelperState::default(),
+            policy: Policy::default(),
+            cluster_id: None,
+            rr_clients: Arc::default(),
+            confed: Confederation::default(),
+            irr_source: None,
+            nexthop: NexthopConfig::default(),
+            policy_changed: BTreeSet::new(),
+            policy_peers: BTreeSet::new(),
+            shutdown_peers: BTreeSet::new(),
+            shutdown: None,
             rib,
             cm: ConfigChannel::new(),
             show: ShowChannel::new(),
             show_cb: HashMap::new(),
+            show_cache: ShowCache::default(),
             redist: RibRxChannel::new(),
+            redistribute: Redist::default(),
             callbacks: HashMap::new(),
             listen_task: None,
             listen_err: None,
+            listen_vrf: BTreeMap::new(),
+            listen_md5: BTreeMap::new(),
         };
         bgp.callback_build();
         bgp.show_build();
+        bgp.families.register(Box::new(FlowSpec));
         bgp
     }
 
@@ -72,23 +244,96 @@ impl Bgp {
         match msg {
             Message::Event(peer, event) => {
                 println!("Message::Event: {:?}", event);
-                fsm(self, peer, event);
+                // Event may be queued for the dynamic peer already removed.
+                if self.peers.contains_key(&peer) {
+                    fsm(self, peer, event);
+                }
+            }
+            Message::Received(peer, session, event, received) => {
+                let current = match self.peers.get(&peer) {
+                    Some(p) => p.queue.recv(session),
+                    None => false,
+                };
+                if current && matches!(event, Event::UpdateMsg(_)) {
+                    let start = Instant::now();
+                    let queued = self.multipath.queue.len();
+                    fsm(self, peer, event);
+                    latency_update(self, peer, received, start, queued);
+                } else if current {
+                    println!("Message::Received: {:?}", event);
+                    fsm(self, peer, event);
+                }
             }
-            Message::Accept(socket, sockaddr) => {
+            Message::Accept(socket, sockaddr, vrf) => {
                 println!("Accept: {:?}", sockaddr);
-                accept(self, socket, sockaddr);
+                accept(self, socket, sockaddr, vrf);
             }
             Message::Show(tx) => {
                 self.tx.send(Message::Show(tx)).unwrap();
             }
+            Message::Snapshot => {
+                persist_snapshot(self);
+            }
+            Message::MrtDump => {
+                mrt_dump_table(self);
+            }
+            Message::SlaMetrics => {
+                sla_metrics_write(self);
+            }
+            Message::LinkLocal(name, addr) => {
+                unnumbered_learn(self, &name, addr);
+            }
+            Message::Health(name, healthy) => {
+                health_update(self, &name, healthy);
+            }
+            Message::Rpki(addr, event) => {
+                rpki_recv(self, addr, event);
+            }
+            Message::RestartDeferral => {
+                restart_complete(self);
+            }
+            Message::Shutdown(tx) => {
+                restart_save(self);
+                let _ = tx.send(());
+            }
+            Message::GracefulShutdown(enable) => {
+                graceful_shutdown_set(self, enable);
+            }
+            Message::Maintenance(addr, enable) => {
+                maintenance_set(self, addr, enable);
+            }
+            Message::AsNames(names) => {
+                as_names_update(self, names);
+            }
+            Message::PrefixListGenerated(name, plist) => {
+                prefix_list_generated(self, name, plist);
+            }
         }
     }
 
     pub fn process_cm_msg(&mut self, msg: ConfigRequest) {
+        if msg.op == ConfigOp::Ready {
+            msg.ready_reply();
+            return;
+        }
+        if msg.op == ConfigOp::CommitEnd {
+            route_policy_update(self);
+            nexthop_update(self);
+            restart_load(self);
+            persist_load(self);
+            peer_shutdown_update(self);
+            self.listen_vrf_update();
+            self.listen_md5_update();
+            return;
+        }
         let (path, args) = path_from_command(&msg.paths);
+        if group_config(self, &path, &args, msg.op.clone()) {
+            return;
+        }
         if let Some(f) = self.callbacks.get(&path) {
-            f(self, args, msg.op);
+            f(self, args.clone(), msg.op.clone());
         }
+        group_neighbor_config(self, &path, &args, msg.op);
     }
 
     async fn process_show_msg(&self, msg: DisplayRequest) {
@@ -100,25 +345,95 @@ impl Bgp {
     }
 
     pub async fn listen(&mut self) -> anyhow::Result<()> {
-        let listener = TcpListener::bind("0.0.0.0:179").await?;
-        let tx = self.tx.clone();
+        let listener = listen_socket(None)?;
+        self.listen_task = Some(listen_start(listener, None, self.tx.clone()));
+        Ok(())
+    }
 
-        let listen_task = Task::spawn(async move {
-            loop {
-                let (socket, sockaddr) = listener.accept().await.unwrap();
-                tx.send(Message::Accept(socket, sockaddr)).unwrap();
+    // Start listeners of the VRFs which neighbors have been added to, and
+    // stop the ones no longer used.
+    pub fn listen_vrf_update(&mut self) {
+        let vrfs: BTreeSet<String> = self
+            .peers
+            .values()
+            .filter_map(|peer| peer.config.transport.vrf.clone())
+            .collect();
+        self.listen_vrf.retain(|vrf, _| vrfs.contains(vrf));
+        for vrf in vrfs.into_iter() {
+            if self.listen_vrf.contains_key(&vrf) {
+                continue;
             }
-        });
-        self.listen_task = Some(listen_task);
-        Ok(())
+            // Keys set on the previous listener of the VRF are gone.
+            self.listen_md5
+                .retain(|_, (md5_vrf, _)| md5_vrf.as_ref() != Some(&vrf));
+            match listen_socket(Some(&vrf)) {
+                Ok(listener) => {
+                    let listener = listen_start(listener, Some(vrf.clone()), self.tx.clone());
+                    self.listen_vrf.insert(vrf, listener);
+                }
+                Err(err) => println!("Listen in VRF {} error: {}", vrf, err),
+            }
+        }
+    }
+
+    fn listener(&self, vrf: &Option<String>) -> Option<&Listener> {
+        match vrf {
+            Some(vrf) => self.listen_vrf.get(vrf),
+            None => self.listen_task.as_ref(),
+        }
+    }
+
+    // Set the TCP MD5 keys of the neighbors on the listener of their VRF, and
+    // remove the ones of the neighbors deleted or moved to another VRF.
+    pub fn listen_md5_update(&mut self) {
+        let keys: BTreeMap<IpAddr, (Option<String>, String)> = self
+            .peers
+            .values()
+            .filter_map(|peer| {
+                let password = peer.config.transport.password.clone()?;
+                Some((peer.address, (peer.config.transport.vrf.clone(), password)))
+            })
+            .collect();
+        let current = std::mem::take(&mut self.listen_md5);
+        for (addr, (vrf, _)) in current.iter() {
+            if keys.get(addr).map(|(new, _)| new) == Some(vrf) {
+                continue;
+            }
+            if let Some(listener) = self.listener(vrf) {
+                let _ = tcp_md5_set(listener.fd, tcp_md5_listen_addr(*addr, listener.v6), "");
+            }
+        }
+        for (addr, (vrf, password)) in keys.into_iter() {
+            if current.get(&addr) == Some(&(vrf.clone(), password.clone())) {
+                self.listen_md5.insert(addr, (vrf, password));
+                continue;
+            }
+            let listener = match self.listener(&vrf) {
+                Some(listener) => listener,
+                None => continue,
+            };
+            match tcp_md5_set(
+                listener.fd,
+                tcp_md5_listen_addr(addr, listener.v6),
+                &password,
+            ) {
+                Ok(_) => {
+                    self.listen_md5.insert(addr, (vrf, password));
+                }
+                Err(err) => println!("TCP MD5 key of {} error: {}", addr, err),
+            }
+        }
     }
 
     pub async fn event_loop(&mut self) {
         if let Err(err) = self.listen().await {
             self.listen_err = Some(err);
         }
+        // Local events and commands take precedence over the queued messages
+        // of the peers.
         loop {
             tokio::select! {
+                biased;
                 Some(msg) = self.rx.recv() => {
                     self.process_msg(msg);
                 }
@@ -128,11 +443,81 @@ impl Bgp {
                 Some(msg) = self.show.rx.recv() => {
             self.process_show_msg(msg).await;
                 }
+                Some(msg) = self.redist.rx.recv() => {
+                    redist_recv(self, msg);
+                }
+                Some(msg) = self.updates.rx.recv() => {
+                    self.process_msg(msg);
+                }
+                Ok(permit) = self.rib.reserve(), if !self.multipath.queue.is_empty() => {
+                    if let Some(msg) = self.multipath.queue.pop_front() {
+                        permit.send(msg);
+                    }
+                }
             }
+            // UPDATE messages of the production neighbors to the lab ones.
+            mirror_flush(self);
         }
     }
 }
 
+// Ask BGP to save the state for graceful restart and wait for it.
+pub async fn shutdown(tx: &UnboundedSender<Message>) {
+    let (resp, rx) = oneshot::channel();
+    if tx.send(Message::Shutdown(resp)).is_ok() {
+        let _ = rx.await;
+    }
+}
+
+fn listen_bind(addr: SocketAddr, vrf: Option<&str>) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(true)?;
+    if let Some(vrf) = vrf {
+        socket.bind_device(Some(vrf.as_bytes()))?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(128)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+// Listen on the IPv6 wildcard address which accepts IPv4 connections as
+// IPv4-mapped addresses as well. Falls back to IPv4 only when IPv6 is
+// disabled on the host.
+fn listen_socket(vrf: Option<&str>) -> std::io::Result<TcpListener> {
+    let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), BGP_PORT);
+    match listen_bind(addr, vrf) {
+        Ok(listener) => Ok(listener),
+        Err(_) => {
+            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), BGP_PORT);
+            listen_bind(addr, vrf)
+        }
+    }
+}
+
+fn listen_start(
+    listener: TcpListener,
+    vrf: Option<String>,
+    tx: UnboundedSender<Message>,
+) -> Listener {
+    let fd = listener.as_raw_fd();
+    let v6 = listener
+        .local_addr()
+        .map(|addr| addr.is_ipv6())
+        .unwrap_or(false);
+    let task = Task::spawn(async move {
+        loop {
+            let (socket, sockaddr) = listener.accept().await.unwrap();
+            tx.send(Message::Accept(socket, sockaddr, vrf.clone()))
+                .unwrap();
+        }
+    });
+    Listener { task, fd, v6 }
+}
+
 pub fn serve(mut bgp: Bgp) {
     tokio::spawn(async move {
         bgp.event_loop().await;
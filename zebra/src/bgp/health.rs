@@ -0,0 +1,397 @@
+use super::handler::{Bgp, Message};
+use super::network::network_apply;
+use super::task::Task;
+use crate::config::Args;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::timeout;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ProbeType {
+    #[default]
+    Tcp,
+    Http,
+    Icmp,
+}
+
+impl FromStr for ProbeType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(Self::Tcp),
+            "http" => Ok(Self::Http),
+            "icmp" => Ok(Self::Icmp),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ProbeType {
+    pub fn to_str(&self) -> &str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Http => "http",
+            Self::Icmp => "icmp",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProbeConfig {
+    pub typ: ProbeType,
+    pub address: Option<IpAddr>,
+    pub port: u16,
+    pub path: String,
+    pub interval: u64,
+    pub timeout: u64,
+    pub rise: u32,
+    pub fall: u32,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            typ: ProbeType::Tcp,
+            address: None,
+            port: 80,
+            path: String::from("/"),
+            interval: 5,
+            timeout: 2,
+            rise: 2,
+            fall: 3,
+        }
+    }
+}
+
+// Health check of a local service. A check starts unhealthy and becomes
+// healthy after rise consecutive successful probes, then unhealthy again
+// after fall consecutive failures.
+#[derive(Debug, Default)]
+pub struct HealthCheck {
+    pub config: ProbeConfig,
+    pub healthy: bool,
+    pub task: Option<Task<()>>,
+}
+
+async fn probe_tcp(addr: SocketAddr) -> bool {
+    TcpStream::connect(addr).await.is_ok()
+}
+
+// Status 2xx and 3xx are healthy.
+async fn probe_http(addr: SocketAddr, path: &str) -> bool {
+    let mut stream = match TcpStream::connect(addr).await {
+        Ok(stream) => stream,
+        Err(_) => return false,
+    };
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path,
+        addr.ip()
+    );
+    if stream.write_all(request.as_bytes()).await.is_err() {
+        return false;
+    }
+    let mut buf = [0u8; 32];
+    let len = match stream.read(&mut buf).await {
+        Ok(len) => len,
+        Err(_) => return false,
+    };
+    let status = String::from_utf8_lossy(&buf[..len]);
+    let mut words = status.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some(version), Some(code)) if version.starts_with("HTTP/") => {
+            matches!(code.parse::<u16>(), Ok(200..=399))
+        }
+        _ => false,
+    }
+}
+
+fn icmp_checksum(buf: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in buf.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u16::from_be_bytes([chunk[0], chunk[1]])
+        } else {
+            u16::from_be_bytes([chunk[0], 0])
+        };
+        sum += word as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn icmp_socket(addr: IpAddr) -> std::io::Result<UdpSocket> {
+    let socket = match addr {
+        IpAddr::V4(_) => Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?,
+        IpAddr::V6(_) => Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?,
+    };
+    socket.connect(&SocketAddr::new(addr, 0).into())?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+// Echo request and wait for the reply with the same identifier and
+// sequence. Checksum of ICMPv6 is filled by the kernel.
+async fn probe_icmp(addr: IpAddr, ident: u16, seq: u16) -> bool {
+    let socket = match icmp_socket(addr) {
+        Ok(socket) => socket,
+        Err(err) => {
+            println!("health: ICMP socket error: {}", err);
+            return false;
+        }
+    };
+    let (request, reply) = match addr {
+        IpAddr::V4(_) => (ICMP_ECHO_REQUEST, ICMP_ECHO_REPLY),
+        IpAddr::V6(_) => (ICMPV6_ECHO_REQUEST, ICMPV6_ECHO_REPLY),
+    };
+    let mut echo = [0u8; 16];
+    echo[0] = request;
+    echo[4..6].copy_from_slice(&ident.to_be_bytes());
+    echo[6..8].copy_from_slice(&seq.to_be_bytes());
+    if addr.is_ipv4() {
+        let checksum = icmp_checksum(&echo);
+        echo[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    if socket.send(&echo).await.is_err() {
+        return false;
+    }
+    let mut buf = [0u8; 1500];
+    loop {
+        let len = match socket.recv(&mut buf).await {
+            Ok(len) => len,
+            Err(_) => return false,
+        };
+        // Raw IPv4 socket receives the IP header as well.
+        let offset = match addr {
+            IpAddr::V4(_) if len > 0 => ((buf[0] & 0x0f) as usize) * 4,
+            _ => 0,
+        };
+        let icmp = match buf.get(offset..len) {
+            Some(icmp) if icmp.len() >= 8 => icmp,
+            _ => continue,
+        };
+        if icmp[0] == reply && icmp[4..6] == ident.to_be_bytes() && icmp[6..8] == seq.to_be_bytes()
+        {
+            return true;
+        }
+    }
+}
+
+async fn probe(config: &ProbeConfig, addr: IpAddr, seq: u16) -> bool {
+    let sockaddr = SocketAddr::new(addr, config.port);
+    let result = match config.typ {
+        ProbeType::Tcp => timeout(Duration::from_secs(config.timeout), probe_tcp(sockaddr)).await,
+        ProbeType::Http => {
+            timeout(
+                Duration::from_secs(config.timeout),
+                probe_http(sockaddr, &config.path),
+            )
+            .await
+        }
+        ProbeType::Icmp => {
+            let ident = std::process::id() as u16;
+            timeout(
+                Duration::from_secs(config.timeout),
+                probe_icmp(addr, ident, seq),
+            )
+            .await
+        }
+    };
+    result.unwrap_or(false)
+}
+
+// Consecutive probe results against the current state. The state changes
+// when they reach rise or fall.
+struct Hysteresis {
+    healthy: bool,
+    count: u32,
+}
+
+impl Hysteresis {
+    fn new(healthy: bool) -> Self {
+        Self { healthy, count: 0 }
+    }
+
+    // Returns true when the state changed.
+    fn update(&mut self, config: &ProbeConfig, result: bool) -> bool {
+        if result == self.healthy {
+            self.count = 0;
+            return false;
+        }
+        self.count += 1;
+        let threshold = if self.healthy {
+            config.fall
+        } else {
+            config.rise
+        };
+        if self.count < threshold.max(1) {
+            return false;
+        }
+        self.healthy = !self.healthy;
+        self.count = 0;
+        true
+    }
+}
+
+// Probe periodically and report transitions of the state to BGP.
+async fn health_probe(
+    name: String,
+    config: ProbeConfig,
+    addr: IpAddr,
+    healthy: bool,
+    tx: UnboundedSender<Message>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval.max(1)));
+    let mut state = Hysteresis::new(healthy);
+    let mut seq: u16 = 0;
+    loop {
+        interval.tick().await;
+        seq = seq.wrapping_add(1);
+        let result = probe(&config, addr, seq).await;
+        if state.update(&config, result) {
+            let _ = tx.send(Message::Health(name.clone(), state.healthy));
+        }
+    }
+}
+
+// Probe task is restarted with the current state whenever the check is
+// reconfigured, so editing a check does not withdraw the networks.
+pub fn health_restart(bgp: &mut Bgp, name: &str) {
+    let tx = bgp.tx.clone();
+    if let Some(health) = bgp.health.get_mut(name) {
+        health.task = health.config.address.map(|addr| {
+            Task::spawn(health_probe(
+                name.to_string(),
+                health.config.clone(),
+                addr,
+                health.healthy,
+                tx,
+            ))
+        });
+    }
+}
+
+fn health_networks(bgp: &mut Bgp, name: &str) {
+    let prefixes: Vec<_> = bgp
+        .networks
+        .iter()
+        .filter(|(_, network)| network.health_check.as_deref() == Some(name))
+        .map(|(prefix, _)| *prefix)
+        .collect();
+    for prefix in prefixes.iter() {
+        network_apply(bgp, prefix);
+    }
+}
+
+pub fn health_add(bgp: &mut Bgp, name: &str) {
+    if !bgp.health.contains_key(name) {
+        bgp.health.insert(name.to_string(), HealthCheck::default());
+    }
+}
+
+pub fn health_delete(bgp: &mut Bgp, name: &str) {
+    if bgp.health.remove(name).is_some() {
+        health_networks(bgp, name);
+    }
+}
+
+pub fn health_update(bgp: &mut Bgp, name: &str, healthy: bool) {
+    match bgp.health.get_mut(name) {
+        Some(health) => health.healthy = healthy,
+        None => return,
+    }
+    println!(
+        "health: {} is {}",
+        name,
+        if healthy { "up" } else { "down" }
+    );
+    health_networks(bgp, name);
+}
+
+pub fn show_bgp_health_check(bgp: &Bgp, _args: Args) -> String {
+    let mut buf = String::new();
+    writeln!(
+        buf,
+        "{:16} {:5} {:28} {:6} Networks",
+        "Name", "Type", "Target", "State"
+    )
+    .unwrap();
+    for (name, health) in bgp.health.iter() {
+        let config = &health.config;
+        let target = match (config.address, config.typ) {
+            (None, _) => String::from("-"),
+            (Some(addr), ProbeType::Icmp) => addr.to_string(),
+            (Some(addr), _) => SocketAddr::new(addr, config.port).to_string(),
+        };
+        let networks: Vec<String> = bgp
+            .networks
+            .iter()
+            .filter(|(_, network)| network.health_check.as_deref() == Some(name.as_str()))
+            .map(|(prefix, _)| prefix.to_string())
+            .collect();
+        writeln!(
+            buf,
+            "{:16} {:5} {:28} {:6} {}",
+            name,
+            config.typ.to_str(),
+            target,
+            if health.healthy { "Up" } else { "Down" },
+            networks.join(" ")
+        )
+        .unwrap();
+    }
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rise_fall() {
+        let config = ProbeConfig::default();
+        let mut state = Hysteresis::new(false);
+        let results = |state: &mut Hysteresis, results: &[bool]| -> Vec<bool> {
+            results
+                .iter()
+                .map(|result| state.update(&config, *result))
+                .collect()
+        };
+
+        // Rise 2, an interleaved failure starts the count again.
+        assert_eq!(results(&mut state, &[true, false, true]), [false; 3]);
+        assert!(!state.healthy);
+        assert_eq!(results(&mut state, &[true]), [true]);
+        assert!(state.healthy);
+
+        // Fall 3.
+        assert_eq!(results(&mut state, &[false, false, true]), [false; 3]);
+        assert_eq!(
+            results(&mut state, &[false, false, false]),
+            [false, false, true]
+        );
+        assert!(!state.healthy);
+
+        // Threshold 0 is taken as 1.
+        let config = ProbeConfig {
+            rise: 0,
+            ..Default::default()
+        };
+        assert!(state.update(&config, true));
+        assert!(state.healthy);
+    }
+}
@@ -0,0 +1,294 @@
+use super::adj_rib::AdjRib;
+use super::handler::{Bgp, Message};
+use super::packet::{
+    parse_bgp_packet, Attribute, BgpHeader, BgpPacket, BgpType, UpdatePacket, BGP_HEADER_LEN,
+};
+use super::peer::{Event, Peer};
+use super::restart::{restart_attrs, restart_route};
+use super::route::{route_add, route_advertise};
+use super::task::{Timer, TimerType};
+use bytes::{BufMut, BytesMut};
+use ipnet::Ipv4Net;
+use nom::bytes::complete::take;
+use nom::number::complete::be_u32;
+use nom::IResult;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Graceful restart helper state is kept on disk, so that the routes retained
+// for the restarting peers survive a quick restart of the daemon. The file is
+// rewritten and synced whenever the stale routes of a peer change. It is a
+// sequence of the peers:
+//
+//   peer address (16 octets) | deadline (4 octets) | paths (4 octets)
+//   | { path identifier (4 octets) | BGP UPDATE message }*
+//
+// Deadline is the UNIX time the stale routes are removed at. Attributes are
+// the ones in Loc-RIB, so that inbound policy is not applied again.
+
+const HELPER_STATE: &str = "helper-state";
+const HELPER_STATE_TMP: &str = "helper-state.tmp";
+
+#[derive(Debug, Default)]
+pub struct HelperState {
+    // Deadline of the stale routes by whether the peer is waiting for the
+    // session to come back, or for End-of-RIB.
+    deadline: BTreeMap<IpAddr, (bool, u32)>,
+}
+
+#[derive(Debug)]
+struct HelperPeer {
+    address: IpAddr,
+    deadline: u32,
+    rib: AdjRib,
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+fn update(attrs: Vec<Attribute>, ipv4_update: Vec<Ipv4Net>) -> UpdatePacket {
+    UpdatePacket {
+        header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+        attrs,
+        ipv4_update,
+        ipv4_withdraw: Vec::new(),
+        ipv4_update_id: Vec::new(),
+        ipv4_withdraw_id: Vec::new(),
+        attr_errors: Vec::new(),
+    }
+}
+
+fn helper_encode(
+    buf: &mut BytesMut,
+    address: IpAddr,
+    deadline: u32,
+    paths: Vec<(u32, UpdatePacket)>,
+) {
+    let address = match address {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    };
+    buf.put(&address.octets()[..]);
+    buf.put_u32(deadline);
+    buf.put_u32(paths.len() as u32);
+    for (id, update) in paths.into_iter() {
+        buf.put_u32(id);
+        let bytes: BytesMut = update.into();
+        buf.put(&bytes[..]);
+    }
+}
+
+fn helper_parse(input: &[u8]) -> IResult<&[u8], HelperPeer> {
+    let (input, address) = take(16usize)(input)?;
+    let address: [u8; 16] = address.try_into().unwrap();
+    let address = IpAddr::V6(Ipv6Addr::from(address)).to_canonical();
+    let (input, deadline) = be_u32(input)?;
+    let (mut input, paths) = be_u32(input)?;
+    let mut rib = AdjRib::new();
+    for _ in 0..paths {
+        let (remain, id) = be_u32(input)?;
+        let (remain, packet) = parse_bgp_packet(remain, true)?;
+        if let BgpPacket::Update(update) = packet {
+            for prefix in update.ipv4_update.iter() {
+                rib.insert(*prefix, id, update.attrs.clone());
+            }
+            if let Some(mp) = update.mp_reach() {
+                let attrs = update.mp_attrs();
+                for (i, prefix) in mp.prefix.iter().enumerate() {
+                    rib.insert6(*prefix, id, update.mp_prefix_attrs(&attrs, i));
+                }
+            }
+        }
+        input = remain;
+    }
+    Ok((
+        input,
+        HelperPeer {
+            address,
+            deadline,
+            rib,
+        },
+    ))
+}
+
+// Stale paths of the peer with the attributes in Loc-RIB.
+fn helper_paths(bgp: &Bgp, peer: &Peer) -> Vec<(u32, UpdatePacket)> {
+    let mut paths = Vec::new();
+    for (prefix, id, _) in peer.stale_rib.paths() {
+        let route = bgp.ptree.get(prefix).and_then(|routes| {
+            routes
+                .iter()
+                .find(|route| route.from == peer.address && route.path_id == id)
+        });
+        if let Some(route) = route {
+            paths.push((id, update(route.attrs.clone(), vec![*prefix])));
+        }
+    }
+    for (prefix, id, _) in peer.stale_rib.paths6() {
+        let route = bgp.ptree6.get(prefix).and_then(|routes| {
+            routes
+                .iter()
+                .find(|route| route.from == peer.address && route.path_id == id)
+        });
+        if let Some(route) = route {
+            let attrs = restart_attrs(&route.attrs, vec![*prefix]);
+            paths.push((id, update(attrs, Vec::new())));
+        }
+    }
+    paths
+}
+
+// The file is replaced by rename after the data is synced, then the
+// directory is synced for the rename to be durable.
+fn helper_write(dir: &Path, buf: &[u8]) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut file = File::create(dir.join(HELPER_STATE_TMP))?;
+    file.write_all(buf)?;
+    file.sync_all()?;
+    fs::rename(dir.join(HELPER_STATE_TMP), dir.join(HELPER_STATE))?;
+    File::open(dir)?.sync_all()
+}
+
+// Save the stale routes of the peers in helper mode. Deadline is kept while
+// the peer stays in the same phase of the restart.
+pub fn helper_save(bgp: &mut Bgp) {
+    let dir = match bgp.restart_config.helper_dir() {
+        Some(dir) => dir.clone(),
+        None => return,
+    };
+    let now = now();
+    let mut deadline = BTreeMap::new();
+    let mut buf = BytesMut::new();
+    for peer in bgp.peers.values() {
+        if peer.stale_rib.count() == 0 {
+            continue;
+        }
+        let waiting = match (&peer.timer.restart, &peer.timer.stale) {
+            (Some(_), _) => true,
+            (None, Some(_)) => false,
+            (None, None) => continue,
+        };
+        let time = match bgp.helper.deadline.get(&peer.address) {
+            Some((phase, time)) if *phase == waiting => *time,
+            _ if waiting => now + peer.restart_time.unwrap_or(0) as u32,
+            _ => now + peer.config.graceful_restart.stale_routes_time(),
+        };
+        deadline.insert(peer.address, (waiting, time));
+        helper_encode(&mut buf, peer.address, time, helper_paths(bgp, peer));
+    }
+    if deadline.is_empty() && bgp.helper.deadline.is_empty() {
+        return;
+    }
+    bgp.helper.deadline = deadline;
+    if let Err(err) = helper_write(&dir, &buf) {
+        println!("helper: save error: {}", err);
+    }
+}
+
+fn helper_timer(peer: &Peer, remain: u32) -> Timer {
+    let ident = peer.ident;
+    let tx = peer.tx.clone();
+    Timer::new(Timer::second(remain as u64), TimerType::Once, move || {
+        let tx = tx.clone();
+        async move {
+            let _ = tx.send(Message::Event(ident, Event::RestartTimerExpires));
+        }
+    })
+}
+
+// Restore the stale routes of the configured peers once at startup. Routes
+// are kept until the rest of the deadline, as if the peer has not come back
+// yet.
+pub fn helper_load(bgp: &mut Bgp) {
+    let dir = match bgp.restart_config.helper_dir() {
+        Some(dir) => dir.clone(),
+        None => return,
+    };
+    let data = match fs::read(dir.join(HELPER_STATE)) {
+        Ok(data) => data,
+        Err(_) => return,
+    };
+    let now = now();
+    let mut input = data.as_slice();
+    let mut prefixes = Vec::new();
+    let mut prefixes6 = Vec::new();
+    let mut count = 0;
+    // A truncated file is not expected as it is replaced by rename, it is
+    // simply ignored from the broken peer.
+    while let Ok((remain, helper)) = helper_parse(input) {
+        input = remain;
+        if helper.deadline <= now {
+            continue;
+        }
+        // Routes restored by our own graceful restart take precedence.
+        let peer = match bgp
+            .peers
+            .values_mut()
+            .find(|peer| peer.address == helper.address)
+        {
+            Some(peer) if peer.stale_rib.count() == 0 => peer,
+            _ => continue,
+        };
+        for (prefix, id, attrs) in helper.rib.paths() {
+            let mut route = restart_route(helper.address, attrs.clone());
+            route.path_id = id;
+            route_add(&mut bgp.ptree, *prefix, route, &bgp.bestpath);
+            prefixes.push(*prefix);
+        }
+        for (prefix, id, attrs) in helper.rib.paths6() {
+            let mut route = restart_route(helper.address, attrs.clone());
+            route.path_id = id;
+            route_add(&mut bgp.ptree6, *prefix, route, &bgp.bestpath);
+            prefixes6.push(*prefix);
+        }
+        peer.stale_rib = helper.rib;
+        peer.timer.restart = Some(helper_timer(peer, helper.deadline - now));
+        bgp.helper
+            .deadline
+            .insert(helper.address, (true, helper.deadline));
+        count += 1;
+    }
+    if count > 0 {
+        println!("helper: restored stale routes of {} peers", count);
+        route_advertise(bgp, &prefixes, &prefixes6);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::OriginAttr;
+
+    #[test]
+    fn helper_state_parse() {
+        let mut buf = BytesMut::new();
+        let address: IpAddr = "10.0.0.1".parse().unwrap();
+        let attrs = vec![Attribute::Origin(OriginAttr { origin: 0 })];
+        let paths = vec![
+            (
+                1,
+                update(attrs.clone(), vec!["10.1.0.0/16".parse().unwrap()]),
+            ),
+            (2, update(attrs, vec!["10.1.0.0/16".parse().unwrap()])),
+        ];
+        helper_encode(&mut buf, address, 1000, paths);
+
+        let (remain, helper) = helper_parse(&buf).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(helper.address, address);
+        assert_eq!(helper.deadline, 1000);
+        let ids: Vec<u32> = helper.rib.paths().map(|(_, id, _)| id).collect();
+        assert_eq!(ids, vec![1, 2]);
+
+        // Truncated peer is ignored.
+        assert!(helper_parse(&buf[..buf.len() - 1]).is_err());
+    }
+}
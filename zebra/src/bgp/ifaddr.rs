@@ -0,0 +1,85 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+// Addresses of the local interfaces with the interface name.
+#[cfg(target_os = "linux")]
+pub fn if_addrs() -> Vec<(String, IpNet)> {
+    use std::ffi::CStr;
+
+    let mut addrs = Vec::new();
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return addrs;
+    }
+    let mut ifa = ifap;
+    while !ifa.is_null() {
+        let entry = unsafe { &*ifa };
+        ifa = entry.ifa_next;
+        let addr = match unsafe { sockaddr_ip(entry.ifa_addr) } {
+            Some(addr) => addr,
+            None => continue,
+        };
+        let plen = match unsafe { sockaddr_ip(entry.ifa_netmask) } {
+            Some(IpAddr::V4(mask)) => u32::from(mask).count_ones(),
+            Some(IpAddr::V6(mask)) => u128::from(mask).count_ones(),
+            None => continue,
+        };
+        let name = unsafe { CStr::from_ptr(entry.ifa_name) }
+            .to_string_lossy()
+            .to_string();
+        if let Ok(net) = IpNet::new(addr, plen as u8) {
+            addrs.push((name, net));
+        }
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+    addrs
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn sockaddr_ip(sa: *const libc::sockaddr) -> Option<IpAddr> {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    if sa.is_null() {
+        return None;
+    }
+    match (*sa).sa_family as libc::c_int {
+        libc::AF_INET => {
+            let sin = &*(sa as *const libc::sockaddr_in);
+            Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                sin.sin_addr.s_addr,
+            ))))
+        }
+        libc::AF_INET6 => {
+            let sin6 = &*(sa as *const libc::sockaddr_in6);
+            Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn if_addrs() -> Vec<(String, IpNet)> {
+    Vec::new()
+}
+
+// Source address of the interface for the address family of the peer.
+// Global address is preferred to link-local one for IPv6.
+pub fn if_source(name: &str, peer: &IpAddr) -> Option<IpAddr> {
+    let mut addrs: Vec<IpAddr> = if_addrs()
+        .into_iter()
+        .filter(|(ifname, net)| ifname == name && net.addr().is_ipv4() == peer.is_ipv4())
+        .map(|(_, net)| net.addr())
+        .collect();
+    addrs.sort_by_key(|addr| match addr {
+        IpAddr::V6(addr) => addr.is_unicast_link_local(),
+        IpAddr::V4(_) => false,
+    });
+    addrs.into_iter().next()
+}
+
+// Peer is on the subnet of one of the interfaces. Taken as connected when
+// the interface addresses are not available on the platform.
+pub fn is_connected(peer: &IpAddr) -> bool {
+    let addrs = if_addrs();
+    addrs.is_empty() || addrs.iter().any(|(_, net)| net.trunc().contains(peer))
+}
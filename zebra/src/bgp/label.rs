@@ -0,0 +1,185 @@
+use super::handler::Bgp;
+use super::network::ROUTE_LOCAL;
+use super::packet::{LABEL_IMPLICIT_NULL, LABEL_MAX, LABEL_MIN};
+use super::route::Route;
+use super::{Afi, AfiSafi, Safi};
+use crate::rib::fib::Dataplane;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+// Local label of the prefix swapped to the label of the best path, or popped
+// for the prefix originated by us which has no next hop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelEntry {
+    pub label: u32,
+    pub out_label: u32,
+    pub nexthop: Option<IpAddr>,
+}
+
+// Labels allocated per prefix for the labeled routes advertised with next hop
+// self. Each label is programmed to the MPLS table of the dataplane.
+#[derive(Default)]
+pub struct LabelTable {
+    pub entries: BTreeMap<IpNet, LabelEntry>,
+    next: u32,
+    free: Vec<u32>,
+    pub dataplane: Option<Box<dyn Dataplane>>,
+}
+
+impl LabelTable {
+    pub fn alloc(&mut self) -> Option<u32> {
+        if let Some(label) = self.free.pop() {
+            return Some(label);
+        }
+        let label = self.next.max(LABEL_MIN);
+        if label > LABEL_MAX {
+            return None;
+        }
+        self.next = label + 1;
+        Some(label)
+    }
+
+    pub fn release(&mut self, label: u32) {
+        self.free.push(label);
+    }
+
+    // Implicit null of the next hop pops the label.
+    fn dataplane_add(&self, entry: &LabelEntry) {
+        let out_label = Some(entry.out_label).filter(|x| *x != LABEL_IMPLICIT_NULL);
+        if let Some(dataplane) = self.dataplane.as_ref() {
+            dataplane.mpls_add(entry.label, out_label, entry.nexthop);
+        }
+    }
+
+    fn dataplane_del(&self, label: u32) {
+        if let Some(dataplane) = self.dataplane.as_ref() {
+            dataplane.mpls_del(label);
+        }
+    }
+}
+
+// Whether labeled unicast of the address family is configured on any peer.
+fn label_configured(bgp: &Bgp, afi: Afi) -> bool {
+    let afi_safi = AfiSafi::new(afi, Safi::MplsLabel);
+    bgp.peers
+        .values()
+        .any(|peer| peer.config.afi_safi.has(&afi_safi))
+}
+
+// Swap of the best path learned with a label. Label of the prefix originated
+// by us is popped when labeled unicast is in use. Routes without a label need
+// no local label.
+fn label_target(
+    best: &Route,
+    nexthop: Option<IpAddr>,
+    labeled: bool,
+) -> Option<(u32, Option<IpAddr>)> {
+    if best.from == ROUTE_LOCAL {
+        return labeled.then_some((LABEL_IMPLICIT_NULL, None));
+    }
+    Some((best.label()?, Some(nexthop?)))
+}
+
+// Allocate, update or release the local label of the prefix after the best
+// path change. Local label of the prefix is returned.
+fn label_entry_update(
+    bgp: &mut Bgp,
+    prefix: IpNet,
+    target: Option<(u32, Option<IpAddr>)>,
+) -> Option<u32> {
+    let (out_label, nexthop) = match target {
+        Some(target) => target,
+        None => {
+            if let Some(entry) = bgp.labels.entries.remove(&prefix) {
+                bgp.labels.dataplane_del(entry.label);
+                bgp.labels.release(entry.label);
+            }
+            return None;
+        }
+    };
+    let label = match bgp.labels.entries.get(&prefix) {
+        Some(entry) => entry.label,
+        None => match bgp.labels.alloc() {
+            Some(label) => label,
+            None => {
+                println!("label: no label left for {}", prefix);
+                return None;
+            }
+        },
+    };
+    let entry = LabelEntry {
+        label,
+        out_label,
+        nexthop,
+    };
+    if bgp.labels.entries.get(&prefix) != Some(&entry) {
+        bgp.labels.dataplane_add(&entry);
+        bgp.labels.entries.insert(prefix, entry);
+    }
+    Some(label)
+}
+
+// Local label of the routes for the advertisement with next hop self.
+// Originated route is advertised with the label configured for the network,
+// otherwise with the allocated one.
+fn label_routes_set(routes: &mut [Route], label: Option<u32>, configured: Option<u32>) {
+    for route in routes.iter_mut() {
+        route.local_label = if route.from == ROUTE_LOCAL {
+            configured.or(label)
+        } else {
+            label
+        };
+    }
+}
+
+fn label_configured_network(bgp: &Bgp, prefix: &IpNet) -> Option<u32> {
+    bgp.networks.get(prefix).and_then(|network| network.label)
+}
+
+// Allocate, update or release the local labels of the prefixes after the best
+// path change, and set the label to the routes for the advertisement.
+pub fn label_update(bgp: &mut Bgp, prefixes: &[Ipv4Net], prefixes6: &[Ipv6Net]) {
+    let labeled = label_configured(bgp, Afi::IP);
+    for prefix in prefixes.iter() {
+        let configured = label_configured_network(bgp, &IpNet::V4(*prefix));
+        let target = bgp.ptree.get(prefix).and_then(|routes| {
+            let best = routes.iter().find(|route| route.selected)?;
+            let nexthop = best.nexthop().map(IpAddr::V4);
+            label_target(best, nexthop, labeled && configured.is_none())
+        });
+        let label = label_entry_update(bgp, IpNet::V4(*prefix), target);
+        if let Some(routes) = bgp.ptree.get_mut(prefix) {
+            label_routes_set(routes, label, configured);
+        }
+    }
+    let labeled = label_configured(bgp, Afi::IP6);
+    for prefix in prefixes6.iter() {
+        let configured = label_configured_network(bgp, &IpNet::V6(*prefix));
+        let target = bgp.ptree6.get(prefix).and_then(|routes| {
+            let best = routes.iter().find(|route| route.selected)?;
+            let nexthop = best.nexthop6().map(IpAddr::V6);
+            label_target(best, nexthop, labeled && configured.is_none())
+        });
+        let label = label_entry_update(bgp, IpNet::V6(*prefix), target);
+        if let Some(routes) = bgp.ptree6.get_mut(prefix) {
+            label_routes_set(routes, label, configured);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn label_alloc() {
+        let mut labels = LabelTable::default();
+        assert_eq!(labels.alloc(), Some(LABEL_MIN));
+        assert_eq!(labels.alloc(), Some(LABEL_MIN + 1));
+        labels.release(LABEL_MIN);
+        assert_eq!(labels.alloc(), Some(LABEL_MIN));
+        labels.next = LABEL_MAX + 1;
+        assert_eq!(labels.alloc(), None);
+    }
+}
@@ -0,0 +1,141 @@
+use super::handler::Bgp;
+use crate::config::Args;
+use crate::rib::api::RibTx;
+use ipnet::IpNet;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// Percentiles are taken from the latest samples.
+const LATENCY_SAMPLES: usize = 1024;
+
+#[derive(Debug, Default)]
+pub struct LatencyStat {
+    samples: VecDeque<Duration>,
+    count: u64,
+}
+
+impl LatencyStat {
+    pub fn record(&mut self, latency: Duration) {
+        if self.samples.len() == LATENCY_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+        self.count += 1;
+    }
+
+    // Nearest rank percentile of the samples, None without any sample.
+    pub fn percentile(&self, percent: usize) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut samples: Vec<Duration> = self.samples.iter().copied().collect();
+        samples.sort();
+        let rank = (samples.len() * percent).div_ceil(100).max(1);
+        samples.get(rank - 1).copied()
+    }
+}
+
+// Latency of the UPDATE pipeline. Receipt is stamped by the reader of the
+// session, so the time in the update queue is included.
+#[derive(Debug, Default)]
+pub struct Latency {
+    // Receipt to Loc-RIB update, per peer.
+    pub rib: BTreeMap<IpAddr, LatencyStat>,
+    // Processing time of an UPDATE from the queue to the advertisement.
+    pub batch: LatencyStat,
+    // Receipt to the ack of the RIB.
+    pub fib: LatencyStat,
+    // Receipt time of the UPDATE which changed the route sent to the RIB.
+    pending: BTreeMap<IpNet, Instant>,
+}
+
+// Record the UPDATE of the peer processed in the event loop. Routes queued to
+// the RIB from `queued` on are the result of the UPDATE.
+pub fn latency_update(
+    bgp: &mut Bgp,
+    peer: IpAddr,
+    received: Instant,
+    start: Instant,
+    queued: usize,
+) {
+    let now = Instant::now();
+    let latency = &mut bgp.latency;
+    latency
+        .rib
+        .entry(peer)
+        .or_default()
+        .record(now.duration_since(received));
+    latency.batch.record(now.duration_since(start));
+    for msg in bgp.multipath.queue.iter().skip(queued) {
+        if let RibTx::RouteAdd(route) | RibTx::RouteDel(route) = msg {
+            latency.pending.entry(route.prefix).or_insert(received);
+        }
+    }
+}
+
+pub fn latency_fib_ack(bgp: &mut Bgp, prefix: &IpNet) {
+    if let Some(received) = bgp.latency.pending.remove(prefix) {
+        bgp.latency.fib.record(received.elapsed());
+    }
+}
+
+fn latency_line(buf: &mut String, name: &str, stat: &LatencyStat) {
+    let ms = |percent| {
+        stat.percentile(percent)
+            .map(|x| format!("{:.3}", x.as_secs_f64() * 1000.0))
+            .unwrap_or_else(|| String::from("-"))
+    };
+    writeln!(
+        buf,
+        "{:<24} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        name,
+        stat.count,
+        ms(50),
+        ms(90),
+        ms(99),
+        ms(100)
+    )
+    .unwrap();
+}
+
+pub fn show_bgp_latency(bgp: &Bgp, _args: Args) -> String {
+    let mut buf = String::new();
+    writeln!(
+        buf,
+        "{:<24} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "Latency (ms)", "Count", "p50", "p90", "p99", "Max"
+    )
+    .unwrap();
+    for (peer, stat) in bgp.latency.rib.iter() {
+        latency_line(&mut buf, &peer.to_string(), stat);
+    }
+    latency_line(&mut buf, "UPDATE processing", &bgp.latency.batch);
+    latency_line(&mut buf, "Receipt to FIB", &bgp.latency.fib);
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn latency_percentile() {
+        let mut stat = LatencyStat::default();
+        assert_eq!(stat.percentile(50), None);
+        for ms in (1..=100).rev() {
+            stat.record(Duration::from_millis(ms));
+        }
+        assert_eq!(stat.percentile(50), Some(Duration::from_millis(50)));
+        assert_eq!(stat.percentile(99), Some(Duration::from_millis(99)));
+        assert_eq!(stat.percentile(100), Some(Duration::from_millis(100)));
+
+        // Only the latest samples are kept.
+        for _ in 0..LATENCY_SAMPLES {
+            stat.record(Duration::from_millis(1));
+        }
+        assert_eq!(stat.percentile(100), Some(Duration::from_millis(1)));
+        assert_eq!(stat.count, 100 + LATENCY_SAMPLES as u64);
+    }
+}
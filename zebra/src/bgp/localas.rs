@@ -0,0 +1,106 @@
+use super::packet::{As4PathAttr, Attribute, Attrs};
+use super::peer::Peer;
+
+// AS presented to the neighbor instead of the global one during AS migration.
+// By default the AS is prepended to the AS path of the received routes, and
+// both the AS and the global AS are prepended to the advertised routes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LocalAs {
+    pub asn: Option<u32>,
+    // Do not prepend the AS to the received routes.
+    pub no_prepend: bool,
+    // Prepend only the AS to the advertised routes.
+    pub replace_as: bool,
+}
+
+impl Peer {
+    // Local AS applies to the eBGP neighbors outside of the confederation.
+    pub fn migration_as(&self) -> Option<u32> {
+        if self.peer_as == self.local_as || self.confed_member() {
+            return None;
+        }
+        self.config.local_as.asn
+    }
+}
+
+pub fn local_as_import(peer: &Peer, attrs: &mut Attrs) {
+    let asn = match peer.migration_as() {
+        Some(asn) if !peer.config.local_as.no_prepend => asn,
+        _ => return,
+    };
+    let mut aspath = None;
+    attrs.retain(|attr| match attr {
+        Attribute::AsPath(x) => {
+            aspath = Some(As4PathAttr::from(x));
+            false
+        }
+        Attribute::As4Path(x) => {
+            aspath = Some(x.clone());
+            false
+        }
+        _ => true,
+    });
+    let aspath = aspath.unwrap_or(As4PathAttr {
+        segments: Vec::new(),
+    });
+    attrs.push(Attribute::As4Path(aspath.prepend(asn)));
+}
+
+// Our AS is the confederation identifier or the global AS, followed by the
+// local AS unless replace-as is set.
+pub fn local_as_prepend(peer: &Peer, aspath: As4PathAttr) -> As4PathAttr {
+    let global = peer.confed.id.unwrap_or(peer.local_as);
+    match peer.migration_as() {
+        Some(asn) if peer.config.local_as.replace_as => aspath.prepend(asn),
+        Some(asn) => aspath.prepend(global).prepend(asn),
+        None => aspath.prepend(global),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn peer() -> Peer {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let addr = "10.0.0.1".parse().unwrap();
+        let update_tx = tx.clone();
+        let mut peer = Peer::new(
+            addr,
+            65000,
+            Ipv4Addr::new(1, 1, 1, 1),
+            65002,
+            addr,
+            tx,
+            update_tx,
+        );
+        peer.config.local_as.asn = Some(65001);
+        peer
+    }
+
+    #[test]
+    fn local_as_path() {
+        let mut peer = peer();
+        let aspath = As4PathAttr {
+            segments: Vec::new(),
+        };
+        assert_eq!(peer.open_as(), 65001);
+        assert_eq!(
+            local_as_prepend(&peer, aspath.clone()).to_string(),
+            "65001 65000"
+        );
+        peer.config.local_as.replace_as = true;
+        assert_eq!(local_as_prepend(&peer, aspath.clone()).to_string(), "65001");
+
+        let mut attrs = vec![Attribute::As4Path(aspath.prepend(65002))];
+        local_as_import(&peer, &mut attrs);
+        match &attrs[..] {
+            [Attribute::As4Path(aspath)] => assert_eq!(aspath.to_string(), "65001 65002"),
+            _ => panic!("AS path is not replaced"),
+        }
+
+        peer.peer_as = 65000;
+        assert_eq!(peer.open_as(), 65000);
+    }
+}
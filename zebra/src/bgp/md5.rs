@@ -0,0 +1,72 @@
+use std::io;
+use std::net::IpAddr;
+use std::os::fd::RawFd;
+
+// TCP MD5 signature option (RFC 2385). The key is set on the socket for the
+// address of the peer, on the connecting socket as well as on the listener
+// so that the accepted connection inherits it. Empty key removes it.
+#[cfg(target_os = "linux")]
+pub fn tcp_md5_set(fd: RawFd, addr: IpAddr, key: &str) -> io::Result<()> {
+    let key = key.as_bytes();
+    if key.len() > libc::TCP_MD5SIG_MAXKEYLEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "TCP MD5 key is too long",
+        ));
+    }
+    let sockaddr = socket2::SockAddr::from(std::net::SocketAddr::new(addr, 0));
+    let mut sig: libc::tcp_md5sig = unsafe { std::mem::zeroed() };
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            sockaddr.as_ptr() as *const u8,
+            &mut sig.tcpm_addr as *mut libc::sockaddr_storage as *mut u8,
+            sockaddr.len() as usize,
+        );
+    }
+    sig.tcpm_keylen = key.len() as u16;
+    sig.tcpm_key[..key.len()].copy_from_slice(key);
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_MD5SIG,
+            &sig as *const libc::tcp_md5sig as *const libc::c_void,
+            std::mem::size_of::<libc::tcp_md5sig>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn tcp_md5_set(_fd: RawFd, _addr: IpAddr, _key: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TCP MD5 is not supported",
+    ))
+}
+
+// IPv4 peer connects to the dual stack listener as IPv4-mapped address, and
+// the key has to be set for the mapped address.
+pub fn tcp_md5_listen_addr(addr: IpAddr, v6: bool) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) if v6 => IpAddr::V6(addr.to_ipv6_mapped()),
+        _ => addr,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn listen_addr() {
+        let addr: IpAddr = "10.0.0.1".parse().unwrap();
+        let mapped: Ipv6Addr = "::ffff:10.0.0.1".parse().unwrap();
+        assert_eq!(tcp_md5_listen_addr(addr, true), IpAddr::V6(mapped));
+        assert_eq!(tcp_md5_listen_addr(addr, false), addr);
+    }
+}
@@ -0,0 +1,163 @@
+use super::handler::{Bgp, Message};
+use super::packet::{attrs_as2, Attribute, BgpType, UpdatePacket};
+use super::peer::{Event, Peer, State};
+use super::Afi;
+use bytes::BytesMut;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+
+// UPDATE messages of the production neighbor duplicated to the lab neighbor.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum MirrorDirection {
+    #[default]
+    Received,
+    Sent,
+    Both,
+}
+
+// Session mirroring of the neighbor. UPDATE messages received from or sent
+// to the neighbor are sent as they are to the lab neighbor, so that new
+// policy can be tested there with the live routing data.
+#[derive(Debug, Default, Clone)]
+pub struct PeerMirror {
+    pub to: Option<IpAddr>,
+    pub direction: MirrorDirection,
+}
+
+impl PeerMirror {
+    fn received(&self) -> bool {
+        self.to.is_some() && self.direction != MirrorDirection::Sent
+    }
+
+    fn sent(&self) -> bool {
+        self.to.is_some() && self.direction != MirrorDirection::Received
+    }
+}
+
+pub fn mirror_received(peer: &mut Peer, update: &UpdatePacket) {
+    if peer.config.mirror.received() {
+        peer.mirror_queue.push(update.clone());
+    }
+}
+
+pub fn mirror_sent(peer: &mut Peer, update: &UpdatePacket) {
+    if peer.config.mirror.sent() {
+        peer.mirror_queue.push(update.clone());
+    }
+}
+
+// Lab neighbor only receives the mirrored UPDATE messages. Our routes are not
+// advertised to it, and routes received from it are discarded so that
+// production is not affected. Session of the neighbor which becomes or stops
+// being the lab one is reset to start over.
+pub fn mirror_lab_update(bgp: &mut Bgp) {
+    let labs: BTreeSet<IpAddr> = bgp
+        .peers
+        .values()
+        .filter_map(|peer| peer.config.mirror.to)
+        .collect();
+    for (addr, peer) in bgp.peers.iter_mut() {
+        let lab = labs.contains(addr);
+        if peer.mirror_lab != lab && peer.state != State::Idle {
+            let _ = bgp.tx.send(Message::Event(*addr, Event::Shutdown(None)));
+        }
+        peer.mirror_lab = lab;
+    }
+}
+
+// Path Identifiers are removed unless ADD-PATH is negotiated with the lab
+// neighbor for the AFI, and the AS path is converted for the 2 octet AS lab
+// neighbor. Path Identifiers in the raw MP_REACH_NLRI are kept.
+fn mirror_packet(
+    mut update: UpdatePacket,
+    add_path: bool,
+    add_path6: bool,
+    as4: bool,
+) -> UpdatePacket {
+    if !add_path {
+        update.ipv4_update_id.clear();
+        update.ipv4_withdraw_id.clear();
+    }
+    if !add_path6 {
+        for attr in update.attrs.iter_mut() {
+            if let Attribute::MpReachNlri(mp) | Attribute::MpUnreachNlri(mp) = attr {
+                mp.path_id.clear();
+            }
+        }
+    }
+    if !as4 {
+        update.attrs = attrs_as2(update.attrs);
+    }
+    update
+}
+
+// Mirrored UPDATE messages are not mirrored again by the lab neighbor.
+fn mirror_send(lab: &mut Peer, update: UpdatePacket) {
+    let add_path = lab.add_path_limit(&Afi::IP).is_some();
+    let add_path6 = lab.add_path_limit(&Afi::IP6).is_some();
+    let update = mirror_packet(update, add_path, add_path6, lab.as4);
+    let bytes: BytesMut = update.into();
+    lab.counter[BgpType::Update as usize].sent += 1;
+    if let Some(packet_tx) = lab.packet_tx.as_ref() {
+        let _ = packet_tx.send(bytes);
+    }
+}
+
+// Send the UPDATE messages queued by the production neighbors to the lab
+// neighbors. They are dropped while the lab session is not established.
+pub fn mirror_flush(bgp: &mut Bgp) {
+    let mut mirrored = Vec::new();
+    for peer in bgp.peers.values_mut() {
+        if peer.mirror_queue.is_empty() {
+            continue;
+        }
+        let updates = std::mem::take(&mut peer.mirror_queue);
+        if let Some(to) = peer.config.mirror.to {
+            mirrored.push((to, updates));
+        }
+    }
+    for (to, updates) in mirrored.into_iter() {
+        let lab = match bgp.peers.get_mut(&to) {
+            Some(lab) if lab.state == State::Established => lab,
+            _ => continue,
+        };
+        for update in updates.into_iter() {
+            mirror_send(lab, update);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{As4PathAttr, As4Segment, BgpHeader, AS_SEQUENCE, BGP_HEADER_LEN};
+
+    #[test]
+    fn mirror_packet_capability() {
+        let aspath = As4PathAttr {
+            segments: vec![As4Segment {
+                typ: AS_SEQUENCE,
+                asn: vec![4200000000],
+            }],
+        };
+        let update = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: vec![Attribute::As4Path(aspath)],
+            ipv4_update: vec!["10.1.0.0/16".parse().unwrap()],
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: vec![7],
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        };
+        let mirrored = mirror_packet(update.clone(), true, true, true);
+        assert_eq!(mirrored.ipv4_update_id, vec![7]);
+        assert_eq!(mirrored.attrs.len(), 1);
+        assert!(matches!(mirrored.attrs[0], Attribute::As4Path(_)));
+
+        let mirrored = mirror_packet(update, false, false, false);
+        assert!(mirrored.ipv4_update_id.is_empty());
+        assert_eq!(mirrored.ipv4_update.len(), 1);
+        assert!(matches!(mirrored.attrs[0], Attribute::AsPath(_)));
+        assert!(matches!(mirrored.attrs[1], Attribute::NewAsPath(_)));
+    }
+}
@@ -1,5 +1,5 @@
 pub mod handler;
-pub use handler::{serve, Bgp};
+pub use handler::{serve, shutdown, Bgp};
 
 pub mod afi;
 pub use afi::*;
@@ -7,11 +7,50 @@ pub use afi::*;
 pub mod constant;
 pub use constant::*;
 
+pub mod adj_rib;
+pub mod asname;
+pub mod audit;
+pub mod backoff;
+pub mod clear;
+pub mod confed;
 pub mod config;
+pub use config::{BGP_SUBTREES, BGP_YANG_MODULES};
+pub mod dynamic;
+pub mod export;
+pub mod family;
+pub mod flowspec;
+pub mod group;
+pub mod gshut;
+pub mod gtsm;
+pub mod health;
+pub mod helper;
+pub mod ifaddr;
+pub mod label;
+pub mod latency;
+pub mod localas;
+pub mod md5;
+pub mod mirror;
+pub mod multipath;
+pub mod network;
+pub mod nexthop;
+pub mod orf;
 pub mod packet;
 pub mod peer;
+pub mod persist;
+pub mod ptree;
+pub mod queue;
+pub mod redist;
+pub mod resource;
+pub mod restart;
+pub mod rib;
 pub mod route;
+pub mod rpki;
 pub mod show;
+pub mod sla;
 pub mod task;
+pub mod unnumbered;
+pub mod vpn;
+pub mod watch;
+pub mod webhook;
 
 pub mod mrt;
@@ -0,0 +1,419 @@
+use super::import::{MrtType, TableDumpV2SubType};
+use super::journal::{JournalWriter, MessageJournal, MESSAGE_JOURNAL_MAX_SIZE};
+use crate::bgp::handler::{Bgp, Message};
+use crate::bgp::network::ROUTE_LOCAL;
+use crate::bgp::packet::{
+    Aggregator4Attr, As4PathAttr, Attribute, AttributeType, BGP_ATTR_FLAG_OPTIONAL,
+};
+use crate::bgp::peer::{Peer, State};
+use crate::bgp::ptree::PrefixTree;
+use crate::bgp::route::Route;
+use crate::bgp::task::{Timer, TimerType};
+use bytes::{BufMut, BytesMut};
+use chrono::Local;
+use ipnet::{Ipv4Net, Ipv6Net};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedSender;
+
+// MRT dumps for offline analysis with the existing MRT tools. The directory
+// has periodic TABLE_DUMP_V2 snapshots of the RIB in "rib.<date>.<time>.mrt"
+// and the UPDATE messages received from all of the peers with the state
+// changes of the sessions in "updates.mrt", as BGP4MP_ET records. The updates
+// file is rotated to "updates.mrt.1" when it reaches the maximum size.
+
+pub const MRT_DUMP_TABLE_INTERVAL: u64 = 7200;
+pub const MRT_DUMP_TABLE_KEEP: usize = 12;
+
+const UPDATES: &str = "updates.mrt";
+
+// Peer type of the peer entry in PEER_INDEX_TABLE (RFC 6396 4.3.1).
+const PEER_TYPE_IPV6: u8 = 0x01;
+const PEER_TYPE_AS4: u8 = 0x02;
+
+pub struct MrtDump {
+    pub dir: Option<PathBuf>,
+    // Interval of the table dump in seconds, zero disables it.
+    pub table_interval: u64,
+    // Number of the table dump files kept in the directory.
+    pub table_keep: usize,
+    pub updates: bool,
+    // Maximum size of the updates file in kilobytes.
+    pub max_size: u64,
+    timer: Option<Timer>,
+    journal: Option<MessageJournal>,
+}
+
+impl Default for MrtDump {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            table_interval: MRT_DUMP_TABLE_INTERVAL,
+            table_keep: MRT_DUMP_TABLE_KEEP,
+            updates: true,
+            max_size: MESSAGE_JOURNAL_MAX_SIZE,
+            timer: None,
+            journal: None,
+        }
+    }
+}
+
+impl MrtDump {
+    pub fn writer(&self) -> Option<JournalWriter> {
+        self.journal.as_ref().map(|journal| journal.writer())
+    }
+
+    fn start(&mut self, tx: UnboundedSender<Message>) {
+        self.timer = None;
+        self.journal = None;
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return,
+        };
+        if let Err(err) = fs::create_dir_all(dir) {
+            println!("mrt: can't create {}: {}", dir.display(), err);
+            return;
+        }
+        if self.updates {
+            self.journal = Some(MessageJournal::start(
+                dir.join(UPDATES),
+                self.max_size * 1024,
+            ));
+        }
+        if self.table_interval > 0 {
+            self.timer = Some(Timer::new(
+                Timer::second(self.table_interval),
+                TimerType::Infinite,
+                move || {
+                    let tx = tx.clone();
+                    async move {
+                        let _ = tx.send(Message::MrtDump);
+                    }
+                },
+            ));
+        }
+    }
+}
+
+// Dump files are reopened, updates are recorded from the next session.
+pub fn mrt_dump_restart(bgp: &mut Bgp) {
+    bgp.mrt_dump.start(bgp.tx.clone());
+    let writer = bgp.mrt_dump.writer();
+    for peer in bgp.peers.values_mut() {
+        peer.mrt_updates = writer.clone();
+    }
+}
+
+fn state_code(state: &State) -> u16 {
+    match state {
+        State::Idle => 1,
+        State::Connect => 2,
+        State::Active => 3,
+        State::OpenSent => 4,
+        State::OpenConfirm => 5,
+        State::Established => 6,
+    }
+}
+
+pub fn mrt_dump_state(peer: &Peer, prev: &State) {
+    let writer = match &peer.mrt_updates {
+        Some(writer) => writer,
+        None => return,
+    };
+    let local = match (peer.local_addr, peer.address) {
+        (Some(local), _) => local,
+        (None, IpAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        (None, IpAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    writer
+        .session(
+            peer.peer_as,
+            peer.local_as,
+            peer.scope_id,
+            peer.address,
+            local,
+        )
+        .state_change(state_code(prev), state_code(&peer.state));
+}
+
+fn mrt_record(buf: &mut BytesMut, timestamp: u32, subtype: TableDumpV2SubType, body: &[u8]) {
+    buf.put_u32(timestamp);
+    buf.put_u16(MrtType::TABLE_DUMP_V2.0);
+    buf.put_u16(subtype.0);
+    buf.put_u32(body.len() as u32);
+    buf.put(body);
+}
+
+fn peer_entry_encode(buf: &mut BytesMut, id: Ipv4Addr, addr: IpAddr, asn: u32) {
+    match addr {
+        IpAddr::V4(addr) => {
+            buf.put_u8(PEER_TYPE_AS4);
+            buf.put(&id.octets()[..]);
+            buf.put(&addr.octets()[..]);
+        }
+        IpAddr::V6(addr) => {
+            buf.put_u8(PEER_TYPE_AS4 | PEER_TYPE_IPV6);
+            buf.put(&id.octets()[..]);
+            buf.put(&addr.octets()[..]);
+        }
+    }
+    buf.put_u32(asn);
+}
+
+// Attributes are encoded with 4 octet AS numbers, and MP_REACH_NLRI only has
+// the next hop (RFC 6396 4.3.4).
+fn attrs_encode(buf: &mut BytesMut, attrs: &[Attribute]) {
+    for attr in attrs.iter() {
+        match attr {
+            Attribute::AsPath(aspath) => {
+                Attribute::As4Path(As4PathAttr::from(aspath)).encode(buf);
+            }
+            Attribute::Aggregator(aggregator) => {
+                let aggregator = Aggregator4Attr {
+                    asn: aggregator.asn as u32,
+                    ip: aggregator.ip,
+                };
+                Attribute::Aggregator4(aggregator).encode(buf);
+            }
+            Attribute::MpReachNlri(m) => {
+                let next_hop = m.next_hop.unwrap_or(Ipv6Addr::UNSPECIFIED);
+                let len = if m.link_local.is_some() { 32 } else { 16 };
+                buf.put_u8(BGP_ATTR_FLAG_OPTIONAL);
+                buf.put_u8(AttributeType::MpReachNlri.0);
+                buf.put_u8(len + 1);
+                buf.put_u8(len);
+                buf.put(&next_hop.octets()[..]);
+                if let Some(link_local) = m.link_local {
+                    buf.put(&link_local.octets()[..]);
+                }
+            }
+            Attribute::MpUnreachNlri(_) | Attribute::MpReachRaw(_) | Attribute::MpUnreachRaw(_) => {
+            }
+            _ => attr.encode(buf),
+        }
+    }
+}
+
+// RIB entries of a prefix. The receipt time of the route is not kept, so the
+// originated time is the time of the dump.
+fn rib_encode(
+    body: &mut BytesMut,
+    timestamp: u32,
+    index: &BTreeMap<IpAddr, u16>,
+    routes: &[Route],
+) {
+    let count_pos = body.len();
+    body.put_u16(0);
+    let mut count: u16 = 0;
+    for route in routes.iter() {
+        let peer_index = match index.get(&route.from) {
+            Some(peer_index) => *peer_index,
+            None => continue,
+        };
+        body.put_u16(peer_index);
+        body.put_u32(timestamp);
+        let len_pos = body.len();
+        body.put_u16(0);
+        attrs_encode(body, &route.attrs);
+        let len = (body.len() - len_pos - 2) as u16;
+        body[len_pos..len_pos + 2].copy_from_slice(&len.to_be_bytes());
+        count += 1;
+    }
+    body[count_pos..count_pos + 2].copy_from_slice(&count.to_be_bytes());
+}
+
+fn prefix_encode(body: &mut BytesMut, len: u8, octets: &[u8]) {
+    let psize = (len as usize + 7) / 8;
+    body.put_u8(len);
+    body.put(&octets[..psize]);
+}
+
+// PEER_INDEX_TABLE followed by the RIB records of IPv4 and IPv6 unicast.
+// Index zero is the local routes, then the configured peers.
+pub fn mrt_table_encode(
+    buf: &mut BytesMut,
+    timestamp: u32,
+    router_id: Ipv4Addr,
+    asn: u32,
+    peers: &BTreeMap<IpAddr, Peer>,
+    ptree: &PrefixTree<Ipv4Net, Vec<Route>>,
+    ptree6: &PrefixTree<Ipv6Net, Vec<Route>>,
+) {
+    let mut index = BTreeMap::new();
+    let mut body = BytesMut::new();
+    body.put(&router_id.octets()[..]);
+    body.put_u16(0);
+    body.put_u16(peers.len() as u16 + 1);
+    peer_entry_encode(&mut body, router_id, ROUTE_LOCAL, asn);
+    index.insert(ROUTE_LOCAL, 0);
+    for peer in peers.values() {
+        index.insert(peer.address, index.len() as u16);
+        peer_entry_encode(&mut body, peer.remote_id, peer.address, peer.peer_as);
+    }
+    mrt_record(buf, timestamp, TableDumpV2SubType::PEER_INDEX_TABLE, &body);
+
+    let mut seq = 0;
+    for (prefix, routes) in ptree.iter() {
+        let mut body = BytesMut::new();
+        body.put_u32(seq);
+        prefix_encode(&mut body, prefix.prefix_len(), &prefix.addr().octets());
+        rib_encode(&mut body, timestamp, &index, routes);
+        mrt_record(buf, timestamp, TableDumpV2SubType::RIB_IPV4_UNICAST, &body);
+        seq += 1;
+    }
+    for (prefix, routes) in ptree6.iter() {
+        let mut body = BytesMut::new();
+        body.put_u32(seq);
+        prefix_encode(&mut body, prefix.prefix_len(), &prefix.addr().octets());
+        rib_encode(&mut body, timestamp, &index, routes);
+        mrt_record(buf, timestamp, TableDumpV2SubType::RIB_IPV6_UNICAST, &body);
+        seq += 1;
+    }
+}
+
+// Remove the oldest table dumps, the names sort in the order of the time.
+fn mrt_table_prune(dir: &Path, keep: usize) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with("rib.") && name.ends_with(".mrt"))
+                .unwrap_or(false)
+        })
+        .collect();
+    files.sort();
+    let remove = files.len().saturating_sub(keep);
+    for path in files.iter().take(remove) {
+        if let Err(err) = fs::remove_file(path) {
+            println!("mrt: can't remove {}: {}", path.display(), err);
+        }
+    }
+}
+
+pub fn mrt_dump_table(bgp: &Bgp) {
+    let dir = match &bgp.mrt_dump.dir {
+        Some(dir) => dir.clone(),
+        None => return,
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as u32)
+        .unwrap_or(0);
+    let mut buf = BytesMut::new();
+    mrt_table_encode(
+        &mut buf,
+        timestamp,
+        bgp.router_id,
+        bgp.asn,
+        &bgp.peers,
+        &bgp.ptree,
+        &bgp.ptree6,
+    );
+    let path = dir.join(Local::now().format("rib.%Y%m%d.%H%M.mrt").to_string());
+    let keep = bgp.mrt_dump.table_keep;
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = fs::write(&path, &buf) {
+            println!("mrt: {} write error: {}", path.display(), err);
+            return;
+        }
+        mrt_table_prune(&dir, keep);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{As4Segment, OriginAttr, AS_SEQUENCE};
+    use crate::bgp::rpki::RpkiState;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn table_dump_records() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let addr: IpAddr = "192.168.0.2".parse().unwrap();
+        let mut peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65002,
+            addr,
+            tx.clone(),
+            tx,
+        );
+        peer.remote_id = Ipv4Addr::new(10, 0, 0, 2);
+        let mut peers = BTreeMap::new();
+        peers.insert(addr, peer);
+
+        let route = Route {
+            from: addr,
+            router_id: Ipv4Addr::new(10, 0, 0, 2),
+            attrs: vec![
+                Attribute::Origin(OriginAttr { origin: 0 }),
+                Attribute::As4Path(As4PathAttr {
+                    segments: vec![As4Segment {
+                        typ: AS_SEQUENCE,
+                        asn: vec![65002],
+                    }],
+                }),
+            ],
+            ibgp: false,
+            weight: 0,
+            metric: 0,
+            selected: true,
+            resolved: true,
+            path_id: 0,
+            local_path_id: 0,
+            local_label: None,
+            multipath: false,
+            rpki: RpkiState::NotFound,
+        };
+        let mut ptree = PrefixTree::new();
+        ptree.insert("10.1.0.0/16".parse().unwrap(), vec![route]);
+
+        let mut buf = BytesMut::new();
+        mrt_table_encode(
+            &mut buf,
+            1000,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65001,
+            &peers,
+            &ptree,
+            &PrefixTree::new(),
+        );
+
+        // PEER_INDEX_TABLE with the local entry and the peer.
+        assert_eq!(&buf[0..4], &1000u32.to_be_bytes());
+        assert_eq!(&buf[4..8], &[0, 13, 0, 1]);
+        let len = u32::from_be_bytes(buf[8..12].try_into().unwrap()) as usize;
+        let body = &buf[12..12 + len];
+        assert_eq!(&body[0..4], &[10, 0, 0, 1]);
+        assert_eq!(&body[6..8], &[0, 2]);
+        assert_eq!(len, 8 + (1 + 4 + 4 + 4) * 2);
+        assert_eq!(body[21], PEER_TYPE_AS4);
+        assert_eq!(&body[26..30], &[192, 168, 0, 2]);
+        assert_eq!(&body[30..34], &65002u32.to_be_bytes());
+
+        // RIB_IPV4_UNICAST with the route of peer index 1.
+        let rib = &buf[12 + len..];
+        assert_eq!(&rib[4..8], &[0, 13, 0, 2]);
+        let body = &rib[12..];
+        assert_eq!(&body[0..4], &[0, 0, 0, 0]);
+        assert_eq!(&body[4..7], &[16, 10, 1]);
+        assert_eq!(&body[7..9], &[0, 1]);
+        assert_eq!(&body[9..11], &[0, 1]);
+        assert_eq!(&body[11..15], &1000u32.to_be_bytes());
+        // ORIGIN and AS_PATH of 4 octet AS.
+        let attr_len = u16::from_be_bytes(body[15..17].try_into().unwrap());
+        assert_eq!(attr_len, 4 + 9);
+        assert_eq!(body.len(), 17 + attr_len as usize);
+    }
+}
@@ -12,7 +12,7 @@ const AFI_IP: u16 = 1;
 const AFI_IP6: u16 = 2;
 
 #[derive(Debug, Eq, PartialEq, NomBE)]
-pub struct MrtType(u16);
+pub struct MrtType(pub u16);
 
 newtype_enum! {
     impl display MrtType {
@@ -29,7 +29,7 @@ newtype_enum! {
 }
 
 #[derive(Debug, Eq, PartialEq, NomBE)]
-pub struct BgpSubType(u16);
+pub struct BgpSubType(pub u16);
 
 newtype_enum! {
     impl display BgpSubType {
@@ -45,7 +45,7 @@ newtype_enum! {
 }
 
 #[derive(Debug, Eq, PartialEq, NomBE)]
-pub struct TableDumpV2SubType(u16);
+pub struct TableDumpV2SubType(pub u16);
 
 newtype_enum! {
     impl display TableDumpV2SubType {
@@ -0,0 +1,209 @@
+use super::import::{BgpSubType, MrtType};
+use crate::bgp::task::Task;
+use bytes::{BufMut, BytesMut};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+const AFI_IP: u16 = 1;
+const AFI_IP6: u16 = 2;
+
+// Default maximum size of a file in kilobytes.
+pub const MESSAGE_JOURNAL_MAX_SIZE: u64 = 10240;
+
+#[derive(Debug)]
+pub struct MessageJournalConfig {
+    pub dir: Option<PathBuf>,
+    pub max_size: u64,
+}
+
+impl Default for MessageJournalConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_size: MESSAGE_JOURNAL_MAX_SIZE,
+        }
+    }
+}
+
+impl MessageJournalConfig {
+    // Journal of the peer, None when the directory is not configured.
+    pub fn start(&self, peer: IpAddr) -> Option<MessageJournal> {
+        let dir = self.dir.as_ref()?;
+        if let Err(err) = fs::create_dir_all(dir) {
+            println!("journal: can't create {}: {}", dir.display(), err);
+            return None;
+        }
+        let path = dir.join(format!("{}.mrt", peer));
+        Some(MessageJournal::start(path, self.max_size * 1024))
+    }
+}
+
+// BGP messages of a peer written to a file as MRT BGP4MP_ET records, received
+// messages as BGP4MP_MESSAGE_AS4 and sent messages as
+// BGP4MP_MESSAGE_AS4_LOCAL. The file is rotated to "<path>.1" when it reaches
+// the maximum size, so at most two files are kept for a peer.
+#[derive(Debug)]
+pub struct MessageJournal {
+    tx: UnboundedSender<BytesMut>,
+    task: Task<()>,
+}
+
+// Endpoints of a session stamped on every record.
+#[derive(Debug, Clone)]
+pub struct JournalSession {
+    tx: UnboundedSender<BytesMut>,
+    peer_as: u32,
+    local_as: u32,
+    ifindex: u16,
+    peer: IpAddr,
+    local: IpAddr,
+}
+
+fn journal_open(path: &PathBuf) -> Option<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path);
+    match file {
+        Ok(file) => {
+            let size = file.metadata().map(|x| x.len()).unwrap_or(0);
+            Some((file, size))
+        }
+        Err(err) => {
+            println!("journal: can't open {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+fn journal_rotate(path: &PathBuf) -> Option<(File, u64)> {
+    let mut rotated = path.clone().into_os_string();
+    rotated.push(".1");
+    if let Err(err) = fs::rename(path, &rotated) {
+        println!("journal: can't rotate {}: {}", path.display(), err);
+    }
+    journal_open(path)
+}
+
+async fn journal_write(path: PathBuf, max_size: u64, mut rx: UnboundedReceiver<BytesMut>) {
+    let (mut file, mut size) = match journal_open(&path) {
+        Some(file) => file,
+        None => return,
+    };
+    while let Some(record) = rx.recv().await {
+        if size > 0 && size + record.len() as u64 > max_size {
+            (file, size) = match journal_rotate(&path) {
+                Some(file) => file,
+                None => return,
+            };
+        }
+        if let Err(err) = file.write_all(&record) {
+            println!("journal: {} write error: {}", path.display(), err);
+            return;
+        }
+        size += record.len() as u64;
+    }
+}
+
+// Sender to the file of the journal, shared by the sessions of the peers.
+#[derive(Debug, Clone)]
+pub struct JournalWriter(UnboundedSender<BytesMut>);
+
+impl JournalWriter {
+    pub fn session(
+        &self,
+        peer_as: u32,
+        local_as: u32,
+        ifindex: u32,
+        peer: IpAddr,
+        local: IpAddr,
+    ) -> JournalSession {
+        JournalSession {
+            tx: self.0.clone(),
+            peer_as,
+            local_as,
+            ifindex: ifindex as u16,
+            peer,
+            local,
+        }
+    }
+}
+
+impl MessageJournal {
+    pub fn start(path: PathBuf, max_size: u64) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            tx,
+            task: Task::spawn(journal_write(path, max_size, rx)),
+        }
+    }
+
+    pub fn writer(&self) -> JournalWriter {
+        JournalWriter(self.tx.clone())
+    }
+
+    pub fn session(
+        &self,
+        peer_as: u32,
+        local_as: u32,
+        ifindex: u32,
+        peer: IpAddr,
+        local: IpAddr,
+    ) -> JournalSession {
+        self.writer()
+            .session(peer_as, local_as, ifindex, peer, local)
+    }
+}
+
+fn addr_put(buf: &mut BytesMut, addr: &IpAddr) {
+    match addr {
+        IpAddr::V4(addr) => buf.put(&addr.octets()[..]),
+        IpAddr::V6(addr) => buf.put(&addr.octets()[..]),
+    }
+}
+
+impl JournalSession {
+    // Record a BGP message, sent is true for a message sent to the peer.
+    pub fn record(&self, sent: bool, msg: &[u8]) {
+        let subtype = if sent {
+            BgpSubType::BGP4MP_MESSAGE_AS4_LOCAL
+        } else {
+            BgpSubType::BGP4MP_MESSAGE_AS4
+        };
+        self.write(subtype, msg);
+    }
+
+    // Record a transition of the FSM with the state codes of MRT, Idle is 1
+    // and Established is 6.
+    pub fn state_change(&self, old: u16, new: u16) {
+        let mut states = BytesMut::with_capacity(4);
+        states.put_u16(old);
+        states.put_u16(new);
+        self.write(BgpSubType::BGP4MP_STATE_CHANGE_AS4, &states);
+    }
+
+    fn write(&self, subtype: BgpSubType, data: &[u8]) {
+        let mut body = BytesMut::new();
+        body.put_u32(self.peer_as);
+        body.put_u32(self.local_as);
+        body.put_u16(self.ifindex);
+        body.put_u16(if self.peer.is_ipv6() { AFI_IP6 } else { AFI_IP });
+        addr_put(&mut body, &self.peer);
+        addr_put(&mut body, &self.local);
+        body.put(data);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut buf = BytesMut::with_capacity(16 + body.len());
+        buf.put_u32(now.as_secs() as u32);
+        buf.put_u16(MrtType::BGP4MP_ET.0);
+        buf.put_u16(subtype.0);
+        // Length includes the microsecond timestamp.
+        buf.put_u32(4 + body.len() as u32);
+        buf.put_u32(now.subsec_micros());
+        buf.put(&body[..]);
+        let _ = self.tx.send(buf);
+    }
+}
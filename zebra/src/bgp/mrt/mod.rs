@@ -1 +1,3 @@
+pub mod dump;
 pub mod import;
+pub mod journal;
@@ -0,0 +1,281 @@
+use super::handler::Bgp;
+use super::network::ROUTE_LOCAL;
+use super::ptree::{Prefix, PrefixTree};
+use super::rib::{rib_compare, rib_compare_path, Bestpath};
+use super::route::{route_advertise, Route};
+use crate::rib::api::{RibRoute, RibTx};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::net::IpAddr;
+
+// Administrative distance of the routes installed to the RIB.
+const DISTANCE_EBGP: u32 = 20;
+const DISTANCE_IBGP: u32 = 200;
+
+// Maximum paths of the ECMP group, 1 disables multipath.
+#[derive(Debug, Clone)]
+pub struct MultipathConfig {
+    pub ebgp: u32,
+    pub ibgp: u32,
+    // eBGP paths from different neighbor AS are also in the group.
+    pub allow_multiple_as: bool,
+}
+
+impl Default for MultipathConfig {
+    fn default() -> Self {
+        Self {
+            ebgp: 1,
+            ibgp: 1,
+            allow_multiple_as: false,
+        }
+    }
+}
+
+// ECMP groups installed to the RIB and the messages waiting for the RIB
+// channel.
+#[derive(Default)]
+pub struct Multipath {
+    pub config: MultipathConfig,
+    pub fib: BTreeMap<IpNet, RibRoute>,
+    pub queue: VecDeque<RibTx>,
+}
+
+fn multipath_equal(
+    best: &Route,
+    route: &Route,
+    config: &MultipathConfig,
+    bestpath: &Bestpath,
+) -> bool {
+    if route.from == ROUTE_LOCAL || !route.resolved {
+        return false;
+    }
+    if rib_compare_path(best, route, bestpath) != Ordering::Equal {
+        return false;
+    }
+    if route.ibgp || config.allow_multiple_as {
+        return true;
+    }
+    let best_aspath = best.as4path();
+    let aspath = route.as4path();
+    best_aspath.as_ref().and_then(|x| x.neighbor_as())
+        == aspath.as_ref().and_then(|x| x.neighbor_as())
+}
+
+fn route_nexthop(route: &Route) -> Option<IpAddr> {
+    match route.nexthop6() {
+        Some(nexthop) => Some(IpAddr::V6(nexthop)),
+        None => route.nexthop().map(IpAddr::V4),
+    }
+}
+
+// Mark the paths of the ECMP group with the best path, in order of
+// preference up to the maximum paths. Returns the route to the RIB, None
+// when no path is learned from the peer.
+pub fn multipath_select(
+    prefix: IpNet,
+    routes: &mut [Route],
+    config: &MultipathConfig,
+    bestpath: &Bestpath,
+) -> Option<RibRoute> {
+    for route in routes.iter_mut() {
+        route.multipath = false;
+    }
+    let best = routes.iter().position(|route| route.selected)?;
+    if routes[best].from == ROUTE_LOCAL {
+        return None;
+    }
+    let max = if routes[best].ibgp {
+        config.ibgp
+    } else {
+        config.ebgp
+    };
+    let mut paths: Vec<usize> = (0..routes.len())
+        .filter(|i| *i == best || multipath_equal(&routes[best], &routes[*i], config, bestpath))
+        .collect();
+    paths.sort_by(|a, b| {
+        (*a != best)
+            .cmp(&(*b != best))
+            .then_with(|| rib_compare(&routes[*a], &routes[*b], bestpath))
+    });
+    paths.truncate(max.max(1) as usize);
+
+    let mut nexthops = Vec::new();
+    for index in paths.iter() {
+        routes[*index].multipath = paths.len() > 1;
+        if let Some(nexthop) = route_nexthop(&routes[*index]) {
+            if !nexthops.contains(&nexthop) {
+                nexthops.push(nexthop);
+            }
+        }
+    }
+    if nexthops.is_empty() {
+        return None;
+    }
+    let distance = if routes[best].ibgp {
+        DISTANCE_IBGP
+    } else {
+        DISTANCE_EBGP
+    };
+    Some(RibRoute {
+        prefix,
+        distance,
+        nexthops,
+    })
+}
+
+fn multipath_prefix<P: Prefix + Into<IpNet>>(
+    ptree: &mut PrefixTree<P, Vec<Route>>,
+    multipath: &mut Multipath,
+    bestpath: &Bestpath,
+    prefix: &P,
+) -> bool {
+    let net: IpNet = (*prefix).into();
+    let route = match ptree.get_mut(prefix) {
+        Some(routes) => multipath_select(net, routes, &multipath.config, bestpath),
+        None => None,
+    };
+    let prev = multipath.fib.get(&net);
+    if prev == route.as_ref() {
+        return false;
+    }
+    match route {
+        Some(route) => {
+            multipath.fib.insert(net, route.clone());
+            multipath.queue.push_back(RibTx::RouteAdd(route));
+        }
+        None => {
+            if let Some(route) = multipath.fib.remove(&net) {
+                multipath.queue.push_back(RibTx::RouteDel(route));
+            }
+        }
+    }
+    true
+}
+
+// Update the ECMP groups of the prefixes. Returns the prefixes which group
+// is changed.
+pub fn multipath_update(
+    bgp: &mut Bgp,
+    prefixes: &[Ipv4Net],
+    prefixes6: &[Ipv6Net],
+) -> (Vec<Ipv4Net>, Vec<Ipv6Net>) {
+    let changed = prefixes
+        .iter()
+        .filter(|prefix| {
+            multipath_prefix(&mut bgp.ptree, &mut bgp.multipath, &bgp.bestpath, *prefix)
+        })
+        .copied()
+        .collect();
+    let changed6 = prefixes6
+        .iter()
+        .filter(|prefix| {
+            multipath_prefix(&mut bgp.ptree6, &mut bgp.multipath, &bgp.bestpath, *prefix)
+        })
+        .copied()
+        .collect();
+    (changed, changed6)
+}
+
+// Maximum paths change the groups of all of the prefixes. Prefixes which
+// group is changed are advertised again to ADD-PATH peers. The FIB is not
+// programmed in monitor-only mode.
+pub fn multipath_config_update(bgp: &mut Bgp) {
+    if bgp.monitor_only {
+        return;
+    }
+    let prefixes: Vec<Ipv4Net> = bgp.ptree.iter().map(|(prefix, _)| *prefix).collect();
+    let prefixes6: Vec<Ipv6Net> = bgp.ptree6.iter().map(|(prefix, _)| *prefix).collect();
+    let (changed, changed6) = multipath_update(bgp, &prefixes, &prefixes6);
+    route_advertise(bgp, &changed, &changed6);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{As4PathAttr, As4Segment, Attribute, NextHopAttr, AS_SEQUENCE};
+    use crate::bgp::rpki::RpkiState;
+    use crate::rib::RibTxChannel;
+    use std::net::Ipv4Addr;
+
+    fn route(from: [u8; 4], neighbor_as: u32, ibgp: bool) -> Route {
+        Route {
+            from: Ipv4Addr::from(from).into(),
+            router_id: Ipv4Addr::from(from),
+            attrs: vec![
+                Attribute::As4Path(As4PathAttr {
+                    segments: vec![As4Segment {
+                        typ: AS_SEQUENCE,
+                        asn: vec![neighbor_as],
+                    }],
+                }),
+                Attribute::NextHop(NextHopAttr { next_hop: from }),
+            ],
+            ibgp,
+            weight: 0,
+            metric: 0,
+            selected: false,
+            resolved: true,
+            path_id: 0,
+            local_path_id: 0,
+            local_label: None,
+            multipath: false,
+            rpki: RpkiState::NotFound,
+        }
+    }
+
+    #[test]
+    fn multipath_select_group() {
+        let prefix: IpNet = "10.0.0.0/8".parse().unwrap();
+        let mut routes = vec![
+            route([192, 0, 2, 3], 65001, false),
+            route([192, 0, 2, 1], 65001, false),
+            route([192, 0, 2, 2], 65002, false),
+            route([192, 0, 2, 4], 65001, true),
+        ];
+        routes[1].selected = true;
+        let mut config = MultipathConfig::default();
+        let bestpath = Bestpath::default();
+        let route = multipath_select(prefix, &mut routes, &config, &bestpath).unwrap();
+        assert_eq!(route.nexthops, vec!["192.0.2.1".parse::<IpAddr>().unwrap()]);
+        assert!(routes.iter().all(|route| !route.multipath));
+
+        // Paths from other neighbor AS and iBGP are not in the group.
+        config.ebgp = 4;
+        let route = multipath_select(prefix, &mut routes, &config, &bestpath).unwrap();
+        assert_eq!(route.nexthops.len(), 2);
+        assert_eq!(route.distance, DISTANCE_EBGP);
+        assert!(routes[0].multipath && routes[1].multipath);
+        assert!(!routes[2].multipath && !routes[3].multipath);
+
+        config.allow_multiple_as = true;
+        let route = multipath_select(prefix, &mut routes, &config, &bestpath).unwrap();
+        assert_eq!(route.nexthops[0], "192.0.2.1".parse::<IpAddr>().unwrap());
+        assert_eq!(route.nexthops.len(), 3);
+
+        // Best path and the next in order of preference.
+        config.ebgp = 2;
+        let route = multipath_select(prefix, &mut routes, &config, &bestpath).unwrap();
+        assert_eq!(route.nexthops.len(), 2);
+        assert!(routes[2].multipath && !routes[0].multipath);
+    }
+
+    #[test]
+    fn multipath_monitor_only() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let mut routes = vec![
+            route([192, 0, 2, 1], 65001, false),
+            route([192, 0, 2, 2], 65001, false),
+        ];
+        routes[0].selected = true;
+        bgp.ptree.insert("10.0.0.0/8".parse().unwrap(), routes);
+        bgp.monitor_only = true;
+        bgp.multipath.config.ebgp = 2;
+        multipath_config_update(&mut bgp);
+        assert!(bgp.multipath.queue.is_empty());
+
+        bgp.monitor_only = false;
+        multipath_config_update(&mut bgp);
+        assert_eq!(bgp.multipath.queue.len(), 1);
+    }
+}
@@ -0,0 +1,199 @@
+use super::handler::Bgp;
+use super::packet::{
+    Attribute, CommunityAttr, ExtendedCom, ExtendedComAttr, LargeCom, LargeComAttr, LocalPrefAttr,
+    MedAttr, OriginAttr,
+};
+use super::route::{route_add, route_advertise, route_withdraw_path, Route};
+use crate::policy::PolicyAction;
+use ipnet::IpNet;
+use std::collections::BTreeSet;
+use std::net::{IpAddr, Ipv4Addr};
+
+// Locally originated routes are held in Loc-RIB with the unspecified address
+// as the source.
+pub const ROUTE_LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+// Locally originated route is preferred over learned ones.
+pub const ROUTE_LOCAL_WEIGHT: u32 = 32768;
+
+// Prefix originated by "network" statement. When a health check is attached
+// the prefix is advertised only while the check is healthy. Attributes of the
+// route are set inline, then the route map modifies or filters them.
+#[derive(Debug, Default)]
+pub struct Network {
+    pub health_check: Option<String>,
+    pub med: Option<u32>,
+    pub local_pref: Option<u32>,
+    pub community: BTreeSet<u32>,
+    pub large_community: BTreeSet<LargeCom>,
+    pub ext_community: BTreeSet<ExtendedCom>,
+    // MPLS label for labeled unicast.
+    pub label: Option<u32>,
+    pub route_map: Option<String>,
+}
+
+impl Network {
+    fn attrs(&self) -> Vec<Attribute> {
+        let mut attrs = vec![Attribute::Origin(OriginAttr { origin: 0 })];
+        if let Some(med) = self.med {
+            attrs.push(Attribute::Med(MedAttr { med }));
+        }
+        if let Some(local_pref) = self.local_pref {
+            attrs.push(Attribute::LocalPref(LocalPrefAttr { local_pref }));
+        }
+        if !self.community.is_empty() {
+            let community = CommunityAttr(self.community.iter().cloned().collect());
+            attrs.push(Attribute::Community(community));
+        }
+        if !self.ext_community.is_empty() {
+            let ecom = ExtendedComAttr(self.ext_community.iter().cloned().collect());
+            attrs.push(Attribute::ExtendedCom(ecom));
+        }
+        if !self.large_community.is_empty() {
+            let lcom = LargeComAttr(self.large_community.iter().cloned().collect());
+            attrs.push(Attribute::LargeCom(lcom));
+        }
+        attrs
+    }
+}
+
+fn network_active(bgp: &Bgp, prefix: &IpNet) -> bool {
+    let network = match bgp.networks.get(prefix) {
+        Some(network) => network,
+        None => return false,
+    };
+    match &network.health_check {
+        Some(name) => bgp
+            .health
+            .get(name)
+            .map(|health| health.healthy)
+            .unwrap_or(false),
+        None => true,
+    }
+}
+
+// Route denied by the route map is not originated.
+fn route_local(bgp: &Bgp, prefix: &IpNet) -> Option<Route> {
+    let network = bgp.networks.get(prefix)?;
+    let mut attrs = network.attrs();
+    if let Some(name) = &network.route_map {
+        if bgp.policy.route_map_apply(name, prefix, &mut attrs) == PolicyAction::Deny {
+            return None;
+        }
+    }
+    let rpki = bgp.rpki.state(prefix, &attrs, bgp.asn);
+    Some(Route {
+        from: ROUTE_LOCAL,
+        router_id: bgp.router_id,
+        attrs,
+        ibgp: false,
+        weight: ROUTE_LOCAL_WEIGHT,
+        metric: 0,
+        selected: false,
+        resolved: true,
+        path_id: 0,
+        local_path_id: 0,
+        local_label: network.label,
+        multipath: false,
+        rpki,
+    })
+}
+
+// Originate or withdraw the prefix according to the configuration and the
+// state of the health check, then advertise the change to peers.
+pub fn network_apply(bgp: &mut Bgp, prefix: &IpNet) {
+    let route = if network_active(bgp, prefix) {
+        route_local(bgp, prefix)
+    } else {
+        None
+    };
+    match prefix {
+        IpNet::V4(prefix) => {
+            match route {
+                Some(route) => route_add(&mut bgp.ptree, *prefix, route, &bgp.bestpath),
+                None => route_withdraw_path(&mut bgp.ptree, ROUTE_LOCAL, 0, prefix, &bgp.bestpath),
+            }
+            route_advertise(bgp, &[*prefix], &[]);
+        }
+        IpNet::V6(prefix) => {
+            match route {
+                Some(route) => route_add(&mut bgp.ptree6, *prefix, route, &bgp.bestpath),
+                None => route_withdraw_path(&mut bgp.ptree6, ROUTE_LOCAL, 0, prefix, &bgp.bestpath),
+            }
+            route_advertise(bgp, &[], &[*prefix]);
+        }
+    }
+}
+
+pub fn network_add(bgp: &mut Bgp, prefix: IpNet) {
+    bgp.networks.entry(prefix).or_default();
+    network_apply(bgp, &prefix);
+}
+
+pub fn network_delete(bgp: &mut Bgp, prefix: IpNet) {
+    if bgp.networks.remove(&prefix).is_some() {
+        network_apply(bgp, &prefix);
+    }
+}
+
+pub fn network_health_check(bgp: &mut Bgp, prefix: IpNet, name: Option<String>) {
+    network_update(bgp, prefix, |network| network.health_check = name);
+}
+
+// Change the setting of the network and originate the route again.
+pub fn network_update<F: FnOnce(&mut Network)>(bgp: &mut Bgp, prefix: IpNet, f: F) {
+    if let Some(network) = bgp.networks.get_mut(&prefix) {
+        f(network);
+        network_apply(bgp, &prefix);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rib::RibTxChannel;
+    use ipnet::Ipv4Net;
+
+    #[test]
+    fn network_attrs() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let prefix: Ipv4Net = "10.1.0.0/16".parse().unwrap();
+        network_add(&mut bgp, IpNet::V4(prefix));
+        network_update(&mut bgp, IpNet::V4(prefix), |network| {
+            network.med = Some(100);
+            network.local_pref = Some(200);
+            network.community.insert(0xfde8_0001);
+            network.label = Some(16);
+        });
+
+        // Route is replaced in place with the inline attributes and label.
+        let routes = bgp.ptree.get(&prefix).unwrap();
+        assert_eq!(routes.len(), 1);
+        let route = &routes[0];
+        assert_eq!(route.from, ROUTE_LOCAL);
+        assert_eq!(route.weight, ROUTE_LOCAL_WEIGHT);
+        assert_eq!(route.local_label, Some(16));
+        let mut found = 0;
+        for attr in route.attrs.iter() {
+            match attr {
+                Attribute::Med(attr) if attr.med == 100 => found += 1,
+                Attribute::LocalPref(attr) if attr.local_pref == 200 => found += 1,
+                Attribute::Community(attr) if attr.0 == vec![0xfde8_0001] => found += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(found, 3);
+
+        // Clearing the label originates an unlabeled route.
+        network_update(&mut bgp, IpNet::V4(prefix), |network| network.label = None);
+        let routes = bgp.ptree.get(&prefix).unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].local_label, None);
+
+        network_delete(&mut bgp, IpNet::V4(prefix));
+        assert!(bgp
+            .ptree
+            .get(&prefix)
+            .map_or(true, |routes| routes.is_empty()));
+    }
+}
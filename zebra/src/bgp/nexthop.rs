@@ -0,0 +1,247 @@
+use super::handler::Bgp;
+use super::network::ROUTE_LOCAL;
+use super::ptree::{Prefix, PrefixTree};
+use super::rib::{rib_select, Bestpath};
+use super::route::{route_advertise, Route};
+use crate::policy::{Policy, PolicyAction};
+use ipnet::{Ipv4Net, Ipv6Net};
+use std::net::IpAddr;
+
+pub const NEXTHOP_MAX_DEPTH: u8 = 8;
+
+// Next hop resolution over Loc-RIB. Next hop which is not covered by a BGP
+// route is taken as resolved by connected or IGP route. Resolving over BGP
+// routes may be forbidden except for the prefixes permitted by the prefix
+// list, and the number of BGP routes followed is limited by the depth.
+#[derive(Debug)]
+pub struct NexthopConfig {
+    pub max_depth: u8,
+    pub resolve_over_bgp: bool,
+    pub prefix_list: Option<String>,
+    // Routes are resolved again at the end of the commit when set.
+    pub changed: bool,
+}
+
+impl Default for NexthopConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: NEXTHOP_MAX_DEPTH,
+            resolve_over_bgp: true,
+            prefix_list: None,
+            changed: false,
+        }
+    }
+}
+
+// Follow the best routes covering the next hop until it is resolved outside
+// of BGP. Next hop resolved through the route itself, directly or by a loop
+// of other routes, is unresolvable (RFC 4271 9.1.2.1).
+fn nexthop_resolve<P: Prefix>(
+    ptree: &PrefixTree<P, Vec<Route>>,
+    prefix: &P,
+    route: &Route,
+    config: &NexthopConfig,
+    nexthop: impl Fn(&Route) -> Option<P>,
+    permit: impl Fn(&P) -> bool,
+) -> bool {
+    let mut visited = vec![*prefix];
+    let mut route = route;
+    loop {
+        let host = match nexthop(route) {
+            Some(host) => host,
+            None => return true,
+        };
+        let (covering, routes) = match ptree.lookup(&host) {
+            Some(found) => found,
+            None => return true,
+        };
+        let best = match routes.iter().find(|route| route.selected) {
+            Some(best) => best,
+            None => return true,
+        };
+        if best.from == ROUTE_LOCAL {
+            return true;
+        }
+        if !config.resolve_over_bgp && !permit(covering) {
+            return false;
+        }
+        if visited.contains(covering) || visited.len() > config.max_depth as usize {
+            return false;
+        }
+        visited.push(*covering);
+        route = best;
+    }
+}
+
+// Next hop which is the address of the peer the route is received from is
+// reachable over the session without recursion.
+pub fn nexthop_resolve4(
+    ptree: &PrefixTree<Ipv4Net, Vec<Route>>,
+    prefix: &Ipv4Net,
+    route: &Route,
+    config: &NexthopConfig,
+    policy: &Policy,
+) -> bool {
+    nexthop_resolve(
+        ptree,
+        prefix,
+        route,
+        config,
+        |route| {
+            route
+                .nexthop()
+                .filter(|nexthop| IpAddr::V4(*nexthop) != route.from)
+                .map(Ipv4Net::from)
+        },
+        |covering| match &config.prefix_list {
+            Some(name) => policy.prefix_list_apply(name, covering) == PolicyAction::Permit,
+            None => false,
+        },
+    )
+}
+
+// Prefix lists are IPv4 only, so resolving over IPv6 BGP routes is either
+// allowed or forbidden as a whole.
+pub fn nexthop_resolve6(
+    ptree: &PrefixTree<Ipv6Net, Vec<Route>>,
+    prefix: &Ipv6Net,
+    route: &Route,
+    config: &NexthopConfig,
+) -> bool {
+    nexthop_resolve(
+        ptree,
+        prefix,
+        route,
+        config,
+        |route| {
+            route
+                .nexthop6()
+                .filter(|nexthop| IpAddr::V6(*nexthop) != route.from)
+                .map(Ipv6Net::from)
+        },
+        |_| false,
+    )
+}
+
+// Resolve all of the routes in the table again. Prefixes which routes
+// changed resolution are returned.
+fn nexthop_update_tree<P: Prefix>(
+    ptree: &mut PrefixTree<P, Vec<Route>>,
+    bestpath: &Bestpath,
+    resolve: impl Fn(&PrefixTree<P, Vec<Route>>, &P, &Route) -> bool,
+) -> Vec<P> {
+    let prefixes: Vec<P> = ptree.iter().map(|(prefix, _)| *prefix).collect();
+    let mut changed = Vec::new();
+    for prefix in prefixes.into_iter() {
+        let resolved: Vec<bool> = match ptree.get(&prefix) {
+            Some(routes) => routes
+                .iter()
+                .map(|route| resolve(ptree, &prefix, route))
+                .collect(),
+            None => continue,
+        };
+        let same = ptree
+            .get(&prefix)
+            .map(|routes| {
+                routes
+                    .iter()
+                    .zip(resolved.iter())
+                    .all(|(route, resolved)| route.resolved == *resolved)
+            })
+            .unwrap_or(true);
+        if same {
+            continue;
+        }
+        if let Some(routes) = ptree.get_mut(&prefix) {
+            for (route, resolved) in routes.iter_mut().zip(resolved.into_iter()) {
+                route.resolved = resolved;
+            }
+            rib_select(routes, bestpath);
+            changed.push(prefix);
+        }
+    }
+    changed
+}
+
+// Apply the change of the resolution config at the end of the commit.
+pub fn nexthop_update(bgp: &mut Bgp) {
+    if !bgp.nexthop.changed {
+        return;
+    }
+    bgp.nexthop.changed = false;
+    let config = &bgp.nexthop;
+    let policy = &bgp.policy;
+    let bestpath = &bgp.bestpath;
+    let changed = nexthop_update_tree(&mut bgp.ptree, bestpath, |ptree, prefix, route| {
+        nexthop_resolve4(ptree, prefix, route, config, policy)
+    });
+    let changed6 = nexthop_update_tree(&mut bgp.ptree6, bestpath, |ptree, prefix, route| {
+        nexthop_resolve6(ptree, prefix, route, config)
+    });
+    route_advertise(bgp, &changed, &changed6);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{Attribute, NextHopAttr};
+    use crate::bgp::rpki::RpkiState;
+    use std::net::Ipv4Addr;
+
+    fn route(from: &str, nexthop: &str) -> Route {
+        let nexthop: Ipv4Addr = nexthop.parse().unwrap();
+        Route {
+            from: from.parse().unwrap(),
+            router_id: Ipv4Addr::UNSPECIFIED,
+            attrs: vec![Attribute::NextHop(NextHopAttr {
+                next_hop: nexthop.octets(),
+            })],
+            ibgp: true,
+            weight: 0,
+            metric: 0,
+            selected: true,
+            resolved: true,
+            path_id: 0,
+            local_path_id: 0,
+            local_label: None,
+            multipath: false,
+            rpki: RpkiState::NotFound,
+        }
+    }
+
+    #[test]
+    fn resolve_over_bgp() {
+        let policy = Policy::default();
+        let mut config = NexthopConfig::default();
+        let mut ptree = PrefixTree::new();
+        ptree.insert(
+            "10.0.0.0/24".parse().unwrap(),
+            vec![route("192.168.0.1", "192.168.0.1")],
+        );
+        ptree.insert(
+            "10.1.0.0/16".parse().unwrap(),
+            vec![route("192.168.0.2", "10.2.0.1")],
+        );
+        ptree.insert(
+            "10.2.0.0/16".parse().unwrap(),
+            vec![route("192.168.0.2", "10.1.0.1")],
+        );
+
+        // Resolved over 10.0.0.0/24 learned from the next hop.
+        let prefix = "172.16.0.0/16".parse().unwrap();
+        let new = route("192.168.0.3", "10.0.0.1");
+        assert!(nexthop_resolve4(&ptree, &prefix, &new, &config, &policy));
+
+        // 10.1.0.0/16 and 10.2.0.0/16 resolve over each other.
+        let new = route("192.168.0.3", "10.1.0.1");
+        assert!(!nexthop_resolve4(&ptree, &prefix, &new, &config, &policy));
+
+        // Not covered by BGP route.
+        let new = route("192.168.0.3", "192.168.0.3");
+        assert!(nexthop_resolve4(&ptree, &prefix, &new, &config, &policy));
+
+        config.resolve_over_bgp = false;
+        let new = route("192.168.0.3", "10.0.0.1");
+        assert!(!nexthop_resolve4(&ptree, &prefix, &new, &config, &policy));
+    }
+}
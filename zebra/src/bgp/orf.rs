@@ -0,0 +1,195 @@
+use super::packet::{
+    BgpType, CapabilityOrf, CapabilityPacket, OrfValue, PrefixOrf, RouteRefreshOrf,
+    RouteRefreshPacket, ORF_IMMEDIATE, ORF_RECEIVE, ORF_SEND, ORF_TYPE_PREFIX,
+};
+use super::peer::{Peer, PeerConfig};
+use super::{Afi, AfiSafi, Safi};
+use crate::policy::{Policy, PolicyAction, PrefixList, PrefixListEntry};
+use bytes::BytesMut;
+
+// Address Prefix ORF capability of IPv4 unicast advertised in OPEN, when ORF
+// is configured for the neighbor.
+pub fn orf_capability(config: &PeerConfig) -> Option<CapabilityOrf> {
+    if config.orf == 0 || !config.afi_safi.has(&AfiSafi::new(Afi::IP, Safi::Unicast)) {
+        return None;
+    }
+    let value = OrfValue {
+        typ: ORF_TYPE_PREFIX,
+        send_receive: config.orf,
+    };
+    Some(CapabilityOrf::new(Afi::IP, Safi::Unicast, vec![value]))
+}
+
+// ORF is sent when we advertise send and the peer advertises receive, and
+// vice versa (RFC 5291 3).
+pub fn orf_negotiated(config: &PeerConfig, send: bool) -> bool {
+    let (local, remote) = if send {
+        (ORF_SEND, ORF_RECEIVE)
+    } else {
+        (ORF_RECEIVE, ORF_SEND)
+    };
+    if config.orf & local == 0 {
+        return false;
+    }
+    config.received.iter().any(|cap| match cap {
+        CapabilityPacket::Orf(m) => {
+            m.afi == Afi::IP
+                && m.safi == Safi::Unicast
+                && m.values
+                    .iter()
+                    .any(|value| value.typ == ORF_TYPE_PREFIX && value.send_receive & remote != 0)
+        }
+        _ => false,
+    })
+}
+
+// Zero length is not specified in ORF, as le and ge are in prefix list.
+fn orf_len(len: Option<u8>) -> u8 {
+    len.unwrap_or(0)
+}
+
+fn orf_len_option(len: u8) -> Option<u8> {
+    if len == 0 {
+        None
+    } else {
+        Some(len)
+    }
+}
+
+// Whole prefix list replaces the one the peer holds for us.
+pub fn orf_build(plist: Option<&PrefixList>) -> RouteRefreshOrf {
+    let mut entries = Vec::new();
+    if let Some(plist) = plist {
+        for (seq, entry) in plist.entry.iter() {
+            let (action, prefix) = match (entry.action, entry.prefix) {
+                (Some(action), Some(prefix)) => (action, prefix),
+                _ => continue,
+            };
+            entries.push(PrefixOrf {
+                remove: false,
+                deny: action == PolicyAction::Deny,
+                seq: *seq,
+                min: orf_len(entry.ge),
+                max: orf_len(entry.le),
+                prefix,
+            });
+        }
+    }
+    RouteRefreshOrf {
+        when: ORF_IMMEDIATE,
+        remove_all: true,
+        entries,
+    }
+}
+
+// Apply received ORF entries to the prefix list of the peer. No prefix list
+// is kept when all of the entries are removed, so that routes are not
+// filtered.
+pub fn orf_update(current: &mut Option<PrefixList>, orf: &RouteRefreshOrf) {
+    let mut plist = match current.take() {
+        Some(plist) if !orf.remove_all => plist,
+        _ => PrefixList::default(),
+    };
+    for entry in orf.entries.iter() {
+        if entry.remove {
+            plist.entry.remove(&entry.seq);
+            continue;
+        }
+        let action = if entry.deny {
+            PolicyAction::Deny
+        } else {
+            PolicyAction::Permit
+        };
+        plist.entry.insert(
+            entry.seq,
+            PrefixListEntry {
+                action: Some(action),
+                prefix: Some(entry.prefix),
+                ge: orf_len_option(entry.min),
+                le: orf_len_option(entry.max),
+            },
+        );
+    }
+    if !plist.entry.is_empty() {
+        *current = Some(plist);
+    }
+}
+
+// Push our inbound prefix list to the peer. The peer re-advertises its
+// Adj-RIB-Out filtered with it.
+pub fn peer_send_orf(peer: &mut Peer, policy: &Policy) {
+    let plist = peer
+        .config
+        .prefix_list_in
+        .as_ref()
+        .and_then(|name| policy.plist.get(name));
+    let mut refresh = RouteRefreshPacket::new(Afi::IP, Safi::Unicast);
+    refresh.orf = Some(orf_build(plist));
+    let bytes: BytesMut = refresh.into();
+    peer.counter[BgpType::RouteRefresh as usize].sent += 1;
+    if let Some(packet_tx) = peer.packet_tx.as_ref() {
+        let _ = packet_tx.send(bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ipnet::Ipv4Net;
+
+    #[test]
+    fn orf_same_as_prefix_list() {
+        let mut plist = PrefixList::default();
+        plist.entry.insert(
+            5,
+            PrefixListEntry {
+                action: Some(PolicyAction::Deny),
+                prefix: Some("10.1.0.0/16".parse().unwrap()),
+                le: Some(32),
+                ..Default::default()
+            },
+        );
+        plist.entry.insert(
+            10,
+            PrefixListEntry {
+                action: Some(PolicyAction::Permit),
+                prefix: Some("10.0.0.0/8".parse().unwrap()),
+                ge: Some(16),
+                le: Some(24),
+            },
+        );
+        plist.entry.insert(
+            20,
+            PrefixListEntry {
+                action: Some(PolicyAction::Permit),
+                prefix: Some("192.0.2.0/24".parse().unwrap()),
+                ..Default::default()
+            },
+        );
+        let mut received = Some(PrefixList::default());
+        orf_update(&mut received, &orf_build(Some(&plist)));
+        let received = received.unwrap();
+        let prefixes = [
+            "10.0.0.0/8",
+            "10.1.1.0/24",
+            "10.2.0.0/16",
+            "10.2.1.128/25",
+            "192.0.2.0/24",
+            "192.0.2.0/25",
+        ];
+        for prefix in prefixes {
+            let prefix: Ipv4Net = prefix.parse().unwrap();
+            assert_eq!(received.apply(&prefix), plist.apply(&prefix));
+        }
+
+        // Removing all of the entries stops filtering.
+        let mut received = Some(received);
+        let mut orf = orf_build(Some(&plist));
+        orf.remove_all = false;
+        for entry in orf.entries.iter_mut() {
+            entry.remove = true;
+        }
+        orf_update(&mut received, &orf);
+        assert!(received.is_none());
+    }
+}
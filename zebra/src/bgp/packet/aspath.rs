@@ -1,37 +1,406 @@
 #![allow(dead_code)]
 use nom_derive::*;
+use std::fmt;
 
 pub const AS_SET: u8 = 1;
 pub const AS_SEQUENCE: u8 = 2;
 pub const AS_CONFED_SEQUENCE: u8 = 3;
 pub const AS_CONFED_SET: u8 = 4;
 
+pub const AS_TRANS: u16 = 23456;
+
+// Private Use ASNs (RFC 6996 5).
+pub fn is_private_as(asn: u32) -> bool {
+    (64512..=65534).contains(&asn) || (4200000000..=4294967294).contains(&asn)
+}
+
 #[derive(Debug, NomBE)]
 pub struct AsSegmentHeader {
     pub typ: u8,
     pub length: u8,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct AsSegment {
     pub typ: u8,
     pub asn: Vec<u16>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct AsPathAttr {
     pub segments: Vec<AsSegment>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct As4Segment {
     pub typ: u8,
     pub asn: Vec<u32>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct As4PathAttr {
     pub segments: Vec<As4Segment>,
 }
 
+impl As4Segment {
+    pub fn is_confed(&self) -> bool {
+        self.typ == AS_CONFED_SEQUENCE || self.typ == AS_CONFED_SET
+    }
+
+    // Number of AS counted for AS path length (RFC 4271 9.1.2.2).
+    pub fn count(&self) -> usize {
+        match self.typ {
+            AS_SET => 1,
+            AS_CONFED_SEQUENCE | AS_CONFED_SET => 0,
+            _ => self.asn.len(),
+        }
+    }
+}
+
+impl As4PathAttr {
+    pub fn length(&self) -> usize {
+        self.segments.iter().map(|x| x.count()).sum()
+    }
+
+    // Leftmost AS of the path, the AS which the route was received from.
+    pub fn neighbor_as(&self) -> Option<u32> {
+        match self.segments.first() {
+            Some(segment) if segment.typ == AS_SEQUENCE => segment.asn.first().cloned(),
+            _ => None,
+        }
+    }
+
+    // Rightmost AS of the path, the AS which originated the route. None when
+    // the path is empty or ends with AS_SET.
+    pub fn origin_as(&self) -> Option<u32> {
+        let segment = self
+            .segments
+            .iter()
+            .rev()
+            .find(|x| x.typ == AS_SEQUENCE || x.typ == AS_SET)?;
+        match segment.typ {
+            AS_SEQUENCE => segment.asn.last().cloned(),
+            _ => None,
+        }
+    }
+
+    // AS path with the AS prepended to the leftmost AS_SEQUENCE.
+    pub fn prepend(&self, asn: u32) -> As4PathAttr {
+        let mut segments = self.segments.clone();
+        match segments.first_mut() {
+            Some(segment) if segment.typ == AS_SEQUENCE && segment.asn.len() < 255 => {
+                segment.asn.insert(0, asn);
+            }
+            _ => segments.insert(
+                0,
+                As4Segment {
+                    typ: AS_SEQUENCE,
+                    asn: vec![asn],
+                },
+            ),
+        }
+        As4PathAttr { segments }
+    }
+
+    // AS path with the member AS prepended to the leftmost AS_CONFED_SEQUENCE
+    // for the neighbor in the confederation (RFC 5065 4.1).
+    pub fn confed_prepend(&self, asn: u32) -> As4PathAttr {
+        let mut segments = self.segments.clone();
+        match segments.first_mut() {
+            Some(segment) if segment.typ == AS_CONFED_SEQUENCE && segment.asn.len() < 255 => {
+                segment.asn.insert(0, asn);
+            }
+            _ => segments.insert(
+                0,
+                As4Segment {
+                    typ: AS_CONFED_SEQUENCE,
+                    asn: vec![asn],
+                },
+            ),
+        }
+        As4PathAttr { segments }
+    }
+
+    // AS path without the confederation segments for the neighbor outside of
+    // the confederation.
+    pub fn confed_remove(&self) -> As4PathAttr {
+        let segments = self
+            .segments
+            .iter()
+            .filter(|x| !x.is_confed())
+            .cloned()
+            .collect();
+        As4PathAttr { segments }
+    }
+
+    // Occurrences of the AS out of the confederation segments.
+    pub fn count_as(&self, asn: u32) -> usize {
+        self.segments
+            .iter()
+            .filter(|x| !x.is_confed())
+            .map(|x| x.asn.iter().filter(|x| **x == asn).count())
+            .sum()
+    }
+
+    pub fn has_confed(&self) -> bool {
+        self.segments.iter().any(|x| x.is_confed())
+    }
+
+    pub fn confed_contains(&self, asn: u32) -> bool {
+        self.segments
+            .iter()
+            .any(|x| x.is_confed() && x.asn.contains(&asn))
+    }
+
+    // AS path without the private ASes. Segments left empty are removed.
+    pub fn remove_private(&self) -> As4PathAttr {
+        let segments = self
+            .segments
+            .iter()
+            .map(|x| As4Segment {
+                typ: x.typ,
+                asn: x
+                    .asn
+                    .iter()
+                    .filter(|x| !is_private_as(**x))
+                    .cloned()
+                    .collect(),
+            })
+            .filter(|x| !x.asn.is_empty())
+            .collect();
+        As4PathAttr { segments }
+    }
+
+    // AS path with the ASes matching the predicate replaced with the AS.
+    fn replace(&self, matches: impl Fn(u32) -> bool, asn: u32) -> As4PathAttr {
+        let segments = self
+            .segments
+            .iter()
+            .map(|x| As4Segment {
+                typ: x.typ,
+                asn: x
+                    .asn
+                    .iter()
+                    .map(|x| if matches(*x) { asn } else { *x })
+                    .collect(),
+            })
+            .collect();
+        As4PathAttr { segments }
+    }
+
+    pub fn replace_private(&self, asn: u32) -> As4PathAttr {
+        self.replace(is_private_as, asn)
+    }
+
+    pub fn replace_as(&self, from: u32, asn: u32) -> As4PathAttr {
+        self.replace(|x| x == from, asn)
+    }
+
+    // Whether the path can't be represented in 2 octet AS.
+    pub fn is_as4(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|x| x.asn.iter().any(|asn| *asn > u16::MAX as u32))
+    }
+}
+
+// 2 octet AS path, AS which does not fit is replaced by AS_TRANS.
+impl From<&As4PathAttr> for AsPathAttr {
+    fn from(aspath: &As4PathAttr) -> Self {
+        let segments = aspath
+            .segments
+            .iter()
+            .map(|x| AsSegment {
+                typ: x.typ,
+                asn: x
+                    .asn
+                    .iter()
+                    .map(|asn| {
+                        if *asn > u16::MAX as u32 {
+                            AS_TRANS
+                        } else {
+                            *asn as u16
+                        }
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self { segments }
+    }
+}
+
+impl From<&AsPathAttr> for As4PathAttr {
+    fn from(aspath: &AsPathAttr) -> Self {
+        let segments = aspath
+            .segments
+            .iter()
+            .map(|x| As4Segment {
+                typ: x.typ,
+                asn: x.asn.iter().map(|asn| *asn as u32).collect(),
+            })
+            .collect();
+        Self { segments }
+    }
+}
+
+impl AsPathAttr {
+    // Reconstruct 4 octet AS path from AS_PATH and AS4_PATH (RFC 6793 4.2.3).
+    pub fn merge_as4(&self, as4path: &As4PathAttr) -> As4PathAttr {
+        let aspath = As4PathAttr::from(self);
+        let length = aspath.length();
+        let as4length = as4path.length();
+        if length < as4length {
+            return aspath;
+        }
+        let mut remain = length - as4length;
+        let mut segments = Vec::new();
+        for mut segment in aspath.segments.into_iter() {
+            if remain == 0 {
+                break;
+            }
+            let count = segment.count();
+            if count > remain {
+                segment.asn.truncate(remain);
+                remain = 0;
+            } else {
+                remain -= count;
+            }
+            segments.push(segment);
+        }
+        segments.extend(as4path.segments.iter().cloned());
+        As4PathAttr { segments }
+    }
+}
+
+fn segment_fmt<T: fmt::Display>(typ: u8, asn: &[T]) -> String {
+    let asn: Vec<String> = asn.iter().map(|x| x.to_string()).collect();
+    match typ {
+        AS_SET => format!("{{{}}}", asn.join(",")),
+        AS_CONFED_SEQUENCE => format!("({})", asn.join(" ")),
+        AS_CONFED_SET => format!("[{}]", asn.join(",")),
+        _ => asn.join(" "),
+    }
+}
+
+impl fmt::Display for AsPathAttr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let segments: Vec<String> = self
+            .segments
+            .iter()
+            .map(|x| segment_fmt(x.typ, &x.asn))
+            .collect();
+        write!(f, "{}", segments.join(" "))
+    }
+}
+
+impl fmt::Display for As4PathAttr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let segments: Vec<String> = self
+            .segments
+            .iter()
+            .map(|x| segment_fmt(x.typ, &x.asn))
+            .collect();
+        write!(f, "{}", segments.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merge_as4() {
+        let aspath = AsPathAttr {
+            segments: vec![AsSegment {
+                typ: AS_SEQUENCE,
+                asn: vec![100, AS_TRANS, AS_TRANS],
+            }],
+        };
+        let as4path = As4PathAttr {
+            segments: vec![As4Segment {
+                typ: AS_SEQUENCE,
+                asn: vec![70000, 70001],
+            }],
+        };
+        let merged = aspath.merge_as4(&as4path);
+        assert_eq!(merged.to_string(), "100 70000 70001");
+        assert_eq!(merged.length(), 3);
+    }
+
+    #[test]
+    fn prepend_as2() {
+        let aspath = As4PathAttr {
+            segments: vec![As4Segment {
+                typ: AS_SET,
+                asn: vec![100, 200],
+            }],
+        };
+        let aspath = aspath.prepend(70000);
+        assert_eq!(aspath.to_string(), "70000 {100,200}");
+        assert!(aspath.is_as4());
+        let aspath = AsPathAttr::from(&aspath.prepend(65000));
+        assert_eq!(aspath.to_string(), "65000 23456 {100,200}");
+    }
+
+    #[test]
+    fn merge_as4_longer() {
+        let aspath = AsPathAttr {
+            segments: vec![AsSegment {
+                typ: AS_SEQUENCE,
+                asn: vec![100, 200],
+            }],
+        };
+        let as4path = As4PathAttr {
+            segments: vec![As4Segment {
+                typ: AS_SEQUENCE,
+                asn: vec![70000, 70001, 70002],
+            }],
+        };
+        let merged = aspath.merge_as4(&as4path);
+        assert_eq!(merged.to_string(), "100 200");
+    }
+
+    #[test]
+    fn confed_path() {
+        let aspath = As4PathAttr {
+            segments: vec![As4Segment {
+                typ: AS_SEQUENCE,
+                asn: vec![100],
+            }],
+        };
+        let aspath = aspath.confed_prepend(65001).confed_prepend(65002);
+        assert_eq!(aspath.to_string(), "(65002 65001) 100");
+        assert_eq!(aspath.length(), 1);
+        assert!(aspath.confed_contains(65001));
+        assert!(!aspath.confed_contains(100));
+        let aspath = aspath.confed_remove().prepend(65000);
+        assert_eq!(aspath.to_string(), "65000 100");
+        assert!(!aspath.has_confed());
+        assert_eq!(aspath.prepend(65000).count_as(65000), 2);
+    }
+
+    #[test]
+    fn private_as() {
+        let aspath = As4PathAttr {
+            segments: vec![
+                As4Segment {
+                    typ: AS_SEQUENCE,
+                    asn: vec![64512, 100, 4200000000],
+                },
+                As4Segment {
+                    typ: AS_SET,
+                    asn: vec![65001],
+                },
+            ],
+        };
+        assert_eq!(aspath.remove_private().to_string(), "100");
+        assert_eq!(aspath.replace_private(200).to_string(), "200 100 200 {200}");
+        assert_eq!(
+            aspath.replace_as(100, 200).to_string(),
+            "64512 200 4200000000 {65001}"
+        );
+        assert!(!is_private_as(65535));
+    }
+}
+
 // let output: Vec<u8> = input.iter().flat_map(|val| val.to_be_bytes()).collect();
@@ -1,17 +1,24 @@
 #![allow(dead_code)]
-use super::{As4PathAttr, AsPathAttr, CommunityAttr, ExtendedComAttr, LargeComAttr};
+use super::{
+    labeled_ipv6_prefix_encode, As4PathAttr, AsPathAttr, CommunityAttr, ExtendedComAttr,
+    LargeComAttr, AS_TRANS, LABEL_IMPLICIT_NULL,
+};
 use crate::bgp::{Afi, Safi};
+use bytes::{BufMut, BytesMut};
 use ipnet::Ipv6Net;
+use nom::error::{make_error, ErrorKind};
+use nom::IResult;
 use nom_derive::*;
 use rusticata_macros::newtype_enum;
-use std::net::Ipv6Addr;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 pub const BGP_ATTR_FLAG_OPTIONAL: u8 = 0x80;
 pub const BGP_ATTR_FLAG_TRNANSITIVE: u8 = 0x40;
 pub const BGP_ATTR_FLAG_WELL_KNOWN: u8 = 0x20;
 pub const BGP_ATTR_FLAG_EXTENDED_LENGTH: u8 = 0x10;
 
-#[derive(Debug, Eq, PartialEq, NomBE)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, NomBE)]
 pub struct AttributeType(pub u8);
 
 newtype_enum! {
@@ -24,13 +31,40 @@ newtype_enum! {
         AtomicAggregate = 6,
         Aggregator = 7,
         Community = 8,
+        OriginatorId = 9,
+        ClusterList = 10,
         MpReachNlri = 14,
         MpUnreachNlri = 15,
         ExtendedCom = 16,
+        As4Path = 17,
+        As4Aggregator = 18,
         LargeCom = 32,
     }
 }
 
+impl AttributeType {
+    // Optional and Transitive bits of the known attribute (RFC 4271 5).
+    pub fn flags(&self) -> Option<u8> {
+        const TRANSITIVE: u8 = BGP_ATTR_FLAG_TRNANSITIVE;
+        const OPTIONAL: u8 = BGP_ATTR_FLAG_OPTIONAL;
+        const OPTIONAL_TRANSITIVE: u8 = BGP_ATTR_FLAG_OPTIONAL | BGP_ATTR_FLAG_TRNANSITIVE;
+
+        match *self {
+            Self::Origin | Self::AsPath | Self::NextHop | Self::LocalPref => Some(TRANSITIVE),
+            Self::AtomicAggregate => Some(TRANSITIVE),
+            Self::Med | Self::MpReachNlri | Self::MpUnreachNlri => Some(OPTIONAL),
+            Self::OriginatorId | Self::ClusterList => Some(OPTIONAL),
+            Self::Aggregator
+            | Self::Community
+            | Self::ExtendedCom
+            | Self::As4Path
+            | Self::As4Aggregator
+            | Self::LargeCom => Some(OPTIONAL_TRANSITIVE),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Attribute {
     Origin(OriginAttr),
@@ -43,14 +77,239 @@ pub enum Attribute {
     Aggregator(AggregatorAttr),
     Aggregator4(Aggregator4Attr),
     Community(CommunityAttr),
+    // ORIGINATOR_ID and CLUSTER_LIST of route reflection (RFC 4456 8).
+    OriginatorId(OriginatorIdAttr),
+    ClusterList(ClusterListAttr),
     MpReachNlri(MpNlriAttr),
     MpUnreachNlri(MpNlriAttr),
+    // MP_REACH_NLRI and MP_UNREACH_NLRI of the AFI/SAFI not handled by the
+    // core, which NLRI is decoded by the registered address family.
+    MpReachRaw(MpRawAttr),
+    MpUnreachRaw(MpRawAttr),
     ExtendedCom(ExtendedComAttr),
+    // AS4_PATH and AS4_AGGREGATOR (RFC 6793).
+    NewAsPath(As4PathAttr),
+    NewAggregator(Aggregator4Attr),
     LargeCom(LargeComAttr),
 }
 
 pub type Attrs = Vec<Attribute>;
 
+// Convert 4 octet AS_PATH and AGGREGATOR for a 2 octet speaker. AS4_PATH and
+// AS4_AGGREGATOR are added when the AS does not fit (RFC 6793 4.2.2).
+pub fn attrs_as2(attrs: Attrs) -> Attrs {
+    let mut new = Vec::new();
+    for attr in attrs.into_iter() {
+        match attr {
+            Attribute::As4Path(aspath) => {
+                new.push(Attribute::AsPath(AsPathAttr::from(&aspath)));
+                if aspath.is_as4() {
+                    new.push(Attribute::NewAsPath(aspath));
+                }
+            }
+            Attribute::Aggregator4(aggregator) => {
+                if aggregator.asn > u16::MAX as u32 {
+                    new.push(Attribute::Aggregator(AggregatorAttr {
+                        asn: AS_TRANS,
+                        ip: aggregator.ip,
+                    }));
+                    new.push(Attribute::NewAggregator(aggregator));
+                } else {
+                    new.push(Attribute::Aggregator(AggregatorAttr {
+                        asn: aggregator.asn as u16,
+                        ip: aggregator.ip,
+                    }));
+                }
+            }
+            _ => new.push(attr),
+        }
+    }
+    new
+}
+
+fn attr_encode(buf: &mut BytesMut, flags: u8, typ: AttributeType, value: &[u8]) {
+    if value.len() > 255 {
+        buf.put_u8(flags | BGP_ATTR_FLAG_EXTENDED_LENGTH);
+        buf.put_u8(typ.0);
+        buf.put_u16(value.len() as u16);
+    } else {
+        buf.put_u8(flags);
+        buf.put_u8(typ.0);
+        buf.put_u8(value.len() as u8);
+    }
+    buf.put(value);
+}
+
+fn ipv6_prefix_encode(buf: &mut BytesMut, prefix: &Ipv6Net) {
+    let psize = (prefix.prefix_len() as usize + 7) / 8;
+    buf.put_u8(prefix.prefix_len());
+    buf.put(&prefix.addr().octets()[..psize]);
+}
+
+impl Attribute {
+    pub fn encode(&self, buf: &mut BytesMut) {
+        const TRANSITIVE: u8 = BGP_ATTR_FLAG_TRNANSITIVE;
+        const OPTIONAL: u8 = BGP_ATTR_FLAG_OPTIONAL;
+        const OPTIONAL_TRANSITIVE: u8 = BGP_ATTR_FLAG_OPTIONAL | BGP_ATTR_FLAG_TRNANSITIVE;
+
+        let mut value = BytesMut::new();
+        match self {
+            Self::Origin(m) => {
+                value.put_u8(m.origin);
+                attr_encode(buf, TRANSITIVE, AttributeType::Origin, &value);
+            }
+            Self::AsPath(m) => {
+                for segment in m.segments.iter() {
+                    value.put_u8(segment.typ);
+                    value.put_u8(segment.asn.len() as u8);
+                    for asn in segment.asn.iter() {
+                        value.put_u16(*asn);
+                    }
+                }
+                attr_encode(buf, TRANSITIVE, AttributeType::AsPath, &value);
+            }
+            Self::As4Path(m) | Self::NewAsPath(m) => {
+                for segment in m.segments.iter() {
+                    value.put_u8(segment.typ);
+                    value.put_u8(segment.asn.len() as u8);
+                    for asn in segment.asn.iter() {
+                        value.put_u32(*asn);
+                    }
+                }
+                if let Self::As4Path(_) = self {
+                    attr_encode(buf, TRANSITIVE, AttributeType::AsPath, &value);
+                } else {
+                    attr_encode(buf, OPTIONAL_TRANSITIVE, AttributeType::As4Path, &value);
+                }
+            }
+            Self::NextHop(m) => {
+                value.put(&m.next_hop[..]);
+                attr_encode(buf, TRANSITIVE, AttributeType::NextHop, &value);
+            }
+            Self::Med(m) => {
+                value.put_u32(m.med);
+                attr_encode(buf, OPTIONAL, AttributeType::Med, &value);
+            }
+            Self::LocalPref(m) => {
+                value.put_u32(m.local_pref);
+                attr_encode(buf, TRANSITIVE, AttributeType::LocalPref, &value);
+            }
+            Self::AtomicAggregate(_) => {
+                attr_encode(buf, TRANSITIVE, AttributeType::AtomicAggregate, &value);
+            }
+            Self::Aggregator(m) => {
+                value.put_u16(m.asn);
+                value.put_u32(m.ip);
+                attr_encode(buf, OPTIONAL_TRANSITIVE, AttributeType::Aggregator, &value);
+            }
+            Self::Aggregator4(m) | Self::NewAggregator(m) => {
+                value.put_u32(m.asn);
+                value.put_u32(m.ip);
+                let typ = if let Self::Aggregator4(_) = self {
+                    AttributeType::Aggregator
+                } else {
+                    AttributeType::As4Aggregator
+                };
+                attr_encode(buf, OPTIONAL_TRANSITIVE, typ, &value);
+            }
+            Self::Community(m) => {
+                for community in m.0.iter() {
+                    value.put_u32(*community);
+                }
+                attr_encode(buf, OPTIONAL_TRANSITIVE, AttributeType::Community, &value);
+            }
+            Self::OriginatorId(m) => {
+                value.put_u32(m.id);
+                attr_encode(buf, OPTIONAL, AttributeType::OriginatorId, &value);
+            }
+            Self::ClusterList(m) => {
+                for id in m.0.iter() {
+                    value.put_u32(*id);
+                }
+                attr_encode(buf, OPTIONAL, AttributeType::ClusterList, &value);
+            }
+            Self::MpReachNlri(m) => {
+                value.put_u16(Afi::IP6.0);
+                value.put_u8(m.safi.0);
+                let next_hop = m.next_hop.unwrap_or(Ipv6Addr::UNSPECIFIED);
+                if let Some(link_local) = m.link_local {
+                    value.put_u8(32);
+                    value.put(&next_hop.octets()[..]);
+                    value.put(&link_local.octets()[..]);
+                } else {
+                    value.put_u8(16);
+                    value.put(&next_hop.octets()[..]);
+                }
+                value.put_u8(0);
+                for (i, prefix) in m.prefix.iter().enumerate() {
+                    let mut nlri = BytesMut::new();
+                    if let Some(path_id) = m.path_id.get(i) {
+                        nlri.put_u32(*path_id);
+                    }
+                    if m.safi == Safi::MplsLabel {
+                        let labels: &[u32] = match m.label.get(i) {
+                            Some(labels) if !labels.is_empty() => labels.as_slice(),
+                            _ => &[LABEL_IMPLICIT_NULL],
+                        };
+                        // Prefix which label stack doesn't fit is left out.
+                        if labeled_ipv6_prefix_encode(&mut nlri, prefix, labels).is_none() {
+                            continue;
+                        }
+                    } else {
+                        ipv6_prefix_encode(&mut nlri, prefix);
+                    }
+                    value.put(&nlri[..]);
+                }
+                attr_encode(buf, OPTIONAL, AttributeType::MpReachNlri, &value);
+            }
+            Self::MpUnreachNlri(m) => {
+                value.put_u16(Afi::IP6.0);
+                value.put_u8(m.safi.0);
+                for (i, prefix) in m.prefix.iter().enumerate() {
+                    if let Some(path_id) = m.path_id.get(i) {
+                        value.put_u32(*path_id);
+                    }
+                    if m.safi == Safi::MplsLabel {
+                        labeled_ipv6_prefix_encode(&mut value, prefix, &[]);
+                    } else {
+                        ipv6_prefix_encode(&mut value, prefix);
+                    }
+                }
+                attr_encode(buf, OPTIONAL, AttributeType::MpUnreachNlri, &value);
+            }
+            Self::MpReachRaw(m) => {
+                value.put_u16(m.afi.0);
+                value.put_u8(m.safi.0);
+                value.put_u8(m.next_hop.len() as u8);
+                value.put(&m.next_hop[..]);
+                value.put_u8(0);
+                value.put(&m.nlri[..]);
+                attr_encode(buf, OPTIONAL, AttributeType::MpReachNlri, &value);
+            }
+            Self::MpUnreachRaw(m) => {
+                value.put_u16(m.afi.0);
+                value.put_u8(m.safi.0);
+                value.put(&m.nlri[..]);
+                attr_encode(buf, OPTIONAL, AttributeType::MpUnreachNlri, &value);
+            }
+            Self::ExtendedCom(m) => {
+                for ecom in m.0.iter() {
+                    value.put_slice(&ecom.encode());
+                }
+                attr_encode(buf, OPTIONAL_TRANSITIVE, AttributeType::ExtendedCom, &value);
+            }
+            Self::LargeCom(m) => {
+                for lcom in m.0.iter() {
+                    value.put_u32(lcom.global);
+                    value.put_u32(lcom.local1);
+                    value.put_u32(lcom.local2);
+                }
+                attr_encode(buf, OPTIONAL_TRANSITIVE, AttributeType::LargeCom, &value);
+            }
+        }
+    }
+}
+
 #[derive(Debug, NomBE)]
 pub struct AttributeHeader {
     pub flags: u8,
@@ -61,6 +320,10 @@ impl AttributeHeader {
     pub fn is_extended(&self) -> bool {
         (self.flags & BGP_ATTR_FLAG_EXTENDED_LENGTH) != 0
     }
+
+    pub fn is_optional(&self) -> bool {
+        (self.flags & BGP_ATTR_FLAG_OPTIONAL) != 0
+    }
 }
 
 #[derive(Clone, Debug, NomBE)]
@@ -73,6 +336,42 @@ pub struct NextHopAttr {
     pub next_hop: [u8; 4],
 }
 
+#[derive(Clone, Debug, NomBE)]
+pub struct OriginatorIdAttr {
+    pub id: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ClusterListAttr(pub Vec<u32>);
+
+impl ClusterListAttr {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        if input.len() % 4 != 0 {
+            return Err(nom::Err::Error(make_error(input, ErrorKind::LengthValue)));
+        }
+        let ids = input
+            .chunks(4)
+            .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+            .collect();
+        Ok((&input[input.len()..], ClusterListAttr(ids)))
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.0.contains(&id)
+    }
+}
+
+impl fmt::Display for ClusterListAttr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids: Vec<String> = self
+            .0
+            .iter()
+            .map(|x| Ipv4Addr::from(*x).to_string())
+            .collect();
+        write!(f, "{}", ids.join(" "))
+    }
+}
+
 #[derive(Clone, Debug, NomBE)]
 pub struct MedAttr {
     pub med: u32,
@@ -114,5 +413,23 @@ pub struct MpNlriUnreachHeader {
 #[derive(Clone, Debug)]
 pub struct MpNlriAttr {
     pub next_hop: Option<Ipv6Addr>,
+    pub link_local: Option<Ipv6Addr>,
     pub prefix: Vec<Ipv6Net>,
+    // Path identifier of each prefix when ADD-PATH is negotiated, empty
+    // otherwise (RFC 7911 3).
+    pub path_id: Vec<u32>,
+    // Unicast or labeled unicast, which NLRI has the label stack of each
+    // prefix (RFC 8277). Labels are not encoded in MP_UNREACH_NLRI.
+    pub safi: Safi,
+    pub label: Vec<Vec<u32>>,
+}
+
+// Next hop and NLRI field kept as received. Next hop is empty in
+// MP_UNREACH_NLRI.
+#[derive(Clone, Debug)]
+pub struct MpRawAttr {
+    pub afi: Afi,
+    pub safi: Safi,
+    pub next_hop: Vec<u8>,
+    pub nlri: Vec<u8>,
 }
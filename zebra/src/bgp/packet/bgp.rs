@@ -1,5 +1,5 @@
 #![allow(dead_code)]
-use super::{NotificationPacket, OpenPacket, UpdatePacket};
+use super::{NotificationPacket, OpenPacket, RouteRefreshPacket, UpdatePacket};
 use nom_derive::*;
 
 pub const BGP_PACKET_LEN: usize = 4096;
@@ -17,7 +17,7 @@ pub enum BgpType {
     Max = 7,
 }
 
-#[derive(Debug, PartialEq, NomBE)]
+#[derive(Debug, Clone, PartialEq, NomBE)]
 pub struct BgpHeader {
     pub marker: [u8; 16],
     pub length: u16,
@@ -40,4 +40,5 @@ pub enum BgpPacket {
     Keepalive(BgpHeader),
     Notification(NotificationPacket),
     Update(UpdatePacket),
+    RouteRefresh(RouteRefreshPacket),
 }
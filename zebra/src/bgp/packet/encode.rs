@@ -1,5 +1,15 @@
-use super::{BgpHeader, NotificationPacket, OpenPacket};
+use super::{BgpHeader, NotificationPacket, OpenPacket, RouteRefreshPacket, UpdatePacket};
 use bytes::{BufMut, BytesMut};
+use ipnet::Ipv4Net;
+
+fn ipv4_prefix_encode(buf: &mut BytesMut, prefix: &Ipv4Net, path_id: Option<&u32>) {
+    if let Some(path_id) = path_id {
+        buf.put_u32(*path_id);
+    }
+    let psize = (prefix.prefix_len() as usize + 7) / 8;
+    buf.put_u8(prefix.prefix_len());
+    buf.put(&prefix.addr().octets()[..psize]);
+}
 
 impl From<BgpHeader> for BytesMut {
     fn from(header: BgpHeader) -> Self {
@@ -53,3 +63,401 @@ impl From<NotificationPacket> for BytesMut {
         buf
     }
 }
+
+impl From<RouteRefreshPacket> for BytesMut {
+    fn from(refresh: RouteRefreshPacket) -> Self {
+        let mut buf = BytesMut::new();
+        let header: BytesMut = refresh.header.into();
+        buf.put(&header[..]);
+        buf.put_u16(refresh.afi.0);
+        buf.put_u8(refresh.subtype);
+        buf.put_u8(refresh.safi.0);
+        if let Some(orf) = refresh.orf.as_ref() {
+            orf.encode(&mut buf);
+
+            const LENGTH_POS: std::ops::Range<usize> = 16..18;
+            let length: u16 = buf.len() as u16;
+            buf[LENGTH_POS].copy_from_slice(&length.to_be_bytes());
+        }
+        buf
+    }
+}
+
+impl From<UpdatePacket> for BytesMut {
+    fn from(update: UpdatePacket) -> Self {
+        let mut buf = BytesMut::new();
+        let header: BytesMut = update.header.into();
+        buf.put(&header[..]);
+
+        let withdraw_pos = buf.len();
+        buf.put_u16(0);
+        for (i, prefix) in update.ipv4_withdraw.iter().enumerate() {
+            ipv4_prefix_encode(&mut buf, prefix, update.ipv4_withdraw_id.get(i));
+        }
+        let withdraw_len = (buf.len() - withdraw_pos - 2) as u16;
+        buf[withdraw_pos..withdraw_pos + 2].copy_from_slice(&withdraw_len.to_be_bytes());
+
+        let attr_pos = buf.len();
+        buf.put_u16(0);
+        for attr in update.attrs.iter() {
+            attr.encode(&mut buf);
+        }
+        let attr_len = (buf.len() - attr_pos - 2) as u16;
+        buf[attr_pos..attr_pos + 2].copy_from_slice(&attr_len.to_be_bytes());
+
+        for (i, prefix) in update.ipv4_update.iter().enumerate() {
+            ipv4_prefix_encode(&mut buf, prefix, update.ipv4_update_id.get(i));
+        }
+
+        const LENGTH_POS: std::ops::Range<usize> = 16..18;
+        let length: u16 = buf.len() as u16;
+        buf[LENGTH_POS].copy_from_slice(&length.to_be_bytes());
+
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::*;
+    use crate::bgp::{Afi, Safi};
+    use bytes::BytesMut;
+    use ipnet::Ipv4Net;
+
+    #[test]
+    fn update_round_trip() {
+        let update = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: vec![
+                Attribute::Origin(OriginAttr { origin: 0 }),
+                Attribute::As4Path(As4PathAttr {
+                    segments: vec![As4Segment {
+                        typ: AS_SEQUENCE,
+                        asn: vec![65000, 70000],
+                    }],
+                }),
+                Attribute::NextHop(NextHopAttr {
+                    next_hop: [10, 0, 0, 1],
+                }),
+                Attribute::Med(MedAttr { med: 100 }),
+            ],
+            ipv4_update: vec!["10.1.0.0/16".parse().unwrap()],
+            ipv4_withdraw: vec!["10.2.0.0/24".parse().unwrap()],
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        };
+        let bytes: BytesMut = update.into();
+        let (remain, packet) = parse_bgp_packet(&bytes, true).unwrap();
+        assert!(remain.is_empty());
+        if let BgpPacket::Update(packet) = packet {
+            assert_eq!(packet.header.length as usize, bytes.len());
+            assert_eq!(packet.attrs.len(), 4);
+            assert!(packet.attr_errors.is_empty());
+            assert_eq!(packet.ipv4_update[0].to_string(), "10.1.0.0/16");
+            assert_eq!(packet.ipv4_withdraw[0].to_string(), "10.2.0.0/24");
+        } else {
+            panic!("not an update packet");
+        }
+    }
+
+    #[test]
+    fn malformed_message() {
+        let update = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: Vec::new(),
+            ipv4_update: Vec::new(),
+            ipv4_withdraw: vec!["10.2.0.0/24".parse().unwrap()],
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        };
+        let bytes: BytesMut = update.into();
+        assert_eq!(bgp_header_check(&bytes).unwrap(), bytes.len());
+
+        // Prefix length longer than 32 in the withdrawn routes.
+        let mut malformed = bytes.to_vec();
+        malformed[21] = 200;
+        assert!(parse_bgp_packet(&malformed, true).is_err());
+
+        // Withdrawn routes length beyond the message.
+        let mut malformed = bytes.to_vec();
+        malformed[19] = 0xff;
+        assert!(parse_bgp_packet(&malformed, true).is_err());
+
+        let mut malformed = bytes.to_vec();
+        malformed[0] = 0;
+        let error = bgp_header_check(&malformed).unwrap_err();
+        assert_eq!(error.code, NotificationCode::MessageHeaderError);
+        assert_eq!(error.sub_code, MessageError::ConnectionNotSynced as u8);
+
+        let mut malformed = bytes.to_vec();
+        malformed[18] = 9;
+        let error = bgp_header_check(&malformed).unwrap_err();
+        assert_eq!(error.sub_code, MessageError::BadMessageType as u8);
+        assert_eq!(error.data, vec![9]);
+    }
+
+    #[test]
+    fn update_malformed_origin() {
+        let update = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: vec![
+                Attribute::Origin(OriginAttr { origin: 5 }),
+                Attribute::As4Path(As4PathAttr {
+                    segments: Vec::new(),
+                }),
+                Attribute::NextHop(NextHopAttr {
+                    next_hop: [10, 0, 0, 1],
+                }),
+            ],
+            ipv4_update: vec!["10.1.0.0/16".parse().unwrap()],
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        };
+        let bytes: BytesMut = update.into();
+        let (_, packet) = parse_bgp_packet(&bytes, true).unwrap();
+        if let BgpPacket::Update(mut packet) = packet {
+            assert_eq!(
+                packet.attr_errors,
+                vec![AttrError::withdraw(
+                    AttributeType::Origin,
+                    AttrErrorKind::Malformed
+                )]
+            );
+            assert_eq!(packet.attr_action(), Some(AttrAction::TreatAsWithdraw));
+            packet.treat_as_withdraw();
+            assert!(packet.ipv4_update.is_empty());
+            assert_eq!(packet.ipv4_withdraw[0].to_string(), "10.1.0.0/16");
+            assert!(packet.attrs.is_empty());
+        } else {
+            panic!("not an update packet");
+        }
+    }
+
+    #[test]
+    fn update_attr_flags() {
+        let update = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: vec![Attribute::Origin(OriginAttr { origin: 0 })],
+            ipv4_update: Vec::new(),
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        };
+        let mut bytes: BytesMut = update.into();
+        // ORIGIN is well-known, so the Optional bit is in conflict.
+        bytes[BGP_HEADER_LEN as usize + 4] |= BGP_ATTR_FLAG_OPTIONAL;
+        let (_, packet) = parse_bgp_packet(&bytes, true).unwrap();
+        if let BgpPacket::Update(packet) = packet {
+            assert_eq!(
+                packet.attr_errors,
+                vec![AttrError::withdraw(
+                    AttributeType::Origin,
+                    AttrErrorKind::Flags
+                )]
+            );
+        } else {
+            panic!("not an update packet");
+        }
+    }
+
+    #[test]
+    fn mp_reach_round_trip() {
+        let update = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: vec![
+                Attribute::Origin(OriginAttr { origin: 0 }),
+                Attribute::MpReachNlri(MpNlriAttr {
+                    next_hop: Some("2001:db8::1".parse().unwrap()),
+                    link_local: Some("fe80::1".parse().unwrap()),
+                    prefix: vec!["2001:db8:1::/48".parse().unwrap()],
+                    path_id: Vec::new(),
+                    safi: Safi::Unicast,
+                    label: Vec::new(),
+                }),
+            ],
+            ipv4_update: Vec::new(),
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        };
+        let bytes: BytesMut = update.into();
+        let (_, packet) = parse_bgp_packet(&bytes, true).unwrap();
+        if let BgpPacket::Update(packet) = packet {
+            let mp = packet.mp_reach().unwrap();
+            assert_eq!(mp.next_hop.unwrap().to_string(), "2001:db8::1");
+            assert_eq!(mp.link_local.unwrap().to_string(), "fe80::1");
+            assert_eq!(mp.prefix[0].to_string(), "2001:db8:1::/48");
+            assert!(packet.mp_attrs().iter().all(|attr| match attr {
+                Attribute::MpReachNlri(mp) => mp.prefix.is_empty(),
+                _ => true,
+            }));
+        } else {
+            panic!("not an update packet");
+        }
+    }
+
+    #[test]
+    fn route_refresh_round_trip() {
+        let refresh = RouteRefreshPacket::new(Afi::IP6, Safi::Unicast);
+        let bytes: BytesMut = refresh.into();
+        assert_eq!(bytes.len(), ROUTE_REFRESH_LEN as usize);
+        let (remain, packet) = parse_bgp_packet(&bytes, true).unwrap();
+        assert!(remain.is_empty());
+        if let BgpPacket::RouteRefresh(packet) = packet {
+            assert_eq!(packet.afi, Afi::IP6);
+            assert_eq!(packet.safi, Safi::Unicast);
+        } else {
+            panic!("not a route refresh packet");
+        }
+    }
+
+    #[test]
+    fn route_refresh_demarcation_round_trip() {
+        let mut refresh = RouteRefreshPacket::new(Afi::IP, Safi::Unicast);
+        refresh.subtype = REFRESH_EORR;
+        let bytes: BytesMut = refresh.into();
+        assert_eq!(bytes[BGP_HEADER_LEN as usize + 2], REFRESH_EORR);
+        let (_, packet) = parse_bgp_packet(&bytes, true).unwrap();
+        if let BgpPacket::RouteRefresh(packet) = packet {
+            assert_eq!(packet.subtype, REFRESH_EORR);
+            assert!(packet.orf.is_none());
+        } else {
+            panic!("not a route refresh packet");
+        }
+    }
+
+    #[test]
+    fn route_refresh_orf_round_trip() {
+        let mut refresh = RouteRefreshPacket::new(Afi::IP, Safi::Unicast);
+        let orf = RouteRefreshOrf {
+            when: ORF_IMMEDIATE,
+            remove_all: true,
+            entries: vec![
+                PrefixOrf {
+                    remove: false,
+                    deny: false,
+                    seq: 5,
+                    min: 16,
+                    max: 24,
+                    prefix: "10.0.0.0/8".parse().unwrap(),
+                },
+                PrefixOrf {
+                    remove: true,
+                    deny: true,
+                    seq: 10,
+                    min: 0,
+                    max: 0,
+                    prefix: "192.0.2.0/24".parse().unwrap(),
+                },
+            ],
+        };
+        refresh.orf = Some(orf.clone());
+        let bytes: BytesMut = refresh.into();
+        // When-to-refresh, type and length, REMOVE-ALL and two entries.
+        assert_eq!(bytes.len(), ROUTE_REFRESH_LEN as usize + 4 + 1 + 9 + 11);
+        let (remain, packet) = parse_bgp_packet(&bytes, true).unwrap();
+        assert!(remain.is_empty());
+        if let BgpPacket::RouteRefresh(packet) = packet {
+            assert_eq!(packet.orf, Some(orf));
+        } else {
+            panic!("not a route refresh packet");
+        }
+    }
+
+    #[test]
+    fn graceful_restart_round_trip() {
+        let values = vec![RestartValue {
+            afi: Afi::IP,
+            safi: Safi::Unicast,
+            flags: GR_FORWARDING_STATE,
+        }];
+        let cap = CapabilityGracefulRestart::new(120, true, values);
+        let header = BgpHeader::new(BgpType::Open, BGP_HEADER_LEN + 10);
+        let router_id = "10.0.0.1".parse().unwrap();
+        let open = OpenPacket::new(
+            header,
+            65000,
+            90,
+            &router_id,
+            vec![CapabilityPacket::GracefulRestart(cap.clone())],
+        );
+        let bytes: BytesMut = open.into();
+        let (remain, packet) = parse_bgp_packet(&bytes, true).unwrap();
+        assert!(remain.is_empty());
+        if let BgpPacket::Open(packet) = packet {
+            assert_eq!(packet.caps, vec![CapabilityPacket::GracefulRestart(cap)]);
+            if let CapabilityPacket::GracefulRestart(cap) = &packet.caps[0] {
+                assert_eq!(cap.restart_time(), 120);
+                assert!(cap.restarting());
+            }
+        } else {
+            panic!("not an open packet");
+        }
+    }
+
+    #[test]
+    fn add_path_capability_round_trip() {
+        let values = vec![AddPathValue {
+            afi: Afi::IP,
+            safi: Safi::Unicast,
+            send_receive: ADD_PATH_SEND | ADD_PATH_RECEIVE,
+        }];
+        let cap = CapabilityPacket::AddPath(CapabilityAddPath::new(values));
+        let header = BgpHeader::new(BgpType::Open, BGP_HEADER_LEN + 10);
+        let router_id = "10.0.0.1".parse().unwrap();
+        let open = OpenPacket::new(header, 65000, 90, &router_id, vec![cap.clone()]);
+        let bytes: BytesMut = open.into();
+        let (remain, packet) = parse_bgp_packet(&bytes, true).unwrap();
+        assert!(remain.is_empty());
+        if let BgpPacket::Open(packet) = packet {
+            assert_eq!(packet.caps, vec![cap]);
+        } else {
+            panic!("not an open packet");
+        }
+    }
+
+    #[test]
+    fn add_path_update_round_trip() {
+        let prefix: Ipv4Net = "10.1.0.0/16".parse().unwrap();
+        let prefix6: ipnet::Ipv6Net = "2001:db8:1::/48".parse().unwrap();
+        let update = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: vec![
+                Attribute::Origin(OriginAttr { origin: 0 }),
+                Attribute::MpReachNlri(MpNlriAttr {
+                    next_hop: Some("2001:db8::1".parse().unwrap()),
+                    link_local: None,
+                    prefix: vec![prefix6, prefix6],
+                    path_id: vec![1, 2],
+                    safi: Safi::Unicast,
+                    label: Vec::new(),
+                }),
+            ],
+            ipv4_update: vec![prefix, prefix],
+            ipv4_withdraw: vec![prefix],
+            ipv4_update_id: vec![1, 2],
+            ipv4_withdraw_id: vec![3],
+            attr_errors: Vec::new(),
+        };
+        let bytes: BytesMut = update.into();
+        let (remain, packet) =
+            parse_bgp_packet_add_path(&bytes, true, &[Afi::IP, Afi::IP6]).unwrap();
+        assert!(remain.is_empty());
+        if let BgpPacket::Update(packet) = packet {
+            assert_eq!(packet.ipv4_update, vec![prefix, prefix]);
+            assert_eq!(packet.ipv4_update_id, vec![1, 2]);
+            assert_eq!(packet.ipv4_withdraw_id, vec![3]);
+            let mp = packet.mp_reach().unwrap();
+            assert_eq!(mp.prefix, vec![prefix6, prefix6]);
+            assert_eq!(mp.path_id, vec![1, 2]);
+        } else {
+            panic!("not an update packet");
+        }
+    }
+}
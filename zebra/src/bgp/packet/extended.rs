@@ -1,9 +1,338 @@
-#[derive(Debug)]
-pub struct ExtendedCom {
-    //
+use nom::bytes::complete::take;
+use nom::IResult;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+// Type high octets of the extended community (RFC 4360, RFC 5668).
+pub const EXT_COM_TYPE_AS2: u8 = 0x00;
+pub const EXT_COM_TYPE_IPV4: u8 = 0x01;
+pub const EXT_COM_TYPE_AS4: u8 = 0x02;
+pub const EXT_COM_TYPE_NON_TRANSITIVE: u8 = 0x40;
+pub const EXT_COM_TYPE_FLOWSPEC: u8 = 0x80;
+
+// Sub-types of the AS and IPv4 address specific extended communities.
+pub const EXT_COM_SUBTYPE_RT: u8 = 0x02;
+pub const EXT_COM_SUBTYPE_SOO: u8 = 0x03;
+pub const EXT_COM_SUBTYPE_LINK_BANDWIDTH: u8 = 0x04;
+
+// Sub-types of the FlowSpec actions (RFC 8955 7).
+pub const EXT_COM_SUBTYPE_TRAFFIC_RATE: u8 = 0x06;
+pub const EXT_COM_SUBTYPE_REDIRECT: u8 = 0x08;
+
+// Global and Local Administrator of the AS and IPv4 address specific
+// extended communities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ExtComAdmin {
+    As2(u16, u32),
+    Ipv4(Ipv4Addr, u16),
+    As4(u32, u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ExtendedCom {
+    RouteTarget(ExtComAdmin),
+    SiteOfOrigin(ExtComAdmin),
+    // Link bandwidth in bytes per second as IEEE float (draft-ietf-idr-link-
+    // bandwidth), kept as the bits to be comparable.
+    LinkBandwidth(u16, u32),
+    // FlowSpec traffic-rate-bytes of the AS, in bytes per second as IEEE
+    // float. Zero rate discards the traffic.
+    TrafficRate(u16, u32),
+    // FlowSpec redirect to the VRF of the route target.
+    Redirect(ExtComAdmin),
+    // Extended community not decoded is kept as is to be propagated.
+    Other([u8; 8]),
+}
+
+impl ExtComAdmin {
+    pub fn decode(typ: u8, value: &[u8]) -> Option<Self> {
+        let u16_at = |i: usize| u16::from_be_bytes([value[i], value[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_be_bytes([value[i], value[i + 1], value[i + 2], value[i + 3]]);
+        match typ {
+            EXT_COM_TYPE_AS2 => Some(Self::As2(u16_at(0), u32_at(2))),
+            EXT_COM_TYPE_IPV4 => Some(Self::Ipv4(Ipv4Addr::from(u32_at(0)), u16_at(4))),
+            EXT_COM_TYPE_AS4 => Some(Self::As4(u32_at(0), u16_at(4))),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, sub_type: u8) -> [u8; 8] {
+        let mut buf = [0u8; 8];
+        buf[1] = sub_type;
+        match self {
+            Self::As2(asn, local) => {
+                buf[0] = EXT_COM_TYPE_AS2;
+                buf[2..4].copy_from_slice(&asn.to_be_bytes());
+                buf[4..8].copy_from_slice(&local.to_be_bytes());
+            }
+            Self::Ipv4(addr, local) => {
+                buf[0] = EXT_COM_TYPE_IPV4;
+                buf[2..6].copy_from_slice(&addr.octets());
+                buf[6..8].copy_from_slice(&local.to_be_bytes());
+            }
+            Self::As4(asn, local) => {
+                buf[0] = EXT_COM_TYPE_AS4;
+                buf[2..6].copy_from_slice(&asn.to_be_bytes());
+                buf[6..8].copy_from_slice(&local.to_be_bytes());
+            }
+        }
+        buf
+    }
+}
+
+impl ExtendedCom {
+    pub fn decode(buf: [u8; 8]) -> Self {
+        let (typ, sub_type, value) = (buf[0], buf[1], &buf[2..]);
+        if typ == EXT_COM_TYPE_NON_TRANSITIVE && sub_type == EXT_COM_SUBTYPE_LINK_BANDWIDTH {
+            let asn = u16::from_be_bytes([value[0], value[1]]);
+            let bandwidth = u32::from_be_bytes([value[2], value[3], value[4], value[5]]);
+            return Self::LinkBandwidth(asn, bandwidth);
+        }
+        if typ == EXT_COM_TYPE_FLOWSPEC && sub_type == EXT_COM_SUBTYPE_TRAFFIC_RATE {
+            let asn = u16::from_be_bytes([value[0], value[1]]);
+            let rate = u32::from_be_bytes([value[2], value[3], value[4], value[5]]);
+            return Self::TrafficRate(asn, rate);
+        }
+        // Redirect has the type of the route target with the FlowSpec bit.
+        if typ & EXT_COM_TYPE_FLOWSPEC != 0 && sub_type == EXT_COM_SUBTYPE_REDIRECT {
+            return match ExtComAdmin::decode(typ & !EXT_COM_TYPE_FLOWSPEC, value) {
+                Some(admin) => Self::Redirect(admin),
+                None => Self::Other(buf),
+            };
+        }
+        match (sub_type, ExtComAdmin::decode(typ, value)) {
+            (EXT_COM_SUBTYPE_RT, Some(admin)) => Self::RouteTarget(admin),
+            (EXT_COM_SUBTYPE_SOO, Some(admin)) => Self::SiteOfOrigin(admin),
+            _ => Self::Other(buf),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; 8] {
+        match self {
+            Self::RouteTarget(admin) => admin.encode(EXT_COM_SUBTYPE_RT),
+            Self::SiteOfOrigin(admin) => admin.encode(EXT_COM_SUBTYPE_SOO),
+            Self::LinkBandwidth(asn, bandwidth) => {
+                let mut buf = [0u8; 8];
+                buf[0] = EXT_COM_TYPE_NON_TRANSITIVE;
+                buf[1] = EXT_COM_SUBTYPE_LINK_BANDWIDTH;
+                buf[2..4].copy_from_slice(&asn.to_be_bytes());
+                buf[4..8].copy_from_slice(&bandwidth.to_be_bytes());
+                buf
+            }
+            Self::TrafficRate(asn, rate) => {
+                let mut buf = [0u8; 8];
+                buf[0] = EXT_COM_TYPE_FLOWSPEC;
+                buf[1] = EXT_COM_SUBTYPE_TRAFFIC_RATE;
+                buf[2..4].copy_from_slice(&asn.to_be_bytes());
+                buf[4..8].copy_from_slice(&rate.to_be_bytes());
+                buf
+            }
+            Self::Redirect(admin) => {
+                let mut buf = admin.encode(EXT_COM_SUBTYPE_REDIRECT);
+                buf[0] |= EXT_COM_TYPE_FLOWSPEC;
+                buf
+            }
+            Self::Other(buf) => *buf,
+        }
+    }
+
+    // Non-transitive one is removed before advertising to another AS.
+    pub fn is_transitive(&self) -> bool {
+        self.encode()[0] & EXT_COM_TYPE_NON_TRANSITIVE == 0
+    }
+
+    pub fn is_route_target(&self) -> bool {
+        matches!(self, Self::RouteTarget(_))
+    }
+
+    pub fn bandwidth(&self) -> Option<f32> {
+        match self {
+            Self::LinkBandwidth(_, bandwidth) => Some(f32::from_bits(*bandwidth)),
+            _ => None,
+        }
+    }
+
+    pub fn rate(&self) -> Option<f32> {
+        match self {
+            Self::TrafficRate(_, rate) => Some(f32::from_bits(*rate)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExtComAdmin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::As2(asn, local) => write!(f, "{}:{}", asn, local),
+            Self::Ipv4(addr, local) => write!(f, "{}:{}", addr, local),
+            Self::As4(asn, local) => write!(f, "{}:{}", asn, local),
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
-pub struct ExtendedComAttr {
-    //
+// Administrator is IPv4 address specific when it is an address, four-octet
+// AS specific when the AS does not fit in two octets.
+impl FromStr for ExtComAdmin {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (global, local) = s.rsplit_once(':').ok_or(())?;
+        if let Ok(addr) = global.parse::<Ipv4Addr>() {
+            return Ok(Self::Ipv4(addr, local.parse().map_err(|_| ())?));
+        }
+        let asn: u32 = global.parse().map_err(|_| ())?;
+        match u16::try_from(asn) {
+            Ok(asn) => Ok(Self::As2(asn, local.parse().map_err(|_| ())?)),
+            Err(_) => Ok(Self::As4(asn, local.parse().map_err(|_| ())?)),
+        }
+    }
+}
+
+impl fmt::Display for ExtendedCom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RouteTarget(admin) => write!(f, "rt {}", admin),
+            Self::SiteOfOrigin(admin) => write!(f, "soo {}", admin),
+            Self::LinkBandwidth(asn, _) => {
+                write!(f, "lb {}:{}", asn, self.bandwidth().unwrap_or(0.0) as u64)
+            }
+            Self::TrafficRate(asn, _) => {
+                write!(f, "rate {}:{}", asn, self.rate().unwrap_or(0.0) as u64)
+            }
+            Self::Redirect(admin) => write!(f, "redirect {}", admin),
+            Self::Other(buf) => {
+                write!(f, "0x")?;
+                for byte in buf.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ExtendedComAttr(pub Vec<ExtendedCom>);
+
+impl ExtendedComAttr {
+    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
+        let mut input = input;
+        let mut ecoms = Vec::new();
+        while !input.is_empty() {
+            let (remain, value) = take(8usize)(input)?;
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(value);
+            ecoms.push(ExtendedCom::decode(buf));
+            input = remain;
+        }
+        Ok((input, ExtendedComAttr(ecoms)))
+    }
+
+    pub fn contains(&self, ecom: &ExtendedCom) -> bool {
+        self.0.contains(ecom)
+    }
+
+    pub fn sort_uniq(&mut self) {
+        let ecoms: BTreeSet<ExtendedCom> = self.0.drain(..).collect();
+        self.0 = ecoms.into_iter().collect();
+    }
+
+    pub fn route_targets(&self) -> impl Iterator<Item = &ExtendedCom> {
+        self.0.iter().filter(|x| x.is_route_target())
+    }
+}
+
+impl fmt::Display for ExtendedComAttr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ecoms: Vec<String> = self.0.iter().map(|x| x.to_string()).collect();
+        write!(f, "{}", ecoms.join(" "))
+    }
+}
+
+// "rt 65000:100 soo 192.0.2.1:1 lb 65000:125000000", bandwidth of lb and
+// rate are in bytes per second.
+impl FromStr for ExtendedComAttr {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_whitespace();
+        let mut ecoms = Vec::new();
+        while let Some(kind) = tokens.next() {
+            let value = tokens.next().ok_or(())?;
+            let ecom = match kind {
+                "rt" => ExtendedCom::RouteTarget(ExtComAdmin::from_str(value)?),
+                "soo" => ExtendedCom::SiteOfOrigin(ExtComAdmin::from_str(value)?),
+                "lb" => {
+                    let (asn, bandwidth) = value.split_once(':').ok_or(())?;
+                    let bandwidth: f32 = bandwidth.parse().map_err(|_| ())?;
+                    ExtendedCom::LinkBandwidth(asn.parse().map_err(|_| ())?, bandwidth.to_bits())
+                }
+                "rate" => {
+                    let (asn, rate) = value.split_once(':').ok_or(())?;
+                    let rate: f32 = rate.parse().map_err(|_| ())?;
+                    ExtendedCom::TrafficRate(asn.parse().map_err(|_| ())?, rate.to_bits())
+                }
+                "redirect" => ExtendedCom::Redirect(ExtComAdmin::from_str(value)?),
+                _ => return Err(()),
+            };
+            ecoms.push(ecom);
+        }
+        if ecoms.is_empty() {
+            return Err(());
+        }
+        Ok(ExtendedComAttr(ecoms))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn extended_community() {
+        let attr =
+            ExtendedComAttr::from_str("rt 65000:100 soo 192.0.2.1:1 rt 4200000000:2").unwrap();
+        assert_eq!(
+            attr.0[2],
+            ExtendedCom::RouteTarget(ExtComAdmin::As4(4200000000, 2))
+        );
+        assert_eq!(
+            format!("{}", attr),
+            "rt 65000:100 soo 192.0.2.1:1 rt 4200000000:2"
+        );
+        for ecom in attr.0.iter() {
+            assert_eq!(ExtendedCom::decode(ecom.encode()), *ecom);
+            assert!(ecom.is_transitive());
+        }
+        assert_eq!(
+            ExtendedCom::RouteTarget(ExtComAdmin::As2(65000, 100)).encode(),
+            [0x00, 0x02, 0xfd, 0xe8, 0x00, 0x00, 0x00, 0x64]
+        );
+
+        let lb = ExtendedComAttr::from_str("lb 65000:125000000").unwrap();
+        assert_eq!(lb.0[0].bandwidth(), Some(125000000.0));
+        assert!(!lb.0[0].is_transitive());
+        assert_eq!(format!("{}", lb), "lb 65000:125000000");
+
+        let flowspec = ExtendedComAttr::from_str("rate 65000:0 redirect 65000:100").unwrap();
+        assert_eq!(flowspec.0[0].encode()[..2], [0x80, 0x06]);
+        assert_eq!(flowspec.0[1].encode()[..2], [0x80, 0x08]);
+        for ecom in flowspec.0.iter() {
+            assert_eq!(ExtendedCom::decode(ecom.encode()), *ecom);
+        }
+        assert_eq!(format!("{}", flowspec), "rate 65000:0 redirect 65000:100");
+        let redirect = ExtendedCom::decode([0x81, 0x08, 192, 0, 2, 1, 0, 5]);
+        assert_eq!(format!("{}", redirect), "redirect 192.0.2.1:5");
+
+        let other = ExtendedCom::decode([0x03, 0x0c, 0, 0, 0, 0, 0, 8]);
+        assert_eq!(format!("{}", other), "0x030c000000000008");
+
+        let (_, attr) = ExtendedComAttr::parse(&[0x00, 0x02, 0xfd, 0xe8, 0, 0, 0, 0x64]).unwrap();
+        assert_eq!(attr.0.len(), 1);
+        assert!(ExtendedComAttr::parse(&[0x00, 0x02, 0xfd]).is_err());
+        assert!(ExtendedComAttr::from_str("rt 65000").is_err());
+    }
 }
@@ -0,0 +1,337 @@
+use bytes::{BufMut, BytesMut};
+use ipnet::Ipv4Net;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+// Component types of the FlowSpec NLRI (RFC 8955 4.2.2).
+pub const FLOWSPEC_DEST_PREFIX: u8 = 1;
+pub const FLOWSPEC_SOURCE_PREFIX: u8 = 2;
+pub const FLOWSPEC_IP_PROTO: u8 = 3;
+pub const FLOWSPEC_PORT: u8 = 4;
+pub const FLOWSPEC_DEST_PORT: u8 = 5;
+pub const FLOWSPEC_SOURCE_PORT: u8 = 6;
+pub const FLOWSPEC_ICMP_TYPE: u8 = 7;
+pub const FLOWSPEC_ICMP_CODE: u8 = 8;
+pub const FLOWSPEC_TCP_FLAGS: u8 = 9;
+pub const FLOWSPEC_PACKET_LEN: u8 = 10;
+pub const FLOWSPEC_DSCP: u8 = 11;
+pub const FLOWSPEC_FRAGMENT: u8 = 12;
+
+// NLRI of 240 octets or longer has 2 octets length of 0xf000 | length.
+const FLOWSPEC_LEN_EXTENDED: usize = 240;
+const FLOWSPEC_LEN_MAX: usize = 4095;
+
+// Bits of the operator octet. Length of the value is 1 << len octets.
+const OP_END: u8 = 0x80;
+const OP_AND: u8 = 0x40;
+const OP_LEN: u8 = 0x30;
+
+// Numeric operator.
+pub const OP_LT: u8 = 0x04;
+pub const OP_GT: u8 = 0x02;
+pub const OP_EQ: u8 = 0x01;
+
+// Bitmask operator.
+pub const OP_NOT: u8 = 0x02;
+pub const OP_MATCH: u8 = 0x01;
+
+// Operation of the numeric and the bitmask component. Reserved bits of the
+// operator are ignored on decoding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlowOp {
+    // AND with the previous operation, otherwise OR.
+    pub and: bool,
+    pub op: u8,
+    pub value: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FlowComponent {
+    Prefix(u8, Ipv4Net),
+    Ops(u8, Vec<FlowOp>),
+}
+
+impl FlowComponent {
+    pub fn typ(&self) -> u8 {
+        match self {
+            Self::Prefix(typ, _) | Self::Ops(typ, _) => *typ,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u8(self.typ());
+        match self {
+            Self::Prefix(_, prefix) => {
+                let psize = (prefix.prefix_len() as usize + 7) / 8;
+                buf.put_u8(prefix.prefix_len());
+                buf.put(&prefix.addr().octets()[..psize]);
+            }
+            Self::Ops(_, ops) => {
+                for (i, op) in ops.iter().enumerate() {
+                    let size = flowspec_value_size(op.value);
+                    let mut octet = op.op & 0x0f;
+                    if i + 1 == ops.len() {
+                        octet |= OP_END;
+                    }
+                    if op.and {
+                        octet |= OP_AND;
+                    }
+                    octet |= (size.trailing_zeros() as u8) << 4;
+                    buf.put_u8(octet);
+                    buf.put_slice(&op.value.to_be_bytes()[8 - size..]);
+                }
+            }
+        }
+    }
+}
+
+fn flowspec_value_size(value: u64) -> usize {
+    if value <= u8::MAX as u64 {
+        1
+    } else if value <= u16::MAX as u64 {
+        2
+    } else if value <= u32::MAX as u64 {
+        4
+    } else {
+        8
+    }
+}
+
+fn flowspec_bitmask(typ: u8) -> bool {
+    typ == FLOWSPEC_TCP_FLAGS || typ == FLOWSPEC_FRAGMENT
+}
+
+// Split the NLRI field into NLRIs including the length octets. None when the
+// length exceeds the field.
+pub fn flowspec_split(mut input: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut nlris = Vec::new();
+    while let Some(first) = input.first() {
+        let (header, len) = if *first as usize >= FLOWSPEC_LEN_EXTENDED {
+            let second = *input.get(1)?;
+            (2, ((*first as usize & 0x0f) << 8) | second as usize)
+        } else {
+            (1, *first as usize)
+        };
+        let nlri = input.get(..header + len)?;
+        nlris.push(nlri);
+        input = &input[header + len..];
+    }
+    Some(nlris)
+}
+
+fn flowspec_prefix(input: &[u8]) -> Option<(Ipv4Net, &[u8])> {
+    let plen = *input.first()?;
+    let psize = (plen as usize + 7) / 8;
+    if plen > 32 {
+        return None;
+    }
+    let addr = input.get(1..1 + psize)?;
+    let mut octets = [0u8; 4];
+    octets[..psize].copy_from_slice(addr);
+    let prefix = Ipv4Net::new(Ipv4Addr::from(octets), plen).ok()?;
+    Some((prefix, &input[1 + psize..]))
+}
+
+fn flowspec_ops(mut input: &[u8]) -> Option<(Vec<FlowOp>, &[u8])> {
+    let mut ops = Vec::new();
+    loop {
+        let octet = *input.first()?;
+        let size = 1 << ((octet & OP_LEN) >> 4);
+        let value = input.get(1..1 + size)?;
+        let value = value.iter().fold(0u64, |acc, x| (acc << 8) | *x as u64);
+        ops.push(FlowOp {
+            and: octet & OP_AND != 0,
+            op: octet & 0x0f,
+            value,
+        });
+        input = &input[1 + size..];
+        if octet & OP_END != 0 {
+            return Some((ops, input));
+        }
+    }
+}
+
+// Components of the NLRI including the length octets. None when the NLRI is
+// malformed (RFC 8955 4.2): no component, unknown component type, types not
+// in strictly increasing order, or the operator list without the end bit.
+pub fn flowspec_components(nlri: &[u8]) -> Option<Vec<FlowComponent>> {
+    let first = *flowspec_split(nlri)?.first()?;
+    let mut input = first.get(1..)?;
+    if *nlri.first()? as usize >= FLOWSPEC_LEN_EXTENDED {
+        input = input.get(1..)?;
+    }
+    let mut components = Vec::new();
+    let mut last = 0;
+    while let Some(typ) = input.first() {
+        let typ = *typ;
+        if typ <= last || typ > FLOWSPEC_FRAGMENT {
+            return None;
+        }
+        last = typ;
+        let component = if typ == FLOWSPEC_DEST_PREFIX || typ == FLOWSPEC_SOURCE_PREFIX {
+            let (prefix, remain) = flowspec_prefix(&input[1..])?;
+            input = remain;
+            FlowComponent::Prefix(typ, prefix)
+        } else {
+            let (ops, remain) = flowspec_ops(&input[1..])?;
+            input = remain;
+            FlowComponent::Ops(typ, ops)
+        };
+        components.push(component);
+    }
+    if components.is_empty() {
+        return None;
+    }
+    Some(components)
+}
+
+// NLRI with the length octets. None when it is too long to be encoded.
+pub fn flowspec_encode(components: &[FlowComponent]) -> Option<Vec<u8>> {
+    let mut value = BytesMut::new();
+    for component in components.iter() {
+        component.encode(&mut value);
+    }
+    let len = value.len();
+    let mut buf = Vec::new();
+    if len > FLOWSPEC_LEN_MAX {
+        return None;
+    } else if len >= FLOWSPEC_LEN_EXTENDED {
+        buf.extend_from_slice(&(0xf000 | len as u16).to_be_bytes());
+    } else {
+        buf.push(len as u8);
+    }
+    buf.extend_from_slice(&value);
+    Some(buf)
+}
+
+fn flowspec_type_name(typ: u8) -> &'static str {
+    match typ {
+        FLOWSPEC_DEST_PREFIX => "dst",
+        FLOWSPEC_SOURCE_PREFIX => "src",
+        FLOWSPEC_IP_PROTO => "proto",
+        FLOWSPEC_PORT => "port",
+        FLOWSPEC_DEST_PORT => "dport",
+        FLOWSPEC_SOURCE_PORT => "sport",
+        FLOWSPEC_ICMP_TYPE => "icmp-type",
+        FLOWSPEC_ICMP_CODE => "icmp-code",
+        FLOWSPEC_TCP_FLAGS => "tcp-flags",
+        FLOWSPEC_PACKET_LEN => "len",
+        FLOWSPEC_DSCP => "dscp",
+        _ => "frag",
+    }
+}
+
+fn flowspec_op_str(typ: u8, op: u8) -> &'static str {
+    if flowspec_bitmask(typ) {
+        return match op & (OP_NOT | OP_MATCH) {
+            OP_NOT => "!",
+            OP_MATCH => "=",
+            0 => "",
+            _ => "!=",
+        };
+    }
+    match op & (OP_LT | OP_GT | OP_EQ) {
+        0 => "false",
+        OP_EQ => "=",
+        OP_GT => ">",
+        OP_LT => "<",
+        0x03 => ">=",
+        0x05 => "<=",
+        0x06 => "!=",
+        _ => "true",
+    }
+}
+
+// "dst 10.0.0.0/24", "dport =80|=443", "tcp-flags =0x2".
+impl fmt::Display for FlowComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", flowspec_type_name(self.typ()))?;
+        match self {
+            Self::Prefix(_, prefix) => write!(f, "{}", prefix),
+            Self::Ops(typ, ops) => {
+                for (i, op) in ops.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "{}", if op.and { "&" } else { "|" })?;
+                    }
+                    write!(f, "{}", flowspec_op_str(*typ, op.op))?;
+                    if flowspec_bitmask(*typ) {
+                        write!(f, "0x{:x}", op.value)?;
+                    } else {
+                        write!(f, "{}", op.value)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flowspec_nlri() {
+        // Destination 10.0.1.0/24, protocol TCP, destination port 80 or 443
+        // and SYN flag set.
+        let components = vec![
+            FlowComponent::Prefix(FLOWSPEC_DEST_PREFIX, "10.0.1.0/24".parse().unwrap()),
+            FlowComponent::Ops(
+                FLOWSPEC_IP_PROTO,
+                vec![FlowOp {
+                    and: false,
+                    op: OP_EQ,
+                    value: 6,
+                }],
+            ),
+            FlowComponent::Ops(
+                FLOWSPEC_DEST_PORT,
+                vec![
+                    FlowOp {
+                        and: false,
+                        op: OP_EQ,
+                        value: 80,
+                    },
+                    FlowOp {
+                        and: false,
+                        op: OP_EQ,
+                        value: 443,
+                    },
+                ],
+            ),
+            FlowComponent::Ops(
+                FLOWSPEC_TCP_FLAGS,
+                vec![FlowOp {
+                    and: false,
+                    op: OP_MATCH,
+                    value: 0x02,
+                }],
+            ),
+        ];
+        let nlri = flowspec_encode(&components).unwrap();
+        assert_eq!(
+            nlri,
+            vec![17, 1, 24, 10, 0, 1, 3, 0x81, 6, 5, 0x01, 80, 0x91, 0x01, 0xbb, 9, 0x81, 0x02]
+        );
+        assert_eq!(flowspec_components(&nlri), Some(components.clone()));
+        let rule: Vec<String> = components.iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            rule.join(" "),
+            "dst 10.0.1.0/24 proto =6 dport =80|=443 tcp-flags =0x2"
+        );
+
+        // Two NLRIs in the field.
+        let mut field = nlri.clone();
+        field.extend_from_slice(&[4, 3, 0x01, 6, 1]);
+        let nlris = flowspec_split(&field).unwrap();
+        assert_eq!(nlris.len(), 2);
+        assert!(flowspec_components(nlris[1]).is_none());
+        assert!(flowspec_split(&field[..field.len() - 1]).is_none());
+
+        // Types out of order, unknown type and operator list without end bit.
+        assert!(flowspec_components(&[6, 3, 0x81, 6, 1, 8, 10]).is_none());
+        assert!(flowspec_components(&[3, 13, 0x81, 6]).is_none());
+        assert!(flowspec_components(&[3, 3, 0x01, 6]).is_none());
+        assert!(flowspec_components(&[0]).is_none());
+    }
+}
@@ -0,0 +1,215 @@
+use bytes::{BufMut, BytesMut};
+use ipnet::{Ipv4Net, Ipv6Net};
+use nom::bytes::complete::take;
+use nom::error::{make_error, ErrorKind};
+use nom::number::complete::{be_u24, be_u32, be_u8};
+use nom::IResult;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// Reserved label values (RFC 3032 2.1).
+pub const LABEL_IMPLICIT_NULL: u32 = 3;
+
+// Range of the labels allocated for the prefixes.
+pub const LABEL_MIN: u32 = 16;
+pub const LABEL_MAX: u32 = 1048575;
+
+// Label field of the withdrawn NLRI (RFC 8277 2.4).
+pub const LABEL_WITHDRAW: u32 = 0x800000;
+
+// Bottom of Stack bit of the label field.
+pub const LABEL_BOS: u32 = 0x01;
+
+// Label field is 3 octets of 20 bits label, 3 bits TC and BoS bit.
+pub fn label_encode(buf: &mut BytesMut, label: u32) {
+    let field = (label << 4) | LABEL_BOS;
+    buf.put_slice(&field.to_be_bytes()[1..]);
+}
+
+// Bottom of Stack bit is set to the last label of the stack (RFC 8277 2.1).
+pub fn label_stack_encode(buf: &mut BytesMut, labels: &[u32]) {
+    for (i, label) in labels.iter().enumerate() {
+        let bos = if i + 1 == labels.len() { LABEL_BOS } else { 0 };
+        let field = (label << 4) | bos;
+        buf.put_slice(&field.to_be_bytes()[1..]);
+    }
+}
+
+// Labels of the encoded stack.
+pub fn label_stack_decode(input: &[u8]) -> Vec<u32> {
+    input
+        .chunks_exact(3)
+        .map(|x| ((x[0] as u32) << 12) | ((x[1] as u32) << 4) | ((x[2] as u32) >> 4))
+        .collect()
+}
+
+// Labels are read until Bottom of Stack bit, and the prefix length left for
+// the prefix is returned. Withdrawn NLRI has 0x800000 or 0x000000 as the label
+// field, which is not a label.
+pub fn parse_label_stack(input: &[u8], mut plen: u8) -> IResult<&[u8], (u8, Vec<u32>)> {
+    let mut input = input;
+    let mut labels = Vec::new();
+    loop {
+        if plen < 24 {
+            return Err(nom::Err::Error(make_error(input, ErrorKind::Verify)));
+        }
+        let (remain, field) = be_u24(input)?;
+        input = remain;
+        plen -= 24;
+        if field == LABEL_WITHDRAW || field == 0 {
+            break;
+        }
+        labels.push(field >> 4);
+        if field & LABEL_BOS != 0 {
+            break;
+        }
+    }
+    Ok((input, (plen, labels)))
+}
+
+// Prefix length covers the label stack (RFC 8277 2.2). Withdrawn NLRI has no
+// label. None when the stack doesn't fit in the prefix length of one octet,
+// and nothing is encoded.
+fn labeled_prefix_encode(buf: &mut BytesMut, plen: u8, addr: &[u8], labels: &[u32]) -> Option<()> {
+    let psize = (plen as usize + 7) / 8;
+    let len = u8::try_from(plen as usize + 24 * labels.len().max(1)).ok()?;
+    buf.put_u8(len);
+    if labels.is_empty() {
+        buf.put_slice(&LABEL_WITHDRAW.to_be_bytes()[1..]);
+    } else {
+        label_stack_encode(buf, labels);
+    }
+    buf.put(&addr[..psize]);
+    Some(())
+}
+
+pub fn labeled_ipv4_prefix_encode(
+    buf: &mut BytesMut,
+    prefix: &Ipv4Net,
+    labels: &[u32],
+) -> Option<()> {
+    labeled_prefix_encode(buf, prefix.prefix_len(), &prefix.addr().octets(), labels)
+}
+
+pub fn labeled_ipv6_prefix_encode(
+    buf: &mut BytesMut,
+    prefix: &Ipv6Net,
+    labels: &[u32],
+) -> Option<()> {
+    labeled_prefix_encode(buf, prefix.prefix_len(), &prefix.addr().octets(), labels)
+}
+
+fn parse_labeled_prefix(input: &[u8], max: usize) -> IResult<&[u8], (u8, [u8; 16], Vec<u32>)> {
+    let (input, plen) = be_u8(input)?;
+    let (input, (plen, labels)) = parse_label_stack(input, plen)?;
+    let psize = (plen as usize + 7) / 8;
+    if psize > max {
+        return Err(nom::Err::Error(make_error(input, ErrorKind::Verify)));
+    }
+    let (input, addr) = take(psize)(input)?;
+    let mut paddr = [0u8; 16];
+    paddr[..psize].copy_from_slice(addr);
+    Ok((input, (plen, paddr, labels)))
+}
+
+pub fn parse_labeled_ipv4_prefix(input: &[u8]) -> IResult<&[u8], (Ipv4Net, Vec<u32>)> {
+    let (input, (plen, paddr, labels)) = parse_labeled_prefix(input, 4)?;
+    let addr = Ipv4Addr::new(paddr[0], paddr[1], paddr[2], paddr[3]);
+    match Ipv4Net::new(addr, plen) {
+        Ok(prefix) => Ok((input, (prefix, labels))),
+        Err(_) => Err(nom::Err::Error(make_error(input, ErrorKind::Verify))),
+    }
+}
+
+pub fn parse_labeled_ipv6_prefix(input: &[u8]) -> IResult<&[u8], (Ipv6Net, Vec<u32>)> {
+    let (input, (plen, paddr, labels)) = parse_labeled_prefix(input, 16)?;
+    match Ipv6Net::new(Ipv6Addr::from(paddr), plen) {
+        Ok(prefix) => Ok((input, (prefix, labels))),
+        Err(_) => Err(nom::Err::Error(make_error(input, ErrorKind::Verify))),
+    }
+}
+
+// Labeled IPv4 NLRI of MP_REACH_NLRI or MP_UNREACH_NLRI kept as received,
+// with the Path Identifier of each prefix. None when malformed.
+pub fn parse_labeled_ipv4_nlris(
+    mut input: &[u8],
+    add_path: bool,
+) -> Option<Vec<(Ipv4Net, u32, Vec<u32>)>> {
+    let mut nlris = Vec::new();
+    while !input.is_empty() {
+        let mut id = 0;
+        if add_path {
+            let (remain, path_id) = be_u32::<_, nom::error::Error<&[u8]>>(input).ok()?;
+            input = remain;
+            id = path_id;
+        }
+        let (remain, (prefix, labels)) = parse_labeled_ipv4_prefix(input).ok()?;
+        input = remain;
+        nlris.push((prefix, id, labels));
+    }
+    Some(nlris)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn labeled_prefix() {
+        let prefix: Ipv6Net = "2001:db8:1::/48".parse().unwrap();
+        let mut buf = BytesMut::new();
+        labeled_ipv6_prefix_encode(&mut buf, &prefix, &[1000]);
+        assert_eq!(&buf[..4], &[72, 0x00, 0x3e, 0x81]);
+        let (remain, (parsed, labels)) = parse_labeled_ipv6_prefix(&buf).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(parsed, prefix);
+        assert_eq!(labels, vec![1000]);
+
+        let mut buf = BytesMut::new();
+        labeled_ipv6_prefix_encode(&mut buf, &prefix, &[]);
+        assert_eq!(&buf[..4], &[72, 0x80, 0x00, 0x00]);
+        let (_, (parsed, labels)) = parse_labeled_ipv6_prefix(&buf).unwrap();
+        assert_eq!(parsed, prefix);
+        assert!(labels.is_empty());
+
+        // Two labels in the stack.
+        let stack = [
+            96, 0x00, 0x3e, 0x80, 0x00, 0x00, 0x31, 0x20, 0x01, 0x0d, 0xb8, 0x00, 0x01,
+        ];
+        let (remain, (parsed, labels)) = parse_labeled_ipv6_prefix(&stack).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(parsed, prefix);
+        assert_eq!(labels, vec![1000, 3]);
+        let mut buf = BytesMut::new();
+        labeled_ipv6_prefix_encode(&mut buf, &prefix, &labels);
+        assert_eq!(&buf[..], &stack[..]);
+        assert!(parse_labeled_ipv6_prefix(&[16, 0x00, 0x3e]).is_err());
+
+        // Stack of 6 labels with /128 exceeds 255 bits of the prefix length.
+        let host: Ipv6Net = "2001:db8::1/128".parse().unwrap();
+        let mut buf = BytesMut::new();
+        assert!(labeled_ipv6_prefix_encode(&mut buf, &host, &[16; 6]).is_none());
+        assert!(buf.is_empty());
+        assert!(labeled_ipv6_prefix_encode(&mut buf, &host, &[16; 5]).is_some());
+        assert_eq!(buf[0], 128 + 120);
+    }
+
+    #[test]
+    fn labeled_ipv4_nlri() {
+        let prefix: Ipv4Net = "10.1.0.0/16".parse().unwrap();
+        let mut buf = BytesMut::new();
+        buf.put_u32(7);
+        labeled_ipv4_prefix_encode(&mut buf, &prefix, &[16, 2000]);
+        assert_eq!(buf[4], 16 + 48);
+        buf.put_u32(8);
+        labeled_ipv4_prefix_encode(&mut buf, &prefix, &[]);
+        let nlris = parse_labeled_ipv4_nlris(&buf, true).unwrap();
+        assert_eq!(nlris.len(), 2);
+        assert_eq!(nlris[0], (prefix, 7, vec![16, 2000]));
+        assert_eq!(nlris[1], (prefix, 8, Vec::new()));
+        assert!(parse_labeled_ipv4_nlris(&buf[..buf.len() - 1], true).is_none());
+
+        let mut stack = BytesMut::new();
+        label_stack_encode(&mut stack, &[16, 2000]);
+        assert_eq!(label_stack_decode(&stack), vec![16, 2000]);
+    }
+}
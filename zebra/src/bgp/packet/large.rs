@@ -1,41 +1,81 @@
 use nom::IResult;
 use nom_derive::*;
+use std::collections::BTreeSet;
 use std::fmt;
+use std::str::FromStr;
 
-#[derive(Clone, Default, Debug, NomBE)]
+#[derive(Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, NomBE)]
 pub struct LargeCom {
     pub global: u32,
     pub local1: u32,
     pub local2: u32,
 }
 
+// Canonical representation is three unsigned decimals (RFC 8092 3).
 impl fmt::Display for LargeCom {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let split = |v: &u32| {
-            let hval: u32 = (v & 0xFFFF0000) >> 16;
-            let lval: u32 = v & 0x0000FFFF;
-            hval.to_string() + "." + &lval.to_string()
-        };
-        let format = |v: &u32| {
-            if *v > u16::MAX.into() {
-                split(v)
-            } else {
-                v.to_string()
-            }
+        write!(f, "{}:{}:{}", self.global, self.local1, self.local2)
+    }
+}
+
+impl FromStr for LargeCom {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut values = s.split(':').map(|x| x.parse::<u32>().map_err(|_| ()));
+        let lcom = LargeCom {
+            global: values.next().ok_or(())??,
+            local1: values.next().ok_or(())??,
+            local2: values.next().ok_or(())??,
         };
-        write!(
-            f,
-            "{}:{}:{}",
-            format(&self.global),
-            format(&self.local1),
-            format(&self.local2)
-        )
+        if values.next().is_some() {
+            return Err(());
+        }
+        Ok(lcom)
     }
 }
 
 #[derive(Clone, Default, Debug, NomBE)]
 pub struct LargeComAttr(pub Vec<LargeCom>);
 
+impl LargeComAttr {
+    pub fn contains(&self, lcom: &LargeCom) -> bool {
+        self.0.contains(lcom)
+    }
+
+    pub fn push(&mut self, lcom: LargeCom) {
+        self.0.push(lcom)
+    }
+
+    // Duplicate values should be removed (RFC 8092 5).
+    pub fn sort_uniq(&mut self) {
+        let lcoms: BTreeSet<LargeCom> = self.0.drain(..).collect();
+        self.0 = lcoms.into_iter().collect();
+    }
+}
+
+impl fmt::Display for LargeComAttr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lcoms: Vec<String> = self.0.iter().map(|x| x.to_string()).collect();
+        write!(f, "{}", lcoms.join(" "))
+    }
+}
+
+impl FromStr for LargeComAttr {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lcoms = s
+            .split_whitespace()
+            .map(LargeCom::from_str)
+            .collect::<Result<Vec<LargeCom>, ()>>()?;
+        if lcoms.is_empty() {
+            return Err(());
+        }
+        Ok(LargeComAttr(lcoms))
+    }
+}
+
 fn parse_large_com(input: &[u8]) -> IResult<&[u8], LargeCom> {
     let (input, lcom) = LargeCom::parse(input)?;
     Ok((input, lcom))
@@ -56,7 +96,17 @@ mod test {
             local1: 65537,
             local2: 65538,
         };
-        assert_eq!(format!("{}", lcom), "1.0:1.1:1.2");
+        assert_eq!(format!("{}", lcom), "65536:65537:65538");
+    }
+
+    #[test]
+    fn from_str() {
+        let lcoms = LargeComAttr::from_str("4294967295:0:1  65000:1:2").unwrap();
+        assert_eq!(format!("{}", lcoms), "4294967295:0:1 65000:1:2");
+        assert!(LargeComAttr::from_str("65000:1").is_err());
+        assert!(LargeComAttr::from_str("65000:1:2:3").is_err());
+        assert!(LargeComAttr::from_str("4294967296:1:2").is_err());
+        assert!(LargeComAttr::from_str("").is_err());
     }
 
     #[test]
@@ -4,11 +4,15 @@ pub mod bgp;
 pub mod community;
 pub mod encode;
 pub mod extended;
+pub mod flowspec;
+pub mod label;
 pub mod large;
 pub mod notification;
 pub mod open;
 pub mod parser;
+pub mod refresh;
 pub mod update;
+pub mod vpn;
 
 pub mod many;
 pub use many::many0;
@@ -18,8 +22,12 @@ pub use attr::*;
 pub use bgp::*;
 pub use community::*;
 pub use extended::*;
+pub use flowspec::*;
+pub use label::*;
 pub use large::*;
 pub use notification::*;
 pub use open::*;
 pub use parser::*;
+pub use refresh::*;
 pub use update::*;
+pub use vpn::*;
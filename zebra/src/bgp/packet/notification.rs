@@ -105,3 +105,95 @@ impl NotificationPacket {
         }
     }
 }
+
+pub fn notification_str(code: u8, sub_code: u8) -> String {
+    let sub = match (NotificationCode(code), sub_code) {
+        (NotificationCode::MessageHeaderError, 1) => "Connection Not Synchronized",
+        (NotificationCode::MessageHeaderError, 2) => "Bad Message Length",
+        (NotificationCode::MessageHeaderError, 3) => "Bad Message Type",
+        (NotificationCode::OpenMessageError, 1) => "Unsupported Version Number",
+        (NotificationCode::OpenMessageError, 2) => "Bad Peer AS",
+        (NotificationCode::OpenMessageError, 3) => "Bad BGP Identifier",
+        (NotificationCode::OpenMessageError, 4) => "Unsupported Optional Parameter",
+        (NotificationCode::OpenMessageError, 6) => "Unacceptable Hold Time",
+        (NotificationCode::OpenMessageError, 7) => "Unsupported Capability",
+        (NotificationCode::OpenMessageError, 11) => "Role Mismatch",
+        (NotificationCode::UpdateMessageError, 1) => "Malformed Attribute List",
+        (NotificationCode::UpdateMessageError, 2) => "Unrecognized Well-known Attribute",
+        (NotificationCode::UpdateMessageError, 3) => "Missing Well-known Attribute",
+        (NotificationCode::UpdateMessageError, 4) => "Attribute Flags Error",
+        (NotificationCode::UpdateMessageError, 5) => "Attribute Length Error",
+        (NotificationCode::UpdateMessageError, 6) => "Invalid ORIGIN Attribute",
+        (NotificationCode::UpdateMessageError, 8) => "Invalid NEXT_HOP Attribute",
+        (NotificationCode::UpdateMessageError, 9) => "Optional Attribute Error",
+        (NotificationCode::UpdateMessageError, 10) => "Invalid Network Field",
+        (NotificationCode::UpdateMessageError, 11) => "Malformed AS_PATH",
+        (NotificationCode::FiniteStateMachineError, 1) => "Unexpected Message in OpenSent",
+        (NotificationCode::FiniteStateMachineError, 2) => "Unexpected Message in OpenConfirm",
+        (NotificationCode::FiniteStateMachineError, 3) => "Unexpected Message in Established",
+        (NotificationCode::Cease, 1) => "Maximum Number of Prefixes Reached",
+        (NotificationCode::Cease, 2) => "Administrative Shutdown",
+        (NotificationCode::Cease, 3) => "Peer De-configured",
+        (NotificationCode::Cease, 4) => "Administrative Reset",
+        (NotificationCode::Cease, 5) => "Connection Rejected",
+        (NotificationCode::Cease, 6) => "Other Configuration Change",
+        (NotificationCode::Cease, 7) => "Connection Collision Resolution",
+        (NotificationCode::Cease, 8) => "Out of Resources",
+        (NotificationCode::Cease, 9) => "Hard Reset",
+        (NotificationCode::Cease, 10) => "BFD Down",
+        _ => "",
+    };
+    if sub.is_empty() {
+        format!("{}/{}", NotificationCode(code), sub_code)
+    } else {
+        format!("{}/{}", NotificationCode(code), sub)
+    }
+}
+
+// Shutdown Communication in Administrative Shutdown and Reset (RFC 9003).
+pub fn shutdown_message_decode(code: u8, sub_code: u8, data: &[u8]) -> Option<String> {
+    if code != NotificationCode::Cease.0
+        || (sub_code != NotificationError::AdministrativeShutdown as u8
+            && sub_code != NotificationError::AdministrativeReset as u8)
+    {
+        return None;
+    }
+    let len = *data.first()? as usize;
+    let message = data.get(1..1 + len)?;
+    String::from_utf8(message.to_vec()).ok()
+}
+
+// Shutdown Communication is truncated to 255 octets without splitting a
+// UTF-8 character.
+pub fn shutdown_message_encode(message: &str) -> Vec<u8> {
+    let mut len = message.len().min(255);
+    while !message.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut data = vec![len as u8];
+    data.extend_from_slice(&message.as_bytes()[..len]);
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shutdown_message() {
+        let code = NotificationCode::Cease.0;
+        let sub_code = NotificationError::AdministrativeShutdown as u8;
+        let data = shutdown_message_encode("maintenance");
+        assert_eq!(
+            shutdown_message_decode(code, sub_code, &data).as_deref(),
+            Some("maintenance")
+        );
+        let long = "\u{3042}".repeat(100);
+        let data = shutdown_message_encode(&long);
+        assert_eq!(data[0], 255);
+        assert_eq!(
+            shutdown_message_decode(code, sub_code, &data),
+            Some("\u{3042}".repeat(85))
+        );
+    }
+}
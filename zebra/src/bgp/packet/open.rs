@@ -1,6 +1,6 @@
 use super::BgpHeader;
 use crate::bgp::BGP_VERSION;
-use crate::bgp::{Afi, Safi};
+use crate::bgp::{Afi, AfiSafi, Safi};
 use bytes::BufMut;
 use bytes::BytesMut;
 use nom_derive::*;
@@ -28,6 +28,7 @@ newtype_enum! {
     impl display CapabilityType {
         MultiProtocol = 1,
         RouteRefresh = 2,
+        Orf = 3,
     ExtendedMessage = 6,
         GracefulRestart = 64,
         As4 = 65,
@@ -46,6 +47,7 @@ newtype_enum! {
 pub enum CapabilityPacket {
     MultiProtocol(CapabilityMultiProtocol),
     RouteRefresh(CapabilityRouteRefresh),
+    Orf(CapabilityOrf),
     ExtendedMessage(CapabilityExtendedMessage),
     As4(CapabilityAs4),
     DynamicCapability(CapabilityDynamicCapability),
@@ -75,6 +77,19 @@ impl CapabilityPacket {
                 buf.put_u8(m.typ.0);
                 buf.put_u8(m.length);
             }
+            Self::Orf(m) => {
+                m.header.encode(buf);
+                buf.put_u8(m.typ.0);
+                buf.put_u8(m.length);
+                buf.put_u16(m.afi.0);
+                buf.put_u8(0);
+                buf.put_u8(m.safi.0);
+                buf.put_u8(m.values.len() as u8);
+                for value in m.values.iter() {
+                    buf.put_u8(value.typ);
+                    buf.put_u8(value.send_receive);
+                }
+            }
             Self::ExtendedMessage(m) => {
                 m.header.encode(buf);
                 buf.put_u8(m.typ.0);
@@ -95,10 +110,22 @@ impl CapabilityPacket {
                 m.header.encode(buf);
                 buf.put_u8(m.typ.0);
                 buf.put_u8(m.length);
+                for value in m.values.iter() {
+                    buf.put_u16(value.afi.0);
+                    buf.put_u8(value.safi.0);
+                    buf.put_u8(value.send_receive);
+                }
             }
             Self::GracefulRestart(m) => {
                 m.header.encode(buf);
-                buf.put_u32(m.restart_time);
+                buf.put_u8(m.typ.0);
+                buf.put_u8(m.length);
+                buf.put_u16(m.flags_time);
+                for value in m.values.iter() {
+                    buf.put_u16(value.afi.0);
+                    buf.put_u8(value.safi.0);
+                    buf.put_u8(value.flags);
+                }
             }
             Self::EnhancedRouteRefresh(m) => {
                 m.header.encode(buf);
@@ -190,6 +217,10 @@ impl CapabilityMultiProtocol {
             safi: safi.clone(),
         }
     }
+
+    pub fn afi_safi(&self) -> AfiSafi {
+        AfiSafi::new(self.afi.clone(), self.safi.clone())
+    }
 }
 
 #[derive(Debug, PartialEq, NomBE, Clone)]
@@ -245,40 +276,122 @@ impl CapabilityDynamicCapability {
     }
 }
 
+// Send/Receive field of ADD-PATH capability (RFC 7911 4).
+pub const ADD_PATH_RECEIVE: u8 = 0x01;
+pub const ADD_PATH_SEND: u8 = 0x02;
+
+#[derive(Debug, PartialEq, NomBE, Clone)]
+pub struct AddPathValue {
+    pub afi: Afi,
+    pub safi: Safi,
+    pub send_receive: u8,
+}
+
 #[derive(Debug, PartialEq, NomBE, Clone)]
 pub struct CapabilityAddPath {
     header: CapabilityHeader,
     typ: CapabilityType,
-    length: u8,
-    afi: Afi,
-    safi: Safi,
-    send_receive: u8,
+    pub length: u8,
+    #[nom(Ignore)]
+    pub values: Vec<AddPathValue>,
 }
 
 impl CapabilityAddPath {
-    pub fn new(afi: Afi, safi: Safi, send_receive: u8) -> Self {
+    pub fn new(values: Vec<AddPathValue>) -> Self {
+        let length = (values.len() * 4) as u8;
         Self {
-            header: CapabilityHeader::new(2),
+            header: CapabilityHeader::new(length + 2),
             typ: CapabilityType::AddPath,
-            length: 4,
-            afi,
-            safi,
-            send_receive,
+            length,
+            values,
         }
     }
 }
 
+// Restart State bit of Restart Flags and Forwarding State bit of AFI flags
+// (RFC 4724 3).
+pub const GR_RESTART_STATE: u16 = 0x8000;
+pub const GR_RESTART_TIME_MASK: u16 = 0x0fff;
+pub const GR_FORWARDING_STATE: u8 = 0x80;
+
+#[derive(Debug, PartialEq, NomBE, Clone)]
+pub struct RestartValue {
+    pub afi: Afi,
+    pub safi: Safi,
+    pub flags: u8,
+}
+
 #[derive(Debug, PartialEq, NomBE, Clone)]
 pub struct CapabilityGracefulRestart {
     header: CapabilityHeader,
-    restart_time: u32,
+    typ: CapabilityType,
+    pub length: u8,
+    pub flags_time: u16,
+    #[nom(Ignore)]
+    pub values: Vec<RestartValue>,
 }
 
 impl CapabilityGracefulRestart {
-    pub fn new(restart_time: u32) -> Self {
+    pub fn new(restart_time: u16, restarting: bool, values: Vec<RestartValue>) -> Self {
+        let length = (2 + values.len() * 4) as u8;
+        let mut flags_time = restart_time & GR_RESTART_TIME_MASK;
+        if restarting {
+            flags_time |= GR_RESTART_STATE;
+        }
         Self {
-            header: CapabilityHeader::new(4),
-            restart_time,
+            header: CapabilityHeader::new(length + 2),
+            typ: CapabilityType::GracefulRestart,
+            length,
+            flags_time,
+            values,
+        }
+    }
+
+    pub fn restart_time(&self) -> u16 {
+        self.flags_time & GR_RESTART_TIME_MASK
+    }
+
+    pub fn restarting(&self) -> bool {
+        self.flags_time & GR_RESTART_STATE != 0
+    }
+}
+
+pub const ORF_RECEIVE: u8 = 0x01;
+pub const ORF_SEND: u8 = 0x02;
+
+#[derive(Debug, PartialEq, NomBE, Clone)]
+pub struct OrfValue {
+    pub typ: u8,
+    pub send_receive: u8,
+}
+
+// Outbound Route Filtering capability (RFC 5291 3). Only the first AFI/SAFI
+// of the capability is kept.
+#[derive(Debug, PartialEq, NomBE, Clone)]
+pub struct CapabilityOrf {
+    header: CapabilityHeader,
+    typ: CapabilityType,
+    pub length: u8,
+    pub afi: Afi,
+    res: u8,
+    pub safi: Safi,
+    pub count: u8,
+    #[nom(Ignore)]
+    pub values: Vec<OrfValue>,
+}
+
+impl CapabilityOrf {
+    pub fn new(afi: Afi, safi: Safi, values: Vec<OrfValue>) -> Self {
+        let length = (5 + values.len() * 2) as u8;
+        Self {
+            header: CapabilityHeader::new(length + 2),
+            typ: CapabilityType::Orf,
+            length,
+            afi,
+            res: 0,
+            safi,
+            count: values.len() as u8,
+            values,
         }
     }
 }
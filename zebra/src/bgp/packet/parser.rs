@@ -22,27 +22,45 @@ fn parse_bgp_capability_packet(input: &[u8]) -> IResult<&[u8], CapabilityPacket>
             CapabilityRouteRefresh::parse,
             CapabilityPacket::RouteRefresh,
         )(input),
+        CapabilityType::Orf => {
+            let (input, mut cap) = CapabilityOrf::parse(input)?;
+            let len = (cap.length as usize).saturating_sub(5);
+            let (input, value) = take(len)(input)?;
+            let (_, values) = count(OrfValue::parse, cap.count as usize)(value)?;
+            cap.values = values;
+            Ok((input, CapabilityPacket::Orf(cap)))
+        }
         CapabilityType::ExtendedMessage => map(
             CapabilityExtendedMessage::parse,
             CapabilityPacket::ExtendedMessage,
         )(input),
-        CapabilityType::GracefulRestart => map(
-            CapabilityGracefulRestart::parse,
-            CapabilityPacket::GracefulRestart,
-        )(input),
+        CapabilityType::GracefulRestart => {
+            let (input, mut cap) = CapabilityGracefulRestart::parse(input)?;
+            let len = (cap.length as usize).saturating_sub(size_of::<u16>());
+            let (input, value) = take(len)(input)?;
+            let (_, values) = many0(RestartValue::parse)(value)?;
+            cap.values = values;
+            Ok((input, CapabilityPacket::GracefulRestart(cap)))
+        }
         CapabilityType::As4 => map(CapabilityAs4::parse, CapabilityPacket::As4)(input),
         CapabilityType::DynamicCapability => map(
             CapabilityDynamicCapability::parse,
             CapabilityPacket::DynamicCapability,
         )(input),
-        CapabilityType::AddPath => map(CapabilityAddPath::parse, CapabilityPacket::AddPath)(input),
+        CapabilityType::AddPath => {
+            let (input, mut cap) = CapabilityAddPath::parse(input)?;
+            let (input, value) = take(cap.length)(input)?;
+            let (_, values) = many0(AddPathValue::parse)(value)?;
+            cap.values = values;
+            Ok((input, CapabilityPacket::AddPath(cap)))
+        }
         CapabilityType::EnhancedRouteRefresh => map(
             CapabilityEnhancedRouteRefresh::parse,
             CapabilityPacket::EnhancedRouteRefresh,
         )(input),
         CapabilityType::LLGR => {
             let (input, mut cap) = CapabilityLLGR::parse(input)?;
-            let (value, input) = input.split_at(cap.length as usize);
+            let (input, value) = take(cap.length)(input)?;
             let (_, values) = many0(LLGRValue::parse)(value)?;
             cap.values = values;
             Ok((input, CapabilityPacket::LLGR(cap)))
@@ -75,6 +93,74 @@ fn parse_bgp_capability_packet(input: &[u8]) -> IResult<&[u8], CapabilityPacket>
     }
 }
 
+// Common part is the action and match flags, the rest is absent for
+// REMOVE-ALL (RFC 5292 3). None for REMOVE-ALL.
+fn parse_prefix_orf(input: &[u8]) -> IResult<&[u8], Option<PrefixOrf>> {
+    let (input, flags) = be_u8(input)?;
+    let action = flags >> 6;
+    if action == ORF_REMOVE_ALL {
+        return Ok((input, None));
+    }
+    let (input, seq) = be_u32(input)?;
+    let (input, min) = be_u8(input)?;
+    let (input, max) = be_u8(input)?;
+    let (input, prefix) = parse_ipv4_prefix(input)?;
+    let entry = PrefixOrf {
+        remove: action == ORF_REMOVE,
+        deny: flags & ORF_DENY != 0,
+        seq,
+        min,
+        max,
+        prefix,
+    };
+    Ok((input, Some(entry)))
+}
+
+// ORF entries of the types other than Address Prefix ORF are skipped
+// (RFC 5291 4).
+fn parse_route_refresh_orf(input: &[u8]) -> IResult<&[u8], RouteRefreshOrf> {
+    let (mut input, when) = be_u8(input)?;
+    let mut orf = RouteRefreshOrf {
+        when,
+        remove_all: false,
+        entries: Vec::new(),
+    };
+    while !input.is_empty() {
+        let (remain, typ) = be_u8(input)?;
+        let (remain, len) = be_u16(remain)?;
+        let (remain, mut entries) = take(len)(remain)?;
+        input = remain;
+        if typ != ORF_TYPE_PREFIX {
+            continue;
+        }
+        while !entries.is_empty() {
+            let (remain, entry) = parse_prefix_orf(entries)?;
+            entries = remain;
+            match entry {
+                Some(entry) => orf.entries.push(entry),
+                None => {
+                    orf.remove_all = true;
+                    orf.entries.clear();
+                }
+            }
+        }
+    }
+    Ok((input, orf))
+}
+
+// ORF entries follow the AFI/SAFI when the message is longer than the plain
+// ROUTE-REFRESH. Address Prefix ORF is supported for IPv4 only.
+fn parse_bgp_route_refresh_packet(input: &[u8]) -> IResult<&[u8], RouteRefreshPacket> {
+    let (input, mut packet) = RouteRefreshPacket::parse(input)?;
+    let len = packet.header.length.saturating_sub(ROUTE_REFRESH_LEN);
+    let (input, orf) = take(len)(input)?;
+    if !orf.is_empty() && packet.afi == Afi::IP {
+        let (_, orf) = parse_route_refresh_orf(orf)?;
+        packet.orf = Some(orf);
+    }
+    Ok((input, packet))
+}
+
 fn parse_bgp_open_packet(input: &[u8]) -> IResult<&[u8], OpenPacket> {
     println!("Parse Open");
     let (input, mut packet) = OpenPacket::parse(input)?;
@@ -83,8 +169,20 @@ fn parse_bgp_open_packet(input: &[u8]) -> IResult<&[u8], OpenPacket> {
     Ok((input, packet))
 }
 
+// Segment of unknown type or without AS is malformed (RFC 7606 7.2).
+fn as_segment_check(
+    input: &[u8],
+    header: &AsSegmentHeader,
+) -> Result<(), nom::Err<nom::error::Error<&[u8]>>> {
+    if !(1..=4).contains(&header.typ) || header.length == 0 {
+        return Err(nom::Err::Error(make_error(input, ErrorKind::Verify)));
+    }
+    Ok(())
+}
+
 fn parse_bgp_attr_as_segment(input: &[u8]) -> IResult<&[u8], AsSegment> {
     let (input, header) = AsSegmentHeader::parse(input)?;
+    as_segment_check(input, &header)?;
     let (input, asns) = count(be_u16, header.length as usize)(input)?;
     let segment = AsSegment {
         typ: header.typ,
@@ -93,15 +191,14 @@ fn parse_bgp_attr_as_segment(input: &[u8]) -> IResult<&[u8], AsSegment> {
     Ok((input, segment))
 }
 
-fn parse_bgp_attr_as_path(input: &[u8], length: u16) -> IResult<&[u8], Attribute> {
-    let (attr, input) = input.split_at(length as usize);
-    let (_, segments) = many0(parse_bgp_attr_as_segment)(attr)?;
-    let as_path = AsPathAttr { segments };
-    Ok((input, Attribute::AsPath(as_path)))
+fn parse_bgp_attr_as_path(input: &[u8]) -> IResult<&[u8], AsPathAttr> {
+    let (input, segments) = many0(parse_bgp_attr_as_segment)(input)?;
+    Ok((input, AsPathAttr { segments }))
 }
 
 fn parse_bgp_attr_as4_segment(input: &[u8]) -> IResult<&[u8], As4Segment> {
     let (input, header) = AsSegmentHeader::parse(input)?;
+    as_segment_check(input, &header)?;
     let (input, asns) = count(be_u32, header.length as usize)(input)?;
     let segment = As4Segment {
         typ: header.typ,
@@ -110,66 +207,128 @@ fn parse_bgp_attr_as4_segment(input: &[u8]) -> IResult<&[u8], As4Segment> {
     Ok((input, segment))
 }
 
-fn parse_bgp_attr_as4_path(input: &[u8], length: u16) -> IResult<&[u8], Attribute> {
-    let (attr, input) = input.split_at(length as usize);
-    let (_, segments) = many0(parse_bgp_attr_as4_segment)(attr)?;
-    let as_path = As4PathAttr { segments };
-    Ok((input, Attribute::As4Path(as_path)))
+fn parse_bgp_attr_as4_path(input: &[u8]) -> IResult<&[u8], As4PathAttr> {
+    let (input, segments) = many0(parse_bgp_attr_as4_segment)(input)?;
+    Ok((input, As4PathAttr { segments }))
+}
+
+fn mp_safi_supported(safi: &Safi) -> bool {
+    *safi == Safi::Unicast || *safi == Safi::MplsLabel
 }
 
-fn parse_bgp_attr_community(input: &[u8], length: u16) -> IResult<&[u8], Attribute> {
-    let (attr, input) = input.split_at(length as usize);
-    let (_, community) = CommunityAttr::parse(attr)?;
-    Ok((input, Attribute::Community(community)))
+// Prefixes, Path Identifiers and label stacks of MP_REACH_NLRI and
+// MP_UNREACH_NLRI.
+type MpNlri = (Vec<Ipv6Net>, Vec<u32>, Vec<Vec<u32>>);
+
+// Labeled unicast NLRI has the label stack in front of the prefix (RFC 8277
+// 2). Labeled IPv4 unicast is kept as raw NLRI.
+fn parse_mp_nlri(input: &[u8], add_path: bool, safi: &Safi) -> IResult<&[u8], MpNlri> {
+    if *safi == Safi::MplsLabel {
+        let (input, (nlri, path_id)) = parse_nlri(input, add_path, parse_labeled_ipv6_prefix)?;
+        let (prefix, label) = nlri.into_iter().unzip();
+        Ok((input, (prefix, path_id, label)))
+    } else {
+        let (input, (prefix, path_id)) = parse_nlri(input, add_path, parse_bgp_nlri_ipv6_prefix)?;
+        Ok((input, (prefix, path_id, Vec::new())))
+    }
 }
 
-fn parse_bgp_attr_mp_reach(input: &[u8], length: u16) -> IResult<&[u8], Attribute> {
+fn parse_bgp_attr_mp_reach(input: &[u8], length: u16, add_path: bool) -> IResult<&[u8], Attribute> {
     if input.len() < size_of::<MpNlriReachHeader>() {
         return Err(nom::Err::Error(make_error(input, ErrorKind::Eof)));
     }
-    let (attr, input) = input.split_at(length as usize);
+    let (input, attr) = take(length)(input)?;
     let (attr, header) = MpNlriReachHeader::parse(attr)?;
-    if header.afi != Afi::IP6 || header.safi != Safi::Unicast {
-        return Err(nom::Err::Error(make_error(input, ErrorKind::Tag)));
+    if header.afi != Afi::IP6 || !mp_safi_supported(&header.safi) {
+        let (attr, next_hop) = take(header.nhop_len)(attr)?;
+        let (nlri, _snpa) = be_u8(attr)?;
+        let mp_raw = MpRawAttr {
+            afi: header.afi,
+            safi: header.safi,
+            next_hop: next_hop.to_vec(),
+            nlri: nlri.to_vec(),
+        };
+        return Ok((input, Attribute::MpReachRaw(mp_raw)));
     }
-    if header.nhop_len != 16 {
+    // Global address optionally followed by link-local one (RFC 2545).
+    if header.nhop_len != 16 && header.nhop_len != 32 {
         return Err(nom::Err::Error(make_error(input, ErrorKind::Tag)));
     }
     let (attr, nhop) = be_u128(attr)?;
     let nhop: Ipv6Addr = Ipv6Addr::from(nhop);
+    let (attr, link_local) = if header.nhop_len == 32 {
+        let (attr, link_local) = be_u128(attr)?;
+        (attr, Some(Ipv6Addr::from(link_local)))
+    } else {
+        (attr, None)
+    };
     let (attr, _snpa) = be_u8(attr)?;
-    let (_, updates) = many0(parse_bgp_nlri_ipv6_prefix)(attr)?;
+    let (_, (updates, path_id, label)) = parse_mp_nlri(attr, add_path, &header.safi)?;
     let mp_nlri = MpNlriAttr {
         next_hop: Some(nhop),
+        link_local,
         prefix: updates,
+        path_id,
+        safi: header.safi,
+        label,
     };
     Ok((input, Attribute::MpReachNlri(mp_nlri)))
 }
 
-fn parse_bgp_attr_mp_unreach(input: &[u8], length: u16) -> IResult<&[u8], Attribute> {
+fn parse_bgp_attr_mp_unreach(
+    input: &[u8],
+    length: u16,
+    add_path: bool,
+) -> IResult<&[u8], Attribute> {
     if input.len() < size_of::<MpNlriUnreachHeader>() {
         return Err(nom::Err::Error(make_error(input, ErrorKind::Eof)));
     }
-    let (attr, input) = input.split_at(length as usize);
+    let (input, attr) = take(length)(input)?;
     let (attr, header) = MpNlriUnreachHeader::parse(attr)?;
-    if header.afi != Afi::IP6 || header.safi != Safi::Unicast {
-        return Err(nom::Err::Error(make_error(input, ErrorKind::Tag)));
+    if header.afi != Afi::IP6 || !mp_safi_supported(&header.safi) {
+        let mp_raw = MpRawAttr {
+            afi: header.afi,
+            safi: header.safi,
+            next_hop: Vec::new(),
+            nlri: attr.to_vec(),
+        };
+        return Ok((input, Attribute::MpUnreachRaw(mp_raw)));
     }
-    let (_, withdrawal) = many0(parse_bgp_nlri_ipv6_prefix)(attr)?;
+    let (_, (withdrawal, path_id, label)) = parse_mp_nlri(attr, add_path, &header.safi)?;
     let mp_nlri = MpNlriAttr {
         next_hop: None,
+        link_local: None,
         prefix: withdrawal,
+        path_id,
+        safi: header.safi,
+        label,
     };
-    Ok((input, Attribute::MpReachNlri(mp_nlri)))
+    Ok((input, Attribute::MpUnreachNlri(mp_nlri)))
 }
 
-fn parse_bgp_attr_large_com(input: &[u8], length: u16) -> IResult<&[u8], Attribute> {
-    let (attr, input) = input.split_at(length as usize);
-    let (_, lcom) = LargeComAttr::parse(attr)?;
-    Ok((input, Attribute::LargeCom(lcom)))
+// Attribute in the path attributes of UPDATE. Malformed attribute is
+// reported with the action instead of failing the whole UPDATE (RFC 7606 2).
+enum AttrParsed {
+    Attr(AttributeType, Attribute),
+    Error(AttrError),
+    Ignore,
+}
+
+// Attribute value parsed in whole. None when the value is shorter or longer
+// than expected by the attribute length.
+fn attr_value<'a, T>(value: &'a [u8], f: impl Fn(&'a [u8]) -> IResult<&'a [u8], T>) -> Option<T> {
+    match f(value) {
+        Ok((remain, attr)) if remain.is_empty() => Some(attr),
+        _ => None,
+    }
 }
 
-fn parse_bgp_attribute(input: &[u8], as4: bool) -> IResult<&[u8], Attribute> {
+// Error which can not be handled by treat-as-withdraw resets the session.
+fn attr_session_reset(input: &[u8]) -> nom::Err<nom::error::Error<&[u8]>> {
+    nom::Err::Failure(make_error(input, ErrorKind::Verify))
+}
+
+fn parse_bgp_attribute(input: &[u8], as4: bool, add_path6: bool) -> IResult<&[u8], AttrParsed> {
     let (input, header) = AttributeHeader::parse(input)?;
     let ext_len: usize = if header.is_extended() { 2 } else { 1 };
     let (input, exts) = take(ext_len)(input)?;
@@ -178,52 +337,243 @@ fn parse_bgp_attribute(input: &[u8], as4: bool) -> IResult<&[u8], Attribute> {
     } else {
         ((exts[0] as u16) << 8) + exts[1] as u16
     };
-    match AttributeType(header.type_code) {
-        AttributeType::Origin => map(OriginAttr::parse, Attribute::Origin)(input),
+    let typ = AttributeType(header.type_code);
+    if input.len() < attr_len as usize {
+        // Attribute length overruns the path attributes (RFC 7606 4).
+        return Ok((
+            &[],
+            AttrParsed::Error(AttrError::withdraw(typ, AttrErrorKind::Malformed)),
+        ));
+    }
+    let (value, input) = input.split_at(attr_len as usize);
+    if let Some(flags) = typ.flags() {
+        // Partial bit is not checked as it may be set by the speakers on
+        // the way.
+        const MASK: u8 = BGP_ATTR_FLAG_OPTIONAL | BGP_ATTR_FLAG_TRNANSITIVE;
+        if header.flags & MASK != flags {
+            if typ == AttributeType::MpReachNlri || typ == AttributeType::MpUnreachNlri {
+                return Err(attr_session_reset(input));
+            }
+            return Ok((
+                input,
+                AttrParsed::Error(AttrError::new(typ, AttrErrorKind::Flags)),
+            ));
+        }
+    }
+    let attr = match typ {
+        AttributeType::Origin => attr_value(value, OriginAttr::parse)
+            .filter(|attr| attr.origin <= 2)
+            .map(Attribute::Origin),
         AttributeType::AsPath => {
             if as4 {
-                parse_bgp_attr_as4_path(input, attr_len)
+                attr_value(value, parse_bgp_attr_as4_path).map(Attribute::As4Path)
             } else {
-                parse_bgp_attr_as_path(input, attr_len)
+                attr_value(value, parse_bgp_attr_as_path).map(Attribute::AsPath)
             }
         }
-        AttributeType::NextHop => map(NextHopAttr::parse, Attribute::NextHop)(input),
-        AttributeType::Med => map(MedAttr::parse, Attribute::Med)(input),
-        AttributeType::LocalPref => map(LocalPrefAttr::parse, Attribute::LocalPref)(input),
+        AttributeType::NextHop => attr_value(value, NextHopAttr::parse).map(Attribute::NextHop),
+        AttributeType::Med => attr_value(value, MedAttr::parse).map(Attribute::Med),
+        AttributeType::LocalPref => {
+            attr_value(value, LocalPrefAttr::parse).map(Attribute::LocalPref)
+        }
         AttributeType::AtomicAggregate => {
-            map(AtomicAggregateAttr::parse, Attribute::AtomicAggregate)(input)
+            attr_value(value, AtomicAggregateAttr::parse).map(Attribute::AtomicAggregate)
         }
         AttributeType::Aggregator => {
             if as4 {
-                map(Aggregator4Attr::parse, Attribute::Aggregator4)(input)
+                attr_value(value, Aggregator4Attr::parse).map(Attribute::Aggregator4)
             } else {
-                map(AggregatorAttr::parse, Attribute::Aggregator)(input)
+                attr_value(value, AggregatorAttr::parse).map(Attribute::Aggregator)
             }
         }
-        AttributeType::Community => parse_bgp_attr_community(input, attr_len),
-        AttributeType::MpReachNlri => parse_bgp_attr_mp_reach(input, attr_len),
-        AttributeType::MpUnreachNlri => parse_bgp_attr_mp_unreach(input, attr_len),
-        AttributeType::LargeCom => parse_bgp_attr_large_com(input, attr_len),
-        _ => Err(nom::Err::Error(make_error(input, ErrorKind::Tag))),
+        AttributeType::Community => {
+            attr_value(value, CommunityAttr::parse).map(Attribute::Community)
+        }
+        AttributeType::OriginatorId => {
+            attr_value(value, OriginatorIdAttr::parse).map(Attribute::OriginatorId)
+        }
+        AttributeType::ClusterList => {
+            attr_value(value, ClusterListAttr::parse).map(Attribute::ClusterList)
+        }
+        // NLRI can not be located in malformed MP_REACH_NLRI and
+        // MP_UNREACH_NLRI (RFC 7606 7.11).
+        AttributeType::MpReachNlri => match parse_bgp_attr_mp_reach(value, attr_len, add_path6) {
+            Ok((_, attr)) => Some(attr),
+            Err(_) => return Err(attr_session_reset(input)),
+        },
+        AttributeType::MpUnreachNlri => {
+            match parse_bgp_attr_mp_unreach(value, attr_len, add_path6) {
+                Ok((_, attr)) => Some(attr),
+                Err(_) => return Err(attr_session_reset(input)),
+            }
+        }
+        AttributeType::As4Path => {
+            attr_value(value, parse_bgp_attr_as4_path).map(Attribute::NewAsPath)
+        }
+        AttributeType::As4Aggregator => {
+            attr_value(value, Aggregator4Attr::parse).map(Attribute::NewAggregator)
+        }
+        // Length not multiple of 8 is treat-as-withdraw (RFC 7606 7.14).
+        AttributeType::ExtendedCom => attr_value(value, ExtendedComAttr::parse)
+            .filter(|attr| !attr.0.is_empty())
+            .map(Attribute::ExtendedCom),
+        // Length not multiple of 12 is treat-as-withdraw (RFC 8092 6).
+        AttributeType::LargeCom => attr_value(value, LargeComAttr::parse)
+            .filter(|attr| !attr.0.is_empty())
+            .map(|mut attr| {
+                attr.sort_uniq();
+                Attribute::LargeCom(attr)
+            }),
+        // Unrecognized optional attribute is ignored, while unrecognized
+        // well-known one is an error (RFC 4271 6.3).
+        _ if header.is_optional() => return Ok((input, AttrParsed::Ignore)),
+        _ => return Err(attr_session_reset(input)),
+    };
+    match attr {
+        Some(attr) => Ok((input, AttrParsed::Attr(typ, attr))),
+        None => Ok((
+            input,
+            AttrParsed::Error(AttrError::new(typ, AttrErrorKind::Malformed)),
+        )),
     }
 }
 
-pub fn parse_bgp_attribute_as(as4: bool) -> impl Fn(&[u8]) -> IResult<&[u8], attr::Attribute> {
-    move |i: &[u8]| parse_bgp_attribute(i, as4)
-}
-
+// Path attributes and errors of the malformed ones. Repeated attribute is
+// discarded except for the first one, but MP_REACH_NLRI and MP_UNREACH_NLRI
+// must not be repeated (RFC 7606 3.g).
 fn parse_bgp_update_attribute(
     input: &[u8],
     length: u16,
     as4: bool,
-) -> IResult<&[u8], Vec<Attribute>> {
-    let (attr, input) = input.split_at(length as usize);
-    let (_, attrs) = many0(parse_bgp_attribute_as(as4))(attr)?;
-    Ok((input, attrs))
+    add_path6: bool,
+) -> IResult<&[u8], (Vec<Attribute>, Vec<AttrError>)> {
+    let (input, mut data) = take(length)(input)?;
+    let mut attrs = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = Vec::new();
+    while !data.is_empty() {
+        let parsed = match parse_bgp_attribute(data, as4, add_path6) {
+            Ok((remain, parsed)) => {
+                data = remain;
+                parsed
+            }
+            Err(nom::Err::Failure(err)) => return Err(nom::Err::Failure(err)),
+            Err(_) => {
+                // Attribute header is truncated (RFC 7606 4).
+                errors.push(AttrError::withdraw(
+                    AttributeType(0),
+                    AttrErrorKind::Malformed,
+                ));
+                break;
+            }
+        };
+        let typ = match &parsed {
+            AttrParsed::Attr(typ, _) => *typ,
+            AttrParsed::Error(error) => error.typ,
+            AttrParsed::Ignore => continue,
+        };
+        if seen.contains(&typ) {
+            if typ == AttributeType::MpReachNlri || typ == AttributeType::MpUnreachNlri {
+                return Err(attr_session_reset(data));
+            }
+            errors.push(AttrError {
+                typ,
+                kind: AttrErrorKind::Duplicate,
+                action: AttrAction::AttributeDiscard,
+            });
+            continue;
+        }
+        seen.push(typ);
+        match parsed {
+            AttrParsed::Attr(_, attr) => attrs.push(attr),
+            AttrParsed::Error(error) => errors.push(error),
+            AttrParsed::Ignore => {}
+        }
+    }
+    Ok((input, (attrs_as4_merge(attrs, as4), errors)))
+}
+
+// Well-known mandatory attributes missing in UPDATE with NLRI. NEXT_HOP is
+// not required when the NLRI is carried only in MP_REACH_NLRI (RFC 7606 3.d).
+fn update_missing_attrs(packet: &UpdatePacket) -> Vec<AttrError> {
+    let reach6 = packet
+        .mp_reach()
+        .map(|mp| !mp.prefix.is_empty())
+        .unwrap_or(false);
+    if packet.ipv4_update.is_empty() && !reach6 {
+        return Vec::new();
+    }
+    let mut mandatory = vec![AttributeType::Origin, AttributeType::AsPath];
+    if !packet.ipv4_update.is_empty() {
+        mandatory.push(AttributeType::NextHop);
+    }
+    mandatory
+        .into_iter()
+        .filter(|typ| !packet.attr_errors.iter().any(|error| error.typ == *typ))
+        .filter(|typ| {
+            !packet.attrs.iter().any(|attr| match attr {
+                Attribute::Origin(_) => *typ == AttributeType::Origin,
+                Attribute::AsPath(_) | Attribute::As4Path(_) => *typ == AttributeType::AsPath,
+                Attribute::NextHop(_) => *typ == AttributeType::NextHop,
+                _ => false,
+            })
+        })
+        .map(|typ| AttrError::withdraw(typ, AttrErrorKind::Missing))
+        .collect()
+}
+
+// Convert AS_PATH and AGGREGATOR into 4 octet form, merging AS4_PATH and
+// AS4_AGGREGATOR received from a 2 octet speaker (RFC 6793 4.2.3).
+fn attrs_as4_merge(attrs: Vec<Attribute>, as4: bool) -> Vec<Attribute> {
+    let mut new_aspath: Option<As4PathAttr> = None;
+    let mut new_aggregator: Option<Aggregator4Attr> = None;
+    let mut rest = Vec::new();
+    for attr in attrs.into_iter() {
+        match attr {
+            Attribute::NewAsPath(aspath) => new_aspath = Some(aspath),
+            Attribute::NewAggregator(aggregator) => new_aggregator = Some(aggregator),
+            _ => rest.push(attr),
+        }
+    }
+    if as4 {
+        // AS4_PATH and AS4_AGGREGATOR from a 4 octet speaker are discarded.
+        return rest;
+    }
+    for attr in rest.iter() {
+        if let Attribute::Aggregator(aggregator) = attr {
+            if aggregator.asn != AS_TRANS {
+                // AGGREGATOR set by a 2 octet speaker, ignore both.
+                new_aspath = None;
+                new_aggregator = None;
+            }
+        }
+    }
+    rest.into_iter()
+        .map(|attr| match attr {
+            Attribute::AsPath(aspath) => {
+                if let Some(new_aspath) = &new_aspath {
+                    Attribute::As4Path(aspath.merge_as4(new_aspath))
+                } else {
+                    Attribute::As4Path(As4PathAttr::from(&aspath))
+                }
+            }
+            Attribute::Aggregator(aggregator) => {
+                if let Some(new_aggregator) = &new_aggregator {
+                    Attribute::Aggregator4(new_aggregator.clone())
+                } else {
+                    Attribute::Aggregator4(Aggregator4Attr {
+                        asn: aggregator.asn as u32,
+                        ip: aggregator.ip,
+                    })
+                }
+            }
+            _ => attr,
+        })
+        .collect()
 }
 
 fn plen2size(plen: u8) -> usize {
-    ((plen + 7) / 8) as usize
+    (plen as usize + 7) / 8
 }
 
 pub fn parse_ipv4_prefix(input: &[u8]) -> IResult<&[u8], Ipv4Net> {
@@ -233,10 +583,15 @@ pub fn parse_ipv4_prefix(input: &[u8]) -> IResult<&[u8], Ipv4Net> {
         return Err(nom::Err::Error(make_error(input, ErrorKind::Eof)));
     }
     let mut paddr = [0u8; 4];
+    if psize > paddr.len() {
+        return Err(nom::Err::Error(make_error(input, ErrorKind::Verify)));
+    }
     paddr[..psize].copy_from_slice(&input[..psize]);
     let (input, _) = take(psize)(input)?;
-    let prefix = Ipv4Net::new(Ipv4Addr::from(paddr), plen).expect("Ipv4Net crete error");
-    Ok((input, prefix))
+    match Ipv4Net::new(Ipv4Addr::from(paddr), plen) {
+        Ok(prefix) => Ok((input, prefix)),
+        Err(_) => Err(nom::Err::Error(make_error(input, ErrorKind::Verify))),
+    }
 }
 
 fn parse_bgp_nlri_ipv6_prefix(input: &[u8]) -> IResult<&[u8], Ipv6Net> {
@@ -246,36 +601,84 @@ fn parse_bgp_nlri_ipv6_prefix(input: &[u8]) -> IResult<&[u8], Ipv6Net> {
         return Err(nom::Err::Error(make_error(input, ErrorKind::Eof)));
     }
     let mut paddr = [0u8; 16];
+    if psize > paddr.len() {
+        return Err(nom::Err::Error(make_error(input, ErrorKind::Verify)));
+    }
     paddr[..psize].copy_from_slice(&input[..psize]);
     let (input, _) = take(psize)(input)?;
-    let prefix = Ipv6Net::new(Ipv6Addr::from(paddr), plen).expect("Ipv6Net create error");
-    Ok((input, prefix))
+    match Ipv6Net::new(Ipv6Addr::from(paddr), plen) {
+        Ok(prefix) => Ok((input, prefix)),
+        Err(_) => Err(nom::Err::Error(make_error(input, ErrorKind::Verify))),
+    }
+}
+
+// NLRI optionally preceded by Path Identifier (RFC 7911 3).
+type Nlri<P> = (Vec<P>, Vec<u32>);
+
+fn parse_nlri<P>(
+    input: &[u8],
+    add_path: bool,
+    f: fn(&[u8]) -> IResult<&[u8], P>,
+) -> IResult<&[u8], Nlri<P>> {
+    if add_path {
+        let (input, nlri) = many0(|input| {
+            let (input, path_id) = be_u32(input)?;
+            let (input, prefix) = f(input)?;
+            Ok((input, (path_id, prefix)))
+        })(input)?;
+        let (path_id, prefix) = nlri.into_iter().unzip();
+        Ok((input, (prefix, path_id)))
+    } else {
+        let (input, prefix) = many0(f)(input)?;
+        Ok((input, (prefix, Vec::new())))
+    }
 }
 
-fn parse_bgp_nlri_ipv4(input: &[u8], length: u16) -> IResult<&[u8], Vec<Ipv4Net>> {
-    let (nlri, input) = input.split_at(length as usize);
-    let (_, prefix) = many0(parse_ipv4_prefix)(nlri)?;
-    Ok((input, prefix))
+fn parse_bgp_nlri_ipv4(input: &[u8], length: u16, add_path: bool) -> IResult<&[u8], Nlri<Ipv4Net>> {
+    let (input, nlri) = take(length)(input)?;
+    let (_, nlri) = parse_nlri(nlri, add_path, parse_ipv4_prefix)?;
+    Ok((input, nlri))
 }
 
-fn parse_bgp_update_packet(input: &[u8], as4: bool) -> IResult<&[u8], UpdatePacket> {
+fn parse_bgp_update_packet(
+    input: &[u8],
+    as4: bool,
+    add_path: &[Afi],
+) -> IResult<&[u8], UpdatePacket> {
+    let add_path4 = add_path.contains(&Afi::IP);
+    let add_path6 = add_path.contains(&Afi::IP6);
     let (input, mut packet) = UpdatePacket::parse(input)?;
     let (input, withdraw_len) = be_u16(input)?;
-    let (input, mut withdrawal) = parse_bgp_nlri_ipv4(input, withdraw_len)?;
-    packet.ipv4_withdraw.append(&mut withdrawal);
+    let (input, (withdrawal, withdraw_id)) = parse_bgp_nlri_ipv4(input, withdraw_len, add_path4)?;
+    packet.ipv4_withdraw = withdrawal;
+    packet.ipv4_withdraw_id = withdraw_id;
     let (input, attr_len) = be_u16(input)?;
-    let (input, mut attrs) = parse_bgp_update_attribute(input, attr_len, as4)?;
+    let (input, (mut attrs, errors)) = parse_bgp_update_attribute(input, attr_len, as4, add_path6)?;
     packet.attrs.append(&mut attrs);
-    let nlri_len = packet.header.length - BGP_HEADER_LEN - 2 - withdraw_len - 2 - attr_len;
-    let (input, mut updates) = parse_bgp_nlri_ipv4(input, nlri_len)?;
-    packet.ipv4_update.append(&mut updates);
+    packet.attr_errors = errors;
+    let nlri_len = match packet
+        .header
+        .length
+        .checked_sub(BGP_HEADER_LEN + 2 + 2)
+        .and_then(|len| len.checked_sub(withdraw_len))
+        .and_then(|len| len.checked_sub(attr_len))
+    {
+        Some(nlri_len) => nlri_len,
+        None => return Err(nom::Err::Error(make_error(input, ErrorKind::LengthValue))),
+    };
+    let (input, (updates, update_id)) = parse_bgp_nlri_ipv4(input, nlri_len, add_path4)?;
+    packet.ipv4_update = updates;
+    packet.ipv4_update_id = update_id;
+    let mut missing = update_missing_attrs(&packet);
+    packet.attr_errors.append(&mut missing);
     Ok((input, packet))
 }
 
 fn parse_bgp_notification_packet(input: &[u8]) -> IResult<&[u8], NotificationPacket> {
-    let (input, packet) = NotificationPacket::parse(input)?;
-    let len = packet.header.length - BGP_HEADER_LEN - 2;
-    let (input, _data) = take(len as usize)(input)?;
+    let (input, mut packet) = NotificationPacket::parse(input)?;
+    let len = packet.header.length.saturating_sub(BGP_HEADER_LEN + 2);
+    let (input, data) = take(len as usize)(input)?;
+    packet.data = data.to_vec();
     Ok((input, packet))
 }
 
@@ -288,16 +691,75 @@ pub fn peek_bgp_length(input: &[u8]) -> usize {
     }
 }
 
+// Minimum length of the message of the type, None when the type is not
+// recognized (RFC 4271 4 and RFC 2918 3).
+fn bgp_min_length(typ: u8) -> Option<usize> {
+    match typ {
+        1 => Some(29),
+        2 => Some(23),
+        3 => Some(21),
+        4 => Some(19),
+        5 => Some(23),
+        _ => None,
+    }
+}
+
+// Message header is checked before its length is trusted for framing the
+// stream. Length of the message is returned, otherwise Message Header Error
+// to be notified (RFC 4271 6.1). Input must have the whole header.
+pub fn bgp_header_check(input: &[u8]) -> Result<usize, NotificationPacket> {
+    let error = |sub_code: MessageError, data: Vec<u8>| {
+        NotificationPacket::new(NotificationCode::MessageHeaderError, sub_code as u8, data)
+    };
+    if input.len() < BGP_HEADER_LEN as usize {
+        return Err(error(MessageError::BadMessageLength, Vec::new()));
+    }
+    if input[..16].iter().any(|x| *x != 0xff) {
+        return Err(error(MessageError::ConnectionNotSynced, Vec::new()));
+    }
+    let length = peek_bgp_length(input);
+    if length < BGP_HEADER_LEN as usize || length > BGP_PACKET_LEN {
+        return Err(error(
+            MessageError::BadMessageLength,
+            input[16..18].to_vec(),
+        ));
+    }
+    let typ = input[18];
+    let min = match bgp_min_length(typ) {
+        Some(min) => min,
+        None => return Err(error(MessageError::BadMessageType, vec![typ])),
+    };
+    if length < min || (typ == BgpType::Keepalive as u8 && length != min) {
+        return Err(error(
+            MessageError::BadMessageLength,
+            input[16..18].to_vec(),
+        ));
+    }
+    Ok(length)
+}
+
 pub fn parse_bgp_packet(input: &[u8], as4: bool) -> IResult<&[u8], BgpPacket> {
+    parse_bgp_packet_add_path(input, as4, &[])
+}
+
+// UPDATE of the AFIs in add_path carries Path Identifier with NLRI.
+pub fn parse_bgp_packet_add_path(
+    input: &[u8],
+    as4: bool,
+    add_path: &[Afi],
+) -> IResult<&[u8], BgpPacket> {
     let (_, header) = peek(BgpHeader::parse)(input)?;
     match header.typ {
         BgpType::Open => map(parse_bgp_open_packet, BgpPacket::Open)(input),
         BgpType::Update => {
-            let (input, p) = parse_bgp_update_packet(input, as4)?;
+            let (input, p) = parse_bgp_update_packet(input, as4, add_path)?;
             Ok((input, BgpPacket::Update(p)))
         }
         BgpType::Notification => map(parse_bgp_notification_packet, BgpPacket::Notification)(input),
         BgpType::Keepalive => map(BgpHeader::parse, BgpPacket::Keepalive)(input),
+        BgpType::RouteRefresh => {
+            map(parse_bgp_route_refresh_packet, BgpPacket::RouteRefresh)(input)
+        }
         _ => Err(nom::Err::Error(make_error(input, ErrorKind::Eof))),
     }
 }
@@ -0,0 +1,95 @@
+use super::{BgpHeader, BgpType, BGP_HEADER_LEN};
+use crate::bgp::{Afi, Safi};
+use bytes::{BufMut, BytesMut};
+use ipnet::Ipv4Net;
+use nom_derive::*;
+
+// ROUTE-REFRESH message (RFC 2918). Subtype is reserved zero unless Enhanced
+// Route Refresh (RFC 7313) is in use.
+#[derive(Debug, PartialEq, NomBE)]
+pub struct RouteRefreshPacket {
+    pub header: BgpHeader,
+    pub afi: Afi,
+    pub subtype: u8,
+    pub safi: Safi,
+    #[nom(Ignore)]
+    pub orf: Option<RouteRefreshOrf>,
+}
+
+pub const ROUTE_REFRESH_LEN: u16 = BGP_HEADER_LEN + 4;
+
+// Message subtype of Enhanced Route Refresh (RFC 7313 3.2).
+pub const REFRESH_NORMAL: u8 = 0;
+pub const REFRESH_BORR: u8 = 1;
+pub const REFRESH_EORR: u8 = 2;
+
+// When-to-refresh of the ORF entries (RFC 5291 4).
+pub const ORF_IMMEDIATE: u8 = 1;
+pub const ORF_DEFER: u8 = 2;
+
+// Address Prefix ORF (RFC 5292).
+pub const ORF_TYPE_PREFIX: u8 = 64;
+
+// Action in the upper two bits of the common part of the entry, match in the
+// next bit.
+pub const ORF_ADD: u8 = 0;
+pub const ORF_REMOVE: u8 = 1;
+pub const ORF_REMOVE_ALL: u8 = 2;
+pub const ORF_DENY: u8 = 0x20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefixOrf {
+    pub remove: bool,
+    pub deny: bool,
+    pub seq: u32,
+    // Zero is not specified.
+    pub min: u8,
+    pub max: u8,
+    pub prefix: Ipv4Net,
+}
+
+// Address Prefix ORF entries carried in ROUTE-REFRESH. REMOVE-ALL is applied
+// before the entries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteRefreshOrf {
+    pub when: u8,
+    pub remove_all: bool,
+    pub entries: Vec<PrefixOrf>,
+}
+
+impl RouteRefreshOrf {
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.put_u8(self.when);
+        buf.put_u8(ORF_TYPE_PREFIX);
+        let length_pos = buf.len();
+        buf.put_u16(0);
+        if self.remove_all {
+            buf.put_u8(ORF_REMOVE_ALL << 6);
+        }
+        for entry in self.entries.iter() {
+            let action = if entry.remove { ORF_REMOVE } else { ORF_ADD };
+            let deny = if entry.deny { ORF_DENY } else { 0 };
+            buf.put_u8(action << 6 | deny);
+            buf.put_u32(entry.seq);
+            buf.put_u8(entry.min);
+            buf.put_u8(entry.max);
+            let psize = (entry.prefix.prefix_len() as usize + 7) / 8;
+            buf.put_u8(entry.prefix.prefix_len());
+            buf.put(&entry.prefix.addr().octets()[..psize]);
+        }
+        let length = (buf.len() - length_pos - 2) as u16;
+        buf[length_pos..length_pos + 2].copy_from_slice(&length.to_be_bytes());
+    }
+}
+
+impl RouteRefreshPacket {
+    pub fn new(afi: Afi, safi: Safi) -> Self {
+        Self {
+            header: BgpHeader::new(BgpType::RouteRefresh, ROUTE_REFRESH_LEN),
+            afi,
+            subtype: REFRESH_NORMAL,
+            safi,
+            orf: None,
+        }
+    }
+}
@@ -1,8 +1,82 @@
-use super::{Attribute, BgpHeader};
+use super::{Attribute, AttributeType, BgpHeader, MpNlriAttr};
+use crate::bgp::{Afi, Safi};
 use ipnet::Ipv4Net;
 use nom_derive::*;
+use std::fmt;
 
-#[derive(Debug, NomBE)]
+// Action on a malformed path attribute in order of severity. Errors which
+// require session reset fail the parse of the UPDATE (RFC 7606 2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AttrAction {
+    AttributeDiscard,
+    TreatAsWithdraw,
+}
+
+impl fmt::Display for AttrAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::AttributeDiscard => write!(f, "attribute-discard"),
+            Self::TreatAsWithdraw => write!(f, "treat-as-withdraw"),
+        }
+    }
+}
+
+// Validation failure of a path attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AttrErrorKind {
+    // Well-known mandatory attribute is missing (RFC 7606 3.d).
+    Missing,
+    // Optional or Transitive bit conflicts with the attribute (RFC 7606 3.c).
+    Flags,
+    // Length or value of the attribute is invalid.
+    Malformed,
+    // Attribute appears more than once (RFC 7606 3.g).
+    Duplicate,
+}
+
+impl fmt::Display for AttrErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "missing"),
+            Self::Flags => write!(f, "flags"),
+            Self::Malformed => write!(f, "malformed"),
+            Self::Duplicate => write!(f, "duplicate"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttrError {
+    pub typ: AttributeType,
+    pub kind: AttrErrorKind,
+    pub action: AttrAction,
+}
+
+impl AttrError {
+    // Malformed optional attributes which do not affect route selection are
+    // discarded (RFC 7606 7.6, 7.7 and RFC 6793 6), the others make the
+    // routes of the UPDATE withdrawn.
+    pub fn new(typ: AttributeType, kind: AttrErrorKind) -> Self {
+        let action = match typ {
+            AttributeType::AtomicAggregate
+            | AttributeType::Aggregator
+            | AttributeType::As4Path
+            | AttributeType::As4Aggregator => AttrAction::AttributeDiscard,
+            _ => AttrAction::TreatAsWithdraw,
+        };
+        Self { typ, kind, action }
+    }
+
+    pub fn withdraw(typ: AttributeType, kind: AttrErrorKind) -> Self {
+        Self {
+            typ,
+            kind,
+            action: AttrAction::TreatAsWithdraw,
+        }
+    }
+}
+
+#[derive(Debug, Clone, NomBE)]
 pub struct UpdatePacket {
     pub header: BgpHeader,
     #[nom(Ignore)]
@@ -11,4 +85,197 @@ pub struct UpdatePacket {
     pub ipv4_update: Vec<Ipv4Net>,
     #[nom(Ignore)]
     pub ipv4_withdraw: Vec<Ipv4Net>,
+    // Path identifiers of the prefixes above when ADD-PATH is negotiated for
+    // IPv4 unicast, empty otherwise (RFC 7911 3).
+    #[nom(Ignore)]
+    pub ipv4_update_id: Vec<u32>,
+    #[nom(Ignore)]
+    pub ipv4_withdraw_id: Vec<u32>,
+    // Malformed attributes removed from the attributes above.
+    #[nom(Ignore)]
+    pub attr_errors: Vec<AttrError>,
+}
+
+impl UpdatePacket {
+    // End-of-RIB marker is an UPDATE without any route for IPv4 unicast, and
+    // with an empty MP_UNREACH_NLRI only for other AFI/SAFIs (RFC 4724 2).
+    pub fn end_of_rib(&self) -> Option<Afi> {
+        if !self.ipv4_update.is_empty() || !self.ipv4_withdraw.is_empty() {
+            return None;
+        }
+        match self.attrs.as_slice() {
+            [] => Some(Afi::IP),
+            [Attribute::MpUnreachNlri(mp)] if mp.prefix.is_empty() => Some(Afi::IP6),
+            _ => None,
+        }
+    }
+
+    // AS_PATH including AS 0 is malformed and the routes are withdrawn, while
+    // AGGREGATOR including AS 0 is discarded (RFC 7607 2).
+    pub fn as_zero_check(&mut self) {
+        let zero = self.attrs.iter().any(|attr| match attr {
+            Attribute::As4Path(aspath) => aspath
+                .segments
+                .iter()
+                .any(|segment| segment.asn.contains(&0)),
+            Attribute::AsPath(aspath) => aspath
+                .segments
+                .iter()
+                .any(|segment| segment.asn.contains(&0)),
+            _ => false,
+        });
+        if zero {
+            self.attr_errors.push(AttrError::withdraw(
+                AttributeType::AsPath,
+                AttrErrorKind::Malformed,
+            ));
+        }
+        let mut errors = Vec::new();
+        self.attrs.retain(|attr| {
+            let typ = match attr {
+                Attribute::Aggregator(aggregator) if aggregator.asn == 0 => {
+                    AttributeType::Aggregator
+                }
+                Attribute::Aggregator4(aggregator) if aggregator.asn == 0 => {
+                    AttributeType::Aggregator
+                }
+                Attribute::NewAggregator(aggregator) if aggregator.asn == 0 => {
+                    AttributeType::As4Aggregator
+                }
+                _ => return true,
+            };
+            errors.push(AttrError::new(typ, AttrErrorKind::Malformed));
+            false
+        });
+        self.attr_errors.append(&mut errors);
+    }
+
+    pub fn mp_reach(&self) -> Option<&MpNlriAttr> {
+        self.attrs.iter().find_map(|attr| match attr {
+            Attribute::MpReachNlri(mp) => Some(mp),
+            _ => None,
+        })
+    }
+
+    pub fn mp_unreach(&self) -> Option<&MpNlriAttr> {
+        self.attrs.iter().find_map(|attr| match attr {
+            Attribute::MpUnreachNlri(mp) => Some(mp),
+            _ => None,
+        })
+    }
+
+    // Path attributes stored with IPv6 routes. NLRI is removed from
+    // MP_REACH_NLRI so that only the next hop is kept.
+    pub fn mp_attrs(&self) -> Vec<Attribute> {
+        self.attrs
+            .iter()
+            .filter_map(|attr| match attr {
+                Attribute::MpReachNlri(mp) => Some(Attribute::MpReachNlri(MpNlriAttr {
+                    next_hop: mp.next_hop,
+                    link_local: mp.link_local,
+                    prefix: Vec::new(),
+                    path_id: Vec::new(),
+                    safi: mp.safi.clone(),
+                    label: Vec::new(),
+                })),
+                Attribute::MpUnreachNlri(_) => None,
+                _ => Some(attr.clone()),
+            })
+            .collect()
+    }
+
+    // Path attributes of the i-th prefix of MP_REACH_NLRI. Label stack of
+    // labeled unicast is kept with the prefix.
+    pub fn mp_prefix_attrs(&self, attrs: &[Attribute], i: usize) -> Vec<Attribute> {
+        let label = match self.mp_reach().and_then(|mp| mp.label.get(i)) {
+            Some(label) => label.clone(),
+            None => return attrs.to_vec(),
+        };
+        attrs
+            .iter()
+            .map(|attr| match attr {
+                Attribute::MpReachNlri(mp) => Attribute::MpReachNlri(MpNlriAttr {
+                    label: vec![label.clone()],
+                    ..mp.clone()
+                }),
+                _ => attr.clone(),
+            })
+            .collect()
+    }
+
+    pub fn attr_action(&self) -> Option<AttrAction> {
+        self.attr_errors.iter().map(|error| error.action).max()
+    }
+
+    // Routes of the UPDATE are handled as withdrawn, for both of IPv4 NLRI
+    // and MP_REACH_NLRI (RFC 7606 2).
+    pub fn treat_as_withdraw(&mut self) {
+        self.ipv4_withdraw.append(&mut self.ipv4_update);
+        self.ipv4_withdraw_id.append(&mut self.ipv4_update_id);
+        let reach = self.mp_reach().filter(|mp| !mp.prefix.is_empty()).cloned();
+        let mut unreach = self.mp_unreach().cloned();
+        if let Some(mut reach) = reach {
+            let unreach = unreach.get_or_insert_with(|| MpNlriAttr {
+                next_hop: None,
+                link_local: None,
+                prefix: Vec::new(),
+                path_id: Vec::new(),
+                safi: Safi::Unicast,
+                label: Vec::new(),
+            });
+            unreach.prefix.append(&mut reach.prefix);
+            unreach.path_id.append(&mut reach.path_id);
+        }
+        self.attrs = unreach.into_iter().map(Attribute::MpUnreachNlri).collect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{
+        Aggregator4Attr, As4PathAttr, As4Segment, BgpType, OriginAttr, AS_SEQUENCE, BGP_HEADER_LEN,
+    };
+
+    fn update(asn: Vec<u32>, aggregator: u32) -> UpdatePacket {
+        UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: vec![
+                Attribute::Origin(OriginAttr { origin: 0 }),
+                Attribute::As4Path(As4PathAttr {
+                    segments: vec![As4Segment {
+                        typ: AS_SEQUENCE,
+                        asn,
+                    }],
+                }),
+                Attribute::Aggregator4(Aggregator4Attr {
+                    asn: aggregator,
+                    ip: 0x0a000001,
+                }),
+            ],
+            ipv4_update: vec!["10.1.0.0/16".parse().unwrap()],
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn as_zero() {
+        let mut packet = update(vec![65002, 0], 65002);
+        packet.as_zero_check();
+        assert_eq!(packet.attr_action(), Some(AttrAction::TreatAsWithdraw));
+        assert_eq!(packet.attrs.len(), 3);
+
+        // AGGREGATOR is discarded, the routes are kept.
+        let mut packet = update(vec![65002], 0);
+        packet.as_zero_check();
+        assert_eq!(packet.attr_action(), Some(AttrAction::AttributeDiscard));
+        assert_eq!(packet.attrs.len(), 2);
+
+        let mut packet = update(vec![65002], 65002);
+        packet.as_zero_check();
+        assert!(packet.attr_errors.is_empty());
+    }
 }
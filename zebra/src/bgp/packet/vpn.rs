@@ -0,0 +1,198 @@
+use super::{label_encode, parse_label_stack, ExtComAdmin, LABEL_WITHDRAW};
+use crate::bgp::Afi;
+use bytes::{BufMut, BytesMut};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use nom::bytes::complete::take;
+use nom::error::{make_error, ErrorKind};
+use nom::number::complete::be_u8;
+use nom::IResult;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+pub const RD_LEN: usize = 8;
+
+// Route Distinguisher (RFC 4364 4.2). Type 0, 1 and 2 have the same
+// Administrator and Assigned Number layout as the extended community.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RouteDistinguisher(pub ExtComAdmin);
+
+impl RouteDistinguisher {
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let buf = buf.get(..RD_LEN)?;
+        if buf[0] != 0 {
+            return None;
+        }
+        ExtComAdmin::decode(buf[1], &buf[2..]).map(Self)
+    }
+
+    pub fn encode(&self) -> [u8; RD_LEN] {
+        let ecom = self.0.encode(0);
+        let mut buf = [0u8; RD_LEN];
+        buf[1] = ecom[0];
+        buf[2..].copy_from_slice(&ecom[2..]);
+        buf
+    }
+}
+
+impl fmt::Display for RouteDistinguisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for RouteDistinguisher {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ExtComAdmin::from_str(s).map(Self)
+    }
+}
+
+// Labeled VPN-IPv4 and VPN-IPv6 NLRI (RFC 4364 4.3.4, RFC 4659 3.2).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VpnNlri {
+    pub label: u32,
+    pub rd: RouteDistinguisher,
+    pub prefix: IpNet,
+}
+
+// Prefix length covers the label field and the RD. Labels are read until
+// Bottom of Stack bit and the top label is used as in labeled unicast.
+pub fn parse_vpn_nlri<'a>(input: &'a [u8], afi: &Afi) -> IResult<&'a [u8], VpnNlri> {
+    let (input, plen) = be_u8(input)?;
+    let (input, (plen, labels)) = parse_label_stack(input, plen)?;
+    if plen < RD_LEN as u8 * 8 {
+        return Err(nom::Err::Error(make_error(input, ErrorKind::Verify)));
+    }
+    let (input, rd) = take(RD_LEN)(input)?;
+    let rd = match RouteDistinguisher::decode(rd) {
+        Some(rd) => rd,
+        None => return Err(nom::Err::Error(make_error(input, ErrorKind::Tag))),
+    };
+    let plen = plen - RD_LEN as u8 * 8;
+    let psize = (plen as usize + 7) / 8;
+    let max = if *afi == Afi::IP { 4 } else { 16 };
+    if psize > max {
+        return Err(nom::Err::Error(make_error(input, ErrorKind::Verify)));
+    }
+    let (input, addr) = take(psize)(input)?;
+    let mut paddr = [0u8; 16];
+    paddr[..psize].copy_from_slice(addr);
+    let prefix = if *afi == Afi::IP {
+        let addr = Ipv4Addr::new(paddr[0], paddr[1], paddr[2], paddr[3]);
+        Ipv4Net::new(addr, plen).map(IpNet::V4)
+    } else {
+        Ipv6Net::new(Ipv6Addr::from(paddr), plen).map(IpNet::V6)
+    };
+    match prefix {
+        Ok(prefix) => Ok((
+            input,
+            VpnNlri {
+                label: labels.first().copied().unwrap_or(0),
+                rd,
+                prefix,
+            },
+        )),
+        Err(_) => Err(nom::Err::Error(make_error(input, ErrorKind::Verify))),
+    }
+}
+
+// NLRI field of MP_REACH_NLRI or MP_UNREACH_NLRI. None when malformed.
+pub fn parse_vpn_nlris(mut input: &[u8], afi: &Afi) -> Option<Vec<VpnNlri>> {
+    let mut nlris = Vec::new();
+    while !input.is_empty() {
+        let (remain, nlri) = parse_vpn_nlri(input, afi).ok()?;
+        nlris.push(nlri);
+        input = remain;
+    }
+    Some(nlris)
+}
+
+// Label field of the withdrawn NLRI is 0x800000 (RFC 8277 2.4).
+pub fn vpn_nlri_encode(buf: &mut BytesMut, nlri: &VpnNlri, withdraw: bool) {
+    let plen = nlri.prefix.prefix_len();
+    let psize = (plen as usize + 7) / 8;
+    buf.put_u8(plen + 24 + RD_LEN as u8 * 8);
+    if withdraw {
+        buf.put_slice(&LABEL_WITHDRAW.to_be_bytes()[1..]);
+    } else {
+        label_encode(buf, nlri.label);
+    }
+    buf.put_slice(&nlri.rd.encode());
+    match nlri.prefix.addr() {
+        IpAddr::V4(addr) => buf.put(&addr.octets()[..psize]),
+        IpAddr::V6(addr) => buf.put(&addr.octets()[..psize]),
+    }
+}
+
+// Next hop is prefixed with RD of zero. Link-local address follows the
+// global one of VPN-IPv6.
+pub fn parse_vpn_nexthop(input: &[u8]) -> Option<IpAddr> {
+    match input.len() {
+        12 => {
+            let addr: [u8; 4] = input[RD_LEN..].try_into().ok()?;
+            Some(IpAddr::V4(Ipv4Addr::from(addr)))
+        }
+        24 | 48 => {
+            let addr: [u8; 16] = input[RD_LEN..RD_LEN + 16].try_into().ok()?;
+            Some(IpAddr::V6(Ipv6Addr::from(addr)))
+        }
+        _ => None,
+    }
+}
+
+pub fn vpn_nexthop_encode(nexthop: &IpAddr) -> Vec<u8> {
+    let mut buf = vec![0u8; RD_LEN];
+    match nexthop {
+        IpAddr::V4(addr) => buf.extend_from_slice(&addr.octets()),
+        IpAddr::V6(addr) => buf.extend_from_slice(&addr.octets()),
+    }
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vpn_nlri() {
+        let rd: RouteDistinguisher = "65000:100".parse().unwrap();
+        assert_eq!(rd.encode(), [0, 0, 0xfd, 0xe8, 0, 0, 0, 100]);
+        assert_eq!(RouteDistinguisher::decode(&rd.encode()), Some(rd));
+        let rd1: RouteDistinguisher = "192.0.2.1:5".parse().unwrap();
+        assert_eq!(rd1.encode(), [0, 1, 192, 0, 2, 1, 0, 5]);
+        assert_eq!(rd1.to_string(), "192.0.2.1:5");
+
+        let nlri = VpnNlri {
+            label: 1000,
+            rd,
+            prefix: "10.1.0.0/16".parse().unwrap(),
+        };
+        let mut buf = BytesMut::new();
+        vpn_nlri_encode(&mut buf, &nlri, false);
+        assert_eq!(buf[0], 16 + 24 + 64);
+        assert_eq!(buf.len(), 1 + 3 + 8 + 2);
+        let nlri6 = VpnNlri {
+            label: 2000,
+            rd: rd1,
+            prefix: "2001:db8:1::/48".parse().unwrap(),
+        };
+        let mut buf6 = BytesMut::new();
+        vpn_nlri_encode(&mut buf6, &nlri6, false);
+        assert_eq!(parse_vpn_nlris(&buf6, &Afi::IP6), Some(vec![nlri6]));
+
+        // Two NLRIs in the field, the second withdrawn.
+        vpn_nlri_encode(&mut buf, &nlri, true);
+        let nlris = parse_vpn_nlris(&buf, &Afi::IP).unwrap();
+        assert_eq!(nlris.len(), 2);
+        assert_eq!(nlris[0], nlri);
+        assert_eq!(nlris[1].prefix, nlri.prefix);
+        assert!(parse_vpn_nlris(&buf[..5], &Afi::IP).is_none());
+
+        let nexthop: IpAddr = "192.0.2.1".parse().unwrap();
+        let encoded = vpn_nexthop_encode(&nexthop);
+        assert_eq!(encoded.len(), 12);
+        assert_eq!(parse_vpn_nexthop(&encoded), Some(nexthop));
+    }
+}
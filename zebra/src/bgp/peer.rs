@@ -1,22 +1,60 @@
 #![allow(dead_code)]
+use super::adj_rib::{AdjRib, StaleIndex};
+use super::backoff::IdleHold;
+use super::confed::Confederation;
+use super::dynamic::{dynamic_accept, dynamic_release};
+use super::family::Families;
+use super::gtsm::gtsm_set;
 use super::handler::Message;
+use super::helper::helper_save;
+use super::ifaddr::{if_source, is_connected};
+use super::localas::LocalAs;
+use super::md5::tcp_md5_set;
+use super::mirror::{mirror_received, mirror_sent, PeerMirror};
+use super::mrt::dump::mrt_dump_state;
+use super::mrt::journal::{JournalSession, JournalWriter, MessageJournal};
+use super::nexthop::NexthopConfig;
+use super::orf::{orf_capability, orf_negotiated, orf_update, peer_send_orf};
 use super::packet::*;
-use super::route::route_from_peer;
+use super::persist::Persist;
+use super::ptree::PrefixTree;
+use super::queue::PeerQueue;
+use super::restart::{restart_eor, restart_established};
+use super::rib::Bestpath;
 use super::route::Route;
+use super::route::{
+    route_advertise, route_clean, route_coalesce_flush, route_flush, route_from_peer,
+    route_reeval_out, route_refresh, route_refresh_flush, route_refresh_stale, route_stale,
+    route_stale_flush, route_sync,
+};
+use super::rpki::Rpki;
 use super::task::*;
+use super::unnumbered::unnumbered_accept;
+use super::vpn::Vpn;
+use super::watch::OriginWatch;
+use super::webhook::{Webhook, WebhookEventType};
 use super::BGP_PORT;
-use super::{Afi, AfiSafi, AfiSafis, Bgp, Safi, BGP_HOLD_TIME};
+use super::{
+    Afi, AfiSafi, AfiSafis, Bgp, Safi, BGP_CONNECT_RETRY, BGP_HOLD_TIME, BGP_LOCAL_PREF,
+    BGP_REFRESH_INTERVAL, BGP_REFRESH_STALE_TIME, BGP_RESTART_TIME, BGP_ROUTE_ADV_EBGP,
+    BGP_ROUTE_ADV_IBGP, BGP_STALE_ROUTES_TIME, BGP_VERSION,
+};
+use crate::policy::{Policy, PrefixList};
 use bytes::BytesMut;
-use ipnet::Ipv4Net;
+use ipnet::{Ipv4Net, Ipv6Net};
 use nom::AsBytes;
-use prefix_trie::PrefixMap;
 use serde::Serialize;
+use socket2::SockRef;
 use std::cmp::min;
-use std::net::{Ipv4Addr, SocketAddr};
-use std::time::Instant;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::os::fd::{AsFd, AsRawFd};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
-use tokio::net::TcpStream;
+use tokio::net::{TcpSocket, TcpStream};
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -57,6 +95,20 @@ pub enum Event {
     NotifMsg(NotificationPacket), // 25
     KeepAliveMsg,                 // 26
     UpdateMsg(UpdatePacket),      // 27
+    RouteAdvTimerExpires,
+    CoalesceTimerExpires,
+    RouteRefreshMsg(RouteRefreshPacket),
+    // Malformed message received, with NOTIFICATION to be sent.
+    MsgError(NotificationPacket),
+    RestartTimerExpires,
+    StaleTimerExpires,
+    RefreshTimerExpires,
+    RefreshStaleTimerExpires,
+    // Soft reset requested by the operator.
+    SoftIn,
+    SoftOut,
+    // Session cleared with Administrative Shutdown and the message.
+    Shutdown(Option<String>),
 }
 
 #[derive(Debug, Default)]
@@ -74,6 +126,15 @@ pub struct PeerTimer {
     pub keepalive: Option<Timer>,
     pub min_as_origin: Option<Timer>,
     pub min_route_adv: Option<Timer>,
+    pub coalesce: Option<Timer>,
+    // Graceful restart of the peer, waiting for the session to come back and
+    // then for End-of-RIB.
+    pub restart: Option<Timer>,
+    pub stale: Option<Timer>,
+    // Re-advertisement requested with ROUTE-REFRESH within the interval, and
+    // the stale paths of Enhanced Route Refresh waiting for EoRR.
+    pub refresh: Option<Timer>,
+    pub refresh_stale: Option<Timer>,
 }
 
 #[derive(Serialize, Debug, Default, Clone, Copy)]
@@ -82,20 +143,155 @@ pub struct PeerCounter {
     pub rcvd: u64,
 }
 
+// Source of the outgoing connection, an address or the address of the
+// interface taken at each connection attempt.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateSource {
+    Address(IpAddr),
+    Interface(String),
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct PeerTransportConfig {
     pub passive: bool,
+    // Source of the outgoing connection. The kernel selects one by the
+    // route to the peer when not set.
+    pub update_source: Option<UpdateSource>,
+    // eBGP neighbor is permitted not to be directly connected, and the
+    // TTL of sent packets is the one configured or 255.
+    pub ebgp_multihop: bool,
+    pub multihop_ttl: Option<u8>,
+    // VRF device the session socket is bound to.
+    pub vrf: Option<String>,
+    // TCP MD5 signature key of the session.
+    pub password: Option<String>,
+    // Maximum hops to the neighbor checked by GTSM.
+    pub ttl_security: Option<u8>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PeerGracefulRestart {
+    pub enabled: bool,
+    pub restart_time: Option<u16>,
+    pub stale_routes_time: Option<u32>,
+}
+
+impl PeerGracefulRestart {
+    pub fn restart_time(&self) -> u16 {
+        self.restart_time.unwrap_or(BGP_RESTART_TIME)
+    }
+
+    pub fn stale_routes_time(&self) -> u32 {
+        self.stale_routes_time.unwrap_or(BGP_STALE_ROUTES_TIME)
+    }
+}
+
+// ADD-PATH of the AFI/SAFI (RFC 7911). All paths are sent when send_all is
+// set, otherwise up to send_max paths.
+#[derive(Debug, Clone)]
+pub struct PeerAddPath {
+    pub afi_safi: AfiSafi,
+    pub receive: bool,
+    pub send_max: Option<u8>,
+    pub send_all: bool,
+}
+
+impl PeerAddPath {
+    pub fn new(afi_safi: AfiSafi) -> Self {
+        Self {
+            afi_safi,
+            receive: false,
+            send_max: None,
+            send_all: false,
+        }
+    }
+
+    fn send_receive(&self) -> u8 {
+        let mut send_receive = 0;
+        if self.receive {
+            send_receive |= ADD_PATH_RECEIVE;
+        }
+        if self.send_limit().is_some() {
+            send_receive |= ADD_PATH_SEND;
+        }
+        send_receive
+    }
+
+    pub fn send_limit(&self) -> Option<usize> {
+        if self.send_all {
+            Some(usize::MAX)
+        } else {
+            self.send_max.filter(|max| *max > 0).map(|max| max as usize)
+        }
+    }
+}
+
+// Number of prefixes accepted from the neighbor.
+#[derive(Debug, Default, Clone)]
+pub struct PeerPrefixLimit {
+    pub max_prefixes: Option<u32>,
+    pub warning_threshold_pct: Option<u8>,
+    pub teardown: bool,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct PeerConfig {
+    // Peer group which settings are inherited.
+    pub peer_group: Option<String>,
     pub transport: PeerTransportConfig,
+    // AS configured with peer-as, which applies again when remote-as is
+    // removed.
+    pub peer_as: Option<u32>,
     pub afi_safi: AfiSafis,
     pub four_octet: bool,
     pub route_refresh: bool,
-    pub graceful_restart: Option<u32>,
+    pub graceful_restart: PeerGracefulRestart,
+    pub add_path: Vec<PeerAddPath>,
     pub received: Vec<CapabilityPacket>,
     pub hold_time: Option<u16>,
+    pub keepalive: Option<u16>,
+    pub connect_retry: Option<u16>,
+    pub route_adv_interval: Option<u16>,
+    // Milliseconds the best path changes are held before the advertisement,
+    // zero sends them right away.
+    pub coalesce_window: u32,
+    // Seconds between the re-advertisements requested with ROUTE-REFRESH, and
+    // seconds the stale paths are kept after BoRR without EoRR.
+    pub refresh_interval: Option<u16>,
+    pub refresh_stale_time: Option<u32>,
+    pub prefix_list_in: Option<String>,
+    pub prefix_list_out: Option<String>,
+    pub route_map_in: Option<String>,
+    // Address Prefix ORF send and receive of IPv4 unicast.
+    pub orf: u8,
+    pub large_community_list_in: Option<String>,
+    pub large_community_list_out: Option<String>,
+    pub ext_community_list_in: Option<String>,
+    pub ext_community_list_out: Option<String>,
+    // Large communities added to received and advertised routes.
+    pub large_community_in: BTreeSet<LargeCom>,
+    pub large_community_out: BTreeSet<LargeCom>,
+    pub prefix_limit: PeerPrefixLimit,
+    pub message_journal: bool,
+    // Administrative shutdown with the Shutdown Communication (RFC 9003).
+    pub shutdown: Option<String>,
+    pub route_reflector_client: bool,
+    // Cluster ID for the neighbor instead of the global one.
+    pub cluster_id: Option<Ipv4Addr>,
+    // Occurrences of the local AS permitted in the received AS path.
+    pub allowas_in: u8,
+    // Outbound rewrites of the next hop and the AS path.
+    pub next_hop_self: bool,
+    pub remove_private_as: Option<PrivateAs>,
+    pub as_override: bool,
+    pub local_as: LocalAs,
+    pub mirror: PeerMirror,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrivateAs {
+    RemoveAll,
+    ReplaceAll,
 }
 
 #[derive(Debug)]
@@ -113,21 +309,198 @@ impl PeerType {
     }
 }
 
+// Remote AS of the neighbor. External and internal accept any AS different
+// from or equal to ours, which is learned from OPEN of the peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteAs {
+    Asn(u32),
+    External,
+    Internal,
+}
+
+impl Default for RemoteAs {
+    fn default() -> Self {
+        Self::Asn(0)
+    }
+}
+
+impl fmt::Display for RemoteAs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Asn(asn) => write!(f, "{}", asn),
+            Self::External => write!(f, "external"),
+            Self::Internal => write!(f, "internal"),
+        }
+    }
+}
+
+impl RemoteAs {
+    // AS of the peer until OPEN is received, zero when it is not known.
+    pub fn peer_as(&self, local_as: u32) -> u32 {
+        match self {
+            Self::Asn(asn) => *asn,
+            Self::External => 0,
+            Self::Internal => local_as,
+        }
+    }
+
+    pub fn peer_type(&self, local_as: u32) -> PeerType {
+        match self {
+            Self::Asn(asn) if *asn == local_as => PeerType::Internal,
+            Self::Asn(_) | Self::External => PeerType::External,
+            Self::Internal => PeerType::Internal,
+        }
+    }
+}
+
 #[derive(Debug, Default, Serialize, Clone)]
 pub struct PeerParam {
     pub hold_time: u16,
     pub keepalive: u16,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetDirection {
+    Sent,
+    Received,
+    Local,
+}
+
+// Reason of session reset, NOTIFICATION sent or received.
+#[derive(Debug, Clone)]
+pub struct PeerReset {
+    pub direction: ResetDirection,
+    pub code: u8,
+    pub sub_code: u8,
+    pub data: Vec<u8>,
+}
+
+// Closed sessions older than the window are not counted for longest and
+// average session duration.
+pub const PEER_SLA_WINDOW: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+// Session availability history for peering SLA report.
+#[derive(Debug, Default)]
+pub struct PeerSla {
+    pub established: u64,
+    pub uptime: Duration,
+    pub sessions: VecDeque<(Instant, Duration)>,
+}
+
+// Validation outcomes of an attribute received in UPDATE.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct AttrErrorStat {
+    pub missing: u64,
+    pub flags: u64,
+    pub malformed: u64,
+    pub duplicate: u64,
+    pub treat_as_withdraw: u64,
+    pub attribute_discard: u64,
+}
+
+impl AttrErrorStat {
+    pub fn count(&mut self, error: &AttrError) {
+        match error.kind {
+            AttrErrorKind::Missing => self.missing += 1,
+            AttrErrorKind::Flags => self.flags += 1,
+            AttrErrorKind::Malformed => self.malformed += 1,
+            AttrErrorKind::Duplicate => self.duplicate += 1,
+        }
+        match error.action {
+            AttrAction::TreatAsWithdraw => self.treat_as_withdraw += 1,
+            AttrAction::AttributeDiscard => self.attribute_discard += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.treat_as_withdraw + self.attribute_discard
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PeerSlaStat {
+    pub established: u64,
+    pub uptime: u64,
+    pub sessions: usize,
+    pub longest: u64,
+    pub average: u64,
+}
+
+impl PeerSla {
+    pub fn up(&mut self) {
+        self.established += 1;
+    }
+
+    pub fn down(&mut self, start: Instant) {
+        let now = Instant::now();
+        let duration = now.duration_since(start);
+        self.uptime += duration;
+        self.sessions.push_back((now, duration));
+        self.expire(now);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((end, _)) = self.sessions.front() {
+            if now.duration_since(*end) <= PEER_SLA_WINDOW {
+                break;
+            }
+            self.sessions.pop_front();
+        }
+    }
+
+    // Statistics in seconds, current session is included when it is up.
+    pub fn stat(&self, current: Option<Instant>) -> PeerSlaStat {
+        self.stat_at(Instant::now(), current)
+    }
+
+    fn stat_at(&self, now: Instant, current: Option<Instant>) -> PeerSlaStat {
+        let mut durations: Vec<Duration> = self
+            .sessions
+            .iter()
+            .filter(|(end, _)| now.duration_since(*end) <= PEER_SLA_WINDOW)
+            .map(|(_, duration)| *duration)
+            .collect();
+        let mut uptime = self.uptime;
+        if let Some(start) = current {
+            let duration = now.duration_since(start);
+            uptime += duration;
+            durations.push(duration);
+        }
+        let total: Duration = durations.iter().sum();
+        PeerSlaStat {
+            established: self.established,
+            uptime: uptime.as_secs(),
+            sessions: durations.len(),
+            longest: durations.iter().max().map(|x| x.as_secs()).unwrap_or(0),
+            average: if durations.is_empty() {
+                0
+            } else {
+                total.as_secs() / durations.len() as u64
+            },
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Peer {
-    pub ident: Ipv4Addr,
-    pub address: Ipv4Addr,
+    pub ident: IpAddr,
+    pub address: IpAddr,
     pub router_id: Ipv4Addr,
+    // Global cluster ID of route reflection.
+    pub global_cluster_id: Option<Ipv4Addr>,
+    // Route reflector clients of the instance, looked up when a route learned
+    // from one of them is advertised.
+    pub rr_clients: Arc<BTreeSet<IpAddr>>,
+    pub confed: Confederation,
+    // LOCAL_PREF sent to iBGP peers when the path has none.
+    pub default_local_pref: u32,
     pub local_identifier: Option<Ipv4Addr>,
     pub remote_id: Ipv4Addr,
     pub local_as: u32,
     pub peer_as: u32,
+    // Peer AS is learned from OPEN unless remote-as is the AS number.
+    pub remote_as: RemoteAs,
     pub active: bool,
     pub peer_type: PeerType,
     pub state: State,
@@ -140,24 +513,82 @@ pub struct Peer {
     pub param_rx: PeerParam,
     pub packet_tx: Option<UnboundedSender<BytesMut>>,
     pub tx: UnboundedSender<Message>,
+    pub queue: PeerQueue,
     pub config: PeerConfig,
     pub instant: Option<Instant>,
+    pub sla: PeerSla,
+    pub reset: Option<PeerReset>,
+    pub last_reset: Option<PeerReset>,
+    pub idle_hold: IdleHold,
+    pub open_error: u64,
+    // Advertisements with unexpected origin AS of the watched prefixes.
+    pub origin_anomaly: u64,
+    // Malformed attributes received in UPDATE by the attribute type.
+    pub attr_error: BTreeMap<AttributeType, AttrErrorStat>,
+    pub local_addr: Option<IpAddr>,
+    // Interface index of the link-local address of unnumbered neighbor.
+    pub scope_id: u32,
+    pub adj_rib_in: AdjRib,
+    pub adj_rib_out: AdjRib,
+    // Routes retained while the peer restarts.
+    pub stale_rib: AdjRib,
+    // Paths waiting for EoRR of Enhanced Route Refresh.
+    pub refresh_stale: StaleIndex,
+    // Restart time of the peer when graceful restart is negotiated.
+    pub restart_time: Option<u16>,
+    // Restart State bit is advertised while we are restarting.
+    pub restarting: bool,
+    pub adv_queue: BTreeSet<Ipv4Net>,
+    pub adv_queue6: BTreeSet<Ipv6Net>,
+    // Last re-advertisement requested with ROUTE-REFRESH, and the AFI/SAFIs
+    // requested since then.
+    pub refresh_last: Option<Instant>,
+    pub refresh_pending: Vec<AfiSafi>,
+    // AFI/SAFI negotiated with the peer.
+    pub afi_safi: AfiSafis,
+    // Number of paths sent per prefix of the AFI when ADD-PATH send is
+    // negotiated.
+    pub add_path_send: Vec<(Afi, usize)>,
+    pub span: Option<tracing::Span>,
+    pub prefix_warning: bool,
+    pub prefix_exceeded: bool,
+    // Advertised routes are tagged with GRACEFUL_SHUTDOWN.
+    pub graceful_shutdown: bool,
+    // Drained by the operator, kept over the session reset.
+    pub maintenance: bool,
+    // Shutdown message of the whole BGP instance.
+    pub instance_shutdown: Option<String>,
+    // Prefix list pushed by the peer with ORF, applied to advertised routes.
+    pub orf: Option<PrefixList>,
+    pub msg_journal: Option<MessageJournal>,
+    // UPDATE messages and state changes recorded to the MRT dump.
+    pub mrt_updates: Option<JournalWriter>,
+    // Lab neighbor of the session mirroring, and UPDATE messages to be
+    // mirrored to the lab neighbor.
+    pub mirror_lab: bool,
+    pub mirror_queue: Vec<UpdatePacket>,
 }
 
 impl Peer {
     pub fn new(
-        ident: Ipv4Addr,
+        ident: IpAddr,
         local_as: u32,
         router_id: Ipv4Addr,
         peer_as: u32,
-        address: Ipv4Addr,
+        address: IpAddr,
         tx: UnboundedSender<Message>,
+        update_tx: UnboundedSender<Message>,
     ) -> Self {
         let mut peer = Self {
             ident,
             router_id,
+            global_cluster_id: None,
+            rr_clients: Arc::default(),
+            confed: Confederation::default(),
+            default_local_pref: BGP_LOCAL_PREF,
             local_as,
             peer_as,
+            remote_as: RemoteAs::Asn(peer_as),
             address,
             active: false,
             peer_type: PeerType::Internal,
@@ -167,6 +598,7 @@ impl Peer {
             counter: [PeerCounter::default(); BgpType::Max as usize],
             packet_tx: None,
             tx,
+            queue: PeerQueue::new(update_tx),
             remote_id: Ipv4Addr::UNSPECIFIED,
             local_identifier: None,
             config: PeerConfig::default(),
@@ -175,35 +607,190 @@ impl Peer {
             param_tx: PeerParam::default(),
             param_rx: PeerParam::default(),
             instant: None,
+            sla: PeerSla::default(),
+            reset: None,
+            last_reset: None,
+            idle_hold: IdleHold::default(),
+            open_error: 0,
+            origin_anomaly: 0,
+            attr_error: BTreeMap::new(),
+            local_addr: None,
+            scope_id: 0,
+            adj_rib_in: AdjRib::new(),
+            adj_rib_out: AdjRib::new(),
+            stale_rib: AdjRib::new(),
+            refresh_stale: StaleIndex::default(),
+            restart_time: None,
+            restarting: false,
+            adv_queue: BTreeSet::new(),
+            adv_queue6: BTreeSet::new(),
+            refresh_last: None,
+            refresh_pending: Vec::new(),
+            afi_safi: AfiSafis::default(),
+            add_path_send: Vec::new(),
+            span: None,
+            prefix_warning: false,
+            prefix_exceeded: false,
+            graceful_shutdown: false,
+            maintenance: false,
+            instance_shutdown: None,
+            orf: None,
+            msg_journal: None,
+            mrt_updates: None,
+            mirror_lab: false,
+            mirror_queue: Vec::new(),
         };
-        peer.config
-            .afi_safi
-            .push(AfiSafi::new(Afi::IP, Safi::Unicast));
+        let afi = if address.is_ipv6() { Afi::IP6 } else { Afi::IP };
+        peer.config.afi_safi.push(AfiSafi::new(afi, Safi::Unicast));
         peer.config.four_octet = true;
         peer.config.route_refresh = true;
-        // peer.config.graceful_restart = Some(65535);
         peer
     }
 
-    pub fn event(&self, ident: Ipv4Addr, event: Event) {
+    pub fn event(&self, ident: IpAddr, event: Event) {
         let _ = self.tx.clone().send(Message::Event(ident, event));
     }
 
+    // AS of the peer is not known until OPEN with external, and the type of
+    // the peer follows the configured AS.
+    pub fn remote_as_set(&mut self, remote_as: RemoteAs) {
+        self.remote_as = remote_as;
+        self.peer_as = remote_as.peer_as(self.local_as);
+        self.peer_type = remote_as.peer_type(self.local_as);
+        self.update();
+    }
+
+    // Peer AS in OPEN must be the configured one, or different from or equal
+    // to ours with remote-as external or internal.
+    pub fn remote_as_accept(&self, asn: u32) -> bool {
+        match self.remote_as {
+            RemoteAs::Asn(_) => asn == self.peer_as,
+            RemoteAs::External => asn != self.local_as,
+            RemoteAs::Internal => asn == self.local_as,
+        }
+    }
+
     pub fn is_passive(&self) -> bool {
         self.config.transport.passive
     }
 
+    // Shutdown message when the neighbor or the whole instance is shut down.
+    // The one of the instance takes precedence.
+    pub fn shutdown(&self) -> Option<String> {
+        self.instance_shutdown
+            .clone()
+            .or_else(|| self.config.shutdown.clone())
+    }
+
+    // TTL of the session to eBGP neighbor, 1 unless ebgp-multihop is
+    // enabled. iBGP uses the default of the system, and GTSM sets 255.
+    pub fn ttl(&self) -> Option<u8> {
+        if self.peer_as == self.local_as || self.config.transport.ttl_security.is_some() {
+            return None;
+        }
+        if self.config.transport.ebgp_multihop {
+            Some(self.config.transport.multihop_ttl.unwrap_or(255))
+        } else {
+            Some(1)
+        }
+    }
+
     pub fn update(&mut self) {
-        if self.peer_as != 0 && !self.address.is_unspecified() && !self.active {
+        let remote_as = self.peer_as != 0 || self.remote_as == RemoteAs::External;
+        if remote_as && !self.address.is_unspecified() && !self.active {
             fsm_init(self);
             self.active = true;
         }
     }
 
+    // Cluster ID of route reflection defaults to the router ID (RFC 4456 7).
+    pub fn cluster_id(&self) -> Ipv4Addr {
+        self.config
+            .cluster_id
+            .or(self.global_cluster_id)
+            .unwrap_or(self.router_id)
+    }
+
+    // Local address of the session for next hop self. IPv4 next hop over
+    // IPv6 transport falls back to the router ID.
+    pub fn local_addr4(&self) -> Ipv4Addr {
+        match self.local_addr {
+            Some(IpAddr::V4(addr)) => addr,
+            _ => self.router_id,
+        }
+    }
+
+    // IPv6 next hop over IPv4 transport is IPv4-mapped IPv6 address.
+    pub fn local_addr6(&self) -> Ipv6Addr {
+        match self.local_addr {
+            Some(IpAddr::V6(addr)) => addr,
+            _ => self.local_addr4().to_ipv6_mapped(),
+        }
+    }
+
     pub fn hold_time(&self) -> u16 {
         self.config.hold_time.unwrap_or(BGP_HOLD_TIME)
     }
 
+    // Configured keepalive is capped to one third of the hold time. Zero
+    // keepalive would let the hold timer of the peer expire, so one third of
+    // the hold time is used instead unless the hold time is zero as well.
+    pub fn keepalive(&self, hold_time: u16) -> u16 {
+        match self.config.keepalive {
+            Some(keepalive) if keepalive > 0 => min(keepalive, hold_time / 3),
+            _ => hold_time / 3,
+        }
+    }
+
+    pub fn connect_retry(&self) -> u16 {
+        self.config.connect_retry.unwrap_or(BGP_CONNECT_RETRY)
+    }
+
+    pub fn route_adv_interval(&self) -> u16 {
+        match self.config.route_adv_interval {
+            Some(interval) => interval,
+            None if self.peer_as == self.local_as => BGP_ROUTE_ADV_IBGP,
+            None => BGP_ROUTE_ADV_EBGP,
+        }
+    }
+
+    pub fn refresh_interval(&self) -> u16 {
+        self.config.refresh_interval.unwrap_or(BGP_REFRESH_INTERVAL)
+    }
+
+    pub fn refresh_stale_time(&self) -> u32 {
+        self.config
+            .refresh_stale_time
+            .unwrap_or(BGP_REFRESH_STALE_TIME)
+    }
+
+    // Apply timer configuration change. Established session re-arms keepalive
+    // and hold timer with the hold time received in the last OPEN.
+    pub fn timer_update(&mut self) {
+        if self.state == State::Established {
+            peer_negotiate_hold_time(self, self.param_rx.hold_time);
+        }
+        if self.timer.connect_retry.is_some() {
+            self.timer.connect_retry = Some(peer_start_connect_retry_timer(self));
+        }
+    }
+
+    pub fn sla_stat(&self) -> PeerSlaStat {
+        let current = if self.state == State::Established {
+            self.instant
+        } else {
+            None
+        };
+        self.sla.stat(current)
+    }
+
+    pub fn add_path_limit(&self, afi: &Afi) -> Option<usize> {
+        self.add_path_send
+            .iter()
+            .find(|(x, _)| x == afi)
+            .map(|(_, limit)| *limit)
+    }
+
     pub fn count_clear(&mut self) {
         for count in self.counter.iter_mut() {
             count.sent = 0;
@@ -214,39 +801,190 @@ impl Peer {
 
 pub struct ConfigRef<'a> {
     pub router_id: &'a Ipv4Addr,
-    pub ptree: &'a mut PrefixMap<Ipv4Net, Vec<Route>>,
+    pub monitor_only: bool,
+    pub strict_parse: bool,
+    pub ptree: &'a mut PrefixTree<Ipv4Net, Vec<Route>>,
+    pub ptree6: &'a mut PrefixTree<Ipv6Net, Vec<Route>>,
+    pub families: &'a mut Families,
+    pub vpn: &'a mut Vpn,
+    pub persist: &'a mut Persist,
+    pub policy: &'a Policy,
+    pub nexthop: &'a NexthopConfig,
+    pub bestpath: &'a Bestpath,
+    pub webhook: &'a Webhook,
+    pub origin_watch: &'a mut OriginWatch,
+    pub rpki: &'a Rpki,
+    pub changed: Vec<Ipv4Net>,
+    pub changed6: Vec<Ipv6Net>,
 }
 
-fn update_rib(_bgp: &mut Bgp, id: &Ipv4Addr, _update: &UpdatePacket) {
+fn update_rib(_bgp: &mut Bgp, id: &IpAddr, _update: &UpdatePacket) {
     println!("XX Recv update packet from id {}", id);
 }
 
-pub fn fsm(bgp: &mut Bgp, id: Ipv4Addr, event: Event) {
+pub fn fsm(bgp: &mut Bgp, id: IpAddr, event: Event) {
     let mut bgp_ref = ConfigRef {
         router_id: &bgp.router_id,
+        monitor_only: bgp.monitor_only,
+        strict_parse: bgp.strict_parse,
         ptree: &mut bgp.ptree,
+        ptree6: &mut bgp.ptree6,
+        families: &mut bgp.families,
+        vpn: &mut bgp.vpn,
+        persist: &mut bgp.persist,
+        policy: &bgp.policy,
+        nexthop: &bgp.nexthop,
+        bestpath: &bgp.bestpath,
+        webhook: &bgp.webhook,
+        origin_watch: &mut bgp.origin_watch,
+        rpki: &bgp.rpki,
+        changed: Vec::new(),
+        changed6: Vec::new(),
     };
     let peer = bgp.peers.get_mut(&id).unwrap();
     let prev_state = peer.state.clone();
+    let prev_stale = peer_stale_state(peer);
+    let eor = match &event {
+        Event::UpdateMsg(packet) => packet.end_of_rib(),
+        _ => None,
+    };
+    let manual = matches!(event, Event::Stop | Event::Shutdown(_));
     peer.state = match event {
         Event::ConfigUpdate => fsm_config_update(&bgp_ref, peer),
         Event::Start => fsm_start(peer),
-        Event::Stop => fsm_stop(peer),
+        Event::Stop => {
+            let message = peer.shutdown();
+            fsm_manual_stop(peer, message)
+        }
+        Event::Shutdown(message) => fsm_manual_stop(peer, message),
         Event::ConnRetryTimerExpires => fsm_conn_retry_expires(peer),
         Event::HoldTimerExpires => fsm_holdtimer_expires(peer),
         Event::KeepaliveTimerExpires => fsm_keepalive_expires(peer),
         Event::IdleHoldTimerExpires => fsm_idle_hold_timer_expires(peer),
         Event::Connected(stream) => fsm_connected(peer, stream),
         Event::ConnFail => fsm_conn_fail(peer),
-        Event::BGPOpen(packet) => fsm_bgp_open(peer, packet),
+        Event::BGPOpen(packet) => fsm_bgp_open(peer, packet, &mut bgp_ref),
         Event::NotifMsg(packet) => fsm_bgp_notification(peer, packet),
         Event::KeepAliveMsg => fsm_bgp_keepalive(peer),
         Event::UpdateMsg(packet) => fsm_bgp_update(peer, packet, &mut bgp_ref),
+        Event::RouteAdvTimerExpires => fsm_route_adv_expires(peer, &bgp_ref),
+        Event::CoalesceTimerExpires => fsm_coalesce_expires(peer, &bgp_ref),
+        Event::RouteRefreshMsg(packet) => fsm_bgp_route_refresh(peer, packet, &mut bgp_ref),
+        Event::MsgError(packet) => fsm_msg_error(peer, packet),
+        Event::SoftIn => fsm_soft_in(peer, &bgp_ref),
+        Event::SoftOut => fsm_soft_out(peer, &bgp_ref),
+        Event::RestartTimerExpires | Event::StaleTimerExpires => {
+            fsm_stale_expires(peer, &mut bgp_ref)
+        }
+        Event::RefreshTimerExpires => fsm_refresh_expires(peer, &bgp_ref),
+        Event::RefreshStaleTimerExpires => fsm_refresh_stale_expires(peer, &mut bgp_ref),
     };
-    if prev_state != State::Idle && peer.state == State::Idle {
+    let reset = prev_state != State::Idle && peer.state == State::Idle;
+    if reset {
+        // Routes are retained when the session of the graceful restart
+        // capable peer goes down without NOTIFICATION (RFC 4724 4.2).
+        let retain = prev_state == State::Established
+            && peer.restart_time.is_some()
+            && peer
+                .reset
+                .as_ref()
+                .map(|reset| reset.direction == ResetDirection::Local)
+                .unwrap_or(true);
+        if manual {
+            peer.idle_hold.reset();
+        } else {
+            let uptime = peer
+                .instant
+                .filter(|_| prev_state == State::Established)
+                .map(|instant| instant.elapsed());
+            peer.idle_hold.failure(uptime);
+        }
         peer.state = fsm_stop(peer);
+        bgp_ref.origin_watch.peer_clear(peer.address);
+        if retain {
+            route_stale(peer);
+            peer.timer.restart = Some(peer_start_restart_timer(peer));
+        } else {
+            route_clean(peer, &mut bgp_ref);
+        }
+    }
+    let changed = bgp_ref.changed;
+    let changed6 = bgp_ref.changed6;
+    if prev_state != State::Established && peer.state == State::Established {
+        peer.sla.up();
+        bgp.webhook.established(peer);
+        peer.span = Some(tracing::info_span!(
+            "bgp.session",
+            peer = %peer.address,
+            remote_as = peer.peer_as,
+            remote_id = %peer.remote_id,
+        ));
+    }
+    if prev_state == State::Established && peer.state != State::Established {
+        if let Some(instant) = peer.instant {
+            peer.sla.down(instant);
+        }
+    }
+    if reset {
+        let reset = peer.reset.take().unwrap_or(PeerReset {
+            direction: ResetDirection::Local,
+            code: 0,
+            sub_code: 0,
+            data: Vec::new(),
+        });
+        bgp.audit.reset(peer, &prev_state, &reset);
+        if prev_state == State::Established {
+            bgp.webhook.down(peer, &reset);
+        }
+        // Session span ends with the reset reason.
+        if let Some(span) = peer.span.take() {
+            tracing::info!(
+                parent: &span,
+                direction = ?reset.direction,
+                code = reset.code,
+                sub_code = reset.sub_code,
+                "session down"
+            );
+        }
+        peer.last_reset = Some(reset);
     }
+    let established = prev_state != State::Established && peer.state == State::Established;
     println!("State: {:?} -> {:?}", prev_state, peer.state);
+    if prev_state != peer.state {
+        mrt_dump_state(peer, &prev_state);
+    }
+    if established {
+        restart_established(&mut bgp.restart, peer);
+        if orf_negotiated(&peer.config, true) {
+            peer_send_orf(peer, &bgp.policy);
+        }
+    }
+    let eor = eor.filter(|_| peer.state == State::Established);
+    let address = peer.address;
+    let stale_changed = peer_stale_state(peer) != prev_stale;
+
+    // Advertise best path changes to peers, then whole table to the peer
+    // which has just come up.
+    route_advertise(bgp, &changed, &changed6);
+    if established {
+        route_sync(bgp, id);
+    }
+    if let Some(afi) = eor {
+        restart_eor(bgp, address, afi);
+    }
+    if stale_changed {
+        helper_save(bgp);
+    }
+    dynamic_release(bgp, address);
+}
+
+// Stale routes and the timers of graceful restart helper mode.
+fn peer_stale_state(peer: &Peer) -> (usize, bool, bool) {
+    (
+        peer.stale_rib.count(),
+        peer.timer.restart.is_some(),
+        peer.timer.stale.is_some(),
+    )
 }
 
 fn fsm_config_update(bgp: &ConfigRef, peer: &mut Peer) -> State {
@@ -255,7 +993,10 @@ fn fsm_config_update(bgp: &ConfigRef, peer: &mut Peer) -> State {
 }
 
 pub fn fsm_init(peer: &mut Peer) -> State {
-    if peer.is_passive() {
+    if peer.shutdown().is_some() {
+        peer.timer.idle_hold_timer = None;
+        State::Idle
+    } else if peer.is_passive() {
         peer.timer.idle_hold_timer = None;
         State::Active
     } else {
@@ -265,17 +1006,111 @@ pub fn fsm_init(peer: &mut Peer) -> State {
 }
 
 pub fn fsm_start(peer: &mut Peer) -> State {
+    if peer.shutdown().is_some() {
+        return peer.state.clone();
+    }
     peer.task.connect = Some(peer_start_connection(peer));
     State::Connect
 }
 
+// Cease the session with Administrative Shutdown when it is up. Empty
+// message is sent without the Shutdown Communication.
+pub fn fsm_manual_stop(peer: &mut Peer, message: Option<String>) -> State {
+    if matches!(
+        peer.state,
+        State::OpenSent | State::OpenConfirm | State::Established
+    ) {
+        let data = match message {
+            Some(message) if !message.is_empty() => shutdown_message_encode(&message),
+            _ => Vec::new(),
+        };
+        peer_send_notification(
+            peer,
+            NotificationCode::Cease,
+            NotificationError::AdministrativeShutdown as u8,
+            data,
+        );
+    }
+    State::Idle
+}
+
+// Apply the administrative shutdown changed in the commit. The session is
+// ceased with the message, or started again when the shutdown is removed.
+pub fn peer_shutdown_update(bgp: &mut Bgp) {
+    let addrs = std::mem::take(&mut bgp.shutdown_peers);
+    for addr in addrs.into_iter() {
+        let peer = match bgp.peers.get_mut(&addr) {
+            Some(peer) => peer,
+            None => continue,
+        };
+        if peer.shutdown().is_some() {
+            if peer.state == State::Idle {
+                peer.timer.idle_hold_timer = None;
+            } else {
+                fsm(bgp, addr, Event::Stop);
+            }
+        } else if peer.state == State::Idle && peer.timer.idle_hold_timer.is_none() {
+            peer.state = fsm_init(peer);
+        }
+    }
+}
+
+fn fsm_open_error(peer: &mut Peer, error: OpenError, data: Vec<u8>) -> State {
+    peer.open_error += 1;
+    peer_send_notification(peer, NotificationCode::OpenMessageError, error as u8, data);
+    State::Idle
+}
+
+// Unexpected message in the state (RFC 6608).
+fn fsm_error(peer: &mut Peer) -> State {
+    let sub_code = match peer.state {
+        State::OpenSent => FsmError::UnexpectedMessageInOpenSent as u8,
+        State::OpenConfirm => FsmError::UnexpectedMessageInOpenConfirm as u8,
+        State::Established => FsmError::UnexpectedMessageInEstablished as u8,
+        _ => 0,
+    };
+    peer_send_notification(
+        peer,
+        NotificationCode::FiniteStateMachineError,
+        sub_code,
+        Vec::new(),
+    );
+    State::Idle
+}
+
+// Only the session is closed with NOTIFICATION on the malformed message
+// (RFC 4271 6).
+fn fsm_msg_error(peer: &mut Peer, packet: NotificationPacket) -> State {
+    println!(
+        "Malformed message from {}: {}",
+        peer.address,
+        notification_str(packet.code.0, packet.sub_code)
+    );
+    peer_send_notification(peer, packet.code, packet.sub_code, packet.data);
+    State::Idle
+}
+
 pub fn fsm_stop(peer: &mut Peer) -> State {
-    peer.task.writer = None;
+    // Writer is detached to flush queued NOTIFICATION before the connection
+    // is closed. It exits when packet_tx is dropped.
+    peer.packet_tx = None;
+    if let Some(mut writer) = peer.task.writer.take() {
+        writer.detach();
+    }
     peer.task.reader = None;
+    // UPDATE of this session still in the queue is dropped.
+    peer.queue.session += 1;
     peer.timer.idle_hold_timer = None;
     peer.timer.connect_retry = None;
     peer.timer.keepalive = None;
     peer.timer.hold_timer = None;
+    peer.timer.min_route_adv = None;
+    peer.timer.coalesce = None;
+    peer.timer.refresh = None;
+    peer.refresh_pending.clear();
+    peer.prefix_warning = false;
+    peer.prefix_exceeded = false;
+    peer.orf = None;
     fsm_init(peer)
 }
 
@@ -288,6 +1123,96 @@ pub fn capability_as4(caps: &Vec<CapabilityPacket>) -> Option<u32> {
     None
 }
 
+pub fn capability_route_refresh(caps: &[CapabilityPacket]) -> bool {
+    caps.iter()
+        .any(|cap| matches!(cap, CapabilityPacket::RouteRefresh(_)))
+}
+
+// Enhanced Route Refresh is in use when both of us advertise it (RFC 7313 3).
+fn peer_enhanced_refresh(peer: &Peer) -> bool {
+    peer.config.route_refresh
+        && peer
+            .config
+            .received
+            .iter()
+            .any(|cap| matches!(cap, CapabilityPacket::EnhancedRouteRefresh(_)))
+}
+
+pub fn capability_graceful_restart(
+    caps: &[CapabilityPacket],
+) -> Option<&CapabilityGracefulRestart> {
+    caps.iter().find_map(|cap| match cap {
+        CapabilityPacket::GracefulRestart(m) => Some(m),
+        _ => None,
+    })
+}
+
+pub fn capability_add_path(caps: &[CapabilityPacket]) -> Vec<AddPathValue> {
+    caps.iter()
+        .filter_map(|cap| match cap {
+            CapabilityPacket::AddPath(m) => Some(m.values.clone()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+// ADD-PATH AFI/SAFIs of the direction. Path Identifier is received when we
+// advertise receive and the peer advertises send, and vice versa.
+fn add_path_negotiated<'a>(
+    config: &'a PeerConfig,
+    caps: &[CapabilityPacket],
+    send: bool,
+) -> Vec<&'a PeerAddPath> {
+    let (local, remote) = if send {
+        (ADD_PATH_SEND, ADD_PATH_RECEIVE)
+    } else {
+        (ADD_PATH_RECEIVE, ADD_PATH_SEND)
+    };
+    let received = capability_add_path(caps);
+    config
+        .add_path
+        .iter()
+        .filter(|add_path| add_path.send_receive() & local != 0)
+        .filter(|add_path| {
+            received.iter().any(|value| {
+                value.afi == add_path.afi_safi.afi
+                    && value.safi == add_path.afi_safi.safi
+                    && value.send_receive & remote != 0
+            })
+        })
+        .collect()
+}
+
+pub fn add_path_receive(config: &PeerConfig) -> Vec<Afi> {
+    add_path_negotiated(config, &config.received, false)
+        .into_iter()
+        .map(|add_path| add_path.afi_safi.afi.clone())
+        .collect()
+}
+
+// AFI/SAFI both side advertise. IPv4 unicast is assumed when the peer does
+// not advertise Multiprotocol Extensions capability (RFC 4760 8).
+pub fn capability_afi_safi(config: &AfiSafis, caps: &[CapabilityPacket]) -> AfiSafis {
+    let mut received = AfiSafis::default();
+    for cap in caps.iter() {
+        if let CapabilityPacket::MultiProtocol(m) = cap {
+            received.push(m.afi_safi());
+        }
+    }
+    if received.0.is_empty() {
+        received.push(AfiSafi::new(Afi::IP, Safi::Unicast));
+    }
+    AfiSafis(
+        config
+            .0
+            .iter()
+            .filter(|afi_safi| received.has(afi_safi))
+            .cloned()
+            .collect(),
+    )
+}
+
 pub fn open_asn(packet: &OpenPacket) -> u32 {
     let asn = capability_as4(&packet.caps);
     if let Some(asn) = asn {
@@ -297,44 +1222,43 @@ pub fn open_asn(packet: &OpenPacket) -> u32 {
     }
 }
 
-pub fn fsm_bgp_open(peer: &mut Peer, packet: OpenPacket) -> State {
-    println!("fsm_bgp_open");
-
+pub fn fsm_bgp_open(peer: &mut Peer, packet: OpenPacket, bgp: &mut ConfigRef) -> State {
     peer.counter[BgpType::Open as usize].rcvd += 1;
 
-    // Peer ASN.
-    let asn = open_asn(&packet);
-    println!("fsm_bgp_open: asn {}", asn);
-
-    // Compare with configured asn.
-    if peer.peer_as != asn {
-        println!("XXX peer as wrong");
-        peer_send_notification(
-            peer,
-            NotificationCode::OpenMessageError,
-            OpenError::BadPeerAS as u8,
-            Vec::new(),
-        );
-        return State::Idle;
-    }
-
     if peer.state != State::OpenSent {
         println!("peer state mismatch {:?}", peer.state);
-        // Send notification.
-        return State::Idle;
+        return fsm_error(peer);
+    }
+
+    if packet.version != BGP_VERSION {
+        println!("unsupported version {}", packet.version);
+        let data = (BGP_VERSION as u16).to_be_bytes().to_vec();
+        return fsm_open_error(peer, OpenError::UnsupportedVersionNumber, data);
     }
-    if packet.asn as u32 != peer.peer_as {
-        // Send notification.
-        println!("ASN mismatch");
-        return State::Idle;
+
+    // Compare peer ASN with configured one.
+    let asn = open_asn(&packet);
+    if !peer.remote_as_accept(asn) {
+        println!(
+            "peer AS mismatch: expected {} received {}",
+            peer.remote_as, asn
+        );
+        return fsm_open_error(peer, OpenError::BadPeerAS, Vec::new());
     }
-    if packet.bgp_id != peer.address.octets() {
-        // Send notification.
-        println!("router-id mismatch {:?}", peer.address);
-        return State::Idle;
+    peer.peer_as = asn;
+
+    // BGP identifier must not be zero, and must differ from ours in the same
+    // AS (RFC 6286 2.2).
+    let bgp_id = Ipv4Addr::from(packet.bgp_id);
+    let local_id = peer.local_identifier.unwrap_or(peer.router_id);
+    if bgp_id.is_unspecified() || (peer.peer_as == peer.local_as && bgp_id == local_id) {
+        println!("bad BGP identifier {} from {}", bgp_id, peer.address);
+        return fsm_open_error(peer, OpenError::BadBgpIdentifier, Vec::new());
     }
+
     if packet.hold_time > 0 && packet.hold_time < 3 {
-        return State::Idle;
+        println!("unacceptable hold time {}", packet.hold_time);
+        return fsm_open_error(peer, OpenError::UnacceptableHoldTime, Vec::new());
     }
     peer.remote_id = Ipv4Addr::new(
         packet.bgp_id[0],
@@ -343,24 +1267,34 @@ pub fn fsm_bgp_open(peer: &mut Peer, packet: OpenPacket) -> State {
         packet.bgp_id[3],
     );
 
+    // 4 octet ASN is used when both side advertise the capability.
+    peer.as4 = peer.config.four_octet && capability_as4(&packet.caps).is_some();
+
+    peer.afi_safi = capability_afi_safi(&peer.config.afi_safi, &packet.caps);
+    peer.add_path_send = add_path_negotiated(&peer.config, &packet.caps, true)
+        .into_iter()
+        .filter_map(|add_path| {
+            add_path
+                .send_limit()
+                .map(|limit| (add_path.afi_safi.afi.clone(), limit))
+        })
+        .collect();
+
+    let restart = capability_graceful_restart(&packet.caps);
+    peer.restart_time = match restart {
+        Some(cap) if peer.config.graceful_restart.enabled && cap.restart_time() > 0 => {
+            Some(cap.restart_time())
+        }
+        _ => None,
+    };
+    peer_stale_open(peer, restart, bgp);
+
     // Remember received hold time.
     peer.param_rx.hold_time = packet.hold_time;
     peer.param_rx.keepalive = packet.hold_time / 3;
 
     // Hold timer negotiation.
-    if packet.hold_time == 0 {
-        peer.param.hold_time = 0;
-        peer.param.keepalive = 0;
-    } else {
-        peer.param.hold_time = min(packet.hold_time, peer.hold_time());
-        peer.param.keepalive = peer.param.hold_time / 3;
-    }
-    if peer.param.keepalive > 0 {
-        peer.timer.keepalive = Some(peer_start_keepalive(peer));
-    }
-    if peer.param.hold_time > 0 {
-        peer.timer.hold_timer = Some(peer_start_holdtimer(peer));
-    }
+    peer_negotiate_hold_time(peer, packet.hold_time);
 
     // Set established time.
     peer.instant = Some(Instant::now());
@@ -368,31 +1302,378 @@ pub fn fsm_bgp_open(peer: &mut Peer, packet: OpenPacket) -> State {
     State::Established
 }
 
-pub fn fsm_bgp_notification(peer: &mut Peer, _packet: NotificationPacket) -> State {
+// Negotiated hold time is the smaller of configured and received one, keepalive
+// is one third of it. Zero hold time disables both of timers.
+fn peer_negotiate_hold_time(peer: &mut Peer, received: u16) {
+    let hold_time = min(received, peer.hold_time());
+    if hold_time == 0 {
+        peer.param.hold_time = 0;
+        peer.param.keepalive = 0;
+    } else {
+        peer.param.hold_time = hold_time;
+        peer.param.keepalive = peer.keepalive(hold_time);
+    }
+    peer.timer.keepalive = if peer.param.keepalive > 0 {
+        Some(peer_start_keepalive(peer))
+    } else {
+        None
+    };
+    peer.timer.hold_timer = if peer.param.hold_time > 0 {
+        peer.queue.touch();
+        Some(peer_start_holdtimer(
+            peer,
+            Timer::second(peer.param.hold_time as u64),
+        ))
+    } else {
+        None
+    };
+}
+
+pub fn fsm_bgp_notification(peer: &mut Peer, packet: NotificationPacket) -> State {
     peer.counter[BgpType::Notification as usize].rcvd += 1;
+    peer.reset = Some(PeerReset {
+        direction: ResetDirection::Received,
+        code: packet.code.0,
+        sub_code: packet.sub_code,
+        data: packet.data,
+    });
     State::Idle
 }
 
 pub fn fsm_bgp_keepalive(peer: &mut Peer) -> State {
     peer.counter[BgpType::Keepalive as usize].rcvd += 1;
-    peer_refresh_holdtimer(peer);
+    if peer.state != State::Established {
+        return fsm_error(peer);
+    }
     State::Established
 }
 
-fn fsm_bgp_update(peer: &mut Peer, packet: UpdatePacket, bgp: &mut ConfigRef) -> State {
+// Malformed attributes are logged and counted, then the routes of the UPDATE
+// are withdrawn when required (RFC 7606 2). Discarded attributes have been
+// removed by the parser already.
+fn peer_attr_error(peer: &mut Peer, packet: &mut UpdatePacket) {
+    for error in packet.attr_errors.iter() {
+        println!(
+            "UPDATE from {}: attribute {} {}, {}",
+            peer.address, error.typ, error.kind, error.action
+        );
+        peer.attr_error.entry(error.typ).or_default().count(error);
+    }
+    if packet.attr_action() == Some(AttrAction::TreatAsWithdraw) {
+        packet.treat_as_withdraw();
+    }
+}
+
+fn fsm_bgp_update(peer: &mut Peer, mut packet: UpdatePacket, bgp: &mut ConfigRef) -> State {
     peer.counter[BgpType::Update as usize].rcvd += 1;
-    peer_refresh_holdtimer(peer);
-    route_from_peer(peer, packet, bgp);
+    if peer.state != State::Established {
+        return fsm_error(peer);
+    }
+    // Routes of the lab neighbor of the session mirroring never reach
+    // production.
+    if peer.mirror_lab {
+        return State::Established;
+    }
+    mirror_received(peer, &packet);
+    let _span = tracing::info_span!(
+        parent: peer.span.as_ref().and_then(|span| span.id()),
+        "bgp.update",
+        update = packet.ipv4_update.len()
+            + packet.mp_reach().map(|mp| mp.prefix.len()).unwrap_or(0),
+        withdraw = packet.ipv4_withdraw.len()
+            + packet.mp_unreach().map(|mp| mp.prefix.len()).unwrap_or(0),
+    )
+    .entered();
+    let eor = packet.end_of_rib();
+    // AS 0 is malformed in either of the parse modes.
+    packet.as_zero_check();
+    let duplicate = packet
+        .attr_errors
+        .iter()
+        .any(|error| error.kind == AttrErrorKind::Duplicate);
+    peer_attr_error(peer, &mut packet);
+    // Repeated attribute is a malformed attribute list (RFC 4271 6.3), while
+    // the lenient mode keeps the first one (RFC 7606 3.g).
+    if bgp.strict_parse && duplicate {
+        return fsm_msg_error(
+            peer,
+            NotificationPacket::new(
+                NotificationCode::UpdateMessageError,
+                UpdateError::MalformedAttributeList as u8,
+                Vec::new(),
+            ),
+        );
+    }
+    route_from_peer(peer, &packet, bgp);
+    if let Some(afi) = eor {
+        route_stale_flush(peer, bgp, &afi);
+        if peer.stale_rib.count() == 0 {
+            peer.timer.stale = None;
+        }
+    }
+    bgp.persist.journal(peer.address, packet);
+    peer_prefix_limit(peer, bgp)
+}
+
+// Adj-RIB-Out of the AFI/SAFI is sent again. Request for AFI/SAFI which is
+// not negotiated is ignored (RFC 2918 4).
+fn fsm_bgp_route_refresh(
+    peer: &mut Peer,
+    packet: RouteRefreshPacket,
+    bgp: &mut ConfigRef,
+) -> State {
+    peer.counter[BgpType::RouteRefresh as usize].rcvd += 1;
+    if peer.state != State::Established {
+        return fsm_error(peer);
+    }
+    let afi_safi = AfiSafi::new(packet.afi, packet.safi);
+    if packet.subtype != REFRESH_NORMAL {
+        peer_refresh_demarcation(peer, packet.subtype, &afi_safi, bgp);
+        return State::Established;
+    }
+    if let Some(orf) = packet.orf.as_ref() {
+        if !orf_negotiated(&peer.config, false) {
+            println!("ORF from {} not negotiated", peer.address);
+        } else {
+            orf_update(&mut peer.orf, orf);
+            // Deferred ORF takes effect with the next ROUTE-REFRESH.
+            if orf.when == ORF_DEFER {
+                return State::Established;
+            }
+            if !bgp.monitor_only {
+                route_reeval_out(peer, bgp.ptree, bgp.ptree6, bgp.policy, bgp.bestpath);
+            }
+        }
+    }
+    if !peer.afi_safi.has(&afi_safi) {
+        println!(
+            "ROUTE-REFRESH for {}/{} not negotiated with {}",
+            afi_safi.afi, afi_safi.safi, peer.address
+        );
+    } else if !bgp.monitor_only {
+        peer_refresh_request(peer, afi_safi, bgp);
+    }
+    State::Established
+}
+
+// Re-advertisement requested with ROUTE-REFRESH is sent at most once per the
+// interval. Requests within the interval are sent together when it ends, so
+// that a flood of ROUTE-REFRESH costs a single re-advertisement.
+fn peer_refresh_request(peer: &mut Peer, afi_safi: AfiSafi, bgp: &ConfigRef) {
+    if !peer.refresh_pending.contains(&afi_safi) {
+        peer.refresh_pending.push(afi_safi);
+    }
+    if peer.timer.refresh.is_some() {
+        return;
+    }
+    let interval = Duration::from_secs(peer.refresh_interval() as u64);
+    match peer.refresh_last.map(|last| last.elapsed()) {
+        Some(elapsed) if elapsed < interval => {
+            peer.timer.refresh = Some(peer_start_refresh_timer(peer, interval - elapsed));
+        }
+        _ => peer_refresh_send(peer, bgp),
+    }
+}
+
+// Re-advertisement is enclosed in BoRR and EoRR with Enhanced Route Refresh
+// (RFC 7313 4.1).
+fn peer_refresh_send(peer: &mut Peer, bgp: &ConfigRef) {
+    peer.refresh_last = Some(Instant::now());
+    let enhanced = peer_enhanced_refresh(peer);
+    for afi_safi in std::mem::take(&mut peer.refresh_pending).into_iter() {
+        if enhanced {
+            peer_send_refresh_subtype(peer, afi_safi.clone(), REFRESH_BORR);
+        }
+        route_refresh(
+            peer,
+            bgp.ptree,
+            bgp.ptree6,
+            bgp.policy,
+            bgp.bestpath,
+            &afi_safi,
+        );
+        if enhanced {
+            peer_send_refresh_subtype(peer, afi_safi, REFRESH_EORR);
+        }
+    }
+}
+
+fn fsm_refresh_expires(peer: &mut Peer, bgp: &ConfigRef) -> State {
+    peer.timer.refresh = None;
+    if bgp.monitor_only {
+        peer.refresh_pending.clear();
+    } else {
+        peer_refresh_send(peer, bgp);
+    }
+    peer.state.clone()
+}
+
+// Paths of the unicast AFI received before BoRR are stale, and removed at
+// EoRR unless advertised again, or when no EoRR is received within the stale
+// time (RFC 7313 4.2). BoRR and EoRR are ignored without the capability, as
+// is an unknown subtype.
+fn peer_refresh_demarcation(peer: &mut Peer, subtype: u8, afi_safi: &AfiSafi, bgp: &mut ConfigRef) {
+    let unicast = afi_safi.safi == Safi::Unicast && peer.afi_safi.has(afi_safi);
+    if !peer_enhanced_refresh(peer) || !unicast {
+        println!(
+            "Ignore ROUTE-REFRESH subtype {} for {}/{} from {}",
+            subtype, afi_safi.afi, afi_safi.safi, peer.address
+        );
+        return;
+    }
+    match subtype {
+        REFRESH_BORR => {
+            route_refresh_stale(peer, &afi_safi.afi);
+            peer.timer.refresh_stale = Some(peer_start_refresh_stale_timer(peer));
+        }
+        REFRESH_EORR => {
+            route_refresh_flush(peer, bgp, &afi_safi.afi);
+            if peer.refresh_stale.count() == 0 {
+                peer.timer.refresh_stale = None;
+            }
+        }
+        _ => {
+            println!(
+                "Ignore ROUTE-REFRESH subtype {} from {}",
+                subtype, peer.address
+            );
+        }
+    }
+}
+
+// EoRR of the refresh did not arrive within the stale time.
+fn fsm_refresh_stale_expires(peer: &mut Peer, bgp: &mut ConfigRef) -> State {
+    println!("{} stale paths of ROUTE-REFRESH are removed", peer.address);
+    peer.timer.refresh_stale = None;
+    route_refresh_flush(peer, bgp, &Afi::IP);
+    route_refresh_flush(peer, bgp, &Afi::IP6);
+    peer.state.clone()
+}
+
+// Ask the peer to send its Adj-RIB-Out again for negotiated AFI/SAFIs. IPv4
+// unicast is asked with our inbound prefix list when ORF is negotiated.
+fn fsm_soft_in(peer: &mut Peer, bgp: &ConfigRef) -> State {
+    if peer.state != State::Established {
+        return peer.state.clone();
+    }
+    if !capability_route_refresh(&peer.config.received) {
+        println!("{} does not support ROUTE-REFRESH", peer.address);
+        return State::Established;
+    }
+    let orf = orf_negotiated(&peer.config, true);
+    for afi_safi in peer.afi_safi.0.clone().into_iter() {
+        if orf && afi_safi == AfiSafi::new(Afi::IP, Safi::Unicast) {
+            peer_send_orf(peer, bgp.policy);
+        } else {
+            peer_send_route_refresh(peer, afi_safi);
+        }
+    }
+    State::Established
+}
+
+fn fsm_soft_out(peer: &mut Peer, bgp: &ConfigRef) -> State {
+    if peer.state != State::Established || bgp.monitor_only {
+        return peer.state.clone();
+    }
+    for afi_safi in peer.afi_safi.0.clone().iter() {
+        route_refresh(
+            peer,
+            bgp.ptree,
+            bgp.ptree6,
+            bgp.policy,
+            bgp.bestpath,
+            afi_safi,
+        );
+    }
+    State::Established
+}
+
+// Check number of prefixes received from the neighbor. Warning and exceeded
+// events are notified once until the count goes below the threshold again.
+fn peer_prefix_limit(peer: &mut Peer, bgp: &ConfigRef) -> State {
+    let max = match peer.config.prefix_limit.max_prefixes {
+        Some(max) => max,
+        None => return State::Established,
+    };
+    let count = peer.adj_rib_in.count();
+    let pct = peer
+        .config
+        .prefix_limit
+        .warning_threshold_pct
+        .unwrap_or(100) as u64;
+    let threshold = (max as u64 * pct / 100) as usize;
+
+    if count > max as usize {
+        if !peer.prefix_exceeded {
+            println!(
+                "{}: prefix count {} exceeds the limit {}",
+                peer.address, count, max
+            );
+            bgp.webhook
+                .max_prefix(peer, WebhookEventType::MaxPrefixExceeded, count, max);
+            peer.prefix_exceeded = true;
+        }
+        if peer.config.prefix_limit.teardown {
+            // AFI, SAFI and the upper bound (RFC 4486).
+            let mut data = Afi::IP.0.to_be_bytes().to_vec();
+            data.push(Safi::Unicast.0);
+            data.extend_from_slice(&max.to_be_bytes());
+            peer_send_notification(
+                peer,
+                NotificationCode::Cease,
+                NotificationError::MaximumNumberOfPrefixReached as u8,
+                data,
+            );
+            return State::Idle;
+        }
+    } else {
+        peer.prefix_exceeded = false;
+    }
+    if count >= threshold && pct < 100 {
+        if !peer.prefix_warning {
+            println!(
+                "{}: prefix count {} reaches {}% of the limit {}",
+                peer.address, count, pct, max
+            );
+            bgp.webhook
+                .max_prefix(peer, WebhookEventType::MaxPrefixWarning, count, max);
+            peer.prefix_warning = true;
+        }
+    } else {
+        peer.prefix_warning = false;
+    }
     State::Established
 }
 
 pub fn fsm_connected(peer: &mut Peer, stream: TcpStream) -> State {
     peer.task.connect = None;
+    if let Ok(addr) = stream.local_addr() {
+        peer.local_addr = Some(addr.ip().to_canonical());
+    }
     let (packet_tx, packet_rx) = mpsc::unbounded_channel::<BytesMut>();
     peer.packet_tx = Some(packet_tx);
+    let local = peer.local_addr.unwrap_or(peer.address);
+    let journal = peer.msg_journal.as_ref().map(|journal| {
+        journal.session(
+            peer.peer_as,
+            peer.local_as,
+            peer.scope_id,
+            peer.address,
+            local,
+        )
+    });
+    let updates = peer.mrt_updates.as_ref().map(|writer| {
+        writer.session(
+            peer.peer_as,
+            peer.local_as,
+            peer.scope_id,
+            peer.address,
+            local,
+        )
+    });
     let (read_half, write_half) = stream.into_split();
-    peer.task.reader = Some(peer_start_reader(peer, read_half));
-    peer.task.writer = Some(peer_start_writer(write_half, packet_rx));
+    peer.task.reader = Some(peer_start_reader(peer, read_half, journal.clone(), updates));
+    peer.task.writer = Some(peer_start_writer(write_half, packet_rx, journal));
     peer_send_open(peer);
     peer_send_keepalive(peer);
     State::OpenSent
@@ -403,7 +1684,15 @@ pub fn fsm_conn_retry_expires(peer: &mut Peer) -> State {
     State::Connect
 }
 
+// Hold time runs from the last message read from the socket, which may be
+// still waiting in the queue.
 pub fn fsm_holdtimer_expires(peer: &mut Peer) -> State {
+    let hold_time = Duration::from_secs(peer.param.hold_time as u64);
+    let idle = peer.queue.idle();
+    if idle < hold_time {
+        peer.timer.hold_timer = Some(peer_start_holdtimer(peer, hold_time - idle));
+        return peer.state.clone();
+    }
     peer_send_notification(peer, NotificationCode::HoldTimerExpired, 0, Vec::new());
     State::Idle
 }
@@ -419,6 +1708,67 @@ pub fn fsm_keepalive_expires(peer: &mut Peer) -> State {
     State::Established
 }
 
+// Send advertisements queued during the interval, the timer is stopped when
+// nothing has been queued. Changes in the coalescing window wait for the end
+// of the window.
+pub fn fsm_route_adv_expires(peer: &mut Peer, bgp: &ConfigRef) -> State {
+    if peer.adv_queue.is_empty() && peer.adv_queue6.is_empty() {
+        peer.timer.min_route_adv = None;
+    } else if !bgp.monitor_only && peer.timer.coalesce.is_none() {
+        route_flush(peer, bgp.ptree, bgp.ptree6, bgp.policy, bgp.bestpath);
+    }
+    peer.state.clone()
+}
+
+// End of the coalescing window, the changes queued in the window are sent.
+pub fn fsm_coalesce_expires(peer: &mut Peer, bgp: &ConfigRef) -> State {
+    peer.timer.coalesce = None;
+    if !bgp.monitor_only {
+        route_coalesce_flush(peer, bgp.ptree, bgp.ptree6, bgp.policy, bgp.bestpath);
+    }
+    peer.state.clone()
+}
+
+// Stale routes are kept until End-of-RIB only when the peer preserved
+// forwarding state of the address family (RFC 4724 4.2).
+fn peer_stale_open(
+    peer: &mut Peer,
+    restart: Option<&CapabilityGracefulRestart>,
+    bgp: &mut ConfigRef,
+) {
+    // Stale routes restored by our own restart are kept until End-of-RIB.
+    let helper = peer.timer.restart.take().is_some();
+    if peer.stale_rib.count() == 0 {
+        return;
+    }
+    for afi in [Afi::IP, Afi::IP6] {
+        let preserved = restart
+            .map(|cap| {
+                cap.values
+                    .iter()
+                    .any(|value| value.afi == afi && value.flags & GR_FORWARDING_STATE != 0)
+            })
+            .unwrap_or(false);
+        if helper && !preserved {
+            route_stale_flush(peer, bgp, &afi);
+        }
+    }
+    if peer.stale_rib.count() > 0 {
+        peer.timer.stale = Some(peer_start_stale_timer(peer));
+    }
+}
+
+// The peer did not come back within the restart time, or did not send
+// End-of-RIB within the stale routes time.
+fn fsm_stale_expires(peer: &mut Peer, bgp: &mut ConfigRef) -> State {
+    println!("{} stale routes are removed", peer.address);
+    peer.timer.restart = None;
+    peer.timer.stale = None;
+    route_stale_flush(peer, bgp, &Afi::IP);
+    route_stale_flush(peer, bgp, &Afi::IP6);
+    peer.state.clone()
+}
+
 pub fn fsm_conn_fail(peer: &mut Peer) -> State {
     peer.task.writer = None;
     peer.task.reader = None;
@@ -429,7 +1779,8 @@ pub fn fsm_conn_fail(peer: &mut Peer) -> State {
 pub fn peer_start_idle_hold_timer(peer: &Peer) -> Timer {
     let ident = peer.ident;
     let tx = peer.tx.clone();
-    Timer::new(Timer::second(1), TimerType::Once, move || {
+    let time = Timer::second(peer.idle_hold.time);
+    Timer::new(time, TimerType::Once, move || {
         let tx = tx.clone();
         async move {
             let _ = tx.send(Message::Event(ident, Event::Start));
@@ -440,49 +1791,164 @@ pub fn peer_start_idle_hold_timer(peer: &Peer) -> Timer {
 pub fn peer_start_connect_retry_timer(peer: &Peer) -> Timer {
     let ident = peer.ident;
     let tx = peer.tx.clone();
-    Timer::new(Timer::second(5), TimerType::Once, move || {
+    Timer::new(
+        Timer::second(peer.connect_retry() as u64),
+        TimerType::Once,
+        move || {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(Message::Event(ident, Event::Start));
+            }
+        },
+    )
+}
+
+pub fn peer_start_route_adv_timer(peer: &Peer) -> Option<Timer> {
+    let interval = peer.route_adv_interval();
+    if interval == 0 {
+        return None;
+    }
+    let ident = peer.ident;
+    let tx = peer.tx.clone();
+    Some(Timer::new(
+        Timer::second(interval as u64),
+        TimerType::Infinite,
+        move || {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(Message::Event(ident, Event::RouteAdvTimerExpires));
+            }
+        },
+    ))
+}
+
+pub fn peer_start_coalesce_timer(peer: &Peer) -> Timer {
+    let ident = peer.ident;
+    let tx = peer.tx.clone();
+    Timer::new(
+        Duration::from_millis(peer.config.coalesce_window as u64),
+        TimerType::Once,
+        move || {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(Message::Event(ident, Event::CoalesceTimerExpires));
+            }
+        },
+    )
+}
+
+pub fn peer_start_restart_timer(peer: &Peer) -> Timer {
+    let ident = peer.ident;
+    let tx = peer.tx.clone();
+    let restart_time = peer.restart_time.unwrap_or(0);
+    Timer::new(
+        Timer::second(restart_time as u64),
+        TimerType::Once,
+        move || {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(Message::Event(ident, Event::RestartTimerExpires));
+            }
+        },
+    )
+}
+
+pub fn peer_start_stale_timer(peer: &Peer) -> Timer {
+    let ident = peer.ident;
+    let tx = peer.tx.clone();
+    let stale_time = peer.config.graceful_restart.stale_routes_time();
+    Timer::new(
+        Timer::second(stale_time as u64),
+        TimerType::Once,
+        move || {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(Message::Event(ident, Event::StaleTimerExpires));
+            }
+        },
+    )
+}
+
+pub fn peer_start_refresh_timer(peer: &Peer, time: Duration) -> Timer {
+    let ident = peer.ident;
+    let tx = peer.tx.clone();
+    Timer::new(time, TimerType::Once, move || {
         let tx = tx.clone();
         async move {
-            let _ = tx.send(Message::Event(ident, Event::Start));
+            let _ = tx.send(Message::Event(ident, Event::RefreshTimerExpires));
         }
     })
 }
 
+pub fn peer_start_refresh_stale_timer(peer: &Peer) -> Timer {
+    let ident = peer.ident;
+    let tx = peer.tx.clone();
+    Timer::new(
+        Timer::second(peer.refresh_stale_time() as u64),
+        TimerType::Once,
+        move || {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(Message::Event(ident, Event::RefreshStaleTimerExpires));
+            }
+        },
+    )
+}
+
+// Messages of the peer are queued in the order received.
 pub fn peer_packet_parse(
     rx: &[u8],
-    ident: Ipv4Addr,
-    tx: UnboundedSender<Message>,
+    ident: IpAddr,
+    queue: &PeerQueue,
     config: &mut PeerConfig,
-) -> Result<(), &'static str> {
-    let as4 = !config.received.is_empty();
+) -> Result<(), NotificationPacket> {
+    let as4 = config.four_octet && capability_as4(&config.received).is_some();
+    let add_path = add_path_receive(config);
 
-    if let Ok((_, p)) = parse_bgp_packet(rx, as4) {
-        match p {
+    if let Ok((_, p)) = parse_bgp_packet_add_path(rx, as4, &add_path) {
+        let event = match p {
             BgpPacket::Open(p) => {
                 config.received = p.caps.clone();
-                let _ = tx.send(Message::Event(ident, Event::BGPOpen(p)));
-            }
-            BgpPacket::Keepalive(_) => {
-                let _ = tx.send(Message::Event(ident, Event::KeepAliveMsg));
-            }
-            BgpPacket::Notification(p) => {
-                let _ = tx.send(Message::Event(ident, Event::NotifMsg(p)));
-            }
-            BgpPacket::Update(p) => {
-                let _ = tx.send(Message::Event(ident, Event::UpdateMsg(p)));
+                Event::BGPOpen(p)
             }
-        }
+            BgpPacket::Keepalive(_) => Event::KeepAliveMsg,
+            BgpPacket::Notification(p) => Event::NotifMsg(p),
+            BgpPacket::Update(p) => Event::UpdateMsg(p),
+            BgpPacket::RouteRefresh(p) => Event::RouteRefreshMsg(p),
+        };
+        queue.send(ident, event);
         Ok(())
     } else {
-        Err("parse error")
+        Err(peer_packet_error(rx))
     }
 }
 
+// Error of the message which header has been checked but the body can not
+// be parsed.
+fn peer_packet_error(rx: &[u8]) -> NotificationPacket {
+    let typ = rx.get(18).copied().unwrap_or(0);
+    let (code, sub_code) = match typ {
+        typ if typ == BgpType::Open as u8 => (NotificationCode::OpenMessageError, 0),
+        typ if typ == BgpType::RouteRefresh as u8 => (
+            NotificationCode::RouteRefreshError,
+            RouteRefreshError::InvalidMessageLength as u8,
+        ),
+        _ => (
+            NotificationCode::UpdateMessageError,
+            UpdateError::MalformedAttributeList as u8,
+        ),
+    };
+    NotificationPacket::new(code, sub_code, Vec::new())
+}
+
 pub async fn peer_read(
-    ident: Ipv4Addr,
+    ident: IpAddr,
     tx: UnboundedSender<Message>,
+    queue: PeerQueue,
     mut read_half: OwnedReadHalf,
     mut config: PeerConfig,
+    journal: Option<JournalSession>,
+    updates: Option<JournalSession>,
 ) {
     let mut buf = BytesMut::with_capacity(BGP_PACKET_LEN * 2);
     loop {
@@ -492,21 +1958,38 @@ pub async fn peer_read(
                     let _ = tx.send(Message::Event(ident, Event::ConnFail));
                     return;
                 }
-                while buf.len() >= BGP_HEADER_LEN as usize
-                    && buf.len() >= peek_bgp_length(buf.as_bytes())
-                {
-                    let length = peek_bgp_length(buf.as_bytes());
+                while buf.len() >= BGP_HEADER_LEN as usize {
+                    let length = match bgp_header_check(buf.as_bytes()) {
+                        Ok(length) => length,
+                        Err(notification) => {
+                            let _ = tx.send(Message::Event(ident, Event::MsgError(notification)));
+                            return;
+                        }
+                    };
+                    if buf.len() < length {
+                        break;
+                    }
 
                     let mut remain = buf.split_off(length);
                     remain.reserve(BGP_PACKET_LEN * 2);
 
-                    match peer_packet_parse(buf.as_bytes(), ident, tx.clone(), &mut config) {
+                    if let Some(journal) = journal.as_ref() {
+                        journal.record(false, buf.as_bytes());
+                    }
+                    // MRT dump has the UPDATE messages only.
+                    if let Some(updates) = updates.as_ref() {
+                        if buf[18] == BgpType::Update as u8 {
+                            updates.record(false, buf.as_bytes());
+                        }
+                    }
+
+                    queue.touch();
+                    match peer_packet_parse(buf.as_bytes(), ident, &queue, &mut config) {
                         Ok(_) => {
                             buf = remain;
                         }
-                        Err(err) => {
-                            println!("E: {}", err);
-                            let _ = tx.send(Message::Event(ident, Event::ConnFail));
+                        Err(notification) => {
+                            let _ = tx.send(Message::Event(ident, Event::MsgError(notification)));
                             return;
                         }
                     }
@@ -520,34 +2003,117 @@ pub async fn peer_read(
     }
 }
 
-pub fn peer_start_reader(peer: &Peer, read_half: OwnedReadHalf) -> Task<()> {
+pub fn peer_start_reader(
+    peer: &Peer,
+    read_half: OwnedReadHalf,
+    journal: Option<JournalSession>,
+    updates: Option<JournalSession>,
+) -> Task<()> {
     let ident = peer.ident;
     let tx = peer.tx.clone();
+    let queue = peer.queue.clone();
     let config = peer.config.clone();
     Task::spawn(async move {
-        peer_read(ident, tx.clone(), read_half, config).await;
+        peer_read(
+            ident,
+            tx.clone(),
+            queue,
+            read_half,
+            config,
+            journal,
+            updates,
+        )
+        .await;
     })
 }
 
 pub fn peer_start_writer(
     mut write_half: OwnedWriteHalf,
     mut rx: UnboundedReceiver<BytesMut>,
+    journal: Option<JournalSession>,
 ) -> Task<()> {
     Task::spawn(async move {
         while let Some(msg) = rx.recv().await {
+            if let Some(journal) = journal.as_ref() {
+                journal.record(true, &msg);
+            }
             let _ = write_half.write_all(&msg).await;
         }
     })
 }
 
+fn peer_ttl_set<S: AsFd>(socket: &S, ipv6: bool, ttl: u8) -> std::io::Result<()> {
+    let socket = SockRef::from(socket);
+    if ipv6 {
+        socket.set_unicast_hops_v6(ttl as u32)
+    } else {
+        socket.set_ttl(ttl as u32)
+    }
+}
+
+// Socket of the outgoing connection with the options of the transport, bound
+// to the update source.
+fn peer_socket(
+    address: SocketAddr,
+    transport: PeerTransportConfig,
+    ttl: Option<u8>,
+) -> std::io::Result<TcpSocket> {
+    let socket = if address.is_ipv6() {
+        TcpSocket::new_v6()?
+    } else {
+        TcpSocket::new_v4()?
+    };
+    if let Some(vrf) = transport.vrf {
+        socket.bind_device(Some(vrf.as_bytes()))?;
+    }
+    if let Some(password) = transport.password {
+        tcp_md5_set(socket.as_raw_fd(), address.ip(), &password)?;
+    }
+    if let Some(hops) = transport.ttl_security {
+        gtsm_set(socket.as_raw_fd(), address.is_ipv6(), hops)?;
+    }
+    if let Some(ttl) = ttl {
+        peer_ttl_set(&socket, address.is_ipv6(), ttl)?;
+    }
+    let local_address = match transport.update_source {
+        Some(UpdateSource::Address(addr)) => Some(addr),
+        Some(UpdateSource::Interface(name)) => match if_source(&name, &address.ip()) {
+            Some(addr) => Some(addr),
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrNotAvailable,
+                    format!("no address on update-source interface {}", name),
+                ))
+            }
+        },
+        None => None,
+    };
+    if let Some(local_address) = local_address {
+        socket.bind(SocketAddr::new(local_address, 0))?;
+    }
+    Ok(socket)
+}
+
+async fn peer_connect(
+    address: SocketAddr,
+    transport: PeerTransportConfig,
+    ttl: Option<u8>,
+) -> std::io::Result<TcpStream> {
+    peer_socket(address, transport, ttl)?.connect(address).await
+}
+
 pub fn peer_start_connection(peer: &mut Peer) -> Task<()> {
     let ident = peer.ident;
     let tx = peer.tx.clone();
-    let address = peer.address;
+    let address = match peer.address {
+        IpAddr::V6(addr) => SocketAddr::V6(SocketAddrV6::new(addr, BGP_PORT, 0, peer.scope_id)),
+        addr => SocketAddr::new(addr, BGP_PORT),
+    };
+    let transport = peer.config.transport.clone();
+    let ttl = peer.ttl();
     Task::spawn(async move {
         let tx = tx.clone();
-        let addr = format!("{}:{}", address, BGP_PORT);
-        let result = TcpStream::connect(addr).await;
+        let result = peer_connect(address, transport, ttl).await;
         match result {
             Ok(stream) => {
                 let _ = tx.send(Message::Event(ident, Event::Connected(stream)));
@@ -573,7 +2139,7 @@ pub fn peer_send_open(peer: &mut Peer) {
         caps.push(CapabilityPacket::MultiProtocol(cap));
     }
     if peer.config.four_octet {
-        let cap = CapabilityAs4::new(peer.local_as);
+        let cap = CapabilityAs4::new(peer.open_as());
         caps.push(CapabilityPacket::As4(cap));
     }
     if peer.config.route_refresh {
@@ -581,23 +2147,62 @@ pub fn peer_send_open(peer: &mut Peer) {
         caps.push(CapabilityPacket::RouteRefresh(cap));
         let cap = CapabilityRouteRefresh::new(CapabilityType::RouteRefreshCisco);
         caps.push(CapabilityPacket::RouteRefresh(cap));
+        let cap = CapabilityEnhancedRouteRefresh::new();
+        caps.push(CapabilityPacket::EnhancedRouteRefresh(cap));
+        // ORF is carried in ROUTE-REFRESH.
+        if let Some(cap) = orf_capability(&peer.config) {
+            caps.push(CapabilityPacket::Orf(cap));
+        }
     }
-    if let Some(restart_time) = peer.config.graceful_restart {
-        let cap = CapabilityGracefulRestart::new(restart_time);
+    // Forwarding state is preserved only while we are restarting with the
+    // routes restored, otherwise helper mode.
+    if peer.config.graceful_restart.enabled {
+        let flags = if peer.restarting {
+            GR_FORWARDING_STATE
+        } else {
+            0
+        };
+        let values = peer
+            .config
+            .afi_safi
+            .0
+            .iter()
+            .map(|afi_safi| RestartValue {
+                afi: afi_safi.afi.clone(),
+                safi: afi_safi.safi.clone(),
+                flags,
+            })
+            .collect();
+        let restart_time = peer.config.graceful_restart.restart_time();
+        let cap = CapabilityGracefulRestart::new(restart_time, peer.restarting, values);
         caps.push(CapabilityPacket::GracefulRestart(cap));
     }
+    let values: Vec<AddPathValue> = peer
+        .config
+        .add_path
+        .iter()
+        .filter(|add_path| add_path.send_receive() != 0)
+        .map(|add_path| AddPathValue {
+            afi: add_path.afi_safi.afi.clone(),
+            safi: add_path.afi_safi.safi.clone(),
+            send_receive: add_path.send_receive(),
+        })
+        .collect();
+    if !values.is_empty() {
+        caps.push(CapabilityPacket::AddPath(CapabilityAddPath::new(values)));
+    }
 
     // Remmeber sent hold time.
     peer.param_tx.hold_time = peer.hold_time();
-    peer.param_tx.keepalive = peer.hold_time() / 3;
-
-    let open = OpenPacket::new(
-        header,
-        peer.local_as as u16,
-        peer.hold_time(),
-        &router_id,
-        caps,
-    );
+    peer.param_tx.keepalive = peer.keepalive(peer.hold_time());
+
+    // 4 octet ASN is carried in the capability, AS_TRANS goes to My AS field.
+    let asn = if peer.open_as() > u16::MAX as u32 {
+        AS_TRANS
+    } else {
+        peer.open_as() as u16
+    };
+    let open = OpenPacket::new(header, asn, peer.hold_time(), &router_id, caps);
     let bytes: BytesMut = open.into();
     peer.counter[BgpType::Open as usize].sent += 1;
     let _ = peer.packet_tx.as_ref().unwrap().send(bytes);
@@ -609,10 +2214,18 @@ pub fn peer_send_notification(
     sub_code: u8,
     data: Vec<u8>,
 ) {
+    peer.reset = Some(PeerReset {
+        direction: ResetDirection::Sent,
+        code: code.0,
+        sub_code,
+        data: data.clone(),
+    });
     let notification = NotificationPacket::new(code, sub_code, data);
     let bytes: BytesMut = notification.into();
     peer.counter[BgpType::Notification as usize].sent += 1;
-    let _ = peer.packet_tx.as_ref().unwrap().send(bytes);
+    if let Some(packet_tx) = peer.packet_tx.as_ref() {
+        let _ = packet_tx.send(bytes);
+    }
 }
 
 pub fn peer_start_keepalive(peer: &Peer) -> Timer {
@@ -637,40 +2250,229 @@ pub fn peer_send_keepalive(peer: &mut Peer) {
     let _ = peer.packet_tx.as_ref().unwrap().send(bytes);
 }
 
-pub fn peer_start_holdtimer(peer: &Peer) -> Timer {
-    let ident = peer.ident;
-    let tx = peer.tx.clone();
-    Timer::new(
-        Timer::second(peer.param.hold_time as u64),
-        TimerType::Infinite,
-        move || {
-            let tx = tx.clone();
-            async move {
-                let _ = tx.send(Message::Event(ident, Event::HoldTimerExpires));
-            }
-        },
-    )
+pub fn peer_send_update(peer: &mut Peer, update: UpdatePacket) {
+    mirror_sent(peer, &update);
+    let bytes: BytesMut = update.into();
+    peer.counter[BgpType::Update as usize].sent += 1;
+    if let Some(packet_tx) = peer.packet_tx.as_ref() {
+        let _ = packet_tx.send(bytes);
+    }
+}
+
+pub fn peer_send_route_refresh(peer: &mut Peer, afi_safi: AfiSafi) {
+    peer_send_refresh_subtype(peer, afi_safi, REFRESH_NORMAL);
 }
 
-pub fn peer_refresh_holdtimer(peer: &Peer) {
-    if let Some(holdtimer) = peer.timer.hold_timer.as_ref() {
-        holdtimer.refresh();
+fn peer_send_refresh_subtype(peer: &mut Peer, afi_safi: AfiSafi, subtype: u8) {
+    let mut refresh = RouteRefreshPacket::new(afi_safi.afi, afi_safi.safi);
+    refresh.subtype = subtype;
+    let bytes: BytesMut = refresh.into();
+    peer.counter[BgpType::RouteRefresh as usize].sent += 1;
+    if let Some(packet_tx) = peer.packet_tx.as_ref() {
+        let _ = packet_tx.send(bytes);
     }
 }
 
-pub fn accept(bgp: &mut Bgp, stream: TcpStream, sockaddr: SocketAddr) {
-    match sockaddr {
-        SocketAddr::V4(addr) => {
-            if let Some(peer) = bgp.peers.get_mut(addr.ip()) {
-                if peer.state == State::Active {
-                    peer.state = fsm_connected(peer, stream);
-                }
+pub fn peer_start_holdtimer(peer: &Peer, duration: Duration) -> Timer {
+    let ident = peer.ident;
+    let tx = peer.tx.clone();
+    Timer::new(duration, TimerType::Once, move || {
+        let tx = tx.clone();
+        async move {
+            let _ = tx.send(Message::Event(ident, Event::HoldTimerExpires));
+        }
+    })
+}
+
+// Connection is accepted only by the listener of the VRF the peer is
+// configured in.
+pub fn accept(bgp: &mut Bgp, stream: TcpStream, sockaddr: SocketAddr, vrf: Option<String>) {
+    if bgp.shutdown.is_some() {
+        println!("Accept: {} refused, BGP is shut down", sockaddr);
+        return;
+    }
+    // IPv4 peer connects to the dual stack listener with IPv4-mapped address.
+    let addr = sockaddr.ip().to_canonical();
+    let mut learned = match sockaddr {
+        SocketAddr::V6(sockaddr) if !bgp.peers.contains_key(&addr) => {
+            unnumbered_accept(bgp, &sockaddr)
+        }
+        _ => false,
+    };
+    // Then the listen ranges for a dynamic peer.
+    if !bgp.peers.contains_key(&addr) {
+        learned = dynamic_accept(bgp, addr, &vrf);
+    }
+    if let Some(peer) = bgp.peers.get_mut(&addr) {
+        if peer.config.transport.vrf != vrf {
+            println!("Accept: {} is not in VRF {:?}", addr, vrf);
+            return;
+        }
+        // Handshake from the listener is sent with the default TTL, and the
+        // check applies to the packets after the accept.
+        if let Some(hops) = peer.config.transport.ttl_security {
+            if let Err(err) = gtsm_set(stream.as_raw_fd(), addr.is_ipv6(), hops) {
+                println!("Accept: {} TTL security error: {}", addr, err);
+                return;
+            }
+        }
+        // Single-hop eBGP neighbor must be on a connected subnet.
+        if let Some(ttl) = peer.ttl() {
+            if ttl == 1 && !is_connected(&addr) {
+                println!("Accept: {} is not directly connected", addr);
+                return;
+            }
+            if let Err(err) = peer_ttl_set(&stream, addr.is_ipv6(), ttl) {
+                println!("Accept: {} TTL error: {}", addr, err);
+                return;
             }
         }
-        SocketAddr::V6(addr) => {
-            println!("IPv6: {:?}", addr);
+        if peer.state == State::Active || learned {
+            peer.timer.idle_hold_timer = None;
+            peer.state = fsm_connected(peer, stream);
         }
     }
+    dynamic_release(bgp, addr);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn keepalive_hold_time() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let addr: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65002,
+            addr,
+            tx.clone(),
+            tx,
+        );
+        assert_eq!(peer.keepalive(90), 30);
 
-    // Next, lookup peer-group for dynamic peer.
+        peer.config.keepalive = Some(10);
+        assert_eq!(peer.keepalive(90), 10);
+        assert_eq!(peer.keepalive(9), 3);
+
+        // Zero keepalive falls back to one third of non-zero hold time.
+        peer.config.keepalive = Some(0);
+        assert_eq!(peer.keepalive(90), 30);
+        assert_eq!(peer.keepalive(0), 0);
+    }
+
+    #[test]
+    fn sla_stat() {
+        let base = Instant::now();
+        let second = |x: u64| base + Duration::from_secs(x);
+        let mut sla = PeerSla::default();
+        for (end, duration) in [(100, 100), (1000, 300)] {
+            sla.up();
+            sla.uptime += Duration::from_secs(duration);
+            sla.sessions
+                .push_back((second(end), Duration::from_secs(duration)));
+        }
+
+        // Current session is up for 600 seconds.
+        sla.up();
+        let stat = sla.stat_at(second(2600), Some(second(2000)));
+        assert_eq!(stat.established, 3);
+        assert_eq!(stat.uptime, 1000);
+        assert_eq!(stat.sessions, 3);
+        assert_eq!(stat.longest, 600);
+        assert_eq!(stat.average, 333);
+
+        // First session is out of the window, while the uptime keeps it.
+        let stat = sla.stat_at(second(500) + PEER_SLA_WINDOW, None);
+        assert_eq!(stat.established, 3);
+        assert_eq!(stat.uptime, 400);
+        assert_eq!(stat.sessions, 1);
+        assert_eq!(stat.longest, 300);
+        assert_eq!(stat.average, 300);
+
+        let stat = PeerSla::default().stat(None);
+        assert_eq!((stat.sessions, stat.longest, stat.average), (0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn hold_timer_received() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let addr: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65002,
+            addr,
+            tx.clone(),
+            tx,
+        );
+        let (packet_tx, mut packet_rx) = mpsc::unbounded_channel();
+        peer.packet_tx = Some(packet_tx);
+        peer.state = State::Established;
+
+        // Message has been read within the hold time, the timer is armed
+        // again for the rest of it.
+        peer.param.hold_time = 90;
+        peer.queue.touch();
+        assert_eq!(fsm_holdtimer_expires(&mut peer), State::Established);
+        assert!(peer.timer.hold_timer.is_some());
+        assert!(packet_rx.try_recv().is_err());
+
+        // Nothing has been read for the hold time.
+        peer.param.hold_time = 0;
+        assert_eq!(fsm_holdtimer_expires(&mut peer), State::Idle);
+        assert!(packet_rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn multihop_ttl() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let addr: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65002,
+            addr,
+            tx.clone(),
+            tx,
+        );
+        assert_eq!(peer.ttl(), Some(1));
+        peer.config.transport.ebgp_multihop = true;
+        assert_eq!(peer.ttl(), Some(255));
+        peer.config.transport.multihop_ttl = Some(5);
+        assert_eq!(peer.ttl(), Some(5));
+
+        // GTSM sets the TTL itself, and iBGP keeps the system default.
+        peer.config.transport.ttl_security = Some(1);
+        assert_eq!(peer.ttl(), None);
+        peer.config.transport.ttl_security = None;
+        peer.peer_as = 65001;
+        assert_eq!(peer.ttl(), None);
+    }
+
+    #[tokio::test]
+    async fn socket_update_source() {
+        let address: SocketAddr = "127.0.0.1:179".parse().unwrap();
+        let transport = PeerTransportConfig {
+            update_source: Some(UpdateSource::Address("127.0.0.1".parse().unwrap())),
+            ..Default::default()
+        };
+        let socket = peer_socket(address, transport, Some(5)).unwrap();
+        assert_eq!(socket.local_addr().unwrap().ip(), address.ip());
+        assert_eq!(SockRef::from(&socket).ttl().unwrap(), 5);
+
+        // Connection is not attempted without the address of the interface.
+        let transport = PeerTransportConfig {
+            update_source: Some(UpdateSource::Interface(String::from("nonexistent0"))),
+            ..Default::default()
+        };
+        let err = peer_socket(address, transport, None).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrNotAvailable);
+    }
 }
@@ -0,0 +1,478 @@
+use super::adj_rib::{path_id, AdjRib};
+use super::handler::{Bgp, Message};
+use super::packet::{
+    parse_bgp_packet_add_path, Attribute, Attrs, BgpHeader, BgpPacket, BgpType, MpNlriAttr,
+    UpdatePacket, BGP_HEADER_LEN,
+};
+use super::route::route_restore;
+use super::task::{Timer, TimerType};
+use super::Afi;
+use bytes::{BufMut, BytesMut};
+use ipnet::{Ipv4Net, Ipv6Net};
+use nom::bytes::complete::take;
+use nom::number::complete::be_u32;
+use nom::IResult;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+
+// Adj-RIB-In persistence for route collector. The store directory has a
+// periodic snapshot of Adj-RIB-In of all of the peers and journals of UPDATE
+// messages received since the snapshot. Both are a sequence of records:
+//
+//   timestamp (4 octets) | peer address (16 octets) | BGP UPDATE message
+//
+// IPv4 peer address is stored as IPv4-mapped IPv6 address. UPDATE messages
+// are always encoded with 4 octet AS path, and every NLRI carries the Path
+// Identifier (RFC 7911), which is 0 unless ADD-PATH is negotiated.
+//
+// Journal is rotated when the snapshot is taken, and its generation is the
+// file name suffix. Snapshot starts with the generation of the journal
+// opened when it was taken, so that the journals of the generation and later
+// are replayed on top of it. Older journals are removed once the snapshot is
+// synced to disk.
+
+pub const PERSIST_SNAPSHOT_INTERVAL: u64 = 300;
+
+const SNAPSHOT: &str = "snapshot";
+const SNAPSHOT_TMP: &str = "snapshot.tmp";
+const JOURNAL: &str = "journal";
+
+// Records carry Path Identifier for both of the address families.
+const PERSIST_ADD_PATH: [Afi; 2] = [Afi::IP, Afi::IP6];
+
+pub struct Persist {
+    pub dir: Option<PathBuf>,
+    pub interval: u64,
+    generation: u32,
+    journal: Option<File>,
+    timer: Option<Timer>,
+    // Snapshot being written, the next one waits for it.
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Default for Persist {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            interval: PERSIST_SNAPSHOT_INTERVAL,
+            generation: 0,
+            journal: None,
+            timer: None,
+            writer: None,
+        }
+    }
+}
+
+fn timestamp() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+pub fn record_encode(buf: &mut BytesMut, from: IpAddr, update: UpdatePacket) {
+    let from = match from {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    };
+    buf.put_u32(timestamp());
+    buf.put(&from.octets()[..]);
+    let bytes: BytesMut = update.into();
+    buf.put(&bytes[..]);
+}
+
+pub fn record_parse<'a>(
+    input: &'a [u8],
+    add_path: &[Afi],
+) -> IResult<&'a [u8], (IpAddr, BgpPacket)> {
+    let (input, _timestamp) = be_u32(input)?;
+    let (input, from) = take(16usize)(input)?;
+    let from: [u8; 16] = from.try_into().unwrap();
+    let from = IpAddr::V6(Ipv6Addr::from(from)).to_canonical();
+    let (input, packet) = parse_bgp_packet_add_path(input, true, add_path)?;
+    Ok((input, (from, packet)))
+}
+
+fn update(attrs: Attrs, ipv4_update: Vec<Ipv4Net>, ipv4_update_id: Vec<u32>) -> UpdatePacket {
+    UpdatePacket {
+        header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+        attrs,
+        ipv4_update,
+        ipv4_withdraw: Vec::new(),
+        ipv4_update_id,
+        ipv4_withdraw_id: Vec::new(),
+        attr_errors: Vec::new(),
+    }
+}
+
+fn mp_ids(mp: &MpNlriAttr) -> MpNlriAttr {
+    let mut mp = mp.clone();
+    mp.path_id = (0..mp.prefix.len())
+        .map(|i| path_id(&mp.path_id, i))
+        .collect();
+    mp
+}
+
+// Path Identifier is filled for every NLRI of the received UPDATE.
+fn update_ids(update: &UpdatePacket) -> UpdatePacket {
+    let ids = |nlri: usize, ids: &[u32]| (0..nlri).map(|i| path_id(ids, i)).collect();
+    UpdatePacket {
+        header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+        attrs: update
+            .attrs
+            .iter()
+            .map(|attr| match attr {
+                Attribute::MpReachNlri(mp) => Attribute::MpReachNlri(mp_ids(mp)),
+                Attribute::MpUnreachNlri(mp) => Attribute::MpUnreachNlri(mp_ids(mp)),
+                _ => attr.clone(),
+            })
+            .collect(),
+        ipv4_update: update.ipv4_update.clone(),
+        ipv4_withdraw: update.ipv4_withdraw.clone(),
+        ipv4_update_id: ids(update.ipv4_update.len(), &update.ipv4_update_id),
+        ipv4_withdraw_id: ids(update.ipv4_withdraw.len(), &update.ipv4_withdraw_id),
+        attr_errors: Vec::new(),
+    }
+}
+
+// Attributes of the IPv6 path with the prefix in MP_REACH_NLRI.
+fn attrs6(attrs: &Attrs, prefix: Ipv6Net, id: u32) -> Attrs {
+    attrs
+        .iter()
+        .map(|attr| match attr {
+            Attribute::MpReachNlri(mp) => {
+                let mut mp = mp.clone();
+                mp.prefix = vec![prefix];
+                mp.path_id = vec![id];
+                Attribute::MpReachNlri(mp)
+            }
+            _ => attr.clone(),
+        })
+        .collect()
+}
+
+// Adj-RIB-In of the peer. Stale paths which have not been advertised again
+// are kept as well.
+fn rib_encode(buf: &mut BytesMut, from: IpAddr, rib: &AdjRib, stale: &AdjRib) {
+    let fresh = |prefix: &Ipv4Net, id: u32| {
+        rib.v4
+            .get(prefix)
+            .map(|paths| paths.contains_key(&id))
+            .unwrap_or(false)
+    };
+    let fresh6 = |prefix: &Ipv6Net, id: u32| {
+        rib.v6
+            .get(prefix)
+            .map(|paths| paths.contains_key(&id))
+            .unwrap_or(false)
+    };
+    let stale4 = stale.paths().filter(|(prefix, id, _)| !fresh(prefix, *id));
+    for (prefix, id, attrs) in rib.paths().chain(stale4) {
+        record_encode(buf, from, update(attrs.clone(), vec![*prefix], vec![id]));
+    }
+    let stale6 = stale
+        .paths6()
+        .filter(|(prefix, id, _)| !fresh6(prefix, *id));
+    for (prefix, id, attrs) in rib.paths6().chain(stale6) {
+        let attrs = attrs6(attrs, *prefix, id);
+        record_encode(buf, from, update(attrs, Vec::new(), Vec::new()));
+    }
+}
+
+// Apply the records to Adj-RIB-In of the peers. A truncated record at the
+// tail is left from an unclean shutdown, it is simply ignored.
+fn replay(input: &[u8], ribs: &mut BTreeMap<IpAddr, AdjRib>) -> usize {
+    let mut input = input;
+    let mut count = 0;
+    while let Ok((remain, (from, packet))) = record_parse(input, &PERSIST_ADD_PATH) {
+        if let BgpPacket::Update(update) = packet {
+            ribs.entry(from).or_insert_with(AdjRib::new).update(&update);
+            count += 1;
+        }
+        input = remain;
+    }
+    count
+}
+
+fn journal_name(generation: u32) -> String {
+    format!("{}.{}", JOURNAL, generation)
+}
+
+// Generations of the journals in the directory.
+fn journals(dir: &Path) -> Vec<u32> {
+    let mut generations: Vec<u32> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let name = entry.file_name().into_string().ok()?;
+                    name.strip_prefix(JOURNAL)?.strip_prefix('.')?.parse().ok()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    generations.sort();
+    generations
+}
+
+// Adj-RIB-In of the peers in the snapshot and the journals after it, and the
+// generation of the last journal.
+fn persist_read(dir: &Path) -> (BTreeMap<IpAddr, AdjRib>, u32) {
+    let mut ribs = BTreeMap::new();
+    let data = fs::read(dir.join(SNAPSHOT)).unwrap_or_default();
+    let (generation, records) = match data.get(..4) {
+        Some(generation) => (
+            u32::from_be_bytes(generation.try_into().unwrap()),
+            &data[4..],
+        ),
+        None => (0, &data[..0]),
+    };
+    let count = replay(records, &mut ribs);
+    println!("persist: {} paths restored from snapshot", count);
+    let mut last = generation;
+    for journal in journals(dir).into_iter().filter(|x| *x >= generation) {
+        let data = fs::read(dir.join(journal_name(journal))).unwrap_or_default();
+        let count = replay(&data, &mut ribs);
+        println!(
+            "persist: {} updates replayed from journal {}",
+            count, journal
+        );
+        last = journal;
+    }
+    (ribs, last)
+}
+
+// The snapshot is replaced by rename after the data is synced, then the
+// directory is synced for the rename to be durable before the journals it
+// covers are removed.
+fn snapshot_write(dir: &Path, generation: u32, buf: &[u8]) -> std::io::Result<()> {
+    let mut file = File::create(dir.join(SNAPSHOT_TMP))?;
+    file.write_all(&generation.to_be_bytes())?;
+    file.write_all(buf)?;
+    file.sync_all()?;
+    fs::rename(dir.join(SNAPSHOT_TMP), dir.join(SNAPSHOT))?;
+    File::open(dir)?.sync_all()?;
+    for journal in journals(dir).into_iter().filter(|x| *x < generation) {
+        fs::remove_file(dir.join(journal_name(journal)))?;
+    }
+    Ok(())
+}
+
+// Restore Adj-RIB-In of the configured peers once the directory is
+// configured, then start journaling. Peers which already have routes are
+// left as they are.
+pub fn persist_load(bgp: &mut Bgp) {
+    let dir = match &bgp.persist.dir {
+        Some(dir) if !bgp.persist.is_enabled() => dir.clone(),
+        _ => return,
+    };
+    if let Err(err) = fs::create_dir_all(&dir) {
+        println!("persist: can't create {}: {}", dir.display(), err);
+        return;
+    }
+    let (ribs, generation) = persist_read(&dir);
+    let mut count = 0;
+    for (addr, rib) in ribs.into_iter() {
+        let empty = match bgp.peers.get(&addr) {
+            Some(peer) => peer.adj_rib_in.count() == 0 && peer.stale_rib.count() == 0,
+            None => false,
+        };
+        if empty && rib.count() > 0 && route_restore(bgp, addr, rib).is_some() {
+            count += 1;
+        }
+    }
+    println!("persist: restored Adj-RIB-In of {} peers", count);
+
+    if let Err(err) = bgp.persist.journal_open(&dir, generation + 1) {
+        println!("persist: can't open journal: {}", err);
+        return;
+    }
+    bgp.persist.timer_start(bgp.tx.clone());
+}
+
+// Write Adj-RIB-In of all of the peers to a new snapshot, and journaling
+// continues to the next generation.
+pub fn persist_snapshot(bgp: &mut Bgp) {
+    let dir = match &bgp.persist.dir {
+        Some(dir) if bgp.persist.is_enabled() => dir.clone(),
+        _ => return,
+    };
+    if let Some(writer) = &bgp.persist.writer {
+        if !writer.is_finished() {
+            return;
+        }
+    }
+    let mut buf = BytesMut::new();
+    for peer in bgp.peers.values() {
+        rib_encode(&mut buf, peer.address, &peer.adj_rib_in, &peer.stale_rib);
+    }
+    let generation = bgp.persist.generation + 1;
+    if let Err(err) = bgp.persist.journal_open(&dir, generation) {
+        println!("persist: can't open journal: {}", err);
+        return;
+    }
+    bgp.persist.writer = Some(tokio::task::spawn_blocking(move || {
+        if let Err(err) = snapshot_write(&dir, generation, &buf) {
+            println!("persist: snapshot error: {}", err);
+        }
+    }));
+}
+
+impl Persist {
+    pub fn is_enabled(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    pub fn stop(&mut self) {
+        self.journal = None;
+        self.timer = None;
+        self.dir = None;
+    }
+
+    fn journal_open(&mut self, dir: &Path, generation: u32) -> std::io::Result<()> {
+        let journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(journal_name(generation)))?;
+        self.journal = Some(journal);
+        self.generation = generation;
+        Ok(())
+    }
+
+    pub fn timer_start(&mut self, tx: UnboundedSender<Message>) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.timer = Some(Timer::new(
+            Timer::second(self.interval),
+            TimerType::Infinite,
+            move || {
+                let tx = tx.clone();
+                async move {
+                    let _ = tx.send(Message::Snapshot);
+                }
+            },
+        ));
+    }
+
+    pub fn journal(&mut self, from: IpAddr, update: UpdatePacket) {
+        if let Some(journal) = self.journal.as_mut() {
+            let mut buf = BytesMut::new();
+            record_encode(&mut buf, from, update_ids(&update));
+            if let Err(err) = journal.write_all(&buf) {
+                println!("persist: journal write error: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{NextHopAttr, OriginAttr};
+    use crate::bgp::Safi;
+
+    fn origin() -> Attrs {
+        vec![
+            Attribute::Origin(OriginAttr { origin: 0 }),
+            Attribute::NextHop(NextHopAttr {
+                next_hop: [10, 0, 0, 1],
+            }),
+        ]
+    }
+
+    fn mp_reach(prefix: Vec<Ipv6Net>, path_id: Vec<u32>) -> Attribute {
+        Attribute::MpReachNlri(MpNlriAttr {
+            next_hop: Some("2001:db8::1".parse().unwrap()),
+            link_local: None,
+            prefix,
+            path_id,
+            safi: Safi::Unicast,
+            label: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn adj_rib_round_trip() {
+        let from: IpAddr = "10.0.0.1".parse().unwrap();
+        let prefix: Ipv4Net = "10.1.0.0/16".parse().unwrap();
+        let prefix6: Ipv6Net = "2001:db8:1::/48".parse().unwrap();
+        let mut rib = AdjRib::new();
+        rib.insert(prefix, 1, origin());
+        rib.insert(prefix, 2, origin());
+        let mut stale = AdjRib::new();
+        stale.insert(prefix, 2, origin());
+        stale.insert(prefix, 3, origin());
+        stale.insert6(prefix6, 7, vec![mp_reach(Vec::new(), Vec::new())]);
+
+        let mut buf = BytesMut::new();
+        rib_encode(&mut buf, from, &rib, &stale);
+        let mut ribs = BTreeMap::new();
+        assert_eq!(replay(&buf, &mut ribs), 4);
+        let rib = &ribs[&from];
+        let ids: Vec<u32> = rib.paths().map(|(_, id, _)| id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        let ids: Vec<u32> = rib.paths6().map(|(_, id, _)| id).collect();
+        assert_eq!(ids, vec![7]);
+
+        // Truncated record at the tail is ignored.
+        let mut ribs = BTreeMap::new();
+        assert_eq!(replay(&buf[..buf.len() - 1], &mut ribs), 3);
+    }
+
+    #[test]
+    fn journal_path_id() {
+        let from: IpAddr = "2001:db8::2".parse().unwrap();
+        let prefix: Ipv4Net = "10.1.0.0/16".parse().unwrap();
+        let prefix6: Ipv6Net = "2001:db8:1::/48".parse().unwrap();
+        // UPDATE without ADD-PATH followed by the withdrawal of the path.
+        let mut attrs = origin();
+        attrs.push(mp_reach(vec![prefix6], Vec::new()));
+        let advertise = update(attrs, vec![prefix], Vec::new());
+        let mut withdraw = update(Vec::new(), Vec::new(), Vec::new());
+        withdraw.ipv4_withdraw = vec![prefix];
+
+        let mut buf = BytesMut::new();
+        record_encode(&mut buf, from, update_ids(&advertise));
+        let mut ribs = BTreeMap::new();
+        assert_eq!(replay(&buf, &mut ribs), 1);
+        assert_eq!(ribs[&from].paths().next().unwrap().1, 0);
+        assert_eq!(ribs[&from].paths6().next().unwrap().0, &prefix6);
+
+        record_encode(&mut buf, from, update_ids(&withdraw));
+        let mut ribs = BTreeMap::new();
+        assert_eq!(replay(&buf, &mut ribs), 2);
+        assert_eq!(ribs[&from].v4.len(), 0);
+        assert_eq!(ribs[&from].v6.len(), 1);
+    }
+
+    #[test]
+    fn snapshot_generation() {
+        let dir = std::env::temp_dir().join(format!("persist-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let from: IpAddr = "10.0.0.1".parse().unwrap();
+        let record = |prefix: &str| {
+            let mut buf = BytesMut::new();
+            let packet = update(origin(), vec![prefix.parse().unwrap()], vec![0]);
+            record_encode(&mut buf, from, packet);
+            buf
+        };
+        // Journal 1 is covered by the snapshot of generation 2.
+        fs::write(dir.join(journal_name(1)), record("10.1.0.0/16")).unwrap();
+        fs::write(dir.join(journal_name(2)), record("10.2.0.0/16")).unwrap();
+        snapshot_write(&dir, 2, &record("10.1.0.0/16")).unwrap();
+        assert_eq!(journals(&dir), vec![2]);
+
+        let (ribs, generation) = persist_read(&dir);
+        assert_eq!(generation, 2);
+        assert_eq!(ribs[&from].prefixes().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,388 @@
+use ipnet::{Ipv4Net, Ipv6Net};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// Prefix stored in PrefixTree.
+pub trait Prefix: Copy + Eq {
+    fn prefix_len(&self) -> u8;
+
+    // Bit of the address at the index, 0 is the most significant bit.
+    fn bit(&self, index: u8) -> usize;
+
+    // Longest prefix which contains both of prefixes.
+    fn common(&self, other: &Self) -> Self;
+
+    fn contains(&self, other: &Self) -> bool;
+
+    // Prefix with host bits cleared.
+    fn trunc(&self) -> Self;
+}
+
+impl Prefix for Ipv4Net {
+    fn prefix_len(&self) -> u8 {
+        Ipv4Net::prefix_len(self)
+    }
+
+    fn bit(&self, index: u8) -> usize {
+        ((u32::from(self.addr()) >> (31 - index)) & 1) as usize
+    }
+
+    fn common(&self, other: &Self) -> Self {
+        let diff = u32::from(self.addr()) ^ u32::from(other.addr());
+        let len = (diff.leading_zeros() as u8)
+            .min(Ipv4Net::prefix_len(self))
+            .min(Ipv4Net::prefix_len(other));
+        Ipv4Net::new(self.addr(), len).unwrap().trunc()
+    }
+
+    fn contains(&self, other: &Self) -> bool {
+        let len = Ipv4Net::prefix_len(self);
+        if len > Ipv4Net::prefix_len(other) {
+            return false;
+        }
+        let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+        u32::from(self.addr()) & mask == u32::from(other.addr()) & mask
+    }
+
+    fn trunc(&self) -> Self {
+        Ipv4Net::new(Ipv4Addr::from(self.network()), Ipv4Net::prefix_len(self)).unwrap()
+    }
+}
+
+impl Prefix for Ipv6Net {
+    fn prefix_len(&self) -> u8 {
+        Ipv6Net::prefix_len(self)
+    }
+
+    fn bit(&self, index: u8) -> usize {
+        ((u128::from(self.addr()) >> (127 - index)) & 1) as usize
+    }
+
+    fn common(&self, other: &Self) -> Self {
+        let diff = u128::from(self.addr()) ^ u128::from(other.addr());
+        let len = (diff.leading_zeros() as u8)
+            .min(Ipv6Net::prefix_len(self))
+            .min(Ipv6Net::prefix_len(other));
+        Ipv6Net::new(self.addr(), len).unwrap().trunc()
+    }
+
+    fn contains(&self, other: &Self) -> bool {
+        let len = Ipv6Net::prefix_len(self);
+        if len > Ipv6Net::prefix_len(other) {
+            return false;
+        }
+        let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+        u128::from(self.addr()) & mask == u128::from(other.addr()) & mask
+    }
+
+    fn trunc(&self) -> Self {
+        Ipv6Net::new(Ipv6Addr::from(self.network()), Ipv6Net::prefix_len(self)).unwrap()
+    }
+}
+
+struct Node<P, T> {
+    prefix: P,
+    value: Option<T>,
+    child: [Option<Box<Node<P, T>>>; 2],
+}
+
+impl<P: Prefix, T> Node<P, T> {
+    fn new(prefix: P, value: Option<T>) -> Box<Self> {
+        Box::new(Self {
+            prefix,
+            value,
+            child: [None, None],
+        })
+    }
+}
+
+// Patricia trie keyed by prefix. Nodes without value are kept only as a
+// branch point of two subtrees. Iteration is in prefix order, a covering
+// prefix comes before more specific ones.
+pub struct PrefixTree<P, T> {
+    root: Option<Box<Node<P, T>>>,
+    len: usize,
+    version: u64,
+}
+
+impl<P, T> fmt::Debug for PrefixTree<P, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrefixTree")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<P: Prefix, T> Default for PrefixTree<P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn node_insert<P: Prefix, T>(slot: &mut Option<Box<Node<P, T>>>, prefix: P, value: T) -> Option<T> {
+    let node = match slot {
+        Some(node) => node,
+        None => {
+            *slot = Some(Node::new(prefix, Some(value)));
+            return None;
+        }
+    };
+    if node.prefix == prefix {
+        return node.value.replace(value);
+    }
+    if node.prefix.contains(&prefix) {
+        let bit = prefix.bit(node.prefix.prefix_len());
+        return node_insert(&mut node.child[bit], prefix, value);
+    }
+    let old = slot.take().unwrap();
+    if prefix.contains(&old.prefix) {
+        let mut new = Node::new(prefix, Some(value));
+        let bit = old.prefix.bit(prefix.prefix_len());
+        new.child[bit] = Some(old);
+        *slot = Some(new);
+    } else {
+        let common = prefix.common(&old.prefix);
+        let mut branch = Node::new(common, None);
+        let bit = old.prefix.bit(common.prefix_len());
+        branch.child[bit] = Some(old);
+        branch.child[1 - bit] = Some(Node::new(prefix, Some(value)));
+        *slot = Some(branch);
+    }
+    None
+}
+
+fn node_remove<P: Prefix, T>(slot: &mut Option<Box<Node<P, T>>>, prefix: &P) -> Option<T> {
+    let node = slot.as_mut()?;
+    let value = if node.prefix == *prefix {
+        node.value.take()
+    } else if node.prefix.contains(prefix) {
+        let bit = prefix.bit(node.prefix.prefix_len());
+        node_remove(&mut node.child[bit], prefix)
+    } else {
+        return None;
+    };
+    // Node without value is removed unless it has two children.
+    if node.value.is_none() && (node.child[0].is_none() || node.child[1].is_none()) {
+        let node = slot.take().unwrap();
+        let [left, right] = node.child;
+        *slot = left.or(right);
+    }
+    value
+}
+
+fn node_get_mut<'a, P: Prefix, T>(
+    slot: &'a mut Option<Box<Node<P, T>>>,
+    prefix: &P,
+) -> Option<&'a mut Node<P, T>> {
+    let node = slot.as_deref_mut()?;
+    if node.prefix == *prefix {
+        return Some(node);
+    }
+    if !node.prefix.contains(prefix) {
+        return None;
+    }
+    let bit = prefix.bit(node.prefix.prefix_len());
+    node_get_mut(&mut node.child[bit], prefix)
+}
+
+impl<P: Prefix, T> PrefixTree<P, T> {
+    pub fn new() -> Self {
+        Self {
+            root: None,
+            len: 0,
+            version: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Incremented on every modification, including mutable access to the
+    // value, so that readers can tell the tree has not changed.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn insert(&mut self, prefix: P, value: T) -> Option<T> {
+        self.version += 1;
+        let old = node_insert(&mut self.root, prefix.trunc(), value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, prefix: &P) -> Option<T> {
+        let value = node_remove(&mut self.root, &prefix.trunc());
+        if value.is_some() {
+            self.len -= 1;
+            self.version += 1;
+        }
+        value
+    }
+
+    pub fn get(&self, prefix: &P) -> Option<&T> {
+        let prefix = prefix.trunc();
+        let mut node = self.root.as_deref();
+        while let Some(n) = node {
+            if n.prefix == prefix {
+                return n.value.as_ref();
+            }
+            if !n.prefix.contains(&prefix) {
+                return None;
+            }
+            node = n.child[prefix.bit(n.prefix.prefix_len())].as_deref();
+        }
+        None
+    }
+
+    pub fn get_mut(&mut self, prefix: &P) -> Option<&mut T> {
+        let value = node_get_mut(&mut self.root, &prefix.trunc())?
+            .value
+            .as_mut()?;
+        self.version += 1;
+        Some(value)
+    }
+
+    pub fn get_or_insert_with<F: FnOnce() -> T>(&mut self, prefix: P, f: F) -> &mut T {
+        if self.get(&prefix).is_none() {
+            self.insert(prefix, f());
+        }
+        self.get_mut(&prefix).unwrap()
+    }
+
+    // Longest prefix match.
+    pub fn lookup(&self, prefix: &P) -> Option<(&P, &T)> {
+        let mut found = None;
+        let mut node = self.root.as_deref();
+        while let Some(n) = node {
+            if !n.prefix.contains(prefix) {
+                break;
+            }
+            if let Some(value) = &n.value {
+                found = Some((&n.prefix, value));
+            }
+            if n.prefix.prefix_len() == prefix.prefix_len() {
+                break;
+            }
+            node = n.child[prefix.bit(n.prefix.prefix_len())].as_deref();
+        }
+        found
+    }
+
+    pub fn iter(&self) -> Iter<'_, P, T> {
+        Iter {
+            stack: self.root.as_deref().into_iter().collect(),
+        }
+    }
+
+    // Entries contained in the prefix, including the prefix itself.
+    pub fn subtree(&self, prefix: &P) -> Iter<'_, P, T> {
+        let prefix = prefix.trunc();
+        let mut node = self.root.as_deref();
+        while let Some(n) = node {
+            if prefix.contains(&n.prefix) {
+                return Iter { stack: vec![n] };
+            }
+            if !n.prefix.contains(&prefix) {
+                break;
+            }
+            node = n.child[prefix.bit(n.prefix.prefix_len())].as_deref();
+        }
+        Iter { stack: Vec::new() }
+    }
+}
+
+pub struct Iter<'a, P, T> {
+    stack: Vec<&'a Node<P, T>>,
+}
+
+impl<'a, P, T> Iterator for Iter<'a, P, T> {
+    type Item = (&'a P, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            for child in node.child.iter().rev().flatten() {
+                self.stack.push(child);
+            }
+            if let Some(value) = &node.value {
+                return Some((&node.prefix, value));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn net(s: &str) -> Ipv4Net {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn insert_remove() {
+        let mut tree = PrefixTree::new();
+        for (i, prefix) in ["10.1.0.0/16", "10.0.0.0/8", "10.2.0.0/16", "192.168.0.0/24"]
+            .iter()
+            .enumerate()
+        {
+            assert!(tree.insert(net(prefix), i).is_none());
+        }
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.get(&net("10.2.0.0/16")), Some(&2));
+        assert_eq!(tree.get(&net("10.3.0.0/16")), None);
+
+        let order: Vec<String> = tree.iter().map(|(p, _)| p.to_string()).collect();
+        assert_eq!(
+            order,
+            vec!["10.0.0.0/8", "10.1.0.0/16", "10.2.0.0/16", "192.168.0.0/24"]
+        );
+
+        let version = tree.version();
+        assert_eq!(tree.remove(&net("10.0.0.0/8")), Some(1));
+        assert_eq!(tree.remove(&net("10.0.0.0/8")), None);
+        assert_eq!(tree.version(), version + 1);
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree.get(&net("10.1.0.0/16")), Some(&0));
+    }
+
+    #[test]
+    fn lookup_subtree() {
+        let mut tree = PrefixTree::new();
+        tree.insert(net("10.0.0.0/8"), 1);
+        tree.insert(net("10.1.0.0/16"), 2);
+        tree.insert(net("10.1.1.0/24"), 3);
+        tree.insert(net("172.16.0.0/12"), 4);
+
+        let (prefix, value) = tree.lookup(&net("10.1.2.0/24")).unwrap();
+        assert_eq!((prefix.to_string(), *value), ("10.1.0.0/16".to_string(), 2));
+        assert!(tree.lookup(&net("11.0.0.0/8")).is_none());
+
+        let subtree: Vec<u32> = tree.subtree(&net("10.1.0.0/16")).map(|(_, v)| *v).collect();
+        assert_eq!(subtree, vec![2, 3]);
+        let subtree: Vec<u32> = tree.subtree(&net("10.0.0.0/7")).map(|(_, v)| *v).collect();
+        assert_eq!(subtree, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn version_get_mut() {
+        let mut tree = PrefixTree::new();
+        tree.insert(net("10.1.0.0/16"), 1);
+        let version = tree.version();
+
+        // Mutable access to a value counts as a change, a miss does not.
+        *tree.get_mut(&net("10.1.0.0/16")).unwrap() = 2;
+        assert_eq!(tree.version(), version + 1);
+        assert!(tree.get_mut(&net("10.2.0.0/16")).is_none());
+        assert_eq!(tree.version(), version + 1);
+        tree.get(&net("10.1.0.0/16"));
+        assert_eq!(tree.version(), version + 1);
+    }
+}
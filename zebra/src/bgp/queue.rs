@@ -0,0 +1,155 @@
+use super::handler::Message;
+use super::peer::Event;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+// Messages received from the peers are queued apart from the local events
+// such as the timers and the teardown of the sessions. The event loop
+// processes the local events first, so that a peer flooding updates can not
+// delay the detection and the teardown of dead sessions, while the messages
+// of a peer are processed in the order they were received.
+pub struct UpdateQueue {
+    pub tx: UnboundedSender<Message>,
+    pub rx: UnboundedReceiver<Message>,
+}
+
+impl UpdateQueue {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self { tx, rx }
+    }
+}
+
+impl Default for UpdateQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Message sent by the reader is tagged with the session, so that the ones
+// queued before the session went down are dropped.
+#[derive(Debug, Clone)]
+pub struct PeerQueue {
+    pub tx: UnboundedSender<Message>,
+    pub session: u64,
+    start: Instant,
+    // Time the last message was read from the socket, in milliseconds since
+    // start.
+    received: Arc<AtomicU64>,
+}
+
+impl PeerQueue {
+    pub fn new(tx: UnboundedSender<Message>) -> Self {
+        Self {
+            tx,
+            session: 0,
+            start: Instant::now(),
+            received: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn send(&self, ident: IpAddr, event: Event) {
+        let _ = self.tx.send(Message::Received(
+            ident,
+            self.session,
+            event,
+            Instant::now(),
+        ));
+    }
+
+    // Message is taken from the queue, returns whether it belongs to the
+    // current session.
+    pub fn recv(&self, session: u64) -> bool {
+        self.session == session
+    }
+
+    // A message is read from the socket.
+    pub fn touch(&self) {
+        let now = self.start.elapsed().as_millis() as u64;
+        self.received.store(now, Ordering::Relaxed);
+    }
+
+    // Time since the last message was read from the socket.
+    pub fn idle(&self) -> Duration {
+        let received = Duration::from_millis(self.received.load(Ordering::Relaxed));
+        self.start.elapsed().saturating_sub(received)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{BgpHeader, BgpType, UpdatePacket, BGP_HEADER_LEN};
+
+    fn update() -> Event {
+        Event::UpdateMsg(UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: Vec::new(),
+            ipv4_update: Vec::new(),
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn peer_queue_session() {
+        let mut queue = UpdateQueue::new();
+        let mut peer = PeerQueue::new(queue.tx.clone());
+        let ident: IpAddr = "10.0.0.1".parse().unwrap();
+        peer.send(ident, update());
+        peer.send(ident, Event::KeepAliveMsg);
+
+        // Session went down with the messages in the queue.
+        peer.session += 1;
+        for _ in 0..2 {
+            match queue.rx.try_recv() {
+                Ok(Message::Received(addr, session, _, _)) => {
+                    assert_eq!(addr, ident);
+                    assert!(!peer.recv(session));
+                }
+                _ => panic!("message is not queued"),
+            }
+        }
+
+        // Reader of the new session.
+        let reader = peer.clone();
+        reader.send(ident, update());
+        match queue.rx.try_recv() {
+            Ok(Message::Received(_, session, _, _)) => assert!(peer.recv(session)),
+            _ => panic!("message is not queued"),
+        }
+    }
+
+    #[test]
+    fn peer_queue_order() {
+        let mut queue = UpdateQueue::new();
+        let peer = PeerQueue::new(queue.tx.clone());
+        let ident: IpAddr = "10.0.0.1".parse().unwrap();
+        peer.send(ident, update());
+        peer.send(ident, Event::KeepAliveMsg);
+        peer.send(ident, update());
+        let mut events = Vec::new();
+        while let Ok(Message::Received(_, _, event, _)) = queue.rx.try_recv() {
+            events.push(matches!(event, Event::UpdateMsg(_)));
+        }
+        assert_eq!(events, [true, false, true]);
+    }
+
+    #[test]
+    fn peer_queue_idle() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let peer = PeerQueue::new(tx);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(peer.idle() >= Duration::from_millis(20));
+
+        // Reader shares the time of the receipt.
+        let reader = peer.clone();
+        reader.touch();
+        assert!(peer.idle() < Duration::from_millis(20));
+    }
+}
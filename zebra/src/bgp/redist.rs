@@ -0,0 +1,133 @@
+use super::handler::Bgp;
+use super::latency::latency_fib_ack;
+use super::network::{ROUTE_LOCAL, ROUTE_LOCAL_WEIGHT};
+use super::packet::{Attribute, MedAttr, OriginAttr};
+use super::route::{route_add, route_advertise, route_withdraw_path, Route};
+use crate::policy::PolicyAction;
+use crate::rib::api::{RedistRoute, RedistSource, RibRx};
+use ipnet::IpNet;
+use std::collections::BTreeMap;
+
+// Redistribution of the routes of other sources. Routes offered by RIB are
+// kept regardless of the configuration, and originated as local routes with
+// origin INCOMPLETE while the source is configured.
+#[derive(Debug, Default)]
+pub struct Redistribute {
+    // MED of the routes, the metric of the source when not configured.
+    pub metric: Option<u32>,
+    pub route_map: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Redist {
+    pub config: BTreeMap<RedistSource, Redistribute>,
+    pub routes: BTreeMap<(RedistSource, IpNet), RedistRoute>,
+}
+
+// Path identifier of the local route by the source, so that routes of the
+// sources and network statement of the same prefix coexist in Loc-RIB.
+fn redist_path_id(source: RedistSource) -> u32 {
+    match source {
+        RedistSource::Connected => 1,
+        RedistSource::Static => 2,
+    }
+}
+
+fn redist_route(bgp: &Bgp, route: &RedistRoute) -> Option<Route> {
+    let config = bgp.redistribute.config.get(&route.source)?;
+    let med = config.metric.unwrap_or(route.metric);
+    let mut attrs = vec![
+        Attribute::Origin(OriginAttr { origin: 2 }),
+        Attribute::Med(MedAttr { med }),
+    ];
+    if let Some(name) = &config.route_map {
+        if bgp.policy.route_map_apply(name, &route.prefix, &mut attrs) == PolicyAction::Deny {
+            return None;
+        }
+    }
+    let rpki = bgp.rpki.state(&route.prefix, &attrs, bgp.asn);
+    Some(Route {
+        from: ROUTE_LOCAL,
+        router_id: bgp.router_id,
+        attrs,
+        ibgp: false,
+        weight: ROUTE_LOCAL_WEIGHT,
+        metric: 0,
+        selected: false,
+        resolved: true,
+        path_id: redist_path_id(route.source),
+        local_path_id: 0,
+        local_label: None,
+        multipath: false,
+        rpki,
+    })
+}
+
+// Originate or withdraw the route of the source, then advertise the change
+// to peers.
+pub fn redist_apply(bgp: &mut Bgp, source: RedistSource, prefix: &IpNet) {
+    let route = bgp
+        .redistribute
+        .routes
+        .get(&(source, *prefix))
+        .and_then(|route| redist_route(bgp, route));
+    let id = redist_path_id(source);
+    match prefix {
+        IpNet::V4(prefix) => {
+            match route {
+                Some(route) => route_add(&mut bgp.ptree, *prefix, route, &bgp.bestpath),
+                None => route_withdraw_path(&mut bgp.ptree, ROUTE_LOCAL, id, prefix, &bgp.bestpath),
+            }
+            route_advertise(bgp, &[*prefix], &[]);
+        }
+        IpNet::V6(prefix) => {
+            match route {
+                Some(route) => route_add(&mut bgp.ptree6, *prefix, route, &bgp.bestpath),
+                None => {
+                    route_withdraw_path(&mut bgp.ptree6, ROUTE_LOCAL, id, prefix, &bgp.bestpath)
+                }
+            }
+            route_advertise(bgp, &[], &[*prefix]);
+        }
+    }
+}
+
+// All of the routes of the source after the configuration is changed.
+pub fn redist_source_apply(bgp: &mut Bgp, source: RedistSource) {
+    let prefixes: Vec<IpNet> = bgp
+        .redistribute
+        .routes
+        .keys()
+        .filter(|(x, _)| *x == source)
+        .map(|(_, prefix)| *prefix)
+        .collect();
+    for prefix in prefixes.iter() {
+        redist_apply(bgp, source, prefix);
+    }
+}
+
+pub fn redist_recv(bgp: &mut Bgp, msg: RibRx) {
+    match msg {
+        RibRx::RedistAdd(route) => {
+            let (source, prefix) = (route.source, route.prefix);
+            bgp.redistribute.routes.insert((source, prefix), route);
+            redist_apply(bgp, source, &prefix);
+        }
+        RibRx::RedistDel(route) => {
+            let (source, prefix) = (route.source, route.prefix);
+            if bgp.redistribute.routes.remove(&(source, prefix)).is_some() {
+                redist_apply(bgp, source, &prefix);
+            }
+        }
+        RibRx::RouteAck(prefix) => latency_fib_ack(bgp, &prefix),
+        RibRx::Link() | RibRx::Nexthop() => {}
+    }
+}
+
+// Change the setting of the source and originate the routes again.
+pub fn redist_update<F: FnOnce(&mut Redistribute)>(bgp: &mut Bgp, source: RedistSource, f: F) {
+    if let Some(config) = bgp.redistribute.config.get_mut(&source) {
+        f(config);
+        redist_source_apply(bgp, source);
+    }
+}
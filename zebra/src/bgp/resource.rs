@@ -0,0 +1,187 @@
+use super::adj_rib::AdjRib;
+use super::handler::Bgp;
+use super::packet::Attribute;
+use super::peer::State;
+use super::ptree::{Prefix, PrefixTree};
+use super::route::Route;
+use crate::config::Args;
+use ipnet::{Ipv4Net, Ipv6Net};
+use std::fmt::Write;
+use std::fs;
+use std::mem::size_of;
+
+// Resource usage of the process read from procfs. None on platforms without
+// it.
+fn proc_status(key: &str) -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with(key))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn proc_fd_count() -> Option<usize> {
+    Some(fs::read_dir("/proc/self/fd").ok()?.count())
+}
+
+fn proc_fd_limit() -> Option<u64> {
+    let limits = fs::read_to_string("/proc/self/limits").ok()?;
+    let line = limits
+        .lines()
+        .find(|line| line.starts_with("Max open files"))?;
+    line.split_whitespace().nth(3)?.parse().ok()
+}
+
+fn or_na<T: ToString>(value: Option<T>) -> String {
+    value
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| String::from("n/a"))
+}
+
+// Memory estimates are the size of the structures and their path attribute
+// vectors. Attributes are held per route, not interned, so identical
+// attributes are counted for every copy.
+#[derive(Default)]
+struct Usage {
+    prefixes: usize,
+    paths: usize,
+    bytes: usize,
+    attrs: usize,
+}
+
+impl Usage {
+    fn attrs(&mut self, attrs: &[Attribute]) {
+        self.attrs += attrs.len() * size_of::<Attribute>();
+    }
+
+    fn kb(&self) -> usize {
+        (self.bytes + self.attrs).div_ceil(1024)
+    }
+}
+
+fn rib_usage<P: Prefix>(ptree: &PrefixTree<P, Vec<Route>>) -> Usage {
+    let mut usage = Usage::default();
+    for (_, routes) in ptree.iter() {
+        usage.prefixes += 1;
+        usage.paths += routes.len();
+        usage.bytes += size_of::<P>() + routes.capacity() * size_of::<Route>();
+        for route in routes.iter() {
+            usage.attrs(&route.attrs);
+        }
+    }
+    usage
+}
+
+fn adj_rib_usage(usage: &mut Usage, rib: &AdjRib) {
+    for (_, paths) in rib.v4.iter() {
+        usage.prefixes += 1;
+        usage.bytes += size_of::<Ipv4Net>();
+        for attrs in paths.values() {
+            usage.paths += 1;
+            usage.attrs(attrs);
+        }
+    }
+    for (_, paths) in rib.v6.iter() {
+        usage.prefixes += 1;
+        usage.bytes += size_of::<Ipv6Net>();
+        for attrs in paths.values() {
+            usage.paths += 1;
+            usage.attrs(attrs);
+        }
+    }
+}
+
+pub fn show_system_resources(bgp: &Bgp, _args: Args) -> String {
+    let mut buf = String::new();
+
+    let runtime = tokio::runtime::Handle::current().metrics();
+    writeln!(buf, "Process:").unwrap();
+    writeln!(
+        buf,
+        "  RSS                  {} kB",
+        or_na(proc_status("VmRSS:"))
+    )
+    .unwrap();
+    writeln!(
+        buf,
+        "  File descriptors     {} / {}",
+        or_na(proc_fd_count()),
+        or_na(proc_fd_limit())
+    )
+    .unwrap();
+    writeln!(
+        buf,
+        "  Tasks                {} ({} workers)",
+        runtime.num_alive_tasks(),
+        runtime.num_workers()
+    )
+    .unwrap();
+
+    let v4 = rib_usage(&bgp.ptree);
+    let v6 = rib_usage(&bgp.ptree6);
+    let mut adj_in = Usage::default();
+    let mut adj_out = Usage::default();
+    let mut queued = 0;
+    let mut established = 0;
+    for peer in bgp.peers.values() {
+        adj_rib_usage(&mut adj_in, &peer.adj_rib_in);
+        adj_rib_usage(&mut adj_out, &peer.adj_rib_out);
+        queued += peer.adv_queue.len() + peer.adv_queue6.len();
+        if peer.state == State::Established {
+            established += 1;
+        }
+    }
+    let attrs = v4.attrs + v6.attrs + adj_in.attrs + adj_out.attrs;
+
+    writeln!(buf, "\nMemory estimates:").unwrap();
+    writeln!(
+        buf,
+        "  {:20} {:>10} {:>10} {:>10}",
+        "", "Prefixes", "Paths", "kB"
+    )
+    .unwrap();
+    for (name, usage) in [
+        ("Loc-RIB IPv4", &v4),
+        ("Loc-RIB IPv6", &v6),
+        ("Adj-RIB-In", &adj_in),
+        ("Adj-RIB-Out", &adj_out),
+    ] {
+        writeln!(
+            buf,
+            "  {:20} {:>10} {:>10} {:>10}",
+            name,
+            usage.prefixes,
+            usage.paths,
+            usage.kb()
+        )
+        .unwrap();
+    }
+    writeln!(
+        buf,
+        "  {:20} {:>10} {:>10} {:>10}",
+        "Path attributes",
+        "",
+        "",
+        attrs.div_ceil(1024)
+    )
+    .unwrap();
+    writeln!(
+        buf,
+        "  {:20} {:>10} {:>10} {:>10}",
+        "Advertise queues",
+        queued,
+        "",
+        (queued * size_of::<Ipv6Net>()).div_ceil(1024)
+    )
+    .unwrap();
+
+    writeln!(buf, "\nPeers:").unwrap();
+    writeln!(buf, "  Configured           {}", bgp.peers.len()).unwrap();
+    writeln!(buf, "  Established          {}", established).unwrap();
+
+    writeln!(buf, "\nChannel depths:").unwrap();
+    writeln!(buf, "  BGP event            {}", bgp.rx.len()).unwrap();
+    writeln!(buf, "  Config               {}", bgp.cm.rx.len()).unwrap();
+    writeln!(buf, "  Show                 {}", bgp.show.rx.len()).unwrap();
+    writeln!(buf, "  Redistribute         {}", bgp.redist.rx.len()).unwrap();
+
+    buf
+}
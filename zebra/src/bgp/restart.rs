@@ -0,0 +1,440 @@
+use super::adj_rib::AdjRib;
+use super::handler::{Bgp, Message};
+use super::helper::helper_load;
+use super::network::ROUTE_LOCAL;
+use super::packet::{Attribute, BgpHeader, BgpPacket, BgpType, UpdatePacket, BGP_HEADER_LEN};
+use super::peer::{Peer, State};
+use super::persist::{record_encode, record_parse};
+use super::route::{route_add, route_sync, route_withdraw, Route};
+use super::rpki::RpkiState;
+use super::task::{Timer, TimerType};
+use super::{Afi, BGP_STALE_ROUTES_TIME};
+use bytes::BytesMut;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+const RESTART_PEERS: &str = "restart-peers";
+const RESTART_RIB: &str = "restart-rib";
+
+// Restarting speaker mode of graceful restart (RFC 4724 4.1). Routes and
+// graceful restart capable peers are saved on shutdown, and restored on the
+// next start. Routes are not advertised until End-of-RIB is received from
+// all of the saved peers or the selection deferral timer expires.
+#[derive(Debug)]
+pub struct RestartConfig {
+    pub enabled: bool,
+    pub helper_only: bool,
+    pub deferral_time: u32,
+    pub dir: Option<PathBuf>,
+    // State is restored only by the first commit after start.
+    pub loaded: bool,
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            helper_only: true,
+            deferral_time: BGP_STALE_ROUTES_TIME,
+            dir: None,
+            loaded: false,
+        }
+    }
+}
+
+impl RestartConfig {
+    fn dir(&self) -> Option<&PathBuf> {
+        if self.enabled && !self.helper_only {
+            self.dir.as_ref()
+        } else {
+            None
+        }
+    }
+
+    // Helper mode state is saved regardless of the restarting speaker mode.
+    pub fn helper_dir(&self) -> Option<&PathBuf> {
+        if self.enabled {
+            self.dir.as_ref()
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RestartState {
+    // Address families of the peer waiting for End-of-RIB. None until the
+    // session is established.
+    pub waiting: BTreeMap<IpAddr, Option<Vec<Afi>>>,
+    // Restored routes handed to the peer as stale when it is configured.
+    pub stale: BTreeMap<IpAddr, AdjRib>,
+    pub timer: Option<Timer>,
+}
+
+pub fn restart_attrs(attrs: &[Attribute], prefix: Vec<ipnet::Ipv6Net>) -> Vec<Attribute> {
+    attrs
+        .iter()
+        .map(|attr| match attr {
+            Attribute::MpReachNlri(mp) => {
+                let mut mp = mp.clone();
+                mp.prefix = prefix.clone();
+                Attribute::MpReachNlri(mp)
+            }
+            _ => attr.clone(),
+        })
+        .collect()
+}
+
+fn restart_rib_write(bgp: &Bgp, path: &Path) -> std::io::Result<()> {
+    let mut buf = BytesMut::new();
+    for (prefix, routes) in bgp.ptree.iter() {
+        for route in routes.iter().filter(|route| route.from != ROUTE_LOCAL) {
+            let update = UpdatePacket {
+                header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+                attrs: route.attrs.clone(),
+                ipv4_update: vec![*prefix],
+                ipv4_withdraw: Vec::new(),
+                ipv4_update_id: Vec::new(),
+                ipv4_withdraw_id: Vec::new(),
+                attr_errors: Vec::new(),
+            };
+            record_encode(&mut buf, route.from, update);
+        }
+    }
+    for (prefix, routes) in bgp.ptree6.iter() {
+        for route in routes.iter().filter(|route| route.from != ROUTE_LOCAL) {
+            let update = UpdatePacket {
+                header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+                attrs: restart_attrs(&route.attrs, vec![*prefix]),
+                ipv4_update: Vec::new(),
+                ipv4_withdraw: Vec::new(),
+                ipv4_update_id: Vec::new(),
+                ipv4_withdraw_id: Vec::new(),
+                attr_errors: Vec::new(),
+            };
+            record_encode(&mut buf, route.from, update);
+        }
+    }
+    fs::write(path, &buf)
+}
+
+// Save graceful restart capable peers and the table on shutdown. Sessions
+// are closed without NOTIFICATION so that the peers retain our routes.
+pub fn restart_save(bgp: &Bgp) {
+    let dir = match bgp.restart_config.dir() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let peers: Vec<String> = bgp
+        .peers
+        .values()
+        .filter(|peer| peer.state == State::Established && peer.restart_time.is_some())
+        .map(|peer| peer.address.to_string())
+        .collect();
+    let result = fs::create_dir_all(dir)
+        .and_then(|_| restart_rib_write(bgp, &dir.join(RESTART_RIB)))
+        .and_then(|_| fs::write(dir.join(RESTART_PEERS), peers.join("\n")));
+    match result {
+        Ok(_) => println!("restart: saved {} peers", peers.len()),
+        Err(err) => println!("restart: save error: {}", err),
+    }
+}
+
+fn restart_stale_add(stale: &mut BTreeMap<IpAddr, AdjRib>, from: IpAddr, update: &UpdatePacket) {
+    let rib = stale.entry(from).or_insert_with(AdjRib::new);
+    for prefix in update.ipv4_update.iter() {
+        rib.insert(*prefix, 0, update.attrs.clone());
+    }
+    if let Some(mp) = update.mp_reach() {
+        let attrs = update.mp_attrs();
+        for (i, prefix) in mp.prefix.iter().enumerate() {
+            rib.insert6(*prefix, 0, update.mp_prefix_attrs(&attrs, i));
+        }
+    }
+}
+
+pub fn restart_route(from: IpAddr, attrs: Vec<Attribute>) -> Route {
+    Route {
+        from,
+        router_id: match from {
+            IpAddr::V4(addr) => addr,
+            IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+        },
+        attrs,
+        ibgp: false,
+        weight: 0,
+        metric: 0,
+        selected: false,
+        resolved: true,
+        path_id: 0,
+        local_path_id: 0,
+        local_label: None,
+        multipath: false,
+        rpki: RpkiState::NotFound,
+    }
+}
+
+// Restore the saved state once at startup, then the stale routes of helper
+// mode.
+pub fn restart_load(bgp: &mut Bgp) {
+    if bgp.restart_config.loaded {
+        return;
+    }
+    bgp.restart_config.loaded = true;
+    restart_restore(bgp);
+    helper_load(bgp);
+}
+
+// The files are removed so that a later crash is not taken as a graceful
+// restart.
+fn restart_restore(bgp: &mut Bgp) {
+    let dir = match bgp.restart_config.dir() {
+        Some(dir) => dir.clone(),
+        None => return,
+    };
+    let peers = match fs::read_to_string(dir.join(RESTART_PEERS)) {
+        Ok(peers) => peers,
+        Err(_) => return,
+    };
+    let data = fs::read(dir.join(RESTART_RIB)).unwrap_or_default();
+    let _ = fs::remove_file(dir.join(RESTART_PEERS));
+    let _ = fs::remove_file(dir.join(RESTART_RIB));
+
+    let mut restart = RestartState::default();
+    for peer in peers.lines() {
+        if let Ok(addr) = peer.trim().parse::<IpAddr>() {
+            restart.waiting.insert(addr, None);
+        }
+    }
+    if restart.waiting.is_empty() {
+        return;
+    }
+    let mut input = data.as_slice();
+    while let Ok((remain, (from, packet))) = record_parse(input, &[]) {
+        if let BgpPacket::Update(update) = packet {
+            for prefix in update.ipv4_update.iter() {
+                route_add(
+                    &mut bgp.ptree,
+                    *prefix,
+                    restart_route(from, update.attrs.clone()),
+                    &bgp.bestpath,
+                );
+            }
+            if let Some(mp) = update.mp_reach() {
+                let attrs = update.mp_attrs();
+                for (i, prefix) in mp.prefix.iter().enumerate() {
+                    route_add(
+                        &mut bgp.ptree6,
+                        *prefix,
+                        restart_route(from, update.mp_prefix_attrs(&attrs, i)),
+                        &bgp.bestpath,
+                    );
+                }
+            }
+            restart_stale_add(&mut restart.stale, from, &update);
+        }
+        input = remain;
+    }
+    println!("restart: restored {} peers", restart.waiting.len());
+
+    let tx = bgp.tx.clone();
+    restart.timer = Some(Timer::new(
+        Timer::second(bgp.restart_config.deferral_time as u64),
+        TimerType::Once,
+        move || {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(Message::RestartDeferral);
+            }
+        },
+    ));
+    for peer in bgp.peers.values_mut() {
+        restart_peer(&mut restart, peer);
+    }
+    bgp.restart = Some(restart);
+}
+
+// Peer saved before the restart advertises the Restart State bit, and its
+// restored routes are stale until End-of-RIB.
+pub fn restart_peer(restart: &mut RestartState, peer: &mut Peer) {
+    if restart.waiting.contains_key(&peer.address) {
+        peer.restarting = true;
+    }
+    if let Some(stale) = restart.stale.remove(&peer.address) {
+        peer.stale_rib = stale;
+    }
+}
+
+pub fn restart_established(restart: &mut Option<RestartState>, peer: &Peer) {
+    if let Some(restart) = restart.as_mut() {
+        if let Some(waiting) = restart.waiting.get_mut(&peer.address) {
+            let afis = peer
+                .afi_safi
+                .0
+                .iter()
+                .map(|afi_safi| afi_safi.afi.clone())
+                .collect();
+            *waiting = Some(afis);
+        }
+    }
+}
+
+// End-of-RIB from the peer. Deferral ends when all of the peers have sent
+// End-of-RIB for all of the address families.
+pub fn restart_eor(bgp: &mut Bgp, addr: IpAddr, afi: Afi) {
+    let restart = match bgp.restart.as_mut() {
+        Some(restart) => restart,
+        None => return,
+    };
+    if let Some(Some(afis)) = restart.waiting.get_mut(&addr) {
+        afis.retain(|x| *x != afi);
+        if afis.is_empty() {
+            restart.waiting.remove(&addr);
+        }
+    }
+    if restart.waiting.is_empty() {
+        restart_complete(bgp);
+    }
+}
+
+fn restart_stale_withdraw(bgp: &mut Bgp, from: IpAddr, rib: &AdjRib) {
+    for prefix in rib.prefixes().iter() {
+        route_withdraw(&mut bgp.ptree, from, prefix, &bgp.bestpath);
+    }
+    for prefix in rib.prefixes6().iter() {
+        route_withdraw(&mut bgp.ptree6, from, prefix, &bgp.bestpath);
+    }
+}
+
+// Restored routes of the peers which have not come back are removed, then
+// best paths are advertised to all of the established peers followed by
+// End-of-RIB.
+pub fn restart_complete(bgp: &mut Bgp) {
+    let restart = match bgp.restart.take() {
+        Some(restart) => restart,
+        None => return,
+    };
+    println!("restart: deferral completed");
+    for (from, rib) in restart.stale.iter() {
+        restart_stale_withdraw(bgp, *from, rib);
+    }
+    let mut stale = Vec::new();
+    let mut ids = Vec::new();
+    for peer in bgp.peers.values_mut() {
+        peer.restarting = false;
+        if peer.state == State::Established {
+            ids.push(peer.ident);
+        } else if peer.stale_rib.count() > 0 && peer.timer.restart.is_none() {
+            stale.push((
+                peer.address,
+                std::mem::replace(&mut peer.stale_rib, AdjRib::new()),
+            ));
+        }
+    }
+    for (from, rib) in stale.iter() {
+        restart_stale_withdraw(bgp, *from, rib);
+    }
+    for id in ids.into_iter() {
+        route_sync(bgp, id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{
+        As4PathAttr, As4Segment, MpNlriAttr, NextHopAttr, OriginAttr, AS_SEQUENCE,
+    };
+    use crate::bgp::Safi;
+    use crate::rib::RibTxChannel;
+    use ipnet::{Ipv4Net, Ipv6Net};
+
+    fn restart_bgp(dir: &Path) -> Bgp {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        bgp.restart_config.enabled = true;
+        bgp.restart_config.helper_only = false;
+        bgp.restart_config.dir = Some(dir.to_path_buf());
+        bgp
+    }
+
+    fn peer_add(bgp: &mut Bgp, addr: IpAddr) -> &mut Peer {
+        let peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65002,
+            addr,
+            bgp.tx.clone(),
+            bgp.tx.clone(),
+        );
+        bgp.peers.insert(addr, peer);
+        bgp.peers.get_mut(&addr).unwrap()
+    }
+
+    #[tokio::test]
+    async fn save_restore() {
+        let dir = std::env::temp_dir().join(format!("restart-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let from: IpAddr = "10.0.0.2".parse().unwrap();
+        let prefix: Ipv4Net = "10.1.0.0/16".parse().unwrap();
+        let prefix6: Ipv6Net = "2001:db8:1::/48".parse().unwrap();
+        let mut attrs = vec![
+            Attribute::Origin(OriginAttr { origin: 0 }),
+            Attribute::As4Path(As4PathAttr {
+                segments: vec![As4Segment {
+                    typ: AS_SEQUENCE,
+                    asn: vec![65002],
+                }],
+            }),
+        ];
+        let mut attrs6 = attrs.clone();
+        attrs.push(Attribute::NextHop(NextHopAttr {
+            next_hop: [10, 0, 0, 2],
+        }));
+        attrs6.push(Attribute::MpReachNlri(MpNlriAttr {
+            next_hop: Some("2001:db8::2".parse().unwrap()),
+            link_local: None,
+            prefix: Vec::new(),
+            path_id: Vec::new(),
+            safi: Safi::Unicast,
+            label: Vec::new(),
+        }));
+
+        let mut bgp = restart_bgp(&dir);
+        let peer = peer_add(&mut bgp, from);
+        peer.state = State::Established;
+        peer.restart_time = Some(120);
+        let routes = vec![
+            restart_route(from, attrs),
+            restart_route(ROUTE_LOCAL, Vec::new()),
+        ];
+        bgp.ptree.insert(prefix, routes);
+        bgp.ptree6
+            .insert(prefix6, vec![restart_route(from, attrs6)]);
+        restart_save(&bgp);
+
+        // Routes of the saved peer are restored as stale, local ones are not
+        // saved.
+        let mut bgp = restart_bgp(&dir);
+        peer_add(&mut bgp, from);
+        restart_load(&mut bgp);
+        let routes = bgp.ptree.get(&prefix).unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].from, from);
+        assert_eq!(bgp.ptree6.get(&prefix6).unwrap()[0].from, from);
+        let peer = &bgp.peers[&from];
+        assert!(peer.restarting);
+        assert_eq!(peer.stale_rib.prefixes(), vec![prefix]);
+        assert_eq!(peer.stale_rib.prefixes6(), vec![prefix6]);
+        let restart = bgp.restart.as_ref().unwrap();
+        assert!(restart.waiting.contains_key(&from));
+        assert!(restart.timer.is_some());
+
+        // The files are consumed by the restore.
+        assert!(!dir.join(RESTART_PEERS).exists());
+        assert!(!dir.join(RESTART_RIB).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
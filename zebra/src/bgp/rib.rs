@@ -0,0 +1,345 @@
+use super::route::Route;
+use super::BGP_LOCAL_PREF;
+use std::cmp::Ordering;
+
+// Modifiers of the decision process.
+#[derive(Debug, Clone)]
+pub struct Bestpath {
+    // MED is compared between routes from different neighbor AS.
+    pub always_compare_med: bool,
+    // Routes are grouped by the neighbor AS and the best of each group is
+    // compared, so that the result does not depend on the order of arrival.
+    pub deterministic_med: bool,
+    // Router ID breaks the tie of eBGP routes, otherwise the current best
+    // path is kept (RFC 5004).
+    pub compare_router_id: bool,
+    // LOCAL_PREF of the routes without the attribute.
+    pub local_pref: u32,
+}
+
+impl Default for Bestpath {
+    fn default() -> Self {
+        Self {
+            always_compare_med: false,
+            deterministic_med: false,
+            compare_router_id: true,
+            local_pref: BGP_LOCAL_PREF,
+        }
+    }
+}
+
+// Step of the decision process which decided between two routes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BestpathStep {
+    Weight,
+    LocalPref,
+    AsPathLength,
+    Origin,
+    Med,
+    Ebgp,
+    IgpMetric,
+    CurrentBest,
+    RouterId,
+    PeerAddress,
+}
+
+impl BestpathStep {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Weight => "weight",
+            Self::LocalPref => "local-pref",
+            Self::AsPathLength => "AS path length",
+            Self::Origin => "origin",
+            Self::Med => "MED",
+            Self::Ebgp => "eBGP over iBGP",
+            Self::IgpMetric => "IGP metric",
+            Self::CurrentBest => "current best path",
+            Self::RouterId => "router-id",
+            Self::PeerAddress => "peer address",
+        }
+    }
+}
+
+// BGP decision process (RFC 4271 9.1.2). Ordering::Less means route a is
+// preferred over route b.
+pub fn rib_compare(a: &Route, b: &Route, bestpath: &Bestpath) -> Ordering {
+    rib_compare_step(a, b, bestpath).0
+}
+
+// Decision process with the step which decided the order.
+pub fn rib_compare_step(a: &Route, b: &Route, bestpath: &Bestpath) -> (Ordering, BestpathStep) {
+    let (ordering, step) = rib_compare_path_step(a, b, bestpath);
+    if ordering != Ordering::Equal {
+        return (ordering, step);
+    }
+
+    // Current best path of eBGP routes.
+    if !bestpath.compare_router_id && !a.ibgp && !b.ibgp && a.selected != b.selected {
+        return (b.selected.cmp(&a.selected), BestpathStep::CurrentBest);
+    }
+
+    // Lowest BGP identifier, then lowest peer address.
+    if a.router_id != b.router_id {
+        return (a.router_id.cmp(&b.router_id), BestpathStep::RouterId);
+    }
+    (a.from.cmp(&b.from), BestpathStep::PeerAddress)
+}
+
+fn neighbor_as(route: &Route) -> Option<u32> {
+    route.as4path().as_ref().and_then(|x| x.neighbor_as())
+}
+
+// Decision process until the IGP metric. Paths equal here are candidates of
+// multipath.
+pub fn rib_compare_path(a: &Route, b: &Route, bestpath: &Bestpath) -> Ordering {
+    rib_compare_path_step(a, b, bestpath).0
+}
+
+fn rib_compare_path_step(a: &Route, b: &Route, bestpath: &Bestpath) -> (Ordering, BestpathStep) {
+    // Weight, higher is preferred.
+    if a.weight != b.weight {
+        return (b.weight.cmp(&a.weight), BestpathStep::Weight);
+    }
+
+    // Local preference, higher is preferred.
+    let a_local_pref = a.local_pref().unwrap_or(bestpath.local_pref);
+    let b_local_pref = b.local_pref().unwrap_or(bestpath.local_pref);
+    if a_local_pref != b_local_pref {
+        return (b_local_pref.cmp(&a_local_pref), BestpathStep::LocalPref);
+    }
+
+    // AS path length, shorter is preferred.
+    let a_aspath = a.as4path();
+    let b_aspath = b.as4path();
+    let a_length = a_aspath.as_ref().map(|x| x.length()).unwrap_or(0);
+    let b_length = b_aspath.as_ref().map(|x| x.length()).unwrap_or(0);
+    if a_length != b_length {
+        return (a_length.cmp(&b_length), BestpathStep::AsPathLength);
+    }
+
+    // Origin, IGP < EGP < INCOMPLETE.
+    if a.origin_type() != b.origin_type() {
+        return (a.origin_type().cmp(&b.origin_type()), BestpathStep::Origin);
+    }
+
+    // MED is compared only between routes from the same neighbor AS unless
+    // always-compare-med is set.
+    if bestpath.always_compare_med || neighbor_as(a) == neighbor_as(b) {
+        let a_med = a.med().unwrap_or(0);
+        let b_med = b.med().unwrap_or(0);
+        if a_med != b_med {
+            return (a_med.cmp(&b_med), BestpathStep::Med);
+        }
+    }
+
+    // eBGP is preferred over iBGP.
+    if a.ibgp != b.ibgp {
+        return (a.ibgp.cmp(&b.ibgp), BestpathStep::Ebgp);
+    }
+
+    // IGP metric to the nexthop, lower is preferred.
+    (a.metric.cmp(&b.metric), BestpathStep::IgpMetric)
+}
+
+// Reason why the route is not the best path, the step where the best path
+// wins over it. With deterministic-med the route may have lost in the group
+// of its neighbor AS, but the direct comparison is shown.
+pub fn rib_reason(route: &Route, best: Option<&Route>, bestpath: &Bestpath) -> Option<String> {
+    if route.selected {
+        return None;
+    }
+    if !route.resolved {
+        return Some(String::from("next hop unreachable"));
+    }
+    let best = best?;
+    let (_, step) = rib_compare_step(best, route, bestpath);
+    Some(format!("lost to {}", step.as_str()))
+}
+
+fn rib_best<'a, I>(routes: &[Route], candidates: I, bestpath: &Bestpath) -> Option<usize>
+where
+    I: Iterator<Item = &'a usize>,
+{
+    let mut best: Option<usize> = None;
+    for index in candidates {
+        best = match best {
+            Some(current)
+                if rib_compare(&routes[current], &routes[*index], bestpath)
+                    != Ordering::Greater =>
+            {
+                Some(current)
+            }
+            _ => Some(*index),
+        };
+    }
+    best
+}
+
+// Run decision process over the candidate routes of a prefix which next hop
+// is resolved, and mark the best path as selected. Returns index of the selected route.
+pub fn rib_select(routes: &mut [Route], bestpath: &Bestpath) -> Option<usize> {
+    let candidates: Vec<usize> = (0..routes.len()).filter(|i| routes[*i].resolved).collect();
+    let best = if bestpath.deterministic_med {
+        let mut groups: Vec<(Option<u32>, Vec<usize>)> = Vec::new();
+        for index in candidates.into_iter() {
+            let asn = neighbor_as(&routes[index]);
+            match groups.iter_mut().find(|(x, _)| *x == asn) {
+                Some((_, group)) => group.push(index),
+                None => groups.push((asn, vec![index])),
+            }
+        }
+        let winners: Vec<usize> = groups
+            .iter()
+            .filter_map(|(_, group)| rib_best(routes, group.iter(), bestpath))
+            .collect();
+        rib_best(routes, winners.iter(), bestpath)
+    } else {
+        rib_best(routes, candidates.iter(), bestpath)
+    };
+    for (index, route) in routes.iter_mut().enumerate() {
+        route.selected = Some(index) == best;
+    }
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{
+        As4PathAttr, As4Segment, Attribute, LocalPrefAttr, MedAttr, OriginAttr, AS_SEQUENCE,
+    };
+    use crate::bgp::rpki::RpkiState;
+    use std::net::Ipv4Addr;
+
+    fn route(from: [u8; 4], attrs: Vec<Attribute>, ibgp: bool) -> Route {
+        Route {
+            from: Ipv4Addr::from(from).into(),
+            router_id: Ipv4Addr::from(from),
+            attrs,
+            ibgp,
+            weight: 0,
+            metric: 0,
+            selected: false,
+            resolved: true,
+            path_id: 0,
+            local_path_id: 0,
+            local_label: None,
+            multipath: false,
+            rpki: RpkiState::NotFound,
+        }
+    }
+
+    #[test]
+    fn select_local_pref() {
+        let mut routes = vec![
+            route([10, 0, 0, 1], vec![], false),
+            route(
+                [10, 0, 0, 2],
+                vec![Attribute::LocalPref(LocalPrefAttr { local_pref: 200 })],
+                true,
+            ),
+        ];
+        assert_eq!(rib_select(&mut routes, &Bestpath::default()), Some(1));
+        assert!(!routes[0].selected);
+        assert!(routes[1].selected);
+    }
+
+    #[test]
+    fn select_origin_med_ebgp() {
+        let mut routes = vec![
+            route(
+                [10, 0, 0, 1],
+                vec![Attribute::Origin(OriginAttr { origin: 2 })],
+                false,
+            ),
+            route(
+                [10, 0, 0, 2],
+                vec![
+                    Attribute::Origin(OriginAttr { origin: 0 }),
+                    Attribute::Med(MedAttr { med: 10 }),
+                ],
+                false,
+            ),
+            route(
+                [10, 0, 0, 3],
+                vec![Attribute::Origin(OriginAttr { origin: 0 })],
+                true,
+            ),
+            route(
+                [10, 0, 0, 4],
+                vec![Attribute::Origin(OriginAttr { origin: 0 })],
+                false,
+            ),
+        ];
+        assert_eq!(rib_select(&mut routes, &Bestpath::default()), Some(3));
+    }
+
+    fn route_med(from: [u8; 4], asn: u32, med: u32) -> Route {
+        let aspath = As4PathAttr {
+            segments: vec![As4Segment {
+                typ: AS_SEQUENCE,
+                asn: vec![asn],
+            }],
+        };
+        let attrs = vec![Attribute::As4Path(aspath), Attribute::Med(MedAttr { med })];
+        route(from, attrs, false)
+    }
+
+    #[test]
+    fn select_bestpath_knobs() {
+        let mut routes = vec![
+            route_med([10, 0, 0, 1], 65001, 50),
+            route_med([10, 0, 0, 2], 65002, 10),
+            route_med([10, 0, 0, 3], 65001, 5),
+        ];
+        let mut bestpath = Bestpath::default();
+        assert_eq!(rib_select(&mut routes, &bestpath), Some(2));
+
+        // Best of 65001 loses to the one of 65002 by router ID.
+        bestpath.deterministic_med = true;
+        assert_eq!(rib_select(&mut routes, &bestpath), Some(1));
+
+        // Current best path is kept when router ID is not compared.
+        bestpath.deterministic_med = false;
+        bestpath.compare_router_id = false;
+        routes.swap(0, 2);
+        assert_eq!(rib_select(&mut routes, &bestpath), Some(1));
+
+        bestpath.always_compare_med = true;
+        assert_eq!(rib_select(&mut routes, &bestpath), Some(0));
+
+        routes[1]
+            .attrs
+            .push(Attribute::LocalPref(LocalPrefAttr { local_pref: 150 }));
+        assert_eq!(rib_select(&mut routes, &bestpath), Some(1));
+        bestpath.local_pref = 200;
+        assert_eq!(rib_select(&mut routes, &bestpath), Some(0));
+    }
+
+    #[test]
+    fn reason_of_non_best() {
+        let mut routes = vec![
+            route_med([10, 0, 0, 1], 65001, 50),
+            route_med([10, 0, 0, 2], 65001, 10),
+            route_med([10, 0, 0, 3], 65002, 10),
+            route(
+                [10, 0, 0, 4],
+                vec![Attribute::LocalPref(LocalPrefAttr { local_pref: 50 })],
+                true,
+            ),
+        ];
+        routes[3].resolved = false;
+        let bestpath = Bestpath::default();
+        let best = rib_select(&mut routes, &bestpath);
+        assert_eq!(best, Some(1));
+        let best = best.map(|index| &routes[index]);
+        let reasons: Vec<Option<String>> = routes
+            .iter()
+            .map(|route| rib_reason(route, best, &bestpath))
+            .collect();
+        assert_eq!(reasons[0].as_deref(), Some("lost to MED"));
+        assert_eq!(reasons[1], None);
+        assert_eq!(reasons[2].as_deref(), Some("lost to router-id"));
+        assert_eq!(reasons[3].as_deref(), Some("next hop unreachable"));
+    }
+}
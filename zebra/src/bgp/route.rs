@@ -1,8 +1,42 @@
 use super::{
-    packet::{Attrs, UpdatePacket},
-    peer::{ConfigRef, Peer},
+    adj_rib::{path_id, AdjPaths, AdjRib, StaleIndex},
+    family::FamilyContext,
+    gshut::{gshut_export, gshut_import, maintenance_import},
+    label::label_update,
+    localas::{local_as_import, local_as_prepend},
+    multipath::multipath_update,
+    network::network_apply,
+    nexthop::{nexthop_resolve4, nexthop_resolve6, NexthopConfig},
+    packet::{
+        attrs_as2, label_stack_decode, label_stack_encode, labeled_ipv4_prefix_encode,
+        parse_labeled_ipv4_nlris, As4PathAttr, AsPathAttr, Attribute, Attrs, BgpHeader, BgpType,
+        ClusterListAttr, CommunityAttr, ExtendedComAttr, LargeComAttr, LocalPrefAttr, MpNlriAttr,
+        MpRawAttr, NextHopAttr, OriginatorIdAttr, UpdatePacket, BGP_HEADER_LEN, BGP_PACKET_LEN,
+        LABEL_IMPLICIT_NULL,
+    },
+    peer::{
+        add_path_receive, peer_send_update, peer_start_coalesce_timer, peer_start_route_adv_timer,
+        peer_start_stale_timer, ConfigRef, Peer, PrivateAs, State,
+    },
+    ptree::{Prefix, PrefixTree},
+    redist::redist_source_apply,
+    rib::{rib_compare, rib_select, Bestpath},
+    rpki::{Rpki, RpkiState},
+    vpn::vpn_sync,
+    watch::origin_watch_check,
+    Afi, AfiSafi, Bgp, Safi,
 };
-use std::net::Ipv4Addr;
+use crate::policy::{
+    ext_community, large_community, large_community_add, Policy, PolicyAction, PolicyDirection,
+};
+use crate::rib::api::RedistSource;
+use bytes::{BufMut, BytesMut};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// MP_UNREACH_NLRI attribute header with extended length, AFI and SAFI.
+const MP_UNREACH_HEADER_LEN: usize = 7;
 
 // pub enum RouteFrom {
 //     Peer,
@@ -13,21 +47,2189 @@ use std::net::Ipv4Addr;
 // }
 
 pub struct Route {
-    pub from: Ipv4Addr,
+    pub from: IpAddr,
+    pub router_id: Ipv4Addr,
     pub attrs: Attrs,
     pub ibgp: bool,
+    pub weight: u32,
+    pub metric: u32,
     pub selected: bool,
+    // Next hop is resolved, otherwise the route is not eligible for best
+    // path selection.
+    pub resolved: bool,
+    // Path Identifier received from the peer with ADD-PATH, otherwise 0.
+    pub path_id: u32,
+    // Path Identifier advertised to ADD-PATH peers.
+    pub local_path_id: u32,
+    // Label advertised with labeled unicast when the next hop is set to self,
+    // the label allocated for the prefix or configured for the network.
+    pub local_label: Option<u32>,
+    // Path is in the ECMP group of the best path.
+    pub multipath: bool,
+    // Origin validation state against the VRPs of the RPKI caches.
+    pub rpki: RpkiState,
+}
+
+impl Route {
+    pub fn origin_type(&self) -> u8 {
+        for attr in self.attrs.iter() {
+            if let Attribute::Origin(origin) = attr {
+                return origin.origin;
+            }
+        }
+        2
+    }
+
+    pub fn origin(&self) -> char {
+        for attr in self.attrs.iter() {
+            if let Attribute::Origin(origin) = attr {
+                return match origin.origin {
+                    0 => 'i',
+                    1 => 'e',
+                    _ => '?',
+                };
+            }
+        }
+        '?'
+    }
+
+    pub fn nexthop(&self) -> Option<Ipv4Addr> {
+        for attr in self.attrs.iter() {
+            if let Attribute::NextHop(nhop) = attr {
+                return Some(Ipv4Addr::from(nhop.next_hop));
+            }
+        }
+        None
+    }
+
+    pub fn nexthop6(&self) -> Option<Ipv6Addr> {
+        for attr in self.attrs.iter() {
+            if let Attribute::MpReachNlri(mp) = attr {
+                return mp.next_hop;
+            }
+        }
+        None
+    }
+
+    // Top label received with labeled unicast.
+    pub fn label(&self) -> Option<u32> {
+        self.labels().first().copied()
+    }
+
+    // Label stack received with labeled unicast. It is kept in MP_REACH_NLRI
+    // of the route, and the NLRI field of the raw one for IPv4.
+    pub fn labels(&self) -> Vec<u32> {
+        for attr in self.attrs.iter() {
+            match attr {
+                Attribute::MpReachNlri(mp) => return mp.label.first().cloned().unwrap_or_default(),
+                Attribute::MpReachRaw(mp) if mp.safi == Safi::MplsLabel => {
+                    return label_stack_decode(&mp.nlri);
+                }
+                _ => {}
+            }
+        }
+        Vec::new()
+    }
+
+    pub fn med(&self) -> Option<u32> {
+        for attr in self.attrs.iter() {
+            if let Attribute::Med(med) = attr {
+                return Some(med.med);
+            }
+        }
+        None
+    }
+
+    pub fn local_pref(&self) -> Option<u32> {
+        for attr in self.attrs.iter() {
+            if let Attribute::LocalPref(local_pref) = attr {
+                return Some(local_pref.local_pref);
+            }
+        }
+        None
+    }
+
+    pub fn ext_community(&self) -> Option<&ExtendedComAttr> {
+        ext_community(&self.attrs)
+    }
+
+    pub fn large_community(&self) -> Option<&LargeComAttr> {
+        large_community(&self.attrs)
+    }
+
+    pub fn community(&self) -> Option<&CommunityAttr> {
+        for attr in self.attrs.iter() {
+            if let Attribute::Community(community) = attr {
+                return Some(community);
+            }
+        }
+        None
+    }
+
+    pub fn originator_id(&self) -> Option<Ipv4Addr> {
+        for attr in self.attrs.iter() {
+            if let Attribute::OriginatorId(originator) = attr {
+                return Some(Ipv4Addr::from(originator.id));
+            }
+        }
+        None
+    }
+
+    pub fn cluster_list(&self) -> Option<&ClusterListAttr> {
+        for attr in self.attrs.iter() {
+            if let Attribute::ClusterList(cluster_list) = attr {
+                return Some(cluster_list);
+            }
+        }
+        None
+    }
+
+    pub fn as4path(&self) -> Option<As4PathAttr> {
+        for attr in self.attrs.iter() {
+            match attr {
+                Attribute::AsPath(aspath) => return Some(As4PathAttr::from(aspath)),
+                Attribute::As4Path(aspath) => return Some(aspath.clone()),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    pub fn aspath(&self) -> String {
+        for attr in self.attrs.iter() {
+            match attr {
+                Attribute::AsPath(aspath) => return aspath.to_string(),
+                Attribute::As4Path(aspath) => return aspath.to_string(),
+                _ => {}
+            }
+        }
+        String::new()
+    }
+}
+
+// AS path is rendered once for the routes which share it, while walking the
+// RIB for show commands.
+#[derive(Default)]
+pub struct AsPathCache<'a> {
+    aspath: HashMap<&'a AsPathAttr, String>,
+    as4path: HashMap<&'a As4PathAttr, String>,
+}
+
+impl<'a> AsPathCache<'a> {
+    pub fn get(&mut self, route: &'a Route) -> &str {
+        for attr in route.attrs.iter() {
+            match attr {
+                Attribute::AsPath(aspath) => {
+                    return self
+                        .aspath
+                        .entry(aspath)
+                        .or_insert_with(|| aspath.to_string());
+                }
+                Attribute::As4Path(aspath) => {
+                    return self
+                        .as4path
+                        .entry(aspath)
+                        .or_insert_with(|| aspath.to_string());
+                }
+                _ => {}
+            }
+        }
+        ""
+    }
 }
 
-pub fn route_from_peer(peer: &mut Peer, packet: UpdatePacket, bgp: &mut ConfigRef) {
-    for ipv4 in packet.ipv4_update.iter() {
-        let route = Route {
+fn route_new(peer: &Peer, path_id: u32, mut attrs: Attrs) -> Route {
+    gshut_import(&mut attrs);
+    maintenance_import(peer, &mut attrs);
+    local_as_import(peer, &mut attrs);
+    large_community_add(&mut attrs, &peer.config.large_community_in);
+    Route {
+        from: peer.address,
+        router_id: peer.remote_id,
+        attrs,
+        ibgp: peer.peer_as == peer.local_as,
+        weight: 0,
+        metric: 0,
+        selected: false,
+        resolved: true,
+        path_id,
+        local_path_id: 0,
+        local_label: None,
+        multipath: false,
+        rpki: RpkiState::NotFound,
+    }
+}
+
+// Route reflected back to us carries our router ID as ORIGINATOR_ID or our
+// cluster ID in CLUSTER_LIST (RFC 4456 8).
+fn route_reflect_loop(peer: &Peer, attrs: &Attrs) -> bool {
+    let cluster_id = u32::from(peer.cluster_id());
+    attrs.iter().any(|attr| match attr {
+        Attribute::OriginatorId(originator) => originator.id == u32::from(peer.router_id),
+        Attribute::ClusterList(cluster_list) => cluster_list.contains(cluster_id),
+        _ => false,
+    })
+}
+
+// Route from outside of the confederation must not carry the confederation
+// segments, and the route looped in the confederation has the local member AS
+// in them (RFC 5065 5).
+fn route_confed_loop(peer: &Peer, aspath: &As4PathAttr) -> bool {
+    if peer.peer_as != peer.local_as && !peer.confed_member() && aspath.has_confed() {
+        return true;
+    }
+    peer.confed.id.is_some() && aspath.confed_contains(peer.local_as)
+}
+
+// AS path containing our AS more than allowas-in times is a loop (RFC 4271
+// 9.1.2). Confederation identifier is our AS as well.
+fn route_as_loop(peer: &Peer, aspath: &As4PathAttr) -> bool {
+    let mut count = aspath.count_as(peer.local_as);
+    if let Some(id) = peer.confed.id {
+        count += aspath.count_as(id);
+    }
+    count > peer.config.allowas_in as usize
+}
+
+fn route_loop(peer: &Peer, attrs: &Attrs) -> bool {
+    if route_reflect_loop(peer, attrs) {
+        return true;
+    }
+    let aspath = attrs.iter().find_map(|attr| match attr {
+        Attribute::AsPath(aspath) => Some(As4PathAttr::from(aspath)),
+        Attribute::As4Path(aspath) => Some(aspath.clone()),
+        _ => None,
+    });
+    match aspath {
+        Some(aspath) => route_confed_loop(peer, &aspath) || route_as_loop(peer, &aspath),
+        None => false,
+    }
+}
+
+// Inbound policy. Denied route is kept in Adj-RIB-In but not in Loc-RIB.
+// Inbound policy of the route. Attributes set by the inbound route map are
+// returned when the route is permitted.
+fn route_import(
+    peer: &Peer,
+    prefix: &Ipv4Net,
+    attrs: &Attrs,
+    rpki: RpkiState,
+    policy: &Policy,
+) -> Option<Attrs> {
+    if route_loop(peer, attrs) {
+        return None;
+    }
+    if let Some(name) = &peer.config.large_community_list_in {
+        if policy.large_community_list_apply(name, large_community(attrs)) != PolicyAction::Permit {
+            return None;
+        }
+    }
+    if let Some(name) = &peer.config.ext_community_list_in {
+        if policy.ext_community_list_apply(name, ext_community(attrs)) != PolicyAction::Permit {
+            return None;
+        }
+    }
+    if let Some(name) = &peer.config.prefix_list_in {
+        if policy.prefix_list_apply(name, prefix) != PolicyAction::Permit {
+            return None;
+        }
+    }
+    let mut attrs = attrs.clone();
+    if let Some(name) = &peer.config.route_map_in {
+        let prefix = IpNet::V4(*prefix);
+        if policy.route_map_import(name, &prefix, rpki, &mut attrs) != PolicyAction::Permit {
+            return None;
+        }
+    }
+    Some(attrs)
+}
+
+pub fn route_from_peer(peer: &mut Peer, packet: &UpdatePacket, bgp: &mut ConfigRef) {
+    peer.adj_rib_in.update(packet);
+    peer.refresh_stale.update(packet);
+    let prefixes = packet.ipv4_update.iter().map(|x| IpNet::V4(*x));
+    origin_watch_check(peer, prefixes, &packet.attrs, bgp);
+    for (i, prefix) in packet.ipv4_withdraw.iter().enumerate() {
+        let id = path_id(&packet.ipv4_withdraw_id, i);
+        route_withdraw_path(bgp.ptree, peer.address, id, prefix, bgp.bestpath);
+        bgp.changed.push(*prefix);
+    }
+    for (i, prefix) in packet.ipv4_update.iter().enumerate() {
+        let id = path_id(&packet.ipv4_update_id, i);
+        route_update4(peer, prefix, id, &packet.attrs, bgp);
+    }
+    // IPv6 unicast routes in MP_REACH_NLRI and MP_UNREACH_NLRI. Prefix lists
+    // and the inbound route map are IPv4 only, so no inbound policy is
+    // applied.
+    if let Some(mp) = packet.mp_unreach() {
+        for (i, prefix) in mp.prefix.iter().enumerate() {
+            route_withdraw_path(
+                bgp.ptree6,
+                peer.address,
+                path_id(&mp.path_id, i),
+                prefix,
+                bgp.bestpath,
+            );
+            bgp.changed6.push(*prefix);
+        }
+    }
+    if let Some(mp) = packet.mp_reach() {
+        let attrs = packet.mp_attrs();
+        let prefixes = mp.prefix.iter().map(|x| IpNet::V6(*x));
+        origin_watch_check(peer, prefixes, &attrs, bgp);
+        let looped = route_loop(peer, &attrs);
+        for (i, prefix) in mp.prefix.iter().enumerate() {
+            if looped {
+                route_withdraw_path(
+                    bgp.ptree6,
+                    peer.address,
+                    path_id(&mp.path_id, i),
+                    prefix,
+                    bgp.bestpath,
+                );
+                bgp.changed6.push(*prefix);
+                continue;
+            }
+            let attrs = packet.mp_prefix_attrs(&attrs, i);
+            let rpki = bgp.rpki.state(&IpNet::V6(*prefix), &attrs, peer.local_as);
+            let mut route = route_new(peer, path_id(&mp.path_id, i), attrs);
+            route.rpki = rpki;
+            route.resolved = nexthop_resolve6(bgp.ptree6, prefix, &route, bgp.nexthop);
+            route_add(bgp.ptree6, *prefix, route, bgp.bestpath);
+            bgp.changed6.push(*prefix);
+        }
+    }
+    route_family_update(peer, packet, bgp);
+}
+
+// IPv4 route from the peer after the inbound policy.
+fn route_update4(peer: &Peer, prefix: &Ipv4Net, id: u32, attrs: &Attrs, bgp: &mut ConfigRef) {
+    let rpki = bgp.rpki.state(&IpNet::V4(*prefix), attrs, peer.local_as);
+    if let Some(attrs) = route_import(peer, prefix, attrs, rpki, bgp.policy) {
+        let mut route = route_new(peer, id, attrs);
+        route.rpki = rpki;
+        route.resolved = nexthop_resolve4(bgp.ptree, prefix, &route, bgp.nexthop, bgp.policy);
+        route_add(bgp.ptree, *prefix, route, bgp.bestpath);
+    } else {
+        route_withdraw_path(bgp.ptree, peer.address, id, prefix, bgp.bestpath);
+    }
+    bgp.changed.push(*prefix);
+}
+
+// Labeled IPv4 unicast routes are IPv4 routes with the label stack. Next hop
+// of MP_REACH_NLRI is kept as NEXT_HOP, and the label stack of the prefix as
+// the NLRI field of the raw MP_REACH_NLRI of the route.
+fn route_labeled_update(
+    peer: &Peer,
+    packet: &UpdatePacket,
+    mp: &MpRawAttr,
+    reach: bool,
+    bgp: &mut ConfigRef,
+) -> Option<()> {
+    let add_path = add_path_receive(&peer.config).contains(&Afi::IP);
+    let nlris = parse_labeled_ipv4_nlris(&mp.nlri, add_path)?;
+    if !reach {
+        for (prefix, id, _) in nlris.into_iter() {
+            route_withdraw_path(bgp.ptree, peer.address, id, &prefix, bgp.bestpath);
+            bgp.changed.push(prefix);
+        }
+        return Some(());
+    }
+    let next_hop: [u8; 4] = mp.next_hop.as_slice().try_into().ok()?;
+    let mut attrs: Attrs = packet
+        .attrs
+        .iter()
+        .filter(|attr| !matches!(attr, Attribute::MpReachRaw(_) | Attribute::NextHop(_)))
+        .cloned()
+        .collect();
+    attrs.push(Attribute::NextHop(NextHopAttr { next_hop }));
+    for (prefix, id, labels) in nlris.into_iter() {
+        let mut attrs = attrs.clone();
+        let mut stack = BytesMut::new();
+        label_stack_encode(&mut stack, &labels);
+        attrs.push(Attribute::MpReachRaw(MpRawAttr {
+            afi: Afi::IP,
+            safi: Safi::MplsLabel,
+            next_hop: Vec::new(),
+            nlri: stack.to_vec(),
+        }));
+        route_update4(peer, &prefix, id, &attrs, bgp);
+    }
+    Some(())
+}
+
+// NLRI of VPN and the registered address families negotiated with the peer.
+fn route_family_update(peer: &Peer, packet: &UpdatePacket, bgp: &mut ConfigRef) {
+    for attr in packet.attrs.iter() {
+        let (mp, reach) = match attr {
+            Attribute::MpReachRaw(mp) => (mp, true),
+            Attribute::MpUnreachRaw(mp) => (mp, false),
+            _ => continue,
+        };
+        let afi_safi = AfiSafi::new(mp.afi.clone(), mp.safi.clone());
+        if !peer.afi_safi.has(&afi_safi) {
+            continue;
+        }
+        if afi_safi == AfiSafi::new(Afi::IP, Safi::MplsLabel) {
+            if route_labeled_update(peer, packet, mp, reach, bgp).is_none() {
+                println!("Ignore labeled unicast NLRI from {}", peer.address);
+            }
+            continue;
+        }
+        let ctx = FamilyContext {
             from: peer.address,
-            attrs: packet.attrs.clone(),
+            peer_as: peer.peer_as,
+            local_as: peer.local_as,
+            ptree: bgp.ptree,
+        };
+        let result = match (reach, mp.safi == Safi::MplsVpn) {
+            (true, true) => bgp.vpn.reach(peer.address, mp, &packet.attrs),
+            (false, true) => bgp.vpn.unreach(peer.address, mp),
+            (true, false) => bgp.families.reach(&ctx, mp, &packet.attrs),
+            (false, false) => bgp.families.unreach(peer.address, mp),
+        };
+        if result.is_none() {
+            println!(
+                "Ignore NLRI of AFI {} SAFI {} from {}",
+                mp.afi.0, mp.safi.0, peer.address
+            );
+        }
+    }
+}
+
+// Implicit withdraw of the previous route with the same Path Identifier from
+// the same peer. The local Path Identifier is kept across the replacement,
+// otherwise the lowest unused one is assigned.
+pub fn route_add<P: Prefix>(
+    ptree: &mut PrefixTree<P, Vec<Route>>,
+    prefix: P,
+    mut route: Route,
+    bestpath: &Bestpath,
+) {
+    let routes = ptree.get_or_insert_with(prefix, Vec::new);
+    let same = |x: &Route| x.from == route.from && x.path_id == route.path_id;
+    route.local_path_id = match routes.iter().find(|&x| same(x)) {
+        Some(prev) => prev.local_path_id,
+        None => (1..)
+            .find(|id| routes.iter().all(|x| x.local_path_id != *id))
+            .unwrap_or(1),
+    };
+    routes.retain(|x| !same(x));
+    routes.push(route);
+    rib_select(routes, bestpath);
+}
+
+pub fn route_withdraw_path<P: Prefix>(
+    ptree: &mut PrefixTree<P, Vec<Route>>,
+    from: IpAddr,
+    path_id: u32,
+    prefix: &P,
+    bestpath: &Bestpath,
+) {
+    if let Some(routes) = ptree.get_mut(prefix) {
+        routes.retain(|route| route.from != from || route.path_id != path_id);
+        if routes.is_empty() {
+            ptree.remove(prefix);
+        } else {
+            rib_select(routes, bestpath);
+        }
+    }
+}
+
+// Withdraw all of the paths of the prefix from the source.
+pub fn route_withdraw<P: Prefix>(
+    ptree: &mut PrefixTree<P, Vec<Route>>,
+    from: IpAddr,
+    prefix: &P,
+    bestpath: &Bestpath,
+) {
+    if let Some(routes) = ptree.get_mut(prefix) {
+        routes.retain(|route| route.from != from);
+        if routes.is_empty() {
+            ptree.remove(prefix);
+        } else {
+            rib_select(routes, bestpath);
+        }
+    }
+}
+
+// Withdraw all of routes received from the peer.
+pub fn route_clean(peer: &mut Peer, bgp: &mut ConfigRef) {
+    for prefix in peer.adj_rib_in.prefixes().into_iter() {
+        route_withdraw(bgp.ptree, peer.address, &prefix, bgp.bestpath);
+        bgp.changed.push(prefix);
+    }
+    for prefix in peer.adj_rib_in.prefixes6().into_iter() {
+        route_withdraw(bgp.ptree6, peer.address, &prefix, bgp.bestpath);
+        bgp.changed6.push(prefix);
+    }
+    bgp.families.peer_clear(peer.address);
+    bgp.vpn.peer_clear(peer.address);
+    peer.adj_rib_in.clear();
+    peer.adj_rib_out.clear();
+    peer.adv_queue.clear();
+    peer.adv_queue6.clear();
+    peer.refresh_stale = StaleIndex::default();
+    route_stale_flush(peer, bgp, &Afi::IP);
+    route_stale_flush(peer, bgp, &Afi::IP6);
+    peer.timer.restart = None;
+    peer.timer.stale = None;
+    peer.timer.refresh_stale = None;
+}
+
+// Routes of the restarting peer are kept in Loc-RIB and remembered as stale
+// until the peer advertises them again (RFC 4724 4.2).
+pub fn route_stale(peer: &mut Peer) {
+    let rib = std::mem::replace(&mut peer.adj_rib_in, AdjRib::new());
+    for (prefix, id, attrs) in rib.paths() {
+        peer.stale_rib.insert(*prefix, id, attrs.clone());
+    }
+    for (prefix, id, attrs) in rib.paths6() {
+        peer.stale_rib.insert6(*prefix, id, attrs.clone());
+    }
+    peer.adj_rib_out.clear();
+    peer.adv_queue.clear();
+    peer.adv_queue6.clear();
+}
+
+// Paths of the address family received before BoRR of Enhanced Route
+// Refresh are stale until EoRR (RFC 7313 4.2). They are kept in Adj-RIB-In
+// and Loc-RIB meanwhile.
+pub fn route_refresh_stale(peer: &mut Peer, afi: &Afi) {
+    if *afi == Afi::IP {
+        peer.refresh_stale.v4 = peer
+            .adj_rib_in
+            .paths()
+            .map(|(prefix, id, _)| (*prefix, id))
+            .collect();
+    } else if *afi == Afi::IP6 {
+        peer.refresh_stale.v6 = peer
+            .adj_rib_in
+            .paths6()
+            .map(|(prefix, id, _)| (*prefix, id))
+            .collect();
+    }
+}
+
+// Withdraw the paths of the address family which have not been advertised
+// again since BoRR.
+pub fn route_refresh_flush(peer: &mut Peer, bgp: &mut ConfigRef, afi: &Afi) {
+    if *afi == Afi::IP {
+        for (prefix, id) in std::mem::take(&mut peer.refresh_stale.v4).into_iter() {
+            if peer.adj_rib_in.remove(&prefix, id) {
+                route_withdraw_path(bgp.ptree, peer.address, id, &prefix, bgp.bestpath);
+                bgp.changed.push(prefix);
+            }
+        }
+    } else if *afi == Afi::IP6 {
+        for (prefix, id) in std::mem::take(&mut peer.refresh_stale.v6).into_iter() {
+            if peer.adj_rib_in.remove6(&prefix, id) {
+                route_withdraw_path(bgp.ptree6, peer.address, id, &prefix, bgp.bestpath);
+                bgp.changed6.push(prefix);
+            }
+        }
+    }
+}
+
+// Withdraw stale routes of the address family which have not been
+// advertised again.
+pub fn route_stale_flush(peer: &mut Peer, bgp: &mut ConfigRef, afi: &Afi) {
+    if *afi == Afi::IP {
+        for (prefix, id, _) in peer.stale_rib.paths() {
+            let fresh = peer.adj_rib_in.v4.get(prefix);
+            if !fresh.map(|paths| paths.contains_key(&id)).unwrap_or(false) {
+                route_withdraw_path(bgp.ptree, peer.address, id, prefix, bgp.bestpath);
+                bgp.changed.push(*prefix);
+            }
+        }
+        peer.stale_rib.v4 = PrefixTree::new();
+    } else if *afi == Afi::IP6 {
+        for (prefix, id, _) in peer.stale_rib.paths6() {
+            let fresh = peer.adj_rib_in.v6.get(prefix);
+            if !fresh.map(|paths| paths.contains_key(&id)).unwrap_or(false) {
+                route_withdraw_path(bgp.ptree6, peer.address, id, prefix, bgp.bestpath);
+                bgp.changed6.push(*prefix);
+            }
+        }
+        peer.stale_rib.v6 = PrefixTree::new();
+    }
+}
+
+// Adj-RIB-In of the peer restored from disk goes through the inbound policy
+// as if it was received again. The paths are stale until the peer advertises
+// them again and sends End-of-RIB.
+pub fn route_restore(bgp: &mut Bgp, addr: IpAddr, rib: AdjRib) -> Option<()> {
+    let peer = bgp.peers.get_mut(&addr)?;
+    peer.adj_rib_in = rib;
+    let changed = route_reeval_in(
+        peer,
+        &mut bgp.ptree,
+        &bgp.policy,
+        &bgp.rpki,
+        &bgp.nexthop,
+        &bgp.bestpath,
+    );
+    let mut changed6 = Vec::new();
+    for (prefix, id, attrs) in peer.adj_rib_in.paths6() {
+        if route_loop(peer, attrs) {
+            continue;
+        }
+        let rpki = bgp.rpki.state(&IpNet::V6(*prefix), attrs, peer.local_as);
+        let mut route = route_new(peer, id, attrs.clone());
+        route.rpki = rpki;
+        route.resolved = nexthop_resolve6(&bgp.ptree6, prefix, &route, &bgp.nexthop);
+        route_add(&mut bgp.ptree6, *prefix, route, &bgp.bestpath);
+        changed6.push(*prefix);
+    }
+    route_stale(peer);
+    peer.timer.stale = Some(peer_start_stale_timer(peer));
+    route_advertise(bgp, &changed, &changed6);
+    Some(())
+}
+
+// Route is not advertised back to the peer it was learned from, and route
+// learned from iBGP is not advertised to iBGP peer unless it is reflected
+// from or to a route reflector client (RFC 4456 6).
+fn route_shareable(peer: &Peer, route: &Route) -> bool {
+    let reflect = peer.rr_clients.contains(&route.from) || peer.config.route_reflector_client;
+    route.from != peer.address && !(route.ibgp && peer.peer_as == peer.local_as && !reflect)
+}
+
+// Whether the route is advertised to the peer.
+fn route_exportable(peer: &Peer, prefix: &Ipv4Net, route: &Route, policy: &Policy) -> bool {
+    if !route_shareable(peer, route) {
+        return false;
+    }
+    if let Some(name) = &peer.config.large_community_list_out {
+        if policy.large_community_list_apply(name, route.large_community()) != PolicyAction::Permit
+        {
+            return false;
+        }
+    }
+    if let Some(name) = &peer.config.ext_community_list_out {
+        if policy.ext_community_list_apply(name, route.ext_community()) != PolicyAction::Permit {
+            return false;
+        }
+    }
+    if let Some(orf) = &peer.orf {
+        if orf.apply(prefix) != PolicyAction::Permit {
+            return false;
+        }
+    }
+    match &peer.config.prefix_list_out {
+        Some(name) => policy.prefix_list_apply(name, prefix) == PolicyAction::Permit,
+        None => true,
+    }
+}
+
+// Routes are advertised with labels when labeled unicast is negotiated.
+fn route_labeled4(peer: &Peer) -> bool {
+    peer.afi_safi.has(&AfiSafi::new(Afi::IP, Safi::MplsLabel))
+}
+
+fn route_labeled6(peer: &Peer) -> bool {
+    peer.afi_safi.has(&AfiSafi::new(Afi::IP6, Safi::MplsLabel))
+}
+
+fn route_exportable6(peer: &Peer, route: &Route) -> bool {
+    let ipv6 = peer.afi_safi.has(&AfiSafi::new(Afi::IP6, Safi::Unicast)) || route_labeled6(peer);
+    ipv6 && route_shareable(peer, route)
+}
+
+// Label stack advertised with labeled unicast. Received label stack is kept
+// when the next hop is unchanged, otherwise the local label is advertised (RFC
+// 8277 3). Implicit null lets the upstream pop the label.
+fn route_label(route: &Route, next_hop_self: bool) -> Vec<u32> {
+    let labels = if next_hop_self {
+        route.local_label.into_iter().collect()
+    } else {
+        route.labels()
+    };
+    if labels.is_empty() {
+        vec![LABEL_IMPLICIT_NULL]
+    } else {
+        labels
+    }
+}
+
+// Path attributes advertised to the peer except next hop, which is set by
+// the caller for each address family.
+fn route_export_attrs(peer: &Peer, route: &Route, next_hop: Attribute) -> Attrs {
+    let ibgp = peer.peer_as == peer.local_as;
+    // MED, LOCAL_PREF and non-transitive attributes are kept in the
+    // confederation (RFC 5065 5.3).
+    let internal = ibgp || peer.confed_member();
+    let mut attrs = Vec::new();
+    for attr in route.attrs.iter() {
+        match attr {
+            Attribute::AsPath(_) | Attribute::As4Path(_) | Attribute::NextHop(_) => {}
+            Attribute::Med(_) | Attribute::LocalPref(_) if !internal => {}
+            Attribute::MpReachNlri(_)
+            | Attribute::MpUnreachNlri(_)
+            | Attribute::MpReachRaw(_)
+            | Attribute::MpUnreachRaw(_)
+            | Attribute::NewAsPath(_)
+            | Attribute::NewAggregator(_) => {}
+            // Set below when the route is reflected.
+            Attribute::OriginatorId(_) | Attribute::ClusterList(_) => {}
+            // Non-transitive extended communities are not sent to another AS
+            // (RFC 4360 6).
+            Attribute::ExtendedCom(ecom) if !internal => {
+                let ecoms: Vec<_> = ecom
+                    .0
+                    .iter()
+                    .filter(|x| x.is_transitive())
+                    .cloned()
+                    .collect();
+                if !ecoms.is_empty() {
+                    attrs.push(Attribute::ExtendedCom(ExtendedComAttr(ecoms)));
+                }
+            }
+            _ => attrs.push(attr.clone()),
+        }
+    }
+    let aspath = route.as4path().unwrap_or(As4PathAttr {
+        segments: Vec::new(),
+    });
+    let aspath = if ibgp {
+        aspath
+    } else if peer.confed_member() {
+        aspath.confed_prepend(peer.local_as)
+    } else if peer.confed.id.is_some() {
+        local_as_prepend(peer, route_aspath_rewrite(peer, aspath.confed_remove()))
+    } else {
+        local_as_prepend(peer, route_aspath_rewrite(peer, aspath))
+    };
+    attrs.push(Attribute::As4Path(aspath));
+    if internal && route.local_pref().is_none() {
+        attrs.push(Attribute::LocalPref(LocalPrefAttr {
+            local_pref: peer.default_local_pref,
+        }));
+    }
+    if ibgp && route.ibgp {
+        route_reflect_attrs(peer, route, &mut attrs);
+    }
+    gshut_export(peer, &mut attrs);
+    large_community_add(&mut attrs, &peer.config.large_community_out);
+    attrs.push(next_hop);
+    if !peer.as4 {
+        attrs = attrs_as2(attrs);
+    }
+    attrs
+}
+
+// AS path rewrites toward the eBGP peer before our AS is prepended.
+// Private ASes are removed or replaced with the local AS, and the peer AS is
+// replaced with the local AS by as-override.
+fn route_aspath_rewrite(peer: &Peer, aspath: As4PathAttr) -> As4PathAttr {
+    let aspath = match peer.config.remove_private_as {
+        Some(PrivateAs::RemoveAll) => aspath.remove_private(),
+        Some(PrivateAs::ReplaceAll) => aspath.replace_private(peer.local_as),
+        None => aspath,
+    };
+    if peer.config.as_override {
+        aspath.replace_as(peer.peer_as, peer.local_as)
+    } else {
+        aspath
+    }
+}
+
+// Next hop is unchanged for iBGP and the neighbors in the confederation
+// unless next-hop-self is configured.
+fn route_nexthop_keep(peer: &Peer) -> bool {
+    !peer.config.next_hop_self && (peer.peer_as == peer.local_as || peer.confed_member())
+}
+
+// ORIGINATOR_ID is the router ID of the peer the route is learned from unless
+// it is set already, and the cluster ID is prepended to CLUSTER_LIST (RFC 4456
+// 8).
+fn route_reflect_attrs(peer: &Peer, route: &Route, attrs: &mut Attrs) {
+    let originator = route.originator_id().unwrap_or(route.router_id);
+    attrs.push(Attribute::OriginatorId(OriginatorIdAttr {
+        id: u32::from(originator),
+    }));
+    let mut cluster_list = vec![u32::from(peer.cluster_id())];
+    if let Some(prev) = route.cluster_list() {
+        cluster_list.extend(prev.0.iter());
+    }
+    attrs.push(Attribute::ClusterList(ClusterListAttr(cluster_list)));
+}
+
+// Attributes of the route advertised to the peer. None when the route must
+// not be advertised. Next hop is unchanged for iBGP and the neighbors in the
+// confederation, and set to self for eBGP or with next-hop-self. Next hop of
+// labeled unicast is carried in MP_REACH_NLRI.
+fn route_export(peer: &Peer, prefix: &Ipv4Net, route: &Route, policy: &Policy) -> Option<Attrs> {
+    if !route_exportable(peer, prefix, route, policy) {
+        return None;
+    }
+    let (next_hop, next_hop_self) = match route.nexthop() {
+        Some(next_hop) if route_nexthop_keep(peer) => (next_hop, false),
+        _ => (peer.local_addr4(), true),
+    };
+    // Label stack of labeled unicast is packed into NLRI on the flush.
+    let next_hop = if route_labeled4(peer) {
+        let mut stack = BytesMut::new();
+        label_stack_encode(&mut stack, &route_label(route, next_hop_self));
+        Attribute::MpReachRaw(MpRawAttr {
+            afi: Afi::IP,
+            safi: Safi::MplsLabel,
+            next_hop: next_hop.octets().to_vec(),
+            nlri: stack.to_vec(),
+        })
+    } else {
+        Attribute::NextHop(NextHopAttr {
+            next_hop: next_hop.octets(),
+        })
+    };
+    Some(route_export_attrs(peer, route, next_hop))
+}
+
+// IPv6 next hop is carried in MP_REACH_NLRI. Next hop self is the local
+// address of the IPv6 transport, or IPv4-mapped address of the IPv4 one.
+fn route_export6(peer: &Peer, route: &Route) -> Option<Attrs> {
+    if !route_exportable6(peer, route) {
+        return None;
+    }
+    let (next_hop, next_hop_self) = match route.nexthop6() {
+        Some(next_hop) if route_nexthop_keep(peer) => (next_hop, false),
+        _ => (peer.local_addr6(), true),
+    };
+    let (safi, label) = if route_labeled6(peer) {
+        (Safi::MplsLabel, vec![route_label(route, next_hop_self)])
+    } else {
+        (Safi::Unicast, Vec::new())
+    };
+    let next_hop = Attribute::MpReachNlri(MpNlriAttr {
+        next_hop: Some(next_hop),
+        link_local: None,
+        prefix: Vec::new(),
+        path_id: Vec::new(),
+        safi,
+        label,
+    });
+    Some(route_export_attrs(peer, route, next_hop))
+}
+
+// Path Identifier of 4 octets precedes each NLRI with ADD-PATH, and label
+// stack of 3 octets each precedes the prefix of labeled unicast.
+fn nlri_size<P: Prefix>(prefix: &P, add_path: bool, label_len: usize) -> usize {
+    let path_id = if add_path { 4 } else { 0 };
+    path_id + label_len + 1 + (prefix.prefix_len() as usize + 7) / 8
+}
+
+// Split prefixes into chunks which encoded size fits in the room of an UPDATE
+// message.
+fn nlri_pack<P: Prefix>(
+    nlri: &[(P, u32)],
+    add_path: bool,
+    label_len: usize,
+    room: usize,
+) -> Vec<Vec<(P, u32)>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut size = 0;
+    for (prefix, id) in nlri.iter() {
+        let len = nlri_size(prefix, add_path, label_len);
+        if size + len > room && !chunk.is_empty() {
+            chunks.push(std::mem::take(&mut chunk));
+            size = 0;
+        }
+        chunk.push((*prefix, *id));
+        size += len;
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+// Prefixes and Path Identifiers of the chunk. Path Identifiers are left empty
+// unless ADD-PATH is negotiated.
+fn nlri_split<P: Prefix>(chunk: Vec<(P, u32)>, add_path: bool) -> (Vec<P>, Vec<u32>) {
+    let (prefixes, ids): (Vec<P>, Vec<u32>) = chunk.into_iter().unzip();
+    if add_path {
+        (prefixes, ids)
+    } else {
+        (prefixes, Vec::new())
+    }
+}
+
+// Labeled IPv4 NLRI of the chunk, with the Path Identifiers when ADD-PATH is
+// negotiated. Withdrawn NLRI has no label. Prefix which label stack doesn't
+// fit in the prefix length is left out.
+fn labeled_nlri_encode(chunk: &[(Ipv4Net, u32)], add_path: bool, labels: &[u32]) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    for (prefix, id) in chunk.iter() {
+        let mut nlri = BytesMut::new();
+        if add_path {
+            nlri.put_u32(*id);
+        }
+        if labeled_ipv4_prefix_encode(&mut nlri, prefix, labels).is_none() {
+            println!("Label stack of {} is too deep to advertise", prefix);
+            continue;
+        }
+        buf.put(&nlri[..]);
+    }
+    buf.to_vec()
+}
+
+// Room for NLRI in an UPDATE message, after header, withdrawn routes length
+// and path attribute length.
+fn update_room(attr_len: usize) -> usize {
+    BGP_PACKET_LEN - BGP_HEADER_LEN as usize - 4 - attr_len
+}
+
+fn route_send_withdraw(peer: &mut Peer, withdraw: Vec<(Ipv4Net, u32)>) {
+    let add_path = peer.add_path_limit(&Afi::IP).is_some();
+    if route_labeled4(peer) {
+        let room = update_room(MP_UNREACH_HEADER_LEN);
+        for chunk in nlri_pack(&withdraw, add_path, 3, room) {
+            let nlri = labeled_nlri_encode(&chunk, add_path, &[]);
+            let update = UpdatePacket {
+                header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+                attrs: vec![Attribute::MpUnreachRaw(MpRawAttr {
+                    afi: Afi::IP,
+                    safi: Safi::MplsLabel,
+                    next_hop: Vec::new(),
+                    nlri,
+                })],
+                ipv4_update: Vec::new(),
+                ipv4_withdraw: Vec::new(),
+                ipv4_update_id: Vec::new(),
+                ipv4_withdraw_id: Vec::new(),
+                attr_errors: Vec::new(),
+            };
+            peer_send_update(peer, update);
+        }
+        return;
+    }
+    for chunk in nlri_pack(&withdraw, add_path, 0, update_room(0)) {
+        let (prefixes, ids) = nlri_split(chunk, add_path);
+        let update = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: Vec::new(),
+            ipv4_update: Vec::new(),
+            ipv4_withdraw: prefixes,
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: ids,
+            attr_errors: Vec::new(),
+        };
+        peer_send_update(peer, update);
+    }
+}
+
+fn route_send_withdraw6(peer: &mut Peer, withdraw: Vec<(Ipv6Net, u32)>) {
+    let add_path = peer.add_path_limit(&Afi::IP6).is_some();
+    let labeled = route_labeled6(peer);
+    let room = update_room(MP_UNREACH_HEADER_LEN);
+    let label_len = if labeled { 3 } else { 0 };
+    for chunk in nlri_pack(&withdraw, add_path, label_len, room) {
+        let (prefixes, ids) = nlri_split(chunk, add_path);
+        let update = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: vec![Attribute::MpUnreachNlri(MpNlriAttr {
+                next_hop: None,
+                link_local: None,
+                prefix: prefixes,
+                path_id: ids,
+                safi: if labeled {
+                    Safi::MplsLabel
+                } else {
+                    Safi::Unicast
+                },
+                label: Vec::new(),
+            })],
+            ipv4_update: Vec::new(),
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        };
+        peer_send_update(peer, update);
+    }
+}
+
+fn route_best<'a, P: Prefix>(
+    ptree: &'a PrefixTree<P, Vec<Route>>,
+    prefix: &P,
+) -> Option<&'a Route> {
+    ptree
+        .get(prefix)
+        .and_then(|routes| routes.iter().find(|route| route.selected))
+}
+
+// Paths of the prefix advertised to the peer with the Path Identifiers. Only
+// the best path is advertised with Path Identifier 0 unless ADD-PATH send is
+// negotiated, otherwise up to the limit of paths in order of preference.
+fn route_paths<P: Prefix, F>(
+    ptree: &PrefixTree<P, Vec<Route>>,
+    prefix: &P,
+    limit: Option<usize>,
+    bestpath: &Bestpath,
+    export: F,
+) -> Vec<(u32, Attrs)>
+where
+    F: Fn(&Route) -> Option<Attrs>,
+{
+    let limit = match limit {
+        Some(limit) => limit,
+        None => {
+            return route_best(ptree, prefix)
+                .and_then(export)
+                .map(|attrs| vec![(0, attrs)])
+                .unwrap_or_default();
+        }
+    };
+    let mut routes: Vec<&Route> = match ptree.get(prefix) {
+        Some(routes) => routes.iter().collect(),
+        None => return Vec::new(),
+    };
+    // Paths of the ECMP group go first.
+    routes.sort_by(|a, b| {
+        b.multipath
+            .cmp(&a.multipath)
+            .then_with(|| rib_compare(a, b, bestpath))
+    });
+    routes
+        .into_iter()
+        .filter_map(|route| export(route).map(|attrs| (route.local_path_id, attrs)))
+        .take(limit)
+        .collect()
+}
+
+// Path Identifiers in Adj-RIB-Out which are no longer advertised.
+fn route_paths_withdrawn(paths: Option<&AdjPaths>, exports: &[(u32, Attrs)]) -> Vec<u32> {
+    paths
+        .map(|paths| {
+            paths
+                .keys()
+                .filter(|id| !exports.iter().any(|(x, _)| x == *id))
+                .copied()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn attrs_key(attrs: &Attrs) -> Vec<u8> {
+    let mut key = BytesMut::new();
+    for attr in attrs.iter() {
+        attr.encode(&mut key);
+    }
+    key.to_vec()
+}
+
+// Send queued advertisements of the peer. Prefixes sharing identical
+// attributes are packed into as few UPDATE messages as possible.
+pub fn route_flush(
+    peer: &mut Peer,
+    ptree: &PrefixTree<Ipv4Net, Vec<Route>>,
+    ptree6: &PrefixTree<Ipv6Net, Vec<Route>>,
+    policy: &Policy,
+    bestpath: &Bestpath,
+) {
+    let prefixes = std::mem::take(&mut peer.adv_queue);
+    let prefixes6 = std::mem::take(&mut peer.adv_queue6);
+    let _span = tracing::info_span!(
+        parent: peer.span.as_ref().and_then(|span| span.id()),
+        "bgp.advertise",
+        prefixes = prefixes.len() + prefixes6.len(),
+    )
+    .entered();
+    let limit = peer.add_path_limit(&Afi::IP);
+    let mut groups: BTreeMap<Vec<u8>, (Attrs, Vec<(Ipv4Net, u32)>)> = BTreeMap::new();
+    let mut withdraw = Vec::new();
+    for prefix in prefixes.into_iter() {
+        let exports = route_paths(ptree, &prefix, limit, bestpath, |route| {
+            route_export(peer, &prefix, route, policy)
+        });
+        for id in route_paths_withdrawn(peer.adj_rib_out.v4.get(&prefix), &exports) {
+            peer.adj_rib_out.remove(&prefix, id);
+            withdraw.push((prefix, id));
+        }
+        for (id, attrs) in exports.into_iter() {
+            peer.adj_rib_out.insert(prefix, id, attrs.clone());
+            groups
+                .entry(attrs_key(&attrs))
+                .or_insert_with(|| (attrs, Vec::new()))
+                .1
+                .push((prefix, id));
+        }
+    }
+    route_send_withdraw(peer, withdraw);
+    let add_path = limit.is_some();
+    for (key, (attrs, nlri)) in groups.into_iter() {
+        if route_labeled4(peer) {
+            route_flush_labeled(peer, &key, attrs, nlri, add_path);
+            continue;
+        }
+        for chunk in nlri_pack(&nlri, add_path, 0, update_room(key.len())) {
+            let (prefixes, ids) = nlri_split(chunk, add_path);
+            let update = UpdatePacket {
+                header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+                attrs: attrs.clone(),
+                ipv4_update: prefixes,
+                ipv4_withdraw: Vec::new(),
+                ipv4_update_id: ids,
+                ipv4_withdraw_id: Vec::new(),
+                attr_errors: Vec::new(),
+            };
+            peer_send_update(peer, update);
+        }
+    }
+    route_flush6(peer, ptree6, prefixes6, bestpath);
+}
+
+// Prefixes grouped by the attributes share the label stack, which is kept in
+// the NLRI field of the raw MP_REACH_NLRI until the NLRI is packed.
+fn route_flush_labeled(
+    peer: &mut Peer,
+    key: &[u8],
+    attrs: Attrs,
+    nlri: Vec<(Ipv4Net, u32)>,
+    add_path: bool,
+) {
+    let labels = attrs
+        .iter()
+        .find_map(|attr| match attr {
+            Attribute::MpReachRaw(mp) => Some(label_stack_decode(&mp.nlri)),
+            _ => None,
+        })
+        .unwrap_or_default();
+    // One more octet for extended length of MP_REACH_NLRI with NLRI.
+    let room = update_room(key.len() + 1);
+    for chunk in nlri_pack(&nlri, add_path, labels.len() * 3, room) {
+        let attrs = attrs
+            .iter()
+            .map(|attr| match attr {
+                Attribute::MpReachRaw(mp) => Attribute::MpReachRaw(MpRawAttr {
+                    nlri: labeled_nlri_encode(&chunk, add_path, &labels),
+                    ..mp.clone()
+                }),
+                _ => attr.clone(),
+            })
+            .collect();
+        let update = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs,
+            ipv4_update: Vec::new(),
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        };
+        peer_send_update(peer, update);
+    }
+}
+
+fn route_flush6(
+    peer: &mut Peer,
+    ptree6: &PrefixTree<Ipv6Net, Vec<Route>>,
+    prefixes: BTreeSet<Ipv6Net>,
+    bestpath: &Bestpath,
+) {
+    let limit = peer.add_path_limit(&Afi::IP6);
+    let mut groups: BTreeMap<Vec<u8>, (Attrs, Vec<(Ipv6Net, u32)>)> = BTreeMap::new();
+    let mut withdraw = Vec::new();
+    for prefix in prefixes.into_iter() {
+        let exports = route_paths(ptree6, &prefix, limit, bestpath, |route| {
+            route_export6(peer, route)
+        });
+        for id in route_paths_withdrawn(peer.adj_rib_out.v6.get(&prefix), &exports) {
+            peer.adj_rib_out.remove6(&prefix, id);
+            withdraw.push((prefix, id));
+        }
+        for (id, attrs) in exports.into_iter() {
+            peer.adj_rib_out.insert6(prefix, id, attrs.clone());
+            groups
+                .entry(attrs_key(&attrs))
+                .or_insert_with(|| (attrs, Vec::new()))
+                .1
+                .push((prefix, id));
+        }
+    }
+    route_send_withdraw6(peer, withdraw);
+    let add_path = limit.is_some();
+    for (key, (attrs, nlri)) in groups.into_iter() {
+        let label_len = attrs
+            .iter()
+            .find_map(|attr| match attr {
+                Attribute::MpReachNlri(mp) => mp.label.first().map(|x| x.len() * 3),
+                _ => None,
+            })
+            .unwrap_or(0);
+        // One more octet for extended length of MP_REACH_NLRI with NLRI.
+        for chunk in nlri_pack(&nlri, add_path, label_len, update_room(key.len() + 1)) {
+            let (prefixes, ids) = nlri_split(chunk, add_path);
+            // Prefixes grouped by the attributes share the label stack.
+            let attrs = attrs
+                .iter()
+                .map(|attr| match attr {
+                    Attribute::MpReachNlri(mp) => Attribute::MpReachNlri(MpNlriAttr {
+                        prefix: prefixes.clone(),
+                        path_id: ids.clone(),
+                        label: match mp.label.first() {
+                            Some(label) => vec![label.clone(); prefixes.len()],
+                            None => Vec::new(),
+                        },
+                        ..mp.clone()
+                    }),
+                    _ => attr.clone(),
+                })
+                .collect();
+            let update = UpdatePacket {
+                header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+                attrs,
+                ipv4_update: Vec::new(),
+                ipv4_withdraw: Vec::new(),
+                ipv4_update_id: Vec::new(),
+                ipv4_withdraw_id: Vec::new(),
+                attr_errors: Vec::new(),
+            };
+            peer_send_update(peer, update);
+        }
+    }
+}
+
+// Whether any path of the prefix is advertised to the peer. Only the best
+// path is considered unless ADD-PATH send is negotiated.
+fn route_queueable<P: Prefix, F>(
+    ptree: &PrefixTree<P, Vec<Route>>,
+    prefix: &P,
+    add_path: bool,
+    exportable: F,
+) -> bool
+where
+    F: Fn(&Route) -> bool,
+{
+    if add_path {
+        ptree
+            .get(prefix)
+            .map(|routes| routes.iter().any(exportable))
+            .unwrap_or(false)
+    } else {
+        route_best(ptree, prefix).map(exportable).unwrap_or(false)
+    }
+}
+
+// Queue advertisement of the prefixes to the peer. Withdrawals are sent
+// immediately, advertisements are batched by MinRouteAdvertisementInterval
+// timer.
+fn route_queue(
+    peer: &mut Peer,
+    ptree: &PrefixTree<Ipv4Net, Vec<Route>>,
+    policy: &Policy,
+    prefixes: &[Ipv4Net],
+) {
+    let add_path = peer.add_path_limit(&Afi::IP).is_some();
+    let mut withdraw = Vec::new();
+    for prefix in prefixes.iter() {
+        let export = route_queueable(ptree, prefix, add_path, |route| {
+            route_exportable(peer, prefix, route, policy)
+        });
+        if export {
+            peer.adv_queue.insert(*prefix);
+        } else {
+            peer.adv_queue.remove(prefix);
+            if let Some(paths) = peer.adj_rib_out.v4.remove(prefix) {
+                withdraw.extend(paths.into_keys().map(|id| (*prefix, id)));
+            }
+        }
+    }
+    route_send_withdraw(peer, withdraw);
+}
+
+fn route_queue6(peer: &mut Peer, ptree6: &PrefixTree<Ipv6Net, Vec<Route>>, prefixes: &[Ipv6Net]) {
+    let add_path = peer.add_path_limit(&Afi::IP6).is_some();
+    let mut withdraw = Vec::new();
+    for prefix in prefixes.iter() {
+        let export = route_queueable(ptree6, prefix, add_path, |route| {
+            route_exportable6(peer, route)
+        });
+        if export {
+            peer.adv_queue6.insert(*prefix);
+        } else {
+            peer.adv_queue6.remove(prefix);
+            if let Some(paths) = peer.adj_rib_out.v6.remove(prefix) {
+                withdraw.extend(paths.into_keys().map(|id| (*prefix, id)));
+            }
+        }
+    }
+    route_send_withdraw6(peer, withdraw);
+}
+
+// Send queued advertisements right away unless the timer is running.
+fn route_adv_start(
+    peer: &mut Peer,
+    ptree: &PrefixTree<Ipv4Net, Vec<Route>>,
+    ptree6: &PrefixTree<Ipv6Net, Vec<Route>>,
+    policy: &Policy,
+    bestpath: &Bestpath,
+) {
+    if peer.timer.min_route_adv.is_some() {
+        return;
+    }
+    if peer.adv_queue.is_empty() && peer.adv_queue6.is_empty() {
+        return;
+    }
+    route_flush(peer, ptree, ptree6, policy, bestpath);
+    peer.timer.min_route_adv = peer_start_route_adv_timer(peer);
+}
+
+// Changes are held for the coalescing window of the peer, so that a storm of
+// best path or policy changes results in one advertisement of each prefix.
+fn route_adv_schedule(
+    peer: &mut Peer,
+    ptree: &PrefixTree<Ipv4Net, Vec<Route>>,
+    ptree6: &PrefixTree<Ipv6Net, Vec<Route>>,
+    policy: &Policy,
+    bestpath: &Bestpath,
+) {
+    if peer.config.coalesce_window == 0 {
+        route_adv_start(peer, ptree, ptree6, policy, bestpath);
+        return;
+    }
+    if peer.timer.coalesce.is_none() && !(peer.adv_queue.is_empty() && peer.adv_queue6.is_empty()) {
+        peer.timer.coalesce = Some(peer_start_coalesce_timer(peer));
+    }
+}
+
+// Whether the paths to export are the same as advertised.
+fn route_adv_same(paths: Option<&AdjPaths>, exports: &[(u32, Attrs)]) -> bool {
+    let paths = match paths {
+        Some(paths) => paths,
+        None => return exports.is_empty(),
+    };
+    paths.len() == exports.len()
+        && exports.iter().all(|(id, attrs)| {
+            paths
+                .get(id)
+                .map_or(false, |x| attrs_key(x) == attrs_key(attrs))
+        })
+}
+
+// Prefixes which change has been reverted in the window are dropped from the
+// queue, then the rest is sent.
+pub fn route_coalesce_flush(
+    peer: &mut Peer,
+    ptree: &PrefixTree<Ipv4Net, Vec<Route>>,
+    ptree6: &PrefixTree<Ipv6Net, Vec<Route>>,
+    policy: &Policy,
+    bestpath: &Bestpath,
+) {
+    let limit = peer.add_path_limit(&Afi::IP);
+    let queue = std::mem::take(&mut peer.adv_queue);
+    peer.adv_queue = queue
+        .into_iter()
+        .filter(|prefix| {
+            let exports = route_paths(ptree, prefix, limit, bestpath, |route| {
+                route_export(peer, prefix, route, policy)
+            });
+            !route_adv_same(peer.adj_rib_out.v4.get(prefix), &exports)
+        })
+        .collect();
+    let limit = peer.add_path_limit(&Afi::IP6);
+    let queue = std::mem::take(&mut peer.adv_queue6);
+    peer.adv_queue6 = queue
+        .into_iter()
+        .filter(|prefix| {
+            let exports = route_paths(ptree6, prefix, limit, bestpath, |route| {
+                route_export6(peer, route)
+            });
+            !route_adv_same(peer.adj_rib_out.v6.get(prefix), &exports)
+        })
+        .collect();
+    route_adv_start(peer, ptree, ptree6, policy, bestpath);
+}
+
+// Advertise best path change of the prefixes to established peers.
+// Nothing is advertised while best path selection is deferred by our
+// restart.
+pub fn route_advertise(bgp: &mut Bgp, prefixes: &[Ipv4Net], prefixes6: &[Ipv6Net]) {
+    // Local labels and ECMP groups follow the best path even while the
+    // advertisement is held.
+    if !bgp.monitor_only {
+        label_update(bgp, prefixes, prefixes6);
+        multipath_update(bgp, prefixes, prefixes6);
+    }
+    if bgp.monitor_only || bgp.restart.is_some() || (prefixes.is_empty() && prefixes6.is_empty()) {
+        return;
+    }
+    let mut prefixes = prefixes.to_vec();
+    prefixes.sort();
+    prefixes.dedup();
+    let mut prefixes6 = prefixes6.to_vec();
+    prefixes6.sort();
+    prefixes6.dedup();
+    for peer in bgp.peers.values_mut() {
+        if peer.state == State::Established && !peer.mirror_lab {
+            route_queue(peer, &bgp.ptree, &bgp.policy, &prefixes);
+            route_queue6(peer, &bgp.ptree6, &prefixes6);
+            route_adv_schedule(peer, &bgp.ptree, &bgp.ptree6, &bgp.policy, &bgp.bestpath);
+        }
+    }
+}
+
+// Initial advertisement of the whole table followed by End-of-RIB marker.
+// Lab neighbor of the session mirroring only receives the mirrored UPDATE.
+pub fn route_sync(bgp: &mut Bgp, id: IpAddr) {
+    if bgp.monitor_only || bgp.restart.is_some() {
+        return;
+    }
+    if let Some(peer) = bgp.peers.get_mut(&id).filter(|peer| !peer.mirror_lab) {
+        peer.graceful_shutdown = bgp.graceful_shutdown;
+        let labeled = route_labeled6(peer);
+        let ipv6 = peer.afi_safi.has(&AfiSafi::new(Afi::IP6, Safi::Unicast)) || labeled;
+        peer.adv_queue
+            .extend(bgp.ptree.iter().map(|(prefix, _)| *prefix));
+        if ipv6 {
+            peer.adv_queue6
+                .extend(bgp.ptree6.iter().map(|(prefix, _)| *prefix));
+        }
+        route_flush(peer, &bgp.ptree, &bgp.ptree6, &bgp.policy, &bgp.bestpath);
+        let eor = UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: Vec::new(),
+            ipv4_update: Vec::new(),
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        };
+        peer_send_update(peer, eor);
+        // End-of-RIB of the other families is an empty MP_UNREACH_NLRI (RFC
+        // 4724).
+        if route_labeled4(peer) {
+            let eor = UpdatePacket {
+                header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+                attrs: vec![Attribute::MpUnreachRaw(MpRawAttr {
+                    afi: Afi::IP,
+                    safi: Safi::MplsLabel,
+                    next_hop: Vec::new(),
+                    nlri: Vec::new(),
+                })],
+                ipv4_update: Vec::new(),
+                ipv4_withdraw: Vec::new(),
+                ipv4_update_id: Vec::new(),
+                ipv4_withdraw_id: Vec::new(),
+                attr_errors: Vec::new(),
+            };
+            peer_send_update(peer, eor);
+        }
+        if ipv6 {
+            let safi = if labeled {
+                Safi::MplsLabel
+            } else {
+                Safi::Unicast
+            };
+            let eor = UpdatePacket {
+                header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+                attrs: vec![Attribute::MpUnreachNlri(MpNlriAttr {
+                    next_hop: None,
+                    link_local: None,
+                    prefix: Vec::new(),
+                    path_id: Vec::new(),
+                    safi,
+                    label: Vec::new(),
+                })],
+                ipv4_update: Vec::new(),
+                ipv4_withdraw: Vec::new(),
+                ipv4_update_id: Vec::new(),
+                ipv4_withdraw_id: Vec::new(),
+                attr_errors: Vec::new(),
+            };
+            peer_send_update(peer, eor);
+        }
+        vpn_sync(peer, &bgp.vpn);
+        peer.timer.min_route_adv = peer_start_route_adv_timer(peer);
+    }
+}
+
+// Re-advertise the AFI/SAFI of the table to the peer, on ROUTE-REFRESH from
+// the peer or soft reset outbound. Outbound policy is evaluated again so that
+// Adj-RIB-Out reflects the current configuration.
+pub fn route_refresh(
+    peer: &mut Peer,
+    ptree: &PrefixTree<Ipv4Net, Vec<Route>>,
+    ptree6: &PrefixTree<Ipv6Net, Vec<Route>>,
+    policy: &Policy,
+    bestpath: &Bestpath,
+    afi_safi: &AfiSafi,
+) {
+    if peer.mirror_lab {
+        return;
+    }
+    if *afi_safi == AfiSafi::new(Afi::IP, Safi::Unicast)
+        || *afi_safi == AfiSafi::new(Afi::IP, Safi::MplsLabel)
+    {
+        peer.adv_queue
+            .extend(ptree.iter().map(|(prefix, _)| *prefix));
+    } else if *afi_safi == AfiSafi::new(Afi::IP6, Safi::Unicast)
+        || *afi_safi == AfiSafi::new(Afi::IP6, Safi::MplsLabel)
+    {
+        peer.adv_queue6
+            .extend(ptree6.iter().map(|(prefix, _)| *prefix));
+    } else {
+        return;
+    }
+    route_flush(peer, ptree, ptree6, policy, bestpath);
+}
+
+// Re-run inbound policy over Adj-RIB-In of the peer. Only prefixes which
+// result has changed are updated in Loc-RIB, except that permitted routes
+// are replaced with the attributes set by the inbound route map.
+fn route_reeval_in(
+    peer: &Peer,
+    ptree: &mut PrefixTree<Ipv4Net, Vec<Route>>,
+    policy: &Policy,
+    rpki: &Rpki,
+    nexthop: &NexthopConfig,
+    bestpath: &Bestpath,
+) -> Vec<Ipv4Net> {
+    let mut changed = Vec::new();
+    for (prefix, id, attrs) in peer.adj_rib_in.paths() {
+        let state = rpki.state(&IpNet::V4(*prefix), attrs, peer.local_as);
+        let import = route_import(peer, prefix, attrs, state, policy);
+        let present = ptree
+            .get(prefix)
+            .map(|routes| {
+                routes
+                    .iter()
+                    .any(|route| route.from == peer.address && route.path_id == id)
+            })
+            .unwrap_or(false);
+        // Attributes of the present route may be changed by the route map.
+        let update = !present || peer.config.route_map_in.is_some();
+        match import {
+            Some(attrs) if update => {
+                let mut route = route_new(peer, id, attrs);
+                route.rpki = state;
+                route.resolved = nexthop_resolve4(ptree, prefix, &route, nexthop, policy);
+                route_add(ptree, *prefix, route, bestpath);
+                changed.push(*prefix);
+            }
+            None if present => {
+                route_withdraw_path(ptree, peer.address, id, prefix, bestpath);
+                changed.push(*prefix);
+            }
+            _ => {}
+        }
+    }
+    changed
+}
+
+// Re-run outbound policy against Loc-RIB, only prefixes which result differs
+// from Adj-RIB-Out are advertised or withdrawn.
+pub fn route_reeval_out(
+    peer: &mut Peer,
+    ptree: &PrefixTree<Ipv4Net, Vec<Route>>,
+    ptree6: &PrefixTree<Ipv6Net, Vec<Route>>,
+    policy: &Policy,
+    bestpath: &Bestpath,
+) {
+    let mut prefixes = Vec::new();
+    for (prefix, routes) in ptree.iter() {
+        let export = routes
+            .iter()
+            .find(|route| route.selected)
+            .map(|route| route_exportable(peer, prefix, route, policy))
+            .unwrap_or(false);
+        if export != peer.adj_rib_out.v4.get(prefix).is_some() {
+            prefixes.push(*prefix);
+        }
+    }
+    route_queue(peer, ptree, policy, &prefixes);
+    route_adv_schedule(peer, ptree, ptree6, policy, bestpath);
+}
+
+// Apply policy changes of the commit without resetting sessions. Only the
+// neighbors which refer to the changed policies are re-evaluated.
+pub fn route_policy_update(bgp: &mut Bgp) {
+    let _span = tracing::info_span!("bgp.commit").entered();
+    let changed = std::mem::take(&mut bgp.policy_changed);
+    let mut targets = std::mem::take(&mut bgp.policy_peers);
+    // Routes are evaluated against the compiled policy from here on.
+    if !changed.is_empty() || bgp.policy.ir.is_none() {
+        bgp.policy.compile();
+    }
+    if let Some(name) = &bgp.nexthop.prefix_list {
+        if changed.contains(name) {
+            bgp.nexthop.changed = true;
+        }
+    }
+    for (addr, peer) in bgp.peers.iter() {
+        let config = &peer.config;
+        let refers = |names: [&Option<String>; 3]| {
+            names
+                .iter()
+                .any(|name| name.as_ref().map_or(false, |x| changed.contains(x)))
+        };
+        let route_map_in = config
+            .route_map_in
+            .as_ref()
+            .map_or(false, |x| bgp.policy.route_map_refers(x, &changed));
+        if route_map_in
+            || refers([
+                &config.prefix_list_in,
+                &config.large_community_list_in,
+                &config.ext_community_list_in,
+            ])
+        {
+            targets.insert((*addr, PolicyDirection::In));
+        }
+        if refers([
+            &config.prefix_list_out,
+            &config.large_community_list_out,
+            &config.ext_community_list_out,
+        ]) {
+            targets.insert((*addr, PolicyDirection::Out));
+        }
+    }
+
+    let mut prefixes = Vec::new();
+    for (addr, direction) in targets.iter() {
+        if let Some(peer) = bgp.peers.get_mut(addr) {
+            if peer.state != State::Established {
+                continue;
+            }
+            match direction {
+                PolicyDirection::In => {
+                    prefixes.extend(route_reeval_in(
+                        peer,
+                        &mut bgp.ptree,
+                        &bgp.policy,
+                        &bgp.rpki,
+                        &bgp.nexthop,
+                        &bgp.bestpath,
+                    ));
+                }
+                PolicyDirection::Out => {
+                    route_reeval_out(peer, &bgp.ptree, &bgp.ptree6, &bgp.policy, &bgp.bestpath);
+                }
+            }
+        }
+    }
+    route_advertise(bgp, &prefixes, &[]);
+
+    // Network statements are originated again when the route map or the
+    // prefix list referred from it is changed.
+    let networks: Vec<IpNet> = bgp
+        .networks
+        .iter()
+        .filter(|(_, network)| match &network.route_map {
+            Some(name) => bgp.policy.route_map_refers(name, &changed),
+            None => false,
+        })
+        .map(|(prefix, _)| *prefix)
+        .collect();
+    for prefix in networks.iter() {
+        network_apply(bgp, prefix);
+    }
+    let sources: Vec<RedistSource> = bgp
+        .redistribute
+        .config
+        .iter()
+        .filter(|(_, config)| match &config.route_map {
+            Some(name) => bgp.policy.route_map_refers(name, &changed),
+            None => false,
+        })
+        .map(|(source, _)| *source)
+        .collect();
+    for source in sources.into_iter() {
+        redist_source_apply(bgp, source);
+    }
+}
+
+fn route_reselect_tree<P: Prefix>(
+    ptree: &mut PrefixTree<P, Vec<Route>>,
+    bestpath: &Bestpath,
+) -> Vec<P> {
+    let prefixes: Vec<P> = ptree.iter().map(|(prefix, _)| *prefix).collect();
+    let mut changed = Vec::new();
+    for prefix in prefixes.into_iter() {
+        if let Some(routes) = ptree.get_mut(&prefix) {
+            let prev = routes.iter().position(|route| route.selected);
+            if rib_select(routes, bestpath) != prev {
+                changed.push(prefix);
+            }
+        }
+    }
+    changed
+}
+
+// Run best path selection again over Loc-RIB after the change of the
+// selection options. Prefixes which best path is changed are advertised.
+pub fn route_bestpath_update(bgp: &mut Bgp) {
+    let changed = route_reselect_tree(&mut bgp.ptree, &bgp.bestpath);
+    let changed6 = route_reselect_tree(&mut bgp.ptree6, &bgp.bestpath);
+    route_advertise(bgp, &changed, &changed6);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::handler::Message;
+    use crate::bgp::packet::{
+        parse_bgp_packet, As4Segment, BgpPacket, CapabilityEnhancedRouteRefresh, CapabilityPacket,
+        OriginAttr, RouteRefreshPacket, AS_SEQUENCE, REFRESH_BORR, REFRESH_EORR,
+    };
+    use crate::bgp::peer::Event;
+    use crate::bgp::queue::PeerQueue;
+    use crate::rib::RibTxChannel;
+    use std::sync::Arc;
+    use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+    const NEIGHBOR: &str = "10.0.0.2";
+
+    fn attrs(asn: u32) -> Attrs {
+        vec![
+            Attribute::Origin(OriginAttr { origin: 0 }),
+            Attribute::As4Path(As4PathAttr {
+                segments: vec![As4Segment {
+                    typ: AS_SEQUENCE,
+                    asn: vec![asn],
+                }],
+            }),
+            Attribute::NextHop(NextHopAttr {
+                next_hop: [10, 0, 0, 3],
+            }),
+        ]
+    }
+
+    fn route(asn: u32) -> Route {
+        Route {
+            from: "10.0.0.3".parse().unwrap(),
+            router_id: Ipv4Addr::new(10, 0, 0, 3),
+            attrs: attrs(asn),
             ibgp: false,
-            selected: false,
+            weight: 0,
+            metric: 0,
+            selected: true,
+            resolved: true,
+            path_id: 0,
+            local_path_id: 1,
+            local_label: None,
+            multipath: false,
+            rpki: RpkiState::NotFound,
+        }
+    }
+
+    fn prefix(i: u32) -> Ipv4Net {
+        Ipv4Net::new(Ipv4Addr::from(0x0a00_0000 + (i << 8)), 24).unwrap()
+    }
+
+    // eBGP neighbor which UPDATE messages are sent to the receiver.
+    fn neighbor(tx: UnboundedSender<Message>) -> (Peer, UnboundedReceiver<BytesMut>) {
+        let addr: IpAddr = NEIGHBOR.parse().unwrap();
+        let mut peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65002,
+            addr,
+            tx.clone(),
+            tx,
+        );
+        let (packet_tx, packet_rx) = mpsc::unbounded_channel();
+        peer.packet_tx = Some(packet_tx);
+        peer.state = State::Established;
+        (peer, packet_rx)
+    }
+
+    fn updates(rx: &mut UnboundedReceiver<BytesMut>) -> Vec<UpdatePacket> {
+        let mut updates = Vec::new();
+        while let Ok(bytes) = rx.try_recv() {
+            match parse_bgp_packet(&bytes, true) {
+                Ok((_, BgpPacket::Update(update))) => updates.push(update),
+                _ => panic!("not an update packet"),
+            }
+        }
+        updates
+    }
+
+    #[test]
+    fn nlri_pack_room() {
+        let attrs = attrs(65003);
+        let attr_len = attrs_key(&attrs).len();
+        let room = update_room(attr_len);
+        let nlri: Vec<(Ipv4Net, u32)> = (0..2000).map(|i| (prefix(i), i)).collect();
+        for (add_path, size) in [(false, 4), (true, 8)] {
+            let chunks = nlri_pack(&nlri, add_path, 0, room);
+            assert_eq!(chunks.iter().map(|x| x.len()).sum::<usize>(), nlri.len());
+            // Every chunk but the last one is full.
+            for chunk in chunks[..chunks.len() - 1].iter() {
+                assert_eq!(chunk.len(), room / size);
+            }
+            for chunk in chunks.into_iter() {
+                let (prefixes, ids) = nlri_split(chunk, add_path);
+                let update = UpdatePacket {
+                    header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+                    attrs: attrs.clone(),
+                    ipv4_update: prefixes,
+                    ipv4_withdraw: Vec::new(),
+                    ipv4_update_id: ids,
+                    ipv4_withdraw_id: Vec::new(),
+                    attr_errors: Vec::new(),
+                };
+                let bytes: BytesMut = update.into();
+                assert!(bytes.len() <= BGP_PACKET_LEN);
+            }
+            // One more prefix does not fit in the full chunk.
+            let full = BGP_HEADER_LEN as usize + 4 + attr_len + room / size * size;
+            assert!(full + size > BGP_PACKET_LEN);
+        }
+
+        // A prefix larger than the room is sent alone.
+        let chunks = nlri_pack(&nlri[..2], false, 0, 3);
+        assert_eq!(chunks.len(), 2);
+    }
+
+    #[test]
+    fn route_flush_group() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (mut peer, mut packet_rx) = neighbor(tx);
+        let mut ptree = PrefixTree::new();
+        ptree.insert(prefix(1), vec![route(65003)]);
+        ptree.insert(prefix(2), vec![route(65004)]);
+        ptree.insert(prefix(3), vec![route(65003)]);
+        peer.adv_queue.extend([prefix(1), prefix(2), prefix(3)]);
+        route_flush(
+            &mut peer,
+            &ptree,
+            &PrefixTree::new(),
+            &Policy::default(),
+            &Bestpath::default(),
+        );
+
+        // Prefixes with identical attributes share one UPDATE.
+        let mut updates = updates(&mut packet_rx);
+        updates.sort_by_key(|update| update.ipv4_update.len());
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].ipv4_update, vec![prefix(2)]);
+        assert_eq!(updates[1].ipv4_update, vec![prefix(1), prefix(3)]);
+        assert_eq!(peer.adj_rib_out.count(), 3);
+        assert!(peer.adv_queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn route_queue_mrai() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let (peer, mut packet_rx) = neighbor(bgp.tx.clone());
+        let addr = peer.address;
+        bgp.peers.insert(addr, peer);
+
+        // First advertisement is sent right away and starts the timer.
+        bgp.ptree.insert(prefix(1), vec![route(65003)]);
+        route_advertise(&mut bgp, &[prefix(1)], &[]);
+        let sent = updates(&mut packet_rx);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].ipv4_update, vec![prefix(1)]);
+        assert!(bgp.peers[&addr].timer.min_route_adv.is_some());
+
+        // Advertisement waits for the timer, while withdrawal is sent
+        // immediately.
+        bgp.ptree.insert(prefix(2), vec![route(65003)]);
+        route_advertise(&mut bgp, &[prefix(2)], &[]);
+        assert!(updates(&mut packet_rx).is_empty());
+        bgp.ptree.remove(&prefix(1));
+        route_advertise(&mut bgp, &[prefix(1)], &[]);
+        let sent = updates(&mut packet_rx);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].ipv4_withdraw, vec![prefix(1)]);
+        assert!(sent[0].ipv4_update.is_empty());
+
+        // Queued advertisement is sent when the timer expires.
+        let peer = bgp.peers.get_mut(&addr).unwrap();
+        assert!(peer.adv_queue.contains(&prefix(2)));
+        route_flush(peer, &bgp.ptree, &bgp.ptree6, &bgp.policy, &bgp.bestpath);
+        let sent = updates(&mut packet_rx);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].ipv4_update, vec![prefix(2)]);
+    }
+
+    #[tokio::test]
+    async fn route_coalesce() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let (mut peer, mut packet_rx) = neighbor(bgp.tx.clone());
+        let addr = peer.address;
+        bgp.ptree.insert(prefix(1), vec![route(65003)]);
+        bgp.ptree.insert(prefix(2), vec![route(65003)]);
+        peer.adv_queue.extend([prefix(1), prefix(2)]);
+        route_flush(
+            &mut peer,
+            &bgp.ptree,
+            &bgp.ptree6,
+            &bgp.policy,
+            &bgp.bestpath,
+        );
+        assert_eq!(updates(&mut packet_rx).len(), 1);
+        peer.config.coalesce_window = 100;
+        bgp.peers.insert(addr, peer);
+
+        // First prefix is reverted, the second one changes twice, and the
+        // third one is new in the window.
+        for (i, asn) in [(1, 65004), (2, 65004), (1, 65003), (2, 65005), (3, 65005)] {
+            bgp.ptree.insert(prefix(i), vec![route(asn)]);
+            route_advertise(&mut bgp, &[prefix(i)], &[]);
+        }
+        assert!(updates(&mut packet_rx).is_empty());
+        assert!(bgp.peers[&addr].timer.coalesce.is_some());
+
+        // One UPDATE carries the last state of the changed prefixes.
+        let peer = bgp.peers.get_mut(&addr).unwrap();
+        peer.timer.coalesce = None;
+        route_coalesce_flush(peer, &bgp.ptree, &bgp.ptree6, &bgp.policy, &bgp.bestpath);
+        let sent = updates(&mut packet_rx);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].ipv4_update, vec![prefix(2), prefix(3)]);
+        assert!(sent[0].ipv4_withdraw.is_empty());
+        let aspath = sent[0].attrs.iter().find_map(|attr| match attr {
+            Attribute::As4Path(aspath) => Some(aspath.to_string()),
+            _ => None,
+        });
+        assert_eq!(aspath.as_deref(), Some("65001 65005"));
+    }
+
+    #[test]
+    fn route_refresh_eorr() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let (mut peer, _packet_rx) = neighbor(bgp.tx.clone());
+        let mut bgp_ref = ConfigRef {
+            router_id: &bgp.router_id,
+            monitor_only: bgp.monitor_only,
+            strict_parse: bgp.strict_parse,
+            ptree: &mut bgp.ptree,
+            ptree6: &mut bgp.ptree6,
+            families: &mut bgp.families,
+            vpn: &mut bgp.vpn,
+            persist: &mut bgp.persist,
+            policy: &bgp.policy,
+            nexthop: &bgp.nexthop,
+            bestpath: &bgp.bestpath,
+            webhook: &bgp.webhook,
+            origin_watch: &mut bgp.origin_watch,
+            rpki: &bgp.rpki,
+            changed: Vec::new(),
+            changed6: Vec::new(),
+        };
+        let update = |prefixes: Vec<Ipv4Net>| UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: attrs(65002),
+            ipv4_update: prefixes,
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
         };
-        bgp.ptree.entry(*ipv4).or_default().push(route);
-        //let node = bgp.ptree.get(&ipv4);
+        route_from_peer(
+            &mut peer,
+            &update(vec![prefix(1), prefix(2), prefix(3)]),
+            &mut bgp_ref,
+        );
+        assert_eq!(bgp_ref.ptree.iter().count(), 3);
+
+        // BoRR marks the paths stale, they are still in the RIBs.
+        route_refresh_stale(&mut peer, &Afi::IP);
+        assert_eq!(peer.refresh_stale.count(), 3);
+        assert_eq!(peer.adj_rib_in.count(), 3);
+        assert_eq!(bgp_ref.ptree.iter().count(), 3);
+
+        // Only the first prefix is advertised again before EoRR.
+        route_from_peer(&mut peer, &update(vec![prefix(1)]), &mut bgp_ref);
+        assert_eq!(peer.refresh_stale.count(), 2);
+        route_refresh_flush(&mut peer, &mut bgp_ref, &Afi::IP);
+        assert_eq!(peer.refresh_stale.count(), 0);
+        assert_eq!(peer.adj_rib_in.prefixes(), vec![prefix(1)]);
+        let prefixes: Vec<Ipv4Net> = bgp_ref.ptree.iter().map(|(prefix, _)| *prefix).collect();
+        assert_eq!(prefixes, vec![prefix(1)]);
+    }
+
+    #[tokio::test]
+    async fn route_refresh_order() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let (mut peer, _packet_rx) = neighbor(bgp.tx.clone());
+        peer.queue = PeerQueue::new(bgp.updates.tx.clone());
+        peer.config.route_refresh = true;
+        peer.config
+            .received
+            .push(CapabilityPacket::EnhancedRouteRefresh(
+                CapabilityEnhancedRouteRefresh::new(),
+            ));
+        peer.afi_safi.push(AfiSafi::new(Afi::IP, Safi::Unicast));
+        let queue = peer.queue.clone();
+        bgp.peers.insert(peer.ident, peer);
+
+        let update = |prefixes: Vec<Ipv4Net>| UpdatePacket {
+            header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+            attrs: attrs(65002),
+            ipv4_update: prefixes,
+            ipv4_withdraw: Vec::new(),
+            ipv4_update_id: Vec::new(),
+            ipv4_withdraw_id: Vec::new(),
+            attr_errors: Vec::new(),
+        };
+        let refresh = |subtype: u8| {
+            let mut packet = RouteRefreshPacket::new(Afi::IP, Safi::Unicast);
+            packet.subtype = subtype;
+            Event::RouteRefreshMsg(packet)
+        };
+        let ident: IpAddr = NEIGHBOR.parse().unwrap();
+        queue.send(ident, Event::UpdateMsg(update(vec![prefix(1), prefix(2)])));
+        queue.send(ident, refresh(REFRESH_BORR));
+        queue.send(ident, Event::UpdateMsg(update(vec![prefix(1)])));
+        queue.send(ident, refresh(REFRESH_EORR));
+
+        // UPDATE sent before EoRR is processed before it, the route survives
+        // the flush of the stale paths.
+        while let Ok(msg) = bgp.updates.rx.try_recv() {
+            bgp.process_msg(msg);
+        }
+        let prefixes: Vec<Ipv4Net> = bgp.ptree.iter().map(|(prefix, _)| *prefix).collect();
+        assert_eq!(prefixes, vec![prefix(1)]);
+        let peer = bgp.peers.get(&ident).unwrap();
+        assert_eq!(peer.adj_rib_in.prefixes(), vec![prefix(1)]);
+        assert_eq!(peer.refresh_stale.count(), 0);
+    }
+
+    #[test]
+    fn route_reflect_loop_drop() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let (mut peer, _packet_rx) = neighbor(bgp.tx.clone());
+        let mut bgp_ref = ConfigRef {
+            router_id: &bgp.router_id,
+            monitor_only: bgp.monitor_only,
+            strict_parse: bgp.strict_parse,
+            ptree: &mut bgp.ptree,
+            ptree6: &mut bgp.ptree6,
+            families: &mut bgp.families,
+            vpn: &mut bgp.vpn,
+            persist: &mut bgp.persist,
+            policy: &bgp.policy,
+            nexthop: &bgp.nexthop,
+            bestpath: &bgp.bestpath,
+            webhook: &bgp.webhook,
+            origin_watch: &mut bgp.origin_watch,
+            rpki: &bgp.rpki,
+            changed: Vec::new(),
+            changed6: Vec::new(),
+        };
+        peer.config.cluster_id = Some(Ipv4Addr::new(192, 0, 2, 1));
+        let update = |prefix: Ipv4Net, attr: Attribute| {
+            let mut attrs = attrs(65002);
+            attrs.push(attr);
+            UpdatePacket {
+                header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+                attrs,
+                ipv4_update: vec![prefix],
+                ipv4_withdraw: Vec::new(),
+                ipv4_update_id: Vec::new(),
+                ipv4_withdraw_id: Vec::new(),
+                attr_errors: Vec::new(),
+            }
+        };
+        let originator = |addr: Ipv4Addr| {
+            Attribute::OriginatorId(OriginatorIdAttr {
+                id: u32::from(addr),
+            })
+        };
+        let cluster_list = |addr: Ipv4Addr| {
+            Attribute::ClusterList(ClusterListAttr(vec![
+                u32::from(Ipv4Addr::new(198, 51, 100, 1)),
+                u32::from(addr),
+            ]))
+        };
+
+        // ORIGINATOR_ID is our router ID.
+        let packet = update(prefix(1), originator(Ipv4Addr::new(10, 0, 0, 1)));
+        route_from_peer(&mut peer, &packet, &mut bgp_ref);
+        // CLUSTER_LIST has our cluster ID.
+        let packet = update(prefix(2), cluster_list(Ipv4Addr::new(192, 0, 2, 1)));
+        route_from_peer(&mut peer, &packet, &mut bgp_ref);
+        assert_eq!(bgp_ref.ptree.iter().count(), 0);
+
+        // Reflected by others only.
+        let packet = update(prefix(3), originator(Ipv4Addr::new(10, 0, 0, 9)));
+        route_from_peer(&mut peer, &packet, &mut bgp_ref);
+        let packet = update(prefix(4), cluster_list(Ipv4Addr::new(192, 0, 2, 2)));
+        route_from_peer(&mut peer, &packet, &mut bgp_ref);
+        let prefixes: Vec<Ipv4Net> = bgp_ref.ptree.iter().map(|(prefix, _)| *prefix).collect();
+        assert_eq!(prefixes, vec![prefix(3), prefix(4)]);
+    }
+
+    #[test]
+    fn route_reflect_client() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let ibgp = |addr: &str, client: bool, clients: &Arc<BTreeSet<IpAddr>>| {
+            let addr: IpAddr = addr.parse().unwrap();
+            let mut peer = Peer::new(
+                addr,
+                65001,
+                Ipv4Addr::new(10, 0, 0, 1),
+                65001,
+                addr,
+                tx.clone(),
+                tx.clone(),
+            );
+            peer.config.route_reflector_client = client;
+            peer.rr_clients = clients.clone();
+            peer
+        };
+        let mut route = route(65002);
+        route.ibgp = true;
+        let non_client: Arc<BTreeSet<IpAddr>> = Arc::default();
+        let client = Arc::new(BTreeSet::from([route.from]));
+
+        // Non-client to non-client is not reflected, to client it is.
+        assert!(!route_shareable(
+            &ibgp("10.0.0.4", false, &non_client),
+            &route
+        ));
+        assert!(route_shareable(
+            &ibgp("10.0.0.4", true, &non_client),
+            &route
+        ));
+
+        // Client to client and non-client, and never back to the client.
+        assert!(route_shareable(&ibgp("10.0.0.4", true, &client), &route));
+        assert!(route_shareable(&ibgp("10.0.0.4", false, &client), &route));
+        assert!(!route_shareable(&ibgp("10.0.0.3", true, &client), &route));
+
+        // Reflected route carries ORIGINATOR_ID and our cluster ID.
+        let peer = ibgp("10.0.0.4", false, &client);
+        let next_hop = Attribute::NextHop(NextHopAttr {
+            next_hop: [10, 0, 0, 3],
+        });
+        let attrs = route_export_attrs(&peer, &route, next_hop);
+        let exported = Route { attrs, ..route };
+        assert_eq!(exported.originator_id(), Some(Ipv4Addr::new(10, 0, 0, 3)));
+        let cluster_list = exported.cluster_list().unwrap();
+        assert!(cluster_list.contains(u32::from(Ipv4Addr::new(10, 0, 0, 1))));
+    }
+
+    #[test]
+    fn allowas_in_count() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (mut peer, _packet_rx) = neighbor(tx);
+        let aspath = |asn: Vec<u32>| As4PathAttr {
+            segments: vec![As4Segment {
+                typ: AS_SEQUENCE,
+                asn,
+            }],
+        };
+        let once = aspath(vec![65002, 65001]);
+        let twice = aspath(vec![65002, 65001, 65003, 65001]);
+        assert!(!route_as_loop(&peer, &aspath(vec![65002, 65003])));
+        assert!(route_as_loop(&peer, &once));
+
+        peer.config.allowas_in = 1;
+        assert!(!route_as_loop(&peer, &once));
+        assert!(route_as_loop(&peer, &twice));
+        peer.config.allowas_in = 2;
+        assert!(!route_as_loop(&peer, &twice));
+
+        // Confederation identifier counts as our AS.
+        peer.confed.id = Some(65100);
+        assert!(route_as_loop(&peer, &aspath(vec![65100, 65001, 65001])));
+        assert!(!route_as_loop(&peer, &aspath(vec![65100, 65001])));
     }
 }
@@ -0,0 +1,441 @@
+pub mod rtr;
+use rtr::{rtr_client, RtrEvent, RTR_PORT};
+
+use super::handler::Bgp;
+use super::packet::{As4PathAttr, Attribute, Attrs};
+use super::ptree::{Prefix, PrefixTree};
+use super::route::{route_policy_update, Route};
+use super::task::Task;
+use crate::config::Args;
+use crate::policy::PolicyDirection;
+use ipnet::IpNet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Instant;
+
+// Route origin validation state (RFC 6811).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RpkiState {
+    Valid,
+    Invalid,
+    #[default]
+    NotFound,
+}
+
+impl FromStr for RpkiState {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "valid" => Ok(Self::Valid),
+            "invalid" => Ok(Self::Invalid),
+            "not-found" => Ok(Self::NotFound),
+            _ => Err(()),
+        }
+    }
+}
+
+impl RpkiState {
+    pub fn code(&self) -> &str {
+        match self {
+            Self::Valid => "V",
+            Self::Invalid => "I",
+            Self::NotFound => "N",
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Valid => "valid",
+            Self::Invalid => "invalid",
+            Self::NotFound => "not-found",
+        }
+    }
+}
+
+// Validated ROA Payload.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Vrp {
+    pub prefix: IpNet,
+    pub max_len: u8,
+    pub asn: u32,
+}
+
+// VRPs of all of the caches. The same VRP from multiple caches is counted.
+#[derive(Debug, Default)]
+pub struct VrpTable {
+    map: BTreeMap<IpNet, BTreeMap<(u8, u32), usize>>,
+    len: usize,
+}
+
+impl VrpTable {
+    pub fn add(&mut self, vrp: &Vrp) {
+        let count = self
+            .map
+            .entry(vrp.prefix)
+            .or_default()
+            .entry((vrp.max_len, vrp.asn))
+            .or_default();
+        if *count == 0 {
+            self.len += 1;
+        }
+        *count += 1;
+    }
+
+    pub fn remove(&mut self, vrp: &Vrp) {
+        let vrps = match self.map.get_mut(&vrp.prefix) {
+            Some(vrps) => vrps,
+            None => return,
+        };
+        if let Some(count) = vrps.get_mut(&(vrp.max_len, vrp.asn)) {
+            *count -= 1;
+            if *count == 0 {
+                vrps.remove(&(vrp.max_len, vrp.asn));
+                self.len -= 1;
+            }
+        }
+        if vrps.is_empty() {
+            self.map.remove(&vrp.prefix);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Valid when a VRP covering the prefix matches the origin AS and the
+    // length, Invalid when covered only by unmatched VRPs. AS 0 never
+    // matches (RFC 6483 4).
+    pub fn validate(&self, prefix: &IpNet, origin: Option<u32>) -> RpkiState {
+        let mut covered = false;
+        for len in 0..=prefix.prefix_len() {
+            let supernet = match IpNet::new(prefix.addr(), len) {
+                Ok(supernet) => supernet.trunc(),
+                Err(_) => continue,
+            };
+            let vrps = match self.map.get(&supernet) {
+                Some(vrps) => vrps,
+                None => continue,
+            };
+            covered = true;
+            let matched = vrps.keys().any(|(max_len, asn)| {
+                prefix.prefix_len() <= *max_len && *asn != 0 && Some(*asn) == origin
+            });
+            if matched {
+                return RpkiState::Valid;
+            }
+        }
+        if covered {
+            RpkiState::Invalid
+        } else {
+            RpkiState::NotFound
+        }
+    }
+}
+
+// Origin AS of the route. Route with empty AS_PATH is originated in our AS,
+// AS_SET at the end leaves no origin.
+pub fn rpki_origin(attrs: &Attrs, local_as: u32) -> Option<u32> {
+    let aspath = attrs.iter().find_map(|attr| match attr {
+        Attribute::AsPath(aspath) => Some(As4PathAttr::from(aspath)),
+        Attribute::As4Path(aspath) => Some(aspath.clone()),
+        _ => None,
+    });
+    match aspath {
+        Some(aspath) if !aspath.segments.is_empty() => aspath.origin_as(),
+        _ => Some(local_as),
+    }
+}
+
+#[derive(Debug)]
+pub struct RpkiCache {
+    pub port: u16,
+    pub task: Option<Task<()>>,
+    pub vrps: BTreeSet<Vrp>,
+    pub up: bool,
+    pub session: Option<u16>,
+    pub serial: Option<u32>,
+    pub updated: Option<Instant>,
+    pub error: Option<String>,
+}
+
+impl Default for RpkiCache {
+    fn default() -> Self {
+        Self {
+            port: RTR_PORT,
+            task: None,
+            vrps: BTreeSet::new(),
+            up: false,
+            session: None,
+            serial: None,
+            updated: None,
+            error: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Rpki {
+    pub caches: BTreeMap<IpAddr, RpkiCache>,
+    pub table: VrpTable,
+}
+
+impl Rpki {
+    pub fn state(&self, prefix: &IpNet, attrs: &Attrs, local_as: u32) -> RpkiState {
+        if self.table.is_empty() {
+            return RpkiState::NotFound;
+        }
+        self.table.validate(prefix, rpki_origin(attrs, local_as))
+    }
+}
+
+// Client is restarted from scratch, VRPs of the cache are replaced when the
+// new session is synchronized.
+pub fn rpki_cache_start(bgp: &mut Bgp, addr: IpAddr) {
+    let tx = bgp.tx.clone();
+    if let Some(cache) = bgp.rpki.caches.get_mut(&addr) {
+        cache.up = false;
+        cache.task = Some(Task::spawn(rtr_client(addr, cache.port, tx)));
+    }
+}
+
+pub fn rpki_cache_add(bgp: &mut Bgp, addr: IpAddr) {
+    if !bgp.rpki.caches.contains_key(&addr) {
+        bgp.rpki.caches.insert(addr, RpkiCache::default());
+        rpki_cache_start(bgp, addr);
+    }
+}
+
+pub fn rpki_cache_delete(bgp: &mut Bgp, addr: IpAddr) {
+    let mut cache = match bgp.rpki.caches.remove(&addr) {
+        Some(cache) => cache,
+        None => return,
+    };
+    let mut prefixes = BTreeSet::new();
+    rpki_flush(&mut bgp.rpki.table, &mut cache.vrps, &mut prefixes);
+    rpki_revalidate(bgp, &prefixes);
+}
+
+fn rpki_flush(table: &mut VrpTable, vrps: &mut BTreeSet<Vrp>, prefixes: &mut BTreeSet<IpNet>) {
+    for vrp in std::mem::take(vrps).iter() {
+        table.remove(vrp);
+        prefixes.insert(vrp.prefix);
+    }
+}
+
+pub fn rpki_recv(bgp: &mut Bgp, addr: IpAddr, event: RtrEvent) {
+    let cache = match bgp.rpki.caches.get_mut(&addr) {
+        Some(cache) => cache,
+        None => return,
+    };
+    let table = &mut bgp.rpki.table;
+    let mut prefixes = BTreeSet::new();
+    match event {
+        RtrEvent::Up => {
+            println!("rpki: cache {} is up", addr);
+            cache.up = true;
+            cache.error = None;
+        }
+        RtrEvent::Down(err) => {
+            println!("rpki: cache {} is down: {}", addr, err);
+            cache.up = false;
+            cache.error = Some(err);
+        }
+        RtrEvent::Expired => {
+            println!("rpki: VRPs of cache {} are expired", addr);
+            rpki_flush(table, &mut cache.vrps, &mut prefixes);
+            cache.session = None;
+            cache.serial = None;
+            cache.updated = None;
+        }
+        RtrEvent::Update {
+            reset,
+            session,
+            serial,
+            announce,
+            withdraw,
+        } => {
+            if reset {
+                rpki_flush(table, &mut cache.vrps, &mut prefixes);
+            }
+            for vrp in withdraw.iter() {
+                if cache.vrps.remove(vrp) {
+                    table.remove(vrp);
+                    prefixes.insert(vrp.prefix);
+                }
+            }
+            for vrp in announce.into_iter() {
+                if !cache.vrps.contains(&vrp) {
+                    table.add(&vrp);
+                    prefixes.insert(vrp.prefix);
+                    cache.vrps.insert(vrp);
+                }
+            }
+            cache.session = Some(session);
+            cache.serial = Some(serial);
+            cache.updated = Some(Instant::now());
+        }
+    }
+    rpki_revalidate(bgp, &prefixes);
+}
+
+// Update the state of the routes under the prefix. Origin of the route from
+// the peer is checked with the local AS of the session.
+fn rpki_retag<P>(
+    ptree: &mut PrefixTree<P, Vec<Route>>,
+    prefix: &P,
+    state: impl Fn(&P, &Route) -> RpkiState,
+) -> BTreeSet<IpAddr>
+where
+    P: Prefix,
+{
+    let mut updates = Vec::new();
+    for (p, routes) in ptree.subtree(prefix) {
+        for (i, route) in routes.iter().enumerate() {
+            let state = state(p, route);
+            if state != route.rpki {
+                updates.push((*p, i, state));
+            }
+        }
+    }
+    let mut from = BTreeSet::new();
+    for (p, i, state) in updates.into_iter() {
+        if let Some(route) = ptree.get_mut(&p).and_then(|routes| routes.get_mut(i)) {
+            route.rpki = state;
+            from.insert(route.from);
+        }
+    }
+    from
+}
+
+// Routes covered by the changed VRPs are validated again. Inbound route map
+// of the peer is applied again when it matches on the state.
+fn rpki_revalidate(bgp: &mut Bgp, prefixes: &BTreeSet<IpNet>) {
+    let rpki = &bgp.rpki;
+    let peers = &bgp.peers;
+    let asn = bgp.asn;
+    let state = |prefix: IpNet, route: &Route| {
+        let local_as = peers.get(&route.from).map_or(asn, |peer| peer.local_as);
+        rpki.state(&prefix, &route.attrs, local_as)
+    };
+    let mut from = BTreeSet::new();
+    for prefix in prefixes.iter() {
+        match prefix {
+            IpNet::V4(prefix) => from.extend(rpki_retag(&mut bgp.ptree, prefix, |p, route| {
+                state(IpNet::V4(*p), route)
+            })),
+            IpNet::V6(prefix) => from.extend(rpki_retag(&mut bgp.ptree6, prefix, |p, route| {
+                state(IpNet::V6(*p), route)
+            })),
+        }
+    }
+    for addr in from.iter() {
+        let uses_rpki = bgp.peers.get(addr).map_or(false, |peer| {
+            peer.config
+                .route_map_in
+                .as_ref()
+                .map_or(false, |name| bgp.policy.route_map_uses_rpki(name))
+        });
+        if uses_rpki {
+            bgp.policy_peers.insert((*addr, PolicyDirection::In));
+        }
+    }
+    if !bgp.policy_peers.is_empty() {
+        route_policy_update(bgp);
+    }
+}
+
+fn show_ago(instant: Option<Instant>) -> String {
+    match instant {
+        Some(instant) => format!("{}s ago", instant.elapsed().as_secs()),
+        None => String::from("never"),
+    }
+}
+
+pub fn show_bgp_rpki(bgp: &Bgp, _args: Args) -> String {
+    let mut buf = String::new();
+    writeln!(
+        buf,
+        "{:40} {:>5} {:5} {:>7} {:>10} {:>8} Updated",
+        "Cache", "Port", "State", "Session", "Serial", "VRPs"
+    )
+    .unwrap();
+    for (addr, cache) in bgp.rpki.caches.iter() {
+        writeln!(
+            buf,
+            "{:40} {:>5} {:5} {:>7} {:>10} {:>8} {}",
+            addr.to_string(),
+            cache.port,
+            if cache.up { "Up" } else { "Down" },
+            cache.session.map_or(String::from("-"), |x| x.to_string()),
+            cache.serial.map_or(String::from("-"), |x| x.to_string()),
+            cache.vrps.len(),
+            show_ago(cache.updated),
+        )
+        .unwrap();
+        if let Some(err) = &cache.error {
+            writeln!(buf, "  Last error: {}", err).unwrap();
+        }
+    }
+    writeln!(buf, "Total VRPs: {}", bgp.rpki.table.len()).unwrap();
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{As4Segment, AS_SEQUENCE, AS_SET};
+
+    #[test]
+    fn rpki_validate() {
+        let vrp = |prefix: &str, max_len, asn| Vrp {
+            prefix: prefix.parse().unwrap(),
+            max_len,
+            asn,
+        };
+        let mut table = VrpTable::default();
+        table.add(&vrp("10.0.0.0/8", 16, 65001));
+        table.add(&vrp("10.0.0.0/8", 16, 65001));
+        table.add(&vrp("10.1.0.0/16", 24, 65002));
+        table.add(&vrp("192.0.2.0/24", 24, 0));
+        table.add(&vrp("2001:db8::/32", 48, 65003));
+        assert_eq!(table.len(), 4);
+
+        let validate = |prefix: &str, origin| table.validate(&prefix.parse().unwrap(), origin);
+        assert_eq!(validate("10.0.0.0/8", Some(65001)), RpkiState::Valid);
+        assert_eq!(validate("10.2.0.0/16", Some(65001)), RpkiState::Valid);
+        // Longer than the max length of the VRP.
+        assert_eq!(validate("10.2.1.0/24", Some(65001)), RpkiState::Invalid);
+        // Covered by the VRPs of both ASes.
+        assert_eq!(validate("10.1.1.0/24", Some(65002)), RpkiState::Valid);
+        assert_eq!(validate("10.1.0.0/16", Some(65001)), RpkiState::Valid);
+        assert_eq!(validate("10.1.1.0/24", Some(65001)), RpkiState::Invalid);
+        assert_eq!(validate("10.1.1.0/24", None), RpkiState::Invalid);
+        assert_eq!(validate("192.0.2.0/24", Some(0)), RpkiState::Invalid);
+        assert_eq!(validate("172.16.0.0/12", Some(65001)), RpkiState::NotFound);
+        assert_eq!(validate("2001:db8:1::/48", Some(65003)), RpkiState::Valid);
+        assert_eq!(validate("2001:db8::/64", Some(65003)), RpkiState::Invalid);
+
+        // VRP is kept until withdrawn by all of the caches.
+        table.remove(&vrp("10.0.0.0/8", 16, 65001));
+        assert_eq!(validate("10.0.0.0/8", Some(65001)), RpkiState::Valid);
+        table.remove(&vrp("10.0.0.0/8", 16, 65001));
+        assert_eq!(validate("10.0.0.0/8", Some(65001)), RpkiState::NotFound);
+        assert_eq!(table.len(), 3);
+
+        let aspath = |typ, asn: Vec<u32>| {
+            vec![Attribute::As4Path(As4PathAttr {
+                segments: vec![As4Segment { typ, asn }],
+            })]
+        };
+        assert_eq!(rpki_origin(&aspath(AS_SEQUENCE, vec![1, 2]), 100), Some(2));
+        assert_eq!(rpki_origin(&aspath(AS_SET, vec![1, 2]), 100), None);
+        assert_eq!(rpki_origin(&Vec::new(), 100), Some(100));
+    }
+}
@@ -0,0 +1,390 @@
+use super::Vrp;
+use crate::bgp::handler::Message;
+use bytes::{Buf, BufMut, BytesMut};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::{sleep, sleep_until, timeout, Instant};
+
+// RPKI to Router protocol (RFC 8210).
+pub const RTR_PORT: u16 = 323;
+const RTR_VERSION: u8 = 1;
+const RTR_HEADER_LEN: usize = 8;
+// Router Key PDU is the largest one, far smaller than this.
+const RTR_PDU_MAX: usize = 65536;
+const RTR_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const PDU_SERIAL_NOTIFY: u8 = 0;
+const PDU_SERIAL_QUERY: u8 = 1;
+const PDU_RESET_QUERY: u8 = 2;
+const PDU_CACHE_RESPONSE: u8 = 3;
+const PDU_IPV4_PREFIX: u8 = 4;
+const PDU_IPV6_PREFIX: u8 = 6;
+const PDU_END_OF_DATA: u8 = 7;
+const PDU_CACHE_RESET: u8 = 8;
+const PDU_ERROR_REPORT: u8 = 10;
+
+const PREFIX_FLAG_ANNOUNCE: u8 = 0x01;
+
+const ERR_UNSUPPORTED_VERSION: u16 = 4;
+
+// Default intervals until the cache tells them in End of Data (RFC 8210 6).
+const RTR_REFRESH: u64 = 3600;
+const RTR_RETRY: u64 = 600;
+const RTR_EXPIRE: u64 = 7200;
+
+#[derive(Debug, PartialEq)]
+pub enum RtrPdu {
+    SerialNotify,
+    CacheResponse(u16),
+    Prefix(bool, Vrp),
+    // Session ID and serial, with refresh, retry and expire intervals in
+    // version 1.
+    EndOfData(u16, u32, Option<(u32, u32, u32)>),
+    CacheReset,
+    ErrorReport(u16, String),
+    // Router Key and unknown PDUs are not used.
+    Other(u8),
+}
+
+// Sent to BGP. VRPs of the cache are replaced when reset, otherwise they are
+// the difference from the previous serial.
+#[derive(Debug)]
+pub enum RtrEvent {
+    Up,
+    Down(String),
+    Update {
+        reset: bool,
+        session: u16,
+        serial: u32,
+        announce: Vec<Vrp>,
+        withdraw: Vec<Vrp>,
+    },
+    // Data of the cache is not refreshed within the expire interval.
+    Expired,
+}
+
+fn rtr_header(buf: &mut BytesMut, version: u8, typ: u8, session: u16, length: u32) {
+    buf.put_u8(version);
+    buf.put_u8(typ);
+    buf.put_u16(session);
+    buf.put_u32(length);
+}
+
+pub fn rtr_reset_query(version: u8) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(RTR_HEADER_LEN);
+    rtr_header(&mut buf, version, PDU_RESET_QUERY, 0, RTR_HEADER_LEN as u32);
+    buf
+}
+
+pub fn rtr_serial_query(version: u8, session: u16, serial: u32) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(RTR_HEADER_LEN + 4);
+    rtr_header(&mut buf, version, PDU_SERIAL_QUERY, session, 12);
+    buf.put_u32(serial);
+    buf
+}
+
+fn rtr_prefix(pdu: &mut impl Buf, v4: bool) -> Result<(bool, Vrp), String> {
+    let flags = pdu.get_u8();
+    let len = pdu.get_u8();
+    let max_len = pdu.get_u8();
+    pdu.advance(1);
+    let prefix = if v4 {
+        let addr = Ipv4Addr::from(pdu.get_u32());
+        Ipv4Net::new(addr, len).map(IpNet::V4)
+    } else {
+        let addr = Ipv6Addr::from(pdu.get_u128());
+        Ipv6Net::new(addr, len).map(IpNet::V6)
+    };
+    let prefix = prefix.map_err(|_| format!("invalid prefix length {}", len))?;
+    if max_len < len || max_len > prefix.max_prefix_len() {
+        return Err(format!("invalid max length {} of {}", max_len, prefix));
+    }
+    let vrp = Vrp {
+        prefix: prefix.trunc(),
+        max_len,
+        asn: pdu.get_u32(),
+    };
+    Ok((flags & PREFIX_FLAG_ANNOUNCE != 0, vrp))
+}
+
+fn rtr_error_report(pdu: &mut impl Buf, code: u16) -> Option<RtrPdu> {
+    if pdu.remaining() < 4 {
+        return None;
+    }
+    let len = pdu.get_u32() as usize;
+    if pdu.remaining() < len + 4 {
+        return None;
+    }
+    pdu.advance(len);
+    let len = pdu.get_u32() as usize;
+    if pdu.remaining() < len {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&pdu.chunk()[..len]).to_string();
+    Some(RtrPdu::ErrorReport(code, text))
+}
+
+// Take a PDU from the head of the buffer. None until the whole PDU is
+// received.
+pub fn rtr_parse(buf: &mut BytesMut) -> Result<Option<RtrPdu>, String> {
+    if buf.len() < RTR_HEADER_LEN {
+        return Ok(None);
+    }
+    let length = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]) as usize;
+    if !(RTR_HEADER_LEN..=RTR_PDU_MAX).contains(&length) {
+        return Err(format!("invalid PDU length {}", length));
+    }
+    if buf.len() < length {
+        return Ok(None);
+    }
+    let mut pdu = buf.split_to(length).freeze();
+    let version = pdu.get_u8();
+    let typ = pdu.get_u8();
+    let session = pdu.get_u16();
+    pdu.advance(4);
+    if version > RTR_VERSION {
+        return Err(format!("unsupported version {}", version));
+    }
+    let short = || format!("PDU type {} length {} is too short", typ, length);
+    let pdu = match typ {
+        PDU_SERIAL_NOTIFY => RtrPdu::SerialNotify,
+        PDU_CACHE_RESPONSE => RtrPdu::CacheResponse(session),
+        PDU_IPV4_PREFIX | PDU_IPV6_PREFIX => {
+            let v4 = typ == PDU_IPV4_PREFIX;
+            let need = if v4 { 12 } else { 24 };
+            if pdu.remaining() < need {
+                return Err(short());
+            }
+            let (announce, vrp) = rtr_prefix(&mut pdu, v4)?;
+            RtrPdu::Prefix(announce, vrp)
+        }
+        PDU_END_OF_DATA => {
+            if pdu.remaining() < 4 {
+                return Err(short());
+            }
+            let serial = pdu.get_u32();
+            let intervals = if version > 0 && pdu.remaining() >= 12 {
+                Some((pdu.get_u32(), pdu.get_u32(), pdu.get_u32()))
+            } else {
+                None
+            };
+            RtrPdu::EndOfData(session, serial, intervals)
+        }
+        PDU_CACHE_RESET => RtrPdu::CacheReset,
+        PDU_ERROR_REPORT => rtr_error_report(&mut pdu, session).ok_or_else(short)?,
+        _ => RtrPdu::Other(typ),
+    };
+    Ok(Some(pdu))
+}
+
+// Session state is kept across connections, so that the client resumes
+// with Serial Query after reconnecting to the cache.
+struct RtrClient {
+    addr: SocketAddr,
+    version: u8,
+    session: Option<u16>,
+    serial: u32,
+    refresh: Duration,
+    retry: Duration,
+    expire: Duration,
+    updated: Option<Instant>,
+    tx: UnboundedSender<Message>,
+}
+
+impl RtrClient {
+    fn send(&self, event: RtrEvent) {
+        let _ = self.tx.send(Message::Rpki(self.addr.ip(), event));
+    }
+
+    async fn query(&self, stream: &mut TcpStream, reset: bool) -> Result<(), String> {
+        let query = match self.session {
+            Some(session) if !reset => rtr_serial_query(self.version, session, self.serial),
+            _ => rtr_reset_query(self.version),
+        };
+        stream
+            .write_all(&query)
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    async fn session(&mut self) -> Result<(), String> {
+        let mut stream = match timeout(RTR_CONNECT_TIMEOUT, TcpStream::connect(self.addr)).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(err)) => return Err(err.to_string()),
+            Err(_) => return Err(String::from("connect timeout")),
+        };
+        self.send(RtrEvent::Up);
+        let mut reset = self.session.is_none();
+        self.query(&mut stream, reset).await?;
+        // Query is outstanding until End of Data.
+        let mut pending = true;
+        let mut refresh = Instant::now() + self.refresh;
+        let mut buf = BytesMut::with_capacity(4096);
+        let mut announce = Vec::new();
+        let mut withdraw = Vec::new();
+        loop {
+            tokio::select! {
+                result = stream.read_buf(&mut buf) => {
+                    match result {
+                        Ok(0) => return Err(String::from("connection closed")),
+                        Ok(_) => {}
+                        Err(err) => return Err(err.to_string()),
+                    }
+                    while let Some(pdu) = rtr_parse(&mut buf)? {
+                        match pdu {
+                            RtrPdu::SerialNotify => {
+                                if !pending {
+                                    self.query(&mut stream, reset).await?;
+                                    pending = true;
+                                }
+                            }
+                            RtrPdu::CacheResponse(session) => {
+                                // Serials of the other session are meaningless.
+                                if !reset && self.session != Some(session) {
+                                    self.session = None;
+                                    return Err(String::from("session ID changed"));
+                                }
+                                self.session = Some(session);
+                                announce.clear();
+                                withdraw.clear();
+                            }
+                            RtrPdu::Prefix(true, vrp) => announce.push(vrp),
+                            RtrPdu::Prefix(false, vrp) => withdraw.push(vrp),
+                            RtrPdu::EndOfData(session, serial, intervals) => {
+                                if let Some((refresh, retry, expire)) = intervals {
+                                    self.refresh = Duration::from_secs(refresh as u64);
+                                    self.retry = Duration::from_secs(retry as u64);
+                                    self.expire = Duration::from_secs(expire as u64);
+                                }
+                                self.serial = serial;
+                                self.send(RtrEvent::Update {
+                                    reset,
+                                    session,
+                                    serial,
+                                    announce: std::mem::take(&mut announce),
+                                    withdraw: std::mem::take(&mut withdraw),
+                                });
+                                self.updated = Some(Instant::now());
+                                reset = false;
+                                pending = false;
+                                refresh = Instant::now() + self.refresh;
+                            }
+                            RtrPdu::CacheReset => {
+                                reset = true;
+                                self.query(&mut stream, reset).await?;
+                                pending = true;
+                            }
+                            RtrPdu::ErrorReport(code, text) => {
+                                // Cache only speaks version 0.
+                                if code == ERR_UNSUPPORTED_VERSION && self.version > 0 {
+                                    self.version = 0;
+                                }
+                                return Err(format!("error report {}: {}", code, text));
+                            }
+                            RtrPdu::Other(_) => {}
+                        }
+                    }
+                }
+                _ = sleep_until(refresh) => {
+                    if !pending {
+                        self.query(&mut stream, reset).await?;
+                        pending = true;
+                    }
+                    refresh = Instant::now() + self.refresh;
+                }
+            }
+        }
+    }
+}
+
+// Connect to the cache and report the VRPs to BGP until the task is dropped.
+// VRPs are kept while reconnecting, until the expire interval passes.
+pub async fn rtr_client(addr: IpAddr, port: u16, tx: UnboundedSender<Message>) {
+    let mut client = RtrClient {
+        addr: SocketAddr::new(addr, port),
+        version: RTR_VERSION,
+        session: None,
+        serial: 0,
+        refresh: Duration::from_secs(RTR_REFRESH),
+        retry: Duration::from_secs(RTR_RETRY),
+        expire: Duration::from_secs(RTR_EXPIRE),
+        updated: None,
+        tx,
+    };
+    loop {
+        if let Err(err) = client.session().await {
+            client.send(RtrEvent::Down(err));
+        }
+        let mut wait = client.retry;
+        if let Some(updated) = client.updated {
+            let left = client.expire.saturating_sub(updated.elapsed());
+            if left.is_zero() {
+                client.send(RtrEvent::Expired);
+                client.updated = None;
+                client.session = None;
+            } else {
+                wait = wait.min(left);
+            }
+        }
+        sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rtr_parse_pdus() {
+        let mut buf = BytesMut::new();
+        rtr_header(&mut buf, 1, PDU_CACHE_RESPONSE, 7, 8);
+        rtr_header(&mut buf, 1, PDU_IPV4_PREFIX, 0, 20);
+        buf.put_u8(PREFIX_FLAG_ANNOUNCE);
+        buf.put_slice(&[16, 24, 0]);
+        buf.put_slice(&[192, 0, 2, 1]);
+        buf.put_u32(65001);
+        rtr_header(&mut buf, 1, PDU_IPV6_PREFIX, 0, 32);
+        buf.put_slice(&[0, 32, 48, 0]);
+        buf.put_u128(u128::from("2001:db8::".parse::<Ipv6Addr>().unwrap()));
+        buf.put_u32(65002);
+        rtr_header(&mut buf, 1, PDU_END_OF_DATA, 7, 24);
+        for value in [42, 900, 300, 3600] {
+            buf.put_u32(value);
+        }
+        // Partially received PDU is left in the buffer.
+        buf.put_slice(&[1, PDU_CACHE_RESET, 0]);
+
+        let mut pdus = Vec::new();
+        while let Some(pdu) = rtr_parse(&mut buf).unwrap() {
+            pdus.push(pdu);
+        }
+        let vrp = |prefix: &str, max_len, asn| Vrp {
+            prefix: prefix.parse().unwrap(),
+            max_len,
+            asn,
+        };
+        assert_eq!(
+            pdus,
+            vec![
+                RtrPdu::CacheResponse(7),
+                RtrPdu::Prefix(true, vrp("192.0.0.0/16", 24, 65001)),
+                RtrPdu::Prefix(false, vrp("2001:db8::/32", 48, 65002)),
+                RtrPdu::EndOfData(7, 42, Some((900, 300, 3600))),
+            ]
+        );
+        assert_eq!(buf.len(), 3);
+
+        buf.put_slice(&[0, 0, 0, 0, 8]);
+        assert_eq!(rtr_parse(&mut buf).unwrap(), Some(RtrPdu::CacheReset));
+
+        // Max length shorter than the prefix.
+        rtr_header(&mut buf, 1, PDU_IPV4_PREFIX, 0, 20);
+        buf.put_slice(&[1, 24, 16, 0, 192, 0, 2, 0]);
+        buf.put_u32(65001);
+        assert!(rtr_parse(&mut buf).is_err());
+    }
+}
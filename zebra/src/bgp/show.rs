@@ -1,26 +1,90 @@
+use super::adj_rib::AdjRib;
+use super::clear::{
+    clear_bgp_neighbor_shutdown, clear_bgp_neighbor_soft_in, clear_bgp_neighbor_soft_out,
+};
+use super::export::request_export_bgp_table;
+use super::family::show_bgp_families;
+use super::flowspec::show_bgp_flowspec;
+use super::gshut::{request_bgp_graceful_shutdown, request_bgp_neighbor_maintenance};
 use super::handler::{Bgp, ShowCallback};
-use super::packet::BgpType;
-use super::peer::{Peer, PeerCounter, PeerParam};
+use super::health::show_bgp_health_check;
+use super::latency::show_bgp_latency;
+use super::network::ROUTE_LOCAL;
+use super::packet::{
+    notification_str, shutdown_message_decode, Attrs, BgpType, CommunityAttr, ExtendedComAttr,
+    LargeComAttr,
+};
+use super::peer::{Peer, PeerCounter, PeerParam, PeerSlaStat, ResetDirection};
+use super::resource::show_system_resources;
+use super::rib::{rib_reason, Bestpath};
+use super::route::{AsPathCache, Route};
+use super::rpki::{show_bgp_rpki, RpkiState};
+use super::vpn::{show_bgp_vpn, show_bgp_vrf};
+use super::watch::show_bgp_origin_watch;
+use super::Afi;
 use crate::config::Args;
+use crate::policy::irr::request_policy_generate;
+use crate::policy::{aspath_regex, PolicyAction};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use serde::Serialize;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Write;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
 use std::time::Instant;
 
-fn show_peer_summary(buf: &mut String, peer: &Peer) {
+// Output of the table dump commands kept with the version of the table, so
+// that repeated queries are answered without walking the table until it
+// changes.
+#[derive(Debug, Default)]
+pub struct ShowCache {
+    enabled: bool,
+    entries: RefCell<HashMap<&'static str, (u64, String)>>,
+}
+
+impl ShowCache {
+    pub fn enable(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.entries.get_mut().clear();
+        }
+    }
+
+    fn get<F: FnOnce() -> String>(&self, key: &'static str, version: u64, f: F) -> String {
+        if !self.enabled {
+            return f();
+        }
+        if let Some((cached, output)) = self.entries.borrow().get(key) {
+            if *cached == version {
+                return output.clone();
+            }
+        }
+        let output = f();
+        self.entries
+            .borrow_mut()
+            .insert(key, (version, output.clone()));
+        output
+    }
+}
+
+fn show_peer_summary(buf: &mut String, peer: &Peer, name: Option<&str>) {
     let mut sent: u64 = 0;
     let mut rcvd: u64 = 0;
     for counter in peer.counter.iter() {
         sent += counter.sent;
         rcvd += counter.rcvd;
     }
-    writeln!(
+    write!(
         buf,
         "{:16} {:11} {:8} {:8}",
         peer.address, peer.peer_as, rcvd, sent,
     )
     .unwrap();
+    match name {
+        Some(name) => writeln!(buf, " {}", name).unwrap(),
+        None => writeln!(buf).unwrap(),
+    }
 }
 
 fn show_bgp_instance(bgp: &Bgp) -> String {
@@ -41,6 +105,24 @@ fn show_bgp_instance(bgp: &Bgp) -> String {
         identifier, asn
     )
     .unwrap();
+    if bgp.monitor_only {
+        writeln!(buf, "BGP is running in monitor-only mode").unwrap();
+    }
+    if bgp.graceful_shutdown {
+        writeln!(buf, "Graceful shutdown in progress").unwrap();
+    }
+    if bgp.shutdown.is_some() {
+        writeln!(buf, "BGP is administratively shut down").unwrap();
+    }
+    let maintenance: Vec<String> = bgp
+        .peers
+        .values()
+        .filter(|peer| peer.maintenance)
+        .map(|peer| peer.address.to_string())
+        .collect();
+    if !maintenance.is_empty() {
+        writeln!(buf, "Neighbors in maintenance: {}", maintenance.join(", ")).unwrap();
+    }
     writeln!(buf).unwrap();
 
     if bgp.peers.is_empty() {
@@ -52,7 +134,7 @@ fn show_bgp_instance(bgp: &Bgp) -> String {
         )
         .unwrap();
         for (_, peer) in bgp.peers.iter() {
-            show_peer_summary(&mut buf, peer);
+            show_peer_summary(&mut buf, peer, bgp.as_names.get(peer.peer_as));
         }
     }
 
@@ -69,13 +151,314 @@ RPKI validation codes: V valid, I invalid, N Not found
      Network          Next Hop            Metric LocPrf Weight Path
 "#;
 
-fn show_bgp_route(bgp: &Bgp) -> String {
+fn show_route_entry(buf: &mut String, prefix: &Ipv4Net, route: &Route, aspath: &str) {
+    let nexthop = match route.nexthop() {
+        Some(nexthop) => nexthop.to_string(),
+        None => "0.0.0.0".to_string(),
+    };
+    show_route_line(buf, prefix.to_string(), nexthop, route, aspath);
+}
+
+fn show_route_entry6(buf: &mut String, prefix: &Ipv6Net, route: &Route, aspath: &str) {
+    let nexthop = match route.nexthop6() {
+        Some(nexthop) => nexthop.to_string(),
+        None => "::".to_string(),
+    };
+    show_route_line(buf, prefix.to_string(), nexthop, route, aspath);
+}
+
+fn show_route_line(buf: &mut String, prefix: String, nexthop: String, route: &Route, aspath: &str) {
+    let valid = if route.selected {
+        "*>"
+    } else if route.multipath {
+        "*="
+    } else if route.resolved {
+        "* "
+    } else {
+        "  "
+    };
+    let internal = if route.ibgp { "i" } else { " " };
+    let med = match route.med() {
+        Some(med) => med.to_string(),
+        None => String::new(),
+    };
+    let local_pref = match route.local_pref() {
+        Some(local_pref) => local_pref.to_string(),
+        None => String::new(),
+    };
+    let space = if aspath.is_empty() { "" } else { " " };
+    writeln!(
+        buf,
+        "{}{}{} {:16} {:18} {:>7} {:>6} {:>6} {}{}{}",
+        valid,
+        internal,
+        route.rpki.code(),
+        prefix,
+        nexthop,
+        med,
+        local_pref,
+        route.weight,
+        aspath,
+        space,
+        route.origin(),
+    )
+    .unwrap();
+}
+
+// Routes of Loc-RIB of the address family which match the filter. Filter
+// is given the rendered AS path of the route as well.
+fn show_bgp_route_filter<F>(bgp: &Bgp, afi: &Afi, filter: F) -> String
+where
+    F: Fn(&Route, &str) -> bool,
+{
     let mut buf = String::new();
+    let mut aspaths = AsPathCache::default();
 
     buf.push_str(SHOW_BGP_HEADER);
 
-    for (key, _value) in bgp.ptree.iter() {
-        writeln!(buf, "{}", key).unwrap();
+    if *afi == Afi::IP6 {
+        for (prefix, routes) in bgp.ptree6.iter() {
+            for route in routes.iter() {
+                let aspath = aspaths.get(route);
+                if filter(route, aspath) {
+                    show_route_entry6(&mut buf, prefix, route, aspath);
+                }
+            }
+        }
+    } else {
+        for (prefix, routes) in bgp.ptree.iter() {
+            for route in routes.iter() {
+                let aspath = aspaths.get(route);
+                if filter(route, aspath) {
+                    show_route_entry(&mut buf, prefix, route, aspath);
+                }
+            }
+        }
+    }
+    buf
+}
+
+// Routes which have all of the communities.
+fn show_bgp_community(bgp: &Bgp, mut args: Args, afi: &Afi) -> String {
+    let community = match args.string().map(|x| CommunityAttr::from_str(&x)) {
+        Some(Ok(community)) => community,
+        _ => return String::from("% Malformed community\n"),
+    };
+    show_bgp_route_filter(bgp, afi, |route, _| match route.community() {
+        Some(attr) => community.0.iter().all(|x| attr.contains(x)),
+        None => false,
+    })
+}
+
+fn show_bgp_extcommunity(bgp: &Bgp, mut args: Args, afi: &Afi) -> String {
+    let ecoms = match args.string().map(|x| ExtendedComAttr::from_str(&x)) {
+        Some(Ok(ecoms)) => ecoms,
+        _ => return String::from("% Malformed extended community\n"),
+    };
+    show_bgp_route_filter(bgp, afi, |route, _| match route.ext_community() {
+        Some(attr) => ecoms.0.iter().all(|x| attr.contains(x)),
+        None => false,
+    })
+}
+
+fn show_bgp_large_community(bgp: &Bgp, mut args: Args, afi: &Afi) -> String {
+    let lcoms = match args.string().map(|x| LargeComAttr::from_str(&x)) {
+        Some(Ok(lcoms)) => lcoms,
+        _ => return String::from("% Malformed large community\n"),
+    };
+    show_bgp_route_filter(bgp, afi, |route, _| match route.large_community() {
+        Some(attr) => lcoms.0.iter().all(|x| attr.contains(x)),
+        None => false,
+    })
+}
+
+fn show_bgp_regexp(bgp: &Bgp, mut args: Args, afi: &Afi) -> String {
+    let regexp = match args.string().and_then(|x| aspath_regex(&x)) {
+        Some(regexp) => regexp,
+        None => return String::from("% Malformed regular expression\n"),
+    };
+    show_bgp_route_filter(bgp, afi, |_, aspath| regexp.is_match(aspath))
+}
+
+fn show_bgp_filter_list(bgp: &Bgp, mut args: Args, afi: &Afi) -> String {
+    let name = args.string().unwrap_or_default();
+    let aslist = match bgp.policy.aslist.get(&name) {
+        Some(aslist) => aslist,
+        None => return format!("% AS path list {} is not defined\n", name),
+    };
+    show_bgp_route_filter(bgp, afi, |_, aspath| {
+        aslist.apply(aspath) == PolicyAction::Permit
+    })
+}
+
+fn show_bgp_route(bgp: &Bgp) -> String {
+    bgp.show_cache.get("ip", bgp.ptree.version(), || {
+        show_bgp_route_filter(bgp, &Afi::IP, |_, _| true)
+    })
+}
+
+fn show_bgp6(bgp: &Bgp, _args: Args) -> String {
+    bgp.show_cache.get("ipv6", bgp.ptree6.version(), || {
+        show_bgp_route_filter(bgp, &Afi::IP6, |_, _| true)
+    })
+}
+
+fn origin_name(origin: u8) -> &'static str {
+    match origin {
+        0 => "IGP",
+        1 => "EGP",
+        _ => "incomplete",
+    }
+}
+
+// Label stack received with labeled unicast, and the local label advertised
+// with next hop self, e.g. "Remote label 1000/3, local label 16".
+fn show_labels(route: &Route) -> Option<String> {
+    let mut labels = Vec::new();
+    let remote: Vec<String> = route.labels().iter().map(|x| x.to_string()).collect();
+    if !remote.is_empty() {
+        labels.push(format!("remote label {}", remote.join("/")));
+    }
+    if let Some(local) = route.local_label {
+        labels.push(format!("local label {}", local));
+    }
+    if labels.is_empty() {
+        return None;
+    }
+    let labels = labels.join(", ");
+    Some(labels[..1].to_uppercase() + &labels[1..])
+}
+
+// All of the paths of the prefix with the attributes, and the step of the
+// decision process each non-best path lost at.
+fn show_prefix_detail<F>(
+    buf: &mut String,
+    bestpath: &Bestpath,
+    prefix: String,
+    routes: &[Route],
+    nexthop: F,
+) where
+    F: Fn(&Route) -> Option<String>,
+{
+    let best = routes.iter().position(|route| route.selected);
+    writeln!(buf, "BGP routing table entry for {}", prefix).unwrap();
+    let best_str = match best {
+        Some(index) => format!("best #{}", index + 1),
+        None => String::from("no best path"),
+    };
+    writeln!(buf, "Paths: {} available, {}", routes.len(), best_str).unwrap();
+    let best = best.map(|index| &routes[index]);
+    for (index, route) in routes.iter().enumerate() {
+        let from = if route.from == ROUTE_LOCAL {
+            String::from("local")
+        } else {
+            route.from.to_string()
+        };
+        let peer_type = if route.ibgp { "internal" } else { "external" };
+        writeln!(
+            buf,
+            "  Path #{}: from {} ({}), {}",
+            index + 1,
+            from,
+            route.router_id,
+            peer_type
+        )
+        .unwrap();
+        let aspath = route.aspath();
+        let aspath = if aspath.is_empty() {
+            String::from("(empty)")
+        } else {
+            aspath
+        };
+        writeln!(buf, "    AS path: {}", aspath).unwrap();
+        let med = match route.med() {
+            Some(med) => med.to_string(),
+            None => String::from("none"),
+        };
+        writeln!(
+            buf,
+            "    Origin {}, local-pref {}, MED {}, weight {}",
+            origin_name(route.origin_type()),
+            route.local_pref().unwrap_or(bestpath.local_pref),
+            med,
+            route.weight
+        )
+        .unwrap();
+        let reachable = if route.resolved {
+            "reachable"
+        } else {
+            "unreachable"
+        };
+        writeln!(
+            buf,
+            "    Next hop {}, IGP metric {}, {}",
+            nexthop(route).unwrap_or_else(|| String::from("none")),
+            route.metric,
+            reachable
+        )
+        .unwrap();
+        if let Some(labels) = show_labels(route) {
+            writeln!(buf, "    {}", labels).unwrap();
+        }
+        if let Some(community) = route.community() {
+            writeln!(buf, "    Community: {}", community).unwrap();
+        }
+        if let Some(ecom) = route.ext_community() {
+            writeln!(buf, "    Extended community: {}", ecom).unwrap();
+        }
+        if let Some(lcom) = route.large_community() {
+            writeln!(buf, "    Large community: {}", lcom).unwrap();
+        }
+        if let Some(originator_id) = route.originator_id() {
+            writeln!(buf, "    Originator: {}", originator_id).unwrap();
+        }
+        if let Some(cluster_list) = route.cluster_list() {
+            writeln!(buf, "    Cluster list: {}", cluster_list).unwrap();
+        }
+        writeln!(buf, "    RPKI validation: {}", route.rpki.name()).unwrap();
+        let multipath = if route.multipath { ", multipath" } else { "" };
+        let status = match rib_reason(route, best, bestpath) {
+            Some(reason) => format!("Not best: {}{}", reason, multipath),
+            None => String::from("Best path"),
+        };
+        writeln!(buf, "    {}", status).unwrap();
+    }
+}
+
+// show ip bgp prefix PREFIX [detail]
+fn show_bgp_prefix(bgp: &Bgp, mut args: Args) -> String {
+    let prefix = match args.string().map(|x| IpNet::from_str(&x)) {
+        Some(Ok(prefix)) => prefix.trunc(),
+        _ => return String::from("% Malformed prefix\n"),
+    };
+    let mut buf = String::new();
+    match prefix {
+        IpNet::V4(prefix) => {
+            let routes = match bgp.ptree.get(&prefix) {
+                Some(routes) => routes,
+                None => return String::from("% Network not in table\n"),
+            };
+            show_prefix_detail(
+                &mut buf,
+                &bgp.bestpath,
+                prefix.to_string(),
+                routes,
+                |route| route.nexthop().map(|x| x.to_string()),
+            );
+        }
+        IpNet::V6(prefix) => {
+            let routes = match bgp.ptree6.get(&prefix) {
+                Some(routes) => routes,
+                None => return String::from("% Network not in table\n"),
+            };
+            show_prefix_detail(
+                &mut buf,
+                &bgp.bestpath,
+                prefix.to_string(),
+                routes,
+                |route| route.nexthop6().map(|x| x.to_string()),
+            );
+        }
     }
     buf
 }
@@ -90,10 +473,12 @@ fn show_bgp(bgp: &Bgp, args: Args) -> String {
 
 #[derive(Serialize, Debug)]
 struct Neighbor<'a> {
-    address: Ipv4Addr,
+    address: IpAddr,
     peer_type: &'a str,
     local_as: u32,
     remote_as: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_as_name: Option<String>,
     local_router_id: Ipv4Addr,
     remote_router_id: Ipv4Addr,
     state: &'a str,
@@ -102,6 +487,18 @@ struct Neighbor<'a> {
     timer_sent: PeerParam,
     timer_recv: PeerParam,
     count: HashMap<&'a str, PeerCounter>,
+    sla: PeerSlaStat,
+    last_reset: String,
+    idle_hold: u64,
+    flaps: u32,
+    open_error: u64,
+    origin_anomaly: u64,
+    attr_error: BTreeMap<String, u64>,
+    graceful_restart: String,
+    vrf: String,
+    peer_group: String,
+    route_reflector_client: bool,
+    maintenance: bool,
 }
 
 fn uptime(instant: &Option<Instant>) -> String {
@@ -114,20 +511,92 @@ fn uptime(instant: &Option<Instant>) -> String {
     }
 }
 
-fn fetch(peer: &Peer) -> Neighbor {
+// Shutdown Communication of the NOTIFICATION is shown with the reason.
+fn last_reset(peer: &Peer) -> String {
+    let reset = match &peer.last_reset {
+        Some(reset) => reset,
+        None => return String::from("never"),
+    };
+    let mut out = match reset.direction {
+        ResetDirection::Sent => format!(
+            "NOTIFICATION sent ({})",
+            notification_str(reset.code, reset.sub_code)
+        ),
+        ResetDirection::Received => format!(
+            "NOTIFICATION received ({})",
+            notification_str(reset.code, reset.sub_code)
+        ),
+        ResetDirection::Local => String::from("Connection closed"),
+    };
+    if let Some(message) = shutdown_message_decode(reset.code, reset.sub_code, &reset.data) {
+        write!(out, ", message \"{}\"", message).unwrap();
+    }
+    out
+}
+
+fn graceful_restart(peer: &Peer) -> String {
+    let mut status = if !peer.config.graceful_restart.enabled {
+        String::from("disabled")
+    } else if let Some(restart_time) = peer.restart_time {
+        format!("negotiated, remote restart time {} seconds", restart_time)
+    } else {
+        String::from("advertised")
+    };
+    let stale = peer.stale_rib.count();
+    if stale > 0 {
+        write!(status, ", {} stale prefixes", stale).unwrap();
+    }
+    status
+}
+
+fn fetch<'a>(bgp: &Bgp, peer: &'a Peer) -> Neighbor<'a> {
     let mut n = Neighbor {
         address: peer.address.clone(),
         remote_as: peer.peer_as,
-        local_as: peer.local_as,
-        peer_type: peer.peer_type.to_str(),
+        remote_as_name: bgp.as_names.get(peer.peer_as).map(|x| x.to_string()),
+        local_as: peer.migration_as().unwrap_or(peer.local_as),
+        peer_type: if peer.confed_member() {
+            "confed-external"
+        } else {
+            peer.peer_type.to_str()
+        },
         local_router_id: peer.router_id.clone(),
         remote_router_id: peer.remote_id.clone(),
-        state: peer.state.to_str(),
+        state: if peer.shutdown().is_some() {
+            "Idle (Admin)"
+        } else {
+            peer.state.to_str()
+        },
         uptime: uptime(&peer.instant),
         timer: peer.param.clone(),
         timer_sent: peer.param_tx.clone(),
         timer_recv: peer.param_rx.clone(),
         count: HashMap::default(),
+        sla: peer.sla_stat(),
+        last_reset: last_reset(peer),
+        idle_hold: peer.idle_hold.time,
+        flaps: peer.idle_hold.flaps,
+        open_error: peer.open_error,
+        origin_anomaly: peer.origin_anomaly,
+        attr_error: peer
+            .attr_error
+            .iter()
+            .map(|(typ, stat)| (typ.to_string(), stat.total()))
+            .collect(),
+        graceful_restart: graceful_restart(peer),
+        vrf: peer
+            .config
+            .transport
+            .vrf
+            .clone()
+            .unwrap_or(String::from("default")),
+        peer_group: peer
+            .config
+            .peer_group
+            .clone()
+            .unwrap_or(String::from("none")),
+        route_reflector_client: peer.config.route_reflector_client,
+        maintenance: peer.maintenance,
     };
 
     // Timers.
@@ -198,11 +667,22 @@ fn fetch(peer: &Peer) -> Neighbor {
 // }
 
 fn render(neighbor: &Neighbor, out: &mut String) -> anyhow::Result<()> {
+    let mut attr_error = String::new();
+    for (error, count) in neighbor.attr_error.iter() {
+        writeln!(attr_error, "    {}: {}", error, count)?;
+    }
     writeln!(
         out,
-        r#"BGP neighbor is {}, remote AS {}, local AS {}, {} link
+        r#"BGP neighbor is {}, remote AS {}{}, local AS {}, {} link
   BGP version 4, remote router ID {}, local router ID {}
   BGP state = {}, up for {}
+  VRF: {}, peer group: {}
+{}{}  Last reset: {}
+  Idle hold time {} seconds, {} flaps
+  OPEN message errors: {}
+  Origin anomalies: {}
+  Malformed attributes: {}
+{}  Graceful restart: {}
   Last read 00:00:00, Last write 00:00:00
   Hold time {} seconds, keepalive {} seconds
   Sent Hold time {} seconds, sent keepalive {} seconds
@@ -216,15 +696,43 @@ fn render(neighbor: &Neighbor, out: &mut String) -> anyhow::Result<()> {
     Route Refresh:      {:>10}    {:>10}
     Capability:         {:>10}    {:>10}
     Total:              {:>10}    {:>10}
+  Session statistics:
+    Established {} times, total uptime {} seconds
+    Sessions {} in last 30 days, longest {} seconds, average {} seconds
 "#,
         neighbor.address,
         neighbor.remote_as,
+        neighbor
+            .remote_as_name
+            .as_ref()
+            .map(|name| format!(" ({})", name))
+            .unwrap_or_default(),
         neighbor.local_as,
         neighbor.peer_type,
         neighbor.remote_router_id,
         neighbor.local_router_id,
         neighbor.state,
         neighbor.uptime,
+        neighbor.vrf,
+        neighbor.peer_group,
+        if neighbor.route_reflector_client {
+            "  Route-Reflector Client\n"
+        } else {
+            ""
+        },
+        if neighbor.maintenance {
+            "  Maintenance mode, routes are advertised with graceful-shutdown\n"
+        } else {
+            ""
+        },
+        neighbor.last_reset,
+        neighbor.idle_hold,
+        neighbor.flaps,
+        neighbor.open_error,
+        neighbor.origin_anomaly,
+        neighbor.attr_error.values().sum::<u64>(),
+        attr_error,
+        neighbor.graceful_restart,
         neighbor.timer.hold_time,
         neighbor.timer.keepalive,
         neighbor.timer_sent.hold_time,
@@ -245,28 +753,160 @@ fn render(neighbor: &Neighbor, out: &mut String) -> anyhow::Result<()> {
         neighbor.count.get("capability").unwrap().rcvd,
         neighbor.count.get("total").unwrap().sent,
         neighbor.count.get("total").unwrap().rcvd,
+        neighbor.sla.established,
+        neighbor.sla.uptime,
+        neighbor.sla.sessions,
+        neighbor.sla.longest,
+        neighbor.sla.average,
     )?;
     Ok(())
 }
 
-fn show_bgp_neighbor(bgp: &Bgp, args: Args) -> String {
+fn show_bgp_neighbor(bgp: &Bgp, mut args: Args) -> String {
     let mut out = String::new();
 
     if args.is_empty() {
         let mut neighbors = Vec::<Neighbor>::new();
         for (_, peer) in bgp.peers.iter() {
-            neighbors.push(fetch(peer));
+            neighbors.push(fetch(bgp, peer));
         }
         for neighbor in neighbors.iter() {
             render(neighbor, &mut out).unwrap();
         }
         // out = serde_json::to_string(&neighbors).unwrap();
+    } else if let Some(peer) = peer_lookup(bgp, &mut args) {
+        render(&fetch(bgp, peer), &mut out).unwrap();
     } else {
-        // Specific neighbor.
+        out.push_str("% No such neighbor\n");
+    }
+    out
+}
+
+// Validation outcomes of the attributes received from the neighbor.
+fn show_bgp_neighbor_errors(bgp: &Bgp, mut args: Args) -> String {
+    let peer = match peer_lookup(bgp, &mut args) {
+        Some(peer) => peer,
+        None => return String::from("% No such neighbor\n"),
+    };
+    let mut out = String::new();
+    writeln!(out, "OPEN message errors: {}", peer.open_error).unwrap();
+    writeln!(
+        out,
+        "{:<16} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "Attribute", "Missing", "Flags", "Malformed", "Duplicate", "Withdraw", "Discard"
+    )
+    .unwrap();
+    for (typ, stat) in peer.attr_error.iter() {
+        writeln!(
+            out,
+            "{:<16} {:>8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+            typ.to_string(),
+            stat.missing,
+            stat.flags,
+            stat.malformed,
+            stat.duplicate,
+            stat.treat_as_withdraw,
+            stat.attribute_discard
+        )
+        .unwrap();
     }
     out
 }
 
+fn show_bgp_sla(bgp: &Bgp, _args: Args) -> String {
+    let stats: BTreeMap<IpAddr, PeerSlaStat> = bgp
+        .peers
+        .iter()
+        .map(|(addr, peer)| (*addr, peer.sla_stat()))
+        .collect();
+    serde_json::to_string_pretty(&stats).unwrap()
+}
+
+fn peer_lookup<'a>(bgp: &'a Bgp, args: &mut Args) -> Option<&'a Peer> {
+    let addr = args.addr()?;
+    bgp.peers.get(&addr)
+}
+
+// Paths of Adj-RIB-In or Adj-RIB-Out of both of the address families, and
+// the number of the paths shown.
+fn show_adj_rib<F>(buf: &mut String, rib: &AdjRib, route: F) -> usize
+where
+    F: Fn(u32, &Attrs) -> Route,
+{
+    let mut count = 0;
+    for (prefix, path_id, attrs) in rib.paths() {
+        let route = route(path_id, attrs);
+        show_route_entry(buf, prefix, &route, &route.aspath());
+        count += 1;
+    }
+    for (prefix, path_id, attrs) in rib.paths6() {
+        let route = route(path_id, attrs);
+        show_route_entry6(buf, prefix, &route, &route.aspath());
+        count += 1;
+    }
+    count
+}
+
+fn show_bgp_neighbor_received_routes(bgp: &Bgp, mut args: Args) -> String {
+    let mut buf = String::new();
+    let peer = match peer_lookup(bgp, &mut args) {
+        Some(peer) => peer,
+        None => {
+            buf.push_str("% No such neighbor\n");
+            return buf;
+        }
+    };
+    buf.push_str(SHOW_BGP_HEADER);
+
+    let count = show_adj_rib(&mut buf, &peer.adj_rib_in, |path_id, attrs| Route {
+        from: peer.address,
+        router_id: peer.remote_id,
+        attrs: attrs.clone(),
+        ibgp: peer.peer_as == peer.local_as,
+        weight: 0,
+        metric: 0,
+        selected: false,
+        resolved: true,
+        path_id,
+        local_path_id: 0,
+        local_label: None,
+        multipath: false,
+        rpki: RpkiState::NotFound,
+    });
+    writeln!(buf, "\nTotal number of prefixes {}", count).unwrap();
+    buf
+}
+
+fn show_bgp_neighbor_advertised_routes(bgp: &Bgp, mut args: Args) -> String {
+    let mut buf = String::new();
+    let peer = match peer_lookup(bgp, &mut args) {
+        Some(peer) => peer,
+        None => {
+            buf.push_str("% No such neighbor\n");
+            return buf;
+        }
+    };
+    buf.push_str(SHOW_BGP_HEADER);
+
+    let count = show_adj_rib(&mut buf, &peer.adj_rib_out, |path_id, attrs| Route {
+        from: peer.address,
+        router_id: peer.router_id,
+        attrs: attrs.clone(),
+        ibgp: false,
+        weight: 0,
+        metric: 0,
+        selected: false,
+        resolved: true,
+        path_id: 0,
+        local_path_id: path_id,
+        local_label: None,
+        multipath: false,
+        rpki: RpkiState::NotFound,
+    });
+    writeln!(buf, "\nTotal number of prefixes {}", count).unwrap();
+    buf
+}
+
 impl Bgp {
     fn show_add(&mut self, path: &str, cb: ShowCallback) {
         self.show_cb.insert(path.to_string(), cb);
@@ -276,5 +916,239 @@ impl Bgp {
         self.show_add("/show/ip/bgp", show_bgp);
         self.show_add("/show/ip/bgp/summary", show_bgp);
         self.show_add("/show/ip/bgp/neighbor", show_bgp_neighbor);
+        self.show_add("/show/ip/bgp/sla", show_bgp_sla);
+        self.show_add("/show/ip/bgp/health-check", show_bgp_health_check);
+        self.show_add("/show/ip/bgp/origin-watch", show_bgp_origin_watch);
+        self.show_add("/show/ip/bgp/community", |bgp, args| {
+            show_bgp_community(bgp, args, &Afi::IP)
+        });
+        self.show_add("/show/ip/bgp/extcommunity", |bgp, args| {
+            show_bgp_extcommunity(bgp, args, &Afi::IP)
+        });
+        self.show_add("/show/ip/bgp/large-community", |bgp, args| {
+            show_bgp_large_community(bgp, args, &Afi::IP)
+        });
+        self.show_add("/show/ip/bgp/regexp", |bgp, args| {
+            show_bgp_regexp(bgp, args, &Afi::IP)
+        });
+        self.show_add("/show/ip/bgp/filter-list", |bgp, args| {
+            show_bgp_filter_list(bgp, args, &Afi::IP)
+        });
+        self.show_add("/show/ip/bgp/families", show_bgp_families);
+        self.show_add("/show/ip/bgp/flowspec", show_bgp_flowspec);
+        self.show_add("/show/ip/bgp/latency", show_bgp_latency);
+        self.show_add("/show/ip/bgp/rpki", show_bgp_rpki);
+        self.show_add("/show/ip/bgp/prefix", show_bgp_prefix);
+        self.show_add("/show/ip/bgp/prefix/detail", show_bgp_prefix);
+        self.show_add("/show/ip/bgp/vpn", show_bgp_vpn);
+        self.show_add("/show/ip/bgp/vrf", show_bgp_vrf);
+        self.show_add("/show/ipv6/bgp", show_bgp6);
+        self.show_add("/show/ipv6/bgp/community", |bgp, args| {
+            show_bgp_community(bgp, args, &Afi::IP6)
+        });
+        self.show_add("/show/ipv6/bgp/extcommunity", |bgp, args| {
+            show_bgp_extcommunity(bgp, args, &Afi::IP6)
+        });
+        self.show_add("/show/ipv6/bgp/large-community", |bgp, args| {
+            show_bgp_large_community(bgp, args, &Afi::IP6)
+        });
+        self.show_add("/show/ipv6/bgp/regexp", |bgp, args| {
+            show_bgp_regexp(bgp, args, &Afi::IP6)
+        });
+        self.show_add("/show/ipv6/bgp/filter-list", |bgp, args| {
+            show_bgp_filter_list(bgp, args, &Afi::IP6)
+        });
+        self.show_add("/show/system/resources", show_system_resources);
+        self.show_add("/clear/ip/bgp/neighbor/soft-in", clear_bgp_neighbor_soft_in);
+        self.show_add(
+            "/clear/ip/bgp/neighbor/soft-out",
+            clear_bgp_neighbor_soft_out,
+        );
+        self.show_add(
+            "/show/ip/bgp/neighbor/received-routes",
+            show_bgp_neighbor_received_routes,
+        );
+        self.show_add(
+            "/show/ip/bgp/neighbor/advertised-routes",
+            show_bgp_neighbor_advertised_routes,
+        );
+        self.show_add("/show/ip/bgp/neighbor/errors", show_bgp_neighbor_errors);
+        self.show_add(
+            "/clear/ip/bgp/neighbor/shutdown",
+            clear_bgp_neighbor_shutdown,
+        );
+        self.show_add(
+            "/clear/ip/bgp/neighbor/shutdown/message",
+            clear_bgp_neighbor_shutdown,
+        );
+        self.show_add("/request/export/bgp/table", request_export_bgp_table);
+        self.show_add("/request/policy/generate/as-set", request_policy_generate);
+        self.show_add(
+            "/request/policy/generate/as-set/prefix-list",
+            request_policy_generate,
+        );
+        self.show_add(
+            "/request/bgp/graceful-shutdown",
+            request_bgp_graceful_shutdown,
+        );
+        self.show_add(
+            "/request/bgp/neighbor/maintenance",
+            request_bgp_neighbor_maintenance,
+        );
+        self.show_add("/request/export/bgp/table/format", request_export_bgp_table);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::{
+        As4PathAttr, As4Segment, Attribute, MpNlriAttr, NextHopAttr, OriginAttr, AS_SEQUENCE,
+    };
+    use crate::bgp::restart::restart_route;
+    use crate::bgp::Safi;
+    use crate::rib::RibTxChannel;
+
+    const NEIGHBOR: &str = "10.0.0.2";
+
+    fn neighbor(bgp: &mut Bgp) -> &mut Peer {
+        let addr: IpAddr = NEIGHBOR.parse().unwrap();
+        let peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65002,
+            addr,
+            bgp.tx.clone(),
+            bgp.tx.clone(),
+        );
+        bgp.peers.entry(addr).or_insert(peer)
+    }
+
+    fn attrs() -> Attrs {
+        vec![
+            Attribute::Origin(OriginAttr { origin: 0 }),
+            Attribute::NextHop(NextHopAttr {
+                next_hop: [10, 0, 0, 2],
+            }),
+        ]
+    }
+
+    fn attrs6() -> Attrs {
+        vec![
+            Attribute::Origin(OriginAttr { origin: 0 }),
+            Attribute::MpReachNlri(MpNlriAttr {
+                next_hop: Some("2001:db8::2".parse().unwrap()),
+                link_local: None,
+                prefix: Vec::new(),
+                path_id: Vec::new(),
+                safi: Safi::Unicast,
+                label: Vec::new(),
+            }),
+        ]
+    }
+
+    fn args(addr: &str) -> Args {
+        Args(vec![addr.to_string()].into())
+    }
+
+    fn rows(out: &str, prefix: &str) -> usize {
+        out.lines().filter(|line| line.contains(prefix)).count()
+    }
+
+    #[test]
+    fn neighbor_received_routes() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let peer = neighbor(&mut bgp);
+        peer.adj_rib_in
+            .insert("10.1.0.0/16".parse().unwrap(), 1, attrs());
+        peer.adj_rib_in
+            .insert("10.1.0.0/16".parse().unwrap(), 2, attrs());
+        peer.adj_rib_in
+            .insert6("2001:db8:1::/48".parse().unwrap(), 0, attrs6());
+
+        let out = show_bgp_neighbor_received_routes(&bgp, args(NEIGHBOR));
+        assert_eq!(rows(&out, "10.1.0.0/16"), 2);
+        assert_eq!(rows(&out, "2001:db8:1::/48"), 1);
+        assert!(out.ends_with("\nTotal number of prefixes 3\n"));
+
+        let out = show_bgp_neighbor_received_routes(&bgp, args("10.0.0.3"));
+        assert_eq!(out, "% No such neighbor\n");
+    }
+
+    #[test]
+    fn neighbor_advertised_routes() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let peer = neighbor(&mut bgp);
+        peer.adj_rib_out
+            .insert("10.1.0.0/16".parse().unwrap(), 0, attrs());
+        peer.adj_rib_out
+            .insert6("2001:db8:1::/48".parse().unwrap(), 0, attrs6());
+        peer.adj_rib_out
+            .insert6("2001:db8:2::/48".parse().unwrap(), 0, attrs6());
+
+        let out = show_bgp_neighbor_advertised_routes(&bgp, args(NEIGHBOR));
+        assert_eq!(rows(&out, "10.1.0.0/16"), 1);
+        assert_eq!(rows(&out, "2001:db8:"), 2);
+        assert!(out.ends_with("\nTotal number of prefixes 3\n"));
+
+        // Prefixes withdrawn from Adj-RIB-Out are not counted.
+        let peer = neighbor(&mut bgp);
+        peer.adj_rib_out.remove(&"10.1.0.0/16".parse().unwrap(), 0);
+        let out = show_bgp_neighbor_advertised_routes(&bgp, args(NEIGHBOR));
+        assert_eq!(rows(&out, "10.1.0.0/16"), 0);
+        assert!(out.ends_with("\nTotal number of prefixes 2\n"));
+    }
+
+    #[test]
+    fn route_filter6() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        let from: IpAddr = NEIGHBOR.parse().unwrap();
+        let aspath = |asn: u32| {
+            Attribute::As4Path(As4PathAttr {
+                segments: vec![As4Segment {
+                    typ: AS_SEQUENCE,
+                    asn: vec![65002, asn],
+                }],
+            })
+        };
+        for (i, asn) in [65003, 65004, 65003].into_iter().enumerate() {
+            let mut attrs = attrs();
+            attrs.push(aspath(asn));
+            let prefix = format!("10.{}.0.0/16", i + 1).parse().unwrap();
+            bgp.ptree.insert(prefix, vec![restart_route(from, attrs)]);
+
+            let mut attrs = attrs6();
+            attrs.push(aspath(asn));
+            let prefix = format!("2001:db8:{}::/48", i + 1).parse().unwrap();
+            bgp.ptree6.insert(prefix, vec![restart_route(from, attrs)]);
+        }
+
+        let out = show_bgp_regexp(&bgp, args("65003$"), &Afi::IP6);
+        assert_eq!(rows(&out, "2001:db8:"), 2);
+        assert_eq!(rows(&out, "2001:db8:2::/48"), 0);
+        assert_eq!(rows(&out, "65002 65003 i"), 2);
+        assert_eq!(rows(&out, "10."), 0);
+
+        let out = show_bgp_regexp(&bgp, args("65004$"), &Afi::IP);
+        assert_eq!(rows(&out, "10.2.0.0/16"), 1);
+        assert_eq!(rows(&out, "2001:db8:"), 0);
+    }
+
+    #[test]
+    fn show_cache_invalidate() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        bgp.show_cache.enable(true);
+        let from: IpAddr = NEIGHBOR.parse().unwrap();
+        let prefix: Ipv4Net = "10.1.0.0/16".parse().unwrap();
+        bgp.ptree.insert(prefix, vec![restart_route(from, attrs())]);
+        let out = show_bgp_route(&bgp);
+        assert_eq!(rows(&out, "*  N 10.1.0.0/16"), 1);
+
+        // Route changed in place is shown, not the kept output.
+        bgp.ptree.get_mut(&prefix).unwrap()[0].selected = true;
+        let out = show_bgp_route(&bgp);
+        assert_eq!(rows(&out, "*> N 10.1.0.0/16"), 1);
+        assert_eq!(show_bgp_route(&bgp), out);
     }
 }
@@ -0,0 +1,157 @@
+use super::handler::{Bgp, Message};
+use super::peer::PeerSlaStat;
+use super::task::{Timer, TimerType};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc::UnboundedSender;
+
+// Session availability of the peers exported as metrics in the Prometheus
+// text exposition format. The file is rewritten periodically to be collected
+// by the textfile collector of node_exporter, which requires ".prom" suffix
+// of the file name.
+
+pub const SLA_METRICS_INTERVAL: u64 = 60;
+
+#[derive(Default)]
+pub struct SlaMetrics {
+    pub file: Option<PathBuf>,
+    timer: Option<Timer>,
+}
+
+impl SlaMetrics {
+    fn start(&mut self, tx: UnboundedSender<Message>) {
+        self.timer = None;
+        if self.file.is_none() {
+            return;
+        }
+        self.timer = Some(Timer::new(
+            Timer::second(SLA_METRICS_INTERVAL),
+            TimerType::Infinite,
+            move || {
+                let tx = tx.clone();
+                async move {
+                    let _ = tx.send(Message::SlaMetrics);
+                }
+            },
+        ));
+    }
+}
+
+pub fn sla_metrics_restart(bgp: &mut Bgp) {
+    bgp.sla_metrics.start(bgp.tx.clone());
+    sla_metrics_write(bgp);
+}
+
+const METRICS: [(&str, &str, &str); 5] = [
+    (
+        "bgp_peer_established_total",
+        "counter",
+        "Number of transitions to Established.",
+    ),
+    (
+        "bgp_peer_uptime_seconds_total",
+        "counter",
+        "Cumulative time in Established.",
+    ),
+    (
+        "bgp_peer_sessions",
+        "gauge",
+        "Sessions in the window including the current one.",
+    ),
+    (
+        "bgp_peer_session_longest_seconds",
+        "gauge",
+        "Longest session duration in the window.",
+    ),
+    (
+        "bgp_peer_session_average_seconds",
+        "gauge",
+        "Average session duration in the window.",
+    ),
+];
+
+fn sla_metrics_encode(stats: &BTreeMap<IpAddr, PeerSlaStat>) -> String {
+    let mut buf = String::new();
+    for (i, (name, typ, help)) in METRICS.iter().enumerate() {
+        writeln!(buf, "# HELP {} {}", name, help).unwrap();
+        writeln!(buf, "# TYPE {} {}", name, typ).unwrap();
+        for (addr, stat) in stats.iter() {
+            let value = match i {
+                0 => stat.established,
+                1 => stat.uptime,
+                2 => stat.sessions as u64,
+                3 => stat.longest,
+                _ => stat.average,
+            };
+            writeln!(buf, "{}{{peer=\"{}\"}} {}", name, addr, value).unwrap();
+        }
+    }
+    buf
+}
+
+// The file is replaced by rename so that the collector never reads a partial
+// one.
+fn sla_metrics_file(path: &Path, buf: &str) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, buf)?;
+    fs::rename(&tmp, path)
+}
+
+pub fn sla_metrics_write(bgp: &Bgp) {
+    let path = match &bgp.sla_metrics.file {
+        Some(path) => path.clone(),
+        None => return,
+    };
+    let stats: BTreeMap<IpAddr, PeerSlaStat> = bgp
+        .peers
+        .iter()
+        .map(|(addr, peer)| (*addr, peer.sla_stat()))
+        .collect();
+    let buf = sla_metrics_encode(&stats);
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = sla_metrics_file(&path, &buf) {
+            println!("sla: {} write error: {}", path.display(), err);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn metrics_encode() {
+        let mut stats = BTreeMap::new();
+        stats.insert(
+            "10.0.0.2".parse().unwrap(),
+            PeerSlaStat {
+                established: 3,
+                uptime: 7200,
+                sessions: 2,
+                longest: 5400,
+                average: 3600,
+            },
+        );
+        stats.insert("2001:db8::2".parse().unwrap(), PeerSlaStat::default());
+        let buf = sla_metrics_encode(&stats);
+        let lines: Vec<&str> = buf.lines().collect();
+        assert_eq!(lines.len(), METRICS.len() * 4);
+        assert_eq!(
+            &lines[..4],
+            &[
+                "# HELP bgp_peer_established_total Number of transitions to Established.",
+                "# TYPE bgp_peer_established_total counter",
+                "bgp_peer_established_total{peer=\"10.0.0.2\"} 3",
+                "bgp_peer_established_total{peer=\"2001:db8::2\"} 0",
+            ]
+        );
+        assert!(lines.contains(&"bgp_peer_uptime_seconds_total{peer=\"10.0.0.2\"} 7200"));
+        assert!(lines.contains(&"bgp_peer_sessions{peer=\"10.0.0.2\"} 2"));
+        assert!(lines.contains(&"bgp_peer_session_longest_seconds{peer=\"10.0.0.2\"} 5400"));
+        assert!(lines.contains(&"bgp_peer_session_average_seconds{peer=\"10.0.0.2\"} 3600"));
+    }
+}
@@ -0,0 +1,248 @@
+use super::handler::{Bgp, Message};
+use super::peer::{fsm, Event, Peer, RemoteAs};
+use super::task::Task;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::fs;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::UnboundedSender;
+
+const ICMPV6_ROUTER_ADVERT: u8 = 134;
+
+// Router advertisement with zero router lifetime, so hosts on the link never
+// take us as a default router. Checksum is filled by the kernel.
+const ROUTER_ADVERT_LEN: usize = 16;
+
+const ROUTER_ADVERT_INTERVAL: Duration = Duration::from_secs(10);
+
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+// Neighbor configured by interface name. Router advertisements are sent and
+// received on the interface so that both ends learn the link-local address of
+// each other, as unnumbered peering of FRR. Remote AS may be external or
+// internal, so that the same config is used on every link of the fabric.
+#[derive(Debug, Default)]
+pub struct Unnumbered {
+    pub remote_as: RemoteAs,
+    // AS configured with peer-as, which applies again when remote-as is
+    // removed.
+    pub peer_as: Option<u32>,
+    pub ifindex: u32,
+    pub address: Option<Ipv6Addr>,
+    pub task: Option<Task<()>>,
+}
+
+pub fn ifindex(name: &str) -> Option<u32> {
+    let index = fs::read_to_string(format!("/sys/class/net/{}/ifindex", name)).ok()?;
+    index.trim().parse().ok()
+}
+
+fn router_advert_socket(ifindex: u32) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?;
+    socket.set_multicast_hops_v6(255)?;
+    socket.set_multicast_if_v6(ifindex)?;
+    socket.set_multicast_loop_v6(false)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+async fn router_advert(
+    name: String,
+    ifindex: u32,
+    socket: UdpSocket,
+    tx: UnboundedSender<Message>,
+) {
+    let all_nodes = SocketAddr::V6(SocketAddrV6::new(ALL_NODES, 0, 0, ifindex));
+    let mut interval = tokio::time::interval(ROUTER_ADVERT_INTERVAL);
+    let mut advert = [0u8; ROUTER_ADVERT_LEN];
+    advert[0] = ICMPV6_ROUTER_ADVERT;
+    let mut buf = [0u8; 1500];
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(err) = socket.send_to(&advert, all_nodes).await {
+                    println!("unnumbered: {} RA send error: {}", name, err);
+                }
+            }
+            result = socket.recv_from(&mut buf) => {
+                match result {
+                    Ok((len, SocketAddr::V6(from)))
+                        if len >= ROUTER_ADVERT_LEN
+                            && buf[0] == ICMPV6_ROUTER_ADVERT
+                            && from.scope_id() == ifindex
+                            && from.ip().is_unicast_link_local() =>
+                    {
+                        let _ = tx.send(Message::LinkLocal(name.clone(), *from.ip()));
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        println!("unnumbered: {} RA receive error: {}", name, err);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn unnumbered_start(bgp: &Bgp, name: &str) -> Unnumbered {
+    let mut unnumbered = Unnumbered::default();
+    match ifindex(name) {
+        Some(ifindex) => unnumbered.ifindex = ifindex,
+        None => {
+            println!("unnumbered: interface {} not found", name);
+            return unnumbered;
+        }
+    }
+    match router_advert_socket(unnumbered.ifindex) {
+        Ok(socket) => {
+            unnumbered.task = Some(Task::spawn(router_advert(
+                name.to_string(),
+                unnumbered.ifindex,
+                socket,
+                bgp.tx.clone(),
+            )));
+        }
+        Err(err) => {
+            println!("unnumbered: {} RA socket error: {}", name, err);
+        }
+    }
+    unnumbered
+}
+
+fn peer_remove(bgp: &mut Bgp, addr: Ipv6Addr) {
+    let ident = IpAddr::V6(addr);
+    if bgp.peers.contains_key(&ident) {
+        fsm(bgp, ident, Event::Stop);
+        bgp.peers.remove(&ident);
+    }
+}
+
+pub fn unnumbered_add(bgp: &mut Bgp, name: &str) {
+    if !bgp.unnumbered.contains_key(name) {
+        let unnumbered = unnumbered_start(bgp, name);
+        bgp.unnumbered.insert(name.to_string(), unnumbered);
+    }
+}
+
+pub fn unnumbered_delete(bgp: &mut Bgp, name: &str) {
+    if let Some(unnumbered) = bgp.unnumbered.remove(name) {
+        if let Some(addr) = unnumbered.address {
+            peer_remove(bgp, addr);
+        }
+    }
+}
+
+pub fn unnumbered_remote_as(bgp: &mut Bgp, name: &str, remote_as: RemoteAs) {
+    let unnumbered = match bgp.unnumbered.get_mut(name) {
+        Some(unnumbered) => unnumbered,
+        None => return,
+    };
+    unnumbered.remote_as = remote_as;
+    if let Some(addr) = unnumbered.address {
+        if let Some(peer) = bgp.peers.get_mut(&IpAddr::V6(addr)) {
+            peer.remote_as_set(remote_as);
+        }
+    }
+}
+
+// Learn the link-local address of the neighbor on the interface. Peer with
+// the previously learned address is removed. Returns true when a new peer is
+// created.
+pub fn unnumbered_learn(bgp: &mut Bgp, name: &str, addr: Ipv6Addr) -> bool {
+    let unnumbered = match bgp.unnumbered.get_mut(name) {
+        Some(unnumbered) => unnumbered,
+        None => return false,
+    };
+    if unnumbered.address == Some(addr) {
+        return false;
+    }
+    let old = unnumbered.address.replace(addr);
+    let remote_as = unnumbered.remote_as;
+    let ifindex = unnumbered.ifindex;
+    if let Some(old) = old {
+        peer_remove(bgp, old);
+    }
+    println!("unnumbered: {} neighbor {}", name, addr);
+
+    let ident = IpAddr::V6(addr);
+    let mut peer = Peer::new(
+        ident,
+        bgp.asn,
+        bgp.router_id,
+        remote_as.peer_as(bgp.asn),
+        ident,
+        bgp.tx.clone(),
+        bgp.updates.tx.clone(),
+    );
+    peer.scope_id = ifindex;
+    peer.global_cluster_id = bgp.cluster_id;
+    peer.rr_clients = bgp.rr_clients.clone();
+    peer.confed = bgp.confed.clone();
+    peer.default_local_pref = bgp.bestpath.local_pref;
+    peer.instance_shutdown = bgp.shutdown.clone();
+    peer.mrt_updates = bgp.mrt_dump.writer();
+    peer.remote_as = remote_as;
+    peer.peer_type = remote_as.peer_type(bgp.asn);
+    peer.update();
+    bgp.peers.insert(ident, peer);
+    true
+}
+
+// Inbound connection from a link-local address of an unnumbered interface.
+pub fn unnumbered_accept(bgp: &mut Bgp, sockaddr: &SocketAddrV6) -> bool {
+    if !sockaddr.ip().is_unicast_link_local() {
+        return false;
+    }
+    let name = bgp
+        .unnumbered
+        .iter()
+        .find(|(_, unnumbered)| unnumbered.ifindex == sockaddr.scope_id())
+        .map(|(name, _)| name.clone());
+    match name {
+        Some(name) => unnumbered_learn(bgp, &name, *sockaddr.ip()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn test_peer(remote_as: RemoteAs) -> Peer {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let addr = "fe80::1".parse().unwrap();
+        let update_tx = tx.clone();
+        let mut peer = Peer::new(
+            addr,
+            65000,
+            Ipv4Addr::new(1, 1, 1, 1),
+            remote_as.peer_as(65000),
+            addr,
+            tx,
+            update_tx,
+        );
+        peer.remote_as = remote_as;
+        peer
+    }
+
+    #[test]
+    fn unnumbered_remote_as_accept() {
+        let peer = test_peer(RemoteAs::External);
+        assert_eq!(peer.peer_as, 0);
+        assert!(peer.remote_as_accept(65001));
+        assert!(!peer.remote_as_accept(65000));
+
+        let peer = test_peer(RemoteAs::Internal);
+        assert_eq!(peer.peer_as, 65000);
+        assert!(peer.remote_as_accept(65000));
+        assert!(!peer.remote_as_accept(65001));
+
+        let peer = test_peer(RemoteAs::Asn(65002));
+        assert!(peer.remote_as_accept(65002));
+        assert!(!peer.remote_as_accept(65001));
+        assert_eq!(RemoteAs::External.to_string(), "external");
+    }
+}
@@ -0,0 +1,550 @@
+use super::handler::Bgp;
+use super::localas::local_as_prepend;
+use super::network::ROUTE_LOCAL;
+use super::packet::{
+    attrs_as2, parse_vpn_nexthop, parse_vpn_nlris, vpn_nexthop_encode, vpn_nlri_encode,
+    As4PathAttr, Attribute, Attrs, BgpHeader, BgpType, ExtComAdmin, ExtendedCom, ExtendedComAttr,
+    LocalPrefAttr, MpRawAttr, OriginAttr, RouteDistinguisher, UpdatePacket, VpnNlri,
+    BGP_HEADER_LEN,
+};
+use super::peer::{peer_send_update, Peer, State};
+use super::{Afi, AfiSafi, Safi};
+use crate::config::Args;
+use bytes::BytesMut;
+use ipnet::IpNet;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::net::IpAddr;
+
+// Path of the VPN route. Next hop is None for the one exported from the local
+// VRF, which is advertised with next hop self.
+#[derive(Debug, Clone)]
+pub struct VpnPath {
+    pub label: u32,
+    pub nexthop: Option<IpAddr>,
+    pub attrs: Attrs,
+}
+
+impl VpnPath {
+    fn route_targets(&self) -> BTreeSet<ExtComAdmin> {
+        let mut rts = BTreeSet::new();
+        for attr in self.attrs.iter() {
+            if let Attribute::ExtendedCom(ecom) = attr {
+                for rt in ecom.route_targets() {
+                    if let ExtendedCom::RouteTarget(admin) = rt {
+                        rts.insert(*admin);
+                    }
+                }
+            }
+        }
+        rts
+    }
+
+    // Best path of the VRF is selected by LOCAL_PREF, AS path length, origin
+    // and MED, then the lowest RD and source.
+    fn rank(&self) -> (Reverse<u32>, usize, u8, u32) {
+        let mut rank = (Reverse(100), 0, 2, 0);
+        for attr in self.attrs.iter() {
+            match attr {
+                Attribute::LocalPref(m) => rank.0 = Reverse(m.local_pref),
+                Attribute::As4Path(m) => rank.1 = m.length(),
+                Attribute::Origin(m) => rank.2 = m.origin,
+                Attribute::Med(m) => rank.3 = m.med,
+                _ => {}
+            }
+        }
+        rank
+    }
+
+    fn show(&self) -> String {
+        let nexthop = match self.nexthop {
+            Some(nexthop) => nexthop.to_string(),
+            None => "self".to_string(),
+        };
+        let rts: Vec<String> = self.route_targets().iter().map(|x| x.to_string()).collect();
+        format!(
+            "next hop {} label {} rt {}",
+            nexthop,
+            self.label,
+            rts.join(" ")
+        )
+    }
+}
+
+type VrfPaths = BTreeMap<(RouteDistinguisher, IpAddr), VpnPath>;
+
+// VRF of the PE. VPN routes carrying one of the import route targets are
+// imported to the RIB of the VRF. Networks of the VRF are exported to VPN
+// with the RD, the export route targets and the label of the VRF.
+#[derive(Debug, Default)]
+pub struct Vrf {
+    pub rd: Option<RouteDistinguisher>,
+    pub import: BTreeSet<ExtComAdmin>,
+    pub export: BTreeSet<ExtComAdmin>,
+    pub networks: BTreeSet<IpNet>,
+    // Label allocated while the VRF exports any network.
+    pub label: Option<u32>,
+    pub rib: BTreeMap<IpNet, VrfPaths>,
+    exported: BTreeSet<(RouteDistinguisher, IpNet)>,
+}
+
+impl Vrf {
+    // Replaced path is removed when it no longer carries the import route
+    // target. Routes exported from the VRF are not imported back.
+    fn import(&mut self, rd: RouteDistinguisher, prefix: IpNet, from: IpAddr, path: &VpnPath) {
+        if from == ROUTE_LOCAL && self.exported.contains(&(rd, prefix)) {
+            return;
+        }
+        if path.route_targets().is_disjoint(&self.import) {
+            self.unimport(rd, prefix, from);
+        } else {
+            self.rib
+                .entry(prefix)
+                .or_default()
+                .insert((rd, from), path.clone());
+        }
+    }
+
+    fn unimport(&mut self, rd: RouteDistinguisher, prefix: IpNet, from: IpAddr) {
+        let paths = match self.rib.get_mut(&prefix) {
+            Some(paths) => paths,
+            None => return,
+        };
+        paths.remove(&(rd, from));
+        if paths.is_empty() {
+            self.rib.remove(&prefix);
+        }
+    }
+}
+
+fn vrf_best(paths: &VrfPaths) -> Option<&(RouteDistinguisher, IpAddr)> {
+    paths
+        .iter()
+        .min_by_key(|(_, path)| path.rank())
+        .map(|(key, _)| key)
+}
+
+// VPN RIB holds the paths of VPN-IPv4 and VPN-IPv6 routes per RD and prefix,
+// which are received from the peers or exported from the local VRFs.
+#[derive(Default)]
+pub struct Vpn {
+    pub vrfs: BTreeMap<String, Vrf>,
+    pub rib: BTreeMap<(RouteDistinguisher, IpNet), BTreeMap<IpAddr, VpnPath>>,
+}
+
+impl Vpn {
+    // MP_REACH_NLRI of SAFI 128. None when the next hop or the NLRI is
+    // malformed. Attributes are stored without MP_REACH_NLRI.
+    pub fn reach(&mut self, from: IpAddr, mp: &MpRawAttr, attrs: &Attrs) -> Option<()> {
+        let nexthop = parse_vpn_nexthop(&mp.next_hop)?;
+        let nlris = parse_vpn_nlris(&mp.nlri, &mp.afi)?;
+        let attrs: Attrs = attrs
+            .iter()
+            .filter(|attr| !matches!(attr, Attribute::MpReachRaw(_) | Attribute::MpUnreachRaw(_)))
+            .cloned()
+            .collect();
+        for nlri in nlris.into_iter() {
+            let path = VpnPath {
+                label: nlri.label,
+                nexthop: Some(nexthop),
+                attrs: attrs.clone(),
+            };
+            self.update(nlri.rd, nlri.prefix, from, path);
+        }
+        Some(())
+    }
+
+    pub fn unreach(&mut self, from: IpAddr, mp: &MpRawAttr) -> Option<()> {
+        let nlris = parse_vpn_nlris(&mp.nlri, &mp.afi)?;
+        for nlri in nlris.iter() {
+            self.withdraw(nlri.rd, nlri.prefix, from);
+        }
+        Some(())
+    }
+
+    // Withdraw all of the paths from the peer.
+    pub fn peer_clear(&mut self, from: IpAddr) {
+        let keys: Vec<_> = self
+            .rib
+            .iter()
+            .filter(|(_, paths)| paths.contains_key(&from))
+            .map(|(key, _)| *key)
+            .collect();
+        for (rd, prefix) in keys.into_iter() {
+            self.withdraw(rd, prefix, from);
+        }
+    }
+
+    fn update(&mut self, rd: RouteDistinguisher, prefix: IpNet, from: IpAddr, path: VpnPath) {
+        for vrf in self.vrfs.values_mut() {
+            vrf.import(rd, prefix, from, &path);
+        }
+        self.rib.entry((rd, prefix)).or_default().insert(from, path);
+    }
+
+    fn withdraw(&mut self, rd: RouteDistinguisher, prefix: IpNet, from: IpAddr) {
+        let paths = match self.rib.get_mut(&(rd, prefix)) {
+            Some(paths) => paths,
+            None => return,
+        };
+        paths.remove(&from);
+        if paths.is_empty() {
+            self.rib.remove(&(rd, prefix));
+        }
+        for vrf in self.vrfs.values_mut() {
+            vrf.unimport(rd, prefix, from);
+        }
+    }
+
+    // Import the VPN RIB to the VRF again after the import route targets
+    // change.
+    pub fn reimport(&mut self, name: &str) {
+        let vrf = match self.vrfs.get_mut(name) {
+            Some(vrf) => vrf,
+            None => return,
+        };
+        vrf.rib.clear();
+        for ((rd, prefix), paths) in self.rib.iter() {
+            for (from, path) in paths.iter() {
+                vrf.import(*rd, *prefix, *from, path);
+            }
+        }
+    }
+}
+
+fn vpn_afi(prefix: &IpNet) -> Afi {
+    match prefix {
+        IpNet::V4(_) => Afi::IP,
+        IpNet::V6(_) => Afi::IP6,
+    }
+}
+
+fn vpn_negotiated(peer: &Peer, afi: Afi) -> bool {
+    peer.afi_safi.has(&AfiSafi::new(afi, Safi::MplsVpn))
+}
+
+fn vpn_packet(attrs: Attrs) -> UpdatePacket {
+    UpdatePacket {
+        header: BgpHeader::new(BgpType::Update, BGP_HEADER_LEN),
+        attrs,
+        ipv4_update: Vec::new(),
+        ipv4_withdraw: Vec::new(),
+        ipv4_update_id: Vec::new(),
+        ipv4_withdraw_id: Vec::new(),
+        attr_errors: Vec::new(),
+    }
+}
+
+// Exported path is advertised with next hop self, VPN-IPv6 over IPv4
+// transport with IPv4-mapped IPv6 address (RFC 4659 3.2.1.2).
+fn vpn_update_packet(
+    peer: &Peer,
+    rd: RouteDistinguisher,
+    prefix: IpNet,
+    path: &VpnPath,
+) -> UpdatePacket {
+    let ibgp = peer.peer_as == peer.local_as;
+    let internal = ibgp || peer.confed_member();
+    let mut attrs = path.attrs.clone();
+    let aspath = As4PathAttr {
+        segments: Vec::new(),
+    };
+    let aspath = if ibgp {
+        aspath
+    } else if peer.confed_member() {
+        aspath.confed_prepend(peer.local_as)
+    } else {
+        local_as_prepend(peer, aspath)
+    };
+    attrs.push(Attribute::As4Path(aspath));
+    if internal {
+        attrs.push(Attribute::LocalPref(LocalPrefAttr {
+            local_pref: peer.default_local_pref,
+        }));
+    }
+    let nexthop = match prefix {
+        IpNet::V4(_) => IpAddr::V4(peer.local_addr4()),
+        IpNet::V6(_) => IpAddr::V6(peer.local_addr6()),
+    };
+    let nlri = VpnNlri {
+        label: path.label,
+        rd,
+        prefix,
+    };
+    let mut buf = BytesMut::new();
+    vpn_nlri_encode(&mut buf, &nlri, false);
+    attrs.push(Attribute::MpReachRaw(MpRawAttr {
+        afi: vpn_afi(&prefix),
+        safi: Safi::MplsVpn,
+        next_hop: vpn_nexthop_encode(&nexthop),
+        nlri: buf.to_vec(),
+    }));
+    if !peer.as4 {
+        attrs = attrs_as2(attrs);
+    }
+    vpn_packet(attrs)
+}
+
+fn vpn_withdraw_packet(rd: RouteDistinguisher, prefix: IpNet) -> UpdatePacket {
+    let nlri = VpnNlri {
+        label: 0,
+        rd,
+        prefix,
+    };
+    let mut buf = BytesMut::new();
+    vpn_nlri_encode(&mut buf, &nlri, true);
+    vpn_packet(vec![Attribute::MpUnreachRaw(MpRawAttr {
+        afi: vpn_afi(&prefix),
+        safi: Safi::MplsVpn,
+        next_hop: Vec::new(),
+        nlri: buf.to_vec(),
+    })])
+}
+
+// Routes exported from the local VRFs are advertised. VPN routes received
+// from the peers are not re-advertised.
+fn vpn_advertise(
+    bgp: &mut Bgp,
+    withdrawn: &[(RouteDistinguisher, IpNet)],
+    updated: &[(RouteDistinguisher, IpNet)],
+) {
+    if bgp.monitor_only || bgp.restart.is_some() {
+        return;
+    }
+    for peer in bgp.peers.values_mut() {
+        if peer.state != State::Established {
+            continue;
+        }
+        for (rd, prefix) in withdrawn.iter() {
+            if vpn_negotiated(peer, vpn_afi(prefix)) {
+                peer_send_update(peer, vpn_withdraw_packet(*rd, *prefix));
+            }
+        }
+        for (rd, prefix) in updated.iter() {
+            let path = match bgp.vpn.rib.get(&(*rd, *prefix)) {
+                Some(paths) => paths.get(&ROUTE_LOCAL),
+                None => None,
+            };
+            if let Some(path) = path {
+                if vpn_negotiated(peer, vpn_afi(prefix)) {
+                    let update = vpn_update_packet(peer, *rd, *prefix, path);
+                    peer_send_update(peer, update);
+                }
+            }
+        }
+    }
+}
+
+// Initial advertisement of the exported routes followed by End-of-RIB marker
+// of each VPN address family.
+pub fn vpn_sync(peer: &mut Peer, vpn: &Vpn) {
+    for afi in [Afi::IP, Afi::IP6] {
+        if !vpn_negotiated(peer, afi.clone()) {
+            continue;
+        }
+        for ((rd, prefix), paths) in vpn.rib.iter() {
+            if vpn_afi(prefix) != afi {
+                continue;
+            }
+            if let Some(path) = paths.get(&ROUTE_LOCAL) {
+                let update = vpn_update_packet(peer, *rd, *prefix, path);
+                peer_send_update(peer, update);
+            }
+        }
+        let eor = vpn_packet(vec![Attribute::MpUnreachRaw(MpRawAttr {
+            afi,
+            safi: Safi::MplsVpn,
+            next_hop: Vec::new(),
+            nlri: Vec::new(),
+        })]);
+        peer_send_update(peer, eor);
+    }
+}
+
+// Originate the networks of the VRF to the VPN RIB, then advertise the change
+// to the peers. Nothing is exported until the RD is configured.
+pub fn vrf_export(bgp: &mut Bgp, name: &str) {
+    let (withdrawn, exports, path) = {
+        let vrf = match bgp.vpn.vrfs.get_mut(name) {
+            Some(vrf) => vrf,
+            None => return,
+        };
+        if vrf.rd.is_none() || vrf.networks.is_empty() {
+            if let Some(label) = vrf.label.take() {
+                bgp.labels.release(label);
+            }
+        } else if vrf.label.is_none() {
+            vrf.label = bgp.labels.alloc();
+        }
+        let exports: BTreeSet<(RouteDistinguisher, IpNet)> = match (vrf.rd, vrf.label) {
+            (Some(rd), Some(_)) => vrf.networks.iter().map(|prefix| (rd, *prefix)).collect(),
+            _ => BTreeSet::new(),
+        };
+        let withdrawn: Vec<_> = vrf.exported.difference(&exports).cloned().collect();
+        vrf.exported = exports.clone();
+        let mut attrs = vec![Attribute::Origin(OriginAttr { origin: 0 })];
+        if !vrf.export.is_empty() {
+            let rts = vrf.export.iter().map(|x| ExtendedCom::RouteTarget(*x));
+            attrs.push(Attribute::ExtendedCom(ExtendedComAttr(rts.collect())));
+        }
+        let path = VpnPath {
+            label: vrf.label.unwrap_or(0),
+            nexthop: None,
+            attrs,
+        };
+        (withdrawn, exports, path)
+    };
+    for (rd, prefix) in withdrawn.iter() {
+        bgp.vpn.withdraw(*rd, *prefix, ROUTE_LOCAL);
+    }
+    let exports: Vec<_> = exports.into_iter().collect();
+    for (rd, prefix) in exports.iter() {
+        bgp.vpn.update(*rd, *prefix, ROUTE_LOCAL, path.clone());
+    }
+    vpn_advertise(bgp, &withdrawn, &exports);
+}
+
+// Change the setting of the VRF and export the networks again.
+pub fn vrf_update<F: FnOnce(&mut Vrf)>(bgp: &mut Bgp, name: &str, f: F) {
+    if let Some(vrf) = bgp.vpn.vrfs.get_mut(name) {
+        f(vrf);
+        vrf_export(bgp, name);
+    }
+}
+
+pub fn vrf_delete(bgp: &mut Bgp, name: &str) {
+    vrf_update(bgp, name, |vrf| vrf.networks.clear());
+    bgp.vpn.vrfs.remove(name);
+}
+
+pub fn show_bgp_vpn(bgp: &Bgp, _args: Args) -> String {
+    let mut out = String::new();
+    let mut current = None;
+    for ((rd, prefix), paths) in bgp.vpn.rib.iter() {
+        if current != Some(rd) {
+            writeln!(out, "Route Distinguisher: {}", rd).unwrap();
+            current = Some(rd);
+        }
+        for (from, path) in paths.iter() {
+            let from = if *from == ROUTE_LOCAL {
+                "local".to_string()
+            } else {
+                from.to_string()
+            };
+            writeln!(out, "  {} from {} {}", prefix, from, path.show()).unwrap();
+        }
+    }
+    out
+}
+
+pub fn show_bgp_vrf(bgp: &Bgp, _args: Args) -> String {
+    let mut out = String::new();
+    for (name, vrf) in bgp.vpn.vrfs.iter() {
+        let rd = match vrf.rd {
+            Some(rd) => rd.to_string(),
+            None => "none".to_string(),
+        };
+        let label = match vrf.label {
+            Some(label) => label.to_string(),
+            None => "none".to_string(),
+        };
+        writeln!(out, "VRF {}, RD {}, label {}", name, rd, label).unwrap();
+        let import: Vec<String> = vrf.import.iter().map(|x| x.to_string()).collect();
+        let export: Vec<String> = vrf.export.iter().map(|x| x.to_string()).collect();
+        writeln!(out, "  Import RT: {}", import.join(" ")).unwrap();
+        writeln!(out, "  Export RT: {}", export.join(" ")).unwrap();
+        for prefix in vrf.networks.iter() {
+            writeln!(out, "  *> {} local", prefix).unwrap();
+        }
+        for (prefix, paths) in vrf.rib.iter() {
+            let best = vrf_best(paths);
+            for (key, path) in paths.iter() {
+                let status = if Some(key) == best { "*>" } else { "* " };
+                let (rd, from) = key;
+                writeln!(
+                    out,
+                    "  {} {} from {} rd {} {}",
+                    status,
+                    prefix,
+                    from,
+                    rd,
+                    path.show()
+                )
+                .unwrap();
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rt(s: &str) -> ExtComAdmin {
+        s.parse().unwrap()
+    }
+
+    fn reach(rd: &str, prefix: &str, label: u32, rts: &[&str]) -> (MpRawAttr, Attrs) {
+        let nlri = VpnNlri {
+            label,
+            rd: rd.parse().unwrap(),
+            prefix: prefix.parse().unwrap(),
+        };
+        let mut buf = BytesMut::new();
+        vpn_nlri_encode(&mut buf, &nlri, false);
+        let mp = MpRawAttr {
+            afi: Afi::IP,
+            safi: Safi::MplsVpn,
+            next_hop: vpn_nexthop_encode(&"192.0.2.1".parse().unwrap()),
+            nlri: buf.to_vec(),
+        };
+        let ecom = rts.iter().map(|x| ExtendedCom::RouteTarget(rt(x)));
+        let attrs = vec![
+            Attribute::Origin(OriginAttr { origin: 0 }),
+            Attribute::ExtendedCom(ExtendedComAttr(ecom.collect())),
+            Attribute::MpReachRaw(mp.clone()),
+        ];
+        (mp, attrs)
+    }
+
+    #[test]
+    fn vrf_import() {
+        let mut vpn = Vpn::default();
+        let mut red = Vrf::default();
+        red.import.insert(rt("65000:1"));
+        vpn.vrfs.insert("red".to_string(), red);
+        vpn.vrfs.insert("blue".to_string(), Vrf::default());
+
+        let from: IpAddr = "192.0.2.1".parse().unwrap();
+        let (mp, attrs) = reach("65000:100", "10.1.0.0/16", 1000, &["65000:1"]);
+        assert!(vpn.reach(from, &mp, &attrs).is_some());
+        let (mp2, attrs2) = reach("65000:200", "10.1.0.0/16", 2000, &["65000:2"]);
+        assert!(vpn.reach(from, &mp2, &attrs2).is_some());
+        assert_eq!(vpn.rib.len(), 2);
+
+        let prefix: IpNet = "10.1.0.0/16".parse().unwrap();
+        let red = &vpn.vrfs["red"];
+        assert_eq!(red.rib[&prefix].len(), 1);
+        let path = red.rib[&prefix].values().next().unwrap();
+        assert_eq!(path.label, 1000);
+        assert_eq!(path.nexthop, Some(from));
+        assert!(vpn.vrfs["blue"].rib.is_empty());
+
+        // Route target of the VRF changes.
+        vpn.vrfs
+            .get_mut("blue")
+            .unwrap()
+            .import
+            .insert(rt("65000:2"));
+        vpn.reimport("blue");
+        assert_eq!(vpn.vrfs["blue"].rib[&prefix].len(), 1);
+
+        assert!(vpn.unreach(from, &mp).is_some());
+        assert!(vpn.vrfs["red"].rib.is_empty());
+        vpn.peer_clear(from);
+        assert!(vpn.rib.is_empty());
+        assert!(vpn.vrfs["blue"].rib.is_empty());
+    }
+}
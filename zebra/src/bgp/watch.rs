@@ -0,0 +1,171 @@
+use super::handler::Bgp;
+use super::packet::{As4PathAttr, Attribute, Attrs};
+use super::peer::{ConfigRef, Peer, PeerType};
+use crate::config::Args;
+use ipnet::IpNet;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+// Number of alerts kept for show.
+pub const ORIGIN_ALERT_MAX: usize = 100;
+
+// Origin AS watch list. Prefix covered by one of the watched prefixes must
+// be originated by one of the ASes of the longest one, e.g. our own address
+// space by our AS. Any other origin is a possible hijack and is alerted,
+// whether the route is accepted by the policy or not.
+#[derive(Debug, Default)]
+pub struct OriginWatch {
+    pub prefixes: BTreeMap<IpNet, BTreeSet<u32>>,
+    pub alerts: VecDeque<OriginAlert>,
+    // Anomalies currently advertised by the peers, alerted once until the
+    // prefix is advertised with the expected origin or the session goes down.
+    active: BTreeMap<(IpAddr, IpNet), Option<u32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OriginAlert {
+    pub time: SystemTime,
+    pub peer: IpAddr,
+    pub prefix: IpNet,
+    pub watch: IpNet,
+    // None when the origin can't be determined as the path ends with AS_SET.
+    pub origin: Option<u32>,
+}
+
+impl OriginWatch {
+    // Watched prefix the origin is not expected for.
+    pub fn lookup(&self, prefix: &IpNet, origin: Option<u32>) -> Option<IpNet> {
+        let (watch, origins) = self
+            .prefixes
+            .iter()
+            .filter(|(watch, _)| watch.contains(prefix))
+            .max_by_key(|(watch, _)| watch.prefix_len())?;
+        if origins.is_empty() {
+            return None;
+        }
+        match origin {
+            Some(origin) if origins.contains(&origin) => None,
+            _ => Some(*watch),
+        }
+    }
+
+    pub fn peer_clear(&mut self, peer: IpAddr) {
+        self.active.retain(|(addr, _), _| *addr != peer);
+    }
+}
+
+fn origin_as(peer: &Peer, attrs: &Attrs) -> Option<u32> {
+    let aspath = attrs.iter().find_map(|attr| match attr {
+        Attribute::AsPath(aspath) => Some(As4PathAttr::from(aspath)),
+        Attribute::As4Path(aspath) => Some(aspath.clone()),
+        _ => None,
+    });
+    match aspath {
+        Some(aspath) if !aspath.segments.is_empty() => aspath.origin_as(),
+        // Empty path from internal peer is originated in our AS.
+        _ if matches!(peer.peer_type, PeerType::Internal) => Some(peer.local_as),
+        _ => None,
+    }
+}
+
+// Check the prefixes advertised by the peer against the watch list.
+pub fn origin_watch_check(
+    peer: &mut Peer,
+    prefixes: impl Iterator<Item = IpNet>,
+    attrs: &Attrs,
+    bgp: &mut ConfigRef,
+) {
+    if bgp.origin_watch.prefixes.is_empty() {
+        return;
+    }
+    let origin = origin_as(peer, attrs);
+    for prefix in prefixes {
+        let key = (peer.address, prefix);
+        let watch = match bgp.origin_watch.lookup(&prefix, origin) {
+            Some(watch) => watch,
+            None => {
+                bgp.origin_watch.active.remove(&key);
+                continue;
+            }
+        };
+        if bgp.origin_watch.active.get(&key) == Some(&origin) {
+            continue;
+        }
+        bgp.origin_watch.active.insert(key, origin);
+        peer.origin_anomaly += 1;
+        println!(
+            "ALERT origin: {} advertised {} with origin {} not expected for {}",
+            peer.address,
+            prefix,
+            origin
+                .map(|x| x.to_string())
+                .unwrap_or(String::from("unknown")),
+            watch
+        );
+        bgp.webhook.origin_anomaly(peer, &prefix, origin);
+        let alerts = &mut bgp.origin_watch.alerts;
+        if alerts.len() >= ORIGIN_ALERT_MAX {
+            alerts.pop_front();
+        }
+        alerts.push_back(OriginAlert {
+            time: SystemTime::now(),
+            peer: peer.address,
+            prefix,
+            watch,
+            origin,
+        });
+    }
+}
+
+pub fn show_bgp_origin_watch(bgp: &Bgp, _args: Args) -> String {
+    let mut buf = String::new();
+    writeln!(buf, "{:43} Origin AS", "Prefix").unwrap();
+    for (prefix, origins) in bgp.origin_watch.prefixes.iter() {
+        let origins: Vec<String> = origins.iter().map(|x| x.to_string()).collect();
+        writeln!(buf, "{:43} {}", prefix.to_string(), origins.join(" ")).unwrap();
+    }
+    writeln!(buf, "\nRecent alerts:").unwrap();
+    writeln!(buf, "{:10} {:39} {:43} Origin", "Age", "Neighbor", "Prefix").unwrap();
+    for alert in bgp.origin_watch.alerts.iter().rev() {
+        let age = alert.time.elapsed().map(|x| x.as_secs()).unwrap_or(0);
+        writeln!(
+            buf,
+            "{:10} {:39} {:43} {}",
+            format!("{}s", age),
+            alert.peer.to_string(),
+            alert.prefix.to_string(),
+            alert
+                .origin
+                .map(|x| x.to_string())
+                .unwrap_or(String::from("unknown"))
+        )
+        .unwrap();
+    }
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn origin_watch_lookup() {
+        let mut watch = OriginWatch::default();
+        let wide: IpNet = "10.0.0.0/8".parse().unwrap();
+        let narrow: IpNet = "10.1.0.0/16".parse().unwrap();
+        watch.prefixes.insert(wide, BTreeSet::from([65001]));
+        watch.prefixes.insert(narrow, BTreeSet::from([65002]));
+
+        let prefix: IpNet = "10.2.0.0/24".parse().unwrap();
+        assert_eq!(watch.lookup(&prefix, Some(65001)), None);
+        assert_eq!(watch.lookup(&prefix, Some(65002)), Some(wide));
+        assert_eq!(watch.lookup(&prefix, None), Some(wide));
+        // Longest watched prefix decides the expected origin.
+        let prefix: IpNet = "10.1.1.0/24".parse().unwrap();
+        assert_eq!(watch.lookup(&prefix, Some(65001)), Some(narrow));
+        let prefix: IpNet = "192.168.0.0/24".parse().unwrap();
+        assert_eq!(watch.lookup(&prefix, Some(65003)), None);
+    }
+}
@@ -0,0 +1,230 @@
+use super::packet::{shutdown_message_decode, NotificationCode};
+use super::peer::{Peer, PeerReset, ResetDirection};
+use super::task::Task;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebhookEventType {
+    Established,
+    Down,
+    MaxPrefixWarning,
+    MaxPrefixExceeded,
+    OriginAnomaly,
+}
+
+#[derive(Serialize)]
+struct WebhookEvent {
+    timestamp: u64,
+    event: WebhookEventType,
+    peer: IpAddr,
+    remote_as: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefixes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_prefixes: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin_as: Option<u32>,
+}
+
+impl WebhookEvent {
+    fn new(event: WebhookEventType, peer: &Peer) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0),
+            event,
+            peer: peer.address,
+            remote_as: peer.peer_as,
+            reason: None,
+            message: None,
+            prefixes: None,
+            max_prefixes: None,
+            prefix: None,
+            origin_as: None,
+        }
+    }
+}
+
+// Events are POSTed as JSON to the URL one by one from a task, so a slow or
+// unreachable receiver never blocks the BGP event loop.
+#[derive(Default)]
+pub struct Webhook {
+    tx: Option<UnboundedSender<String>>,
+    task: Option<Task<()>>,
+}
+
+async fn webhook_post(url: String, mut rx: mpsc::UnboundedReceiver<String>) {
+    let client = match reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(err) => {
+            println!("webhook: can't create client: {}", err);
+            return;
+        }
+    };
+    while let Some(body) = rx.recv().await {
+        let result = client
+            .post(&url)
+            .header("content-type", "application/json")
+            .body(body)
+            .send()
+            .await;
+        match result {
+            Ok(resp) if !resp.status().is_success() => {
+                println!("webhook: {} returned {}", url, resp.status());
+            }
+            Ok(_) => {}
+            Err(err) => {
+                println!("webhook: post to {} failed: {}", url, err);
+            }
+        }
+    }
+}
+
+impl Webhook {
+    pub fn start(&mut self, url: String) {
+        self.stop();
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.task = Some(Task::spawn(webhook_post(url, rx)));
+        self.tx = Some(tx);
+    }
+
+    pub fn stop(&mut self) {
+        self.tx = None;
+        self.task = None;
+    }
+
+    fn send(&self, event: WebhookEvent) {
+        if let Some(tx) = self.tx.as_ref() {
+            let _ = tx.send(serde_json::to_string(&event).unwrap());
+        }
+    }
+
+    pub fn established(&self, peer: &Peer) {
+        self.send(WebhookEvent::new(WebhookEventType::Established, peer));
+    }
+
+    pub fn down(&self, peer: &Peer, reset: &PeerReset) {
+        let mut event = WebhookEvent::new(WebhookEventType::Down, peer);
+        event.reason = Some(if reset.direction == ResetDirection::Local {
+            String::from("Connection closed")
+        } else {
+            NotificationCode(reset.code).to_string()
+        });
+        event.message = shutdown_message_decode(reset.code, reset.sub_code, &reset.data);
+        self.send(event);
+    }
+
+    pub fn max_prefix(&self, peer: &Peer, typ: WebhookEventType, prefixes: usize, max: u32) {
+        let mut event = WebhookEvent::new(typ, peer);
+        event.prefixes = Some(prefixes);
+        event.max_prefixes = Some(max);
+        self.send(event);
+    }
+
+    // Origin AS is omitted when the path ends with AS_SET.
+    pub fn origin_anomaly(&self, peer: &Peer, prefix: &IpNet, origin: Option<u32>) {
+        let mut event = WebhookEvent::new(WebhookEventType::OriginAnomaly, peer);
+        event.prefix = Some(prefix.to_string());
+        event.origin_as = origin;
+        self.send(event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::packet::shutdown_message_encode;
+    use serde_json::Value;
+    use std::net::Ipv4Addr;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    fn event(rx: &mut UnboundedReceiver<String>) -> Value {
+        serde_json::from_str(&rx.try_recv().unwrap()).unwrap()
+    }
+
+    fn keys(event: &Value) -> Vec<&str> {
+        event
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|x| x.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn event_payload() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let addr: IpAddr = "10.0.0.2".parse().unwrap();
+        let peer = Peer::new(
+            addr,
+            65001,
+            Ipv4Addr::new(10, 0, 0, 1),
+            65002,
+            addr,
+            tx.clone(),
+            tx,
+        );
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let webhook = Webhook {
+            tx: Some(event_tx),
+            task: None,
+        };
+
+        webhook.established(&peer);
+        let established = event(&mut event_rx);
+        assert_eq!(
+            keys(&established),
+            ["timestamp", "event", "peer", "remote_as"]
+        );
+        assert_eq!(established["event"], "established");
+        assert_eq!(established["peer"], "10.0.0.2");
+        assert_eq!(established["remote_as"], 65002);
+
+        let mut reset = PeerReset {
+            direction: ResetDirection::Received,
+            code: NotificationCode::Cease.0,
+            sub_code: 2,
+            data: shutdown_message_encode("maintenance"),
+        };
+        webhook.down(&peer, &reset);
+        let down = event(&mut event_rx);
+        assert_eq!(down["event"], "down");
+        assert_eq!(down["reason"], NotificationCode::Cease.to_string());
+        assert_eq!(down["message"], "maintenance");
+
+        // Message is omitted when there is none.
+        reset.direction = ResetDirection::Local;
+        reset.data.clear();
+        webhook.down(&peer, &reset);
+        let down = event(&mut event_rx);
+        assert_eq!(down["reason"], "Connection closed");
+        assert!(down.get("message").is_none());
+
+        webhook.max_prefix(&peer, WebhookEventType::MaxPrefixExceeded, 101, 100);
+        let max_prefix = event(&mut event_rx);
+        assert_eq!(max_prefix["event"], "max-prefix-exceeded");
+        assert_eq!(max_prefix["prefixes"], 101);
+        assert_eq!(max_prefix["max_prefixes"], 100);
+
+        let prefix: IpNet = "10.1.0.0/16".parse().unwrap();
+        webhook.origin_anomaly(&peer, &prefix, None);
+        let anomaly = event(&mut event_rx);
+        assert_eq!(anomaly["event"], "origin-anomaly");
+        assert_eq!(anomaly["prefix"], "10.1.0.0/16");
+        assert!(anomaly.get("origin_as").is_none());
+    }
+}
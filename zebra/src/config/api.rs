@@ -1,4 +1,6 @@
-use super::vtysh::CommandPath;
+use super::commands::SnapshotFunc;
+use super::manager::Snapshot;
+use super::vtysh::{CommandPath, ExecError};
 use super::{Completion, ExecCode};
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::Sender;
@@ -14,6 +16,13 @@ pub enum ConfigOp {
     Set,
     Delete,
     Completion,
+    // End of the changes published by a commit.
+    CommitEnd,
+    // Start of the running config replayed to the subscriber, ended by
+    // CommitEnd.
+    Resync,
+    // Startup handshake, answered when the subscriber polls the receiver.
+    Ready,
 }
 
 #[derive(Debug)]
@@ -31,6 +40,13 @@ impl ConfigRequest {
             resp: None,
         }
     }
+
+    // Answer to the startup handshake.
+    pub fn ready_reply(self) {
+        if let Some(resp) = self.resp {
+            let _ = resp.send(Vec::new());
+        }
+    }
 }
 
 impl ConfigChannel {
@@ -45,6 +61,8 @@ pub struct ExecuteRequest {
     pub mode: String,
     pub input: String,
     pub resp: Sender<ExecuteResponse>,
+    // Request from the read-only observer service.
+    pub observer: bool,
 }
 
 #[derive(Debug, Default)]
@@ -52,6 +70,15 @@ pub struct ExecuteResponse {
     pub code: ExecCode,
     pub output: String,
     pub paths: Vec<CommandPath>,
+    pub error: Option<ExecError>,
+    // Output to be rendered from the snapshot before the response is sent.
+    pub render: Option<Render>,
+}
+
+#[derive(Debug)]
+pub struct Render {
+    pub func: SnapshotFunc,
+    pub snapshot: Snapshot,
 }
 
 #[derive(Debug)]
@@ -73,14 +100,19 @@ impl ExecuteRequest {
             mode: mode.to_string(),
             input: input.to_string(),
             resp,
+            observer: false,
         }
     }
 }
 
-impl ExecuteResponse {
-    pub fn new() -> Self {
+impl From<(ExecCode, String, Vec<CommandPath>)> for ExecuteResponse {
+    fn from((code, output, paths): (ExecCode, String, Vec<CommandPath>)) -> Self {
         Self {
-            ..Default::default()
+            code,
+            output,
+            paths,
+            error: None,
+            render: None,
         }
     }
 }
@@ -107,6 +139,16 @@ impl CompletionResponse {
 pub enum Message {
     Execute(ExecuteRequest),
     Completion(CompletionRequest),
+    // Replay the running config to the subscriber of the name.
+    Resync(String),
+    // Time of the scheduled commit has come.
+    CommitAt,
+    // Scheduled commit has not been confirmed in time.
+    CommitRollback,
+    // Subscriber of the name has answered the startup handshake.
+    Ready(String),
+    // Subscribers have not answered the startup handshake in time.
+    ReadyTimeout,
 }
 
 #[derive(Debug)]
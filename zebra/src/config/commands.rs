@@ -1,17 +1,28 @@
-use super::manager::ConfigManager;
+use super::comps::ytype_str;
+use super::manager::{ConfigManager, Snapshot};
 use super::util::trim_first_line;
-use super::ExecCode;
-use libyang::Entry;
+use super::{Args, ExecCode};
+use libyang::{Entry, YangType};
 use similar::TextDiff;
 use std::collections::HashMap;
+use std::fmt::Write;
 use std::rc::Rc;
 
 type FuncMap = HashMap<String, fn(&ConfigManager) -> (ExecCode, String)>;
 
+// Commands which take the values of the leaves, e.g. "commit at TIME".
+type ArgFuncMap = HashMap<String, fn(&ConfigManager, Args) -> (ExecCode, String)>;
+
+// Commands which render the config snapshot. They run on a blocking worker
+// task, so that a large config does not stall the other commands.
+pub type SnapshotFunc = fn(&Snapshot) -> String;
+
 #[derive(Debug)]
 pub struct Mode {
     pub entry: Rc<Entry>,
     pub fmap: FuncMap,
+    pub afmap: ArgFuncMap,
+    pub smap: HashMap<String, SnapshotFunc>,
 }
 
 impl Mode {
@@ -19,20 +30,50 @@ impl Mode {
         Self {
             entry,
             fmap: HashMap::new(),
+            afmap: HashMap::new(),
+            smap: HashMap::new(),
         }
     }
 
     pub fn install_func(&mut self, path: String, f: fn(&ConfigManager) -> (ExecCode, String)) {
         self.fmap.insert(path, f);
     }
+
+    pub fn install_arg_func(
+        &mut self,
+        path: String,
+        f: fn(&ConfigManager, Args) -> (ExecCode, String),
+    ) {
+        self.afmap.insert(path, f);
+    }
+
+    pub fn install_snapshot_func(&mut self, path: String, f: SnapshotFunc) {
+        self.smap.insert(path, f);
+    }
 }
 
+// Commands run by the observers besides the config renderings. Others change
+// the config or the system state.
+pub const OBSERVER_COMMANDS: &[&str] = &[
+    "/help",
+    "/exit",
+    "/configure",
+    "/show/version",
+    "/show/ip/route",
+    "/show/yang/modules",
+    "/show/yang/node",
+    "/show/configuration/validation",
+];
+
 pub fn exec_mode_create(entry: Rc<Entry>) -> Mode {
     let mut mode = Mode::new(entry);
     mode.install_func(String::from("/help"), help);
     mode.install_func(String::from("/show/version"), show_version);
     mode.install_func(String::from("/show/ip/route"), show_ip_route_prefix);
     mode.install_func(String::from("/configure"), configure);
+    mode.install_func(String::from("/request/system/yang/reload"), yang_reload);
+    mode.install_func(String::from("/show/yang/modules"), show_yang_modules);
+    mode.install_arg_func(String::from("/show/yang/node"), show_yang_node);
     mode
 }
 
@@ -40,14 +81,21 @@ pub fn configure_mode_create(entry: Rc<Entry>) -> Mode {
     let mut mode = Mode::new(entry);
     mode.install_func(String::from("/help"), help);
     mode.install_func(String::from("/exit"), exit);
-    mode.install_func(String::from("/show"), show);
-    mode.install_func(String::from("/candidate"), candidate);
-    mode.install_func(String::from("/running"), running);
-    mode.install_func(String::from("/json"), json);
-    mode.install_func(String::from("/yaml"), yaml);
+    mode.install_snapshot_func(String::from("/show"), show);
+    mode.install_func(
+        String::from("/show/configuration/validation"),
+        show_configuration_validation,
+    );
+    mode.install_snapshot_func(String::from("/candidate"), candidate);
+    mode.install_snapshot_func(String::from("/running"), running);
+    mode.install_snapshot_func(String::from("/json"), json);
+    mode.install_snapshot_func(String::from("/yaml"), yaml);
     mode.install_func(String::from("/commit"), commit);
+    mode.install_arg_func(String::from("/commit/at"), commit_at);
+    mode.install_func(String::from("/commit/confirm"), commit_confirm);
+    mode.install_func(String::from("/commit/cancel"), commit_cancel);
     mode.install_func(String::from("/discard"), discard);
-    mode.install_func(String::from("/list"), list);
+    mode.install_snapshot_func(String::from("/list"), list);
     mode.install_func(String::from("/load"), load);
     mode.install_func(String::from("/save"), save);
     mode
@@ -74,56 +122,107 @@ CLI_MODE=configure;CLI_MODE_STR=Configure;CLI_PRIVILEGE=15;_cli_refresh"#;
     (ExecCode::Success, cli_command.to_string())
 }
 
+fn yang_reload(config: &ConfigManager) -> (ExecCode, String) {
+    config.reload.set(true);
+    (ExecCode::Show, String::from(""))
+}
+
 fn exit(_config: &ConfigManager) -> (ExecCode, String) {
     let cli_command = r#"SuccessExec
 CLI_MODE=exec;CLI_PRIVILEGE=1;_cli_refresh"#;
     (ExecCode::Success, cli_command.to_string())
 }
 
-fn show(config: &ConfigManager) -> (ExecCode, String) {
+fn show(snapshot: &Snapshot) -> String {
     let mut running = String::new();
     let mut candidate = String::new();
-    config.store.running.borrow().format(&mut running);
-    config.store.candidate.borrow().format(&mut candidate);
+    snapshot.running.format(&mut running);
+    snapshot.candidate.format(&mut candidate);
 
     if running != candidate {
         let text_diff = TextDiff::from_lines(&running, &candidate);
         let mut binding = text_diff.unified_diff();
         let mut diff = binding.context_radius(65535).to_string();
-        let diff = trim_first_line(&mut diff);
-        (ExecCode::Show, diff)
+        trim_first_line(&mut diff)
     } else {
-        (ExecCode::Show, candidate)
+        candidate
     }
 }
 
-fn candidate(config: &ConfigManager) -> (ExecCode, String) {
+fn show_configuration_validation(config: &ConfigManager) -> (ExecCode, String) {
+    let errors = config.validate_config();
+    if errors.is_empty() {
+        return (
+            ExecCode::Show,
+            String::from("Candidate configuration is valid\n"),
+        );
+    }
     let mut output = String::new();
-    config.store.candidate.borrow().format(&mut output);
+    for err in errors.iter() {
+        output.push_str(err);
+        output.push('\n');
+    }
     (ExecCode::Show, output)
 }
 
-fn running(config: &ConfigManager) -> (ExecCode, String) {
+fn candidate(snapshot: &Snapshot) -> String {
     let mut output = String::new();
-    config.store.running.borrow().format(&mut output);
-    (ExecCode::Show, output)
+    snapshot.candidate.format(&mut output);
+    output
 }
 
-fn json(config: &ConfigManager) -> (ExecCode, String) {
+fn running(snapshot: &Snapshot) -> String {
     let mut output = String::new();
-    config.store.candidate.borrow().json(&mut output);
-    (ExecCode::Show, output)
+    snapshot.running.format(&mut output);
+    output
 }
 
-fn yaml(config: &ConfigManager) -> (ExecCode, String) {
+fn json(snapshot: &Snapshot) -> String {
     let mut output = String::new();
-    config.store.candidate.borrow().yaml(&mut output);
-    (ExecCode::Show, output)
+    snapshot.candidate.json(&mut output);
+    output
 }
 
+fn yaml(snapshot: &Snapshot) -> String {
+    let mut output = String::new();
+    snapshot.candidate.yaml(&mut output);
+    output
+}
+
+// Commit confirms the scheduled commit activated as well, as the running
+// config includes it.
 fn commit(config: &ConfigManager) -> (ExecCode, String) {
-    config.commit_config();
-    (ExecCode::Show, String::from(""))
+    match config.commit_config() {
+        Ok(()) => {
+            config.confirm.take();
+            (ExecCode::Show, String::from(""))
+        }
+        Err(err) => (ExecCode::Show, err),
+    }
+}
+
+fn commit_at(config: &ConfigManager, mut args: Args) -> (ExecCode, String) {
+    let at = args.string().unwrap_or_default();
+    match config.commit_at(&at) {
+        Ok(output) => (ExecCode::Show, output),
+        Err(err) => (ExecCode::Show, err),
+    }
+}
+
+fn commit_confirm(config: &ConfigManager) -> (ExecCode, String) {
+    let output = match config.confirm.take() {
+        Some(_) => "Commit confirmed\n",
+        None => "% No commit waiting for confirmation\n",
+    };
+    (ExecCode::Show, output.to_string())
+}
+
+fn commit_cancel(config: &ConfigManager) -> (ExecCode, String) {
+    let output = match config.scheduled.take() {
+        Some(_) => "Scheduled commit cancelled\n",
+        None => "% No scheduled commit\n",
+    };
+    (ExecCode::Show, output.to_string())
 }
 
 fn discard(config: &ConfigManager) -> (ExecCode, String) {
@@ -141,8 +240,144 @@ fn save(config: &ConfigManager) -> (ExecCode, String) {
     (ExecCode::Show, String::from(""))
 }
 
-fn list(config: &ConfigManager) -> (ExecCode, String) {
+fn list(snapshot: &Snapshot) -> String {
+    let mut output = String::new();
+    snapshot.candidate.list(&mut output);
+    output
+}
+
+fn show_yang_modules(config: &ConfigManager) -> (ExecCode, String) {
     let mut output = String::new();
-    config.store.candidate.borrow().list(&mut output);
+    for (tree, base) in [("exec", "exec"), ("config", "configure")] {
+        let mut modules = vec![base.to_string()];
+        modules.extend(config.yang_modules.get(tree).cloned().unwrap_or_default());
+        writeln!(output, "{} tree: {}", tree, modules.join(" ")).unwrap();
+    }
+    writeln!(output, "\n{:40} Revision", "Module").unwrap();
+    for (module, revision) in config.revisions.iter() {
+        writeln!(output, "{:40} {}", module, revision).unwrap();
+    }
     (ExecCode::Show, output)
 }
+
+// Schema node of the configuration by the path of the node names, keys of
+// the lists are not included, e.g. /routing/bgp/neighbors/neighbor/peer-as.
+fn yang_node(root: &Rc<Entry>, path: &str) -> Option<Rc<Entry>> {
+    let mut entry = root.clone();
+    for name in path.split(['/', ' ']).filter(|name| !name.is_empty()) {
+        let child = entry
+            .dir
+            .borrow()
+            .iter()
+            .find(|child| child.name == name)
+            .cloned()?;
+        entry = child;
+    }
+    Some(entry)
+}
+
+fn yang_node_kind(entry: &Entry) -> &'static str {
+    if entry.has_key() {
+        "list"
+    } else if entry.is_directory_entry() && entry.presence {
+        "presence container"
+    } else if entry.is_directory_entry() {
+        "container"
+    } else if entry.is_leaflist() {
+        "leaf-list"
+    } else {
+        "leaf"
+    }
+}
+
+fn yang_node_type(entry: &Entry) -> Option<String> {
+    let node = entry.type_node.as_ref()?;
+    let mut output = ytype_str(&node.kind).to_string();
+    if let Some(typedef) = &node.typedef {
+        write!(output, " ({})", typedef).unwrap();
+    }
+    if let Some(range) = &node.range {
+        write!(output, " range {}", range).unwrap();
+    }
+    if node.kind == YangType::Enumeration {
+        let names: Vec<&str> = node.enum_stmt.iter().map(|x| x.name.as_str()).collect();
+        write!(output, " {{{}}}", names.join(", ")).unwrap();
+    }
+    if node.kind == YangType::Union {
+        let kinds: Vec<&str> = node.union.iter().map(|x| ytype_str(&x.kind)).collect();
+        write!(output, " [{}]", kinds.join(", ")).unwrap();
+    }
+    Some(output)
+}
+
+fn show_yang_node(config: &ConfigManager, mut args: Args) -> (ExecCode, String) {
+    let path = args.string().unwrap_or_default();
+    let root = config.modes.get("configure").and_then(|mode| {
+        mode.entry
+            .dir
+            .borrow()
+            .iter()
+            .find(|entry| entry.name == "set")
+            .cloned()
+    });
+    let entry = match root.and_then(|root| yang_node(&root, &path)) {
+        Some(entry) => entry,
+        None => return (ExecCode::Show, format!("% No such schema node {}\n", path)),
+    };
+    let mut output = String::new();
+    writeln!(output, "Node: {}", path).unwrap();
+    writeln!(output, "Kind: {}", yang_node_kind(&entry)).unwrap();
+    if let Some(ytype) = yang_node_type(&entry) {
+        writeln!(output, "Type: {}", ytype).unwrap();
+    }
+    if entry.has_key() {
+        writeln!(output, "Keys: {}", entry.key.join(" ")).unwrap();
+    }
+    if let Some(help) = entry.extension.get("ext:help") {
+        writeln!(output, "Description: {}", help).unwrap();
+    }
+    let children: Vec<String> = entry
+        .dir
+        .borrow()
+        .iter()
+        .map(|child| format!("{} ({})", child.name, yang_node_kind(child)))
+        .collect();
+    if !children.is_empty() {
+        writeln!(output, "Children:").unwrap();
+        for child in children.iter() {
+            writeln!(output, "  {}", child).unwrap();
+        }
+    }
+    (ExecCode::Show, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dir(name: &str, presence: bool, children: Vec<Rc<Entry>>) -> Rc<Entry> {
+        let mut entry = Entry::new_dir(name.to_string());
+        entry.presence = presence;
+        entry.dir.borrow_mut().extend(children);
+        Rc::new(entry)
+    }
+
+    #[test]
+    fn yang_node_path() {
+        let bgp = dir("bgp", true, vec![dir("global", false, Vec::new())]);
+        let root = dir("set", false, vec![dir("routing", false, vec![bgp])]);
+
+        let entry = yang_node(&root, "/routing/bgp").unwrap();
+        assert_eq!(entry.name, "bgp");
+        assert_eq!(yang_node_kind(&entry), "presence container");
+
+        // Path is given with either slashes or spaces.
+        let entry = yang_node(&root, "routing bgp global").unwrap();
+        assert_eq!(entry.name, "global");
+        assert_eq!(yang_node_kind(&entry), "container");
+
+        assert!(yang_node(&root, "/routing/ospf").is_none());
+        assert!(yang_node(&root, "/routing/bgp/global/as").is_none());
+        assert_eq!(yang_node(&root, "").unwrap().name, "set");
+    }
+}
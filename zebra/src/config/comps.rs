@@ -1,15 +1,19 @@
 use super::parse::State;
 use super::parse::{entry_is_key, ymatch_complete, ytype_from_typedef};
-use super::vtysh::YangMatch;
+use super::vtysh::{CompletionKind, YangMatch};
 use super::Config;
 use libyang::{Entry, TypeNode, YangType};
 use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Debug, Default, Clone)]
 pub struct Completion {
     pub name: String,
     pub help: String,
     pub ymatch: YangMatch,
+    pub kind: CompletionKind,
+    // YANG type of the value hint.
+    pub ytype: String,
 }
 
 impl Completion {
@@ -18,6 +22,7 @@ impl Completion {
             name: name.to_string(),
             help: help.to_string(),
             ymatch: YangMatch::Leaf,
+            ..Default::default()
         }
     }
 
@@ -28,6 +33,11 @@ impl Completion {
             ..Default::default()
         }
     }
+
+    pub fn with_kind(mut self, kind: CompletionKind) -> Self {
+        self.kind = kind;
+        self
+    }
 }
 
 pub fn cname(name: &str) -> Completion {
@@ -38,16 +48,40 @@ pub fn cname(name: &str) -> Completion {
     }
 }
 
+// Value to be typed by the user, e.g. "A.B.C.D" or "1..255".
+pub fn chint(name: &str, ytype: &YangType) -> Completion {
+    Completion {
+        name: name.to_string(),
+        ymatch: YangMatch::Leaf,
+        kind: CompletionKind::ValueHint,
+        ytype: ytype_name(ytype).to_string(),
+        ..Default::default()
+    }
+}
+
 pub fn crange(e: &Rc<Entry>, n: &TypeNode) -> Completion {
     let name = if let Some(range) = &n.range {
         range.to_string()
     } else {
         format!("<{}:{}>", e.name.to_owned(), ytype_str(&n.kind))
     };
-    Completion::new_name(&name)
+    chint(&name, &n.kind)
+}
+
+// Name of the YANG built-in or ietf-inet-types type.
+pub fn ytype_name(ytype: &YangType) -> &'static str {
+    match ytype {
+        YangType::Binary => "binary",
+        YangType::Decimal64 => "decimal64",
+        YangType::Ipv4Addr => "ipv4-address",
+        YangType::Ipv4Prefix => "ipv4-prefix",
+        YangType::Ipv6Addr => "ipv6-address",
+        YangType::Ipv6Prefix => "ipv6-prefix",
+        _ => ytype_str(ytype),
+    }
 }
 
-fn ytype_str(ytype: &YangType) -> &'static str {
+pub fn ytype_str(ytype: &YangType) -> &'static str {
     match ytype {
         YangType::Binary => "bianry",
         YangType::Bits => "bits",
@@ -87,26 +121,32 @@ pub fn centry(entry: &Rc<Entry>) -> Completion {
         name: entry.name.clone(),
         help: comps_help_string(entry).clone(),
         ymatch,
+        ..Default::default()
     }
 }
 
 pub fn cleaf(entry: &Rc<Entry>) -> Completion {
-    let name = if let Some(node) = &entry.type_node {
+    let (name, ytype) = if let Some(node) = &entry.type_node {
         if let Some(ytype) = ytype_from_typedef(&node.typedef) {
-            ytype_str(&ytype).to_string()
+            (ytype_str(&ytype).to_string(), ytype_name(&ytype))
         } else if let Some(range) = &node.range {
-            range.to_string()
+            (range.to_string(), ytype_name(&node.kind))
         } else {
-            format!("<{}:{}>", entry.name, ytype_str(&node.kind))
+            (
+                format!("<{}:{}>", entry.name, ytype_str(&node.kind)),
+                ytype_name(&node.kind),
+            )
         }
     } else {
-        format!("<{}>", entry.name)
+        (format!("<{}>", entry.name), "")
     };
     let help = entry.extension.get("ext:help").map_or_else(|| "", |v| v);
     Completion {
         name: name.to_string(),
         help: help.to_string(),
         ymatch: YangMatch::Leaf,
+        kind: CompletionKind::ValueHint,
+        ytype: ytype.to_string(),
     }
 }
 
@@ -115,7 +155,7 @@ fn comps_exists(comps: &[Completion], name: &String) -> bool {
 }
 
 pub fn comps_add_cr(comps: &mut Vec<Completion>) {
-    comps.push(Completion::new_name("<cr>"));
+    comps.push(Completion::new_name("<cr>").with_kind(CompletionKind::EndOfCommand));
 }
 
 pub fn comps_append(from: &mut Vec<Completion>, to: &mut Vec<Completion>) {
@@ -133,10 +173,15 @@ pub fn comps_help_string(e: &Entry) -> String {
     }
 }
 
+// Value present in the configuration.
+pub fn cconfig(name: &str) -> Completion {
+    Completion::new_name(name).with_kind(CompletionKind::ConfigValue)
+}
+
 pub fn comps_add_config(
     comps: &mut Vec<Completion>,
     ymatch: YangMatch,
-    config: &Option<Rc<Config>>,
+    config: &Option<Arc<Config>>,
 ) {
     if ymatch == YangMatch::LeafMatched {
         comps_add_cr(comps);
@@ -144,21 +189,21 @@ pub fn comps_add_config(
     }
     if let Some(config) = config {
         if config.has_dir() {
-            for config in config.configs.borrow().iter() {
-                comps.push(Completion::new_name(&config.name));
+            for config in config.configs.iter() {
+                comps.push(cconfig(&config.name));
             }
             if ymatch == YangMatch::Key {
-                for key in config.keys.borrow().iter() {
-                    comps.push(Completion::new_name(&key.name));
+                for key in config.keys.iter() {
+                    comps.push(cconfig(&key.name));
                 }
             }
-        } else if config.list.borrow().is_empty() {
-            if !config.value.borrow().is_empty() {
-                comps.push(Completion::new_name(&config.value.borrow()));
+        } else if config.list.is_empty() {
+            if !config.value.is_empty() {
+                comps.push(cconfig(&config.value));
             }
         } else {
-            for value in config.list.borrow().iter() {
-                comps.push(Completion::new_name(value));
+            for value in config.list.iter() {
+                comps.push(cconfig(value));
             }
         }
     }
@@ -211,7 +256,8 @@ pub fn comps_add_all(comps: &mut Vec<Completion>, ymatch: YangMatch, entry: &Rc<
                         comps_as_leaf(comps, entry);
                         if entry.name == "interface" {
                             for link in s.links.iter() {
-                                comps.push(Completion::new_name(link));
+                                let comp = Completion::new_name(link);
+                                comps.push(comp.with_kind(CompletionKind::DynamicValue));
                             }
                         }
                     }
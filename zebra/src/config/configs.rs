@@ -1,12 +1,12 @@
 use super::parse::match_keyword;
 use super::parse::{Match, MatchType};
-use super::vtysh::{CommandPath, YangMatch};
+use super::vtysh::{CommandPath, CompletionKind, YangMatch};
 use super::Completion;
 use crate::bgp::{Afi, AfiSafi, Safi};
-use ipnet::{Ipv4Net, Ipv6Net};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use std::collections::VecDeque;
-use std::net::{Ipv4Addr, Ipv6Addr};
-use std::{cell::RefCell, rc::Rc};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct Args(pub VecDeque<String>);
@@ -36,6 +36,12 @@ impl Args {
         Some(arg)
     }
 
+    pub fn addr(&mut self) -> Option<IpAddr> {
+        let item = self.0.pop_front()?;
+        let arg: IpAddr = item.parse().ok()?;
+        Some(arg)
+    }
+
     pub fn v4net(&mut self) -> Option<Ipv4Net> {
         let item = self.0.pop_front()?;
         let arg: Ipv4Net = item.parse().ok()?;
@@ -54,12 +60,24 @@ impl Args {
         Some(arg)
     }
 
+    pub fn net(&mut self) -> Option<IpNet> {
+        let item = self.0.pop_front()?;
+        let arg: IpNet = item.parse().ok()?;
+        Some(arg)
+    }
+
     pub fn boolean(&mut self) -> Option<bool> {
         let item = self.0.pop_front()?;
         let arg: bool = item.parse().ok()?;
         Some(arg)
     }
 
+    pub fn u8(&mut self) -> Option<u8> {
+        let item = self.0.pop_front()?;
+        let arg: u8 = item.parse().ok()?;
+        Some(arg)
+    }
+
     pub fn u16(&mut self) -> Option<u16> {
         let item = self.0.pop_front()?;
         let arg: u16 = item.parse().ok()?;
@@ -73,69 +91,83 @@ impl Args {
             "ipv4-labeled-unicast" => Some(AfiSafi::new(Afi::IP, Safi::MplsLabel)),
             "ipv6-unicast" => Some(AfiSafi::new(Afi::IP6, Safi::Unicast)),
             "ipv6-labeled-unicast" => Some(AfiSafi::new(Afi::IP6, Safi::MplsLabel)),
+            "l3vpn-ipv4-unicast" => Some(AfiSafi::new(Afi::IP, Safi::MplsVpn)),
+            "l3vpn-ipv6-unicast" => Some(AfiSafi::new(Afi::IP6, Safi::MplsVpn)),
             _ => None,
         }
     }
 }
 
-#[derive(Default, Debug)]
+// Leaves of passwords and keys, which are redacted from the config shown to
+// the observers.
+pub const REDACTED_LEAVES: &[&str] = &["password", "keystring", "hexadecimal-string"];
+pub const REDACTED: &str = "********";
+
+// Node of the configuration tree. Nodes are immutable once shared and
+// updated by path copying, so that the running and the candidate config
+// share the unchanged subtrees and copying the config is O(1).
+#[derive(Default, Debug, Clone)]
 pub struct Config {
     pub name: String,
     pub prefix: String,
-    pub value: RefCell<String>,
-    pub list: RefCell<Vec<String>>,
-    pub configs: RefCell<Vec<Rc<Config>>>,
-    pub keys: RefCell<Vec<Rc<Config>>>,
+    pub value: String,
+    pub list: Vec<String>,
+    pub configs: Vec<Arc<Config>>,
+    pub keys: Vec<Arc<Config>>,
     pub presence: bool,
-    pub parent: Option<Rc<Config>>,
 }
 
 impl Config {
-    pub fn new(name: String, parent: Option<Rc<Config>>) -> Self {
+    pub fn new(name: String) -> Self {
         Config {
             name,
-            parent,
             ..Default::default()
         }
     }
 
     pub fn has_dir(&self) -> bool {
-        !self.configs.borrow().is_empty() || !self.keys.borrow().is_empty()
+        !self.configs.is_empty() || !self.keys.is_empty()
     }
 
-    pub fn lookup(&self, name: &String) -> Option<Rc<Config>> {
-        for config in self.configs.borrow().iter() {
-            if config.name == *name {
-                return Some(config.clone());
-            }
-        }
-        None
+    pub fn lookup(&self, name: &String) -> Option<Arc<Config>> {
+        self.configs.iter().find(|x| x.name == *name).cloned()
     }
 
-    pub fn lookup_key(&self, name: &String) -> Option<Rc<Config>> {
-        for key in self.keys.borrow().iter() {
-            if key.name == *name {
-                return Some(key.clone());
-            }
-        }
-        None
+    pub fn lookup_key(&self, name: &String) -> Option<Arc<Config>> {
+        self.keys.iter().find(|x| x.name == *name).cloned()
     }
 
-    pub fn prefix_write(&self, out: &mut String) {
-        if self.prefix.is_empty() {
-            return;
+    // Number of list entries at the path. Lists on the way are expanded, e.g.
+    // ["prefix-list", "seq"] counts the entries of all prefix-lists.
+    pub fn count(&self, path: &[&str]) -> usize {
+        let (name, rest) = match path.split_first() {
+            Some((name, rest)) => (name.to_string(), rest),
+            None => return self.keys.len(),
+        };
+        if self.keys.is_empty() {
+            self.lookup(&name).map(|x| x.count(rest)).unwrap_or(0)
+        } else {
+            self.keys
+                .iter()
+                .filter_map(|key| key.lookup(&name))
+                .map(|x| x.count(rest))
+                .sum()
         }
+    }
 
-        if let Some(parent) = self.parent.as_ref() {
-            if !parent.prefix.is_empty() {
-                parent.prefix_write(out);
-            }
-            out.push_str(&format!("{} ", parent.name));
+    // Words written before the name of the list entry, the list name and the
+    // preceding keys of the multi key list. The tree has no parent pointer
+    // as the subtrees are shared, so it is passed down from the parent.
+    fn key_prefix(&self, prefix: &str) -> String {
+        if self.prefix.is_empty() {
+            format!("{} ", self.name)
+        } else {
+            format!("{}{} ", prefix, self.name)
         }
     }
 
     pub fn display_entry(&self) -> bool {
-        self.keys.borrow().is_empty()
+        self.keys.is_empty()
     }
 
     pub fn quote(&self) -> bool {
@@ -147,28 +179,29 @@ impl Config {
         false
     }
 
-    pub fn write(&self, depth: usize, out: &mut String) {
-        let configs = self.configs.borrow();
-        let brace = !configs.is_empty();
+    pub fn write(&self, depth: usize, prefix: &str, out: &mut String) {
+        let brace = !self.configs.is_empty();
 
         if self.display_entry() {
             if depth != 0 {
                 out.push_str(&" ".repeat(depth * 4).to_string());
             }
-            self.prefix_write(out);
+            if !self.prefix.is_empty() {
+                out.push_str(prefix);
+            }
 
             out.push_str(&self.name.to_string());
 
-            if !self.value.borrow().is_empty() {
+            if !self.value.is_empty() {
                 if self.quote() {
-                    out.push_str(&format!(" \"{}\"", self.value.borrow()));
+                    out.push_str(&format!(" \"{}\"", self.value));
                 } else {
-                    out.push_str(&format!(" {}", self.value.borrow()));
+                    out.push_str(&format!(" {}", self.value));
                 }
             }
 
-            if !self.list.borrow().is_empty() {
-                for value in self.list.borrow().iter() {
+            if !self.list.is_empty() {
+                for value in self.list.iter() {
                     if self.quote() {
                         out.push_str(&format!("\"{}\"", value));
                     } else {
@@ -184,12 +217,13 @@ impl Config {
             }
         }
 
-        for key in self.keys.borrow().iter() {
-            key.write(depth, out);
+        let key_prefix = self.key_prefix(prefix);
+        for key in self.keys.iter() {
+            key.write(depth, &key_prefix, out);
         }
 
-        for config in configs.iter() {
-            config.write(depth + 1, out);
+        for config in self.configs.iter() {
+            config.write(depth + 1, "", out);
         }
 
         if self.display_entry() && brace {
@@ -200,9 +234,30 @@ impl Config {
         }
     }
 
+    // Copy of the tree with the values of the secret leaves replaced. Subtrees
+    // without a secret are shared with the original.
+    pub fn redact(self: &Arc<Self>) -> Arc<Self> {
+        let secret = REDACTED_LEAVES.contains(&self.name.as_str()) && !self.value.is_empty();
+        let configs: Vec<Arc<Config>> = self.configs.iter().map(|x| x.redact()).collect();
+        let keys: Vec<Arc<Config>> = self.keys.iter().map(|x| x.redact()).collect();
+        let same = |a: &[Arc<Config>], b: &[Arc<Config>]| {
+            a.iter().zip(b.iter()).all(|(x, y)| Arc::ptr_eq(x, y))
+        };
+        if !secret && same(&configs, &self.configs) && same(&keys, &self.keys) {
+            return self.clone();
+        }
+        let mut config = Config::clone(self);
+        if secret {
+            config.value = REDACTED.to_string();
+        }
+        config.configs = configs;
+        config.keys = keys;
+        Arc::new(config)
+    }
+
     pub fn format(&self, out: &mut String) {
-        for config in self.configs.borrow().iter() {
-            config.write(0usize, out);
+        for config in self.configs.iter() {
+            config.write(0usize, "", out);
         }
     }
 
@@ -215,17 +270,17 @@ impl Config {
             out.push(',');
         }
 
-        if !self.keys.borrow().is_empty() {
+        if !self.keys.is_empty() {
             out.push_str(&format!("\"{}\": [", self.name));
         } else if self.has_prefix() {
             out.push('{');
             out.push_str(&format!("\"{}\":\"{}\"", self.prefix, self.name));
         } else {
-            let value = self.value.borrow();
+            let value = &self.value;
             if !value.is_empty() {
                 out.push_str(&format!("\"{}\":\"{}\"", self.name, value));
             } else {
-                let value_list = self.list.borrow();
+                let value_list = &self.list;
                 if value_list.len() > 0 {
                     //
                 } else {
@@ -234,7 +289,7 @@ impl Config {
             }
         }
 
-        let keys = self.keys.borrow();
+        let keys = &self.keys;
         for (pos, n) in keys.iter().enumerate() {
             // if n.key_only_config {
             //     n.json_marshal(pos + 1, out);
@@ -243,7 +298,7 @@ impl Config {
             // }
         }
 
-        let configs = self.configs.borrow();
+        let configs = &self.configs;
         if configs.len() > 0 {
             if !self.has_prefix() {
                 out.push('{');
@@ -264,13 +319,13 @@ impl Config {
             out.push_str("{}");
         }
 
-        if !self.keys.borrow().is_empty() {
+        if !self.keys.is_empty() {
             out.push(']');
         }
     }
 
     pub fn json(&self, out: &mut String) {
-        let keys = self.keys.borrow();
+        let keys = &self.keys;
         if keys.len() > 0 {
             out.push('[');
             for (pos, key) in keys.iter().enumerate() {
@@ -284,7 +339,7 @@ impl Config {
         }
 
         out.push('{');
-        let configs = self.configs.borrow();
+        let configs = &self.configs;
         for (pos, config) in configs.iter().enumerate() {
             if pos != 0 {
                 out.push(',');
@@ -302,17 +357,19 @@ impl Config {
         out.push_str(&yaml_str);
     }
 
-    pub fn list_command(&self) -> Vec<String> {
+    // Command of the node following the command of the parent. The root has
+    // no parent and no command.
+    pub fn list_command(&self, parent: Option<&[String]>) -> Vec<String> {
         let mut commands = Vec::new();
-        if let Some(parent) = self.parent.as_ref() {
-            for com in parent.list_command().iter() {
+        if let Some(parent) = parent {
+            for com in parent.iter() {
                 commands.push(com.clone());
             }
             commands.push(self.name.clone());
-            if !self.value.borrow().is_empty() {
-                commands.push(self.value.borrow().clone());
+            if !self.value.is_empty() {
+                commands.push(self.value.clone());
             }
-            for list in self.list.borrow().iter() {
+            for list in self.list.iter() {
                 commands.push(list.clone());
             }
         }
@@ -320,92 +377,78 @@ impl Config {
     }
 
     pub fn list(&self, output: &mut String) {
+        self.list_at(None, output);
+    }
+
+    fn list_at(&self, parent: Option<&[String]>, output: &mut String) {
+        let commands = self.list_command(parent);
         if !self.has_dir() || self.presence || !self.prefix.is_empty() {
-            let commands = self.list_command();
             if !commands.is_empty() {
                 output.push_str(&commands.join(" "));
                 output.push('\n');
             }
         }
-        for key in self.keys.borrow().iter() {
-            key.list(output);
+        for key in self.keys.iter() {
+            key.list_at(Some(&commands), output);
         }
-        for config in self.configs.borrow().iter() {
-            config.list(output);
+        for config in self.configs.iter() {
+            config.list_at(Some(&commands), output);
         }
     }
 }
 
-pub fn carbon_copy(conf: &Rc<Config>, parent: Option<Rc<Config>>) -> Rc<Config> {
-    let p = Rc::new(Config {
-        name: conf.name.clone(),
-        prefix: conf.prefix.clone(),
-        value: conf.value.clone(),
-        list: conf.list.clone(),
-        presence: conf.presence,
-        parent,
-        ..Default::default()
-    });
-    for conf in conf.configs.borrow().iter() {
-        let c = carbon_copy(&conf.clone(), Some(p.clone()));
-        p.configs.borrow_mut().push(c);
-    }
-    for key in conf.keys.borrow().iter() {
-        let k = carbon_copy(&key.clone(), Some(p.clone()));
-        p.keys.borrow_mut().push(k);
-    }
-    p
-}
-
-// Config set.
-fn config_set_dir(config: &Rc<Config>, cpath: &CommandPath) -> Rc<Config> {
-    let find = config.lookup(&cpath.name);
-    match find {
-        Some(find) => find,
+// Config set. The nodes on the path are copied when they are shared with
+// another tree, e.g. the running config.
+fn config_set_dir<'a>(config: &'a mut Arc<Config>, cpath: &CommandPath) -> &'a mut Arc<Config> {
+    let config = Arc::make_mut(config);
+    let index = match config.configs.iter().position(|x| x.name == cpath.name) {
+        Some(index) => index,
         None => {
-            let n = Rc::new(Config {
+            let n = Arc::new(Config {
                 name: cpath.name.clone(),
-                parent: Some(config.clone()),
                 presence: (ymatch_enum(cpath.ymatch) == YangMatch::DirMatched),
                 ..Default::default()
             });
-            config.configs.borrow_mut().push(n.clone());
-            config
+            let index = config
                 .configs
-                .borrow_mut()
-                .sort_by(|a, b| a.name.cmp(&b.name));
-            n.clone()
+                .binary_search_by(|x| x.name.cmp(&cpath.name))
+                .unwrap_or_else(|x| x);
+            config.configs.insert(index, n);
+            index
         }
-    }
+    };
+    &mut config.configs[index]
 }
 
-fn config_set_key(config: &Rc<Config>, cpath: &CommandPath) -> Rc<Config> {
-    let find = config.lookup_key(&cpath.name);
-    match find {
-        Some(find) => find,
+fn config_set_key<'a>(config: &'a mut Arc<Config>, cpath: &CommandPath) -> &'a mut Arc<Config> {
+    let config = Arc::make_mut(config);
+    let index = match config.keys.iter().position(|x| x.name == cpath.name) {
+        Some(index) => index,
         None => {
-            let n = Rc::new(Config {
+            let n = Arc::new(Config {
                 name: cpath.name.clone(),
-                parent: Some(config.clone()),
                 prefix: cpath.key.clone(),
                 ..Default::default()
             });
-            config.keys.borrow_mut().push(n.clone());
-            config
+            let index = config
                 .keys
-                .borrow_mut()
-                .sort_by(|a, b| alphanumeric_sort::compare_str(&a.name, &b.name));
-            n.clone()
+                .binary_search_by(|x| alphanumeric_sort::compare_str(&x.name, &cpath.name))
+                .unwrap_or_else(|x| x);
+            config.keys.insert(index, n);
+            index
         }
-    }
+    };
+    &mut config.keys[index]
 }
 
-fn config_set_value(config: &Rc<Config>, cpath: &CommandPath) {
-    config.value.replace(cpath.name.to_owned());
+fn config_set_value(config: &mut Arc<Config>, cpath: &CommandPath) {
+    if config.value != cpath.name {
+        Arc::make_mut(config).value = cpath.name.to_owned();
+    }
 }
 
-fn config_set_list_value(config: &mut Rc<Config>, cpath: &CommandPath) {
-    config.list.borrow_mut().push(cpath.name.clone());
+fn config_set_list_value(config: &mut Arc<Config>, cpath: &CommandPath) {
+    Arc::make_mut(config).list.push(cpath.name.clone());
 }
 
 pub fn ymatch_enum(ymatch: i32) -> YangMatch {
@@ -421,7 +464,7 @@ pub fn ymatch_enum(ymatch: i32) -> YangMatch {
     }
 }
 
-pub fn set(paths: Vec<CommandPath>, mut config: Rc<Config>) {
+pub fn set(paths: Vec<CommandPath>, mut config: &mut Arc<Config>) {
     for path in paths.iter() {
         match ymatch_enum(path.ymatch) {
             YangMatch::Dir
@@ -429,83 +472,85 @@ pub fn set(paths: Vec<CommandPath>, mut config: Rc<Config>) {
             | YangMatch::Key
             | YangMatch::Leaf
             | YangMatch::LeafList => {
-                config = config_set_dir(&config, path);
+                config = config_set_dir(config, path);
             }
             YangMatch::KeyMatched => {
-                config = config_set_key(&config, path);
+                config = config_set_key(config, path);
             }
             YangMatch::LeafMatched => {
-                config_set_value(&config, path);
+                config_set_value(config, path);
             }
             YangMatch::LeafListMatched => {
-                config_set_list_value(&mut config, path);
+                config_set_list_value(config, path);
             }
         }
     }
 }
 
-fn config_delete(config: Rc<Config>, name: &String) {
-    let mut configs = config.configs.borrow_mut();
-    if let Some(remove_index) = configs.iter().position(|x| *x.name == *name) {
-        configs.remove(remove_index);
-    }
-    let mut keys = config.keys.borrow_mut();
-    if let Some(remove_index) = keys.iter().position(|x| *x.name == *name) {
-        keys.remove(remove_index);
+// Delete the child at the path. Returns true when the child is removed, and
+// the node itself is left empty and to be removed from the parent as well.
+fn config_delete_child(
+    config: &mut Arc<Config>,
+    name: &String,
+    key: bool,
+    paths: &[CommandPath],
+) -> bool {
+    let children = if key { &config.keys } else { &config.configs };
+    let index = match children.iter().position(|x| x.name == *name) {
+        Some(index) => index,
+        None => return true,
+    };
+    let node = Arc::make_mut(config);
+    let children = if key {
+        &mut node.keys
+    } else {
+        &mut node.configs
+    };
+    if !config_delete(&mut children[index], paths) {
+        return false;
     }
+    children.remove(index);
+    !node.has_dir() && !node.presence
 }
 
-pub fn delete(paths: Vec<CommandPath>, mut config: Rc<Config>) {
-    for path in paths.iter() {
-        match ymatch_enum(path.ymatch) {
-            YangMatch::Dir | YangMatch::DirMatched | YangMatch::Leaf | YangMatch::Key => {
-                if let Some(next) = config.lookup(&path.name) {
-                    config = next;
-                } else {
-                    break;
-                }
-            }
-            YangMatch::KeyMatched => {
-                if let Some(next) = config.lookup_key(&path.name) {
-                    config = next;
-                } else {
-                    break;
-                }
-            }
-            YangMatch::LeafMatched => {
-                if config.value.borrow().as_ref() != path.name {
-                    break;
-                }
+// Delete the path under the node. Returns true when the node is to be
+// removed from the parent. The node is removed as well when the path does
+// not exist below it.
+fn config_delete(config: &mut Arc<Config>, paths: &[CommandPath]) -> bool {
+    let (path, rest) = match paths.split_first() {
+        Some(split) => split,
+        None => return true,
+    };
+    match ymatch_enum(path.ymatch) {
+        YangMatch::Dir
+        | YangMatch::DirMatched
+        | YangMatch::Leaf
+        | YangMatch::Key
+        | YangMatch::LeafList => config_delete_child(config, &path.name, false, rest),
+        YangMatch::KeyMatched => config_delete_child(config, &path.name, true, rest),
+        YangMatch::LeafMatched => {
+            if config.value != path.name {
+                return true;
             }
-            YangMatch::LeafList => {
-                if let Some(next) = config.lookup(&path.name) {
-                    config = next;
-                } else {
-                    break;
-                }
+            config_delete(config, rest)
+        }
+        YangMatch::LeafListMatched => {
+            if let Some(remove_index) = config.list.iter().position(|x| *x == path.name) {
+                Arc::make_mut(config).list.remove(remove_index);
             }
-            YangMatch::LeafListMatched => {
-                let mut lists = config.list.borrow_mut();
-                if let Some(remove_index) = lists.iter().position(|x| *x == path.name) {
-                    lists.remove(remove_index);
-                }
-                if !lists.is_empty() {
-                    return;
-                }
+            if !config.list.is_empty() {
+                return false;
             }
+            config_delete(config, rest)
         }
     }
+}
 
-    while let Some(parent) = config.parent.as_ref() {
-        config_delete(parent.clone(), &config.name);
-        config = parent.clone();
-        if config.has_dir() || config.presence {
-            break;
-        }
-    }
+pub fn delete(paths: Vec<CommandPath>, config: &mut Arc<Config>) {
+    config_delete(config, &paths);
 }
 
-fn config_match_keyword(config: &Rc<Config>, name: &str, input: &str, mx: &mut Match) {
+fn config_match_keyword(config: &Arc<Config>, name: &str, input: &str, mx: &mut Match) {
     let (m, p) = match_keyword(input, name);
     if m == MatchType::None {
         return;
@@ -518,32 +563,120 @@ fn config_match_keyword(config: &Rc<Config>, name: &str, input: &str, mx: &mut M
     } else if m == mx.matched_type {
         mx.count += 1;
     }
-    mx.comps.push(Completion::new(name, ""));
+    mx.comps
+        .push(Completion::new(name, "").with_kind(CompletionKind::ConfigValue));
 }
 
-fn config_match_dir(config: &Rc<Config>, input: &str, mx: &mut Match) {
-    for config in config.configs.borrow().iter() {
+fn config_match_dir(config: &Arc<Config>, input: &str, mx: &mut Match) {
+    for config in config.configs.iter() {
         config_match_keyword(config, &config.name, input, mx);
     }
-    for key in config.keys.borrow().iter() {
+    for key in config.keys.iter() {
         config_match_keyword(key, &key.name, input, mx);
     }
 }
 
-fn config_match_value(config: &Rc<Config>, input: &str, mx: &mut Match) {
-    if config.list.borrow().is_empty() {
-        config_match_keyword(config, &config.value.borrow(), input, mx);
+fn config_match_value(config: &Arc<Config>, input: &str, mx: &mut Match) {
+    if config.list.is_empty() {
+        config_match_keyword(config, &config.value, input, mx);
     } else {
-        for value in config.list.borrow().iter() {
+        for value in config.list.iter() {
             config_match_keyword(config, value, input, mx);
         }
     }
 }
 
-pub fn config_match(config: &Rc<Config>, input: &str, mx: &mut Match) {
+pub fn config_match(config: &Arc<Config>, input: &str, mx: &mut Match) {
     if config.has_dir() {
         config_match_dir(config, input, mx);
     } else {
         config_match_value(config, input, mx);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cpath(name: &str, key: &str, ymatch: YangMatch) -> CommandPath {
+        CommandPath {
+            name: name.to_string(),
+            key: key.to_string(),
+            ymatch: ymatch as i32,
+        }
+    }
+
+    fn peer_as(addr: &str, asn: &str) -> Vec<CommandPath> {
+        vec![
+            cpath("neighbor", "", YangMatch::Key),
+            cpath(addr, "address", YangMatch::KeyMatched),
+            cpath("peer-as", "", YangMatch::Leaf),
+            cpath(asn, "", YangMatch::LeafMatched),
+        ]
+    }
+
+    #[test]
+    fn config_shared() {
+        let mut candidate = Arc::new(Config::new("".to_string()));
+        set(peer_as("10.0.0.1", "65001"), &mut candidate);
+        set(peer_as("10.0.0.2", "65002"), &mut candidate);
+        let running = candidate.clone();
+
+        set(peer_as("10.0.0.2", "65003"), &mut candidate);
+        let mut output = String::new();
+        running.list(&mut output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "neighbor 10.0.0.1",
+                "neighbor 10.0.0.1 peer-as 65001",
+                "neighbor 10.0.0.2",
+                "neighbor 10.0.0.2 peer-as 65002",
+            ]
+        );
+        let neighbor = |config: &Arc<Config>| config.lookup(&"neighbor".to_string()).unwrap();
+        let unchanged = |config: &Arc<Config>| {
+            neighbor(config)
+                .lookup_key(&"10.0.0.1".to_string())
+                .unwrap()
+        };
+        assert!(Arc::ptr_eq(&unchanged(&running), &unchanged(&candidate)));
+        assert!(!Arc::ptr_eq(&neighbor(&running), &neighbor(&candidate)));
+
+        delete(peer_as("10.0.0.1", "65001"), &mut candidate);
+        delete(peer_as("10.0.0.2", "65003"), &mut candidate);
+        assert!(!candidate.has_dir());
+        assert_eq!(neighbor(&running).keys.len(), 2);
+    }
+
+    #[test]
+    fn config_redact() {
+        let mut config = Arc::new(Config::new("".to_string()));
+        set(peer_as("10.0.0.1", "65001"), &mut config);
+        let mut password = peer_as("10.0.0.2", "65002");
+        password.truncate(2);
+        password.push(cpath("transport", "", YangMatch::Dir));
+        password.push(cpath("password", "", YangMatch::Leaf));
+        password.push(cpath("secret", "", YangMatch::LeafMatched));
+        set(password, &mut config);
+
+        let redacted = config.redact();
+        let mut output = String::new();
+        redacted.list(&mut output);
+        assert!(output.contains("neighbor 10.0.0.2 transport password ********"));
+        assert!(!output.contains("secret"));
+        output.clear();
+        config.list(&mut output);
+        assert!(output.contains("password secret"));
+
+        // Subtree without a secret is shared.
+        let neighbor = |config: &Arc<Config>| config.lookup(&"neighbor".to_string()).unwrap();
+        let peer = |config: &Arc<Config>| {
+            neighbor(config)
+                .lookup_key(&"10.0.0.1".to_string())
+                .unwrap()
+        };
+        assert!(Arc::ptr_eq(&peer(&config), &peer(&redacted)));
+    }
+}
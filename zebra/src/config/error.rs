@@ -0,0 +1,113 @@
+use super::comps::Completion;
+use super::parse::{ParseLimit, State, PARSE_INPUT_MAX};
+use super::paths::path_from_command;
+use super::vtysh::{ExecError, ExecErrorCategory};
+use super::ExecCode;
+
+fn token_at(input: &str, position: usize) -> String {
+    input[position..]
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_string()
+}
+
+pub fn exec_error_limit(input: &str) -> ExecError {
+    ExecError {
+        category: ExecErrorCategory::ErrorLimitExceeded as i32,
+        path: String::new(),
+        message: ParseLimit::Input.message(),
+        hint: String::new(),
+        position: PARSE_INPUT_MAX.min(input.len()) as u32,
+        token: String::new(),
+    }
+}
+
+// Structured error of the parse result. Path is the YANG path matched before
+// the offending token, so that frontends can highlight the token and
+// automation can branch on the category.
+pub fn exec_error(
+    input: &str,
+    code: ExecCode,
+    state: &State,
+    comps: &[Completion],
+) -> Option<ExecError> {
+    let (path, _) = path_from_command(&state.paths);
+    let position = input.len().saturating_sub(state.remain);
+    let candidates: Vec<&str> = comps
+        .iter()
+        .map(|comp| comp.name.as_str())
+        .filter(|name| *name != "<cr>")
+        .collect();
+    let (category, message, hint, position) = match (code, state.limited) {
+        (ExecCode::Nomatch, Some(limit)) => (
+            ExecErrorCategory::ErrorLimitExceeded,
+            limit.message(),
+            String::new(),
+            position,
+        ),
+        (ExecCode::Nomatch, None) => (
+            ExecErrorCategory::ErrorUnknownCommand,
+            String::from("Unknown command"),
+            String::from("Use ? to list the possible completions"),
+            position,
+        ),
+        (ExecCode::Ambiguous, _) => (
+            ExecErrorCategory::ErrorAmbiguousCommand,
+            String::from("Ambiguous command"),
+            format!("Candidates: {}", candidates.join(", ")),
+            position,
+        ),
+        (ExecCode::Incomplete, _) => (
+            ExecErrorCategory::ErrorIncompleteCommand,
+            String::from("Incomplete command"),
+            String::from("Command requires more tokens"),
+            input.trim_end().len(),
+        ),
+        _ => return None,
+    };
+    Some(ExecError {
+        category: category as i32,
+        path,
+        message,
+        hint,
+        position: position as u32,
+        token: token_at(input, position),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exec_error_position() {
+        let mut state = State::new();
+        let input = "show ip bgpp summary";
+        state.remain = "bgpp summary".len();
+        let error = exec_error(input, ExecCode::Nomatch, &state, &[]).unwrap();
+        assert_eq!(
+            error.category,
+            ExecErrorCategory::ErrorUnknownCommand as i32
+        );
+        assert_eq!(error.position, 8);
+        assert_eq!(error.token, "bgpp");
+
+        let comps = vec![Completion::new_name("summary"), Completion::new_name("sla")];
+        state.remain = "s".len();
+        let input = "show ip bgp s";
+        let error = exec_error(input, ExecCode::Ambiguous, &state, &comps).unwrap();
+        assert_eq!(error.hint, "Candidates: summary, sla");
+        assert_eq!(error.token, "s");
+
+        let error = exec_error("show ip ", ExecCode::Incomplete, &state, &[]).unwrap();
+        assert_eq!(error.position, 7);
+        assert!(exec_error(input, ExecCode::Success, &state, &[]).is_none());
+
+        // Limit is named in the message.
+        state.limited = Some(ParseLimit::Token);
+        let error = exec_error(input, ExecCode::Nomatch, &state, &[]).unwrap();
+        assert_eq!(error.category, ExecErrorCategory::ErrorLimitExceeded as i32);
+        assert_eq!(error.message, "Token exceeds 1024 bytes");
+    }
+}
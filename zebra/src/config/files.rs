@@ -1,5 +1,6 @@
-use super::token::tokenizer;
+use super::token::tokenizer_lines;
 use super::token::Token;
+use std::path::{Path, PathBuf};
 
 fn flatten(stack: &[Vec<String>]) -> String {
     let mut flat: Vec<String> = Vec::new();
@@ -16,13 +17,14 @@ fn flatten(stack: &[Vec<String>]) -> String {
     cmd
 }
 
-pub fn load_config_file(input: String) -> Vec<String> {
+// Words of each statement with the enclosing blocks, and the line number.
+fn load_statements(input: String) -> Vec<(Vec<Vec<String>>, usize)> {
     let mut stack: Vec<Vec<String>> = Vec::new();
     let mut cmds: Vec<String> = Vec::new();
-    let mut outputs: Vec<String> = Vec::new();
+    let mut outputs: Vec<(Vec<Vec<String>>, usize)> = Vec::new();
 
-    let tokens = tokenizer(input);
-    for token in tokens.iter() {
+    let tokens = tokenizer_lines(input);
+    for (token, line) in tokens.iter() {
         match token {
             Token::String(m) => {
                 cmds.push(m.to_string());
@@ -37,8 +39,7 @@ pub fn load_config_file(input: String) -> Vec<String> {
             Token::SemiColon => {
                 stack.push(cmds.clone());
                 cmds.clear();
-                let cmd = flatten(&stack);
-                outputs.push(cmd);
+                outputs.push((stack.clone(), *line));
                 stack.pop();
             }
             _ => {}
@@ -46,3 +47,117 @@ pub fn load_config_file(input: String) -> Vec<String> {
     }
     outputs
 }
+
+// Set command with the file and the line number of the statement, so that
+// errors are reported against the file which has the statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigLine {
+    pub cmd: String,
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+// "include <file>;" is replaced with the statements of the file, nested in
+// the block where it appears, so that the files are merged in order.
+// Relative path is resolved from the directory of the including file.
+// Unreadable file and include cycle are reported as errors with the location
+// of the include statement, and the file is skipped.
+pub fn load_config_files(path: &Path, input: String) -> (Vec<ConfigLine>, Vec<String>) {
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    let mut files = vec![canonical(path)];
+    load_include(path, input, &[], &mut files, &mut lines, &mut errors);
+    (lines, errors)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn load_include(
+    path: &Path,
+    input: String,
+    prefix: &[Vec<String>],
+    files: &mut Vec<PathBuf>,
+    lines: &mut Vec<ConfigLine>,
+    errors: &mut Vec<String>,
+) {
+    for (stack, line) in load_statements(input).into_iter() {
+        let mut stack: Vec<Vec<String>> = prefix.iter().cloned().chain(stack).collect();
+        let include = match stack.last() {
+            Some(cmds) if cmds.len() == 2 && cmds[0] == "include" => cmds[1].clone(),
+            _ => {
+                lines.push(ConfigLine {
+                    cmd: flatten(&stack),
+                    file: path.to_path_buf(),
+                    line,
+                });
+                continue;
+            }
+        };
+        stack.pop();
+        let included = match path.parent() {
+            Some(dir) => dir.join(&include),
+            None => PathBuf::from(&include),
+        };
+        let location = format!("{}:{}", path.display(), line);
+        if files.contains(&canonical(&included)) {
+            errors.push(format!(
+                "{}: include cycle: {}",
+                location,
+                included.display()
+            ));
+            continue;
+        }
+        let input = match std::fs::read_to_string(&included) {
+            Ok(input) => input,
+            Err(err) => {
+                errors.push(format!("{}: {}: {}", location, included.display(), err));
+                continue;
+            }
+        };
+        files.push(canonical(&included));
+        load_include(&included, input, &stack, files, lines, errors);
+        files.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn include_files() {
+        let dir = std::env::temp_dir().join(format!("zebra-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("peers")).unwrap();
+        let main = dir.join("zebra.conf");
+        std::fs::write(
+            &main,
+            "routing {\n  bgp {\n    global {\n      as 65000;\n    }\n    neighbors {\n      \
+             include peers/a.conf;\n    }\n  }\n}\ninclude missing.conf;\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("peers/a.conf"),
+            "neighbor 10.0.0.1 {\n  peer-as 65001;\n}\ninclude ../zebra.conf;\n",
+        )
+        .unwrap();
+
+        let input = std::fs::read_to_string(&main).unwrap();
+        let (lines, errors) = load_config_files(&main, input);
+        let cmds: Vec<&str> = lines.iter().map(|x| x.cmd.as_str()).collect();
+        assert_eq!(
+            cmds,
+            vec![
+                "set routing bgp global as 65000",
+                "set routing bgp neighbors neighbor 10.0.0.1 peer-as 65001",
+            ]
+        );
+        assert_eq!(lines[1].file, dir.join("peers/a.conf"));
+        assert_eq!(lines[1].line, 2);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("a.conf:4: include cycle"));
+        assert!(errors[1].contains("zebra.conf:11: "));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,44 +1,99 @@
-use super::api::{CompletionResponse, ConfigOp, ExecuteResponse, Message};
-use super::commands::Mode;
+use super::api::{CompletionResponse, ConfigOp, ExecuteResponse, Message, Render};
 use super::commands::{configure_mode_create, exec_mode_create};
-use super::configs::{carbon_copy, delete, set};
-use super::files::load_config_file;
+use super::commands::{Mode, OBSERVER_COMMANDS};
+use super::configs::{delete, set};
+use super::error::{exec_error, exec_error_limit};
+use super::files::load_config_files;
+use super::migrate::{header, header_check, header_parse, migrate, module_revisions, MIGRATIONS};
 use super::parse::parse;
-use super::parse::State;
-use super::paths::{path_trim, paths_str};
+use super::parse::{ParseLimit, State, PARSE_INPUT_MAX};
+use super::paths::{path_from_command, path_trim, paths_str};
+use super::ready::{ready_request, Readiness, ReadyState, READY_TIMEOUT};
+use super::schedule::{
+    commit_time, schedule_timer, PendingConfirm, ScheduledCommit, COMMIT_CONFIRM_TIMEOUT,
+};
 use super::util::trim_first_line;
+use super::validate::{mandatory_errors, reference_errors};
 use super::vtysh::CommandPath;
 use super::{Completion, Config, ConfigRequest, ExecCode};
+use chrono::Local;
 use libyang::{to_entry, Entry, YangStore};
 use similar::TextDiff;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::Arc;
 use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedSender};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 
+// Running and candidate config, changed by the config task only. Readers
+// take a snapshot, which is immutable and stays valid while the store is
+// changed, so the snapshot is rendered by the other tasks. Completion and
+// validation parse with the schema, which stays on the config task.
 pub struct ConfigStore {
-    pub running: RefCell<Rc<Config>>,
-    pub candidate: RefCell<Rc<Config>>,
+    running: RefCell<Arc<Config>>,
+    candidate: RefCell<Arc<Config>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub running: Arc<Config>,
+    pub candidate: Arc<Config>,
+}
+
+impl Snapshot {
+    // Snapshot shown to the observers.
+    pub fn redact(&self) -> Snapshot {
+        Snapshot {
+            running: self.running.redact(),
+            candidate: self.candidate.redact(),
+        }
+    }
 }
 
 impl ConfigStore {
     pub fn new() -> Self {
         Self {
-            running: RefCell::new(Rc::new(Config::new("".to_string(), None))),
-            candidate: RefCell::new(Rc::new(Config::new("".to_string(), None))),
+            running: RefCell::new(Arc::new(Config::new("".to_string()))),
+            candidate: RefCell::new(Arc::new(Config::new("".to_string()))),
         }
     }
 
+    pub fn running(&self) -> Arc<Config> {
+        self.running.borrow().clone()
+    }
+
+    pub fn candidate(&self) -> Arc<Config> {
+        self.candidate.borrow().clone()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            running: self.running(),
+            candidate: self.candidate(),
+        }
+    }
+
+    pub fn replace(&self, candidate: Arc<Config>) {
+        *self.candidate.borrow_mut() = candidate;
+    }
+
+    // Nodes of the candidate on the path of the change are copied unless
+    // they are owned by the candidate only.
+    pub fn update(&self, f: impl FnOnce(&mut Arc<Config>)) {
+        f(&mut self.candidate.borrow_mut());
+    }
+
+    // The trees share the nodes until either of them is changed.
     pub fn commit(&self) {
-        let running = carbon_copy(&self.candidate.borrow(), None);
-        self.running.replace(running);
+        let running = self.candidate();
+        *self.running.borrow_mut() = running;
     }
 
     pub fn discard(&self) {
-        let candidate = carbon_copy(&self.running.borrow(), None);
-        self.candidate.replace(candidate);
+        let candidate = self.running();
+        self.replace(candidate);
     }
 }
 
@@ -50,6 +105,21 @@ pub struct ConfigManager {
     pub tx: Sender<Message>,
     pub rx: Receiver<Message>,
     pub cm_clients: HashMap<String, UnboundedSender<ConfigRequest>>,
+    pub cm_subtrees: HashMap<String, Vec<String>>,
+    pub yang_modules: HashMap<String, Vec<String>>,
+    // Revisions of the YANG modules recorded in the saved configuration.
+    pub revisions: BTreeMap<String, String>,
+    // YANG modules are reloaded after the command which requested it, as
+    // the modes can not be replaced while a command of them is executed.
+    pub reload: Cell<bool>,
+    // Candidate stored by "commit at", and the running config to restore
+    // when the activated commit is not confirmed.
+    pub scheduled: RefCell<Option<ScheduledCommit>>,
+    pub confirm: RefCell<Option<PendingConfirm>>,
+    // Startup handshake with the subscribers, and the state published to
+    // the health service.
+    pub ready: ReadyState,
+    pub readiness: watch::Sender<Readiness>,
 }
 
 impl ConfigManager {
@@ -67,30 +137,108 @@ impl ConfigManager {
             tx,
             rx,
             cm_clients: HashMap::new(),
+            cm_subtrees: HashMap::new(),
+            yang_modules: HashMap::new(),
+            revisions: BTreeMap::new(),
+            reload: Cell::new(false),
+            scheduled: RefCell::new(None),
+            confirm: RefCell::new(None),
+            ready: ReadyState::new(),
+            readiness: watch::channel(Readiness::Starting).0,
         };
-        cm.init()?;
         Ok(cm)
     }
 
-    fn init(&mut self) -> anyhow::Result<()> {
+    // Register YANG module of a protocol. "exec" module is merged into exec
+    // mode commands and "config" module is merged into configuration tree.
+    // Must be called before init().
+    pub fn yang_add(&mut self, tree: &str, module: &str) {
+        self.yang_modules
+            .entry(tree.to_string())
+            .or_default()
+            .push(module.to_string());
+    }
+
+    pub fn init(&mut self) -> anyhow::Result<()> {
         let mut yang = YangStore::new();
         yang.add_path(&self.yang_path);
 
         let entry = self.load_mode(&mut yang, "exec")?;
+        for module in self.yang_modules("exec") {
+            let module = self.load_mode(&mut yang, &module)?;
+            entry_merge(&entry, &module);
+        }
         let exec = entry.clone();
         let exec_mode = exec_mode_create(entry);
         self.modes.insert("exec".to_string(), exec_mode);
 
         let entry = self.load_mode(&mut yang, "configure")?;
+        for module in self.yang_modules("config") {
+            let module = self.load_mode(&mut yang, &module)?;
+            for dir in entry.dir.borrow().iter() {
+                if dir.name == "set" || dir.name == "delete" {
+                    entry_merge(dir, &module);
+                }
+            }
+        }
         entry.dir.borrow_mut().push(run_from_exec(exec));
         let configure_mode = configure_mode_create(entry);
         self.modes.insert("configure".to_string(), configure_mode);
+        self.revisions = module_revisions(&self.yang_path);
 
         Ok(())
     }
 
-    pub fn subscribe(&mut self, name: &str, cm_tx: UnboundedSender<ConfigRequest>) {
+    fn yang_modules(&self, tree: &str) -> Vec<String> {
+        self.yang_modules.get(tree).cloned().unwrap_or_default()
+    }
+
+    // Read the YANG directory again and rebuild the modes. The previous modes
+    // are kept when a module fails to load. Running configuration is checked
+    // against the new schema, and the statements which no longer parse are
+    // reported but left as is.
+    pub fn yang_reload(&mut self) -> String {
+        let modes = std::mem::take(&mut self.modes);
+        if let Err(err) = self.init() {
+            self.modes = modes;
+            return format!("% YANG reload failed: {}\n", err);
+        }
+        let errors = self.config_errors(&self.store.running());
+        let mut output = String::from("YANG modules reloaded\n");
+        if !errors.is_empty() {
+            output.push_str(&format!(
+                "Running configuration has {} errors against the new schema:\n",
+                errors.len()
+            ));
+            for err in errors.iter() {
+                output.push_str(err);
+                output.push('\n');
+            }
+        }
+        output
+    }
+
+    // Subscribe config changes under the subtrees, e.g. "/routing/bgp". The
+    // subscriber receives every change when subtrees is empty.
+    pub fn subscribe(
+        &mut self,
+        name: &str,
+        cm_tx: UnboundedSender<ConfigRequest>,
+        subtrees: &[&str],
+    ) {
         self.cm_clients.insert(name.to_owned(), cm_tx);
+        self.ready.waiting.insert(name.to_owned());
+        self.cm_subtrees.insert(
+            name.to_owned(),
+            subtrees.iter().map(|x| x.to_string()).collect(),
+        );
+    }
+
+    fn is_owner(&self, name: &str, path: &str) -> bool {
+        match self.cm_subtrees.get(name) {
+            Some(subtrees) if !subtrees.is_empty() => subtree_owned(subtrees, path),
+            _ => true,
+        }
     }
 
     fn paths(&self, input: String) -> Option<Vec<CommandPath>> {
@@ -113,11 +261,49 @@ impl ConfigManager {
         }
     }
 
-    pub fn commit_config(&self) {
+    // Nodes of the candidate which fail validation, with the path of each.
+    // Values are parsed against the schema again so that a config loaded by
+    // a newer schema is checked as well.
+    pub fn validate_config(&self) -> Vec<String> {
+        self.config_errors(&self.store.candidate())
+    }
+
+    fn config_errors(&self, config: &Arc<Config>) -> Vec<String> {
+        let mut output = String::new();
+        config.list(&mut output);
+        let mut errors: Vec<String> = output
+            .lines()
+            .filter(|line| self.paths(line.to_string()).is_none())
+            .map(|line| format!("{}: invalid value", line))
+            .collect();
+        errors.extend(mandatory_errors(config));
+        errors.extend(reference_errors(config));
+        errors.extend(config_limit_errors(config));
+        errors
+    }
+
+    fn commit_check(&self) -> Result<(), String> {
+        config_limit_check(&self.store.candidate())?;
+        let errors = self.validate_config();
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(format!("Commit failed: {}", errors[0])),
+            len => Err(format!(
+                "Commit failed: {} and {} more, see \"show configuration validation\"",
+                errors[0],
+                len - 1
+            )),
+        }
+    }
+
+    pub fn commit_config(&self) -> Result<(), String> {
+        self.commit_check()?;
+
         let mut running = String::new();
         let mut candidate = String::new();
-        self.store.running.borrow().list(&mut running);
-        self.store.candidate.borrow().list(&mut candidate);
+        let snapshot = self.store.snapshot();
+        snapshot.running.list(&mut running);
+        snapshot.candidate.list(&mut candidate);
 
         let text_diff = TextDiff::from_lines(&running, &candidate);
         let mut binding = text_diff.unified_diff();
@@ -126,6 +312,7 @@ impl ConfigManager {
 
         let remove_first_char = |s: &str| -> String { s.chars().skip(1).collect() };
 
+        let mut ops = Vec::new();
         for line in diff.lines() {
             if !line.is_empty() {
                 let first_char = line.chars().next().unwrap();
@@ -140,13 +327,134 @@ impl ConfigManager {
                     continue;
                 }
                 let paths = paths.unwrap();
-                for (_, tx) in self.cm_clients.iter() {
+                let (path, _) = path_from_command(&paths);
+                ops.push((op, path, paths));
+            }
+        }
+
+        let _span = tracing::info_span!("config.commit", changes = ops.len()).entered();
+        let order = apply_order(
+            &ops.iter()
+                .map(|(op, path, _)| (op.clone(), path.clone()))
+                .collect::<Vec<_>>(),
+        );
+        let mut notified = HashSet::new();
+        for index in order {
+            let (op, path, paths) = &ops[index];
+            for (name, tx) in self.cm_clients.iter() {
+                if self.is_owner(name, path) {
                     tx.send(ConfigRequest::new(paths.clone(), op.clone()))
                         .unwrap();
+                    notified.insert(name);
                 }
             }
         }
+        for name in notified.into_iter() {
+            if let Some(tx) = self.cm_clients.get(name) {
+                tx.send(ConfigRequest::new(Vec::new(), ConfigOp::CommitEnd))
+                    .unwrap();
+            }
+        }
         self.store.commit();
+        Ok(())
+    }
+
+    // Store the candidate to be committed at the time, replacing the one
+    // scheduled before. It is validated now so that errors are found ahead
+    // of the maintenance window.
+    pub fn commit_at(&self, at: &str) -> Result<String, String> {
+        let now = Local::now();
+        let time = match commit_time(at, now) {
+            Some(time) => time,
+            None => return Err(format!("% Invalid or past time {}\n", at)),
+        };
+        self.commit_check()?;
+        let delay = (time - now).to_std().unwrap_or_default();
+        self.scheduled.replace(Some(ScheduledCommit {
+            at: time,
+            config: self.store.candidate(),
+            timer: schedule_timer(self.tx.clone(), delay, Message::CommitAt),
+        }));
+        Ok(format!(
+            "Commit scheduled at {}, confirm within {} seconds after the activation\n",
+            time.format("%Y-%m-%d %H:%M:%S %:z"),
+            COMMIT_CONFIRM_TIMEOUT.as_secs()
+        ))
+    }
+
+    // Activate the scheduled commit. Uncommitted changes of the candidate
+    // are replaced by the scheduled one.
+    pub fn commit_scheduled(&self) {
+        let scheduled = match self.scheduled.take() {
+            Some(scheduled) => scheduled,
+            None => return,
+        };
+        let rollback = self.store.running();
+        self.store.replace(scheduled.config.clone());
+        match self.commit_config() {
+            Ok(()) => {
+                println!(
+                    "config: commit scheduled at {} activated",
+                    scheduled.at.format("%Y-%m-%d %H:%M:%S %:z")
+                );
+                self.confirm.replace(Some(PendingConfirm {
+                    rollback,
+                    timer: schedule_timer(
+                        self.tx.clone(),
+                        COMMIT_CONFIRM_TIMEOUT,
+                        Message::CommitRollback,
+                    ),
+                }));
+            }
+            Err(err) => {
+                println!("config: scheduled commit failed: {}", err);
+                self.store.discard();
+            }
+        }
+    }
+
+    // Restore the running config before the scheduled commit which has not
+    // been confirmed.
+    pub fn commit_rollback(&self) {
+        let pending = match self.confirm.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        self.store.replace(pending.rollback.clone());
+        match self.commit_config() {
+            Ok(()) => println!("config: scheduled commit not confirmed, rolled back"),
+            Err(err) => println!("config: rollback failed: {}", err),
+        }
+    }
+
+    // Replay the running config to the subscriber which has lost its state,
+    // e.g. the BGP process of split-process deployment reconnected.
+    pub fn resync(&self, name: &str) {
+        let tx = match self.cm_clients.get(name) {
+            Some(tx) => tx,
+            None => return,
+        };
+        let mut running = String::new();
+        self.store.running().list(&mut running);
+        let mut ops = Vec::new();
+        for line in running.lines() {
+            if let Some(paths) = self.paths(line.to_string()) {
+                let (path, _) = path_from_command(&paths);
+                if self.is_owner(name, &path) {
+                    ops.push((ConfigOp::Set, path, paths));
+                }
+            }
+        }
+        let order = apply_order(
+            &ops.iter()
+                .map(|(op, path, _)| (op.clone(), path.clone()))
+                .collect::<Vec<_>>(),
+        );
+        let _ = tx.send(ConfigRequest::new(Vec::new(), ConfigOp::Resync));
+        for index in order {
+            let _ = tx.send(ConfigRequest::new(ops[index].2.clone(), ConfigOp::Set));
+        }
+        let _ = tx.send(ConfigRequest::new(Vec::new(), ConfigOp::CommitEnd));
     }
 
     fn load_mode(&self, yang: &mut YangStore, mode: &str) -> anyhow::Result<Rc<Entry>> {
@@ -159,52 +467,162 @@ impl ConfigManager {
     pub fn load_config(&self) {
         let output = std::fs::read_to_string(&self.config_path);
         if let Ok(output) = output {
-            let cmds = load_config_file(output);
+            let saved = header_parse(&output);
+            header_check(&saved, &self.revisions);
+            let (lines, errors) = load_config_files(&self.config_path, output);
+            for err in errors.iter() {
+                println!("Config load: {}", err);
+            }
+            let cmds: Vec<String> = lines
+                .into_iter()
+                .filter_map(|line| migrate(line.cmd, &saved, MIGRATIONS))
+                .collect();
             if let Some(mode) = self.modes.get("configure") {
                 for cmd in cmds.iter() {
-                    let _ = self.execute(mode, cmd);
+                    let _ = self.execute(mode, cmd, false);
                 }
             }
         }
-        self.commit_config();
+        if let Err(err) = self.commit_config() {
+            println!("Config load failed: {}", err);
+            self.store.discard();
+        }
     }
 
+    // Validate the configuration file without publishing it. Each statement
+    // is parsed against the YANG schema, then the whole config is checked as
+    // a commit. Errors are returned with the file and the line number.
+    pub fn check_config(&self) -> Vec<String> {
+        let name = self.config_path.display();
+        let input = match std::fs::read_to_string(&self.config_path) {
+            Ok(input) => input,
+            Err(err) => return vec![format!("{}: {}", name, err)],
+        };
+        let mode = match self.modes.get("configure") {
+            Some(mode) => mode,
+            None => return vec![format!("{}: configure mode is not loaded", name)],
+        };
+        let saved = header_parse(&input);
+        let (lines, mut errors) = load_config_files(&self.config_path, input);
+        for line in lines.into_iter() {
+            let cmd = match migrate(line.cmd, &saved, MIGRATIONS) {
+                Some(cmd) => cmd,
+                None => continue,
+            };
+            let err = match self.execute(mode, &cmd, false).code {
+                ExecCode::Show => continue,
+                ExecCode::Incomplete => "incomplete command",
+                ExecCode::Ambiguous => "ambiguous command",
+                _ => "invalid command",
+            };
+            errors.push(format!(
+                "{}:{}: {}: {}",
+                line.file.display(),
+                line.line,
+                err,
+                cmd
+            ));
+        }
+        for err in self.validate_config().into_iter() {
+            errors.push(format!("{}: {}", name, err));
+        }
+        errors
+    }
+
+    // Module revisions are recorded as comments so that deprecated paths are
+    // migrated when the configuration is loaded by a later release.
     pub fn save_config(&self) {
-        let mut output = String::new();
-        self.store.running.borrow().format(&mut output);
+        let mut output = header(&self.revisions);
+        self.store.running().format(&mut output);
         std::fs::write(&self.config_path, output).expect("Unable to write file");
     }
 
-    pub fn execute(&self, mode: &Mode, input: &str) -> (ExecCode, String, Vec<CommandPath>) {
+    // Observers run the show commands only, and the config is rendered with
+    // the secrets redacted.
+    pub fn execute(&self, mode: &Mode, input: &str, observer: bool) -> ExecuteResponse {
+        if input.len() > PARSE_INPUT_MAX {
+            let output = format!("% {}\n", ParseLimit::Input.message());
+            let mut resp = ExecuteResponse::from((ExecCode::Show, output, Vec::new()));
+            resp.error = Some(exec_error_limit(input));
+            return resp;
+        }
         let state = State::new();
-        let (code, _comps, state) = parse(
+        let (code, comps, state) = parse(
             input,
             mode.entry.clone(),
-            Some(self.store.candidate.borrow().clone()),
+            Some(self.store.candidate()),
             state,
         );
+        if observer && observer_denied(mode, &state) {
+            let output = String::from("% Command is not permitted for observer\n");
+            return ExecuteResponse::from((ExecCode::Show, output, Vec::new()));
+        }
+        let error = exec_error(input, code, &state, &comps);
+        if let Some(limit) = state.limited {
+            let output = format!("% {}\n", limit.message());
+            let mut resp = ExecuteResponse::from((ExecCode::Show, output, Vec::new()));
+            resp.error = error;
+            return resp;
+        }
+        let render = match mode.smap.get(&paths_str(&state.paths)) {
+            Some(func) if !state.set && !state.delete => {
+                let snapshot = self.store.snapshot();
+                Some(Render {
+                    func: *func,
+                    snapshot: if observer {
+                        snapshot.redact()
+                    } else {
+                        snapshot
+                    },
+                })
+            }
+            _ => None,
+        };
+        let mut resp = ExecuteResponse::from(self.execute_paths(mode, input, code, state));
+        resp.error = error;
+        resp.render = render;
+        resp
+    }
+
+    fn execute_paths(
+        &self,
+        mode: &Mode,
+        input: &str,
+        code: ExecCode,
+        state: State,
+    ) -> (ExecCode, String, Vec<CommandPath>) {
         if state.set {
             if code != ExecCode::Success {
                 return (code, String::from(""), state.paths);
             }
             let paths = path_trim("set", state.paths.clone());
-            set(paths, self.store.candidate.borrow().clone());
+            self.store.update(|config| set(paths, config));
             (ExecCode::Show, String::from(""), state.paths)
         } else if state.delete {
             let paths = path_trim("delete", state.paths.clone());
             for p in paths.iter() {
                 println!("{:?}", p);
             }
-            delete(paths, self.store.candidate.borrow().clone());
+            self.store.update(|config| delete(paths, config));
             (ExecCode::Show, String::from(""), state.paths)
-        } else if state.show && state.paths.len() > 1 {
+        } else if state.show
+            && state.paths.len() > 1
+            && !mode.fmap.contains_key(&paths_str(&state.paths))
+            && !mode.afmap.contains_key(&path_from_command(&state.paths).0)
+        {
             let paths = path_trim("run", state.paths.clone());
             (ExecCode::RedirectShow, input.to_string(), paths)
         } else {
             let path = paths_str(&state.paths);
+            let (arg_path, args) = path_from_command(&state.paths);
             if let Some(f) = mode.fmap.get(&path) {
                 let (code, input) = f(self);
                 (code, input, state.paths)
+            } else if mode.smap.contains_key(&path) {
+                (ExecCode::Show, String::new(), state.paths)
+            } else if let Some(f) = mode.afmap.get(&arg_path) {
+                let (code, input) = f(self, args);
+                (code, input, state.paths)
             } else {
                 (code, "".to_string(), state.paths)
             }
@@ -236,7 +654,7 @@ impl ConfigManager {
         let (code, comps, _state) = parse(
             input,
             mode.entry.clone(),
-            Some(self.store.candidate.borrow().clone()),
+            Some(self.store.candidate()),
             state,
         );
         (code, comps)
@@ -245,16 +663,32 @@ impl ConfigManager {
     pub async fn process_message(&mut self, m: Message) {
         match m {
             Message::Execute(req) => {
-                let mut resp = ExecuteResponse::new();
-                match self.modes.get(&req.mode) {
-                    Some(mode) => {
-                        (resp.code, resp.output, resp.paths) = self.execute(mode, &req.input);
-                    }
-                    None => {
-                        resp.code = ExecCode::Nomatch;
+                // Changes before the startup config would be overwritten by
+                // it.
+                if self.ready.state != Readiness::Ready && req.mode == "configure" {
+                    let output = String::from("% Startup configuration is not loaded yet\n");
+                    let resp = ExecuteResponse::from((ExecCode::Show, output, Vec::new()));
+                    req.resp.send(resp).unwrap();
+                    return;
+                }
+                let mut resp = match self.modes.get(&req.mode) {
+                    Some(mode) => self.execute(mode, &req.input, req.observer),
+                    None => ExecuteResponse::from((ExecCode::Nomatch, String::new(), Vec::new())),
+                };
+                if self.reload.take() {
+                    resp.output = self.yang_reload();
+                }
+                // Rendering of the snapshot does not block the messages
+                // following it.
+                match resp.render.take() {
+                    Some(render) => {
+                        tokio::task::spawn_blocking(move || {
+                            resp.output = (render.func)(&render.snapshot);
+                            let _ = req.resp.send(resp);
+                        });
                     }
+                    None => req.resp.send(resp).unwrap(),
                 }
-                req.resp.send(resp).unwrap();
             }
             Message::Completion(req) => {
                 let mut resp = CompletionResponse::new();
@@ -268,6 +702,192 @@ impl ConfigManager {
                 }
                 req.resp.send(resp).unwrap();
             }
+            Message::Resync(name) => {
+                self.resync(&name);
+            }
+            Message::CommitAt => {
+                self.commit_scheduled();
+            }
+            Message::CommitRollback => {
+                self.commit_rollback();
+            }
+            Message::Ready(name) => {
+                if self.ready.ready(&name) {
+                    self.ready_load();
+                }
+            }
+            Message::ReadyTimeout => {
+                if self.ready.timeout() {
+                    let waiting: Vec<String> = self.ready.waiting.iter().cloned().collect();
+                    println!(
+                        "config: {} not ready, loading startup config",
+                        waiting.join(" ")
+                    );
+                    self.ready_load();
+                }
+            }
+        }
+    }
+
+    pub fn readiness(&self) -> watch::Receiver<Readiness> {
+        self.readiness.subscribe()
+    }
+
+    fn ready_set(&mut self, state: Readiness) {
+        self.ready.state = state;
+        self.readiness.send_replace(state);
+    }
+
+    // Handshake with the subscribers, the startup config is loaded when all
+    // of them have answered.
+    fn ready_start(&mut self) {
+        for (name, tx) in self.cm_clients.iter() {
+            ready_request(name, tx, self.tx.clone());
+        }
+        let _ = schedule_timer(self.tx.clone(), READY_TIMEOUT, Message::ReadyTimeout);
+        if self.ready.waiting.is_empty() {
+            self.ready_load();
+        }
+    }
+
+    fn ready_load(&mut self) {
+        self.ready_set(Readiness::Loading);
+        self.load_config();
+        self.ready_set(Readiness::Ready);
+        println!("config: startup config loaded");
+    }
+}
+
+// Limits on the size of the configuration. A commit exceeding one is
+// rejected before any change is published, so a broken automation push can't
+// load a config which stalls the subscribers. The limit is configured under
+// "system config-limits" with the name, otherwise the default applies.
+const CONFIG_LIMITS: &[(&str, &[&str], usize)] = &[
+    (
+        "max-neighbors",
+        &["routing", "bgp", "neighbors", "neighbor"],
+        1024,
+    ),
+    ("max-prefix-list-entries", &["prefix-list", "seq"], 100000),
+    ("max-route-map-entries", &["route-map", "seq"], 10000),
+];
+
+fn config_limit(config: &Config, name: &str) -> Option<usize> {
+    let limit = config
+        .lookup(&"system".to_string())?
+        .lookup(&"config-limits".to_string())?
+        .lookup(&name.to_string())?;
+    let value = limit.value.parse().ok();
+    value
+}
+
+fn config_limit_errors(config: &Config) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (name, path, default) in CONFIG_LIMITS.iter() {
+        let limit = config_limit(config, name).unwrap_or(*default);
+        let count = config.count(path);
+        if count > limit {
+            errors.push(format!(
+                "{} {} exceeds {} {}",
+                path.join(" "),
+                count,
+                name,
+                limit
+            ));
+        }
+    }
+    errors
+}
+
+fn config_limit_check(config: &Config) -> Result<(), String> {
+    match config_limit_errors(config).into_iter().next() {
+        Some(err) => Err(format!("Commit failed: {}", err)),
+        None => Ok(()),
+    }
+}
+
+// Config subtrees which define objects referenced from other config, such as
+// community-list referenced from policy and peer group from neighbors. The
+// ones referenced from the later ones come first.
+const DEFINITIONS: &[&str] = &[
+    "/community-list",
+    "/large-community-list",
+    "/extended-community-list",
+    "/prefix-list",
+    "/as-path-list",
+    "/route-map",
+    "/routing/bgp/peer-groups",
+];
+
+// Set, delete and the commands other than the show commands.
+fn observer_denied(mode: &Mode, state: &State) -> bool {
+    if state.set || state.delete {
+        return true;
+    }
+    let path = paths_str(&state.paths);
+    let (arg_path, _) = path_from_command(&state.paths);
+    let permitted = |path: &String| OBSERVER_COMMANDS.contains(&path.as_str());
+    (mode.fmap.contains_key(&path) && !permitted(&path))
+        || (mode.afmap.contains_key(&arg_path) && !permitted(&arg_path))
+}
+
+// Whether the config path is one of the subtrees or under it.
+fn subtree_owned<S: AsRef<str>>(subtrees: &[S], path: &str) -> bool {
+    subtrees
+        .iter()
+        .map(|x| x.as_ref())
+        .any(|x| path == x || path.starts_with(&format!("{}/", x)))
+}
+
+fn definition_rank(path: &str) -> Option<usize> {
+    DEFINITIONS
+        .iter()
+        .position(|x| path == *x || path.starts_with(&format!("{}/", x)))
+}
+
+// Order of publishing committed changes so that a subscriber never sees a
+// reference to an object which does not exist. Referrers are deleted before
+// definitions, and definitions are set before referrers, which includes
+// route-map referencing the lists. Deletes are applied in reverse order so
+// that children are removed before the parent.
+fn apply_order(ops: &[(ConfigOp, String)]) -> Vec<usize> {
+    let phase = |op: &ConfigOp, path: &str| match (op, definition_rank(path)) {
+        (ConfigOp::Delete, None) => (0, 0),
+        (ConfigOp::Delete, Some(rank)) => (1, DEFINITIONS.len() - rank),
+        (_, Some(rank)) => (2, rank),
+        (_, None) => (3, 0),
+    };
+    let mut order: Vec<usize> = (0..ops.len()).collect();
+    order.sort_by_key(|&index| {
+        let (op, path) = &ops[index];
+        let phase = phase(op, path);
+        let pos = if *op == ConfigOp::Delete {
+            ops.len() - index
+        } else {
+            index
+        };
+        (phase, pos)
+    });
+    order
+}
+
+// Merge children of src into dst. Directories with the same name are merged
+// recursively.
+fn entry_merge(dst: &Rc<Entry>, src: &Rc<Entry>) {
+    for child in src.dir.borrow().iter() {
+        let found = dst
+            .dir
+            .borrow()
+            .iter()
+            .find(|e| e.name == child.name)
+            .cloned();
+        match found {
+            Some(found) => {
+                if !Rc::ptr_eq(&found, child) {
+                    entry_merge(&found, child);
+                }
+            }
+            None => dst.dir.borrow_mut().push(child.clone()),
         }
     }
 }
@@ -282,7 +902,7 @@ fn run_from_exec(exec: Rc<Entry>) -> Rc<Entry> {
 }
 
 pub async fn event_loop(mut config: ConfigManager) {
-    config.load_config();
+    config.ready_start();
     loop {
         tokio::select! {
             Some(msg) = config.rx.recv() => {
@@ -296,3 +916,132 @@ fn has_interfaces(input: &str) -> bool {
     input.split_whitespace().any(|s| s == "interfaces")
         | input.split_whitespace().any(|s| s == "neighbors")
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgp::{Bgp, BGP_SUBTREES};
+    use crate::config::vtysh::YangMatch;
+    use crate::rib::RibTxChannel;
+
+    fn cpath(name: &str, key: &str, ymatch: YangMatch) -> CommandPath {
+        CommandPath {
+            name: name.to_string(),
+            key: key.to_string(),
+            ymatch: ymatch as i32,
+        }
+    }
+
+    // Commit of the list is sent to BGP and reaches its callback.
+    fn bgp_commit(bgp: &mut Bgp, list: &str, name: &str) {
+        let paths = vec![
+            cpath(list, "", YangMatch::Key),
+            cpath(name, "name", YangMatch::KeyMatched),
+        ];
+        let (path, _) = path_from_command(&paths);
+        assert!(subtree_owned(BGP_SUBTREES, &path));
+        bgp.process_cm_msg(ConfigRequest::new(paths, ConfigOp::Set));
+    }
+
+    #[test]
+    fn bgp_subtree_commit() {
+        let mut bgp = Bgp::new(RibTxChannel::new().tx);
+        bgp_commit(&mut bgp, "large-community-list", "customers");
+        assert!(bgp.policy.lclist.contains_key("customers"));
+        bgp_commit(&mut bgp, "extended-community-list", "targets");
+        assert!(bgp.policy.eclist.contains_key("targets"));
+        assert!(definition_rank("/large-community-list/seq").is_some());
+        assert!(definition_rank("/extended-community-list/seq").is_some());
+
+        // Every callback is reachable by the config sent to BGP.
+        for path in bgp.callbacks.keys() {
+            assert!(subtree_owned(BGP_SUBTREES, path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn apply_order_definition_first() {
+        let ops = vec![
+            (
+                ConfigOp::Delete,
+                "/routing/bgp/neighbors/neighbor".to_string(),
+            ),
+            (ConfigOp::Delete, "/community-list/seq".to_string()),
+            (
+                ConfigOp::Delete,
+                "/routing/bgp/neighbors/neighbor/peer-as".to_string(),
+            ),
+            (ConfigOp::Set, "/routing/bgp/global/as".to_string()),
+            (ConfigOp::Set, "/community-list".to_string()),
+            (ConfigOp::Set, "/community-list/seq".to_string()),
+        ];
+        assert_eq!(apply_order(&ops), vec![2, 0, 1, 4, 5, 3]);
+    }
+
+    #[test]
+    fn apply_order_route_map() {
+        let ops = vec![
+            (
+                ConfigOp::Set,
+                "/routing/bgp/neighbors/neighbor/route-map".to_string(),
+            ),
+            (ConfigOp::Set, "/route-map".to_string()),
+            (
+                ConfigOp::Set,
+                "/route-map/seq/match/prefix-list".to_string(),
+            ),
+            (ConfigOp::Set, "/prefix-list".to_string()),
+            (ConfigOp::Delete, "/prefix-list".to_string()),
+            (ConfigOp::Delete, "/route-map".to_string()),
+            (
+                ConfigOp::Delete,
+                "/routing/bgp/network/route-map".to_string(),
+            ),
+        ];
+        assert_eq!(apply_order(&ops), vec![6, 5, 4, 3, 1, 2, 0]);
+    }
+
+    #[test]
+    fn store_snapshot() {
+        let store = ConfigStore::new();
+        let snapshot = store.snapshot();
+        store.update(|config| {
+            Arc::make_mut(config)
+                .configs
+                .push(Arc::new(Config::new("system".to_string())));
+        });
+        assert!(!snapshot.candidate.has_dir());
+        assert!(store.candidate().has_dir());
+        assert!(!store.running().has_dir());
+
+        let snapshot = std::thread::spawn(move || store.snapshot()).join().unwrap();
+        assert!(snapshot.candidate.has_dir());
+    }
+
+    #[test]
+    fn config_limit_exceeded() {
+        let mut plist = Config::new("prefix-list".to_string());
+        for name in ["a", "b"] {
+            let mut seq = Config::new("seq".to_string());
+            for num in ["10", "20"] {
+                seq.keys.push(Arc::new(Config::new(num.to_string())));
+            }
+            let mut list = Config::new(name.to_string());
+            list.configs.push(Arc::new(seq));
+            plist.keys.push(Arc::new(list));
+        }
+        let mut root = Config::new("".to_string());
+        root.configs.push(Arc::new(plist));
+        assert_eq!(root.count(&["prefix-list", "seq"]), 4);
+        assert!(config_limit_check(&root).is_ok());
+
+        let mut limit = Config::new("max-prefix-list-entries".to_string());
+        limit.value = "3".to_string();
+        let mut limits = Config::new("config-limits".to_string());
+        limits.configs.push(Arc::new(limit));
+        let mut system = Config::new("system".to_string());
+        system.configs.push(Arc::new(limits));
+        root.configs.push(Arc::new(system));
+        assert!(config_limit_check(&root).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+// Format version of the saved configuration.
+pub const CONFIG_VERSION: u32 = 1;
+
+const HEADER_VERSION: &str = "# config-version ";
+const HEADER_MODULE: &str = "# yang-module ";
+
+// Rewrite of a deprecated config path. It is applied to the configuration
+// saved with a revision of the module older than the one which deprecated the
+// path, or saved without revisions. Statements under "from" are moved under
+// "to", or removed when "to" is empty. "*" matches a list key and is copied
+// to the same position of "to".
+pub struct Migration {
+    pub module: &'static str,
+    pub revision: &'static str,
+    pub from: &'static str,
+    pub to: &'static str,
+}
+
+// Migrations in the order of the revisions.
+pub const MIGRATIONS: &[Migration] = &[];
+
+// Revisions of the YANG modules in the directory, taken from the
+// "module@revision.yang" file names.
+pub fn module_revisions(dir: &str) -> BTreeMap<String, String> {
+    let mut revisions = BTreeMap::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return revisions,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some((module, revision)) = name
+            .strip_suffix(".yang")
+            .and_then(|name| name.split_once('@'))
+        {
+            revisions.insert(module.to_string(), revision.to_string());
+        }
+    }
+    revisions
+}
+
+// Comment lines recorded at the top of the saved configuration.
+pub fn header(revisions: &BTreeMap<String, String>) -> String {
+    let mut out = format!("{}{}\n", HEADER_VERSION, CONFIG_VERSION);
+    for (module, revision) in revisions.iter() {
+        out.push_str(&format!("{}{}@{}\n", HEADER_MODULE, module, revision));
+    }
+    out
+}
+
+// Module revisions recorded in the saved configuration. Empty when the
+// configuration was saved before versioning.
+pub fn header_parse(input: &str) -> BTreeMap<String, String> {
+    input
+        .lines()
+        .filter_map(|line| line.strip_prefix(HEADER_MODULE))
+        .filter_map(|module| module.trim().split_once('@'))
+        .map(|(module, revision)| (module.to_string(), revision.to_string()))
+        .collect()
+}
+
+// Warn about the modules saved by a newer release, which statements may not
+// be understood by this one.
+pub fn header_check(saved: &BTreeMap<String, String>, current: &BTreeMap<String, String>) {
+    for (module, revision) in saved.iter() {
+        if let Some(current) = current.get(module) {
+            if revision > current {
+                println!(
+                    "config: {} is saved with revision {} newer than {}",
+                    module, revision, current
+                );
+            }
+        }
+    }
+}
+
+fn path_rewrite(words: &[&str], from: &str, to: &str) -> Option<Vec<String>> {
+    let from: Vec<&str> = from.split_whitespace().collect();
+    if words.len() < from.len() {
+        return None;
+    }
+    let mut keys = Vec::new();
+    for (word, pattern) in words.iter().zip(from.iter()) {
+        if *pattern == "*" {
+            keys.push(*word);
+        } else if word != pattern {
+            return None;
+        }
+    }
+    let mut keys = keys.into_iter();
+    let mut rewritten: Vec<String> = to
+        .split_whitespace()
+        .map(|word| match word {
+            "*" => keys.next().unwrap_or(word).to_string(),
+            _ => word.to_string(),
+        })
+        .collect();
+    rewritten.extend(words[from.len()..].iter().map(|word| word.to_string()));
+    Some(rewritten)
+}
+
+// Apply the migrations to a "set" command loaded from the configuration.
+// None when the statement is removed.
+pub fn migrate(
+    mut cmd: String,
+    saved: &BTreeMap<String, String>,
+    migrations: &[Migration],
+) -> Option<String> {
+    for migration in migrations.iter() {
+        let outdated = saved
+            .get(migration.module)
+            .map(|revision| revision.as_str() < migration.revision)
+            .unwrap_or(true);
+        if !outdated {
+            continue;
+        }
+        let words: Vec<&str> = cmd.split_whitespace().collect();
+        let (set, path) = match words.split_first() {
+            Some((set, path)) => (set, path),
+            None => continue,
+        };
+        let rewritten = match path_rewrite(path, migration.from, migration.to) {
+            Some(rewritten) => rewritten,
+            None => continue,
+        };
+        if migration.to.is_empty() {
+            println!("config: removed deprecated statement: {}", cmd);
+            return None;
+        }
+        let migrated = format!("{} {}", set, rewritten.join(" "));
+        println!("config: migrated {} to {}", cmd, migrated);
+        cmd = migrated;
+    }
+    Some(cmd)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            module: "ietf-bgp",
+            revision: "2024-01-01",
+            from: "routing bgp neighbors neighbor * old-timer",
+            to: "routing bgp neighbors neighbor * timers new-timer",
+        },
+        Migration {
+            module: "ietf-bgp",
+            revision: "2024-01-01",
+            from: "routing bgp obsolete",
+            to: "",
+        },
+    ];
+
+    #[test]
+    fn migrate_outdated() {
+        let revisions = BTreeMap::from([("ietf-bgp".to_string(), "2023-07-05".to_string())]);
+        let saved = header_parse(&header(&revisions));
+        assert_eq!(saved, revisions);
+
+        let cmd = "set routing bgp neighbors neighbor 10.0.0.1 old-timer 30".to_string();
+        assert_eq!(
+            migrate(cmd, &saved, TEST_MIGRATIONS).as_deref(),
+            Some("set routing bgp neighbors neighbor 10.0.0.1 timers new-timer 30")
+        );
+        let cmd = "set routing bgp obsolete true".to_string();
+        assert_eq!(migrate(cmd, &saved, TEST_MIGRATIONS), None);
+        let cmd = "set routing bgp global as 65000".to_string();
+        assert_eq!(migrate(cmd.clone(), &saved, TEST_MIGRATIONS), Some(cmd));
+
+        // Current configuration is left as is.
+        let current = BTreeMap::from([("ietf-bgp".to_string(), "2024-01-01".to_string())]);
+        let cmd = "set routing bgp obsolete true".to_string();
+        assert_eq!(migrate(cmd.clone(), &current, TEST_MIGRATIONS), Some(cmd));
+    }
+}
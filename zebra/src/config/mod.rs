@@ -1,7 +1,11 @@
 mod vtysh {
     tonic::include_proto!("vtysh");
 }
-pub use vtysh::ExecCode;
+
+mod health {
+    tonic::include_proto!("grpc.health.v1");
+}
+pub use vtysh::{CommandPath, ExecCode};
 
 mod manager;
 pub use manager::event_loop;
@@ -22,11 +26,16 @@ mod paths;
 pub use paths::path_from_command;
 
 mod api;
-pub use api::{ConfigChannel, ConfigOp, ConfigRequest, DisplayRequest, ShowChannel};
+pub use api::{ConfigChannel, ConfigOp, ConfigRequest, DisplayRequest, Message, ShowChannel};
 
 mod commands;
+mod error;
 mod files;
 mod ip;
+mod migrate;
 mod parse;
+mod ready;
+mod schedule;
 mod token;
 mod util;
+mod validate;
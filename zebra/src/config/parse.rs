@@ -1,5 +1,6 @@
 use super::comps::{
-    centry, cleaf, cname, comps_add_all, comps_add_config, comps_add_cr, comps_append, crange,
+    centry, chint, cleaf, cname, comps_add_all, comps_add_config, comps_add_cr, comps_append,
+    crange,
 };
 use super::configs::config_match;
 use super::ip::*;
@@ -10,6 +11,31 @@ use libyang::{range_match, Entry, MinMax, RangeExtract, RangeNode, TypeNode, Yan
 use regex::Regex;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::Arc;
+
+// Limits of the command line, which may come from the gRPC API, so that a
+// pathological input is rejected instead of exhausting the stack and CPU.
+pub const PARSE_INPUT_MAX: usize = 8192;
+pub const PARSE_TOKEN_MAX: usize = 1024;
+pub const PARSE_DEPTH_MAX: usize = 256;
+
+// Limit exceeded by the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseLimit {
+    Input,
+    Depth,
+    Token,
+}
+
+impl ParseLimit {
+    pub fn message(&self) -> String {
+        match self {
+            ParseLimit::Input => format!("Command exceeds {} bytes", PARSE_INPUT_MAX),
+            ParseLimit::Depth => format!("Command exceeds {} tokens", PARSE_DEPTH_MAX),
+            ParseLimit::Token => format!("Token exceeds {} bytes", PARSE_TOKEN_MAX),
+        }
+    }
+}
 
 pub struct State {
     ymatch: YangMatch,
@@ -19,6 +45,12 @@ pub struct State {
     pub show: bool,
     pub paths: Vec<CommandPath>,
     pub links: Vec<String>,
+    // Number of tokens parsed so far, the depth of the recursion.
+    depth: usize,
+    // Length of the input remaining at the last token parsed, and the limit
+    // the parse stopped at.
+    pub remain: usize,
+    pub limited: Option<ParseLimit>,
 }
 
 impl State {
@@ -31,6 +63,9 @@ impl State {
             paths: Vec::new(),
             index: 0usize,
             links: Vec::new(),
+            depth: 0,
+            remain: 0,
+            limited: None,
         }
     }
 }
@@ -128,7 +163,7 @@ pub struct Match {
     pub comps: Vec<Completion>,
     pub matched_entry: Rc<Entry>,
     pub matched_type: MatchType,
-    pub matched_config: Rc<Config>,
+    pub matched_config: Arc<Config>,
 }
 
 impl Match {
@@ -223,19 +258,35 @@ fn match_builder() -> MatchMap {
         })
         .kind(YangType::Ipv4Addr)
         .exec(|m, entry, input, _node| {
-            m.process(entry, match_ipv4_addr(input), cname("A.B.C.D"));
+            m.process(
+                entry,
+                match_ipv4_addr(input),
+                chint("A.B.C.D", &YangType::Ipv4Addr),
+            );
         })
         .kind(YangType::Ipv4Prefix)
         .exec(|m, entry, input, _node| {
-            m.process(entry, match_ipv4_net(input), cname("A.B.C.D/M"));
+            m.process(
+                entry,
+                match_ipv4_net(input),
+                chint("A.B.C.D/M", &YangType::Ipv4Prefix),
+            );
         })
         .kind(YangType::Ipv6Addr)
         .exec(|m, entry, input, _node| {
-            m.process(entry, match_ipv6_addr(input), cname("X:X::X:X"));
+            m.process(
+                entry,
+                match_ipv6_addr(input),
+                chint("X:X::X:X", &YangType::Ipv6Addr),
+            );
         })
         .kind(YangType::Ipv6Prefix)
         .exec(|m, entry, input, _node| {
-            m.process(entry, match_ipv6_net(input), cname("X:X::X:X/M"));
+            m.process(
+                entry,
+                match_ipv6_net(input),
+                chint("X:X::X:X/M", &YangType::Ipv6Prefix),
+            );
         })
         .kind(YangType::Enumeration)
         .exec(|m, entry, input, node| {
@@ -361,12 +412,35 @@ pub fn ymatch_complete(ymatch: YangMatch) -> bool {
         || ymatch == YangMatch::LeafListMatched
 }
 
+// Limit exceeded by the remaining input at the depth, if any.
+fn parse_limit_exceeded(input: &str, depth: usize) -> Option<ParseLimit> {
+    if depth == 0 && input.len() > PARSE_INPUT_MAX {
+        return Some(ParseLimit::Input);
+    }
+    if depth >= PARSE_DEPTH_MAX {
+        return Some(ParseLimit::Depth);
+    }
+    let token = input
+        .find(|c: char| c == ' ' || c == '\n' || c == '\t')
+        .unwrap_or(input.len());
+    if token > PARSE_TOKEN_MAX {
+        return Some(ParseLimit::Token);
+    }
+    None
+}
+
 pub fn parse(
     input: &str,
     entry: Rc<Entry>,
-    mut config: Option<Rc<Config>>,
+    mut config: Option<Arc<Config>>,
     mut s: State,
 ) -> (ExecCode, Vec<Completion>, State) {
+    s.remain = input.len();
+    if let Some(limit) = parse_limit_exceeded(input, s.depth) {
+        s.limited = Some(limit);
+        return (ExecCode::Nomatch, Vec::new(), s);
+    }
+
     // Config match for "set" and "delete".
     let mut cx = Match::default();
     if s.set || s.delete {
@@ -484,7 +558,9 @@ pub fn parse(
     if path.name == "delete" {
         s.delete = true;
     }
-    if path.name == "show" {
+    // Clear and request commands are redirected to the protocol as well as
+    // show, unless they are handled by the config manager.
+    if path.name == "show" || path.name == "clear" || path.name == "request" {
         s.show = true;
     }
     s.paths.push(path);
@@ -515,7 +591,7 @@ pub fn parse(
         }
     }
 
-    let remain = input.to_string().split_off(mx.pos);
+    let remain = &input[mx.pos..];
 
     if remain.is_empty() {
         if !ymatch_complete(s.ymatch) {
@@ -532,6 +608,35 @@ pub fn parse(
         if next.name == "delete" {
             s.delete = true;
         }
-        parse(&remain, next, config.clone(), s)
+        s.depth += 1;
+        parse(remain, next, config.clone(), s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_limit() {
+        assert_eq!(
+            parse_limit_exceeded("set routing bgp global as 65001", 0),
+            None
+        );
+        assert_eq!(
+            parse_limit_exceeded(&"a ".repeat(PARSE_INPUT_MAX), 0),
+            Some(ParseLimit::Input)
+        );
+        assert_eq!(
+            parse_limit_exceeded("65001", PARSE_DEPTH_MAX),
+            Some(ParseLimit::Depth)
+        );
+        let token = "a".repeat(PARSE_TOKEN_MAX + 1);
+        assert_eq!(
+            parse_limit_exceeded(&format!("{} b", token), 1),
+            Some(ParseLimit::Token)
+        );
+        assert_eq!(parse_limit_exceeded(&format!("b {}", token), 1), None);
+        assert_eq!(ParseLimit::Depth.message(), "Command exceeds 256 tokens");
     }
 }
@@ -0,0 +1,99 @@
+use super::api::{ConfigOp, ConfigRequest, Message};
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tokio::sync::mpsc::{Sender, UnboundedSender};
+use tokio::sync::oneshot;
+
+// Startup configuration is loaded without the subscribers which have not
+// answered the handshake in time, so that a stuck module does not block the
+// others.
+pub const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Startup of the config manager. Changes are published only after every
+// subscriber has answered the handshake, which proves that its receiver is
+// polled by the event loop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Readiness {
+    // Waiting for the subscribers to answer the handshake.
+    Starting,
+    // Startup configuration is being loaded and published.
+    Loading,
+    // Startup configuration is published.
+    Ready,
+}
+
+impl Readiness {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            Readiness::Starting => "starting",
+            Readiness::Loading => "loading",
+            Readiness::Ready => "ready",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReadyState {
+    pub state: Readiness,
+    // Subscribers which have not answered the handshake.
+    pub waiting: BTreeSet<String>,
+}
+
+impl ReadyState {
+    pub fn new() -> Self {
+        Self {
+            state: Readiness::Starting,
+            waiting: BTreeSet::new(),
+        }
+    }
+
+    // Returns true when the startup configuration is to be loaded, i.e. the
+    // last subscriber has answered.
+    pub fn ready(&mut self, name: &str) -> bool {
+        self.waiting.remove(name);
+        self.state == Readiness::Starting && self.waiting.is_empty()
+    }
+
+    // Returns true when the startup configuration is to be loaded without
+    // the subscribers still waiting.
+    pub fn timeout(&self) -> bool {
+        self.state == Readiness::Starting
+    }
+}
+
+// Send the handshake to the subscriber. The answer is reported to the config
+// manager by a task. A subscriber which drops the request without answering
+// has polled the receiver as well.
+pub fn ready_request(name: &str, tx: &UnboundedSender<ConfigRequest>, cm: Sender<Message>) {
+    let (resp, rx) = oneshot::channel();
+    let req = ConfigRequest {
+        paths: Vec::new(),
+        op: ConfigOp::Ready,
+        resp: Some(resp),
+    };
+    let _ = tx.send(req);
+    let name = name.to_string();
+    tokio::spawn(async move {
+        let _ = rx.await;
+        let _ = cm.send(Message::Ready(name)).await;
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ready_all_subscribers() {
+        let mut ready = ReadyState::new();
+        ready.waiting.insert("bgp".to_string());
+        ready.waiting.insert("rib".to_string());
+        assert!(!ready.ready("rib"));
+        assert!(!ready.ready("unknown"));
+        assert!(ready.ready("bgp"));
+
+        ready.state = Readiness::Ready;
+        assert!(!ready.ready("bgp"));
+        assert!(!ready.timeout());
+    }
+}
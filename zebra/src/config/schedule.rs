@@ -0,0 +1,81 @@
+use super::api::Message;
+use super::Config;
+use chrono::{DateTime, Local, NaiveTime};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+
+// Scheduled commit is rolled back unless it is confirmed within the time
+// after the activation.
+pub const COMMIT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(600);
+
+// Candidate stored by "commit at" until the time comes.
+pub struct ScheduledCommit {
+    pub at: DateTime<Local>,
+    pub config: Arc<Config>,
+    pub timer: JoinHandle<()>,
+}
+
+// Running config before the activation of the scheduled commit, restored
+// when the timer expires without "commit confirm".
+pub struct PendingConfirm {
+    pub rollback: Arc<Config>,
+    pub timer: JoinHandle<()>,
+}
+
+impl Drop for ScheduledCommit {
+    fn drop(&mut self) {
+        self.timer.abort();
+    }
+}
+
+impl Drop for PendingConfirm {
+    fn drop(&mut self) {
+        self.timer.abort();
+    }
+}
+
+// Time of "commit at", either RFC 3339 or HH:MM of the local time. HH:MM
+// which has passed today is taken as tomorrow.
+pub fn commit_time(at: &str, now: DateTime<Local>) -> Option<DateTime<Local>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(at) {
+        let time = time.with_timezone(&Local);
+        return if time > now { Some(time) } else { None };
+    }
+    let time = NaiveTime::parse_from_str(at, "%H:%M").ok()?;
+    let mut date = now.date_naive();
+    loop {
+        let time = date.and_time(time).and_local_timezone(Local).earliest()?;
+        if time > now {
+            return Some(time);
+        }
+        date = date.succ_opt()?;
+    }
+}
+
+// Send the message to the config manager after the delay.
+pub fn schedule_timer(tx: Sender<Message>, delay: Duration, msg: Message) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let _ = tx.send(msg).await;
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn commit_time_parse() {
+        let now = Local::now();
+        let at = (now + chrono::Duration::hours(1)).to_rfc3339();
+        assert!(commit_time(&at, now).is_some());
+        let at = (now - chrono::Duration::hours(1)).to_rfc3339();
+        assert_eq!(commit_time(&at, now), None);
+
+        let time = commit_time("03:30", now).unwrap();
+        assert!(time > now && time - now <= chrono::Duration::hours(25));
+        assert_eq!(commit_time("25:00", now), None);
+    }
+}
@@ -1,29 +1,41 @@
 use std::collections::HashMap;
 
+use socket2::{Domain, Socket, Type};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
 use tokio::sync::mpsc::{Sender, UnboundedSender};
-use tokio::sync::{mpsc, oneshot};
-use tokio_stream::wrappers::ReceiverStream;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
 use tonic::transport::Server;
 use tonic::Response;
 
 use super::api::{
     CompletionRequest, CompletionResponse, DisplayRequest, ExecuteRequest, ExecuteResponse, Message,
 };
+use super::health::health_check_response::ServingStatus;
+use super::health::health_server::{Health, HealthServer};
+use super::health::{HealthCheckRequest, HealthCheckResponse};
+use super::ready::Readiness;
 use super::vtysh::exec_server::{Exec, ExecServer};
 use super::vtysh::show_server::{Show, ShowServer};
 use super::vtysh::{
-    CommandPath, ExecCode, ExecReply, ExecRequest, ExecType, ShowReply, ShowRequest, YangMatch,
+    CommandPath, CompletionEntry, CompletionResult, ExecCode, ExecReply, ExecRequest, ExecType,
+    ShowReply, ShowRequest, YangMatch,
 };
 
 #[derive(Debug)]
 struct ExecService {
     pub tx: mpsc::Sender<Message>,
+    // Port of the server which is told to the shell for the show commands.
+    pub port: u16,
+    pub observer: bool,
 }
 
 impl ExecService {
     async fn execute_request(&self, mode: &str, input: &str) -> ExecuteResponse {
         let (tx, rx) = oneshot::channel();
-        let req = ExecuteRequest::new(mode, input, tx);
+        let mut req = ExecuteRequest::new(mode, input, tx);
+        req.observer = self.observer;
         self.tx.send(Message::Execute(req)).await.unwrap();
         rx.await.unwrap()
     }
@@ -38,26 +50,60 @@ impl ExecService {
     fn reply(&self, code: ExecCode, lines: String) -> Result<Response<ExecReply>, tonic::Status> {
         let reply = ExecReply {
             code: code as i32,
-            candidates: Vec::new(),
             lines,
-            port: 2650,
-            paths: Vec::new(),
+            port: self.port as u32,
+            ..Default::default()
         };
         Ok(Response::new(reply))
     }
 
-    fn reply_exec(
+    // Tab separated lines are kept for the shell along with the typed entries.
+    fn reply_comp(
         &self,
-        code: ExecCode,
         lines: String,
-        paths: Vec<CommandPath>,
+        resp: &CompletionResponse,
     ) -> Result<Response<ExecReply>, tonic::Status> {
+        let reply = ExecReply {
+            code: ExecCode::Success as i32,
+            lines,
+            port: self.port as u32,
+            completions: comp_entries(resp),
+            ..Default::default()
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn reply_batch(
+        &self,
+        request: &ExecRequest,
+    ) -> Result<Response<ExecReply>, tonic::Status> {
+        let mut batch = Vec::new();
+        for line in request.commands.iter() {
+            let resp = self.completion_request(&request.mode, line).await;
+            batch.push(CompletionResult {
+                line: line.clone(),
+                code: resp.code as i32,
+                completions: comp_entries(&resp),
+            });
+        }
+        let reply = ExecReply {
+            code: ExecCode::Success as i32,
+            port: self.port as u32,
+            batch,
+            ..Default::default()
+        };
+        Ok(Response::new(reply))
+    }
+
+    fn reply_exec(&self, resp: &ExecuteResponse) -> Result<Response<ExecReply>, tonic::Status> {
+        let (code, lines, paths) = exec_commands(resp);
         let reply = ExecReply {
             code: code as i32,
-            candidates: Vec::new(),
             lines,
-            port: 2650,
+            port: self.port as u32,
             paths,
+            error: resp.error.clone(),
+            ..Default::default()
         };
         Ok(Response::new(reply))
     }
@@ -73,23 +119,23 @@ impl Exec for ExecService {
         match request.r#type {
             x if x == ExecType::Exec as i32 => {
                 let resp = self.execute_request(&request.mode, &request.line).await;
-                let (code, output, paths) = exec_commands(&resp);
-                self.reply_exec(code, output, paths)
+                self.reply_exec(&resp)
             }
             x if x == ExecType::CompleteFirstCommands as i32 => {
                 let resp = self.completion_request(&request.mode, &request.line).await;
-                self.reply(ExecCode::Success, first_commands(&resp))
+                self.reply_comp(first_commands(&resp), &resp)
             }
             x if x == ExecType::Complete as i32 => {
                 let resp = self.completion_request(&request.mode, &request.line).await;
-                self.reply(ExecCode::Success, comp_commands(&resp))
+                self.reply_comp(comp_commands(&resp), &resp)
             }
             x if x == ExecType::CompleteTrailingSpace as i32 => {
                 let mut input = request.line.clone();
                 input.push(' ');
                 let resp = self.completion_request(&request.mode, &input).await;
-                self.reply(ExecCode::Success, comp_commands(&resp))
+                self.reply_comp(comp_commands(&resp), &resp)
             }
+            x if x == ExecType::CompleteBatch as i32 => self.reply_batch(request).await,
             _ => self.reply(ExecCode::Success, String::from("Success\n")),
         }
     }
@@ -123,6 +169,19 @@ fn comp_commands(resp: &CompletionResponse) -> String {
     line
 }
 
+fn comp_entries(resp: &CompletionResponse) -> Vec<CompletionEntry> {
+    resp.comps
+        .iter()
+        .map(|comp| CompletionEntry {
+            name: comp.name.clone(),
+            help: comp.help.clone(),
+            kind: comp.kind as i32,
+            ymatch: comp.ymatch as i32,
+            r#type: comp.ytype.clone(),
+        })
+        .collect()
+}
+
 fn exec_commands(resp: &ExecuteResponse) -> (ExecCode, String, Vec<CommandPath>) {
     if resp.code == ExecCode::Nomatch {
         return (
@@ -153,8 +212,9 @@ struct ShowService {
     show_clients: HashMap<String, UnboundedSender<DisplayRequest>>,
 }
 
+// System resources are reported by BGP which holds most of the state.
 fn is_bgp(paths: &[CommandPath]) -> bool {
-    paths.iter().any(|x| x.name == "bgp")
+    paths.iter().any(|x| x.name == "bgp" || x.name == "system")
 }
 
 #[tonic::async_trait]
@@ -194,16 +254,51 @@ impl Show for ShowService {
     }
 }
 
+// The process is serving once the startup configuration is loaded. Unknown
+// service name is reported as SERVICE_UNKNOWN.
+#[derive(Debug)]
+struct HealthService {
+    readiness: watch::Receiver<Readiness>,
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        request: tonic::Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, tonic::Status> {
+        let status = match request.get_ref().service.as_str() {
+            "" | "zebra" => match *self.readiness.borrow() {
+                Readiness::Ready => ServingStatus::Serving,
+                _ => ServingStatus::NotServing,
+            },
+            _ => ServingStatus::ServiceUnknown,
+        };
+        Ok(Response::new(HealthCheckResponse {
+            status: status as i32,
+        }))
+    }
+}
+
 pub struct Cli {
     pub tx: mpsc::Sender<Message>,
     pub show_clients: HashMap<String, UnboundedSender<DisplayRequest>>,
+    // Management VRF the gRPC server is bound to.
+    pub vrf: Option<String>,
+    pub readiness: watch::Receiver<Readiness>,
+    // Port of the read-only server for the observers, which run the show
+    // commands only and see the config with the secrets redacted.
+    pub observer_port: Option<u16>,
 }
 
 impl Cli {
-    pub fn new(config_tx: Sender<Message>) -> Self {
+    pub fn new(config_tx: Sender<Message>, readiness: watch::Receiver<Readiness>) -> Self {
         Self {
             tx: config_tx,
             show_clients: HashMap::new(),
+            vrf: None,
+            readiness,
+            observer_port: None,
         }
     }
 
@@ -212,8 +307,21 @@ impl Cli {
     }
 }
 
+const GRPC_PORT: u16 = 2650;
+
 pub fn serve(cli: Cli) {
-    let exec_service = ExecService { tx: cli.tx.clone() };
+    server_spawn(&cli, GRPC_PORT, false);
+    if let Some(port) = cli.observer_port {
+        server_spawn(&cli, port, true);
+    }
+}
+
+fn server_spawn(cli: &Cli, port: u16, observer: bool) {
+    let exec_service = ExecService {
+        tx: cli.tx.clone(),
+        port,
+        observer,
+    };
     let exec_server = ExecServer::new(exec_service);
 
     let mut show_service = ShowService {
@@ -225,14 +333,87 @@ pub fn serve(cli: Cli) {
             .insert(client.to_string(), tx.clone());
     }
     let show_server = ShowServer::new(show_service);
+    let health_server = HealthServer::new(HealthService {
+        readiness: cli.readiness.clone(),
+    });
 
-    let addr = "0.0.0.0:2650".parse().unwrap();
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
 
+    let vrf = match cli.vrf.as_ref() {
+        Some(vrf) => vrf,
+        None => {
+            tokio::spawn(async move {
+                Server::builder()
+                    .add_service(exec_server)
+                    .add_service(show_server)
+                    .add_service(health_server)
+                    .serve(addr)
+                    .await
+            });
+            return;
+        }
+    };
+    let listener = match listen_vrf(addr, vrf) {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("gRPC listen in VRF {} error: {}", vrf, err);
+            return;
+        }
+    };
     tokio::spawn(async move {
         Server::builder()
             .add_service(exec_server)
             .add_service(show_server)
-            .serve(addr)
+            .add_service(health_server)
+            .serve_with_incoming(TcpListenerStream::new(listener))
             .await
     });
 }
+
+// Socket of the management plane is bound to the VRF device so that it is
+// reachable only from the management network.
+fn listen_vrf(addr: SocketAddr, vrf: &str) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    socket.bind_device(Some(vrf.as_bytes()))?;
+    socket.bind(&addr.into())?;
+    socket.listen(128)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::comps::{chint, comps_add_config};
+    use super::super::vtysh::CompletionKind;
+    use super::super::Config;
+    use super::*;
+    use libyang::YangType;
+    use std::sync::Arc;
+
+    #[test]
+    fn comp_entries_typed() {
+        let mut neighbor = Config::new("neighbor".to_string());
+        neighbor
+            .keys
+            .push(Arc::new(Config::new("10.0.0.1".to_string())));
+        let mut comps = vec![chint("A.B.C.D", &YangType::Ipv4Addr)];
+        comps_add_config(&mut comps, YangMatch::Key, &Some(Arc::new(neighbor)));
+        comps_add_config(&mut comps, YangMatch::LeafMatched, &None);
+        let resp = CompletionResponse {
+            code: ExecCode::Success,
+            comps,
+        };
+
+        // Value hint carries its YANG type, configured key and <cr> their kinds.
+        let entries = comp_entries(&resp);
+        let entry = |name: &str| entries.iter().find(|entry| entry.name == name).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entry("A.B.C.D").kind, CompletionKind::ValueHint as i32);
+        assert_eq!(entry("A.B.C.D").r#type, "ipv4-address");
+        assert_eq!(entry("10.0.0.1").kind, CompletionKind::ConfigValue as i32);
+        assert_eq!(entry("10.0.0.1").ymatch, YangMatch::Leaf as i32);
+        assert_eq!(entry("<cr>").kind, CompletionKind::EndOfCommand as i32);
+        assert!(entry("<cr>").r#type.is_empty());
+    }
+}
@@ -11,16 +11,22 @@ pub enum Token {
     SemiColon,
 }
 
-pub fn tokenizer(input: String) -> Vec<Token> {
-    let mut tokens = Vec::<Token>::new();
+// Tokens with the line number where they appear, starting from 1.
+pub fn tokenizer_lines(input: String) -> Vec<(Token, usize)> {
+    let mut tokens = Vec::<(Token, usize)>::new();
     let mut chars = input.chars().peekable();
+    let mut line = 1;
 
     while let Some(ch) = chars.next() {
         match ch {
+            '\n' => {
+                line += 1;
+            }
             ch if ch.is_whitespace() => {
                 continue;
             }
-            'a'..='z' | '0'..='9' => {
+            // Path of include statement starts with '.' or '/'.
+            'a'..='z' | '0'..='9' | '.' | '/' => {
                 let s: String = iter::once(ch)
                     .chain(from_fn(|| {
                         chars.by_ref().next_if(|c| {
@@ -32,7 +38,7 @@ pub fn tokenizer(input: String) -> Vec<Token> {
                         })
                     }))
                     .collect();
-                tokens.push(Token::String(s));
+                tokens.push((Token::String(s), line));
             }
             '"' => {
                 let _s: String = chars
@@ -42,15 +48,16 @@ pub fn tokenizer(input: String) -> Vec<Token> {
             }
             '#' => {
                 let s: String = chars.by_ref().take_while(|c| c != &'\n').collect();
-                tokens.push(Token::Comment(s));
+                tokens.push((Token::Comment(s), line));
+                line += 1;
             }
             '{' => {
-                tokens.push(Token::LeftBrace);
+                tokens.push((Token::LeftBrace, line));
             }
-            '}' => tokens.push(Token::RightBrace),
-            '[' => tokens.push(Token::LeftBracket),
-            ']' => tokens.push(Token::RightBracket),
-            ';' => tokens.push(Token::SemiColon),
+            '}' => tokens.push((Token::RightBrace, line)),
+            '[' => tokens.push((Token::LeftBracket, line)),
+            ']' => tokens.push((Token::RightBracket, line)),
+            ';' => tokens.push((Token::SemiColon, line)),
             _ => {}
         }
     }
@@ -77,12 +84,21 @@ routing {
     }
 }
 "#;
-        let tokens = tokenizer(config.to_string());
+        let tokens: Vec<Token> = tokenizer_lines(config.to_string())
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
         assert_eq!(tokens.len(), 22);
         assert_eq!(
             tokens.get(10).unwrap(),
             &Token::String("neighbors".to_string())
         );
         assert_eq!(tokens.get(11).unwrap(), &Token::LeftBrace);
+
+        let tokens = tokenizer_lines(config.to_string());
+        assert_eq!(
+            tokens.get(13).unwrap(),
+            &(Token::String("10.0.0.1".to_string()), 8)
+        );
     }
 }
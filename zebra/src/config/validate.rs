@@ -0,0 +1,226 @@
+use super::Config;
+use std::sync::Arc;
+
+// Leaves which must be set in each entry of the list. One of the leaves is
+// enough when there are alternatives, e.g. peer-as may be inherited from the
+// peer group.
+const MANDATORY: &[(&[&str], &[&str])] = &[
+    (
+        &["routing", "bgp", "neighbors", "neighbor"],
+        &["peer-as", "peer-group"],
+    ),
+    (&["routing", "bgp", "global", "health-check"], &["address"]),
+    (
+        &["routing", "bgp", "global", "listen", "range"],
+        &["peer-group"],
+    ),
+];
+
+// Leaves which value refers to a key of the list, like leafref.
+const REFERENCES: &[(&[&str], &[&str])] = &[
+    (
+        &[
+            "routing",
+            "bgp",
+            "neighbors",
+            "neighbor",
+            "prefix-list",
+            "in",
+        ],
+        &["prefix-list"],
+    ),
+    (
+        &[
+            "routing",
+            "bgp",
+            "neighbors",
+            "neighbor",
+            "prefix-list",
+            "out",
+        ],
+        &["prefix-list"],
+    ),
+    (
+        &["routing", "bgp", "global", "network", "health-check"],
+        &["routing", "bgp", "global", "health-check"],
+    ),
+    (
+        &[
+            "routing",
+            "bgp",
+            "peer-groups",
+            "peer-group",
+            "prefix-list",
+            "in",
+        ],
+        &["prefix-list"],
+    ),
+    (
+        &[
+            "routing",
+            "bgp",
+            "peer-groups",
+            "peer-group",
+            "prefix-list",
+            "out",
+        ],
+        &["prefix-list"],
+    ),
+    (
+        &["routing", "bgp", "neighbors", "neighbor", "peer-group"],
+        &["routing", "bgp", "peer-groups", "peer-group"],
+    ),
+    (
+        &["routing", "bgp", "global", "listen", "range", "peer-group"],
+        &["routing", "bgp", "peer-groups", "peer-group"],
+    ),
+];
+
+// Nodes at the path with the path words including list keys, e.g.
+// "routing bgp neighbors neighbor 10.0.0.1". Entries of the lists on the
+// way are expanded.
+fn config_nodes(
+    config: &Arc<Config>,
+    path: &[&str],
+    words: Vec<String>,
+    nodes: &mut Vec<(Vec<String>, Arc<Config>)>,
+) {
+    if !config.keys.is_empty() {
+        for key in config.keys.iter() {
+            let mut words = words.clone();
+            words.push(key.name.clone());
+            config_nodes(key, path, words, nodes);
+        }
+        return;
+    }
+    match path.split_first() {
+        Some((name, rest)) => {
+            if let Some(child) = config.lookup(&name.to_string()) {
+                let mut words = words;
+                words.push(name.to_string());
+                config_nodes(&child, rest, words, nodes);
+            }
+        }
+        None => nodes.push((words, config.clone())),
+    }
+}
+
+fn config_keys(config: &Arc<Config>, path: &[&str]) -> Vec<String> {
+    let mut nodes = Vec::new();
+    config_nodes(config, path, Vec::new(), &mut nodes);
+    nodes
+        .into_iter()
+        .filter_map(|(words, _)| words.last().cloned())
+        .collect()
+}
+
+pub fn mandatory_errors(config: &Arc<Config>) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (path, leaves) in MANDATORY.iter() {
+        let mut nodes = Vec::new();
+        config_nodes(config, path, Vec::new(), &mut nodes);
+        for (words, node) in nodes.iter() {
+            if leaves
+                .iter()
+                .all(|leaf| node.lookup(&leaf.to_string()).is_none())
+            {
+                errors.push(format!(
+                    "{}: missing mandatory {}",
+                    words.join(" "),
+                    leaves.join(" or ")
+                ));
+            }
+        }
+    }
+    errors
+}
+
+pub fn reference_errors(config: &Arc<Config>) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (path, target) in REFERENCES.iter() {
+        let mut nodes = Vec::new();
+        config_nodes(config, path, Vec::new(), &mut nodes);
+        if nodes.is_empty() {
+            continue;
+        }
+        let keys = config_keys(config, target);
+        for (words, node) in nodes.iter() {
+            let value = &node.value;
+            if !keys.contains(value) {
+                errors.push(format!(
+                    "{} {}: {} {} does not exist",
+                    words.join(" "),
+                    value,
+                    target.join(" "),
+                    value
+                ));
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(name: &str, configs: Vec<Config>, keys: Vec<Config>) -> Config {
+        Config {
+            name: name.to_string(),
+            configs: configs.into_iter().map(Arc::new).collect(),
+            keys: keys.into_iter().map(Arc::new).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn leaf(name: &str, value: &str) -> Config {
+        Config {
+            name: name.to_string(),
+            value: value.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn tree(configs: Vec<Config>) -> Arc<Config> {
+        Arc::new(node("", configs, vec![]))
+    }
+
+    #[test]
+    fn validate_neighbor() {
+        let peers = vec![
+            node("10.0.0.1", vec![leaf("peer-as", "65001")], vec![]),
+            node(
+                "10.0.0.2",
+                vec![node("prefix-list", vec![leaf("in", "ebgp-in")], vec![])],
+                vec![],
+            ),
+        ];
+        let neighbor = node("neighbor", vec![], peers);
+        let routing = node(
+            "routing",
+            vec![node(
+                "bgp",
+                vec![node("neighbors", vec![neighbor], vec![])],
+                vec![],
+            )],
+            vec![],
+        );
+        let root = tree(vec![routing.clone()]);
+
+        assert_eq!(
+            mandatory_errors(&root),
+            vec![
+                "routing bgp neighbors neighbor 10.0.0.2: missing mandatory peer-as or peer-group"
+            ]
+        );
+        assert_eq!(
+            reference_errors(&root),
+            vec![
+                "routing bgp neighbors neighbor 10.0.0.2 prefix-list in ebgp-in: prefix-list ebgp-in does not exist"
+            ]
+        );
+
+        let plist = node("prefix-list", vec![], vec![node("ebgp-in", vec![], vec![])]);
+        assert!(reference_errors(&tree(vec![plist, routing])).is_empty());
+    }
+}
@@ -0,0 +1,711 @@
+// Route exchange channel of split-process deployment. The core process runs
+// config and RIB, and the BGP process connects to it over a Unix domain
+// socket. The core forwards config changes and show requests of BGP, and RIB
+// messages are exchanged in both directions. When the BGP process connects,
+// the running config is replayed and the BGP process applies the difference
+// from the config it has, so that a reconnect does not reset the sessions.
+
+use crate::config::{
+    CommandPath, ConfigChannel, ConfigOp, ConfigRequest, DisplayRequest, Message, ShowChannel,
+};
+use crate::rib::api::{RedistRoute, RedistSource, RibRoute, RibRx, RibRxChannel, RibTx};
+use ipnet::IpNet;
+use prost::Message as _;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+
+mod proto {
+    tonic::include_proto!("ipc");
+}
+use proto::{frame::Msg, ConfigType, Frame, Hello, Path, RibType, Show, ShowReply};
+
+pub const IPC_SOCKET: &str = "/var/run/zebra-bgp.sock";
+
+const IPC_FRAME_MAX: usize = 16 * 1024 * 1024;
+const IPC_RECONNECT_MIN: Duration = Duration::from_secs(1);
+const IPC_RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+fn path_encode(paths: &[CommandPath]) -> Vec<Path> {
+    paths
+        .iter()
+        .map(|x| Path {
+            name: x.name.clone(),
+            key: x.key.clone(),
+            ymatch: x.ymatch,
+        })
+        .collect()
+}
+
+fn path_decode(paths: Vec<Path>) -> Vec<CommandPath> {
+    paths
+        .into_iter()
+        .map(|x| CommandPath {
+            name: x.name,
+            key: x.key,
+            ymatch: x.ymatch,
+        })
+        .collect()
+}
+
+fn route_encode(route: &RibRoute) -> proto::Route {
+    proto::Route {
+        prefix: route.prefix.to_string(),
+        distance: route.distance,
+        nexthops: route.nexthops.iter().map(|x| x.to_string()).collect(),
+    }
+}
+
+fn route_decode(route: proto::Route) -> Option<RibRoute> {
+    let nexthops: Result<Vec<IpAddr>, _> = route.nexthops.iter().map(|x| x.parse()).collect();
+    Some(RibRoute {
+        prefix: route.prefix.parse().ok()?,
+        distance: route.distance,
+        nexthops: nexthops.ok()?,
+    })
+}
+
+fn rib_tx_encode(msg: &RibTx) -> proto::Rib {
+    let (typ, route) = match msg {
+        RibTx::RouteAdd(route) => (RibType::RouteAdd, Some(route_encode(route))),
+        RibTx::RouteDel(route) => (RibType::RouteDel, Some(route_encode(route))),
+        RibTx::NexthopResgister() => (RibType::NexthopRegister, None),
+        RibTx::NexthopUnresgister() => (RibType::NexthopUnregister, None),
+    };
+    proto::Rib {
+        r#type: typ as i32,
+        redist: None,
+        route,
+    }
+}
+
+fn rib_tx_decode(rib: proto::Rib) -> Option<RibTx> {
+    match RibType::from_i32(rib.r#type)? {
+        RibType::RouteAdd => Some(RibTx::RouteAdd(route_decode(rib.route?)?)),
+        RibType::RouteDel => Some(RibTx::RouteDel(route_decode(rib.route?)?)),
+        RibType::NexthopRegister => Some(RibTx::NexthopResgister()),
+        RibType::NexthopUnregister => Some(RibTx::NexthopUnresgister()),
+        _ => None,
+    }
+}
+
+fn redist_encode(route: &RedistRoute) -> proto::Redist {
+    let source = match route.source {
+        RedistSource::Connected => proto::RedistSource::Connected,
+        RedistSource::Static => proto::RedistSource::Static,
+    };
+    proto::Redist {
+        source: source as i32,
+        prefix: route.prefix.to_string(),
+        metric: route.metric,
+        nexthop: route.nexthop.map(|x| x.to_string()).unwrap_or_default(),
+    }
+}
+
+fn redist_decode(redist: proto::Redist) -> Option<RedistRoute> {
+    let source = match proto::RedistSource::from_i32(redist.source)? {
+        proto::RedistSource::Connected => RedistSource::Connected,
+        proto::RedistSource::Static => RedistSource::Static,
+    };
+    Some(RedistRoute {
+        source,
+        prefix: redist.prefix.parse().ok()?,
+        metric: redist.metric,
+        nexthop: redist.nexthop.parse().ok(),
+    })
+}
+
+fn rib_rx_encode(msg: &RibRx) -> proto::Rib {
+    let (typ, redist) = match msg {
+        RibRx::RedistAdd(route) => (RibType::RedistAdd, Some(redist_encode(route))),
+        RibRx::RedistDel(route) => (RibType::RedistDel, Some(redist_encode(route))),
+        RibRx::RouteAck(_) => (RibType::RouteAck, None),
+        RibRx::Link() => (RibType::Link, None),
+        RibRx::Nexthop() => (RibType::Nexthop, None),
+    };
+    // Ack carries the prefix only.
+    let route = match msg {
+        RibRx::RouteAck(prefix) => Some(proto::Route {
+            prefix: prefix.to_string(),
+            ..Default::default()
+        }),
+        _ => None,
+    };
+    proto::Rib {
+        r#type: typ as i32,
+        redist,
+        route,
+    }
+}
+
+fn rib_rx_decode(rib: proto::Rib) -> Option<RibRx> {
+    match RibType::from_i32(rib.r#type)? {
+        RibType::RedistAdd => Some(RibRx::RedistAdd(redist_decode(rib.redist?)?)),
+        RibType::RedistDel => Some(RibRx::RedistDel(redist_decode(rib.redist?)?)),
+        RibType::RouteAck => Some(RibRx::RouteAck(rib.route?.prefix.parse().ok()?)),
+        RibType::Link => Some(RibRx::Link()),
+        RibType::Nexthop => Some(RibRx::Nexthop()),
+        _ => None,
+    }
+}
+
+fn rib_frame(rib: proto::Rib) -> Frame {
+    Frame {
+        msg: Some(Msg::Rib(rib)),
+    }
+}
+
+fn config_frame(typ: ConfigType, paths: &[CommandPath]) -> Frame {
+    Frame {
+        msg: Some(Msg::Config(proto::Config {
+            r#type: typ as i32,
+            paths: path_encode(paths),
+        })),
+    }
+}
+
+async fn frame_read(reader: &mut OwnedReadHalf) -> anyhow::Result<Frame> {
+    let len = reader.read_u32().await? as usize;
+    if len > IPC_FRAME_MAX {
+        anyhow::bail!("frame length {} exceeds the limit", len);
+    }
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf).await?;
+    Ok(Frame::decode(buf.as_slice())?)
+}
+
+// Frames are read by a task, since a read is not cancel safe in select. The
+// channel is closed when the connection is lost.
+fn frame_reader(mut reader: OwnedReadHalf) -> UnboundedReceiver<Frame> {
+    let (tx, rx) = mpsc::unbounded_channel::<Frame>();
+    tokio::spawn(async move {
+        loop {
+            match frame_read(&mut reader).await {
+                Ok(frame) => {
+                    if tx.send(frame).is_err() {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    println!("ipc: read error: {}", err);
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
+
+// Frames are written by a task so that the event loops never wait on the
+// socket.
+fn frame_writer(mut writer: OwnedWriteHalf) -> UnboundedSender<Frame> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Frame>();
+    tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            let buf = frame.encode_to_vec();
+            if writer.write_u32(buf.len() as u32).await.is_err()
+                || writer.write_all(&buf).await.is_err()
+            {
+                return;
+            }
+        }
+    });
+    tx
+}
+
+// Channels which stand for the BGP module in the core process. They are
+// subscribed to the config manager, the CLI and the RIB in place of the
+// channels of Bgp.
+pub struct IpcProxy {
+    pub cm: ConfigChannel,
+    pub show: ShowChannel,
+    pub redist: RibRxChannel,
+}
+
+impl IpcProxy {
+    pub fn new() -> Self {
+        Self {
+            cm: ConfigChannel::new(),
+            show: ShowChannel::new(),
+            redist: RibRxChannel::new(),
+        }
+    }
+}
+
+struct Core {
+    proxy: IpcProxy,
+    config: Sender<Message>,
+    rib: Sender<RibTx>,
+    // Routes offered for redistribution, replayed when the BGP process
+    // connects.
+    redist: BTreeMap<(RedistSource, IpNet), RedistRoute>,
+}
+
+impl Core {
+    fn redist_update(&mut self, msg: &RibRx) {
+        match msg {
+            RibRx::RedistAdd(route) => {
+                self.redist
+                    .insert((route.source, route.prefix), route.clone());
+            }
+            RibRx::RedistDel(route) => {
+                self.redist.remove(&(route.source, route.prefix));
+            }
+            _ => {}
+        }
+    }
+
+    // Changes queued while the BGP process was away are covered by the
+    // replay, so config is forwarded only after the resync marker.
+    async fn session(&mut self, stream: UnixStream) -> anyhow::Result<()> {
+        let (reader, writer) = stream.into_split();
+        let mut reader = frame_reader(reader);
+        let writer = frame_writer(writer);
+        match reader.recv().await.and_then(|frame| frame.msg) {
+            Some(Msg::Hello(hello)) => println!("ipc: {} connected", hello.module),
+            _ => anyhow::bail!("hello is expected"),
+        }
+        self.config
+            .send(Message::Resync(String::from("bgp")))
+            .await?;
+        for route in self.redist.values() {
+            let msg = RibRx::RedistAdd(route.clone());
+            writer.send(rib_frame(rib_rx_encode(&msg)))?;
+        }
+
+        let mut synced = false;
+        let mut pending: HashMap<u64, Sender<String>> = HashMap::new();
+        let mut id = 0u64;
+        loop {
+            tokio::select! {
+                Some(msg) = self.proxy.cm.rx.recv() => {
+                    let typ = match msg.op {
+                        ConfigOp::Resync => {
+                            synced = true;
+                            ConfigType::Resync
+                        }
+                        ConfigOp::Ready => {
+                            msg.ready_reply();
+                            continue;
+                        }
+                        _ if !synced => continue,
+                        ConfigOp::Set => ConfigType::Set,
+                        ConfigOp::Delete => ConfigType::Delete,
+                        ConfigOp::CommitEnd => ConfigType::CommitEnd,
+                        ConfigOp::Completion => continue,
+                    };
+                    writer.send(config_frame(typ, &msg.paths))?;
+                }
+                Some(msg) = self.proxy.show.rx.recv() => {
+                    id += 1;
+                    pending.insert(id, msg.resp);
+                    writer.send(Frame {
+                        msg: Some(Msg::Show(Show {
+                            id,
+                            paths: path_encode(&msg.paths),
+                        })),
+                    })?;
+                }
+                Some(msg) = self.proxy.redist.rx.recv() => {
+                    self.redist_update(&msg);
+                    writer.send(rib_frame(rib_rx_encode(&msg)))?;
+                }
+                frame = reader.recv() => {
+                    let frame = match frame {
+                        Some(frame) => frame,
+                        None => anyhow::bail!("connection closed"),
+                    };
+                    match frame.msg {
+                        Some(Msg::ShowReply(reply)) => {
+                            if let Some(resp) = pending.get(&reply.id) {
+                                if !reply.str.is_empty() {
+                                    let _ = resp.send(reply.str).await;
+                                }
+                            }
+                            if reply.end {
+                                pending.remove(&reply.id);
+                            }
+                        }
+                        Some(Msg::Rib(rib)) => {
+                            if let Some(msg) = rib_tx_decode(rib) {
+                                let _ = self.rib.send(msg).await;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    // Show requests are answered locally while the BGP process is away. The
+    // startup handshake is answered by the proxy, as the running config is
+    // replayed when the BGP process connects.
+    async fn wait(&mut self, listener: &UnixListener) -> std::io::Result<UnixStream> {
+        loop {
+            tokio::select! {
+                result = listener.accept() => {
+                    return result.map(|(stream, _)| stream);
+                }
+                Some(msg) = self.proxy.show.rx.recv() => {
+                    let _ = msg.resp.send(String::from("% BGP process is not connected\n")).await;
+                }
+                Some(msg) = self.proxy.cm.rx.recv() => {
+                    if msg.op == ConfigOp::Ready {
+                        msg.ready_reply();
+                    }
+                }
+                Some(msg) = self.proxy.redist.rx.recv() => {
+                    self.redist_update(&msg);
+                }
+            }
+        }
+    }
+}
+
+// Core process side. One BGP process is served at a time.
+pub fn serve(path: PathBuf, proxy: IpcProxy, config: Sender<Message>, rib: Sender<RibTx>) {
+    let _ = std::fs::remove_file(&path);
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("ipc: {} bind error: {}", path.display(), err);
+            return;
+        }
+    };
+    let mut core = Core {
+        proxy,
+        config,
+        rib,
+        redist: BTreeMap::new(),
+    };
+    tokio::spawn(async move {
+        loop {
+            let stream = match core.wait(&listener).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    println!("ipc: accept error: {}", err);
+                    continue;
+                }
+            };
+            if let Err(err) = core.session(stream).await {
+                println!("ipc: BGP process disconnected: {}", err);
+            }
+        }
+    });
+}
+
+fn statement_key(paths: &[CommandPath]) -> String {
+    paths
+        .iter()
+        .map(|x| {
+            if x.key.is_empty() {
+                x.name.clone()
+            } else {
+                format!("{} {}", x.name, x.key)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Config applied to the BGP module, by the order of the application.
+#[derive(Default)]
+struct Applied {
+    seq: u64,
+    statements: BTreeMap<String, (u64, Vec<CommandPath>)>,
+}
+
+impl Applied {
+    fn set(&mut self, paths: &[CommandPath]) {
+        self.seq += 1;
+        self.statements
+            .insert(statement_key(paths), (self.seq, paths.to_vec()));
+    }
+
+    fn delete(&mut self, paths: &[CommandPath]) {
+        self.statements.remove(&statement_key(paths));
+    }
+
+    // Changes to reach the replayed config. Statements which are gone are
+    // deleted in the reverse order of the application, then the new ones are
+    // set in the order of the replay.
+    fn resync(&mut self, replay: Vec<Vec<CommandPath>>) -> Vec<(ConfigOp, Vec<CommandPath>)> {
+        let keys: Vec<String> = replay.iter().map(|paths| statement_key(paths)).collect();
+        let mut deleted: Vec<(u64, Vec<CommandPath>)> = self
+            .statements
+            .iter()
+            .filter(|(key, _)| !keys.contains(*key))
+            .map(|(_, statement)| statement.clone())
+            .collect();
+        deleted.sort_by_key(|(seq, _)| std::cmp::Reverse(*seq));
+
+        let mut ops = Vec::new();
+        for (_, paths) in deleted.into_iter() {
+            self.delete(&paths);
+            ops.push((ConfigOp::Delete, paths));
+        }
+        for (key, paths) in keys.into_iter().zip(replay.into_iter()) {
+            if !self.statements.contains_key(&key) {
+                self.set(&paths);
+                ops.push((ConfigOp::Set, paths));
+            }
+        }
+        ops
+    }
+}
+
+struct Client {
+    cm: UnboundedSender<ConfigRequest>,
+    show: UnboundedSender<DisplayRequest>,
+    redist: Sender<RibRx>,
+    rib: Receiver<RibTx>,
+    applied: Applied,
+    replay: Option<Vec<Vec<CommandPath>>>,
+}
+
+impl Client {
+    fn config(&mut self, config: proto::Config) {
+        let typ = ConfigType::from_i32(config.r#type);
+        let paths = path_decode(config.paths);
+        if let Some(replay) = self.replay.as_mut() {
+            match typ {
+                Some(ConfigType::Set) => replay.push(paths),
+                Some(ConfigType::CommitEnd) => {
+                    let replay = self.replay.take().unwrap_or_default();
+                    for (op, paths) in self.applied.resync(replay).into_iter() {
+                        let _ = self.cm.send(ConfigRequest::new(paths, op));
+                    }
+                    let _ = self
+                        .cm
+                        .send(ConfigRequest::new(Vec::new(), ConfigOp::CommitEnd));
+                }
+                _ => {}
+            }
+            return;
+        }
+        let op = match typ {
+            Some(ConfigType::Resync) => {
+                self.replay = Some(Vec::new());
+                return;
+            }
+            Some(ConfigType::Set) => {
+                self.applied.set(&paths);
+                ConfigOp::Set
+            }
+            Some(ConfigType::Delete) => {
+                self.applied.delete(&paths);
+                ConfigOp::Delete
+            }
+            Some(ConfigType::CommitEnd) => ConfigOp::CommitEnd,
+            None => return,
+        };
+        let _ = self.cm.send(ConfigRequest::new(paths, op));
+    }
+
+    fn show(&self, show: Show, writer: UnboundedSender<Frame>) {
+        let (tx, mut rx) = mpsc::channel::<String>(4);
+        let req = DisplayRequest {
+            paths: path_decode(show.paths),
+            resp: tx,
+        };
+        if self.show.send(req).is_err() {
+            return;
+        }
+        let id = show.id;
+        tokio::spawn(async move {
+            while let Some(output) = rx.recv().await {
+                let reply = ShowReply {
+                    id,
+                    str: output,
+                    end: false,
+                };
+                let _ = writer.send(Frame {
+                    msg: Some(Msg::ShowReply(reply)),
+                });
+            }
+            let reply = ShowReply {
+                id,
+                str: String::new(),
+                end: true,
+            };
+            let _ = writer.send(Frame {
+                msg: Some(Msg::ShowReply(reply)),
+            });
+        });
+    }
+
+    async fn session(&mut self, stream: UnixStream) -> anyhow::Result<()> {
+        let (reader, writer) = stream.into_split();
+        let mut reader = frame_reader(reader);
+        let writer = frame_writer(writer);
+        writer.send(Frame {
+            msg: Some(Msg::Hello(Hello {
+                module: String::from("bgp"),
+            })),
+        })?;
+        self.replay = None;
+        loop {
+            tokio::select! {
+                Some(msg) = self.rib.recv() => {
+                    writer.send(rib_frame(rib_tx_encode(&msg)))?;
+                }
+                frame = reader.recv() => {
+                    let frame = match frame {
+                        Some(frame) => frame,
+                        None => anyhow::bail!("connection closed"),
+                    };
+                    match frame.msg {
+                        Some(Msg::Config(config)) => self.config(config),
+                        Some(Msg::Show(show)) => self.show(show, writer.clone()),
+                        Some(Msg::Rib(rib)) => {
+                            if let Some(msg) = rib_rx_decode(rib) {
+                                let _ = self.redist.send(msg).await;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+// BGP process side. The connection is retried with exponential backoff, and
+// the BGP module keeps running with the config it has while disconnected.
+pub fn connect(
+    path: PathBuf,
+    cm: UnboundedSender<ConfigRequest>,
+    show: UnboundedSender<DisplayRequest>,
+    redist: Sender<RibRx>,
+    rib: Receiver<RibTx>,
+) {
+    let mut client = Client {
+        cm,
+        show,
+        redist,
+        rib,
+        applied: Applied::default(),
+        replay: None,
+    };
+    tokio::spawn(async move {
+        let mut interval = IPC_RECONNECT_MIN;
+        loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => {
+                    println!("ipc: connected to {}", path.display());
+                    interval = IPC_RECONNECT_MIN;
+                    if let Err(err) = client.session(stream).await {
+                        println!("ipc: core process disconnected: {}", err);
+                    }
+                }
+                Err(err) => {
+                    println!("ipc: {} connect error: {}", path.display(), err);
+                }
+            }
+            tokio::time::sleep(interval).await;
+            interval = std::cmp::min(interval * 2, IPC_RECONNECT_MAX);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn statement(line: &str) -> Vec<CommandPath> {
+        line.split_whitespace()
+            .map(|name| CommandPath {
+                name: name.to_string(),
+                key: String::new(),
+                ymatch: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resync_difference() {
+        let mut applied = Applied::default();
+        applied.set(&statement("routing bgp global as 65000"));
+        applied.set(&statement("routing bgp neighbors neighbor 10.0.0.1"));
+        applied.set(&statement(
+            "routing bgp neighbors neighbor 10.0.0.1 peer-as 65001",
+        ));
+
+        let ops = applied.resync(vec![
+            statement("routing bgp global as 65000"),
+            statement("routing bgp neighbors neighbor 10.0.0.2"),
+        ]);
+        let ops: Vec<(ConfigOp, String)> = ops
+            .iter()
+            .map(|(op, paths)| (op.clone(), statement_key(paths)))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                (
+                    ConfigOp::Delete,
+                    "routing bgp neighbors neighbor 10.0.0.1 peer-as 65001".to_string()
+                ),
+                (
+                    ConfigOp::Delete,
+                    "routing bgp neighbors neighbor 10.0.0.1".to_string()
+                ),
+                (
+                    ConfigOp::Set,
+                    "routing bgp neighbors neighbor 10.0.0.2".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn redist_frame() {
+        let route = RedistRoute {
+            source: RedistSource::Static,
+            prefix: "10.1.0.0/16".parse().unwrap(),
+            metric: 10,
+            nexthop: Some("192.0.2.1".parse().unwrap()),
+        };
+        let rib = rib_rx_encode(&RibRx::RedistAdd(route.clone()));
+        match rib_rx_decode(rib) {
+            Some(RibRx::RedistAdd(decoded)) => assert_eq!(decoded, route),
+            _ => panic!("redistributed route is not decoded"),
+        }
+
+        // Connected route has no next hop.
+        let route = RedistRoute {
+            source: RedistSource::Connected,
+            prefix: "2001:db8::/64".parse().unwrap(),
+            metric: 0,
+            nexthop: None,
+        };
+        match rib_rx_decode(rib_rx_encode(&RibRx::RedistDel(route.clone()))) {
+            Some(RibRx::RedistDel(decoded)) => assert_eq!(decoded, route),
+            _ => panic!("redistributed route is not decoded"),
+        }
+
+        match rib_rx_decode(rib_rx_encode(&RibRx::RouteAck(route.prefix))) {
+            Some(RibRx::RouteAck(prefix)) => assert_eq!(prefix, route.prefix),
+            _ => panic!("route ack is not decoded"),
+        }
+    }
+
+    #[test]
+    fn route_frame() {
+        let route = RibRoute {
+            prefix: "10.1.0.0/16".parse().unwrap(),
+            distance: 20,
+            nexthops: vec!["192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap()],
+        };
+        match rib_tx_decode(rib_tx_encode(&RibTx::RouteAdd(route.clone()))) {
+            Some(RibTx::RouteAdd(decoded)) => assert_eq!(decoded, route),
+            _ => panic!("route is not decoded"),
+        }
+    }
+}
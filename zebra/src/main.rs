@@ -4,17 +4,92 @@ mod config;
 use config::{Cli, ConfigManager};
 use std::path::PathBuf;
 mod bgp;
-use bgp::Bgp;
+use bgp::{Bgp, BGP_SUBTREES, BGP_YANG_MODULES};
 mod rib;
-use rib::Rib;
+use rib::fib::{dataplane_new, Dataplane, DataplaneType};
+use rib::{Rib, RibTxChannel};
+mod ipc;
+use ipc::{IpcProxy, IPC_SOCKET};
 mod policy;
-use clap::Parser;
+mod trace;
+use clap::{Parser, ValueEnum};
+use tokio::signal::unix::{signal, SignalKind};
+
+// Modules run by the process. The core and BGP processes of split-process
+// deployment are connected by the Unix domain socket.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Role {
+    All,
+    Core,
+    Bgp,
+}
+
+// Protocols which can be disabled. CLI commands and configuration of the
+// protocol are available only when it is enabled.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Protocol {
+    Bgp,
+}
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Arg {
     #[arg(short, long, help = "YANG load path", default_value = "")]
     yang_path: String,
+
+    #[arg(long, help = "OpenTelemetry OTLP endpoint to export spans")]
+    otlp_endpoint: Option<String>,
+
+    #[arg(long, help = "Validate the configuration file and exit")]
+    check_config: bool,
+
+    #[arg(long, help = "Management VRF to bind the gRPC server")]
+    mgmt_vrf: Option<String>,
+
+    #[arg(long, help = "Port of the read-only gRPC server with secrets redacted")]
+    observer_port: Option<u16>,
+
+    #[arg(long, value_enum, default_value = "all", help = "Modules to run")]
+    role: Role,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Protocol not to run, its YANG modules are not loaded"
+    )]
+    disable: Vec<Protocol>,
+
+    #[arg(
+        long,
+        help = "Socket between the core and BGP processes",
+        default_value = IPC_SOCKET
+    )]
+    ipc_socket: PathBuf,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "kernel",
+        help = "Dataplane to program the routes"
+    )]
+    dataplane: DataplaneType,
+
+    #[arg(long, help = "gRPC endpoint of the external dataplane")]
+    dataplane_endpoint: Option<String>,
+}
+
+// Load YANG modules and the configuration file then report errors, for
+// validating a config before deployment.
+fn check_config(config: &ConfigManager) -> ! {
+    let errors = config.check_config();
+    for err in errors.iter() {
+        eprintln!("{}", err);
+    }
+    if errors.is_empty() {
+        println!("{}: configuration is valid", config.config_path.display());
+        std::process::exit(0);
+    }
+    std::process::exit(1);
 }
 
 fn system_path(arg: &Arg) -> PathBuf {
@@ -42,32 +117,135 @@ fn system_path(arg: &Arg) -> PathBuf {
     }
 }
 
+fn protocol_enabled(arg: &Arg, protocol: Protocol) -> bool {
+    !arg.disable.contains(&protocol)
+}
+
+// RIB and BGP labels have their own connection to the dataplane.
+fn dataplane(arg: &Arg) -> anyhow::Result<Box<dyn Dataplane>> {
+    dataplane_new(arg.dataplane, arg.dataplane_endpoint.as_deref())
+}
+
+// SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let mut term = match signal(SignalKind::terminate()) {
+        Ok(term) => term,
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = term.recv() => {}
+    }
+}
+
+// BGP process of split-process deployment. Config and show requests come
+// from the core process over the socket.
+async fn bgp_process(arg: &Arg) -> anyhow::Result<()> {
+    trace::init(arg.otlp_endpoint.as_deref())?;
+
+    let rib = RibTxChannel::new();
+    let mut bgp = Bgp::new(rib.tx.clone());
+    bgp.labels.dataplane = Some(dataplane(arg)?);
+    let bgp_tx = bgp.tx.clone();
+    ipc::connect(
+        arg.ipc_socket.clone(),
+        bgp.cm.tx.clone(),
+        bgp.show.tx.clone(),
+        bgp.redist.tx.clone(),
+        rib.rx,
+    );
+    bgp::serve(bgp);
+
+    println!("zebra: BGP process started");
+
+    shutdown_signal().await;
+    bgp::shutdown(&bgp_tx).await;
+
+    trace::shutdown();
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let arg = Arg::parse();
+    let bgp_enabled = protocol_enabled(&arg, Protocol::Bgp);
+    if arg.role == Role::Bgp {
+        if !bgp_enabled {
+            anyhow::bail!("BGP process is started with BGP disabled");
+        }
+        return bgp_process(&arg).await;
+    }
 
-    let mut rib = Rib::new()?;
+    let mut config = ConfigManager::new(system_path(&arg))?;
+    if bgp_enabled {
+        for (tree, module) in BGP_YANG_MODULES.iter() {
+            config.yang_add(tree, module);
+        }
+    }
+    if arg.check_config {
+        if let Err(err) = config.init() {
+            eprintln!("YANG load failed: {}", err);
+            std::process::exit(1);
+        }
+        check_config(&config);
+    }
+    config.init()?;
 
-    let bgp = Bgp::new(rib.api.tx.clone());
-    rib.subscribe(bgp.redist.tx.clone());
+    trace::init(arg.otlp_endpoint.as_deref())?;
 
-    let mut config = ConfigManager::new(system_path(&arg))?;
-    config.subscribe("rib", rib.cm.tx.clone());
-    config.subscribe("bgp", bgp.cm.tx.clone());
+    let mut rib = Rib::new(dataplane(&arg)?)?;
+    config.subscribe("rib", rib.cm.tx.clone(), &["/routing/static"]);
 
-    let mut cli = Cli::new(config.tx.clone());
+    let mut cli = Cli::new(config.tx.clone(), config.readiness());
+    cli.vrf = arg.mgmt_vrf.clone();
+    cli.observer_port = arg.observer_port;
     cli.subscribe("rib", rib.show.tx.clone());
-    cli.subscribe("bgp", bgp.show.tx.clone());
 
-    config::serve(cli);
+    let bgp_tx = if !bgp_enabled {
+        None
+    } else if arg.role == Role::Core {
+        let proxy = IpcProxy::new();
+        rib.subscribe(proxy.redist.tx.clone());
+        config.subscribe("bgp", proxy.cm.tx.clone(), BGP_SUBTREES);
+        cli.subscribe("bgp", proxy.show.tx.clone());
+        ipc::serve(
+            arg.ipc_socket.clone(),
+            proxy,
+            config.tx.clone(),
+            rib.api.tx.clone(),
+        );
+        None
+    } else {
+        let mut bgp = Bgp::new(rib.api.tx.clone());
+        bgp.labels.dataplane = Some(dataplane(&arg)?);
+        let bgp_tx = bgp.tx.clone();
+        rib.subscribe(bgp.redist.tx.clone());
+        config.subscribe("bgp", bgp.cm.tx.clone(), BGP_SUBTREES);
+        cli.subscribe("bgp", bgp.show.tx.clone());
+        bgp::serve(bgp);
+        Some(bgp_tx)
+    };
 
-    bgp::serve(bgp);
+    config::serve(cli);
 
     rib::serve(rib);
 
     println!("zebra: started");
 
-    config::event_loop(config).await;
+    tokio::select! {
+        _ = config::event_loop(config) => {}
+        _ = shutdown_signal() => {
+            if let Some(bgp_tx) = bgp_tx.as_ref() {
+                bgp::shutdown(bgp_tx).await;
+            }
+        }
+    }
+
+    trace::shutdown();
 
     Ok(())
 }
@@ -0,0 +1,126 @@
+use super::{Policy, PolicyAction};
+use crate::config::{Args, ConfigOp};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Debug, Default)]
+pub struct AsPathList {
+    pub entry: BTreeMap<u32, AsPathListEntry>,
+}
+
+#[derive(Debug, Default)]
+pub struct AsPathListEntry {
+    pub action: Option<PolicyAction>,
+    pub regexp: Option<Regex>,
+}
+
+// AS path regular expression. '_' matches delimiter of AS number, that is
+// space, AS_SET and confederation brackets, beginning or end of the path.
+pub fn aspath_regex(s: &str) -> Option<Regex> {
+    let s = s.replace('_', "(^|[ ,{}()\\[\\]]|$)");
+    Regex::new(&s).ok()
+}
+
+impl AsPathList {
+    // Entries are evaluated in sequence order, no match is implicit deny.
+    pub fn apply(&self, aspath: &str) -> PolicyAction {
+        for entry in self.entry.values() {
+            if let (Some(action), Some(regexp)) = (entry.action, &entry.regexp) {
+                if regexp.is_match(aspath) {
+                    return action;
+                }
+            }
+        }
+        PolicyAction::Deny
+    }
+}
+
+// as-path-list hoge
+// as-path-list hoge seq 5
+// as-path-list hoge seq 5 action permit
+// as-path-list hoge seq 5 regexp _3356_
+
+fn aslist_entry<'a>(
+    policy: &'a mut Policy,
+    args: &mut Args,
+    op: &ConfigOp,
+) -> Option<&'a mut AsPathListEntry> {
+    let name = args.string()?;
+    let seq = args.u32()?;
+    if *op == ConfigOp::Set {
+        Some(
+            policy
+                .aslist
+                .entry(name)
+                .or_default()
+                .entry
+                .entry(seq)
+                .or_default(),
+        )
+    } else {
+        policy.aslist.get_mut(&name)?.entry.get_mut(&seq)
+    }
+}
+
+pub fn config_aslist(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    if op == ConfigOp::Set {
+        policy.aslist.entry(name).or_default();
+    } else {
+        policy.aslist.remove(&name);
+    }
+    Some(())
+}
+
+pub fn config_aslist_seq(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let seq = args.u32()?;
+    if op == ConfigOp::Set {
+        policy
+            .aslist
+            .entry(name)
+            .or_default()
+            .entry
+            .entry(seq)
+            .or_default();
+    } else if let Some(aslist) = policy.aslist.get_mut(&name) {
+        aslist.entry.remove(&seq);
+    }
+    Some(())
+}
+
+pub fn config_aslist_action(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = aslist_entry(policy, &mut args, &op)?;
+    entry.action = if op == ConfigOp::Set {
+        Some(PolicyAction::from_str(&args.string()?).ok()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_aslist_regexp(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = aslist_entry(policy, &mut args, &op)?;
+    entry.regexp = if op == ConfigOp::Set {
+        aspath_regex(&args.string()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn aspath_regex_delimiter() {
+        let regex = aspath_regex("_3356_").unwrap();
+        assert!(regex.is_match("3356"));
+        assert!(regex.is_match("65000 3356 174"));
+        assert!(regex.is_match("65000 {174,3356}"));
+        assert!(!regex.is_match("65000 33560"));
+        assert!(!regex.is_match("13356 174"));
+    }
+}
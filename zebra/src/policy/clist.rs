@@ -1,9 +1,13 @@
 use std::collections::HashMap;
 
+use super::{
+    AsPathList, ExtCommunityList, LargeCommunityList, PolicyAction, PolicyIr, PrefixList, RouteMap,
+};
 use crate::{
-    bgp::packet::CommunityAttr,
+    bgp::packet::{CommunityAttr, ExtendedComAttr, LargeComAttr},
     config::{Args, ConfigOp},
 };
+use ipnet::Ipv4Net;
 
 #[derive(Debug)]
 pub struct CommunityList {
@@ -23,9 +27,67 @@ pub enum CommunityMember {
     Community(CommunityAttr),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Policy {
     pub clist: HashMap<String, CommunityList>,
+    pub plist: HashMap<String, PrefixList>,
+    // Prefix lists generated from the IRR data by request.
+    pub generated: HashMap<String, PrefixList>,
+    pub aslist: HashMap<String, AsPathList>,
+    pub lclist: HashMap<String, LargeCommunityList>,
+    pub eclist: HashMap<String, ExtCommunityList>,
+    pub rmap: HashMap<String, RouteMap>,
+    // Compiled at the commit, the config is interpreted until the first one.
+    pub ir: Option<PolicyIr>,
+}
+
+impl Policy {
+    pub fn compile(&mut self) {
+        self.ir = Some(PolicyIr::compile(self));
+    }
+
+    // Prefix-list which is not defined yet denies everything, so that a typo
+    // in the reference does not leak routes. Configured prefix-list takes
+    // precedence over the generated one of the same name.
+    pub fn prefix_list_apply(&self, name: &str, prefix: &Ipv4Net) -> PolicyAction {
+        if let Some(ir) = &self.ir {
+            return ir.prefix_list_apply(name, prefix);
+        }
+        match self.plist.get(name).or(self.generated.get(name)) {
+            Some(plist) => plist.apply(prefix),
+            None => PolicyAction::Deny,
+        }
+    }
+
+    // Large community list which is not defined yet permits everything.
+    pub fn large_community_list_apply(
+        &self,
+        name: &str,
+        attr: Option<&LargeComAttr>,
+    ) -> PolicyAction {
+        if let Some(ir) = &self.ir {
+            return ir.large_community_list_apply(name, attr);
+        }
+        match self.lclist.get(name) {
+            Some(lclist) => lclist.apply(attr),
+            None => PolicyAction::Permit,
+        }
+    }
+
+    // Extended community list which is not defined yet permits everything.
+    pub fn ext_community_list_apply(
+        &self,
+        name: &str,
+        attr: Option<&ExtendedComAttr>,
+    ) -> PolicyAction {
+        if let Some(ir) = &self.ir {
+            return ir.ext_community_list_apply(name, attr);
+        }
+        match self.eclist.get(name) {
+            Some(eclist) => eclist.apply(attr),
+            None => PolicyAction::Permit,
+        }
+    }
 }
 
 // community-list hoge
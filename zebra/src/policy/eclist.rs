@@ -0,0 +1,150 @@
+use super::{Policy, PolicyAction};
+use crate::bgp::packet::{Attribute, Attrs, ExtendedCom, ExtendedComAttr};
+use crate::config::{Args, ConfigOp};
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+#[derive(Debug, Default)]
+pub struct ExtCommunityList {
+    pub entry: BTreeMap<u32, ExtCommunityListEntry>,
+}
+
+#[derive(Debug, Default)]
+pub struct ExtCommunityListEntry {
+    pub action: Option<PolicyAction>,
+    pub member: BTreeSet<ExtendedCom>,
+}
+
+impl ExtCommunityListEntry {
+    // All of the members must be present in the route.
+    pub fn matches(&self, attr: Option<&ExtendedComAttr>) -> bool {
+        if self.member.is_empty() {
+            return false;
+        }
+        match attr {
+            Some(attr) => self.member.iter().all(|x| attr.contains(x)),
+            None => false,
+        }
+    }
+}
+
+impl ExtCommunityList {
+    // Entries are evaluated in sequence order, no match is implicit deny.
+    pub fn apply(&self, attr: Option<&ExtendedComAttr>) -> PolicyAction {
+        for entry in self.entry.values() {
+            if let Some(action) = entry.action {
+                if entry.matches(attr) {
+                    return action;
+                }
+            }
+        }
+        PolicyAction::Deny
+    }
+}
+
+pub fn ext_community(attrs: &Attrs) -> Option<&ExtendedComAttr> {
+    attrs.iter().find_map(|attr| match attr {
+        Attribute::ExtendedCom(ecom) => Some(ecom),
+        _ => None,
+    })
+}
+
+// extended-community-list hoge
+// extended-community-list hoge seq 5
+// extended-community-list hoge seq 5 action permit
+// extended-community-list hoge seq 5 member rt 65000:100
+
+fn eclist_entry<'a>(
+    policy: &'a mut Policy,
+    args: &mut Args,
+    op: &ConfigOp,
+) -> Option<&'a mut ExtCommunityListEntry> {
+    let name = args.string()?;
+    let seq = args.u32()?;
+    if *op == ConfigOp::Set {
+        Some(
+            policy
+                .eclist
+                .entry(name)
+                .or_default()
+                .entry
+                .entry(seq)
+                .or_default(),
+        )
+    } else {
+        policy.eclist.get_mut(&name)?.entry.get_mut(&seq)
+    }
+}
+
+pub fn config_eclist(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    if op == ConfigOp::Set {
+        policy.eclist.entry(name).or_default();
+    } else {
+        policy.eclist.remove(&name);
+    }
+    Some(())
+}
+
+pub fn config_eclist_seq(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let seq = args.u32()?;
+    if op == ConfigOp::Set {
+        policy
+            .eclist
+            .entry(name)
+            .or_default()
+            .entry
+            .entry(seq)
+            .or_default();
+    } else if let Some(eclist) = policy.eclist.get_mut(&name) {
+        eclist.entry.remove(&seq);
+    }
+    Some(())
+}
+
+pub fn config_eclist_action(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = eclist_entry(policy, &mut args, &op)?;
+    entry.action = if op == ConfigOp::Set {
+        Some(PolicyAction::from_str(&args.string()?).ok()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_eclist_member(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = eclist_entry(policy, &mut args, &op)?;
+    let member = *ExtendedComAttr::from_str(&args.string()?).ok()?.0.first()?;
+    if op == ConfigOp::Set {
+        entry.member.insert(member);
+    } else {
+        entry.member.remove(&member);
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ext_community_list_apply() {
+        let rt = ExtendedComAttr::from_str("rt 65000:100").unwrap();
+        let mut eclist = ExtCommunityList::default();
+        eclist.entry.insert(
+            5,
+            ExtCommunityListEntry {
+                action: Some(PolicyAction::Permit),
+                member: rt.0.iter().cloned().collect(),
+            },
+        );
+        let attrs = vec![Attribute::ExtendedCom(
+            ExtendedComAttr::from_str("soo 65000:1 rt 65000:100").unwrap(),
+        )];
+        assert_eq!(eclist.apply(ext_community(&attrs)), PolicyAction::Permit);
+        let attr = ExtendedComAttr::from_str("rt 65000:200").unwrap();
+        assert_eq!(eclist.apply(Some(&attr)), PolicyAction::Deny);
+        assert_eq!(eclist.apply(None), PolicyAction::Deny);
+    }
+}
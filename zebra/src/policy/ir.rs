@@ -0,0 +1,377 @@
+use super::{Policy, PolicyAction, PrefixList, RouteMap};
+use crate::bgp::packet::{
+    Attribute, Attrs, CommunityAttr, ExtendedCom, ExtendedComAttr, LargeCom, LargeComAttr,
+    LocalPrefAttr, MedAttr, OriginAttr,
+};
+use crate::bgp::rpki::RpkiState;
+use ipnet::{IpNet, Ipv4Net};
+use std::collections::{BTreeSet, HashMap};
+use std::mem::discriminant;
+
+// Policy compiled at the commit. Names are resolved to indexes, entries
+// without action are dropped and prefix matchers are reduced to the masked
+// address and the prefix length range, so that per route evaluation does not
+// walk the config structures.
+#[derive(Debug, Default)]
+pub struct PolicyIr {
+    plist: Vec<PrefixListIr>,
+    plist_index: HashMap<String, usize>,
+    rmap: HashMap<String, RouteMapIr>,
+    lclist: HashMap<String, MemberListIr<LargeCom>>,
+    eclist: HashMap<String, MemberListIr<ExtendedCom>>,
+}
+
+#[derive(Debug)]
+struct PrefixMatcher {
+    action: PolicyAction,
+    addr: u32,
+    mask: u32,
+    min: u8,
+    max: u8,
+}
+
+#[derive(Debug, Default)]
+struct PrefixListIr(Vec<PrefixMatcher>);
+
+impl PrefixListIr {
+    fn compile(plist: &PrefixList) -> Self {
+        let mut matchers = Vec::new();
+        for entry in plist.entry.values() {
+            let (action, base) = match (entry.action, entry.prefix) {
+                (Some(action), Some(base)) => (action, base),
+                _ => continue,
+            };
+            let len = base.prefix_len();
+            let (min, max) = match (entry.ge, entry.le) {
+                (None, None) => (len, len),
+                (ge, le) => (ge.unwrap_or(len).max(len), le.unwrap_or(32)),
+            };
+            matchers.push(PrefixMatcher {
+                action,
+                addr: u32::from(base.network()),
+                mask: u32::from(base.netmask()),
+                min,
+                max,
+            });
+        }
+        Self(matchers)
+    }
+
+    fn apply(&self, prefix: &Ipv4Net) -> PolicyAction {
+        let addr = u32::from(prefix.network());
+        let len = prefix.prefix_len();
+        for m in self.0.iter() {
+            if addr & m.mask == m.addr && len >= m.min && len <= m.max {
+                return m.action;
+            }
+        }
+        PolicyAction::Deny
+    }
+}
+
+#[derive(Debug)]
+enum MatchIr {
+    Any,
+    PrefixList(usize),
+}
+
+#[derive(Debug)]
+struct RouteMapEntryIr {
+    action: PolicyAction,
+    matcher: MatchIr,
+    rpki: Option<RpkiState>,
+    // Attributes replaced by the entry.
+    replace: Vec<Attribute>,
+    community: Vec<u32>,
+}
+
+#[derive(Debug, Default)]
+struct RouteMapIr(Vec<RouteMapEntryIr>);
+
+#[derive(Debug)]
+struct MemberListIr<T>(Vec<(PolicyAction, Vec<T>)>);
+
+impl<T: Clone + PartialEq> MemberListIr<T> {
+    fn compile<'a, I>(entries: I) -> Self
+    where
+        I: Iterator<Item = (Option<PolicyAction>, &'a BTreeSet<T>)>,
+        T: 'a,
+    {
+        let entries = entries
+            .filter(|(_, member)| !member.is_empty())
+            .filter_map(|(action, member)| Some((action?, member.iter().cloned().collect())))
+            .collect();
+        Self(entries)
+    }
+
+    // All of the members must be present in the route.
+    fn apply(&self, values: Option<&[T]>) -> PolicyAction {
+        let values = match values {
+            Some(values) => values,
+            None => return PolicyAction::Deny,
+        };
+        for (action, member) in self.0.iter() {
+            if member.iter().all(|x| values.contains(x)) {
+                return *action;
+            }
+        }
+        PolicyAction::Deny
+    }
+}
+
+impl PolicyIr {
+    pub fn compile(policy: &Policy) -> Self {
+        let mut ir = PolicyIr::default();
+        // Configured prefix-list takes precedence over the generated one.
+        for (name, plist) in policy.generated.iter().chain(policy.plist.iter()) {
+            ir.plist_index.insert(name.clone(), ir.plist.len());
+            ir.plist.push(PrefixListIr::compile(plist));
+        }
+        for (name, rmap) in policy.rmap.iter() {
+            let rmap = ir.route_map_compile(rmap);
+            ir.rmap.insert(name.clone(), rmap);
+        }
+        for (name, lclist) in policy.lclist.iter() {
+            let entries = lclist.entry.values().map(|x| (x.action, &x.member));
+            ir.lclist
+                .insert(name.clone(), MemberListIr::compile(entries));
+        }
+        for (name, eclist) in policy.eclist.iter() {
+            let entries = eclist.entry.values().map(|x| (x.action, &x.member));
+            ir.eclist
+                .insert(name.clone(), MemberListIr::compile(entries));
+        }
+        ir
+    }
+
+    fn route_map_compile(&self, rmap: &RouteMap) -> RouteMapIr {
+        let mut entries = Vec::new();
+        for entry in rmap.entry.values() {
+            let action = match entry.action {
+                Some(action) => action,
+                None => continue,
+            };
+            // Undefined prefix list matches nothing.
+            let matcher = match &entry.match_prefix_list {
+                Some(name) => match self.plist_index.get(name) {
+                    Some(index) => MatchIr::PrefixList(*index),
+                    None => continue,
+                },
+                None => MatchIr::Any,
+            };
+            let mut replace = Vec::new();
+            if let Some(med) = entry.set_med {
+                replace.push(Attribute::Med(MedAttr { med }));
+            }
+            if let Some(local_pref) = entry.set_local_pref {
+                replace.push(Attribute::LocalPref(LocalPrefAttr { local_pref }));
+            }
+            if let Some(origin) = entry.set_origin {
+                replace.push(Attribute::Origin(OriginAttr { origin }));
+            }
+            entries.push(RouteMapEntryIr {
+                action,
+                matcher,
+                rpki: entry.match_rpki,
+                replace,
+                community: entry.set_community.iter().cloned().collect(),
+            });
+        }
+        RouteMapIr(entries)
+    }
+
+    pub fn prefix_list_apply(&self, name: &str, prefix: &Ipv4Net) -> PolicyAction {
+        match self.plist_index.get(name) {
+            Some(index) => self.plist[*index].apply(prefix),
+            None => PolicyAction::Deny,
+        }
+    }
+
+    pub fn large_community_list_apply(
+        &self,
+        name: &str,
+        attr: Option<&LargeComAttr>,
+    ) -> PolicyAction {
+        match self.lclist.get(name) {
+            Some(lclist) => lclist.apply(attr.map(|x| &x.0[..])),
+            None => PolicyAction::Permit,
+        }
+    }
+
+    pub fn ext_community_list_apply(
+        &self,
+        name: &str,
+        attr: Option<&ExtendedComAttr>,
+    ) -> PolicyAction {
+        match self.eclist.get(name) {
+            Some(eclist) => eclist.apply(attr.map(|x| &x.0[..])),
+            None => PolicyAction::Permit,
+        }
+    }
+
+    // Prefix lists are IPv4 only, so IPv6 prefix does not match them.
+    pub fn route_map_apply(&self, name: &str, prefix: &IpNet, attrs: &mut Attrs) -> PolicyAction {
+        self.route_map_eval(name, prefix, None, attrs)
+    }
+
+    pub fn route_map_import(
+        &self,
+        name: &str,
+        prefix: &IpNet,
+        rpki: RpkiState,
+        attrs: &mut Attrs,
+    ) -> PolicyAction {
+        self.route_map_eval(name, prefix, Some(rpki), attrs)
+    }
+
+    fn route_map_eval(
+        &self,
+        name: &str,
+        prefix: &IpNet,
+        rpki: Option<RpkiState>,
+        attrs: &mut Attrs,
+    ) -> PolicyAction {
+        let rmap = match self.rmap.get(name) {
+            Some(rmap) => rmap,
+            None => return PolicyAction::Permit,
+        };
+        for entry in rmap.0.iter() {
+            if entry.rpki.is_some() && entry.rpki != rpki {
+                continue;
+            }
+            let matched = match (&entry.matcher, prefix) {
+                (MatchIr::Any, _) => true,
+                (MatchIr::PrefixList(index), IpNet::V4(prefix)) => {
+                    self.plist[*index].apply(prefix) == PolicyAction::Permit
+                }
+                (MatchIr::PrefixList(_), IpNet::V6(_)) => false,
+            };
+            if !matched {
+                continue;
+            }
+            if entry.action == PolicyAction::Permit {
+                route_map_set(entry, attrs);
+            }
+            return entry.action;
+        }
+        PolicyAction::Deny
+    }
+}
+
+fn route_map_set(entry: &RouteMapEntryIr, attrs: &mut Attrs) {
+    for attr in entry.replace.iter() {
+        attrs.retain(|x| discriminant(x) != discriminant(attr));
+        attrs.push(attr.clone());
+    }
+    if entry.community.is_empty() {
+        return;
+    }
+    let mut community: BTreeSet<u32> = entry.community.iter().cloned().collect();
+    for attr in attrs.iter() {
+        if let Attribute::Community(attr) = attr {
+            community.extend(attr.0.iter());
+        }
+    }
+    attrs.retain(|attr| !matches!(attr, Attribute::Community(_)));
+    attrs.push(Attribute::Community(CommunityAttr(
+        community.into_iter().collect(),
+    )));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::policy::{PrefixListEntry, RouteMapEntry};
+
+    #[test]
+    fn policy_ir_same_as_config() {
+        let mut policy = Policy::default();
+        let mut plist = PrefixList::default();
+        plist.entry.insert(
+            5,
+            PrefixListEntry {
+                action: Some(PolicyAction::Deny),
+                prefix: Some("10.0.0.0/8".parse().unwrap()),
+                ..Default::default()
+            },
+        );
+        plist.entry.insert(
+            10,
+            PrefixListEntry {
+                action: Some(PolicyAction::Permit),
+                prefix: Some("10.0.0.0/8".parse().unwrap()),
+                ge: Some(16),
+                le: Some(24),
+            },
+        );
+        policy.plist.insert("plist".to_string(), plist);
+        let mut rmap = RouteMap::default();
+        rmap.entry.insert(
+            10,
+            RouteMapEntry {
+                action: Some(PolicyAction::Permit),
+                match_prefix_list: Some("plist".to_string()),
+                set_med: Some(100),
+                set_community: BTreeSet::from([1, 2]),
+                ..Default::default()
+            },
+        );
+        rmap.entry.insert(
+            20,
+            RouteMapEntry {
+                action: Some(PolicyAction::Deny),
+                ..Default::default()
+            },
+        );
+        policy.rmap.insert("rmap".to_string(), rmap);
+        let ir = PolicyIr::compile(&policy);
+
+        let prefixes = ["10.0.0.0/8", "10.1.0.0/16", "10.1.1.0/25", "192.0.2.0/24"];
+        for prefix in prefixes {
+            let v4: Ipv4Net = prefix.parse().unwrap();
+            assert_eq!(
+                ir.prefix_list_apply("plist", &v4),
+                policy.prefix_list_apply("plist", &v4)
+            );
+            let attrs = vec![
+                Attribute::Med(MedAttr { med: 5 }),
+                Attribute::Community(CommunityAttr(vec![3])),
+            ];
+            let mut expect = attrs.clone();
+            let mut result = attrs;
+            let prefix = IpNet::V4(v4);
+            assert_eq!(
+                ir.route_map_apply("rmap", &prefix, &mut result),
+                policy.route_map_apply("rmap", &prefix, &mut expect)
+            );
+            assert_eq!(format!("{:?}", result), format!("{:?}", expect));
+        }
+        let v6: IpNet = "2001:db8::/32".parse().unwrap();
+        let mut attrs = Vec::new();
+        assert_eq!(
+            ir.route_map_apply("rmap", &v6, &mut attrs),
+            PolicyAction::Deny
+        );
+        assert_eq!(
+            ir.route_map_apply("undefined", &v6, &mut attrs),
+            PolicyAction::Permit
+        );
+
+        // Undefined prefix list denies, and the entry referring to it
+        // is skipped.
+        let v4: Ipv4Net = "10.1.0.0/16".parse().unwrap();
+        assert_eq!(ir.prefix_list_apply("undefined", &v4), PolicyAction::Deny);
+        policy.plist.remove("plist");
+        let ir = PolicyIr::compile(&policy);
+        let prefix = IpNet::V4(v4);
+        let mut expect = Vec::new();
+        assert_eq!(
+            ir.route_map_apply("rmap", &prefix, &mut attrs),
+            policy.route_map_apply("rmap", &prefix, &mut expect)
+        );
+        assert_eq!(
+            ir.route_map_apply("rmap", &prefix, &mut attrs),
+            PolicyAction::Deny
+        );
+    }
+}
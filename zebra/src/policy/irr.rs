@@ -0,0 +1,290 @@
+use super::{PolicyAction, PrefixList, PrefixListEntry};
+use crate::bgp::handler::{Bgp, Message};
+use crate::bgp::route::route_policy_update;
+use crate::config::Args;
+use ipnet::Ipv4Net;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Sequence number step of the generated entries.
+pub const IRR_SEQ_STEP: u32 = 5;
+
+// Depth of nested as-set expansion.
+pub const IRR_DEPTH_MAX: usize = 16;
+
+// route and as-set objects of a RPSL dump of the IRR database.
+#[derive(Debug, Default)]
+pub struct IrrDatabase {
+    pub routes: BTreeMap<u32, BTreeSet<Ipv4Net>>,
+    pub as_sets: BTreeMap<String, Vec<String>>,
+}
+
+fn irr_asn(member: &str) -> Option<u32> {
+    member.strip_prefix("AS")?.parse().ok()
+}
+
+impl IrrDatabase {
+    // Objects are separated by blank lines. Continuation lines start with a
+    // space, a tab or '+'.
+    pub fn parse(text: &str) -> Self {
+        let mut db = IrrDatabase::default();
+        let mut object: Vec<(String, String)> = Vec::new();
+        for line in text.lines().chain(std::iter::once("")) {
+            if line.starts_with('%') || line.starts_with('#') {
+                continue;
+            }
+            if line.trim().is_empty() {
+                db.object_add(&object);
+                object.clear();
+                continue;
+            }
+            if line.starts_with([' ', '\t', '+']) {
+                if let Some((_, value)) = object.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim_start_matches('+').trim());
+                }
+                continue;
+            }
+            if let Some((attr, value)) = line.split_once(':') {
+                let value = value.split('#').next().unwrap_or("").trim();
+                object.push((attr.trim().to_lowercase(), value.to_string()));
+            }
+        }
+        db
+    }
+
+    fn object_add(&mut self, object: &[(String, String)]) {
+        let (class, key) = match object.first() {
+            Some(first) => first,
+            None => return,
+        };
+        let values = |name: &str| -> Vec<String> {
+            object
+                .iter()
+                .filter(|(attr, _)| attr == name)
+                .flat_map(|(_, value)| value.split([',', ' ']))
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_uppercase())
+                .collect()
+        };
+        match class.as_str() {
+            "route" => {
+                let prefix: Ipv4Net = match key.parse() {
+                    Ok(prefix) => prefix,
+                    Err(_) => return,
+                };
+                for origin in values("origin").iter().filter_map(|x| irr_asn(x)) {
+                    self.routes
+                        .entry(origin)
+                        .or_default()
+                        .insert(prefix.trunc());
+                }
+            }
+            "as-set" => {
+                self.as_sets
+                    .entry(key.to_uppercase())
+                    .or_default()
+                    .extend(values("members"));
+            }
+            _ => {}
+        }
+    }
+
+    // ASes of the as-set, nested sets are expanded once.
+    pub fn expand(&self, as_set: &str) -> Option<BTreeSet<u32>> {
+        let mut asns = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        self.expand_set(&as_set.to_uppercase(), &mut asns, &mut visited, 0)?;
+        Some(asns)
+    }
+
+    fn expand_set(
+        &self,
+        name: &str,
+        asns: &mut BTreeSet<u32>,
+        visited: &mut BTreeSet<String>,
+        depth: usize,
+    ) -> Option<()> {
+        if depth > IRR_DEPTH_MAX || !visited.insert(name.to_string()) {
+            return Some(());
+        }
+        for member in self.as_sets.get(name)?.iter() {
+            match irr_asn(member) {
+                Some(asn) => {
+                    asns.insert(asn);
+                }
+                None => {
+                    // Unknown nested set is skipped like bgpq does.
+                    let _ = self.expand_set(member, asns, visited, depth + 1);
+                }
+            }
+        }
+        Some(())
+    }
+
+    pub fn prefixes(&self, as_set: &str) -> Option<BTreeSet<Ipv4Net>> {
+        let asns = match irr_asn(&as_set.to_uppercase()) {
+            Some(asn) => BTreeSet::from([asn]),
+            None => self.expand(as_set)?,
+        };
+        Some(
+            asns.iter()
+                .filter_map(|asn| self.routes.get(asn))
+                .flatten()
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+// bgpq4 -j output, {"NAME": [{"prefix": "10.0.0.0/8", "exact": true}, ...]}.
+fn bgpq_prefix_list(text: &str, name: &str) -> Option<PrefixList> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    let object = value.as_object()?;
+    let entries = match object.get(name) {
+        Some(entries) => entries,
+        None if object.len() == 1 => object.values().next()?,
+        None => return None,
+    };
+    let mut plist = PrefixList::default();
+    for (i, entry) in entries.as_array()?.iter().enumerate() {
+        let prefix: Ipv4Net = match entry.get("prefix").and_then(|x| x.as_str()) {
+            Some(prefix) => match prefix.parse() {
+                Ok(prefix) => prefix,
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        let len = |key: &str| entry.get(key).and_then(|x| x.as_u64()).map(|x| x as u8);
+        let exact = entry.get("exact").and_then(|x| x.as_bool()).unwrap_or(true);
+        let (ge, le) = if exact {
+            (None, None)
+        } else {
+            (len("greater-equal"), len("less-equal").or(Some(32)))
+        };
+        plist.entry.insert(
+            (i as u32 + 1) * IRR_SEQ_STEP,
+            PrefixListEntry {
+                action: Some(PolicyAction::Permit),
+                prefix: Some(prefix.trunc()),
+                le,
+                ge,
+            },
+        );
+    }
+    Some(plist)
+}
+
+// Prefix list permitting the prefixes of the as-set exactly, either from the
+// RPSL dump or from the bgpq4 JSON output.
+pub fn irr_prefix_list(text: &str, as_set: &str) -> Option<PrefixList> {
+    if text.trim_start().starts_with('{') {
+        return bgpq_prefix_list(text, as_set);
+    }
+    let prefixes = IrrDatabase::parse(text).prefixes(as_set)?;
+    let mut plist = PrefixList::default();
+    for (i, prefix) in prefixes.into_iter().enumerate() {
+        plist.entry.insert(
+            (i as u32 + 1) * IRR_SEQ_STEP,
+            PrefixListEntry {
+                action: Some(PolicyAction::Permit),
+                prefix: Some(prefix),
+                ..Default::default()
+            },
+        );
+    }
+    Some(plist)
+}
+
+// request policy generate as-set AS-EXAMPLE [prefix-list NAME]
+pub fn request_policy_generate(bgp: &Bgp, mut args: Args) -> String {
+    let as_set = match args.string() {
+        Some(as_set) => as_set,
+        None => return String::from("% AS set is required\n"),
+    };
+    let name = args.string().unwrap_or(as_set.clone());
+    let source = match &bgp.irr_source {
+        Some(source) => source.clone(),
+        None => return String::from("% IRR source is not configured\n"),
+    };
+    let tx = bgp.tx.clone();
+    let reply = format!(
+        "Generating prefix-list {} for {} from {}\n",
+        name, as_set, source
+    );
+    tokio::spawn(async move {
+        let text = match tokio::fs::read_to_string(&source).await {
+            Ok(text) => text,
+            Err(err) => {
+                println!("irr: can't read {}: {}", source, err);
+                return;
+            }
+        };
+        match irr_prefix_list(&text, &as_set) {
+            Some(plist) => {
+                println!(
+                    "irr: prefix-list {} generated with {} entries for {}",
+                    name,
+                    plist.entry.len(),
+                    as_set
+                );
+                let _ = tx.send(Message::PrefixListGenerated(name, plist));
+            }
+            None => println!("irr: {} is not found in {}", as_set, source),
+        }
+    });
+    reply
+}
+
+// Generated list is installed as the named prefix-list object and applied to
+// the neighbors referring to it.
+pub fn prefix_list_generated(bgp: &mut Bgp, name: String, plist: PrefixList) {
+    bgp.policy.generated.insert(name.clone(), plist);
+    bgp.policy_changed.insert(name);
+    route_policy_update(bgp);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn irr_prefix_list_rpsl() {
+        let dump = "\
+as-set:     AS-EXAMPLE
+members:    AS64496, AS-CUSTOMER
++           AS64497
+
+as-set:     AS-CUSTOMER
+members:    AS64498, AS-EXAMPLE
+
+route:      192.0.2.0/24
+origin:     AS64496
+
+route:      198.51.100.0/24
+origin:     AS64498 # customer
+
+route:      203.0.113.0/24
+origin:     AS64499
+";
+        let plist = irr_prefix_list(dump, "as-example").unwrap();
+        let prefixes: Vec<String> = plist
+            .entry
+            .values()
+            .map(|entry| entry.prefix.unwrap().to_string())
+            .collect();
+        assert_eq!(prefixes, vec!["192.0.2.0/24", "198.51.100.0/24"]);
+        assert_eq!(
+            plist.apply(&"192.0.2.0/25".parse().unwrap()),
+            PolicyAction::Deny
+        );
+        assert!(irr_prefix_list(dump, "AS-UNKNOWN").is_none());
+
+        let json = r#"{"NN": [{"prefix": "192.0.2.0/24", "exact": false,
+            "greater-equal": 24, "less-equal": 25}]}"#;
+        let plist = irr_prefix_list(json, "AS-EXAMPLE").unwrap();
+        assert_eq!(
+            plist.apply(&"192.0.2.0/25".parse().unwrap()),
+            PolicyAction::Permit
+        );
+    }
+}
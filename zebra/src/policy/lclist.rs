@@ -0,0 +1,175 @@
+use super::{Policy, PolicyAction};
+use crate::bgp::packet::{Attribute, Attrs, LargeCom, LargeComAttr};
+use crate::config::{Args, ConfigOp};
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+#[derive(Debug, Default)]
+pub struct LargeCommunityList {
+    pub entry: BTreeMap<u32, LargeCommunityListEntry>,
+}
+
+#[derive(Debug, Default)]
+pub struct LargeCommunityListEntry {
+    pub action: Option<PolicyAction>,
+    pub member: BTreeSet<LargeCom>,
+}
+
+impl LargeCommunityListEntry {
+    // All of the members must be present in the route.
+    pub fn matches(&self, attr: Option<&LargeComAttr>) -> bool {
+        if self.member.is_empty() {
+            return false;
+        }
+        match attr {
+            Some(attr) => self.member.iter().all(|x| attr.contains(x)),
+            None => false,
+        }
+    }
+}
+
+impl LargeCommunityList {
+    // Entries are evaluated in sequence order, no match is implicit deny.
+    pub fn apply(&self, attr: Option<&LargeComAttr>) -> PolicyAction {
+        for entry in self.entry.values() {
+            if let Some(action) = entry.action {
+                if entry.matches(attr) {
+                    return action;
+                }
+            }
+        }
+        PolicyAction::Deny
+    }
+}
+
+pub fn large_community(attrs: &Attrs) -> Option<&LargeComAttr> {
+    attrs.iter().find_map(|attr| match attr {
+        Attribute::LargeCom(lcom) => Some(lcom),
+        _ => None,
+    })
+}
+
+// Add the values to LARGE_COMMUNITY of the attributes.
+pub fn large_community_add(attrs: &mut Attrs, values: &BTreeSet<LargeCom>) {
+    if values.is_empty() {
+        return;
+    }
+    for attr in attrs.iter_mut() {
+        if let Attribute::LargeCom(lcom) = attr {
+            lcom.0.extend(values.iter().cloned());
+            lcom.sort_uniq();
+            return;
+        }
+    }
+    attrs.push(Attribute::LargeCom(LargeComAttr(
+        values.iter().cloned().collect(),
+    )));
+}
+
+// large-community-list hoge
+// large-community-list hoge seq 5
+// large-community-list hoge seq 5 action permit
+// large-community-list hoge seq 5 member 65000:1:2
+
+fn lclist_entry<'a>(
+    policy: &'a mut Policy,
+    args: &mut Args,
+    op: &ConfigOp,
+) -> Option<&'a mut LargeCommunityListEntry> {
+    let name = args.string()?;
+    let seq = args.u32()?;
+    if *op == ConfigOp::Set {
+        Some(
+            policy
+                .lclist
+                .entry(name)
+                .or_default()
+                .entry
+                .entry(seq)
+                .or_default(),
+        )
+    } else {
+        policy.lclist.get_mut(&name)?.entry.get_mut(&seq)
+    }
+}
+
+pub fn config_lclist(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    if op == ConfigOp::Set {
+        policy.lclist.entry(name).or_default();
+    } else {
+        policy.lclist.remove(&name);
+    }
+    Some(())
+}
+
+pub fn config_lclist_seq(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let seq = args.u32()?;
+    if op == ConfigOp::Set {
+        policy
+            .lclist
+            .entry(name)
+            .or_default()
+            .entry
+            .entry(seq)
+            .or_default();
+    } else if let Some(lclist) = policy.lclist.get_mut(&name) {
+        lclist.entry.remove(&seq);
+    }
+    Some(())
+}
+
+pub fn config_lclist_action(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = lclist_entry(policy, &mut args, &op)?;
+    entry.action = if op == ConfigOp::Set {
+        Some(PolicyAction::from_str(&args.string()?).ok()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_lclist_member(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = lclist_entry(policy, &mut args, &op)?;
+    let member = LargeCom::from_str(&args.string()?).ok()?;
+    if op == ConfigOp::Set {
+        entry.member.insert(member);
+    } else {
+        entry.member.remove(&member);
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn large_community_list_apply() {
+        let mut lclist = LargeCommunityList::default();
+        lclist.entry.insert(
+            5,
+            LargeCommunityListEntry {
+                action: Some(PolicyAction::Permit),
+                member: BTreeSet::from([LargeCom::from_str("65000:1:1").unwrap()]),
+            },
+        );
+        let attr = LargeComAttr::from_str("65000:1:1 65000:2:2").unwrap();
+        assert_eq!(lclist.apply(Some(&attr)), PolicyAction::Permit);
+        let attr = LargeComAttr::from_str("65000:2:2").unwrap();
+        assert_eq!(lclist.apply(Some(&attr)), PolicyAction::Deny);
+        assert_eq!(lclist.apply(None), PolicyAction::Deny);
+
+        let mut attrs = vec![Attribute::LargeCom(attr)];
+        let values = BTreeSet::from([
+            LargeCom::from_str("65000:1:1").unwrap(),
+            LargeCom::from_str("65000:2:2").unwrap(),
+        ]);
+        large_community_add(&mut attrs, &values);
+        assert_eq!(
+            large_community(&attrs).map(|x| x.to_string()),
+            Some(String::from("65000:1:1 65000:2:2"))
+        );
+    }
+}
@@ -1,2 +1,48 @@
+use std::str::FromStr;
+
+pub mod aslist;
+pub use aslist::*;
+
 pub mod clist;
 pub use clist::*;
+
+pub mod plist;
+pub use plist::*;
+
+pub mod irr;
+
+pub mod ir;
+pub use ir::*;
+
+pub mod lclist;
+pub use lclist::*;
+
+pub mod eclist;
+pub use eclist::*;
+
+pub mod rmap;
+pub use rmap::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyAction {
+    Permit,
+    Deny,
+}
+
+impl FromStr for PolicyAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "permit" => Ok(Self::Permit),
+            "deny" => Ok(Self::Deny),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PolicyDirection {
+    In,
+    Out,
+}
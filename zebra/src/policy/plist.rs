@@ -0,0 +1,199 @@
+use super::{Policy, PolicyAction};
+use crate::config::{Args, ConfigOp};
+use ipnet::Ipv4Net;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Debug, Default)]
+pub struct PrefixList {
+    pub entry: BTreeMap<u32, PrefixListEntry>,
+}
+
+#[derive(Debug, Default)]
+pub struct PrefixListEntry {
+    pub action: Option<PolicyAction>,
+    pub prefix: Option<Ipv4Net>,
+    pub le: Option<u8>,
+    pub ge: Option<u8>,
+}
+
+impl PrefixListEntry {
+    // Without le and ge, prefix length must be exact match.
+    pub fn matches(&self, prefix: &Ipv4Net) -> bool {
+        let base = match &self.prefix {
+            Some(base) => base,
+            None => return false,
+        };
+        if !base.contains(prefix) {
+            return false;
+        }
+        let len = prefix.prefix_len();
+        match (self.ge, self.le) {
+            (None, None) => len == base.prefix_len(),
+            (ge, le) => len >= ge.unwrap_or(base.prefix_len()) && len <= le.unwrap_or(32),
+        }
+    }
+}
+
+impl PrefixList {
+    // Entries are evaluated in sequence order, no match is implicit deny.
+    pub fn apply(&self, prefix: &Ipv4Net) -> PolicyAction {
+        for entry in self.entry.values() {
+            if let Some(action) = entry.action {
+                if entry.matches(prefix) {
+                    return action;
+                }
+            }
+        }
+        PolicyAction::Deny
+    }
+}
+
+// prefix-list hoge
+// prefix-list hoge seq 5
+// prefix-list hoge seq 5 action permit
+// prefix-list hoge seq 5 prefix 10.0.0.0/8
+// prefix-list hoge seq 5 le 24
+// prefix-list hoge seq 5 ge 16
+
+fn plist_entry<'a>(
+    policy: &'a mut Policy,
+    args: &mut Args,
+    op: &ConfigOp,
+) -> Option<&'a mut PrefixListEntry> {
+    let name = args.string()?;
+    let seq = args.u32()?;
+    if *op == ConfigOp::Set {
+        Some(
+            policy
+                .plist
+                .entry(name)
+                .or_default()
+                .entry
+                .entry(seq)
+                .or_default(),
+        )
+    } else {
+        policy.plist.get_mut(&name)?.entry.get_mut(&seq)
+    }
+}
+
+pub fn config_plist(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    if op == ConfigOp::Set {
+        policy.plist.entry(name).or_default();
+    } else {
+        policy.plist.remove(&name);
+    }
+    Some(())
+}
+
+pub fn config_plist_seq(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let seq = args.u32()?;
+    if op == ConfigOp::Set {
+        policy
+            .plist
+            .entry(name)
+            .or_default()
+            .entry
+            .entry(seq)
+            .or_default();
+    } else if let Some(plist) = policy.plist.get_mut(&name) {
+        plist.entry.remove(&seq);
+    }
+    Some(())
+}
+
+pub fn config_plist_action(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = plist_entry(policy, &mut args, &op)?;
+    entry.action = if op == ConfigOp::Set {
+        Some(PolicyAction::from_str(&args.string()?).ok()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_plist_prefix(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = plist_entry(policy, &mut args, &op)?;
+    entry.prefix = if op == ConfigOp::Set {
+        Some(args.v4net()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_plist_le(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = plist_entry(policy, &mut args, &op)?;
+    entry.le = if op == ConfigOp::Set {
+        Some(args.u32()? as u8)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_plist_ge(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = plist_entry(policy, &mut args, &op)?;
+    entry.ge = if op == ConfigOp::Set {
+        Some(args.u32()? as u8)
+    } else {
+        None
+    };
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prefix_list_apply() {
+        let mut plist = PrefixList::default();
+        plist.entry.insert(
+            5,
+            PrefixListEntry {
+                action: Some(PolicyAction::Deny),
+                prefix: Some("10.0.0.0/8".parse().unwrap()),
+                ..Default::default()
+            },
+        );
+        plist.entry.insert(
+            10,
+            PrefixListEntry {
+                action: Some(PolicyAction::Permit),
+                prefix: Some("10.0.0.0/8".parse().unwrap()),
+                le: Some(24),
+                ..Default::default()
+            },
+        );
+        let apply = |prefix: &str| plist.apply(&prefix.parse().unwrap());
+        assert_eq!(apply("10.0.0.0/8"), PolicyAction::Deny);
+        assert_eq!(apply("10.1.0.0/16"), PolicyAction::Permit);
+        assert_eq!(apply("10.1.1.0/25"), PolicyAction::Deny);
+        assert_eq!(apply("192.168.0.0/16"), PolicyAction::Deny);
+    }
+
+    #[test]
+    fn prefix_list_action() {
+        let mut policy = Policy::default();
+        let args = |v: &[&str]| Args(v.iter().map(|s| s.to_string()).collect());
+        config_plist_action(&mut policy, args(&["hoge", "5", "permit"]), ConfigOp::Set);
+        let action = |policy: &Policy| policy.plist["hoge"].entry[&5].action;
+        assert_eq!(action(&policy), Some(PolicyAction::Permit));
+
+        // Unknown action is rejected and the entry is kept as is.
+        let ret = config_plist_action(&mut policy, args(&["hoge", "5", "allow"]), ConfigOp::Set);
+        assert!(ret.is_none());
+        assert_eq!(action(&policy), Some(PolicyAction::Permit));
+
+        // Undefined prefix-list denies.
+        let prefix = "10.0.0.0/8".parse().unwrap();
+        assert_eq!(
+            policy.prefix_list_apply("undefined", &prefix),
+            PolicyAction::Deny
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use super::{Policy, PolicyAction};
+use crate::bgp::packet::{Attribute, Attrs, CommunityAttr, LocalPrefAttr, MedAttr, OriginAttr};
+use crate::bgp::rpki::RpkiState;
+use crate::config::{Args, ConfigOp};
+use ipnet::IpNet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+#[derive(Debug, Default)]
+pub struct RouteMap {
+    pub entry: BTreeMap<u32, RouteMapEntry>,
+}
+
+// Entry without match condition matches every route. Communities are added
+// to the ones of the route.
+#[derive(Debug, Default)]
+pub struct RouteMapEntry {
+    pub action: Option<PolicyAction>,
+    pub match_prefix_list: Option<String>,
+    pub match_rpki: Option<RpkiState>,
+    pub set_med: Option<u32>,
+    pub set_local_pref: Option<u32>,
+    pub set_origin: Option<u8>,
+    pub set_community: BTreeSet<u32>,
+}
+
+impl RouteMapEntry {
+    // Prefix lists are IPv4 only, so IPv6 prefix does not match. Validation
+    // state is only known for received routes.
+    fn matches(&self, policy: &Policy, prefix: &IpNet, rpki: Option<RpkiState>) -> bool {
+        if self.match_rpki.is_some() && self.match_rpki != rpki {
+            return false;
+        }
+        match (&self.match_prefix_list, prefix) {
+            (None, _) => true,
+            (Some(name), IpNet::V4(prefix)) => {
+                policy.prefix_list_apply(name, prefix) == PolicyAction::Permit
+            }
+            (Some(_), IpNet::V6(_)) => false,
+        }
+    }
+
+    fn set(&self, attrs: &mut Attrs) {
+        if let Some(med) = self.set_med {
+            attrs.retain(|attr| !matches!(attr, Attribute::Med(_)));
+            attrs.push(Attribute::Med(MedAttr { med }));
+        }
+        if let Some(local_pref) = self.set_local_pref {
+            attrs.retain(|attr| !matches!(attr, Attribute::LocalPref(_)));
+            attrs.push(Attribute::LocalPref(LocalPrefAttr { local_pref }));
+        }
+        if let Some(origin) = self.set_origin {
+            attrs.retain(|attr| !matches!(attr, Attribute::Origin(_)));
+            attrs.push(Attribute::Origin(OriginAttr { origin }));
+        }
+        if !self.set_community.is_empty() {
+            let mut community = self.set_community.clone();
+            for attr in attrs.iter() {
+                if let Attribute::Community(attr) = attr {
+                    community.extend(attr.0.iter());
+                }
+            }
+            attrs.retain(|attr| !matches!(attr, Attribute::Community(_)));
+            attrs.push(Attribute::Community(CommunityAttr(
+                community.into_iter().collect(),
+            )));
+        }
+    }
+}
+
+impl RouteMap {
+    // Entries are evaluated in sequence order. Attributes are set by the
+    // first permit entry matched, no match is implicit deny.
+    pub fn apply(
+        &self,
+        policy: &Policy,
+        prefix: &IpNet,
+        rpki: Option<RpkiState>,
+        attrs: &mut Attrs,
+    ) -> PolicyAction {
+        for entry in self.entry.values() {
+            if let Some(action) = entry.action {
+                if entry.matches(policy, prefix, rpki) {
+                    if action == PolicyAction::Permit {
+                        entry.set(attrs);
+                    }
+                    return action;
+                }
+            }
+        }
+        PolicyAction::Deny
+    }
+}
+
+impl Policy {
+    // Route map which is not defined yet permits everything as is.
+    pub fn route_map_apply(&self, name: &str, prefix: &IpNet, attrs: &mut Attrs) -> PolicyAction {
+        if let Some(ir) = &self.ir {
+            return ir.route_map_apply(name, prefix, attrs);
+        }
+        match self.rmap.get(name) {
+            Some(rmap) => rmap.apply(self, prefix, None, attrs),
+            None => PolicyAction::Permit,
+        }
+    }
+
+    // Route map of the received route, which may match on the RPKI
+    // validation state.
+    pub fn route_map_import(
+        &self,
+        name: &str,
+        prefix: &IpNet,
+        rpki: RpkiState,
+        attrs: &mut Attrs,
+    ) -> PolicyAction {
+        if let Some(ir) = &self.ir {
+            return ir.route_map_import(name, prefix, rpki, attrs);
+        }
+        match self.rmap.get(name) {
+            Some(rmap) => rmap.apply(self, prefix, Some(rpki), attrs),
+            None => PolicyAction::Permit,
+        }
+    }
+
+    // Routes have to be evaluated again when the validation state changes.
+    pub fn route_map_uses_rpki(&self, name: &str) -> bool {
+        self.rmap.get(name).map_or(false, |rmap| {
+            rmap.entry.values().any(|entry| entry.match_rpki.is_some())
+        })
+    }
+}
+
+impl Policy {
+    // Whether the route map itself or the prefix list used in it is changed.
+    pub fn route_map_refers(&self, name: &str, changed: &BTreeSet<String>) -> bool {
+        if changed.contains(name) {
+            return true;
+        }
+        self.rmap.get(name).map_or(false, |rmap| {
+            rmap.entry.values().any(|entry| {
+                entry
+                    .match_prefix_list
+                    .as_ref()
+                    .map_or(false, |x| changed.contains(x))
+            })
+        })
+    }
+}
+
+fn origin_from_str(s: &str) -> Option<u8> {
+    match s {
+        "igp" => Some(0),
+        "egp" => Some(1),
+        "incomplete" => Some(2),
+        _ => None,
+    }
+}
+
+// route-map hoge
+// route-map hoge seq 10
+// route-map hoge seq 10 action permit
+// route-map hoge seq 10 match prefix-list fuga
+// route-map hoge seq 10 set med 100
+
+fn rmap_entry<'a>(
+    policy: &'a mut Policy,
+    args: &mut Args,
+    op: &ConfigOp,
+) -> Option<&'a mut RouteMapEntry> {
+    let name = args.string()?;
+    let seq = args.u32()?;
+    if *op == ConfigOp::Set {
+        Some(
+            policy
+                .rmap
+                .entry(name)
+                .or_default()
+                .entry
+                .entry(seq)
+                .or_default(),
+        )
+    } else {
+        policy.rmap.get_mut(&name)?.entry.get_mut(&seq)
+    }
+}
+
+pub fn config_rmap(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    if op == ConfigOp::Set {
+        policy.rmap.entry(name).or_default();
+    } else {
+        policy.rmap.remove(&name);
+    }
+    Some(())
+}
+
+pub fn config_rmap_seq(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let name = args.string()?;
+    let seq = args.u32()?;
+    if op == ConfigOp::Set {
+        policy
+            .rmap
+            .entry(name)
+            .or_default()
+            .entry
+            .entry(seq)
+            .or_default();
+    } else if let Some(rmap) = policy.rmap.get_mut(&name) {
+        rmap.entry.remove(&seq);
+    }
+    Some(())
+}
+
+pub fn config_rmap_action(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = rmap_entry(policy, &mut args, &op)?;
+    entry.action = if op == ConfigOp::Set {
+        Some(PolicyAction::from_str(&args.string()?).ok()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_rmap_match_prefix_list(
+    policy: &mut Policy,
+    mut args: Args,
+    op: ConfigOp,
+) -> Option<()> {
+    let entry = rmap_entry(policy, &mut args, &op)?;
+    entry.match_prefix_list = if op == ConfigOp::Set {
+        Some(args.string()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_rmap_match_rpki(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = rmap_entry(policy, &mut args, &op)?;
+    entry.match_rpki = if op == ConfigOp::Set {
+        Some(RpkiState::from_str(&args.string()?).ok()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_rmap_set_med(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = rmap_entry(policy, &mut args, &op)?;
+    entry.set_med = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_rmap_set_local_pref(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = rmap_entry(policy, &mut args, &op)?;
+    entry.set_local_pref = if op == ConfigOp::Set {
+        Some(args.u32()?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_rmap_set_origin(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = rmap_entry(policy, &mut args, &op)?;
+    entry.set_origin = if op == ConfigOp::Set {
+        Some(origin_from_str(&args.string()?)?)
+    } else {
+        None
+    };
+    Some(())
+}
+
+pub fn config_rmap_set_community(policy: &mut Policy, mut args: Args, op: ConfigOp) -> Option<()> {
+    let entry = rmap_entry(policy, &mut args, &op)?;
+    let community = CommunityAttr::from_str(&args.string()?).ok()?;
+    for value in community.0.into_iter() {
+        if op == ConfigOp::Set {
+            entry.set_community.insert(value);
+        } else {
+            entry.set_community.remove(&value);
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::policy::{PrefixList, PrefixListEntry};
+
+    #[test]
+    fn route_map_apply() {
+        let mut policy = Policy::default();
+        let mut rmap = RouteMap::default();
+        rmap.entry.insert(
+            10,
+            RouteMapEntry {
+                action: Some(PolicyAction::Deny),
+                match_prefix_list: Some("deny-list".to_string()),
+                ..Default::default()
+            },
+        );
+        rmap.entry.insert(
+            20,
+            RouteMapEntry {
+                action: Some(PolicyAction::Permit),
+                set_med: Some(100),
+                set_origin: Some(2),
+                ..Default::default()
+            },
+        );
+        policy.rmap.insert("local".to_string(), rmap);
+
+        // Undefined prefix list matches nothing.
+        let prefix: IpNet = "10.0.0.0/24".parse().unwrap();
+        let mut attrs = vec![Attribute::Origin(OriginAttr { origin: 0 })];
+        let action = policy.route_map_apply("local", &prefix, &mut attrs);
+        assert_eq!(action, PolicyAction::Permit);
+        let med = attrs.iter().find_map(|attr| match attr {
+            Attribute::Med(med) => Some(med.med),
+            _ => None,
+        });
+        assert_eq!(med, Some(100));
+        assert_eq!(attrs.len(), 2);
+
+        let mut plist = PrefixList::default();
+        plist.entry.insert(
+            5,
+            PrefixListEntry {
+                action: Some(PolicyAction::Permit),
+                prefix: Some("10.0.0.0/8".parse().unwrap()),
+                le: Some(24),
+                ..Default::default()
+            },
+        );
+        policy.plist.insert("deny-list".to_string(), plist);
+        let mut attrs = vec![Attribute::Origin(OriginAttr { origin: 0 })];
+        let action = policy.route_map_apply("local", &prefix, &mut attrs);
+        assert_eq!(action, PolicyAction::Deny);
+        assert_eq!(attrs.len(), 1);
+
+        let action = policy.route_map_apply("undefined", &prefix, &mut attrs);
+        assert_eq!(action, PolicyAction::Permit);
+    }
+
+    #[test]
+    fn route_map_match_rpki() {
+        let mut policy = Policy::default();
+        let mut rmap = RouteMap::default();
+        rmap.entry.insert(
+            10,
+            RouteMapEntry {
+                action: Some(PolicyAction::Deny),
+                match_rpki: Some(RpkiState::Invalid),
+                ..Default::default()
+            },
+        );
+        rmap.entry.insert(
+            20,
+            RouteMapEntry {
+                action: Some(PolicyAction::Permit),
+                ..Default::default()
+            },
+        );
+        policy.rmap.insert("rpki".to_string(), rmap);
+        assert!(policy.route_map_uses_rpki("rpki"));
+
+        let prefix: IpNet = "192.0.2.0/24".parse().unwrap();
+        let mut attrs = Vec::new();
+        for (state, expect) in [
+            (RpkiState::Valid, PolicyAction::Permit),
+            (RpkiState::Invalid, PolicyAction::Deny),
+            (RpkiState::NotFound, PolicyAction::Permit),
+        ] {
+            let action = policy.route_map_import("rpki", &prefix, state, &mut attrs);
+            assert_eq!(action, expect);
+        }
+        // Locally originated route does not have the state.
+        let action = policy.route_map_apply("rpki", &prefix, &mut attrs);
+        assert_eq!(action, PolicyAction::Permit);
+    }
+}
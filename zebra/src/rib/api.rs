@@ -1,3 +1,7 @@
+use ipnet::IpNet;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
 #[derive(Debug)]
@@ -15,13 +19,22 @@ impl RibTxChannel {
 
 // Message from protocol module to rib.
 #[allow(dead_code)]
+#[derive(Debug, Clone)]
 pub enum RibTx {
-    RouteAdd(),
-    RouteDel(),
+    RouteAdd(RibRoute),
+    RouteDel(RibRoute),
     NexthopResgister(),
     NexthopUnresgister(),
 }
 
+// Route of the protocol with the next hops of the ECMP group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RibRoute {
+    pub prefix: IpNet,
+    pub distance: u32,
+    pub nexthops: Vec<IpAddr>,
+}
+
 pub struct RibRxChannel {
     pub tx: Sender<RibRx>,
     pub rx: Receiver<RibRx>,
@@ -36,9 +49,49 @@ impl RibRxChannel {
 
 // Message from rib to protocol module.
 #[allow(dead_code)]
+#[derive(Debug, Clone)]
 pub enum RibRx {
-    RedistAdd(),
-    RedistDel(),
+    RedistAdd(RedistRoute),
+    RedistDel(RedistRoute),
+    // Route of the protocol module is handed to the FIB.
+    RouteAck(IpNet),
     Link(),
     Nexthop(),
 }
+
+// Source of the routes offered for redistribution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RedistSource {
+    Connected,
+    Static,
+}
+
+impl FromStr for RedistSource {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "connected" => Ok(Self::Connected),
+            "static" => Ok(Self::Static),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for RedistSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connected => write!(f, "connected"),
+            Self::Static => write!(f, "static"),
+        }
+    }
+}
+
+// Route of other source, the next hop is absent for connected routes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedistRoute {
+    pub source: RedistSource,
+    pub prefix: IpNet,
+    pub metric: u32,
+    pub nexthop: Option<IpAddr>,
+}
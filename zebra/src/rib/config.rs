@@ -1,9 +1,9 @@
 use super::{
-    entry::{RibEntry, RibType},
+    api::{RedistRoute, RedistSource},
     instance::Rib,
 };
 use crate::config::{Args, ConfigOp};
-use ipnet::Ipv4Net;
+use ipnet::{IpNet, Ipv4Net};
 use std::net::{IpAddr, Ipv4Addr};
 
 pub async fn config_dispatch(rib: &mut Rib, path: String, args: Args, op: ConfigOp) {
@@ -29,15 +29,22 @@ async fn static_route_nexthop(rib: &mut Rib, mut args: Args, op: ConfigOp) -> Op
     if op == ConfigOp::Set && args.len() > 1 {
         let dest: Ipv4Net = args.v4net()?;
         let gateway: Ipv4Addr = args.v4addr()?;
-        //
-        let mut entry = RibEntry::new(RibType::Static);
-        entry.gateway = IpAddr::V4(gateway);
-        // XXX rib.rib.insert(dest, entry);
-
-        rib.fib_handle.route_ipv4_add(dest, gateway).await;
-        // if let Some(handle) = rib.handle.as_ref() {
-        //     route_add(handle.clone(), dest, gateway).await;
-        // }
+        rib.static_route_add(dest, IpAddr::V4(gateway));
+        rib.redist_add(RedistRoute {
+            source: RedistSource::Static,
+            prefix: IpNet::V4(dest),
+            metric: 0,
+            nexthop: Some(IpAddr::V4(gateway)),
+        });
+    } else if op == ConfigOp::Delete {
+        let dest: Ipv4Net = args.v4net()?;
+        rib.static_route_del(dest);
+        rib.redist_del(RedistRoute {
+            source: RedistSource::Static,
+            prefix: IpNet::V4(dest),
+            metric: 0,
+            nexthop: None,
+        });
     }
     Some(())
 }
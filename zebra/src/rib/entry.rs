@@ -74,6 +74,14 @@ impl RibEntry {
             } else {
                 "directly connected unknown".to_string()
             }
+        } else if self.nexthops.len() > 1 {
+            // ECMP group.
+            let nexthops: Vec<String> = self
+                .nexthops
+                .iter()
+                .map(|x| format!("via {}", x.nexthop))
+                .collect();
+            nexthops.join(", ")
         } else {
             format!("via {:?}", &self.gateway)
         }
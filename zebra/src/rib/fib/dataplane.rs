@@ -0,0 +1,105 @@
+use super::grpc::GrpcDataplane;
+use clap::ValueEnum;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+// Southbound of the FIB programming. Operations are issued without waiting
+// for the result, and errors are reported by the dataplane itself.
+pub trait Dataplane: Send {
+    fn name(&self) -> &str;
+
+    // Route of the ECMP group. It replaces the previous one of the prefix.
+    fn route_add(&self, prefix: IpNet, nexthops: &[IpAddr]);
+
+    fn route_del(&self, prefix: IpNet);
+
+    // Incoming label swapped to the outgoing one via the next hop. The label
+    // is popped when the outgoing one is None, and the packet is looked up in
+    // the IP table when the next hop is None.
+    fn mpls_add(&self, label: u32, out_label: Option<u32>, via: Option<IpAddr>);
+
+    fn mpls_del(&self, label: u32);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum DataplaneType {
+    // Kernel of the OS, netlink on Linux.
+    Kernel,
+    // Operations are logged and nothing is programmed.
+    Log,
+    // External dataplane such as switch ASIC SDK or DPDK application.
+    Grpc,
+}
+
+pub struct LogDataplane;
+
+impl Dataplane for LogDataplane {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    fn route_add(&self, prefix: IpNet, nexthops: &[IpAddr]) {
+        println!("dataplane: route add {} via {:?}", prefix, nexthops);
+    }
+
+    fn route_del(&self, prefix: IpNet) {
+        println!("dataplane: route del {}", prefix);
+    }
+
+    fn mpls_add(&self, label: u32, out_label: Option<u32>, via: Option<IpAddr>) {
+        println!(
+            "dataplane: mpls add {} out {:?} via {:?}",
+            label, out_label, via
+        );
+    }
+
+    fn mpls_del(&self, label: u32) {
+        println!("dataplane: mpls del {}", label);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn kernel_dataplane() -> anyhow::Result<Box<dyn Dataplane>> {
+    Ok(Box::new(super::netlink::NetlinkDataplane::new()?))
+}
+
+#[cfg(target_os = "macos")]
+fn kernel_dataplane() -> anyhow::Result<Box<dyn Dataplane>> {
+    Ok(Box::new(super::macos::RouteSocketDataplane::new()?))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn kernel_dataplane() -> anyhow::Result<Box<dyn Dataplane>> {
+    println!("dataplane: kernel is not supported, operations are logged");
+    Ok(Box::new(LogDataplane))
+}
+
+// Endpoint is the URL of the gRPC server of the external dataplane.
+pub fn dataplane_new(
+    typ: DataplaneType,
+    endpoint: Option<&str>,
+) -> anyhow::Result<Box<dyn Dataplane>> {
+    match typ {
+        DataplaneType::Kernel => kernel_dataplane(),
+        DataplaneType::Log => Ok(Box::new(LogDataplane)),
+        DataplaneType::Grpc => match endpoint {
+            Some(endpoint) => Ok(Box::new(GrpcDataplane::new(endpoint)?)),
+            None => Err(anyhow::anyhow!("gRPC dataplane requires the endpoint")),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn dataplane_type() {
+        let dataplane = dataplane_new(DataplaneType::Log, None).unwrap();
+        assert_eq!(dataplane.name(), "log");
+        assert!(dataplane_new(DataplaneType::Grpc, None).is_err());
+        assert!(dataplane_new(DataplaneType::Grpc, Some("not a url")).is_err());
+        let dataplane = dataplane_new(DataplaneType::Grpc, Some("http://[::1]:50061")).unwrap();
+        assert_eq!(dataplane.name(), "grpc");
+    }
+}
@@ -0,0 +1,105 @@
+use super::dataplane::Dataplane;
+use ipnet::IpNet;
+use std::net::IpAddr;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tonic::transport::{Channel, Endpoint};
+
+mod proto {
+    tonic::include_proto!("dataplane");
+}
+use proto::dataplane_client::DataplaneClient;
+use proto::{Mpls, Prefix, Route};
+
+enum DataplaneOp {
+    RouteAdd(Route),
+    RouteDel(Route),
+    MplsAdd(Mpls),
+    MplsDel(Mpls),
+}
+
+// External dataplane driven by the gRPC client. Operations are sent one by
+// one by the task so that the add and the following delete of the same
+// route are not reordered. The channel connects lazily and reconnects when
+// the server restarts.
+pub struct GrpcDataplane {
+    tx: UnboundedSender<DataplaneOp>,
+}
+
+impl GrpcDataplane {
+    pub fn new(endpoint: &str) -> anyhow::Result<Self> {
+        let channel = Endpoint::from_shared(endpoint.to_string())?.connect_lazy();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(grpc_dataplane_task(DataplaneClient::new(channel), rx));
+        Ok(Self { tx })
+    }
+
+    fn send(&self, op: DataplaneOp) {
+        let _ = self.tx.send(op);
+    }
+}
+
+fn addr_bytes(addr: &IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    }
+}
+
+fn route_message(prefix: IpNet, nexthops: &[IpAddr]) -> Route {
+    Route {
+        prefix: Some(Prefix {
+            addr: addr_bytes(&prefix.addr()),
+            len: prefix.prefix_len() as u32,
+        }),
+        nexthops: nexthops.iter().map(addr_bytes).collect(),
+    }
+}
+
+impl Dataplane for GrpcDataplane {
+    fn name(&self) -> &str {
+        "grpc"
+    }
+
+    fn route_add(&self, prefix: IpNet, nexthops: &[IpAddr]) {
+        self.send(DataplaneOp::RouteAdd(route_message(prefix, nexthops)));
+    }
+
+    fn route_del(&self, prefix: IpNet) {
+        self.send(DataplaneOp::RouteDel(route_message(prefix, &[])));
+    }
+
+    fn mpls_add(&self, label: u32, out_label: Option<u32>, via: Option<IpAddr>) {
+        self.send(DataplaneOp::MplsAdd(Mpls {
+            label,
+            has_out_label: out_label.is_some(),
+            out_label: out_label.unwrap_or(0),
+            via: via.as_ref().map(addr_bytes).unwrap_or_default(),
+        }));
+    }
+
+    fn mpls_del(&self, label: u32) {
+        self.send(DataplaneOp::MplsDel(Mpls {
+            label,
+            ..Default::default()
+        }));
+    }
+}
+
+// Failed operation is not retried. The dataplane is expected to be resynced
+// by restarting the daemon when it loses the state.
+async fn grpc_dataplane_task(
+    mut client: DataplaneClient<Channel>,
+    mut rx: UnboundedReceiver<DataplaneOp>,
+) {
+    while let Some(op) = rx.recv().await {
+        let result = match op {
+            DataplaneOp::RouteAdd(route) => client.route_add(route).await,
+            DataplaneOp::RouteDel(route) => client.route_del(route).await,
+            DataplaneOp::MplsAdd(mpls) => client.mpls_add(mpls).await,
+            DataplaneOp::MplsDel(mpls) => client.mpls_del(mpls).await,
+        };
+        if let Err(status) = result {
+            println!("dataplane: grpc error: {}", status.message());
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use super::dataplane::Dataplane;
 use super::message::{FibAddr, FibLink, FibMessage, FibRoute};
 use crate::rib::link;
 use anyhow::Result;
@@ -11,30 +12,78 @@ use nix::net::if_::InterfaceFlags;
 use std::collections::BTreeMap;
 use std::ffi::CString;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
-pub struct FibHandle {
-    h: net_route::Handle,
-}
+pub struct FibHandle;
 
 impl FibHandle {
     pub fn new(_rib_tx: UnboundedSender<FibMessage>) -> Result<Self> {
+        Ok(Self)
+    }
+}
+
+enum RouteOp {
+    Add(IpNet, Vec<IpAddr>),
+    Del(IpNet),
+}
+
+// Kernel dataplane of the routing socket. Operations are executed one by one
+// by the task so that they reach the kernel in order. The first next hop of
+// the ECMP group is used, and MPLS is not supported.
+pub struct RouteSocketDataplane {
+    tx: UnboundedSender<RouteOp>,
+}
+
+impl RouteSocketDataplane {
+    pub fn new() -> Result<Self> {
         let h = net_route::Handle::new()?;
-        Ok(Self { h })
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(route_socket_task(h, rx));
+        Ok(Self { tx })
     }
+}
 
-    pub async fn route_ipv4_add(&self, dest: Ipv4Net, gateway: Ipv4Addr) {
-        let route = Route::new(IpAddr::V4(dest.addr()), dest.prefix_len())
-            .with_gateway(IpAddr::V4(gateway));
-        self.h.add(&route).await.unwrap();
+async fn route_socket_task(h: net_route::Handle, mut rx: UnboundedReceiver<RouteOp>) {
+    while let Some(op) = rx.recv().await {
+        let (prefix, result) = match op {
+            // Routing socket has no replace, the previous route is deleted.
+            RouteOp::Add(prefix, nexthops) => {
+                let mut route = Route::new(prefix.addr(), prefix.prefix_len());
+                let _ = h.delete(&route).await;
+                if let Some(nexthop) = nexthops.first() {
+                    route = route.with_gateway(*nexthop);
+                }
+                (prefix, h.add(&route).await)
+            }
+            RouteOp::Del(prefix) => {
+                let route = Route::new(prefix.addr(), prefix.prefix_len());
+                (prefix, h.delete(&route).await)
+            }
+        };
+        if let Err(err) = result {
+            println!("route: {} error: {}", prefix, err);
+        }
+    }
+}
+
+impl Dataplane for RouteSocketDataplane {
+    fn name(&self) -> &str {
+        "kernel"
     }
 
-    #[allow(dead_code)]
-    pub async fn route_ipv4_del(&self, dest: Ipv4Net, gateway: Ipv4Addr) {
-        let route = Route::new(IpAddr::V4(dest.addr()), dest.prefix_len())
-            .with_gateway(IpAddr::V4(gateway));
-        self.h.delete(&route).await.unwrap();
+    fn route_add(&self, prefix: IpNet, nexthops: &[IpAddr]) {
+        let _ = self.tx.send(RouteOp::Add(prefix, nexthops.to_vec()));
     }
+
+    fn route_del(&self, prefix: IpNet) {
+        let _ = self.tx.send(RouteOp::Del(prefix));
+    }
+
+    fn mpls_add(&self, label: u32, _out_label: Option<u32>, _via: Option<IpAddr>) {
+        println!("mpls: label {} is not supported", label);
+    }
+
+    fn mpls_del(&self, _label: u32) {}
 }
 
 fn os_link_flags(flags: InterfaceFlags) -> link::LinkFlags {
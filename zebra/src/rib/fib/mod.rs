@@ -20,6 +20,11 @@ pub use macos::os_traffic_dump;
 #[cfg(target_os = "macos")]
 pub use macos::FibHandle;
 
+pub mod dataplane;
+pub use dataplane::{dataplane_new, Dataplane, DataplaneType};
+
+pub mod grpc;
+
 pub mod message;
 pub use message::{FibChannel, FibMessage};
 
@@ -1,3 +1,4 @@
+use super::dataplane::Dataplane;
 use super::message::{FibAddr, FibLink, FibMessage, FibRoute};
 use crate::rib::link;
 use anyhow::Result;
@@ -7,7 +8,8 @@ use netlink_packet_core::{NetlinkMessage, NetlinkPayload};
 use netlink_packet_route::address::{AddressAttribute, AddressMessage};
 use netlink_packet_route::link::{LinkAttribute, LinkFlag, LinkLayerType, LinkMessage};
 use netlink_packet_route::route::{
-    RouteAddress, RouteAttribute, RouteHeader, RouteMessage, RouteProtocol, RouteScope, RouteType,
+    MplsLabel, RouteAddress, RouteAttribute, RouteHeader, RouteMessage, RouteNextHop,
+    RouteProtocol, RouteScope, RouteType, RouteVia,
 };
 use netlink_packet_route::{AddressFamily, RouteNetlinkMessage};
 use netlink_sys::{AsyncSocket, SocketAddr};
@@ -20,7 +22,11 @@ use rtnetlink::{
 use std::collections::HashMap;
 use std::fmt::Write;
 use std::net::{IpAddr, Ipv4Addr};
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+// Protocol of the routes programmed by the RIB. They are not learned back
+// from the kernel as the kernel routes.
+const RIB_PROTOCOL: RouteProtocol = RouteProtocol::Zebra;
 
 pub struct FibHandle {
     handle: rtnetlink::Handle,
@@ -50,8 +56,6 @@ impl FibHandle {
 
         Ok(Self { handle })
     }
-
-    pub async fn route_ipv4_add(&self, _dest: Ipv4Net, _gateway: Ipv4Addr) {}
 }
 
 fn flags_u32(f: &LinkFlag) -> u32 {
@@ -181,14 +185,14 @@ fn process_msg(msg: NetlinkMessage<RouteNetlinkMessage>, tx: UnboundedSender<Fib
                 let msg = FibMessage::DelAddr(addr);
                 tx.send(msg).unwrap();
             }
-            RouteNetlinkMessage::NewRoute(msg) => {
+            RouteNetlinkMessage::NewRoute(msg) if msg.header.protocol != RIB_PROTOCOL => {
                 let route = route_from_msg(msg);
                 if !route.gateway.is_unspecified() {
                     let msg = FibMessage::NewRoute(route);
                     tx.send(msg).unwrap();
                 }
             }
-            RouteNetlinkMessage::DelRoute(msg) => {
+            RouteNetlinkMessage::DelRoute(msg) if msg.header.protocol != RIB_PROTOCOL => {
                 let route = route_from_msg(msg);
                 let msg = FibMessage::DelRoute(route);
                 tx.send(msg).unwrap();
@@ -226,6 +230,12 @@ async fn route_dump(
 ) -> Result<()> {
     let mut routes = handle.route().get(ip_version).execute();
     while let Some(msg) = routes.try_next().await? {
+        // Routes left by the previous run are deleted, and the RIB programs
+        // them again.
+        if msg.header.protocol == RIB_PROTOCOL {
+            let _ = handle.route().del(msg).execute().await;
+            continue;
+        }
         let route = route_from_msg(msg);
         let msg = FibMessage::NewRoute(route);
         tx.send(msg).unwrap();
@@ -312,6 +322,199 @@ pub async fn route_del(handle: rtnetlink::Handle, dest: Ipv4Net, gateway: Ipv4Ad
     }
 }
 
+// MPLS route of the incoming label swapped to the outgoing one via the next
+// hop. The label is popped when the outgoing one is None.
+fn mpls_route_message(label: u32, out_label: Option<u32>, via: Option<IpAddr>) -> RouteMessage {
+    let mpls = |label| MplsLabel {
+        label,
+        traffic_class: 0,
+        bottom_of_stack: true,
+        ttl: 0,
+    };
+    let mut msg = RouteMessage::default();
+    msg.header.table = RouteHeader::RT_TABLE_MAIN;
+    msg.header.protocol = RouteProtocol::Bgp;
+    msg.header.scope = RouteScope::Universe;
+    msg.header.kind = RouteType::Unicast;
+    msg.header.address_family = AddressFamily::Mpls;
+    msg.header.destination_prefix_length = 20;
+    msg.attributes
+        .push(RouteAttribute::Destination(RouteAddress::Mpls(mpls(label))));
+    if let Some(out_label) = out_label {
+        msg.attributes
+            .push(RouteAttribute::NewDestination(vec![mpls(out_label)]));
+    }
+    match via {
+        Some(IpAddr::V4(addr)) => msg
+            .attributes
+            .push(RouteAttribute::Via(RouteVia::Inet(addr))),
+        Some(IpAddr::V6(addr)) => msg
+            .attributes
+            .push(RouteAttribute::Via(RouteVia::Inet6(addr))),
+        None => {}
+    }
+    msg
+}
+
+async fn mpls_route_add(
+    handle: rtnetlink::Handle,
+    label: u32,
+    out_label: Option<u32>,
+    via: IpAddr,
+) {
+    let mut request = handle.route().add().replace();
+    *request.message_mut() = mpls_route_message(label, out_label, Some(via));
+    if let Err(err) = request.execute().await {
+        println!("mpls: label {} add error: {}", label, err);
+    }
+}
+
+// Loopback device, where the packet of the popped label is looked up in the
+// IP table.
+const LOOPBACK_IFINDEX: u32 = 1;
+
+// MPLS route of the local label of the prefix originated by us.
+async fn mpls_route_local(handle: rtnetlink::Handle, label: u32) {
+    let mut msg = mpls_route_message(label, None, None);
+    msg.attributes.push(RouteAttribute::Oif(LOOPBACK_IFINDEX));
+    let mut request = handle.route().add().replace();
+    *request.message_mut() = msg;
+    if let Err(err) = request.execute().await {
+        println!("mpls: label {} add error: {}", label, err);
+    }
+}
+
+async fn mpls_route_del(handle: rtnetlink::Handle, label: u32) {
+    let msg = mpls_route_message(label, None, None);
+    if let Err(err) = handle.route().del(msg).execute().await {
+        println!("mpls: label {} delete error: {}", label, err);
+    }
+}
+
+// Route of the RIB with the next hops of the ECMP group. IPv6 next hop of the
+// IPv4 prefix is set as via (RFC 8950).
+fn rib_route_message(prefix: IpNet, nexthops: &[IpAddr]) -> RouteMessage {
+    let mut msg = RouteMessage::default();
+    msg.header.table = RouteHeader::RT_TABLE_MAIN;
+    msg.header.protocol = RIB_PROTOCOL;
+    msg.header.scope = RouteScope::Universe;
+    msg.header.kind = RouteType::Unicast;
+    msg.header.destination_prefix_length = prefix.prefix_len();
+    match prefix {
+        IpNet::V4(v4) => {
+            msg.header.address_family = AddressFamily::Inet;
+            msg.attributes
+                .push(RouteAttribute::Destination(RouteAddress::Inet(v4.addr())));
+        }
+        IpNet::V6(v6) => {
+            msg.header.address_family = AddressFamily::Inet6;
+            msg.attributes
+                .push(RouteAttribute::Destination(RouteAddress::Inet6(v6.addr())));
+        }
+    }
+    let gateway = |nexthop: &IpAddr| match (prefix, nexthop) {
+        (IpNet::V4(_), IpAddr::V4(addr)) => RouteAttribute::Gateway(RouteAddress::Inet(*addr)),
+        (IpNet::V4(_), IpAddr::V6(addr)) => RouteAttribute::Via(RouteVia::Inet6(*addr)),
+        (IpNet::V6(_), IpAddr::V4(addr)) => RouteAttribute::Via(RouteVia::Inet(*addr)),
+        (IpNet::V6(_), IpAddr::V6(addr)) => RouteAttribute::Gateway(RouteAddress::Inet6(*addr)),
+    };
+    match nexthops {
+        [] => {}
+        [nexthop] => msg.attributes.push(gateway(nexthop)),
+        _ => {
+            let nexthops = nexthops
+                .iter()
+                .map(|nexthop| RouteNextHop {
+                    attributes: vec![gateway(nexthop)],
+                    ..Default::default()
+                })
+                .collect();
+            msg.attributes.push(RouteAttribute::MultiPath(nexthops));
+        }
+    }
+    msg
+}
+
+async fn rib_route_add(handle: rtnetlink::Handle, prefix: IpNet, nexthops: Vec<IpAddr>) {
+    let mut request = handle.route().add().replace();
+    *request.message_mut() = rib_route_message(prefix, &nexthops);
+    if let Err(err) = request.execute().await {
+        println!("route: {} add error: {}", prefix, err);
+    }
+}
+
+async fn rib_route_del(handle: rtnetlink::Handle, prefix: IpNet) {
+    let msg = rib_route_message(prefix, &[]);
+    if let Err(err) = handle.route().del(msg).execute().await {
+        println!("route: {} delete error: {}", prefix, err);
+    }
+}
+
+enum NetlinkOp {
+    RouteAdd(IpNet, Vec<IpAddr>),
+    RouteDel(IpNet),
+    MplsAdd(u32, Option<u32>, Option<IpAddr>),
+    MplsDel(u32),
+}
+
+// Kernel dataplane. Operations are executed one by one by the task so that
+// an add and the following delete of the same route or label reach the
+// kernel in order.
+pub struct NetlinkDataplane {
+    tx: UnboundedSender<NetlinkOp>,
+}
+
+impl NetlinkDataplane {
+    pub fn new() -> anyhow::Result<Self> {
+        let (connection, handle, _) = new_connection()?;
+        tokio::spawn(connection);
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(netlink_dataplane_task(handle, rx));
+        Ok(Self { tx })
+    }
+
+    fn send(&self, op: NetlinkOp) {
+        let _ = self.tx.send(op);
+    }
+}
+
+async fn netlink_dataplane_task(handle: rtnetlink::Handle, mut rx: UnboundedReceiver<NetlinkOp>) {
+    while let Some(op) = rx.recv().await {
+        let handle = handle.clone();
+        match op {
+            NetlinkOp::RouteAdd(prefix, nexthops) => rib_route_add(handle, prefix, nexthops).await,
+            NetlinkOp::RouteDel(prefix) => rib_route_del(handle, prefix).await,
+            NetlinkOp::MplsAdd(label, out_label, Some(via)) => {
+                mpls_route_add(handle, label, out_label, via).await
+            }
+            NetlinkOp::MplsAdd(label, _, None) => mpls_route_local(handle, label).await,
+            NetlinkOp::MplsDel(label) => mpls_route_del(handle, label).await,
+        }
+    }
+}
+
+impl Dataplane for NetlinkDataplane {
+    fn name(&self) -> &str {
+        "kernel"
+    }
+
+    fn route_add(&self, prefix: IpNet, nexthops: &[IpAddr]) {
+        self.send(NetlinkOp::RouteAdd(prefix, nexthops.to_vec()));
+    }
+
+    fn route_del(&self, prefix: IpNet) {
+        self.send(NetlinkOp::RouteDel(prefix));
+    }
+
+    fn mpls_add(&self, label: u32, out_label: Option<u32>, via: Option<IpAddr>) {
+        self.send(NetlinkOp::MplsAdd(label, out_label, via));
+    }
+
+    fn mpls_del(&self, label: u32) {
+        self.send(NetlinkOp::MplsDel(label));
+    }
+}
+
 pub async fn fib_dump(handle: &FibHandle, tx: UnboundedSender<FibMessage>) -> Result<()> {
     link_dump(handle.handle.clone(), tx.clone()).await?;
     address_dump(handle.handle.clone(), tx.clone()).await?;
@@ -1,8 +1,8 @@
-use super::api::RibRx;
+use super::api::{RibRx, RibTx};
 use super::config::config_dispatch;
 use super::entry::RibEntry;
 use super::fib::fib_dump;
-use super::fib::{FibChannel, FibHandle, FibMessage};
+use super::fib::{Dataplane, FibChannel, FibHandle, FibMessage};
 use super::{Link, RibTxChannel};
 use crate::config::{path_from_command, Args};
 use crate::config::{ConfigChannel, ConfigOp, ConfigRequest, DisplayRequest, ShowChannel};
@@ -21,13 +21,16 @@ pub struct Rib {
     pub show_cb: HashMap<String, ShowCallback>,
     pub fib: FibChannel,
     pub fib_handle: FibHandle,
+    pub dataplane: Box<dyn Dataplane>,
     pub redists: Vec<Sender<RibRx>>,
+    pub redist_queue: Vec<RibRx>,
     pub links: BTreeMap<u32, Link>,
     pub rib: PrefixMap<Ipv4Net, Vec<RibEntry>>,
 }
 
 impl Rib {
-    pub fn new() -> anyhow::Result<Self> {
+    pub fn new(dataplane: Box<dyn Dataplane>) -> anyhow::Result<Self> {
+        println!("rib: {} dataplane", dataplane.name());
         let fib = FibChannel::new();
         let fib_handle = FibHandle::new(fib.tx.clone())?;
         let mut rib = Rib {
@@ -37,7 +40,9 @@ impl Rib {
             show_cb: HashMap::new(),
             fib,
             fib_handle,
+            dataplane,
             redists: Vec::new(),
+            redist_queue: Vec::new(),
             links: BTreeMap::new(),
             rib: prefix_trie::PrefixMap::new(),
         };
@@ -81,9 +86,28 @@ impl Rib {
                 let (path, args) = path_from_command(&msg.paths);
                 config_dispatch(self, path, args, msg.op).await;
             }
+            ConfigOp::CommitEnd | ConfigOp::Resync => {}
+            ConfigOp::Ready => msg.ready_reply(),
         }
     }
 
+    fn process_api_msg(&mut self, msg: RibTx) {
+        let prefix = match msg {
+            RibTx::RouteAdd(route) => {
+                let prefix = route.prefix;
+                self.bgp_route_add(route);
+                prefix
+            }
+            RibTx::RouteDel(route) => {
+                let prefix = route.prefix;
+                self.bgp_route_del(route);
+                prefix
+            }
+            RibTx::NexthopResgister() | RibTx::NexthopUnresgister() => return,
+        };
+        self.redist_queue.push(RibRx::RouteAck(prefix));
+    }
+
     async fn process_show_msg(&self, msg: DisplayRequest) {
         let (path, args) = path_from_command(&msg.paths);
         if let Some(f) = self.show_cb.get(&path) {
@@ -107,7 +131,11 @@ impl Rib {
                 Some(msg) = self.show.rx.recv() => {
                     self.process_show_msg(msg).await;
                 }
+                Some(msg) = self.api.rx.recv() => {
+                    self.process_api_msg(msg);
+                }
             }
+            self.redist_flush().await;
         }
     }
 }
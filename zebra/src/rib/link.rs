@@ -275,6 +275,7 @@ impl Rib {
                 if let IpNet::V4(net) = addr.addr {
                     self.ipv4_add(net, e);
                 }
+                self.redist_connected(addr.addr, true);
             }
         }
     }
@@ -282,7 +283,9 @@ impl Rib {
     pub fn addr_del(&mut self, osaddr: FibAddr) {
         let addr = LinkAddr::from(osaddr);
         if let Some(link) = self.links.get_mut(&addr.link_index) {
-            link_addr_del(link, addr);
+            if link_addr_del(link, addr.clone()).is_some() {
+                self.redist_connected(addr.addr, false);
+            }
         }
     }
 }
@@ -11,6 +11,8 @@ pub mod entry;
 
 pub mod route;
 
+pub mod redist;
+
 pub mod nexthop;
 
 pub mod config;
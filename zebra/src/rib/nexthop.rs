@@ -1,7 +1,12 @@
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub struct Nexthop {
-    nexthop: Ipv4Addr,
+    pub nexthop: IpAddr,
+}
+
+impl Nexthop {
+    pub fn new(nexthop: IpAddr) -> Self {
+        Self { nexthop }
+    }
 }
@@ -0,0 +1,49 @@
+use super::api::{RedistRoute, RedistSource, RibRx};
+use super::Rib;
+use ipnet::IpNet;
+
+// Link-local addresses are not offered as connected routes.
+fn is_link_local(prefix: &IpNet) -> bool {
+    match prefix {
+        IpNet::V4(net) => net.addr().is_link_local(),
+        IpNet::V6(net) => net.addr().segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+impl Rib {
+    pub fn redist_add(&mut self, route: RedistRoute) {
+        self.redist_queue.push(RibRx::RedistAdd(route));
+    }
+
+    pub fn redist_del(&mut self, route: RedistRoute) {
+        self.redist_queue.push(RibRx::RedistDel(route));
+    }
+
+    pub fn redist_connected(&mut self, addr: IpNet, add: bool) {
+        let prefix = addr.trunc();
+        if is_link_local(&prefix) {
+            return;
+        }
+        let route = RedistRoute {
+            source: RedistSource::Connected,
+            prefix,
+            metric: 0,
+            nexthop: None,
+        };
+        if add {
+            self.redist_add(route);
+        } else {
+            self.redist_del(route);
+        }
+    }
+
+    // Messages are queued by the handlers and sent from the event loop, as
+    // the channels to the protocol modules are bounded.
+    pub async fn redist_flush(&mut self) {
+        for msg in std::mem::take(&mut self.redist_queue).into_iter() {
+            for tx in self.redists.iter() {
+                let _ = tx.send(msg.clone()).await;
+            }
+        }
+    }
+}
@@ -1,11 +1,20 @@
+use super::api::RibRoute;
 use super::entry::{RibEntry, RibType};
 use super::fib::message::FibRoute;
 use super::instance::Rib;
+use super::nexthop::Nexthop;
 use ipnet::{IpNet, Ipv4Net};
+use std::net::IpAddr;
 
 // Route.
 impl Rib {
     pub fn ipv4_add(&mut self, dest: Ipv4Net, e: RibEntry) {
+        let prev = self.ipv4_programmed(&dest);
+        self.ipv4_push(dest, e);
+        self.ipv4_select(dest, prev);
+    }
+
+    fn ipv4_push(&mut self, dest: Ipv4Net, e: RibEntry) {
         if let Some(n) = self.rib.get_mut(&dest) {
             n.push(e);
         } else {
@@ -28,9 +37,179 @@ impl Rib {
 
     pub fn route_del(&mut self, r: FibRoute) {
         if let IpNet::V4(v4) = r.route {
-            if let Some(_ribs) = self.rib.get(&v4) {
-                //
+            let prev = self.ipv4_programmed(&v4);
+            if let Some(ribs) = self.rib.get_mut(&v4) {
+                ribs.retain(|e| e.rtype != RibType::Kernel || e.gateway != r.gateway);
+                if ribs.is_empty() {
+                    self.rib.remove(&v4);
+                }
+            }
+            self.ipv4_select(v4, prev);
+        }
+    }
+
+    // Route of BGP. The ECMP group replaces the previous one of the prefix.
+    // IPv6 routes are not kept as the RIB is IPv4 only, and they are
+    // programmed to the dataplane as they are.
+    pub fn bgp_route_add(&mut self, r: RibRoute) {
+        if r.nexthops.is_empty() {
+            self.bgp_route_del(r);
+            return;
+        }
+        match r.prefix {
+            IpNet::V4(v4) => {
+                let mut e = RibEntry::new(RibType::BGP);
+                e.distance = r.distance;
+                e.gateway = r.nexthops[0];
+                e.nexthops = r.nexthops.iter().map(|x| Nexthop::new(*x)).collect();
+                self.ipv4_replace(v4, e);
+            }
+            IpNet::V6(_) => self.dataplane.route_add(r.prefix, &r.nexthops),
+        }
+    }
+
+    pub fn bgp_route_del(&mut self, r: RibRoute) {
+        match r.prefix {
+            IpNet::V4(v4) => self.ipv4_delete(v4, &RibType::BGP),
+            IpNet::V6(_) => self.dataplane.route_del(r.prefix),
+        }
+    }
+
+    // Static route with the next hop added to the ECMP group of the prefix.
+    pub fn static_route_add(&mut self, dest: Ipv4Net, gateway: IpAddr) {
+        let prev = self.ipv4_programmed(&dest);
+        let entry = self
+            .rib
+            .get_mut(&dest)
+            .and_then(|ribs| ribs.iter_mut().find(|e| e.rtype == RibType::Static));
+        match entry {
+            Some(e) => {
+                if !e.nexthops.iter().any(|x| x.nexthop == gateway) {
+                    e.nexthops.push(Nexthop::new(gateway));
+                }
+            }
+            None => {
+                let mut e = RibEntry::new(RibType::Static);
+                e.distance = STATIC_DISTANCE;
+                e.gateway = gateway;
+                e.nexthops.push(Nexthop::new(gateway));
+                self.ipv4_push(dest, e);
+            }
+        }
+        self.ipv4_select(dest, prev);
+    }
+
+    pub fn static_route_del(&mut self, dest: Ipv4Net) {
+        self.ipv4_delete(dest, &RibType::Static);
+    }
+
+    // Entry of the type replaces the previous one of the prefix.
+    fn ipv4_replace(&mut self, dest: Ipv4Net, e: RibEntry) {
+        let prev = self.ipv4_programmed(&dest);
+        if let Some(ribs) = self.rib.get_mut(&dest) {
+            ribs.retain(|x| x.rtype != e.rtype);
+        }
+        self.ipv4_push(dest, e);
+        self.ipv4_select(dest, prev);
+    }
+
+    fn ipv4_delete(&mut self, dest: Ipv4Net, rtype: &RibType) {
+        let prev = self.ipv4_programmed(&dest);
+        if let Some(ribs) = self.rib.get_mut(&dest) {
+            ribs.retain(|e| e.rtype != *rtype);
+            if ribs.is_empty() {
+                self.rib.remove(&dest);
             }
         }
+        self.ipv4_select(dest, prev);
+    }
+
+    // Next hops of the route of the prefix programmed to the dataplane.
+    fn ipv4_programmed(&self, dest: &Ipv4Net) -> Option<Vec<IpAddr>> {
+        rib_programmed_nexthops(self.rib.get(dest)?)
+    }
+
+    // Selected static or BGP route is programmed to the dataplane, and
+    // deleted from it when another entry is selected.
+    fn ipv4_select(&mut self, dest: Ipv4Net, prev: Option<Vec<IpAddr>>) {
+        if let Some(ribs) = self.rib.get_mut(&dest) {
+            rib_select(ribs);
+        }
+        match self.ipv4_programmed(&dest) {
+            Some(nexthops) if Some(&nexthops) != prev.as_ref() => {
+                self.dataplane.route_add(IpNet::V4(dest), &nexthops);
+            }
+            Some(_) => {}
+            None if prev.is_some() => self.dataplane.route_del(IpNet::V4(dest)),
+            None => {}
+        }
+    }
+}
+
+// Administrative distance of the static route.
+const STATIC_DISTANCE: u32 = 1;
+
+// Kernel and connected routes are in the kernel already.
+fn rib_programmed(e: &RibEntry) -> bool {
+    e.rtype == RibType::Static || e.rtype == RibType::BGP
+}
+
+fn rib_programmed_nexthops(ribs: &[RibEntry]) -> Option<Vec<IpAddr>> {
+    let e = ribs.iter().find(|e| e.fib && rib_programmed(e))?;
+    Some(e.nexthops.iter().map(|x| x.nexthop).collect())
+}
+
+// Entry of the lowest distance is selected, the first added one among the
+// same distance. Only the selected static or BGP route is in the FIB.
+fn rib_select(ribs: &mut [RibEntry]) {
+    let best = ribs
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, e)| e.distance)
+        .map(|(i, _)| i);
+    for (i, e) in ribs.iter_mut().enumerate() {
+        e.selected = Some(i) == best;
+        if rib_programmed(e) {
+            e.fib = e.selected;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(rtype: RibType, distance: u32, nexthop: &str) -> RibEntry {
+        let mut e = RibEntry::new(rtype);
+        e.distance = distance;
+        e.gateway = nexthop.parse().unwrap();
+        e.nexthops.push(Nexthop::new(e.gateway));
+        e
+    }
+
+    #[test]
+    fn rib_select_distance() {
+        let bgp: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut ribs = vec![entry(RibType::BGP, 20, "192.0.2.1")];
+        rib_select(&mut ribs);
+        assert!(ribs[0].selected && ribs[0].fib);
+        assert_eq!(rib_programmed_nexthops(&ribs), Some(vec![bgp]));
+
+        // Static route overrides BGP, and connected route overrides both
+        // without being programmed.
+        ribs.push(entry(RibType::Static, STATIC_DISTANCE, "192.0.2.2"));
+        rib_select(&mut ribs);
+        assert!(!ribs[0].selected && !ribs[0].fib);
+        assert!(ribs[1].selected && ribs[1].fib);
+        let mut connected = entry(RibType::Connected, 0, "0.0.0.0");
+        connected.fib = true;
+        ribs.push(connected);
+        rib_select(&mut ribs);
+        assert!(ribs[2].selected && ribs[2].fib);
+        assert_eq!(rib_programmed_nexthops(&ribs), None);
+
+        ribs.retain(|e| e.rtype == RibType::BGP);
+        rib_select(&mut ribs);
+        assert_eq!(rib_programmed_nexthops(&ribs), Some(vec![bgp]));
     }
 }
@@ -0,0 +1,47 @@
+// Span export to OpenTelemetry collector over OTLP. Spans are recorded with
+// tracing crate, the exporter is available with "otel" feature.
+
+#[cfg(feature = "otel")]
+pub fn init(endpoint: Option<&str>) -> anyhow::Result<()> {
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime, trace, Resource};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let endpoint = match endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(()),
+    };
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_resource(Resource::new(vec![KeyValue::new("service.name", "zebra")])),
+        )
+        .install_batch(runtime::Tokio)?;
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+    println!("trace: exporting spans to {}", endpoint);
+    Ok(())
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init(endpoint: Option<&str>) -> anyhow::Result<()> {
+    if endpoint.is_some() {
+        println!("trace: OTLP export is not supported, build with \"otel\" feature");
+    }
+    Ok(())
+}
+
+// Flush spans which are not exported yet.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}